@@ -0,0 +1,123 @@
+//! Last-accessed times of sounds, recorded without a write per read
+//!
+//! Opening a sound only notes its ID in memory; a background task writes
+//! the IDs noted since the last flush in one `UPDATE` per batch, stamping
+//! them with the time of the flush. A crash loses at most the accesses
+//! noted since then. Listings and plain lookups don't count as access.
+
+use crate::error::Result;
+use crate::local::LocalLibrary;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// Most IDs bound in one `UPDATE`, well under SQLite's variable limit
+const FLUSH_CHUNK: usize = 500;
+
+/// IDs of the sounds accessed since the last flush
+#[derive(Default)]
+pub(crate) struct AccessLog {
+    pending: Mutex<HashSet<String>>,
+}
+
+impl AccessLog {
+    /// Note an access to a sound, to be written at the next flush
+    pub(crate) fn note(&self, id: &str) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if !pending.contains(id) {
+            pending.insert(id.to_string());
+        }
+    }
+
+    /// Take the IDs noted so far, sorted
+    fn take(&self) -> Vec<String> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut ids: Vec<String> = pending.into_iter().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Put back IDs whose flush failed, to be retried
+    fn restore(&self, ids: Vec<String>) {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).extend(ids);
+    }
+}
+
+impl LocalLibrary {
+    /// Write the last-accessed time of the sounds accessed since the last
+    /// flush, returning how many were written
+    ///
+    /// Nothing is written when no sound was accessed. On failure the
+    /// accesses are kept for the next flush.
+    pub async fn flush_accesses(&self) -> Result<usize> {
+        let ids = self.accesses.take();
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        for id in &ids {
+            self.forget_sound(id);
+        }
+
+        let now = Utc::now();
+        let written = async {
+            let mut tx = self.db.begin().await?;
+            for chunk in ids.chunks(FLUSH_CHUNK) {
+                let placeholders = vec!["?"; chunk.len()].join(", ");
+                let sql = format!("UPDATE sounds SET last_accessed_at = ? WHERE id IN ({})", placeholders);
+                let sql = self.sql(&sql);
+                let mut query = sqlx::query(&sql).bind(now);
+                for id in chunk {
+                    query = query.bind(id);
+                }
+                query.execute(&mut *tx).await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        }
+        .await;
+
+        match written {
+            Ok(()) => Ok(ids.len()),
+            Err(e) => {
+                self.accesses.restore(ids);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Background task flushing the accesses noted
+pub(crate) struct AccessFlusher {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AccessFlusher {
+    /// Flush every `interval`, the first time one interval from now; `None`
+    /// only flushes on close and on demand
+    pub(crate) fn start(local: &Arc<LocalLibrary>, interval: Option<Duration>) -> Self {
+        let task = interval.filter(|interval| !interval.is_zero()).map(|interval| {
+            let local = local.clone();
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    ticks.tick().await;
+                    // A failed flush is retried at the next tick
+                    let _ = local.flush_accesses().await;
+                }
+            })
+        });
+
+        Self { task: Mutex::new(task) }
+    }
+
+    /// Stop flushing; accesses not flushed yet stay in memory
+    pub(crate) fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Finding a remote sound and bringing it into the vault in one call
+
+use crate::error::{Result, VaultError};
+use crate::license::License;
+use crate::local::LocalLibrary;
+use crate::models::{Sound, SoundMetadata, SoundSource};
+use crate::source::RemoteSource;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+/// Number of results asked of the source when a request doesn't say
+const DEFAULT_PAGE_SIZE: usize = 30;
+
+/// Which of the matching results [`SoundVault::acquire`](crate::SoundVault::acquire)
+/// takes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcquirePick {
+    /// The first result, in the source's order
+    #[default]
+    First,
+    /// The result rated highest by the source; unrated results come last,
+    /// and ties go to the earlier result
+    HighestRated,
+    /// A random one of the first `n` results
+    RandomTop(usize),
+}
+
+/// What to find and where to put it, for
+/// [`SoundVault::acquire`](crate::SoundVault::acquire)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AcquireRequest {
+    /// Search text
+    pub query: String,
+
+    /// Name of the remote source searched, Freesound by default
+    pub source: String,
+
+    /// Number of results asked of the source, before filtering
+    pub page_size: usize,
+
+    /// Shortest duration, in seconds
+    pub min_duration: Option<f32>,
+
+    /// Longest duration, in seconds
+    pub max_duration: Option<f32>,
+
+    /// Licenses allowed; empty allows any
+    pub licenses: Vec<License>,
+
+    /// Tags a result must all have
+    pub tags: Vec<String>,
+
+    /// Which matching result to take
+    pub pick: AcquirePick,
+
+    /// Collection the sound is added to
+    pub collection_id: Option<String>,
+}
+
+impl AcquireRequest {
+    /// Request the first Freesound result for `query`, whatever it is
+    pub fn new(query: &str) -> Self {
+        Self {
+            query: query.to_string(),
+            source: SoundSource::Freesound.as_str().to_string(),
+            page_size: DEFAULT_PAGE_SIZE,
+            min_duration: None,
+            max_duration: None,
+            licenses: Vec::new(),
+            tags: Vec::new(),
+            pick: AcquirePick::First,
+            collection_id: None,
+        }
+    }
+
+    /// Check whether a search result passes the filters
+    pub fn matches(&self, metadata: &SoundMetadata) -> bool {
+        self.min_duration.is_none_or(|min| metadata.duration >= min)
+            && self.max_duration.is_none_or(|max| metadata.duration <= max)
+            && (self.licenses.is_empty() || self.licenses.contains(&License::parse(&metadata.license)))
+            && self
+                .tags
+                .iter()
+                .all(|tag| metadata.tags.iter().any(|own| own.eq_ignore_ascii_case(tag.trim())))
+    }
+}
+
+/// Step of [`SoundVault::acquire`](crate::SoundVault::acquire) that failed
+/// after a sound was picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcquireStage {
+    /// Downloading and importing the sound
+    Download,
+    /// Adding the imported sound to the collection
+    AddToCollection,
+}
+
+impl fmt::Display for AcquireStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Download => "download",
+            Self::AddToCollection => "adding to the collection",
+        })
+    }
+}
+
+impl AcquirePick {
+    /// Index of the result taken out of `results`, which isn't empty
+    fn pick(&self, results: &[SoundMetadata]) -> usize {
+        match self {
+            Self::First => 0,
+            Self::HighestRated => results
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, metadata)| (metadata.rating, std::cmp::Reverse(*index)))
+                .map_or(0, |(index, _)| index),
+            Self::RandomTop(n) => {
+                let n = (*n).clamp(1, results.len());
+                (Uuid::new_v4().as_u128() % n as u128) as usize
+            }
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Search a source, pick a result passing the request's filters,
+    /// download it and add it to the request's collection
+    pub(crate) async fn acquire(&self, source: &dyn RemoteSource, request: &AcquireRequest) -> Result<Sound> {
+        // Fail before downloading anything if the collection doesn't exist
+        if let Some(collection_id) = &request.collection_id {
+            self.get_collection(collection_id).await?;
+        }
+
+        let found = source.search(&request.query, request.page_size).await?;
+        let searched = found.len();
+        let matching: Vec<SoundMetadata> = found.into_iter().filter(|metadata| request.matches(metadata)).collect();
+        if matching.is_empty() {
+            return Err(VaultError::NoMatch {
+                query: request.query.clone(),
+                searched,
+            });
+        }
+
+        let picked = &matching[request.pick.pick(&matching)];
+        let remote_id = picked.remote_id.clone().unwrap_or_default();
+        let failed = |stage, sound_id: Option<&str>, error| VaultError::AcquireFailed {
+            sound: picked.name.clone(),
+            remote_id: remote_id.clone(),
+            stage,
+            sound_id: sound_id.map(str::to_string),
+            error: Box::new(error),
+        };
+
+        let id = self
+            .download_remote(source, &remote_id)
+            .await
+            .map_err(|e| failed(AcquireStage::Download, None, e))?;
+        if let Some(collection_id) = &request.collection_id {
+            self.add_sound_to_collection(&id, collection_id)
+                .await
+                .map_err(|e| failed(AcquireStage::AddToCollection, Some(&id), e))?;
+        }
+
+        self.get_sound(&id).await
+    }
+}
@@ -0,0 +1,428 @@
+//! Compression of rarely used sound files
+
+use crate::audio::{AudioFormat, AudioInfo, PcmParts, SampleFormat, join_pcm, split_pcm};
+use crate::audit::{AuditOperation, audit_diff};
+use crate::context::OpContext;
+use crate::error::{Result, VaultError};
+use crate::flac;
+use crate::journal::OpKind;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::SoundMetadata;
+use crate::pack::read_packed;
+use crate::paths::write_atomic;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// ID of the FLAC APPLICATION block holding the original container
+const FLAC_APPLICATION_ID: &[u8; 4] = b"SVLT";
+
+/// Version of the container layout stored in FLAC archives
+const FLAC_PAYLOAD_VERSION: u8 = 1;
+
+/// zstd compression level; archived files are written once and read rarely
+const ZSTD_LEVEL: i32 = 12;
+
+/// How an archived sound file is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArchivalCodec {
+    /// Lossless audio compression, for integer PCM of up to 24 bits and 8 channels
+    Flac,
+    /// General-purpose compression, for any file
+    Zstd,
+}
+
+/// Where the compressed file of an archived sound came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Archive {
+    /// Compression used
+    pub codec: ArchivalCodec,
+
+    /// SHA-256 hash of the compressed file; the sound's `hash` remains the
+    /// hash of the original
+    pub hash: String,
+}
+
+/// Which sounds [`SoundVault::compress_cold_sounds`](crate::SoundVault::compress_cold_sounds) archives
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColdStoragePolicy {
+    /// Sounds not opened for this many days are archived; sounds never
+    /// opened count from their import
+    pub unused_days: u32,
+
+    /// Files smaller than this many bytes are left alone
+    pub min_size: u64,
+
+    /// Compression to use; `None` picks FLAC where it applies and zstd otherwise
+    pub codec: Option<ArchivalCodec>,
+}
+
+/// Outcome of [`SoundVault::compress_cold_sounds`](crate::SoundVault::compress_cold_sounds)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColdStorageReport {
+    /// IDs of the sounds archived
+    pub compressed: Vec<String>,
+
+    /// Bytes freed in the library
+    pub bytes_saved: u64,
+
+    /// Sounds that couldn't be archived, with the reason
+    pub errors: Vec<(String, String)>,
+}
+
+impl Default for ColdStoragePolicy {
+    fn default() -> Self {
+        Self {
+            unused_days: 365,
+            min_size: 1 << 20,
+            codec: None,
+        }
+    }
+}
+
+impl ArchivalCodec {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Parse a name stored in the database
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "flac" => Some(Self::Flac),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Extension appended to the original file name
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Zstd => "zst",
+        }
+    }
+
+    /// Pick FLAC for a file it can hold losslessly, zstd otherwise
+    fn for_file(bytes: &[u8]) -> Self {
+        match split_pcm(bytes) {
+            Ok(parts) if parts.bytes_per_sample <= 3 && parts.info.channels <= 8 => Self::Flac,
+            _ => Self::Zstd,
+        }
+    }
+}
+
+/// Compress the bytes of a file
+fn compress(bytes: &[u8], codec: ArchivalCodec) -> Result<Vec<u8>> {
+    match codec {
+        ArchivalCodec::Zstd => Ok(zstd::encode_all(bytes, ZSTD_LEVEL)?),
+        ArchivalCodec::Flac => {
+            let parts = split_pcm(bytes)?;
+            if parts.bytes_per_sample > 3 {
+                return Err(VaultError::InvalidOperation(
+                    "FLAC holds at most 24 bits per sample; use zstd".to_string(),
+                ));
+            }
+
+            let format = match parts.info.format {
+                AudioFormat::Wav => 0,
+                AudioFormat::Aiff => 1,
+                AudioFormat::Caf => 2,
+            };
+            let mut payload = vec![
+                FLAC_PAYLOAD_VERSION,
+                format,
+                parts.bytes_per_sample as u8,
+                parts.big_endian as u8,
+            ];
+            payload.extend((parts.header.len() as u32).to_be_bytes());
+            payload.extend(&parts.header);
+            payload.extend(&parts.trailer);
+
+            flac::encode(
+                parts.info.channels,
+                parts.info.sample_rate,
+                parts.bytes_per_sample as u16 * 8,
+                &parts.samples,
+                Some((FLAC_APPLICATION_ID, &payload)),
+            )
+        }
+    }
+}
+
+/// Restore the original bytes of a compressed file
+fn decompress(bytes: &[u8], codec: ArchivalCodec) -> Result<Vec<u8>> {
+    match codec {
+        ArchivalCodec::Zstd => Ok(zstd::decode_all(bytes)?),
+        ArchivalCodec::Flac => {
+            let stream = flac::decode(bytes, FLAC_APPLICATION_ID)?;
+            let payload = stream
+                .application
+                .filter(|p| p.len() >= 8 && p[0] == FLAC_PAYLOAD_VERSION)
+                .ok_or_else(|| VaultError::InvalidOperation("Archive lacks its original container".to_string()))?;
+
+            let format = match payload[1] {
+                0 => AudioFormat::Wav,
+                1 => AudioFormat::Aiff,
+                _ => AudioFormat::Caf,
+            };
+            let header_len = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]) as usize;
+            let header = payload
+                .get(8..8 + header_len)
+                .ok_or_else(|| VaultError::InvalidOperation("Archive container is truncated".to_string()))?;
+
+            let bits = stream.bits;
+            Ok(join_pcm(&PcmParts {
+                info: AudioInfo::new(format, stream.channels, stream.sample_rate, SampleFormat::Int(bits)),
+                header: header.to_vec(),
+                samples: stream.samples,
+                trailer: payload[8 + header_len..].to_vec(),
+                bytes_per_sample: payload[2] as usize,
+                big_endian: payload[3] != 0,
+            }))
+        }
+    }
+}
+
+/// Path of the original file of an archived one
+fn original_path(path: &Path, codec: ArchivalCodec) -> PathBuf {
+    match path.extension() {
+        Some(ext) if ext == codec.extension() => path.with_extension(""),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl LocalLibrary {
+    /// Replace a sound's file by a compressed copy
+    ///
+    /// The copy is checked to decompress to the original before the
+    /// original is deleted. Reads through the vault keep returning the
+    /// original bytes.
+    pub async fn compress_sound(&self, id: &str, codec: ArchivalCodec) -> Result<()> {
+        let before = self.get_sound(id).await?.metadata;
+        if before.external {
+            return Err(VaultError::InvalidOperation(format!("Sound file is external: {}", id)));
+        }
+        self.check_in_library(&before)?;
+        if before.archive.is_some() {
+            return Err(VaultError::InvalidOperation(format!("Sound is already compressed: {}", id)));
+        }
+        if before.path.as_deref().and_then(|path| self.object_hash(path)).is_some() {
+            return Err(VaultError::InvalidOperation(format!("File of {} is content-addressed and may be shared", id)));
+        }
+
+        let path = self.readable_file(&before)?;
+        let original = std::fs::read(&path)?;
+        let compressed = compress(&original, codec)?;
+        if decompress(&compressed, codec)? != original {
+            return Err(VaultError::InvalidOperation(format!("Compressed copy of {} doesn't round-trip", id)));
+        }
+
+        let mut archived = path.clone().into_os_string();
+        archived.push(".");
+        archived.push(codec.extension());
+        let archived = self.library_file(Path::new(&archived))?;
+
+        // The original is deleted once the compressed copy is recorded
+        let op = self.begin_op(OpKind::Compress, &[&archived], &[&path]).await?;
+        let result = async {
+            write_atomic(&archived, &compressed).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to write compressed file: {}", e))
+            })?;
+
+            let mut after = before.clone();
+            after.archive = Some(Archive {
+                codec,
+                hash: hash_file(&archived)?,
+            });
+            after.path = Some(archived.clone());
+            self.save_storage(&before, &after, &op).await
+        }
+        .await;
+        self.settle_op(&op, result).await
+    }
+
+    /// Restore the original file of a compressed sound
+    pub async fn decompress_sound(&self, id: &str) -> Result<()> {
+        let before = self.get_sound(id).await?.metadata;
+        let Some(archive) = &before.archive else {
+            return Ok(());
+        };
+        self.check_in_library(&before)?;
+
+        let path = self.readable_file(&before)?;
+        let original = self.library_file(&original_path(&path, archive.codec))?;
+
+        // The compressed file is deleted once the original is recorded
+        let op = self.begin_op(OpKind::Decompress, &[&original], &[&path]).await?;
+        let result = async {
+            write_atomic(&original, &self.sound_bytes(&before)?).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to write original file: {}", e))
+            })?;
+
+            let mut after = before.clone();
+            after.archive = None;
+            after.path = Some(original.clone());
+            self.save_storage(&before, &after, &op).await
+        }
+        .await;
+        self.settle_op(&op, result).await
+    }
+
+    /// Compress the sounds that haven't been opened for a while
+    ///
+    /// Each sound is compressed on its own: if `context` stops the run, the
+    /// sounds compressed so far stay compressed.
+    pub async fn compress_cold_sounds(&self, policy: &ColdStoragePolicy, context: &OpContext) -> Result<ColdStorageReport> {
+        let deadline = context.start("compress_cold_sounds");
+        let cutoff = Utc::now() - chrono::Duration::days(policy.unused_days as i64);
+        let ids: Vec<String> = sqlx::query_scalar(
+            &self.sql(r#"
+            SELECT id FROM sounds
+            WHERE external = 0 AND archive_codec IS NULL AND path IS NOT NULL AND storage_backend IS NULL
+              AND object_hash IS NULL AND COALESCE(last_played_at, created_at) < ?
+            ORDER BY id
+            "#),
+        )
+        .bind(cutoff.format("%Y-%m-%d %H:%M:%S").to_string())
+        .fetch_all(&self.reader)
+        .await?;
+
+        let mut report = ColdStorageReport::default();
+        let total = ids.len();
+        for (done, id) in ids.into_iter().enumerate() {
+            deadline.check(|| format!("{} of {} cold sounds handled", done, total))?;
+            let outcome = async {
+                let metadata = self.get_sound(&id).await?.metadata;
+                let path = self.readable_file(&metadata)?;
+                let size = std::fs::metadata(&path)?.len();
+                if size < policy.min_size {
+                    return Ok(None);
+                }
+
+                let codec = match policy.codec {
+                    Some(codec) => codec,
+                    None => ArchivalCodec::for_file(&std::fs::read(&path)?),
+                };
+                self.compress_sound(&id, codec).await?;
+
+                let compressed = self.get_sound(&id).await?.metadata;
+                let stored = std::fs::metadata(self.readable_file(&compressed)?)?.len();
+                Ok::<_, VaultError>(Some(size.saturating_sub(stored)))
+            }
+            .await;
+
+            match outcome {
+                Ok(Some(saved)) => {
+                    report.compressed.push(id);
+                    report.bytes_saved += saved;
+                }
+                Ok(None) => {}
+                Err(e) => report.errors.push((id, e.to_string())),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Read a sound's original file for playback
+    ///
+    /// Compressed sounds are decompressed on the fly. Marks the sound as
+    /// played, which keeps it out of cold storage, and notes the access.
+    pub async fn open_sound(&self, id: &str) -> Result<Vec<u8>> {
+        let metadata = self.stored_sound(id).await?;
+        let bytes = self.sound_bytes(&metadata)?;
+        self.record_play(id).await?;
+        self.accesses.note(id);
+
+        Ok(bytes)
+    }
+
+    /// Mark a sound as played, which keeps it out of cold storage
+    pub(crate) async fn record_play(&self, id: &str) -> Result<()> {
+        sqlx::query(&self.sql("UPDATE sounds SET last_played_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Original bytes of a sound's file, decompressing archived ones and
+    /// inflating packed ones
+    pub(crate) fn sound_bytes(&self, metadata: &SoundMetadata) -> Result<Vec<u8>> {
+        if metadata.packed {
+            let path = metadata.path.as_deref().unwrap_or(Path::new(""));
+            return read_packed(path)?.ok_or_else(|| VaultError::FileMissing(metadata.id.clone()));
+        }
+        let bytes = std::fs::read(self.readable_file(metadata)?)?;
+        match &metadata.archive {
+            Some(archive) => decompress(&bytes, archive.codec),
+            None => Ok(bytes),
+        }
+    }
+
+    /// SHA-256 hash of the original content of a sound's file
+    ///
+    /// For an archived sound whose compressed file is intact this is the
+    /// recorded hash; otherwise the content is decompressed and hashed.
+    pub(crate) fn content_hash(&self, metadata: &SoundMetadata) -> Result<String> {
+        if metadata.packed {
+            return Ok(format!("{:x}", Sha256::digest(self.sound_bytes(metadata)?)));
+        }
+        let path = self.readable_file(metadata)?;
+        let Some(archive) = &metadata.archive else {
+            return hash_file(&path);
+        };
+
+        let stored = hash_file(&path)?;
+        if stored == archive.hash
+            && let Some(hash) = &metadata.hash
+        {
+            return Ok(hash.clone());
+        }
+        match self.sound_bytes(metadata) {
+            Ok(bytes) => Ok(format!("{:x}", Sha256::digest(&bytes))),
+            Err(_) => Ok(stored),
+        }
+    }
+
+    /// Path under which a sound's content is known, whether archived or not
+    pub(crate) fn logical_path(metadata: &SoundMetadata) -> Option<PathBuf> {
+        let path = metadata.path.as_deref()?;
+        Some(match &metadata.archive {
+            Some(archive) => original_path(path, archive.codec),
+            None => path.to_path_buf(),
+        })
+    }
+
+    /// Record where a sound's file is stored now
+    async fn save_storage(&self, before: &SoundMetadata, after: &SoundMetadata, op: &str) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            &self.sql(r#"
+            UPDATE sounds
+            SET path = ?, archive_codec = ?, archive_hash = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            "#),
+        )
+        .bind(after.path.as_ref().map(|p| p.to_string_lossy().to_string()))
+        .bind(after.archive.as_ref().map(|a| a.codec.as_str()))
+        .bind(after.archive.as_ref().map(|a| a.hash.clone()))
+        .bind(&after.id)
+        .execute(&mut *tx)
+        .await?;
+
+        let changes = audit_diff(Some(&serde_json::to_value(before)?), Some(&serde_json::to_value(after)?));
+        self.audit(&mut tx, AuditOperation::UpdateSound, &after.id, changes).await?;
+        self.commit_op(&mut tx, op).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! Generated files kept beside the library, and evicting them
+//!
+//! Previews, spectrograms and waveform images are recorded when written and
+//! touched whenever they're served, so the least recently used go first. An
+//! evicted file is generated again the next time it's asked for. Waveform
+//! peaks are computed on request and never stored.
+
+use crate::error::{Result, VaultError};
+use crate::human::{ByteSize, HumanDuration, deserialize_days};
+use crate::integrity::PREVIEW_DIR;
+use crate::local::LocalLibrary;
+use crate::plan::{PlannedFile, check_snapshot, snapshot_token};
+use crate::quota::QuotaUsage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Kind of generated file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    /// WAV preview written by [`SoundVault::generate_preview`](crate::SoundVault::generate_preview)
+    Preview,
+    /// PNG image cached by `SoundVault::get_spectrogram`
+    Spectrogram,
+    /// PNG image cached by `SoundVault::waveform_png`
+    Waveform,
+}
+
+impl ArtifactKind {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Preview => "preview",
+            Self::Spectrogram => "spectrogram",
+            Self::Waveform => "waveform",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown artifact kind: {}", name)))
+    }
+}
+
+/// Limits on the generated files of one kind
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtifactLimits {
+    /// Total size kept; `None` keeps any size
+    pub max_bytes: Option<ByteSize>,
+
+    /// Files not used for this long are evicted; `None` keeps them. Read
+    /// from `max_age_days` too, and a bare number counts days, as it used to
+    #[serde(alias = "max_age_days", deserialize_with = "deserialize_days")]
+    pub max_age: Option<HumanDuration>,
+}
+
+/// Limits on the generated files kept, kind by kind
+///
+/// The default keeps everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtifactPolicy {
+    /// Limits on previews
+    pub previews: ArtifactLimits,
+
+    /// Limits on spectrograms
+    pub spectrograms: ArtifactLimits,
+
+    /// Limits on waveform images
+    pub waveforms: ArtifactLimits,
+}
+
+impl ArtifactPolicy {
+    /// Limits on the files of `kind`
+    pub fn limits(&self, kind: ArtifactKind) -> &ArtifactLimits {
+        match kind {
+            ArtifactKind::Preview => &self.previews,
+            ArtifactKind::Spectrogram => &self.spectrograms,
+            ArtifactKind::Waveform => &self.waveforms,
+        }
+    }
+}
+
+/// Outcome of [`SoundVault::gc_artifacts`](crate::SoundVault::gc_artifacts)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcReport {
+    /// Files evicted, least recently used first
+    pub evicted: Vec<PathBuf>,
+
+    /// Bytes freed
+    pub bytes_freed: u64,
+
+    /// Files that couldn't be removed, with the reason
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// What [`SoundVault::apply_gc_artifacts`](crate::SoundVault::apply_gc_artifacts) evicts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcPlan {
+    /// Files to evict, least recently used first
+    pub evictions: Vec<PlannedFile>,
+
+    /// Bytes the evictions free
+    pub bytes_reclaimed: u64,
+
+    /// Token of the generated files' state the plan was made from
+    pub snapshot: String,
+}
+
+/// Space taken by the library, from [`SoundVault::disk_usage`](crate::SoundVault::disk_usage)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    /// Bytes of the sounds' files stored in the library; referenced files
+    /// aren't counted
+    pub originals: u64,
+
+    /// Bytes of the generated files, kind by kind
+    pub artifacts: BTreeMap<ArtifactKind, u64>,
+
+    /// Bytes of the generated files the artifact policy would evict now
+    pub reclaimable: u64,
+
+    /// Recorded space taken by the library against its limit
+    pub quota: QuotaUsage,
+}
+
+impl DiskUsage {
+    /// Bytes of all the generated files
+    pub fn artifacts_total(&self) -> u64 {
+        self.artifacts.values().sum()
+    }
+}
+
+/// A recorded generated file
+struct Artifact {
+    path: String,
+    kind: ArtifactKind,
+    sound_id: String,
+    size: u64,
+    accessed_at: DateTime<Utc>,
+}
+
+impl LocalLibrary {
+    /// Record that a generated file was written or served
+    pub(crate) async fn touch_artifact(&self, kind: ArtifactKind, sound_id: &str, path: &Path) -> Result<()> {
+        let size = std::fs::metadata(path)?.len();
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO artifacts (path, kind, sound_id, size, accessed_at) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(path) DO UPDATE SET size = excluded.size, accessed_at = excluded.accessed_at
+            "#),
+        )
+        .bind(path.to_string_lossy().to_string())
+        .bind(kind.as_str())
+        .bind(sound_id)
+        .bind(size as i64)
+        .bind(Utc::now())
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Stop tracking a generated file that was removed
+    pub(crate) async fn forget_artifact(&self, path: &Path) -> Result<()> {
+        sqlx::query(&self.sql("DELETE FROM artifacts WHERE path = ?"))
+            .bind(path.to_string_lossy().to_string())
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Recorded generated files, least recently used first
+    async fn artifacts(&self) -> Result<Vec<Artifact>> {
+        let rows = sqlx::query(&self.sql(
+            "SELECT path, kind, sound_id, size, accessed_at FROM artifacts ORDER BY accessed_at, path",
+        ))
+        .fetch_all(&self.reader)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(Artifact {
+                    path: row.try_get("path")?,
+                    kind: ArtifactKind::parse(row.try_get("kind")?)?,
+                    sound_id: row.try_get("sound_id")?,
+                    size: row.try_get::<i64, _>("size")? as u64,
+                    accessed_at: row.try_get("accessed_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Recorded generated files, with the IDs of the sounds
+    async fn artifact_state(&self) -> Result<(Vec<Artifact>, HashSet<String>)> {
+        let sounds: HashSet<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds"))
+            .fetch_all(&self.reader)
+            .await?
+            .into_iter()
+            .collect();
+        Ok((self.artifacts().await?, sounds))
+    }
+
+    /// Generated files the artifact policy evicts, least recently used first
+    ///
+    /// Files of deleted sounds and files unused for longer than their kind's
+    /// age limit go first; then the least recently used of each kind until
+    /// the rest fits its size limit.
+    fn evictable(&self, artifacts: Vec<Artifact>, sounds: &HashSet<String>) -> Vec<Artifact> {
+        let now = Utc::now();
+        let (mut evicted, kept): (Vec<_>, Vec<_>) = artifacts.into_iter().partition(|artifact| {
+            let limits = self.artifact_policy.limits(artifact.kind);
+            !sounds.contains(&artifact.sound_id)
+                || limits
+                    .max_age
+                    .is_some_and(|age| artifact.accessed_at < now - chrono::Duration::from(age))
+        });
+
+        let mut totals: BTreeMap<ArtifactKind, u64> = BTreeMap::new();
+        for artifact in &kept {
+            *totals.entry(artifact.kind).or_default() += artifact.size;
+        }
+        for artifact in kept {
+            let total = totals.entry(artifact.kind).or_default();
+            if self.artifact_policy.limits(artifact.kind).max_bytes.is_some_and(|max| *total > max.as_u64()) {
+                *total -= artifact.size;
+                evicted.push(artifact);
+            }
+        }
+        evicted.sort_by(|a, b| (a.accessed_at, &a.path).cmp(&(b.accessed_at, &b.path)));
+
+        evicted
+    }
+
+    /// Compute what [`gc_artifacts`](Self::gc_artifacts) would evict, without
+    /// removing anything
+    pub async fn plan_gc_artifacts(&self) -> Result<GcPlan> {
+        let (artifacts, sounds) = self.artifact_state().await?;
+        let snapshot = gc_snapshot(&artifacts, &sounds)?;
+        let evictions: Vec<PlannedFile> = self
+            .evictable(artifacts, &sounds)
+            .into_iter()
+            .map(|artifact| PlannedFile {
+                path: PathBuf::from(artifact.path),
+                size: artifact.size,
+            })
+            .collect();
+
+        Ok(GcPlan {
+            bytes_reclaimed: evictions.iter().map(|file| file.size).sum(),
+            evictions,
+            snapshot,
+        })
+    }
+
+    /// Evict exactly the files of a plan
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PlanOutdated` if generated files were written, served
+    ///   or removed, or their sounds deleted, since the plan was made
+    pub async fn apply_gc_artifacts(&self, plan: &GcPlan) -> Result<GcReport> {
+        let (artifacts, sounds) = self.artifact_state().await?;
+        check_snapshot("gc_artifacts", &plan.snapshot, &gc_snapshot(&artifacts, &sounds)?)?;
+        self.evict(&plan.evictions).await
+    }
+
+    /// Evict the generated files the artifact policy doesn't keep
+    pub async fn gc_artifacts(&self) -> Result<GcReport> {
+        let plan = self.plan_gc_artifacts().await?;
+        self.evict(&plan.evictions).await
+    }
+
+    async fn evict(&self, files: &[PlannedFile]) -> Result<GcReport> {
+        let previews = self.library_path.join(PREVIEW_DIR);
+        let mut report = GcReport::default();
+        for file in files {
+            let path = file.path.clone();
+            let removed = match self.library_file(&path) {
+                Ok(path) => std::fs::remove_file(&path).or_else(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(e.into()),
+                }),
+                Err(e) => Err(e),
+            };
+            match removed {
+                Ok(()) => {
+                    self.forget_artifact(&path).await?;
+                    // Per-sound folders of the preview directory go once empty
+                    if let Some(dir) = path.parent().filter(|dir| dir.parent() == Some(previews.as_path())) {
+                        let _ = std::fs::remove_dir(dir);
+                    }
+                    report.bytes_freed += file.size;
+                    report.evicted.push(path);
+                }
+                Err(e) => report.errors.push((path, e.to_string())),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Space taken by the sounds' files and by generated files
+    pub async fn disk_usage(&self) -> Result<DiskUsage> {
+        let paths: Vec<String> =
+            sqlx::query_scalar(&self.sql("SELECT path FROM sounds WHERE external = 0 AND path IS NOT NULL"))
+                .fetch_all(&self.reader)
+                .await?;
+
+        let mut usage = DiskUsage {
+            originals: paths
+                .iter()
+                .filter_map(|path| std::fs::metadata(path).ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+            ..Default::default()
+        };
+        for artifact in self.artifacts().await? {
+            *usage.artifacts.entry(artifact.kind).or_default() += artifact.size;
+        }
+        usage.reclaimable = self.plan_gc_artifacts().await?.bytes_reclaimed;
+        usage.quota = self.quota_usage().await?;
+
+        Ok(usage)
+    }
+}
+
+/// Token of the generated files and whether their sounds exist
+fn gc_snapshot(artifacts: &[Artifact], sounds: &HashSet<String>) -> Result<String> {
+    let state: Vec<_> = artifacts
+        .iter()
+        .map(|artifact| (&artifact.path, artifact.size, artifact.accessed_at, sounds.contains(&artifact.sound_id)))
+        .collect();
+    snapshot_token(&state)
+}
@@ -0,0 +1,378 @@
+//! Attribution written into exported files, in each container's own tags
+//!
+//! | Field | WAV | AIFF | CAF |
+//! |-------|-----|------|-----|
+//! | Title | `LIST`/`INFO` `INAM` | `NAME` | `info` `title` |
+//! | Author | `INFO` `IART` | `AUTH` | `info` `artist` |
+//! | License URL | `INFO` `ICOP` | `(c) ` | `info` `copyright` |
+//! | Source URL | `INFO` `ISRC` | `ANNO` | `info` `comments` |
+//!
+//! WAV files also get the whole attribution as their `bext` description.
+
+use crate::audio::ChannelMix;
+use crate::embedded::with_bext_description;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// WAV chunks holding audio rather than metadata
+const WAV_AUDIO_CHUNKS: [&[u8; 4]; 3] = [b"fmt ", b"fact", b"data"];
+
+/// AIFF chunks holding audio rather than metadata
+const AIFF_AUDIO_CHUNKS: [&[u8; 4]; 3] = [b"COMM", b"SSND", b"FVER"];
+
+/// CAF chunks holding audio rather than metadata
+const CAF_AUDIO_CHUNKS: [&[u8; 4]; 5] = [b"desc", b"data", b"pakt", b"kuki", b"chan"];
+
+/// Options of [`SoundVault::export_sound_with_options`](crate::SoundVault::export_sound_with_options)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// What to do with the channels
+    pub mix: ChannelMix,
+
+    /// Write the sound's title, author, license URL and source URL into the
+    /// file's tags, replacing those it had
+    pub attribution: bool,
+
+    /// Remove every tag the file had, keeping only its audio
+    pub strip_metadata: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            mix: ChannelMix::Preserve,
+            attribution: true,
+            strip_metadata: false,
+        }
+    }
+}
+
+/// Credits of a sound, as written into exported files
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attribution {
+    /// Name of the sound
+    pub title: Option<String>,
+
+    /// Who made it: the custom `author` value, or the Freesound uploader
+    pub author: Option<String>,
+
+    /// Address of its license
+    pub license_url: Option<String>,
+
+    /// Page of the sound at the source it was downloaded from
+    pub source_url: Option<String>,
+}
+
+impl Attribution {
+    /// One-line credit, e.g. `Rain by ana (https://...), from https://...`
+    pub fn credit(&self) -> String {
+        let mut credit = self.title.clone().unwrap_or_default();
+        if let Some(author) = &self.author {
+            credit.push_str(&format!(" by {}", author));
+        }
+        if let Some(license) = &self.license_url {
+            credit.push_str(&format!(" ({})", license));
+        }
+        if let Some(source) = &self.source_url {
+            credit.push_str(&format!(", from {}", source));
+        }
+        credit.trim().to_string()
+    }
+}
+
+/// Read the attribution tags of an exported WAV, AIFF or CAF file
+///
+/// # Errors
+///
+/// * `VaultError::Io` if the file can't be read
+/// * `VaultError::InvalidOperation` if it isn't a WAV, AIFF or CAF file
+pub fn read_attribution(path: &Path) -> Result<Attribution> {
+    let bytes = std::fs::read(path)?;
+    let mut attribution = Attribution::default();
+    let set = |field: &mut Option<String>, value: &[u8]| {
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        let value = String::from_utf8_lossy(&value[..end]).trim().to_string();
+        if !value.is_empty() {
+            *field = Some(value);
+        }
+    };
+
+    match container(&bytes)? {
+        Container::Wav => {
+            for (id, data) in chunks(&bytes, false)? {
+                if id != *b"LIST" || !data.starts_with(b"INFO") {
+                    continue;
+                }
+                for (id, value) in chunks_from(data, 4, false)? {
+                    match &id {
+                        b"INAM" => set(&mut attribution.title, value),
+                        b"IART" => set(&mut attribution.author, value),
+                        b"ICOP" => set(&mut attribution.license_url, value),
+                        b"ISRC" => set(&mut attribution.source_url, value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Container::Aiff => {
+            for (id, value) in chunks(&bytes, true)? {
+                match &id {
+                    b"NAME" => set(&mut attribution.title, value),
+                    b"AUTH" => set(&mut attribution.author, value),
+                    b"(c) " => set(&mut attribution.license_url, value),
+                    b"ANNO" => set(&mut attribution.source_url, value),
+                    _ => {}
+                }
+            }
+        }
+        Container::Caf => {
+            for (id, data) in caf_chunks(&bytes)? {
+                if id != *b"info" || data.len() < 4 {
+                    continue;
+                }
+                let mut strings = data[4..].split(|&b| b == 0);
+                while let (Some(key), Some(value)) = (strings.next(), strings.next()) {
+                    match key {
+                        b"title" => set(&mut attribution.title, value),
+                        b"artist" => set(&mut attribution.author, value),
+                        b"copyright" => set(&mut attribution.license_url, value),
+                        b"comments" => set(&mut attribution.source_url, value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(attribution)
+}
+
+/// Containers whose tags can be written
+enum Container {
+    Wav,
+    Aiff,
+    Caf,
+}
+
+fn container(bytes: &[u8]) -> Result<Container> {
+    match (bytes.get(0..4), bytes.get(8..12)) {
+        (Some(b"RIFF"), Some(b"WAVE")) => Ok(Container::Wav),
+        (Some(b"FORM"), Some(b"AIFF" | b"AIFC")) => Ok(Container::Aiff),
+        (Some(b"caff"), _) => Ok(Container::Caf),
+        _ => Err(VaultError::InvalidOperation("Not a WAV, AIFF or CAF file".to_string())),
+    }
+}
+
+/// Chunks of a RIFF or IFF file, after its 12-byte header
+fn chunks(bytes: &[u8], big_endian: bool) -> Result<Vec<([u8; 4], &[u8])>> {
+    chunks_from(bytes, 12, big_endian)
+}
+
+/// Chunks from `start` to the end of `bytes`, skipping pad bytes
+fn chunks_from(bytes: &[u8], start: usize, big_endian: bool) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= bytes.len() {
+        let id: [u8; 4] = bytes[pos..pos + 4].try_into().unwrap_or_default();
+        let size: [u8; 4] = bytes[pos + 4..pos + 8].try_into().unwrap_or_default();
+        let len = if big_endian { u32::from_be_bytes(size) } else { u32::from_le_bytes(size) } as usize;
+        let data = bytes.get(pos + 8..pos + 8 + len).ok_or_else(|| {
+            VaultError::InvalidOperation(format!("{} chunk runs past the end of the file", String::from_utf8_lossy(&id)))
+        })?;
+        chunks.push((id, data));
+        pos += 8 + len + len % 2;
+    }
+    Ok(chunks)
+}
+
+/// Append a RIFF or IFF chunk, padded to an even length
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8], big_endian: bool) {
+    let len = data.len() as u32;
+    out.extend_from_slice(id);
+    out.extend_from_slice(&if big_endian { len.to_be_bytes() } else { len.to_le_bytes() });
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Chunks of a CAF file, after its 8-byte header
+fn caf_chunks(bytes: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= bytes.len() {
+        let id: [u8; 4] = bytes[pos..pos + 4].try_into().unwrap_or_default();
+        let size = i64::from_be_bytes(bytes[pos + 4..pos + 12].try_into().unwrap_or_default());
+        // A data chunk of unknown size runs to the end of the file
+        let end = if size < 0 { bytes.len() } else { pos + 12 + size as usize };
+        let data = bytes.get(pos + 12..end).ok_or_else(|| {
+            VaultError::InvalidOperation(format!("{} chunk runs past the end of the file", String::from_utf8_lossy(&id)))
+        })?;
+        chunks.push((id, data));
+        pos = end;
+    }
+    Ok(chunks)
+}
+
+/// Tagged text, NUL-terminated
+fn text(value: &str) -> Vec<u8> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Rewrite the tags of an encoded file
+///
+/// Files in containers without writable tags are returned unchanged.
+pub(crate) fn retag(bytes: Vec<u8>, attribution: Option<&Attribution>, strip: bool) -> Result<Vec<u8>> {
+    let Ok(kind) = container(&bytes) else {
+        return Ok(bytes);
+    };
+    if attribution.is_none() && !strip {
+        return Ok(bytes);
+    }
+
+    match kind {
+        Container::Wav => retag_wav(&bytes, attribution, strip),
+        Container::Aiff => retag_aiff(&bytes, attribution, strip),
+        Container::Caf => retag_caf(&bytes, attribution, strip),
+    }
+}
+
+fn retag_wav(bytes: &[u8], attribution: Option<&Attribution>, strip: bool) -> Result<Vec<u8>> {
+    let mut out = bytes[0..12].to_vec();
+    for (id, data) in chunks(bytes, false)? {
+        let info = id == *b"LIST" && data.starts_with(b"INFO");
+        let keep = if strip { WAV_AUDIO_CHUNKS.contains(&&id) } else { !(info && attribution.is_some()) };
+        if id == *b"data"
+            && let Some(attribution) = attribution
+        {
+            let mut list = b"INFO".to_vec();
+            for (id, value) in [
+                (b"INAM", &attribution.title),
+                (b"IART", &attribution.author),
+                (b"ICOP", &attribution.license_url),
+                (b"ISRC", &attribution.source_url),
+            ] {
+                if let Some(value) = value {
+                    push_chunk(&mut list, id, &text(value), false);
+                }
+            }
+            push_chunk(&mut out, b"LIST", &list, false);
+        }
+        if keep {
+            push_chunk(&mut out, &id, data, false);
+        }
+    }
+    finish_riff(&mut out, false)?;
+
+    match attribution {
+        Some(attribution) => with_bext_description(&out, &truncated(&attribution.credit(), 256)),
+        None => Ok(out),
+    }
+}
+
+fn retag_aiff(bytes: &[u8], attribution: Option<&Attribution>, strip: bool) -> Result<Vec<u8>> {
+    let mut out = bytes[0..12].to_vec();
+    for (id, data) in chunks(bytes, true)? {
+        let tag = [b"NAME", b"AUTH", b"(c) ", b"ANNO"].contains(&&id);
+        let keep = if strip { AIFF_AUDIO_CHUNKS.contains(&&id) } else { !(tag && attribution.is_some()) };
+        if id == *b"SSND"
+            && let Some(attribution) = attribution
+        {
+            for (id, value) in [
+                (b"NAME", &attribution.title),
+                (b"AUTH", &attribution.author),
+                (b"(c) ", &attribution.license_url),
+                (b"ANNO", &attribution.source_url),
+            ] {
+                if let Some(value) = value {
+                    push_chunk(&mut out, id, value.as_bytes(), true);
+                }
+            }
+        }
+        if keep {
+            push_chunk(&mut out, &id, data, true);
+        }
+    }
+    finish_riff(&mut out, true)?;
+    Ok(out)
+}
+
+fn retag_caf(bytes: &[u8], attribution: Option<&Attribution>, strip: bool) -> Result<Vec<u8>> {
+    let push = |out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]| {
+        out.extend_from_slice(id);
+        out.extend_from_slice(&(data.len() as i64).to_be_bytes());
+        out.extend_from_slice(data);
+    };
+
+    let mut out = bytes[0..8].to_vec();
+    for (id, data) in caf_chunks(bytes)? {
+        let keep = if strip { CAF_AUDIO_CHUNKS.contains(&&id) } else { !(id == *b"info" && attribution.is_some()) };
+        if id == *b"data"
+            && let Some(attribution) = attribution
+        {
+            let entries: Vec<(&str, &String)> = [
+                ("title", &attribution.title),
+                ("artist", &attribution.author),
+                ("copyright", &attribution.license_url),
+                ("comments", &attribution.source_url),
+            ]
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+            .collect();
+            let mut info = (entries.len() as u32).to_be_bytes().to_vec();
+            for (key, value) in entries {
+                info.extend(text(key));
+                info.extend(text(value));
+            }
+            push(&mut out, b"info", &info);
+        }
+        if keep {
+            push(&mut out, &id, data);
+        }
+    }
+    Ok(out)
+}
+
+/// Set the size of the outer chunk of a RIFF or IFF file
+fn finish_riff(out: &mut [u8], big_endian: bool) -> Result<()> {
+    let len = u32::try_from(out.len() - 8)
+        .map_err(|_| VaultError::InvalidOperation("File would exceed 4 GiB".to_string()))?;
+    out[4..8].copy_from_slice(&if big_endian { len.to_be_bytes() } else { len.to_le_bytes() });
+    Ok(())
+}
+
+/// The longest prefix of `text` within `max` bytes
+fn truncated(text: &str, max: usize) -> String {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+impl LocalLibrary {
+    /// Credits of a sound, from its structured license and author fields
+    pub(crate) async fn attribution(&self, metadata: &SoundMetadata) -> Result<Attribution> {
+        let nonempty = |value: Option<&String>| value.filter(|value| !value.trim().is_empty()).cloned();
+
+        let license_url = metadata.license_info().canonical_url();
+
+        let source_url = match nonempty(metadata.get_custom("freesound_url")) {
+            Some(url) => Some(url),
+            None => self.provenance(&metadata.id).await?.into_iter().rev().find_map(|entry| entry.source_url),
+        };
+
+        Ok(Attribution {
+            title: nonempty(Some(&metadata.name)),
+            author: nonempty(metadata.get_custom("author")).or_else(|| nonempty(metadata.get_custom("freesound_username"))),
+            license_url,
+            source_url,
+        })
+    }
+}
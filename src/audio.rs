@@ -0,0 +1,1003 @@
+//! Probing, decoding and encoding of uncompressed audio files
+
+use crate::error::{Result, VaultError};
+use crate::artifacts::ArtifactKind;
+use crate::attribution::{ExportOptions, retag};
+use crate::integrity::PREVIEW_DIR;
+use crate::levels::stream_waveform;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::pack::split_packed;
+use crate::paths::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Container format of an audio file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioFormat {
+    /// RIFF WAVE
+    Wav,
+    /// AIFF or AIFF-C
+    Aiff,
+    /// Core Audio Format
+    Caf,
+}
+
+/// How samples are stored in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SampleFormat {
+    /// Signed (or 8-bit WAV unsigned) integer PCM of the given bit depth
+    Int(u16),
+    /// IEEE floating point of the given bit depth
+    Float(u16),
+    /// A compressed codec; the file can be probed but not decoded
+    Compressed,
+}
+
+/// What to do with the channels of a sound when writing it out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelMix {
+    /// Keep every channel as is
+    #[default]
+    Preserve,
+    /// Fold all channels into two
+    Stereo,
+    /// Fold all channels into one
+    Mono,
+}
+
+/// Technical properties of an audio file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AudioInfo {
+    /// Container format
+    pub format: AudioFormat,
+
+    /// Number of channels
+    pub channels: u16,
+
+    /// Frames per second
+    pub sample_rate: u32,
+
+    /// Sample encoding
+    pub sample_format: SampleFormat,
+
+    /// Number of frames (samples per channel)
+    pub frames: u64,
+}
+
+/// Where the samples of a file are and how to read them
+#[derive(Debug, Clone, Copy)]
+struct DataLayout {
+    offset: u64,
+    len: u64,
+    bytes_per_sample: usize,
+    big_endian: bool,
+}
+
+impl AudioInfo {
+    /// Describe PCM audio to be encoded
+    pub fn new(format: AudioFormat, channels: u16, sample_rate: u32, sample_format: SampleFormat) -> Self {
+        Self {
+            format,
+            channels,
+            sample_rate,
+            sample_format,
+            frames: 0,
+        }
+    }
+
+    /// Duration in seconds
+    pub fn duration(&self) -> f32 {
+        if self.sample_rate == 0 {
+            0.0
+        } else {
+            (self.frames as f64 / self.sample_rate as f64) as f32
+        }
+    }
+}
+
+impl ChannelMix {
+    /// Number of channels written for a source with `channels` channels
+    pub fn output_channels(&self, channels: u16) -> u16 {
+        match self {
+            Self::Preserve => channels,
+            Self::Stereo => channels.min(2),
+            Self::Mono => 1.min(channels),
+        }
+    }
+}
+
+/// Read the technical properties of a WAV, AIFF or CAF file from its headers
+pub fn probe_file(path: &Path) -> Result<AudioInfo> {
+    probe(&mut std::fs::File::open(path)?)
+}
+
+/// Read the technical properties of audio from any seekable reader
+pub(crate) fn probe<R: Read + Seek>(reader: &mut R) -> Result<AudioInfo> {
+    Ok(parse(reader)?.0)
+}
+
+/// Decode a WAV, AIFF or CAF file
+///
+/// # Returns
+///
+/// The file's properties and its interleaved samples scaled to `-1.0..=1.0`
+pub fn decode_file(path: &Path) -> Result<(AudioInfo, Vec<f32>)> {
+    let mut file = std::fs::File::open(path)?;
+    decode(&mut file)
+}
+
+/// Decode audio from any seekable reader
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{AudioFormat, AudioInfo, SampleFormat, decode, encode};
+/// use std::io::Cursor;
+///
+/// // One second over four ambisonic channels, then six-channel CAF and WAV
+/// for (format, channels) in [(AudioFormat::Aiff, 4), (AudioFormat::Caf, 6), (AudioFormat::Wav, 6)] {
+///     let info = AudioInfo::new(format, channels, 8000, SampleFormat::Int(24));
+///     let samples = vec![0.25; 8000 * channels as usize];
+///     let bytes = encode(&info, &samples).unwrap();
+///
+///     let (decoded_info, decoded) = decode(&mut Cursor::new(bytes)).unwrap();
+///     assert_eq!(decoded_info.channels, channels);
+///     assert_eq!(decoded_info.duration(), 1.0);
+///     assert_eq!(decoded, samples);
+/// }
+/// ```
+pub fn decode<R: Read + Seek>(reader: &mut R) -> Result<(AudioInfo, Vec<f32>)> {
+    let (info, layout) = parse(reader)?;
+    let layout = layout.ok_or_else(|| unsupported("compressed audio cannot be decoded"))?;
+
+    reader.seek(SeekFrom::Start(layout.offset))?;
+    let mut bytes = Vec::new();
+    reader.take(layout.len).read_to_end(&mut bytes)?;
+
+    let samples = bytes
+        .chunks_exact(layout.bytes_per_sample)
+        .map(|sample| read_sample(sample, &info, &layout))
+        .collect();
+
+    Ok((info, samples))
+}
+
+/// Samples of a file decoded a fixed number of frames at a time, so a file
+/// of any length is read in bounded memory
+pub(crate) struct SampleChunks<R> {
+    reader: R,
+    info: AudioInfo,
+    layout: DataLayout,
+    /// Number of whole frames in the file
+    frames: u64,
+    /// Bytes of whole frames not read yet
+    remaining: u64,
+    /// Bytes read at a time
+    chunk_len: usize,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read + Seek> SampleChunks<R> {
+    pub fn new(mut reader: R, frames_per_chunk: usize) -> Result<Self> {
+        let (info, layout) = parse(&mut reader)?;
+        let layout = layout.ok_or_else(|| unsupported("compressed audio cannot be decoded"))?;
+
+        // A truncated file holds fewer frames than its header says
+        let frame = (layout.bytes_per_sample * info.channels.max(1) as usize) as u64;
+        let end = reader.seek(SeekFrom::End(0))?;
+        let len = layout.len.min(end.saturating_sub(layout.offset));
+        reader.seek(SeekFrom::Start(layout.offset))?;
+
+        Ok(Self {
+            reader,
+            info,
+            layout,
+            frames: len / frame,
+            remaining: len - len % frame,
+            chunk_len: frame as usize * frames_per_chunk.max(1),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Properties of the file
+    pub fn info(&self) -> AudioInfo {
+        self.info
+    }
+
+    /// Number of whole frames in the file
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Decode the next chunk into `samples`, interleaved
+    ///
+    /// # Returns
+    ///
+    /// `false`, leaving `samples` empty, once every frame was read
+    pub fn next_chunk(&mut self, samples: &mut Vec<f32>) -> Result<bool> {
+        samples.clear();
+        if self.remaining == 0 {
+            return Ok(false);
+        }
+
+        let len = (self.chunk_len as u64).min(self.remaining) as usize;
+        self.buffer.resize(len, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        self.remaining -= len as u64;
+        samples.extend(
+            self.buffer
+                .chunks_exact(self.layout.bytes_per_sample)
+                .map(|sample| read_sample(sample, &self.info, &self.layout)),
+        );
+        Ok(true)
+    }
+}
+
+/// Integer samples of a file, with the bytes around them kept verbatim
+pub(crate) struct PcmParts {
+    /// Properties of the file
+    pub info: AudioInfo,
+    /// Bytes before the samples
+    pub header: Vec<u8>,
+    /// Interleaved samples at their stored width
+    pub samples: Vec<i32>,
+    /// Bytes after the samples
+    pub trailer: Vec<u8>,
+    /// Stored width of a sample
+    pub bytes_per_sample: usize,
+    /// Byte order of the samples
+    pub big_endian: bool,
+}
+
+/// Split an integer PCM file into its samples and surrounding bytes
+///
+/// [`join_pcm`] puts the parts back together byte for byte.
+pub(crate) fn split_pcm(bytes: &[u8]) -> Result<PcmParts> {
+    let (info, layout) = parse(&mut Cursor::new(bytes))?;
+    let layout = layout
+        .filter(|_| matches!(info.sample_format, SampleFormat::Int(_)))
+        .ok_or_else(|| unsupported("only integer PCM can be split"))?;
+
+    let frame = layout.bytes_per_sample * info.channels as usize;
+    let start = (layout.offset as usize).min(bytes.len());
+    let len = (layout.len as usize).min(bytes.len() - start);
+    let end = start + len - len % frame;
+    let unsigned_8bit = info.format == AudioFormat::Wav;
+
+    Ok(PcmParts {
+        info,
+        header: bytes[..start].to_vec(),
+        samples: bytes[start..end]
+            .chunks_exact(layout.bytes_per_sample)
+            .map(|sample| read_raw_int(sample, layout.big_endian, unsigned_8bit))
+            .collect(),
+        trailer: bytes[end..].to_vec(),
+        bytes_per_sample: layout.bytes_per_sample,
+        big_endian: layout.big_endian,
+    })
+}
+
+/// Rebuild the file [`split_pcm`] took apart
+pub(crate) fn join_pcm(parts: &PcmParts) -> Vec<u8> {
+    let unsigned_8bit = parts.info.format == AudioFormat::Wav;
+    let mut out = Vec::with_capacity(parts.header.len() + parts.samples.len() * parts.bytes_per_sample + parts.trailer.len());
+    out.extend(&parts.header);
+    for &sample in &parts.samples {
+        write_raw_int(&mut out, sample, parts.bytes_per_sample, parts.big_endian, unsigned_8bit);
+    }
+    out.extend(&parts.trailer);
+    out
+}
+
+/// Encode interleaved samples as an uncompressed file
+///
+/// Integer formats of 8, 16, 24 and 32 bits and 32-bit floats are supported;
+/// `info.frames` is ignored and derived from the samples.
+pub fn encode(info: &AudioInfo, samples: &[f32]) -> Result<Vec<u8>> {
+    let (bits, float) = match info.sample_format {
+        SampleFormat::Int(bits @ (8 | 16 | 24 | 32)) => (bits, false),
+        SampleFormat::Float(32) => (32, true),
+        other => return Err(unsupported(&format!("cannot encode {:?}", other))),
+    };
+    if info.channels == 0 {
+        return Err(unsupported("no channels"));
+    }
+
+    let big_endian = info.format != AudioFormat::Wav;
+    let bytes_per_sample = bits as usize / 8;
+    let mut data = Vec::with_capacity(samples.len() * bytes_per_sample);
+    for &sample in samples {
+        if float {
+            write_float(&mut data, sample, big_endian);
+        } else {
+            write_int(&mut data, sample, bytes_per_sample, big_endian, info.format == AudioFormat::Wav);
+        }
+    }
+
+    let frames = (samples.len() / info.channels as usize) as u32;
+    let block_align = info.channels * bits / 8;
+    let mut out = Vec::with_capacity(data.len() + 128);
+
+    match info.format {
+        AudioFormat::Wav => {
+            out.extend(wav_header(info.channels, info.sample_rate, bits, float, data.len() as u32));
+            out.extend(&data);
+            if data.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+        AudioFormat::Aiff => {
+            let comm_len: u32 = if float { 24 } else { 18 };
+            let form_len = 4 + 8 + comm_len + 8 + 8 + data.len() as u32 + data.len() as u32 % 2;
+            out.extend(b"FORM");
+            out.extend(form_len.to_be_bytes());
+            out.extend(if float { b"AIFC" } else { b"AIFF" });
+            out.extend(b"COMM");
+            out.extend(comm_len.to_be_bytes());
+            out.extend(info.channels.to_be_bytes());
+            out.extend(frames.to_be_bytes());
+            out.extend(bits.to_be_bytes());
+            out.extend(extended_from_rate(info.sample_rate));
+            if float {
+                out.extend(b"fl32");
+                out.extend([0, 0]);
+            }
+            out.extend(b"SSND");
+            out.extend((8 + data.len() as u32).to_be_bytes());
+            out.extend([0u8; 8]);
+            out.extend(&data);
+            if data.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+        AudioFormat::Caf => {
+            out.extend(b"caff");
+            out.extend(1u16.to_be_bytes());
+            out.extend(0u16.to_be_bytes());
+            out.extend(b"desc");
+            out.extend(32i64.to_be_bytes());
+            out.extend((info.sample_rate as f64).to_be_bytes());
+            out.extend(b"lpcm");
+            out.extend((if float { 1u32 } else { 0u32 }).to_be_bytes());
+            out.extend((block_align as u32).to_be_bytes());
+            out.extend(1u32.to_be_bytes());
+            out.extend((info.channels as u32).to_be_bytes());
+            out.extend((bits as u32).to_be_bytes());
+            out.extend(b"data");
+            out.extend((4 + data.len() as i64).to_be_bytes());
+            out.extend(0u32.to_be_bytes());
+            out.extend(&data);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Header of a WAV file holding `data_len` bytes of samples
+fn wav_header(channels: u16, sample_rate: u32, bits: u16, float: bool, data_len: u32) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let mut out = Vec::with_capacity(44);
+    out.extend(b"RIFF");
+    out.extend((36 + data_len + data_len % 2).to_le_bytes());
+    out.extend(b"WAVEfmt ");
+    out.extend(16u32.to_le_bytes());
+    out.extend((if float { 3u16 } else { 1u16 }).to_le_bytes());
+    out.extend(channels.to_le_bytes());
+    out.extend(sample_rate.to_le_bytes());
+    out.extend((sample_rate * block_align as u32).to_le_bytes());
+    out.extend(block_align.to_le_bytes());
+    out.extend(bits.to_le_bytes());
+    out.extend(b"data");
+    out.extend(data_len.to_le_bytes());
+    out
+}
+
+/// WAV file written a chunk at a time, so a sound of any length is encoded
+/// in bounded memory
+pub(crate) struct WavWriter<W> {
+    writer: W,
+    bytes_per_sample: usize,
+    float: bool,
+    /// Bytes of samples written so far
+    written: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> WavWriter<W> {
+    /// Start a file of `total_frames` frames in one of the formats
+    /// [`encode`] writes; the frames written must add up to it
+    pub fn new(mut writer: W, channels: u16, sample_rate: u32, format: SampleFormat, total_frames: u64) -> Result<Self> {
+        let (bits, float) = match format {
+            SampleFormat::Int(bits @ (8 | 16 | 24 | 32)) => (bits, false),
+            SampleFormat::Float(32) => (32, true),
+            other => return Err(unsupported(&format!("cannot encode {:?}", other))),
+        };
+        if channels == 0 {
+            return Err(unsupported("no channels"));
+        }
+        let data_len = u32::try_from(total_frames * (channels * bits / 8) as u64)
+            .map_err(|_| unsupported("WAV files hold at most 4 GB of samples"))?;
+        writer.write_all(&wav_header(channels, sample_rate, bits, float, data_len))?;
+        Ok(Self {
+            writer,
+            bytes_per_sample: bits as usize / 8,
+            float,
+            written: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Encode interleaved samples
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.buffer.clear();
+        for &sample in samples {
+            if self.float {
+                write_float(&mut self.buffer, sample, false);
+            } else {
+                write_int(&mut self.buffer, sample, self.bytes_per_sample, false, true);
+            }
+        }
+        self.writer.write_all(&self.buffer)?;
+        self.written += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Pad the samples to an even length and hand back the writer
+    pub fn finish(mut self) -> Result<W> {
+        if self.written % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        Ok(self.writer)
+    }
+}
+
+/// Fold interleaved samples into fewer channels
+///
+/// Mono averages every channel. Stereo keeps mono and stereo sources as they
+/// are and otherwise averages even-numbered channels into the left and
+/// odd-numbered channels into the right output.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{ChannelMix, downmix};
+///
+/// // One frame of four channels
+/// let frame = [1.0, 0.0, 0.5, 0.0];
+/// assert_eq!(downmix(&frame, 4, ChannelMix::Stereo), vec![0.75, 0.0]);
+/// assert_eq!(downmix(&frame, 4, ChannelMix::Mono), vec![0.375]);
+/// ```
+pub fn downmix(samples: &[f32], channels: u16, mix: ChannelMix) -> Vec<f32> {
+    let channels = channels as usize;
+    let output = mix.output_channels(channels as u16) as usize;
+    if channels == 0 || output == channels {
+        return samples.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(samples.len() / channels * output);
+    for frame in samples.chunks_exact(channels) {
+        for side in 0..output {
+            let (sum, count) = frame
+                .iter()
+                .enumerate()
+                .filter(|(channel, _)| output == 1 || channel % 2 == side)
+                .fold((0.0, 0), |(sum, count), (_, sample)| (sum + sample, count + 1));
+            out.push(sum / count as f32);
+        }
+    }
+    out
+}
+
+/// Closest sample format [`encode`] can write
+pub(crate) fn writable_format(format: SampleFormat) -> SampleFormat {
+    match format {
+        SampleFormat::Float(_) => SampleFormat::Float(32),
+        SampleFormat::Int(bits) => SampleFormat::Int(bits.div_ceil(8).clamp(1, 4) * 8),
+        SampleFormat::Compressed => SampleFormat::Int(16),
+    }
+}
+
+/// Peak level of the mono mixdown over `buckets` equal slices of the sound
+pub fn waveform(samples: &[f32], channels: u16, buckets: usize) -> Vec<f32> {
+    let mono = downmix(samples, channels, ChannelMix::Mono);
+    if buckets == 0 || mono.is_empty() {
+        return vec![0.0; buckets];
+    }
+
+    (0..buckets)
+        .map(|bucket| {
+            let start = bucket * mono.len() / buckets;
+            let end = ((bucket + 1) * mono.len() / buckets).max(start + 1).min(mono.len());
+            mono[start.min(end)..end].iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+        })
+        .collect()
+}
+
+/// Name of an exported file: the stored file's, with the extension of the
+/// format read from it
+pub(crate) fn export_file_name(metadata: &SoundMetadata) -> PathBuf {
+    let path = metadata.path.as_deref().unwrap_or(Path::new(&metadata.id));
+    // A packed sound is named after its archive entry
+    let entry = split_packed(path).filter(|_| metadata.packed).map(|(_, entry)| PathBuf::from(entry));
+    let path = entry.as_deref().unwrap_or(path);
+    let mut name = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+    if let Some(format) = metadata.format {
+        name.set_extension(format.extension());
+    }
+    name
+}
+
+impl LocalLibrary {
+    /// Write a sound's file to `destination`, optionally folding its channels
+    ///
+    /// With [`ChannelMix::Preserve`] the original file is written byte for
+    /// byte; otherwise it is decoded, downmixed and re-encoded in its own
+    /// container and bit depth.
+    pub async fn export_sound(&self, id: &str, destination: &Path, mix: ChannelMix) -> Result<()> {
+        let options = ExportOptions {
+            mix,
+            attribution: false,
+            strip_metadata: false,
+        };
+        self.export_sound_with_options(id, destination, &options).await
+    }
+
+    /// Write a sound's file to `destination`, rewriting its tags as the
+    /// options say
+    ///
+    /// The audio is written as by [`export_sound`](Self::export_sound); only
+    /// the tags of WAV, AIFF and CAF files change. Files in other formats are
+    /// written as stored.
+    ///
+    /// If `destination` is a directory, the file is written into it, named
+    /// after the stored file with the extension of its true format.
+    pub async fn export_sound_with_options(&self, id: &str, destination: &Path, options: &ExportOptions) -> Result<()> {
+        let metadata = self.stored_sound(id).await?;
+        Self::check_not_expired(&metadata)?;
+        let mut bytes = self.sound_bytes(&metadata)?;
+
+        let mix = options.mix;
+        if mix != ChannelMix::Preserve {
+            let (info, samples) = decode(&mut Cursor::new(bytes))?;
+            let output = AudioInfo::new(
+                info.format,
+                mix.output_channels(info.channels),
+                info.sample_rate,
+                writable_format(info.sample_format),
+            );
+            bytes = encode(&output, &downmix(&samples, info.channels, mix))?;
+        }
+
+        let attribution = match options.attribution {
+            true => Some(self.attribution(&metadata).await?),
+            false => None,
+        };
+        let bytes = retag(bytes, attribution.as_ref(), options.strip_metadata)?;
+        let destination = match destination.is_dir() {
+            true => destination.join(export_file_name(&metadata)),
+            false => destination.to_path_buf(),
+        };
+        write_atomic(&destination, &bytes).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to export sound: {}", e))
+        })?;
+
+        Ok(())
+    }
+
+    /// Generate a 16-bit stereo (or mono) WAV preview of a sound
+    ///
+    /// # Returns
+    ///
+    /// The path of the preview, under the library's preview directory
+    pub async fn generate_preview(&self, id: &str) -> Result<PathBuf> {
+        let (info, samples) = decode(&mut Cursor::new(self.sound_content(id).await?))?;
+
+        let output = AudioInfo::new(
+            AudioFormat::Wav,
+            ChannelMix::Stereo.output_channels(info.channels),
+            info.sample_rate,
+            SampleFormat::Int(16),
+        );
+        let bytes = encode(&output, &downmix(&samples, info.channels, ChannelMix::Stereo))?;
+
+        let dir = self.library_path.join(PREVIEW_DIR);
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to create preview directory: {}", e))
+        })?;
+        let preview = dir.join(format!("{}.wav", id));
+        write_atomic(&preview, &bytes).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to write preview: {}", e))
+        })?;
+        self.touch_artifact(ArtifactKind::Preview, id, &preview).await?;
+
+        Ok(preview)
+    }
+
+    /// Peak levels of a sound's mono mixdown, one per bucket
+    ///
+    /// The file is decoded a chunk at a time on the background job queue.
+    pub async fn sound_waveform(&self, id: &str, buckets: usize) -> Result<Vec<f32>> {
+        let content = self.sound_stream(&self.stored_sound(id).await?)?;
+        self.jobs
+            .run(move || content.read(|reader| stream_waveform(reader, buckets)))
+            .await
+    }
+
+    /// Original content of a sound's file
+    async fn sound_content(&self, id: &str) -> Result<Vec<u8>> {
+        let metadata = self.stored_sound(id).await?;
+        self.sound_bytes(&metadata)
+    }
+}
+
+/// Walk the chunks of a file and collect its properties
+fn parse<R: Read + Seek>(reader: &mut R) -> Result<(AudioInfo, Option<DataLayout>)> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header).map_err(|_| unsupported("file too short"))?;
+
+    match (&header[0..4], &header[8..12]) {
+        (b"RIFF", b"WAVE") => parse_wav(reader),
+        (b"FORM", b"AIFF") => parse_aiff(reader, false),
+        (b"FORM", b"AIFC") => parse_aiff(reader, true),
+        (b"caff", _) => {
+            reader.seek(SeekFrom::Start(8))?;
+            parse_caf(reader)
+        }
+        _ => Err(unsupported("not a WAV, AIFF or CAF file")),
+    }
+}
+
+fn parse_wav<R: Read + Seek>(reader: &mut R) -> Result<(AudioInfo, Option<DataLayout>)> {
+    let mut fmt = None;
+    let mut data = None;
+
+    while let Some((id, len)) = read_chunk_header(reader, false)? {
+        match &id {
+            b"fmt " => fmt = Some(read_chunk(reader, len)?),
+            b"data" => {
+                data = Some((reader.stream_position()?, len));
+                reader.seek(SeekFrom::Current(len as i64))?;
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+        if len % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+
+    let fmt = fmt.filter(|f| f.len() >= 16).ok_or_else(|| unsupported("missing fmt chunk"))?;
+    let (offset, len) = data.ok_or_else(|| unsupported("missing data chunk"))?;
+
+    let mut tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+    let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+    let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+    let block_align = u16::from_le_bytes([fmt[12], fmt[13]]);
+    let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+    if tag == 0xFFFE && fmt.len() >= 26 {
+        // WAVE_FORMAT_EXTENSIBLE keeps the real format in its sub-format GUID
+        tag = u16::from_le_bytes([fmt[24], fmt[25]]);
+    }
+
+    let sample_format = match tag {
+        1 => SampleFormat::Int(bits),
+        3 => SampleFormat::Float(bits),
+        _ => SampleFormat::Compressed,
+    };
+    let frames = if block_align > 0 { len / block_align as u64 } else { 0 };
+    let layout = layout(sample_format, offset, len, channels, block_align as usize, false);
+
+    Ok((
+        AudioInfo {
+            format: AudioFormat::Wav,
+            channels,
+            sample_rate,
+            sample_format,
+            frames,
+        },
+        layout,
+    ))
+}
+
+fn parse_aiff<R: Read + Seek>(reader: &mut R, aifc: bool) -> Result<(AudioInfo, Option<DataLayout>)> {
+    let mut comm = None;
+    let mut data = None;
+
+    while let Some((id, len)) = read_chunk_header(reader, true)? {
+        match &id {
+            b"COMM" => comm = Some(read_chunk(reader, len)?),
+            b"SSND" => {
+                let mut offset = [0u8; 8];
+                reader.read_exact(&mut offset)?;
+                let skip = u32::from_be_bytes([offset[0], offset[1], offset[2], offset[3]]) as u64;
+                data = Some((reader.stream_position()? + skip, len.saturating_sub(8 + skip)));
+                reader.seek(SeekFrom::Current(len as i64 - 8))?;
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+        if len % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+
+    let comm = comm.filter(|c| c.len() >= 18).ok_or_else(|| unsupported("missing COMM chunk"))?;
+    let channels = u16::from_be_bytes([comm[0], comm[1]]);
+    let frames = u32::from_be_bytes([comm[2], comm[3], comm[4], comm[5]]) as u64;
+    let bits = u16::from_be_bytes([comm[6], comm[7]]);
+    let sample_rate = rate_from_extended(&comm[8..18]);
+
+    let compression = if aifc && comm.len() >= 22 { &comm[18..22] } else { b"NONE".as_slice() };
+    let (sample_format, big_endian) = match compression {
+        b"NONE" | b"twos" => (SampleFormat::Int(bits), true),
+        b"sowt" => (SampleFormat::Int(bits), false),
+        b"fl32" | b"FL32" => (SampleFormat::Float(32), true),
+        b"fl64" | b"FL64" => (SampleFormat::Float(64), true),
+        _ => (SampleFormat::Compressed, true),
+    };
+
+    let layout = match data {
+        Some((offset, len)) => {
+            let bytes = match sample_format {
+                SampleFormat::Int(bits) | SampleFormat::Float(bits) => bits.div_ceil(8) as usize,
+                SampleFormat::Compressed => 0,
+            };
+            let len = len.min(frames * (bytes * channels as usize) as u64);
+            layout(sample_format, offset, len, channels, bytes * channels as usize, big_endian)
+        }
+        None => None,
+    };
+
+    Ok((
+        AudioInfo {
+            format: AudioFormat::Aiff,
+            channels,
+            sample_rate,
+            sample_format,
+            frames,
+        },
+        layout,
+    ))
+}
+
+fn parse_caf<R: Read + Seek>(reader: &mut R) -> Result<(AudioInfo, Option<DataLayout>)> {
+    let mut desc = None;
+    let mut data = None;
+    let mut valid_frames = None;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(8))?;
+
+    loop {
+        let mut header = [0u8; 12];
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        let size = i64::from_be_bytes(header[4..12].try_into().unwrap_or_default());
+        let start = reader.stream_position()?;
+        // A data chunk of unknown size runs to the end of the file
+        let len = if size < 0 { end - start } else { size as u64 };
+
+        match &header[0..4] {
+            b"desc" => desc = Some(read_chunk(reader, len)?),
+            b"pakt" => {
+                let pakt = read_chunk(reader, len)?;
+                if pakt.len() >= 16 {
+                    valid_frames = Some(i64::from_be_bytes(pakt[8..16].try_into().unwrap_or_default()) as u64);
+                }
+            }
+            b"data" => {
+                // Skip the edit count
+                data = Some((start + 4, len.saturating_sub(4)));
+                reader.seek(SeekFrom::Start(start + len))?;
+            }
+            _ => {
+                reader.seek(SeekFrom::Start(start + len))?;
+            }
+        }
+    }
+
+    let desc = desc.filter(|d| d.len() >= 32).ok_or_else(|| unsupported("missing desc chunk"))?;
+    let word = |i: usize| u32::from_be_bytes([desc[i], desc[i + 1], desc[i + 2], desc[i + 3]]);
+    let sample_rate = f64::from_be_bytes(desc[0..8].try_into().unwrap_or_default()) as u32;
+    let flags = word(12);
+    let bytes_per_packet = word(16);
+    let frames_per_packet = word(20);
+    let channels = word(24) as u16;
+    let bits = word(28) as u16;
+
+    let sample_format = match (&desc[8..12], flags & 1) {
+        (b"lpcm", 1) => SampleFormat::Float(bits),
+        (b"lpcm", _) => SampleFormat::Int(bits),
+        _ => SampleFormat::Compressed,
+    };
+    let (offset, len) = data.ok_or_else(|| unsupported("missing data chunk"))?;
+    let frames = match valid_frames {
+        Some(frames) => frames,
+        None if bytes_per_packet > 0 => len / bytes_per_packet as u64 * frames_per_packet.max(1) as u64,
+        None => 0,
+    };
+    let little_endian = flags & 2 != 0;
+    let layout = layout(sample_format, offset, len, channels, bytes_per_packet as usize, !little_endian);
+
+    Ok((
+        AudioInfo {
+            format: AudioFormat::Caf,
+            channels,
+            sample_rate,
+            sample_format,
+            frames,
+        },
+        layout,
+    ))
+}
+
+/// Describe decodable PCM data; `None` when it cannot be decoded
+fn layout(
+    sample_format: SampleFormat,
+    offset: u64,
+    len: u64,
+    channels: u16,
+    block_align: usize,
+    big_endian: bool,
+) -> Option<DataLayout> {
+    if channels == 0 || block_align == 0 || !block_align.is_multiple_of(channels as usize) {
+        return None;
+    }
+    let bytes_per_sample = block_align / channels as usize;
+    let supported = match sample_format {
+        SampleFormat::Int(_) => (1..=4).contains(&bytes_per_sample),
+        SampleFormat::Float(_) => bytes_per_sample == 4 || bytes_per_sample == 8,
+        SampleFormat::Compressed => false,
+    };
+    supported.then_some(DataLayout {
+        offset,
+        len: len - len % block_align as u64,
+        bytes_per_sample,
+        big_endian,
+    })
+}
+
+/// Read a chunk ID and 32-bit size, or `None` at the end of the file
+pub(crate) fn read_chunk_header<R: Read>(reader: &mut R, big_endian: bool) -> Result<Option<([u8; 4], u64)>> {
+    let mut header = [0u8; 8];
+    if reader.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let size = [header[4], header[5], header[6], header[7]];
+    let len = if big_endian { u32::from_be_bytes(size) } else { u32::from_le_bytes(size) };
+    Ok(Some(([header[0], header[1], header[2], header[3]], len as u64)))
+}
+
+/// Read a small chunk in full
+pub(crate) fn read_chunk<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    if len > 1 << 20 {
+        return Err(unsupported("oversized header chunk"));
+    }
+    let mut chunk = vec![0u8; len as usize];
+    reader.read_exact(&mut chunk).map_err(|_| unsupported("truncated header chunk"))?;
+    Ok(chunk)
+}
+
+fn read_int(bytes: &[u8], big_endian: bool, unsigned_8bit: bool) -> f32 {
+    if bytes.len() == 1 {
+        let value = if unsigned_8bit { bytes[0] as i32 - 128 } else { bytes[0] as i8 as i32 };
+        return value as f32 / 128.0;
+    }
+
+    // Place the sample in the top bytes of an i32 so the sign extends for free
+    let mut word = [0u8; 4];
+    for (i, &byte) in bytes.iter().enumerate() {
+        let position = if big_endian { i } else { bytes.len() - 1 - i };
+        word[position] = byte;
+    }
+    i32::from_be_bytes(word) as f32 / 2_147_483_648.0
+}
+
+/// Read an integer sample without scaling it
+fn read_raw_int(bytes: &[u8], big_endian: bool, unsigned_8bit: bool) -> i32 {
+    if bytes.len() == 1 {
+        return if unsigned_8bit { bytes[0] as i32 - 128 } else { bytes[0] as i8 as i32 };
+    }
+
+    let mut word = [0u8; 4];
+    for (i, &byte) in bytes.iter().enumerate() {
+        let position = if big_endian { i } else { bytes.len() - 1 - i };
+        word[position] = byte;
+    }
+    i32::from_be_bytes(word) >> (32 - bytes.len() * 8)
+}
+
+/// Write an integer sample read by [`read_raw_int`]
+fn write_raw_int(out: &mut Vec<u8>, sample: i32, bytes: usize, big_endian: bool, unsigned_8bit: bool) {
+    if bytes == 1 {
+        out.push(if unsigned_8bit { (sample + 128) as u8 } else { sample as i8 as u8 });
+        return;
+    }
+
+    let word = (sample << (32 - bytes * 8)).to_be_bytes();
+    if big_endian {
+        out.extend(&word[..bytes]);
+    } else {
+        out.extend(word[..bytes].iter().rev());
+    }
+}
+
+/// Read a sample stored as the file's layout says
+fn read_sample(bytes: &[u8], info: &AudioInfo, layout: &DataLayout) -> f32 {
+    match info.sample_format {
+        SampleFormat::Float(_) => read_float(bytes, layout.big_endian),
+        _ => read_int(bytes, layout.big_endian, info.format == AudioFormat::Wav),
+    }
+}
+
+fn read_float(bytes: &[u8], big_endian: bool) -> f32 {
+    match bytes.len() {
+        4 => {
+            let word = bytes.try_into().unwrap_or_default();
+            if big_endian { f32::from_be_bytes(word) } else { f32::from_le_bytes(word) }
+        }
+        _ => {
+            let word = bytes.try_into().unwrap_or_default();
+            (if big_endian { f64::from_be_bytes(word) } else { f64::from_le_bytes(word) }) as f32
+        }
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, sample: f32, bytes: usize, big_endian: bool, unsigned_8bit: bool) {
+    let sample = sample.clamp(-1.0, 1.0) as f64;
+    if bytes == 1 {
+        let value = (sample * 128.0).round().clamp(-128.0, 127.0) as i32;
+        out.push(if unsigned_8bit { (value + 128) as u8 } else { value as i8 as u8 });
+        return;
+    }
+
+    let max = (1i64 << (bytes * 8 - 1)) as f64;
+    let value = (sample * max).round().clamp(-max, max - 1.0) as i32;
+    let word = (value << (32 - bytes * 8)).to_be_bytes();
+    if big_endian {
+        out.extend(&word[..bytes]);
+    } else {
+        out.extend(word[..bytes].iter().rev());
+    }
+}
+
+fn write_float(out: &mut Vec<u8>, sample: f32, big_endian: bool) {
+    if big_endian {
+        out.extend(sample.to_be_bytes());
+    } else {
+        out.extend(sample.to_le_bytes());
+    }
+}
+
+/// Convert an 80-bit IEEE extended float, as used by AIFF, to a sample rate
+fn rate_from_extended(bytes: &[u8]) -> u32 {
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7FFF) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap_or_default());
+    if exponent == 0 || mantissa == 0 {
+        return 0;
+    }
+    let shift = exponent - 16383 - 63;
+    (mantissa as f64 * 2f64.powi(shift)).round() as u32
+}
+
+/// Convert a sample rate to an 80-bit IEEE extended float
+fn extended_from_rate(rate: u32) -> [u8; 10] {
+    let mut bytes = [0u8; 10];
+    if rate == 0 {
+        return bytes;
+    }
+    let msb = 31 - rate.leading_zeros();
+    let exponent = (16383 + msb) as u16;
+    let mantissa = (rate as u64) << (63 - msb);
+    bytes[0..2].copy_from_slice(&exponent.to_be_bytes());
+    bytes[2..10].copy_from_slice(&mantissa.to_be_bytes());
+    bytes
+}
+
+fn unsupported(reason: &str) -> VaultError {
+    VaultError::InvalidOperation(format!("Unsupported audio file: {}", reason))
+}
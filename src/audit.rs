@@ -0,0 +1,231 @@
+//! Audit log of vault mutations
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::undo::{OperationId, current_operation};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{QueryBuilder, Row, Sqlite, SqliteConnection};
+
+/// Kind of mutation recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    /// A sound was imported
+    CreateSound,
+    /// A sound's metadata changed
+    UpdateSound,
+    /// A sound was deleted
+    DeleteSound,
+    /// A collection was created
+    CreateCollection,
+    /// A collection's properties changed
+    UpdateCollection,
+    /// A collection was deleted, e.g. an expired session collection
+    DeleteCollection,
+    /// A sound was added to a collection
+    AddToCollection,
+    /// A sound was removed from a collection
+    RemoveFromCollection,
+    /// A sound was locked against edits
+    LockSound,
+    /// A sound was unlocked
+    UnlockSound,
+    /// An actor was given a role
+    GrantRole,
+    /// An actor's role was taken away
+    RevokeRole,
+    /// An operation was refused for the actor's role
+    PermissionDenied,
+}
+
+/// An entry of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Sequence number of the entry
+    pub id: i64,
+
+    /// Kind of mutation
+    pub operation: AuditOperation,
+
+    /// ID of the sound or collection that changed
+    pub entity_id: String,
+
+    /// When the mutation happened
+    pub timestamp: DateTime<Utc>,
+
+    /// Who made the change, if an actor was set
+    pub actor: Option<String>,
+
+    /// Changed fields, as `{"field": [old, new]}`
+    pub changes: Value,
+
+    /// Batch operation the mutation was part of, if any
+    #[serde(default)]
+    pub operation_id: Option<OperationId>,
+}
+
+impl AuditOperation {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CreateSound => "create_sound",
+            Self::UpdateSound => "update_sound",
+            Self::DeleteSound => "delete_sound",
+            Self::CreateCollection => "create_collection",
+            Self::UpdateCollection => "update_collection",
+            Self::DeleteCollection => "delete_collection",
+            Self::AddToCollection => "add_to_collection",
+            Self::RemoveFromCollection => "remove_from_collection",
+            Self::LockSound => "lock_sound",
+            Self::UnlockSound => "unlock_sound",
+            Self::GrantRole => "grant_role",
+            Self::RevokeRole => "revoke_role",
+            Self::PermissionDenied => "permission_denied",
+        }
+    }
+
+    /// Parse a name stored in the database
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown audit operation: {}", name)))
+    }
+}
+
+/// Compute a compact diff between two JSON objects
+///
+/// Only changed fields are kept, each as an `[old, new]` pair; a missing side
+/// is `null`.
+///
+/// # Examples
+///
+/// ```
+/// use serde_json::json;
+/// use soundvault::audit_diff;
+///
+/// let before = json!({"name": "Wind", "tags": ["air"], "duration": 2.0});
+/// let after = json!({"name": "Gust", "tags": ["air"], "duration": 2.0});
+/// assert_eq!(audit_diff(Some(&before), Some(&after)), json!({"name": ["Wind", "Gust"]}));
+///
+/// assert_eq!(audit_diff(None, Some(&json!({"name": "Gust"}))), json!({"name": [null, "Gust"]}));
+/// ```
+pub fn audit_diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut diff = Map::new();
+    for (key, old) in before {
+        let new = after.get(key).unwrap_or(&Value::Null);
+        if old != new {
+            diff.insert(key.clone(), Value::Array(vec![old.clone(), new.clone()]));
+        }
+    }
+    for (key, new) in after {
+        if !before.contains_key(key) && !new.is_null() {
+            diff.insert(key.clone(), Value::Array(vec![Value::Null, new.clone()]));
+        }
+    }
+
+    Value::Object(diff)
+}
+
+impl LocalLibrary {
+    /// Set the actor recorded with subsequent mutations
+    pub fn set_actor(&self, actor: Option<&str>) {
+        *self.actor.write().unwrap_or_else(|e| e.into_inner()) = actor.map(str::to_string);
+    }
+
+    /// Actor recorded with mutations
+    pub fn actor(&self) -> Option<String> {
+        self.actor.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Record a mutation, within the transaction performing it
+    pub(crate) async fn audit(
+        &self,
+        conn: &mut SqliteConnection,
+        operation: AuditOperation,
+        entity_id: &str,
+        changes: Value,
+    ) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO audit_log (operation, entity_id, timestamp, actor, changes, operation_id)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#),
+        )
+        .bind(operation.as_str())
+        .bind(entity_id)
+        .bind(Utc::now())
+        .bind(self.actor())
+        .bind(serde_json::to_string(&changes)?)
+        .bind(current_operation().map(|id| id.as_str().to_string()))
+        .execute(&mut *conn)
+        .await?;
+
+        match operation.change() {
+            Some((entity, kind)) => self.record_change(conn, entity, entity_id, kind).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Get audit log entries, newest first
+    ///
+    /// # Arguments
+    ///
+    /// * `entity_id` - Only entries about this sound or collection
+    /// * `since` - Only entries recorded at or after this time
+    /// * `limit` - Maximum number of entries to return
+    pub async fn audit_history(
+        &self,
+        entity_id: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<AuditEntry>> {
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            self.sql("SELECT id, operation, entity_id, timestamp, actor, changes, operation_id FROM audit_log WHERE 1 = 1"),
+        );
+        if let Some(entity_id) = entity_id {
+            builder.push(" AND entity_id = ");
+            builder.push_bind(entity_id.to_string());
+        }
+        if let Some(since) = since {
+            builder.push(" AND timestamp >= ");
+            builder.push_bind(since);
+        }
+        builder.push(" ORDER BY id DESC LIMIT ");
+        builder.push_bind(i64::from(limit));
+
+        let rows = builder.build().fetch_all(&self.reader).await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(AuditEntry {
+                    id: row.try_get("id")?,
+                    operation: AuditOperation::parse(row.try_get("operation")?)?,
+                    entity_id: row.try_get("entity_id")?,
+                    timestamp: row.try_get("timestamp")?,
+                    actor: row.try_get("actor")?,
+                    changes: serde_json::from_str(row.try_get("changes")?)?,
+                    operation_id: row.try_get::<Option<String>, _>("operation_id")?.map(OperationId::from_stored),
+                })
+            })
+            .collect()
+    }
+
+    /// Delete audit log entries recorded before a point in time
+    ///
+    /// # Returns
+    ///
+    /// The number of deleted entries
+    pub async fn prune_audit_log(&self, older_than: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(&self.sql("DELETE FROM audit_log WHERE timestamp < ?"))
+            .bind(older_than)
+            .execute(&self.db)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
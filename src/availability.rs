@@ -0,0 +1,225 @@
+//! Referenced files that went missing, and pointing their sounds at them again
+
+use crate::audio::{probe, probe_file};
+use crate::audit::{AuditOperation, audit_diff};
+use crate::context::OpContext;
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{Availability, Sound, SoundMetadata};
+use crate::pack::{packed_path, read_packed, split_packed};
+use crate::sniff::{FileFormat, sniff_format};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Row, SqliteConnection};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Outcome of [`SoundVault::relink_by_prefix`](crate::SoundVault::relink_by_prefix)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelinkReport {
+    /// IDs of the sounds pointed at their file under the new prefix
+    pub relinked: Vec<String>,
+
+    /// IDs of the sounds left alone, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+impl LocalLibrary {
+    /// Remember the availability of a sound's file, announcing changes to
+    /// subscribers
+    ///
+    /// The first time a sound is seen only records its availability.
+    pub(crate) fn note_availability(&self, id: &str, availability: Availability) {
+        let previous = self
+            .availability
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id.to_string(), availability);
+        if previous.is_some_and(|previous| previous != availability) {
+            self.emit(VaultEvent::AvailabilityChanged {
+                sound_id: id.to_string(),
+                availability,
+            });
+        }
+    }
+
+    /// Sounds pointing at a file that isn't there, e.g. on an unplugged drive
+    ///
+    /// Checks the file of every sound, so subscribers also hear about files
+    /// that came back. Packed sounds are missing when their archive is.
+    pub async fn list_missing(&self) -> Result<Vec<Sound>> {
+        let rows = sqlx::query(&self.sql("SELECT id, path, packed FROM sounds WHERE path IS NOT NULL AND storage_backend IS NULL ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?;
+
+        let mut missing = Vec::new();
+        for row in rows {
+            let id: String = row.try_get("id")?;
+            let mut path = PathBuf::from(row.try_get::<String, _>("path")?);
+            if row.try_get("packed")?
+                && let Some((archive, _)) = split_packed(&path)
+            {
+                path = archive;
+            }
+            let availability = if path.exists() { Availability::Available } else { Availability::FileMissing };
+            self.note_availability(&id, availability);
+            if availability == Availability::FileMissing {
+                missing.push(self.get_sound(&id).await?);
+            }
+        }
+
+        Ok(missing)
+    }
+
+    /// Point an external sound at its file's new location
+    ///
+    /// The file must have the content the sound was recorded with, unless
+    /// `force` is set, in which case its hash and technical properties are
+    /// read again.
+    pub async fn relink(&self, id: &str, new_path: &Path, force: bool) -> Result<()> {
+        let before = self.get_sound(id).await?.metadata;
+        let after = relinked(&before, new_path, force)?;
+
+        let mut tx = self.db.begin().await?;
+        self.write_relink(&mut tx, &before, &after).await?;
+        tx.commit().await?;
+        self.note_availability(id, Availability::Available);
+
+        Ok(())
+    }
+
+    /// Point the external sounds whose file was under `old_prefix` at the
+    /// same file under `new_prefix`, e.g. when a drive is mounted elsewhere
+    ///
+    /// Sounds whose file isn't under the new prefix with the same content are
+    /// skipped. The others are relinked in one transaction, so if `context`
+    /// stops the relink, no sound is relinked.
+    pub async fn relink_by_prefix(&self, old_prefix: &Path, new_prefix: &Path, context: &OpContext) -> Result<RelinkReport> {
+        let deadline = context.start("relink_by_prefix");
+        let ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE external = 1 ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?;
+
+        let mut report = RelinkReport::default();
+        let mut changes = Vec::new();
+        let total = ids.len();
+        for (done, id) in ids.into_iter().enumerate() {
+            deadline.check(|| format!("{} of {} referenced sounds checked, nothing relinked", done, total))?;
+            let before = self.get_sound(&id).await?.metadata;
+            let Some(path) = before.path.as_deref() else {
+                continue;
+            };
+            // A packed sound moves with its archive, or the folder holding it
+            let new_path = match split_packed(path) {
+                Some((archive, entry)) if before.packed && archive == old_prefix => packed_path(new_prefix, &entry),
+                _ => match path.strip_prefix(old_prefix) {
+                    Ok(rest) => new_prefix.join(rest),
+                    Err(_) => continue,
+                },
+            };
+            match relinked(&before, &new_path, false) {
+                Ok(after) => changes.push((before, after)),
+                Err(e) => report.skipped.push((id, e.to_string())),
+            }
+        }
+
+        let mut tx = self.db.begin().await?;
+        for (before, after) in &changes {
+            self.write_relink(&mut tx, before, after).await?;
+        }
+        tx.commit().await?;
+        for (before, _) in changes {
+            self.note_availability(&before.id, Availability::Available);
+            report.relinked.push(before.id);
+        }
+
+        Ok(report)
+    }
+
+    async fn write_relink(&self, conn: &mut SqliteConnection, before: &SoundMetadata, after: &SoundMetadata) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+            UPDATE sounds
+            SET path = ?, hash = ?, duration = ?, channels = ?, sample_rate = ?, format = ?, updated_at = CURRENT_TIMESTAMP,
+                fingerprint = CASE WHEN hash IS ? THEN fingerprint END
+            WHERE id = ?
+            "#),
+        )
+        .bind(after.path.as_ref().map(|p| p.to_string_lossy().to_string()))
+        .bind(&after.hash)
+        .bind(after.duration)
+        .bind(after.channels)
+        .bind(after.sample_rate)
+        .bind(after.format.map(|format| format.as_str()))
+        .bind(&after.hash)
+        .bind(&after.id)
+        .execute(&mut *conn)
+        .await?;
+
+        let changes = audit_diff(Some(&serde_json::to_value(before)?), Some(&serde_json::to_value(after)?));
+        self.audit(conn, AuditOperation::UpdateSound, &after.id, changes).await
+    }
+}
+
+/// Metadata of an external sound pointed at `new_path`, checking the file's
+/// content unless `force` is set
+///
+/// A packed sound is pointed at an entry of an archive, `<archive>!<entry>`.
+fn relinked(before: &SoundMetadata, new_path: &Path, force: bool) -> Result<SoundMetadata> {
+    if !before.external {
+        return Err(VaultError::InvalidOperation(format!(
+            "Only referenced sounds can be relinked; replace the file of {} instead",
+            before.id
+        )));
+    }
+    let packed = if before.packed {
+        let bytes = read_packed(new_path)?
+            .ok_or_else(|| VaultError::FileSystem(format!("Archive entry does not exist: {:?}", new_path)))?;
+        Some(bytes)
+    } else if new_path.is_file() {
+        None
+    } else {
+        return Err(VaultError::FileSystem(format!("File does not exist: {:?}", new_path)));
+    };
+
+    let hash = match &packed {
+        Some(bytes) => format!("{:x}", Sha256::digest(bytes)),
+        None => hash_file(new_path)?,
+    };
+    let mut after = before.clone();
+    after.path = Some(std::path::absolute(new_path)?);
+    if before.hash.as_ref() == Some(&hash) {
+        return Ok(after);
+    }
+    if !force {
+        return Err(VaultError::InvalidOperation(format!(
+            "Content of {:?} differs from sound {}",
+            new_path, before.id
+        )));
+    }
+
+    after.hash = Some(hash);
+    let info = match &packed {
+        Some(bytes) => {
+            after.format = FileFormat::sniff(bytes);
+            probe(&mut Cursor::new(bytes))
+        }
+        None => {
+            after.format = sniff_format(new_path).ok().flatten();
+            probe_file(new_path)
+        }
+    };
+    match info {
+        Ok(info) => {
+            after.duration = info.duration();
+            after.channels = Some(info.channels);
+            after.sample_rate = Some(info.sample_rate);
+        }
+        Err(_) => {
+            after.channels = None;
+            after.sample_rate = None;
+        }
+    }
+    Ok(after)
+}
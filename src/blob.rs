@@ -0,0 +1,423 @@
+//! Where the files of sounds are stored
+//!
+//! The library directory is the default store. Other stores, such as an
+//! S3-compatible bucket, are registered with
+//! [`SoundVault::add_blob_store`](crate::SoundVault::add_blob_store); each
+//! sound records the store holding its file, so a vault can move its files
+//! gradually with [`SoundVault::migrate_blobs`](crate::SoundVault::migrate_blobs).
+//!
+//! A file is stored under its path relative to the library, e.g.
+//! `<sound id>/rain.wav`. Files of other stores are read through a copy at
+//! that path in the library, fetched the first time the sound is read; the
+//! copy is only a cache, dropped when the file moves.
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::paths::{copy_atomic, finish_temp, resolve_within, temp_path};
+use crate::query::SoundFilter;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+
+/// Name of the store of files kept in the library directory
+pub const LIBRARY_STORE: &str = "local";
+
+/// How long the preview URLs of files in other stores stay valid
+const PREVIEW_URL_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Future returned by [`BlobStore`] methods
+pub type BlobFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Content of a stored file, read as it arrives
+pub type BlobReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// A place the files of sounds are stored, by key
+///
+/// Keys are relative paths with `/` separators, e.g. `<sound id>/rain.wav`.
+pub trait BlobStore: Send + Sync {
+    /// Name identifying the store, recorded with the sounds it holds
+    fn name(&self) -> &str;
+
+    /// Store the file at `source` under `key`, replacing any file there
+    fn put<'a>(&'a self, key: &'a str, source: &'a Path) -> BlobFuture<'a, ()>;
+
+    /// Read the file stored under `key`
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, BlobReader>;
+
+    /// Remove the file stored under `key`; a missing file is not an error
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()>;
+
+    /// Whether a file is stored under `key`
+    fn exists<'a>(&'a self, key: &'a str) -> BlobFuture<'a, bool>;
+
+    /// Size in bytes of the file stored under `key`
+    fn size<'a>(&'a self, key: &'a str) -> BlobFuture<'a, u64>;
+
+    /// URL the file under `key` can be fetched from without credentials for
+    /// `lifetime`, if the store can hand one out
+    ///
+    /// The default hands out none.
+    fn presigned_url(&self, key: &str, lifetime: Duration) -> Option<String> {
+        let _ = (key, lifetime);
+        None
+    }
+}
+
+/// Files kept in a directory, under their key
+///
+/// # Examples
+///
+/// Files move to another directory, e.g. a network share, and back:
+///
+/// ```
+/// use soundvault::{FileSystemStore, SoundFilter, SoundVault, VaultConfig};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let mut vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+/// let share = tempfile::tempdir()?;
+/// vault.add_blob_store(Box::new(FileSystemStore::new("share", share.path())))?;
+///
+/// let file = dir.path().join("rain.wav");
+/// std::fs::write(&file, b"rain")?;
+/// let id = vault.import_file(&file, None).await?;
+/// let stored = vault.get_sound(&id).await?.metadata.path.unwrap();
+///
+/// let report = vault.migrate_blobs("local", "share", &SoundFilter::default()).await?;
+/// assert_eq!(report.moved, vec![id.clone()]);
+/// assert!(share.path().join(&id).join("rain.wav").exists() && !stored.exists());
+/// assert_eq!(vault.get_sound(&id).await?.metadata.storage_backend.as_deref(), Some("share"));
+///
+/// // Reading fetches a copy into the library
+/// assert_eq!(vault.open_sound(&id).await?, b"rain");
+/// assert!(stored.exists());
+///
+/// vault.migrate_blobs("share", "local", &SoundFilter::default()).await?;
+/// assert!(!share.path().join(&id).join("rain.wav").exists());
+/// assert_eq!(vault.get_sound(&id).await?.metadata.storage_backend, None);
+/// assert_eq!(std::fs::read(&stored)?, b"rain");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileSystemStore {
+    name: String,
+    root: PathBuf,
+}
+
+impl FileSystemStore {
+    /// Store files named `name` under `root`, which must exist
+    pub fn new(name: &str, root: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            root: root.into(),
+        }
+    }
+
+    /// Path of the file under `key`, refused if it escapes the root
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        resolve_within(&self.root, Path::new(key))
+    }
+}
+
+impl BlobStore for FileSystemStore {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn put<'a>(&'a self, key: &'a str, source: &'a Path) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            copy_atomic(source, &path)
+                .map_err(|e| VaultError::FileSystem(format!("Failed to store {}: {}", key, e)))
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, BlobReader> {
+        Box::pin(async move {
+            let file = tokio::fs::File::open(self.path(key)?).await?;
+            Ok(Box::pin(file) as BlobReader)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            // Folders of sounds go once empty
+            let root = self.root.canonicalize()?;
+            if let Some(dir) = path.parent().filter(|dir| *dir != root) {
+                let _ = std::fs::remove_dir(dir);
+            }
+            Ok(())
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BlobFuture<'a, bool> {
+        Box::pin(async move { Ok(self.path(key)?.is_file()) })
+    }
+
+    fn size<'a>(&'a self, key: &'a str) -> BlobFuture<'a, u64> {
+        Box::pin(async move { Ok(std::fs::metadata(self.path(key)?)?.len()) })
+    }
+}
+
+/// Outcome of [`SoundVault::migrate_blobs`](crate::SoundVault::migrate_blobs)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobMigrationReport {
+    /// IDs of the sounds whose file moved
+    pub moved: Vec<String>,
+
+    /// Sounds whose file couldn't move, with the reason; they stay where
+    /// they were
+    pub errors: Vec<(String, String)>,
+}
+
+impl LocalLibrary {
+    /// Register a store; its name must be new
+    pub(crate) fn add_blob_store(&self, store: Arc<dyn BlobStore>) -> Result<()> {
+        let mut stores = self.blob_stores.write().unwrap_or_else(|e| e.into_inner());
+        let name = store.name().to_string();
+        if name.is_empty() || name == LIBRARY_STORE || stores.contains_key(&name) {
+            return Err(VaultError::InvalidOperation(format!("Invalid or duplicate blob store name: {:?}", name)));
+        }
+        stores.insert(name, store);
+        Ok(())
+    }
+
+    /// Names of the registered stores, the library's first
+    pub(crate) fn blob_store_names(&self) -> Vec<String> {
+        let stores = self.blob_stores.read().unwrap_or_else(|e| e.into_inner());
+        std::iter::once(LIBRARY_STORE.to_string()).chain(stores.keys().cloned()).collect()
+    }
+
+    /// The registered store named `name`, other than the library's
+    fn blob_store(&self, name: &str) -> Result<Arc<dyn BlobStore>> {
+        self.blob_stores
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+            .ok_or_else(|| VaultError::NotFound(format!("Blob store not registered: {}", name)))
+    }
+
+    /// Key of a sound's file: its path relative to the library
+    fn blob_key(&self, metadata: &SoundMetadata) -> Result<String> {
+        let path = self.readable_file(metadata)?;
+        let root = self.library_path.canonicalize()?;
+        let relative = path
+            .strip_prefix(&root)
+            .map_err(|_| VaultError::InvalidOperation(format!("Sound file is external: {}", metadata.id)))?;
+        Ok(relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    /// URL a sound held by another store can be previewed from
+    pub(crate) fn blob_preview_url(&self, metadata: &SoundMetadata) -> Option<String> {
+        let store = self.blob_store(metadata.storage_backend.as_deref()?).ok()?;
+        store.presigned_url(&self.blob_key(metadata).ok()?, PREVIEW_URL_LIFETIME)
+    }
+
+    /// Refuse to rewrite the file of a sound held by another store
+    pub(crate) fn check_in_library(&self, metadata: &SoundMetadata) -> Result<()> {
+        match &metadata.storage_backend {
+            Some(store) => Err(VaultError::InvalidOperation(format!(
+                "File of {} is in blob store {}; migrate it to the library first",
+                metadata.id, store
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a sound to read its file, fetching a copy into the library if
+    /// another store holds it
+    pub(crate) async fn stored_sound(&self, id: &str) -> Result<SoundMetadata> {
+        let metadata = self.get_sound(id).await?.metadata;
+        self.stage_blob(&metadata).await?;
+        Ok(metadata)
+    }
+
+    /// Fetch a copy of a sound's file into the library if another store
+    /// holds it and it isn't there yet
+    pub(crate) async fn stage_blob(&self, metadata: &SoundMetadata) -> Result<()> {
+        let Some(name) = &metadata.storage_backend else {
+            return Ok(());
+        };
+        let path = self.readable_file(metadata)?;
+        if path.exists() {
+            return Ok(());
+        }
+
+        let store = self.blob_store(name)?;
+        let key = self.blob_key(metadata)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temp = temp_path(&path);
+        let transfer = self.governor.transfer().await?;
+        let fetched = async {
+            let mut reader = store.get(&key).await?;
+            let mut file = tokio::fs::File::create(&temp).await?;
+            tokio::io::copy(&mut reader, &mut file).await?;
+            Ok::<_, VaultError>(())
+        }
+        .await;
+        drop(transfer);
+        if let Err(e) = fetched {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+
+        // A stream cut short must not pass for the file
+        let size = std::fs::metadata(&temp)?.len();
+        let expected = store.size(&key).await?;
+        if size != expected {
+            let _ = std::fs::remove_file(&temp);
+            return Err(VaultError::FileSystem(format!(
+                "Fetched {} bytes of {} from {}, expected {}",
+                size, key, name, expected
+            )));
+        }
+        finish_temp(&temp, &path).map_err(|e| VaultError::FileSystem(format!("Failed to save {}: {}", key, e)))?;
+
+        Ok(())
+    }
+
+    /// Whether the store holding a sound's file has it; a store that can't
+    /// be reached, or isn't registered, doesn't
+    pub(crate) async fn blob_exists(&self, metadata: &SoundMetadata) -> bool {
+        match &metadata.storage_backend {
+            Some(name) => match (self.blob_store(name), self.blob_key(metadata)) {
+                (Ok(store), Ok(key)) => store.exists(&key).await.unwrap_or(false),
+                _ => false,
+            },
+            None => self.readable_file(metadata).is_ok_and(|path| path.exists()),
+        }
+    }
+
+    /// Remove a sound's file from the store holding it, if not the library
+    pub(crate) async fn delete_blob(&self, metadata: &SoundMetadata) -> Result<()> {
+        match &metadata.storage_backend {
+            Some(name) => self.blob_store(name)?.delete(&self.blob_key(metadata)?).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Move the file of a sound just added to the library to the default
+    /// store, if one is configured
+    ///
+    /// A file that can't move stays in the library, reported as a
+    /// [`VaultEvent::ImportWarning`]; it can move later with
+    /// [`migrate_blobs`](Self::migrate_blobs).
+    pub(crate) async fn offload_new_sound(&self, id: &str) {
+        let Some(store) = &self.default_blob_store else {
+            return;
+        };
+        if let Err(e) = self.migrate_blob(id, LIBRARY_STORE, store).await {
+            self.emit(VaultEvent::ImportWarning {
+                sound_id: id.to_string(),
+                warning: format!("File kept in the library, not moved to blob store {}: {}", store, e),
+            });
+        }
+    }
+
+    /// Move the files of the sounds matching a filter from one store to
+    /// another
+    ///
+    /// Sounds held by other stores, referenced files and content-addressed
+    /// files, which may be shared, are left alone.
+    /// Each file is copied, checked and recorded under its new store before
+    /// the old copy is removed, so a failure leaves it where it was.
+    pub async fn migrate_blobs(&self, from: &str, to: &str, filter: &SoundFilter) -> Result<BlobMigrationReport> {
+        for name in [from, to] {
+            if name != LIBRARY_STORE {
+                self.blob_store(name)?;
+            }
+        }
+
+        let mut report = BlobMigrationReport::default();
+        for id in self.query_ids(filter).await? {
+            match self.migrate_blob(&id, from, to).await {
+                Ok(true) => report.moved.push(id),
+                Ok(false) => {}
+                Err(e) => report.errors.push((id, e.to_string())),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Move the file of a sound between stores
+    ///
+    /// # Returns
+    ///
+    /// Whether it moved; it doesn't if `from` doesn't hold it
+    async fn migrate_blob(&self, id: &str, from: &str, to: &str) -> Result<bool> {
+        let metadata = self.get_sound(id).await?.metadata;
+        let current = metadata.storage_backend.as_deref().unwrap_or(LIBRARY_STORE);
+        let shared = metadata.path.as_deref().and_then(|path| self.object_hash(path)).is_some();
+        if metadata.external || shared || metadata.path.is_none() || current != from || from == to {
+            return Ok(false);
+        }
+
+        self.stage_blob(&metadata).await?;
+        let path = self.readable_file(&metadata)?;
+        let key = self.blob_key(&metadata)?;
+        if to != LIBRARY_STORE {
+            let store = self.blob_store(to)?;
+            let transfer = self.governor.transfer().await?;
+            store.put(&key, &path).await?;
+            drop(transfer);
+            let (stored, local) = (store.size(&key).await?, std::fs::metadata(&path)?.len());
+            if stored != local {
+                return Err(VaultError::FileSystem(format!(
+                    "{} holds {} bytes of {}, expected {}",
+                    to, stored, key, local
+                )));
+            }
+        }
+
+        let storage_backend = (to != LIBRARY_STORE).then(|| to.to_string());
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("UPDATE sounds SET storage_backend = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(&storage_backend)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let changes = audit_diff(
+            Some(&json!({ "storage_backend": metadata.storage_backend })),
+            Some(&json!({ "storage_backend": storage_backend })),
+        );
+        self.audit(&mut tx, AuditOperation::UpdateSound, id, changes).await?;
+        tx.commit().await?;
+
+        // The old copy goes only once the new one is recorded
+        self.delete_blob(&metadata).await?;
+        if to != LIBRARY_STORE {
+            std::fs::remove_file(&path)?;
+            if let Some(dir) = path.parent().filter(|dir| dir.file_name().is_some_and(|name| name == id)) {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+
+        Ok(true)
+    }
+}
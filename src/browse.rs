@@ -0,0 +1,158 @@
+//! Virtual folders grouping sounds by metadata values
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::query::SoundFilter;
+use crate::tables::Tables;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Row, Sqlite};
+
+/// Extension of a sound's file, lowercased, ignoring the suffix added when
+/// it's archived; `NULL` for files without one
+const FORMAT_SQL: &str = r#"(
+    SELECT CASE WHEN ext = '' OR ext = logical OR instr(ext, '/') > 0 OR instr(ext, '\') > 0
+        THEN NULL ELSE lower(ext) END
+    FROM (
+        SELECT logical, substr(logical, length(rtrim(logical, replace(logical, '.', ''))) + 1) AS ext
+        FROM (
+            SELECT CASE
+                WHEN sounds.archive_codec = 'flac' AND sounds.path LIKE '%.flac'
+                    THEN substr(sounds.path, 1, length(sounds.path) - 5)
+                WHEN sounds.archive_codec = 'zstd' AND sounds.path LIKE '%.zst'
+                    THEN substr(sounds.path, 1, length(sounds.path) - 4)
+                ELSE sounds.path
+            END AS logical
+        )
+    )
+)"#;
+
+/// Append the SQL value of a field of the current `sounds` row
+///
+/// Built-in fields are `author` (the custom `author` value, or the Freesound
+/// uploader), `license` and `format` (the file extension); any other name is
+/// a custom metadata key.
+pub(crate) fn push_field(builder: &mut QueryBuilder<'_, Sqlite>, tables: &Tables, field: &str) {
+    match field {
+        "license" => {
+            builder.push("NULLIF(license, '')");
+        }
+        "format" => {
+            builder.push(tables.sql(FORMAT_SQL));
+        }
+        "author" => {
+            builder.push("COALESCE(");
+            push_custom(builder, tables, "author");
+            builder.push(", ");
+            push_custom(builder, tables, "freesound_username");
+            builder.push(")");
+        }
+        key => push_custom(builder, tables, key),
+    }
+}
+
+pub(crate) fn push_custom(builder: &mut QueryBuilder<'_, Sqlite>, tables: &Tables, key: &str) {
+    builder.push(tables.sql("(SELECT value FROM metadata WHERE object_id = sounds.id AND object_type = 'sound' AND key = "));
+    builder.push_bind(key.to_string());
+    builder.push(")");
+}
+
+/// A node of the tree built by [`SoundVault::browse`](crate::SoundVault::browse)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrowseNode {
+    /// Field and value picked at each level, from the root down to this node;
+    /// `None` groups the sounds without a value
+    pub path: Vec<(String, Option<String>)>,
+
+    /// Number of sounds under this node
+    pub count: u64,
+
+    /// Fields grouping the levels below this node
+    pub remaining: Vec<String>,
+
+    /// One node per value of the next field, ordered by value with the
+    /// sounds lacking one last; `None` until the node is expanded
+    pub children: Option<Vec<BrowseNode>>,
+}
+
+impl BrowseNode {
+    /// Field grouping this node's level; `None` for the root
+    pub fn field(&self) -> Option<&str> {
+        self.path.last().map(|(field, _)| field.as_str())
+    }
+
+    /// Value of the node's field shared by its sounds
+    pub fn value(&self) -> Option<&str> {
+        self.path.last().and_then(|(_, value)| value.as_deref())
+    }
+
+    /// Check whether there is no level below this node
+    pub fn is_leaf(&self) -> bool {
+        self.remaining.is_empty()
+    }
+
+    /// Filter selecting the sounds under this node
+    pub fn filter(&self) -> SoundFilter {
+        SoundFilter {
+            fields: self.path.iter().cloned().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Group sounds by the values of fields, level by level
+    ///
+    /// # Returns
+    ///
+    /// The root node, expanded one level
+    pub async fn browse(&self, hierarchy: &[&str]) -> Result<BrowseNode> {
+        for (index, field) in hierarchy.iter().enumerate() {
+            if field.is_empty() || hierarchy[..index].contains(field) {
+                return Err(VaultError::InvalidOperation(format!("Invalid browse hierarchy: {:?}", hierarchy)));
+            }
+        }
+
+        let mut root = BrowseNode {
+            path: Vec::new(),
+            count: self.count(&SoundFilter::default()).await?,
+            remaining: hierarchy.iter().map(|field| field.to_string()).collect(),
+            children: None,
+        };
+        self.expand_browse_node(&mut root).await?;
+
+        Ok(root)
+    }
+
+    /// Load the children of a node, counting the sounds under each value of
+    /// the next field
+    pub async fn expand_browse_node(&self, node: &mut BrowseNode) -> Result<()> {
+        let Some((field, remaining)) = node.remaining.split_first() else {
+            node.children = Some(Vec::new());
+            return Ok(());
+        };
+
+        let mut builder = QueryBuilder::new("SELECT ");
+        push_field(&mut builder, &self.tables, field);
+        builder.push(self.sql(" AS value, COUNT(*) AS count FROM sounds"));
+        node.filter().push_where(&mut builder, &self.tables);
+        builder.push(" GROUP BY value ORDER BY value IS NULL, value");
+        let rows = builder.build().fetch_all(&self.reader).await?;
+
+        let children = rows
+            .iter()
+            .map(|row| {
+                let mut path = node.path.clone();
+                path.push((field.clone(), row.try_get("value")?));
+                Ok(BrowseNode {
+                    path,
+                    count: row.try_get::<i64, _>("count")? as u64,
+                    remaining: remaining.to_vec(),
+                    children: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        node.children = Some(children);
+
+        Ok(())
+    }
+}
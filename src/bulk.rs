@@ -0,0 +1,141 @@
+//! Editing every sound of a collection at once
+
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::patch::MetadataPatch;
+use crate::undo::OperationId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Options of [`SoundVault::patch_collection_sounds`](crate::SoundVault::patch_collection_sounds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BulkPatchOptions {
+    /// List the changes without writing them
+    pub dry_run: bool,
+
+    /// Number of sounds written per transaction
+    pub chunk_size: usize,
+}
+
+impl Default for BulkPatchOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            chunk_size: 500,
+        }
+    }
+}
+
+/// Outcome of an operation applied to many items
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchOutcome<T> {
+    /// Whether the changes were written, rather than previewed
+    pub applied: bool,
+
+    /// Items changed, each with the changes made to it
+    pub changed: Vec<(T, MetadataPatch)>,
+
+    /// Items already as the operation would make them
+    pub unchanged: Vec<T>,
+
+    /// Items left alone, with the reason
+    pub skipped: Vec<(T, String)>,
+
+    /// Number of items each field was changed on, keyed as `name`, `tags`,
+    /// `custom.<key>`, `localized.<lang>`...
+    pub fields: BTreeMap<String, u64>,
+
+    /// Operation to pass to [`SoundVault::undo_operation`](crate::SoundVault::undo_operation)
+    /// to take applied changes back
+    pub operation: Option<OperationId>,
+}
+
+impl<T> Default for BatchOutcome<T> {
+    fn default() -> Self {
+        Self {
+            applied: false,
+            changed: Vec::new(),
+            unchanged: Vec::new(),
+            skipped: Vec::new(),
+            fields: BTreeMap::new(),
+            operation: None,
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Apply a patch to every sound of a collection
+    ///
+    /// Sounds are patched in order, `options.chunk_size` per transaction,
+    /// with a [`VaultEvent::BulkPatchProgress`] after each chunk. Locked
+    /// sounds are skipped unless the patch overrides locks. Custom values
+    /// are checked against the declared fields before anything is written.
+    /// A dry run goes through the same steps and rolls each chunk back.
+    pub async fn patch_collection_sounds(
+        &self,
+        collection_id: &str,
+        patch: &MetadataPatch,
+        options: &BulkPatchOptions,
+    ) -> Result<BatchOutcome<String>> {
+        if patch.slug.is_some() {
+            return Err(VaultError::InvalidOperation(
+                "Sounds of a collection can't all be given the same slug".to_string(),
+            ));
+        }
+        if let Some(Some(trim)) = &patch.trim {
+            trim.check()?;
+        }
+        let sound_ids = self.get_collection(collection_id).await?.sound_ids;
+        self.check_custom_fields(&mut *self.reader.acquire().await?, &patch.set_custom, &patch.remove_custom)
+            .await?;
+
+        let mut outcome = BatchOutcome {
+            applied: !options.dry_run,
+            ..Default::default()
+        };
+        let (mut done, total) = (0, sound_ids.len() as u64);
+        for chunk in sound_ids.chunks(options.chunk_size.max(1)) {
+            let mut tx = self.db.begin().await?;
+            for id in chunk {
+                let before = match self.fetch_sound(&mut tx, id).await {
+                    Ok(sound) => sound.metadata,
+                    Err(VaultError::NotFound(_)) => {
+                        outcome.skipped.push((id.clone(), "not in the vault".to_string()));
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                if !patch.override_lock && before.locked {
+                    outcome.skipped.push((id.clone(), "locked".to_string()));
+                    continue;
+                }
+
+                self.apply_patch(&mut tx, id, patch).await?;
+                let after = self.fetch_sound(&mut tx, id).await?.metadata;
+                let changes = MetadataPatch::between(&before, &after);
+                if changes.is_empty() {
+                    outcome.unchanged.push(id.clone());
+                    continue;
+                }
+                for field in changes.fields() {
+                    *outcome.fields.entry(field).or_default() += 1;
+                }
+                outcome.changed.push((id.clone(), changes));
+            }
+            if outcome.applied {
+                tx.commit().await?;
+            }
+
+            done += chunk.len() as u64;
+            self.emit(VaultEvent::BulkPatchProgress {
+                collection_id: collection_id.to_string(),
+                done,
+                total,
+            });
+        }
+
+        Ok(outcome)
+    }
+}
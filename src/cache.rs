@@ -0,0 +1,198 @@
+//! Read-through cache of sounds fetched by ID
+//!
+//! [`LocalLibrary::get_sound`] keeps the metadata it reads in a bounded
+//! cache, dropping the least recently used entries first. Every write to a
+//! sound drops its entry: the change feed records each audited change, and
+//! the few writes it doesn't see forget the sound themselves. Availability
+//! and preview URLs are worked out again on each hit, so a file going missing
+//! is still noticed.
+//!
+//! A read overlapping a write could otherwise cache what the write is about
+//! to replace, so sounds written since the write connection was last idle
+//! aren't cached.
+
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Sounds cached unless [`VaultConfig::sound_cache_capacity`](crate::VaultConfig::sound_cache_capacity)
+/// says otherwise
+pub(crate) const DEFAULT_CAPACITY: usize = 4096;
+
+/// Use of the sound cache since the vault opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SoundCacheStats {
+    /// Sounds found in the cache
+    pub hits: u64,
+
+    /// Sounds read from the database
+    pub misses: u64,
+
+    /// Sounds cached right now
+    pub entries: usize,
+
+    /// Most sounds cached; 0 when caching is off
+    pub capacity: usize,
+}
+
+/// Bounded cache of sound metadata, least recently used first out
+pub(crate) struct SoundCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Metadata by sound ID, with the tick it was last used at
+    entries: HashMap<String, (SoundMetadata, u64)>,
+    /// Sound IDs by the tick they were last used at, oldest first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    /// Bumped whenever a sound is forgotten, so reads that started before
+    /// don't cache what they found
+    generation: u64,
+    /// Sounds written since the write connection was last idle
+    pending: HashSet<String>,
+}
+
+impl CacheState {
+    fn remove(&mut self, id: &str) {
+        if let Some((_, tick)) = self.entries.remove(id) {
+            self.recency.remove(&tick);
+        }
+    }
+}
+
+impl SoundCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Look a sound up, marking it as recently used
+    ///
+    /// # Returns
+    ///
+    /// The cached metadata, or the generation to hand to
+    /// [`insert`](Self::insert) once it has been read
+    fn get(&self, id: &str) -> std::result::Result<SoundMetadata, u64> {
+        let mut state = self.state();
+        state.tick += 1;
+        let tick = state.tick;
+        let Some((metadata, used)) = state.entries.get_mut(id) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Err(state.generation);
+        };
+        let (metadata, used) = (metadata.clone(), std::mem::replace(used, tick));
+        state.recency.remove(&used);
+        state.recency.insert(tick, id.to_string());
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Ok(metadata)
+    }
+
+    /// Cache a sound read while the cache was at `generation`
+    fn insert(&self, metadata: &SoundMetadata, generation: u64) {
+        let mut state = self.state();
+        if state.generation != generation || state.pending.contains(&metadata.id) {
+            return;
+        }
+
+        state.remove(&metadata.id);
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(metadata.id.clone(), (metadata.clone(), tick));
+        state.recency.insert(tick, metadata.id.clone());
+        while state.entries.len() > self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else { break };
+            state.entries.remove(&oldest);
+        }
+    }
+
+    /// Drop a sound about to be written
+    fn forget(&self, id: &str) {
+        let mut state = self.state();
+        state.remove(id);
+        state.generation += 1;
+        state.pending.insert(id.to_string());
+    }
+
+    /// Let the sounds written so far be cached again, once their writes are
+    /// committed
+    fn settle(&self) {
+        let mut state = self.state();
+        state.pending.clear();
+    }
+
+    fn clear(&self) {
+        let mut state = self.state();
+        state.entries.clear();
+        state.recency.clear();
+        state.generation += 1;
+    }
+
+    fn stats(&self) -> SoundCacheStats {
+        SoundCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.state().entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Get a sound's metadata from the cache, or read it and cache it
+    pub(crate) async fn cached_metadata(&self, id: &str) -> crate::error::Result<SoundMetadata> {
+        if self.sound_cache.capacity == 0 {
+            let mut conn = self.reader.acquire().await?;
+            return Ok(self.fetch_sound(&mut conn, id).await?.metadata);
+        }
+
+        // With the write connection idle, every write is committed or
+        // rolled back, and reads from now on see its outcome
+        if self.db.num_idle() == self.db.size() as usize {
+            self.sound_cache.settle();
+        }
+        let generation = match self.sound_cache.get(id) {
+            Ok(metadata) => return Ok(metadata),
+            Err(generation) => generation,
+        };
+
+        let mut conn = self.reader.acquire().await?;
+        let metadata = self.fetch_sound(&mut conn, id).await?.metadata;
+        self.sound_cache.insert(&metadata, generation);
+        Ok(metadata)
+    }
+
+    /// Drop a sound from the cache before writing it
+    ///
+    /// Within a transaction, the sound isn't cached again before the
+    /// transaction ends.
+    pub(crate) fn forget_sound(&self, id: &str) {
+        self.sound_cache.forget(id);
+    }
+
+    /// Empty the sound cache, e.g. after another process changed the
+    /// database
+    pub fn clear_cache(&self) {
+        self.sound_cache.clear();
+    }
+
+    /// Use of the sound cache since the vault opened
+    pub fn cache_stats(&self) -> SoundCacheStats {
+        self.sound_cache.stats()
+    }
+}
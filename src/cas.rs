@@ -0,0 +1,374 @@
+//! Content-addressed storage of the library's files
+//!
+//! In this layout a file is stored at `objects/<hash[0..2]>/<hash>` in the
+//! library, named after the SHA-256 of its content, and the sounds with the
+//! same content share it: importing content already stored only adds a
+//! sound. The `objects` table counts the sounds referencing each file, and
+//! the last one deleted removes it.
+//!
+//! Compressed and packed sounds, referenced files and files held by other
+//! blob stores keep their own path. The classic layout, a folder per sound
+//! holding its file under its own name, stays the default, since it can be
+//! browsed outside the vault.
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::SoundMetadata;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+/// Directory of the library holding content-addressed files
+pub(crate) const OBJECTS_DIR: &str = "objects";
+
+/// Key in `vault_info` recording that new files are content-addressed
+const CONTENT_ADDRESSED: &str = "content_addressed";
+
+/// How the files of the library are laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageLayout {
+    /// Each sound's file in a folder named after the sound, under the file's
+    /// own name
+    #[default]
+    Classic,
+    /// Files named after the hash of their content, shared by the sounds
+    /// with the same content
+    ContentAddressed,
+}
+
+/// Outcome of [`SoundVault::migrate_to_cas`](crate::SoundVault::migrate_to_cas)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CasMigrationReport {
+    /// IDs of the sounds whose file moved into the objects directory
+    pub migrated: Vec<String>,
+
+    /// IDs of the migrated sounds whose content was already stored, whose
+    /// own copy was removed
+    pub deduplicated: Vec<String>,
+
+    /// Bytes the removed copies took
+    pub bytes_reclaimed: u64,
+
+    /// Sounds that couldn't migrate, with the reason; they keep their file
+    pub errors: Vec<(String, String)>,
+}
+
+/// A content-addressed file whose recorded count of references disagrees
+/// with the sounds referencing it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectRefMismatch {
+    /// Hash of the content, naming the file
+    pub hash: String,
+
+    /// References recorded in the `objects` table; 0 if it has no row
+    pub recorded: u64,
+
+    /// Sounds referencing the file
+    pub actual: u64,
+}
+
+impl LocalLibrary {
+    /// Whether new files are content-addressed
+    pub(crate) fn content_addressed(&self) -> bool {
+        self.content_addressed.load(Ordering::Relaxed)
+    }
+
+    /// Settle the layout of new files when the vault opens
+    ///
+    /// A vault keeps the content-addressed layout once it has used it, even
+    /// if later opened with the classic one, so its files never mix again.
+    pub(crate) async fn load_storage_layout(&self, layout: StorageLayout) -> Result<()> {
+        if layout == StorageLayout::ContentAddressed {
+            return self.use_content_addressing().await;
+        }
+
+        let flag: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(CONTENT_ADDRESSED)
+            .fetch_optional(&self.reader)
+            .await?;
+        self.content_addressed.store(flag.is_some(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Store new files content-addressed, from now on
+    async fn use_content_addressing(&self) -> Result<()> {
+        sqlx::query(&self.sql("INSERT OR IGNORE INTO vault_info (key, value) VALUES (?, '1')"))
+            .bind(CONTENT_ADDRESSED)
+            .execute(&self.db)
+            .await?;
+        self.content_addressed.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Path of the file holding the content with the given hash
+    pub(crate) fn object_path(&self, hash: &str) -> PathBuf {
+        self.library_path.join(OBJECTS_DIR).join(hash.get(..2).unwrap_or_default()).join(hash)
+    }
+
+    /// Hash of the content a path holds, if it's a content-addressed file
+    pub(crate) fn object_hash(&self, path: &Path) -> Option<String> {
+        let hash = path.strip_prefix(self.library_path.join(OBJECTS_DIR)).ok()?.file_name()?;
+        Some(hash.to_string_lossy().into_owned())
+    }
+
+    /// Whether the content with the given hash is stored content-addressed
+    pub(crate) async fn object_exists(&self, hash: &str) -> Result<bool> {
+        let recorded: bool = sqlx::query_scalar(&self.sql("SELECT EXISTS (SELECT 1 FROM objects WHERE hash = ?)"))
+            .bind(hash)
+            .fetch_one(&self.reader)
+            .await?;
+
+        Ok(recorded && self.object_path(hash).is_file())
+    }
+
+    /// Move a sound's new file into the objects directory and count the
+    /// reference, in the transaction recording the sound
+    ///
+    /// If the content is already stored, the new file is removed instead.
+    /// Files that keep their own path are left alone, as are all files while
+    /// the vault uses the classic layout, except those already
+    /// content-addressed.
+    ///
+    /// # Returns
+    ///
+    /// Whether the content was already stored
+    pub(crate) async fn intern_file(&self, conn: &mut SqliteConnection, metadata: &mut SoundMetadata) -> Result<bool> {
+        let Some(path) = metadata.path.clone() else {
+            return Ok(false);
+        };
+        let interned = self.object_hash(&path).is_some();
+        let eligible = !metadata.external
+            && !metadata.packed
+            && metadata.archive.is_none()
+            && metadata.storage_backend.is_none();
+        if !eligible || !(interned || self.content_addressed()) {
+            return Ok(false);
+        }
+
+        let path = self.library_file(&path)?;
+        let hash = match &metadata.hash {
+            Some(hash) => hash.clone(),
+            None => hash_file(&path)?,
+        };
+        if hash.len() < 2 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(VaultError::InvalidOperation(format!("Invalid content hash for {}: {:?}", metadata.id, hash)));
+        }
+        let object = self.object_path(&hash);
+        let size = std::fs::metadata(&path)
+            .map_err(|e| VaultError::FileSystem(format!("Failed to read {:?}: {}", path, e)))?
+            .len();
+
+        // Adding the reference first takes the write lock, so no other
+        // sound drops the file meanwhile
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO objects (hash, size, refs) VALUES (?, ?, 1)
+            ON CONFLICT(hash) DO UPDATE SET refs = refs + 1
+            "#),
+        )
+        .bind(&hash)
+        .bind(size as i64)
+        .execute(&mut *conn)
+        .await?;
+
+        let stored = object.is_file();
+        let reused = stored && path != object;
+        if path != object {
+            if stored {
+                std::fs::remove_file(&path)
+                    .map_err(|e| VaultError::FileSystem(format!("Failed to remove duplicate {:?}: {}", path, e)))?;
+            } else {
+                std::fs::create_dir_all(object.parent().unwrap_or(&self.library_path))?;
+                std::fs::rename(&path, &object)
+                    .map_err(|e| VaultError::FileSystem(format!("Failed to store {:?}: {}", path, e)))?;
+            }
+
+            // The sound's folder stays only if it holds its sidecar
+            if let Some(dir) = path.parent().filter(|dir| dir.file_name().is_some_and(|name| name == metadata.id.as_str())) {
+                let _ = std::fs::remove_dir(dir);
+            }
+        } else if !stored {
+            return Err(VaultError::FileSystem(format!("Content-addressed file is missing: {:?}", object)));
+        }
+
+        metadata.path = Some(object);
+        metadata.hash = Some(hash);
+        Ok(reused)
+    }
+
+    /// Drop a sound's reference to a content-addressed file, in the
+    /// transaction removing or repointing the sound; the last reference
+    /// removes the file
+    ///
+    /// Other paths are left alone.
+    pub(crate) async fn release_object(&self, conn: &mut SqliteConnection, path: &Path) -> Result<()> {
+        let Some(hash) = self.object_hash(path) else {
+            return Ok(());
+        };
+
+        let refs: Option<i64> = sqlx::query_scalar(&self.sql("UPDATE objects SET refs = refs - 1 WHERE hash = ? RETURNING refs"))
+            .bind(&hash)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if refs.is_some_and(|refs| refs <= 0) {
+            sqlx::query(&self.sql("DELETE FROM objects WHERE hash = ?"))
+                .bind(&hash)
+                .execute(&mut *conn)
+                .await?;
+            let path = self.object_path(&hash);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(VaultError::FileSystem(format!("Failed to delete {:?}: {}", path, e)));
+                }
+                _ => {}
+            }
+            if let Some(dir) = path.parent() {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the files of the vault's sounds into the content-addressed
+    /// layout, and store new files that way from now on
+    ///
+    /// Each file's content is checked against its recorded hash before it
+    /// moves; a sound whose file changed is reported and keeps it.
+    pub async fn migrate_to_cas(&self) -> Result<CasMigrationReport> {
+        self.use_content_addressing().await?;
+
+        let ids: Vec<String> = sqlx::query_scalar(
+            &self.sql(r#"
+            SELECT id FROM sounds
+            WHERE path IS NOT NULL AND object_hash IS NULL AND external = 0 AND packed = 0
+              AND archive_codec IS NULL AND storage_backend IS NULL
+            ORDER BY id
+            "#),
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        let mut report = CasMigrationReport::default();
+        for id in ids {
+            match self.migrate_file(&id).await {
+                Ok(Some(reclaimed)) => {
+                    report.bytes_reclaimed += reclaimed;
+                    report.deduplicated.push(id.clone());
+                    report.migrated.push(id);
+                }
+                Ok(None) => report.migrated.push(id),
+                Err(e) => report.errors.push((id, e.to_string())),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Move a sound's file into the objects directory
+    ///
+    /// # Returns
+    ///
+    /// The size of its file if the content was already stored and the file
+    /// removed
+    async fn migrate_file(&self, id: &str) -> Result<Option<u64>> {
+        let before = self.get_sound(id).await?.metadata;
+        let path = self.readable_file(&before)?;
+        let checked = path.clone();
+        let hash = self.jobs.run(move || hash_file(&checked)).await?;
+        if before.hash.as_ref().is_some_and(|recorded| *recorded != hash) {
+            return Err(VaultError::InvalidOperation(format!(
+                "File of {} no longer matches its recorded hash",
+                id
+            )));
+        }
+        let size = std::fs::metadata(&path)?.len();
+
+        let mut after = before.clone();
+        after.hash = Some(hash);
+        let mut tx = self.db.begin().await?;
+        let deduplicated = self.intern_file(&mut tx, &mut after).await?;
+        sqlx::query(&self.sql("UPDATE sounds SET path = ?, hash = ?, object_hash = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(after.path.as_ref().map(|p| p.to_string_lossy().to_string()))
+            .bind(&after.hash)
+            .bind(after.path.as_deref().and_then(|path| self.object_hash(path)))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let changes = audit_diff(Some(&json!({ "path": before.path })), Some(&json!({ "path": after.path })));
+        self.audit(&mut tx, AuditOperation::UpdateSound, id, changes).await?;
+        tx.commit().await?;
+
+        Ok(deduplicated.then_some(size))
+    }
+
+    /// Compare the recorded references of each content-addressed file with
+    /// the sounds referencing it
+    pub(crate) async fn object_ref_mismatches(&self) -> Result<Vec<ObjectRefMismatch>> {
+        let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+            &self.sql(r#"
+            SELECT hash, recorded, actual FROM (
+                SELECT o.hash, o.refs AS recorded,
+                       (SELECT COUNT(*) FROM sounds s WHERE s.object_hash = o.hash) AS actual
+                FROM objects o
+                UNION ALL
+                SELECT object_hash, 0, COUNT(*) FROM sounds
+                WHERE object_hash IS NOT NULL AND object_hash NOT IN (SELECT hash FROM objects)
+                GROUP BY object_hash
+            )
+            WHERE recorded != actual
+            ORDER BY hash
+            "#),
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(hash, recorded, actual)| ObjectRefMismatch {
+                hash,
+                recorded: recorded.max(0) as u64,
+                actual: actual as u64,
+            })
+            .collect())
+    }
+
+    /// Set the recorded references of content-addressed files to the sounds
+    /// referencing them
+    ///
+    /// A file no sound references loses its row, and is left to be found as
+    /// an orphan.
+    pub(crate) async fn recount_objects(&self, mismatches: &[ObjectRefMismatch]) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        for mismatch in mismatches {
+            if mismatch.actual == 0 {
+                sqlx::query(&self.sql("DELETE FROM objects WHERE hash = ?"))
+                    .bind(&mismatch.hash)
+                    .execute(&mut *tx)
+                    .await?;
+                continue;
+            }
+            let size = std::fs::metadata(self.object_path(&mismatch.hash)).map(|m| m.len()).unwrap_or_default();
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO objects (hash, size, refs) VALUES (?, ?, ?)
+                ON CONFLICT(hash) DO UPDATE SET refs = excluded.refs
+                "#),
+            )
+            .bind(&mismatch.hash)
+            .bind(size as i64)
+            .bind(mismatch.actual as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
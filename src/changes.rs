@@ -0,0 +1,254 @@
+//! Feed of the sounds and collections that changed, for external sync
+//!
+//! Every mutation recorded in the audit log also appends a row to the feed,
+//! in the same transaction, so the feed never misses or invents a change.
+//! Deletions stay in the feed after the row is gone. A client keeps the
+//! [`ChangeCursor`] it was last given and asks for what came after it.
+
+use crate::audit::AuditOperation;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+/// Most changes returned by one call to
+/// [`SoundVault::changes_since`](crate::SoundVault::changes_since)
+const CHANGES_PAGE: i64 = 1000;
+
+/// `vault_info` key of the last sequence number pruned from the feed
+const PRUNED_THROUGH: &str = "change_feed_pruned_through";
+
+/// Position in the change feed
+///
+/// The cursor is a plain sequence number, so it can be stored anywhere and
+/// stays valid across restarts. [`ChangeCursor::default`] is the start of
+/// the feed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ChangeCursor {
+    /// Sequence number of the last change seen, 0 for none
+    pub seq: i64,
+}
+
+/// Kind of object a change is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeEntity {
+    /// A sound
+    Sound,
+    /// A collection
+    Collection,
+}
+
+/// What happened to the object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// It was created
+    Created,
+    /// Its metadata or members changed
+    Updated,
+    /// It was deleted
+    Deleted,
+}
+
+impl ChangeEntity {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sound => "sound",
+            Self::Collection => "collection",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown change entity: {}", name)))
+    }
+}
+
+impl ChangeKind {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown change kind: {}", name)))
+    }
+}
+
+impl AuditOperation {
+    /// Object and change fed to the change feed for this mutation; `None`
+    /// for entries about no sound or collection
+    pub(crate) fn change(&self) -> Option<(ChangeEntity, ChangeKind)> {
+        Some(match self {
+            Self::CreateSound => (ChangeEntity::Sound, ChangeKind::Created),
+            Self::UpdateSound | Self::LockSound | Self::UnlockSound => (ChangeEntity::Sound, ChangeKind::Updated),
+            Self::DeleteSound => (ChangeEntity::Sound, ChangeKind::Deleted),
+            Self::CreateCollection => (ChangeEntity::Collection, ChangeKind::Created),
+            Self::DeleteCollection => (ChangeEntity::Collection, ChangeKind::Deleted),
+            Self::UpdateCollection | Self::AddToCollection | Self::RemoveFromCollection => {
+                (ChangeEntity::Collection, ChangeKind::Updated)
+            }
+            Self::GrantRole | Self::RevokeRole | Self::PermissionDenied => return None,
+        })
+    }
+}
+
+/// A change of a sound or collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// Sequence number of the change, increasing with every change
+    pub seq: i64,
+
+    /// Kind of object that changed
+    pub entity: ChangeEntity,
+
+    /// ID of the sound or collection
+    pub entity_id: String,
+
+    /// What happened to it
+    pub kind: ChangeKind,
+
+    /// When it happened
+    pub changed_at: DateTime<Utc>,
+}
+
+impl LocalLibrary {
+    /// Append a change to the feed, within the transaction making it
+    pub(crate) async fn record_change(
+        &self,
+        conn: &mut SqliteConnection,
+        entity: ChangeEntity,
+        entity_id: &str,
+        kind: ChangeKind,
+    ) -> Result<()> {
+        if entity == ChangeEntity::Sound {
+            self.forget_sound(entity_id);
+        }
+        sqlx::query(&self.sql("INSERT INTO change_feed (entity, entity_id, kind, changed_at) VALUES (?, ?, ?, ?)"))
+            .bind(entity.as_str())
+            .bind(entity_id)
+            .bind(kind.as_str())
+            .bind(Utc::now())
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the changes made after a cursor, oldest first
+    ///
+    /// # Returns
+    ///
+    /// At most 1000 changes and the cursor to ask for the next ones with;
+    /// the same cursor when there are none
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::ChangesPruned` if changes after the cursor were pruned
+    pub async fn changes_since(&self, cursor: ChangeCursor) -> Result<(Vec<Change>, ChangeCursor)> {
+        let pruned_through = self.changes_pruned_through().await?;
+        if cursor.seq < pruned_through {
+            return Err(VaultError::ChangesPruned {
+                seq: cursor.seq,
+                pruned_through,
+            });
+        }
+
+        let rows: Vec<(i64, String, String, String, DateTime<Utc>)> = sqlx::query_as(&self.sql(
+            "SELECT seq, entity, entity_id, kind, changed_at FROM change_feed WHERE seq > ? ORDER BY seq LIMIT ?",
+        ))
+        .bind(cursor.seq)
+        .bind(CHANGES_PAGE)
+        .fetch_all(&self.reader)
+        .await?;
+
+        let changes = rows
+            .into_iter()
+            .map(|(seq, entity, entity_id, kind, changed_at)| {
+                Ok(Change {
+                    seq,
+                    entity: ChangeEntity::parse(&entity)?,
+                    entity_id,
+                    kind: ChangeKind::parse(&kind)?,
+                    changed_at,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let next = changes.last().map_or(cursor, |change| ChangeCursor { seq: change.seq });
+
+        Ok((changes, next))
+    }
+
+    /// Cursor of the last change made, to follow the feed from after a full
+    /// sync
+    pub async fn latest_change_cursor(&self) -> Result<ChangeCursor> {
+        let seq: Option<i64> = sqlx::query_scalar(&self.sql("SELECT MAX(seq) FROM change_feed"))
+            .fetch_one(&self.reader)
+            .await?;
+
+        Ok(ChangeCursor {
+            seq: seq.unwrap_or_default().max(self.changes_pruned_through().await?),
+        })
+    }
+
+    async fn changes_pruned_through(&self) -> Result<i64> {
+        let value: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(PRUNED_THROUGH)
+            .fetch_optional(&self.reader)
+            .await?;
+
+        Ok(value.and_then(|value| value.parse().ok()).unwrap_or_default())
+    }
+
+    /// Delete changes recorded before a point in time
+    ///
+    /// Clients whose cursor is older than the last deleted change get
+    /// `VaultError::ChangesPruned` and must sync from scratch.
+    ///
+    /// # Returns
+    ///
+    /// The number of deleted changes
+    pub async fn prune_changes(&self, older_than: DateTime<Utc>) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let last: Option<i64> = sqlx::query_scalar(&self.sql("SELECT MAX(seq) FROM change_feed WHERE changed_at < ?"))
+            .bind(older_than)
+            .fetch_one(&mut *tx)
+            .await?;
+        let Some(last) = last else {
+            return Ok(0);
+        };
+
+        let result = sqlx::query(&self.sql("DELETE FROM change_feed WHERE seq <= ?"))
+            .bind(last)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&self.sql(
+            "INSERT INTO vault_info (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        ))
+        .bind(PRUNED_THROUGH)
+        .bind(last.to_string())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete the changes older than the configured retention
+    pub(crate) async fn apply_change_retention(&self) -> Result<()> {
+        if let Some(retention) = self.change_retention {
+            self.prune_changes(Utc::now() - chrono::Duration::from(retention)).await?;
+        }
+        Ok(())
+    }
+}
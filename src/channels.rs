@@ -0,0 +1,320 @@
+//! Sounds made of the channels of multichannel sounds
+
+use crate::audio::{ChannelMix, SampleChunks, SampleFormat, WavWriter, downmix, probe, writable_format};
+use crate::derivative::DERIVATIVE_TAG;
+use crate::error::{Result, VaultError};
+use crate::flac::FlacWriter;
+use crate::journal::OpKind;
+use crate::levels::ReadSeek;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{SoundMetadata, SoundSource};
+use crate::paths::{finish_temp, temp_path};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Frames decoded at a time
+const CHUNK_FRAMES: usize = 65536;
+
+/// File format of the sounds made from channels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelFormat {
+    /// WAV, in the bit depth of the source
+    #[default]
+    Wav,
+    /// FLAC, in the bit depth of the source up to 24 bits
+    Flac,
+}
+
+/// How [`SoundVault::split_channels_with_options`](crate::SoundVault::split_channels_with_options)
+/// and [`SoundVault::fold_to_mono_with_options`](crate::SoundVault::fold_to_mono_with_options)
+/// write the sounds they make
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelOptions {
+    /// File format of the new sounds
+    pub format: ChannelFormat,
+}
+
+/// A sound made from channels of another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelAsset {
+    /// ID of the new sound
+    pub id: String,
+
+    /// Name of the new sound
+    pub name: String,
+
+    /// Duration in seconds
+    pub duration: f32,
+
+    /// Size of the file in bytes
+    pub file_size: u64,
+}
+
+impl ChannelFormat {
+    /// Extension of the files written
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+        }
+    }
+}
+
+/// What a new sound holds
+#[derive(Debug, Clone, Copy)]
+enum Mix {
+    /// One channel of the source
+    Channel(usize),
+    /// Every channel averaged
+    Mono,
+}
+
+/// Label of a channel in names: `L` and `R` for stereo, `ch1` to `chN` for
+/// other layouts
+fn channel_label(channel: usize, channels: u16) -> String {
+    match (channels, channel) {
+        (2, 0) => "L".to_string(),
+        (2, _) => "R".to_string(),
+        _ => format!("ch{}", channel + 1),
+    }
+}
+
+/// Mono file being written
+enum Writer {
+    Wav(WavWriter<BufWriter<File>>),
+    Flac { writer: FlacWriter<BufWriter<File>>, bits: u16 },
+}
+
+impl Writer {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        match self {
+            Self::Wav(writer) => writer.write(samples),
+            Self::Flac { writer, bits } => {
+                let max = (1i64 << (*bits - 1)) as f64;
+                let samples: Vec<i32> = samples
+                    .iter()
+                    .map(|&sample| (sample.clamp(-1.0, 1.0) as f64 * max).round().clamp(-max, max - 1.0) as i32)
+                    .collect();
+                writer.write(&samples)
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        let file = match self {
+            Self::Wav(writer) => writer.finish()?,
+            Self::Flac { writer, .. } => writer.finish()?,
+        };
+        file.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    }
+}
+
+/// Write mono files from one pass over a sound's samples
+///
+/// # Returns
+///
+/// The number of frames and the sample rate of every file
+fn write_mixes(reader: &mut dyn ReadSeek, outputs: &[(Mix, PathBuf)], format: ChannelFormat) -> Result<(u64, u32)> {
+    let mut chunks = SampleChunks::new(reader, CHUNK_FRAMES)?;
+    let info = chunks.info();
+    let (frames, channels) = (chunks.frames(), info.channels.max(1));
+
+    let temps: Vec<PathBuf> = outputs.iter().map(|(_, path)| temp_path(path)).collect();
+    let written = (|| {
+        let mut writers = Vec::with_capacity(outputs.len());
+        for temp in &temps {
+            if let Some(dir) = temp.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let file = BufWriter::new(File::create(temp)?);
+            writers.push(match format {
+                ChannelFormat::Wav => {
+                    Writer::Wav(WavWriter::new(file, 1, info.sample_rate, writable_format(info.sample_format), frames)?)
+                }
+                ChannelFormat::Flac => {
+                    let bits = match writable_format(info.sample_format) {
+                        SampleFormat::Int(bits) if bits <= 24 => bits,
+                        _ => 24,
+                    };
+                    let writer = FlacWriter::new(file, 1, info.sample_rate, bits, frames)?;
+                    Writer::Flac { writer, bits }
+                }
+            });
+        }
+
+        let mut samples = Vec::new();
+        let mut mono = Vec::new();
+        while chunks.next_chunk(&mut samples)? {
+            for ((mix, _), writer) in outputs.iter().zip(&mut writers) {
+                match *mix {
+                    Mix::Channel(channel) => {
+                        mono.clear();
+                        mono.extend(samples.iter().skip(channel).step_by(channels as usize));
+                        writer.write(&mono)?;
+                    }
+                    Mix::Mono => writer.write(&downmix(&samples, channels, ChannelMix::Mono))?,
+                }
+            }
+        }
+        for writer in writers {
+            writer.finish()?;
+        }
+        for (temp, (_, path)) in temps.iter().zip(outputs) {
+            finish_temp(temp, path)?;
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = written {
+        for temp in &temps {
+            let _ = std::fs::remove_file(temp);
+        }
+        return Err(e);
+    }
+    Ok((frames, info.sample_rate))
+}
+
+impl LocalLibrary {
+    /// Make one mono sound of each channel of a sound
+    ///
+    /// See [`SoundVault::split_channels_with_options`](crate::SoundVault::split_channels_with_options).
+    pub async fn split_channels(&self, id: &str, options: &ChannelOptions) -> Result<Vec<ChannelAsset>> {
+        let parent = self.stored_sound(id).await?;
+        let channels = self.source_channels(&parent)?;
+        let mixes = (0..channels as usize)
+            .map(|channel| {
+                let label = channel_label(channel, channels);
+                (Mix::Channel(channel), format!("{}.{}", parent.name, label), label)
+            })
+            .collect();
+        self.write_channel_sounds(&parent, mixes, options.format).await
+    }
+
+    /// Make a mono sound averaging the channels of a sound
+    ///
+    /// See [`SoundVault::fold_to_mono_with_options`](crate::SoundVault::fold_to_mono_with_options).
+    pub async fn fold_to_mono(&self, id: &str, options: &ChannelOptions) -> Result<ChannelAsset> {
+        let parent = self.stored_sound(id).await?;
+        self.source_channels(&parent)?;
+        let mixes = vec![(Mix::Mono, format!("{} (mono)", parent.name), "mono".to_string())];
+        let mut assets = self.write_channel_sounds(&parent, mixes, options.format).await?;
+        Ok(assets.remove(0))
+    }
+
+    /// Number of channels of a sound, refusing a mono one
+    fn source_channels(&self, metadata: &SoundMetadata) -> Result<u16> {
+        let channels = self.sound_stream(metadata)?.read(|mut reader| probe(&mut reader))?.channels;
+        if channels < 2 {
+            return Err(VaultError::InvalidOperation(format!("Sound {} has a single channel", metadata.id)));
+        }
+        Ok(channels)
+    }
+
+    /// Write the mixes of a sound, each with its name and the label its file
+    /// is named with, and add them as its derivatives
+    async fn write_channel_sounds(
+        &self,
+        parent: &SoundMetadata,
+        mixes: Vec<(Mix, String, String)>,
+        format: ChannelFormat,
+    ) -> Result<Vec<ChannelAsset>> {
+        let source = Self::logical_path(parent).ok_or_else(|| {
+            VaultError::InvalidOperation(format!("Sound has no file: {}", parent.id))
+        })?;
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy().to_string();
+
+        // Each file is journaled on its own, so a failure adding one sound
+        // keeps the others
+        let mut planned = Vec::with_capacity(mixes.len());
+        for (mix, name, label) in mixes {
+            let id = Uuid::new_v4().to_string();
+            let file_name = format!("{}.{}.{}", stem, label, format.extension());
+            let path = self.library_file(&self.library_path.join(&id).join(file_name))?;
+            let op = self.begin_op(OpKind::Derive, &[&path], &[]).await?;
+            planned.push((id, mix, name, path, op));
+        }
+
+        let outputs: Vec<(Mix, PathBuf)> = planned.iter().map(|(_, mix, _, path, _)| (*mix, path.clone())).collect();
+        let written = async {
+            let content = self.sound_stream(parent)?;
+            self.jobs
+                .run(move || content.read(|reader| write_mixes(reader, &outputs, format)))
+                .await
+        }
+        .await;
+
+        let mut result = written.map(|(frames, sample_rate)| (frames, sample_rate, Vec::with_capacity(planned.len())));
+        for (id, _, name, path, op) in planned {
+            // Once something failed, the files left are only removed
+            let added = match &result {
+                Ok((frames, sample_rate, _)) => {
+                    self.add_channel_sound(parent, id, name, &path, &op, *frames, *sample_rate).await.map(Some)
+                }
+                Err(_) => Ok(None),
+            };
+            match self.settle_op(&op, added).await {
+                Ok(Some(asset)) => {
+                    if let Ok((_, _, assets)) = &mut result {
+                        assets.push(asset);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result.map(|(_, _, assets)| assets)
+    }
+
+    /// Add a written mix as a derivative of its sound
+    #[allow(clippy::too_many_arguments)]
+    async fn add_channel_sound(
+        &self,
+        parent: &SoundMetadata,
+        id: String,
+        name: String,
+        path: &Path,
+        op: &str,
+        frames: u64,
+        sample_rate: u32,
+    ) -> Result<ChannelAsset> {
+        let mut tags = parent.tags.clone();
+        if !tags.iter().any(|t| t == DERIVATIVE_TAG) {
+            tags.push(DERIVATIVE_TAG.to_string());
+        }
+        let duration = if sample_rate == 0 { 0.0 } else { (frames as f64 / sample_rate as f64) as f32 };
+        let metadata = SoundMetadata {
+            id: id.clone(),
+            name: name.clone(),
+            source: SoundSource::Local,
+            tags,
+            description: parent.description.clone(),
+            duration,
+            channels: Some(1),
+            sample_rate: Some(sample_rate),
+            license: parent.license.clone(),
+            hash: Some(hash_file(path)?),
+            path: Some(path.to_path_buf()),
+            derived_from: Some(parent.id.clone()),
+            expires_at: parent.expires_at,
+            ..Default::default()
+        };
+        self.insert_sound(&metadata, Some(op), None).await?;
+
+        Ok(ChannelAsset {
+            id,
+            name,
+            duration,
+            file_size: std::fs::metadata(path)?.len(),
+        })
+    }
+}
@@ -0,0 +1,272 @@
+//! Checking sounds out for editing in other programs
+//!
+//! A checked-out sound gets a working copy of its file under
+//! [`CHECKOUT_DIR`]; the stored file stays untouched until the copy is
+//! checked in, when it replaces the file through
+//! [`replace_file`](LocalLibrary::replace_file). A sound is checked out to
+//! one holder at a time.
+
+use crate::audio::probe_file;
+use crate::error::{Result, VaultError};
+use crate::integrity::CHECKOUT_DIR;
+use crate::local::{LocalLibrary, hash_file};
+use crate::paths::write_atomic;
+use crate::sniff::sniff_format;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Sound ID, token, working path, actor, hash and time of a checkout
+type CheckoutRow = (String, String, String, Option<String>, Option<String>, DateTime<Utc>);
+
+/// A sound checked out for editing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckoutHandle {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// Token identifying this checkout; checking in needs it
+    pub token: String,
+
+    /// Working copy of the sound's file, to edit
+    pub working_path: PathBuf,
+
+    /// Actor who checked the sound out
+    pub actor: Option<String>,
+
+    /// Hash of the sound's file when it was checked out
+    pub hash: Option<String>,
+
+    /// When the sound was checked out
+    pub checked_out_at: DateTime<Utc>,
+}
+
+impl From<CheckoutRow> for CheckoutHandle {
+    fn from((sound_id, token, working_path, actor, hash, checked_out_at): CheckoutRow) -> Self {
+        Self {
+            sound_id,
+            token,
+            working_path: PathBuf::from(working_path),
+            actor,
+            hash,
+            checked_out_at,
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Check a sound out, copying its file to a working path to edit
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::CheckedOut` if the sound is checked out already
+    /// * `VaultError::Locked` if the sound is locked
+    /// * `VaultError::InvalidOperation` if the sound's file is referenced
+    ///   rather than stored, or held by another blob store
+    pub async fn checkout(&self, id: &str) -> Result<CheckoutHandle> {
+        if let Some(held) = self.find_checkout(id).await? {
+            return Err(VaultError::CheckedOut {
+                sound_id: id.to_string(),
+                actor: held.actor,
+            });
+        }
+        let metadata = self.stored_sound(id).await?;
+        if metadata.locked {
+            return Err(VaultError::Locked(id.to_string()));
+        }
+        if metadata.external {
+            return Err(VaultError::InvalidOperation(format!(
+                "File of {} is referenced; edit it in place",
+                id
+            )));
+        }
+        self.check_in_library(&metadata)?;
+        let file_name = Self::logical_path(&metadata)
+            .and_then(|path| path.file_name().map(ToOwned::to_owned))
+            .ok_or_else(|| VaultError::FileMissing(id.to_string()))?;
+
+        let token = Uuid::new_v4().to_string();
+        let dir = self.library_path.join(CHECKOUT_DIR).join(&token);
+        let handle = CheckoutHandle {
+            sound_id: id.to_string(),
+            token,
+            working_path: dir.join(file_name),
+            actor: self.actor(),
+            hash: metadata.hash.clone(),
+            checked_out_at: Utc::now(),
+        };
+        let copied = self.sound_bytes(&metadata).and_then(|bytes| {
+            std::fs::create_dir_all(&dir)?;
+            write_atomic(&handle.working_path, &bytes)
+                .map_err(|e| VaultError::FileSystem(format!("Failed to write working copy: {}", e)))
+        });
+        if let Err(e) = copied {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+
+        // Of two checkouts racing, the first recorded wins
+        let recorded = sqlx::query(
+            &self.sql(r#"
+            INSERT INTO checkouts (sound_id, token, working_path, actor, hash, checked_out_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(sound_id) DO NOTHING
+            "#),
+        )
+        .bind(&handle.sound_id)
+        .bind(&handle.token)
+        .bind(handle.working_path.to_string_lossy())
+        .bind(&handle.actor)
+        .bind(&handle.hash)
+        .bind(handle.checked_out_at)
+        .execute(&self.db)
+        .await;
+        match recorded {
+            Ok(done) if done.rows_affected() == 1 => Ok(handle),
+            Ok(_) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                Err(VaultError::CheckedOut {
+                    sound_id: id.to_string(),
+                    actor: self.find_checkout(id).await?.and_then(|held| held.actor),
+                })
+            }
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Check a sound back in, replacing its file with the edited working
+    /// copy, and release it
+    ///
+    /// The working copy must still be audio of a known format, readable if
+    /// the original was. An unchanged copy leaves the stored file alone.
+    ///
+    /// # Returns
+    ///
+    /// Whether the sound's file was replaced
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the handle isn't the sound's
+    ///   current checkout, the working copy isn't valid audio, or the
+    ///   sound's file was replaced since it was checked out
+    pub async fn checkin(&self, handle: &CheckoutHandle) -> Result<bool> {
+        let held = match self.find_checkout(&handle.sound_id).await? {
+            Some(held) if held.token == handle.token => held,
+            _ => {
+                return Err(VaultError::InvalidOperation(format!(
+                    "Sound {} isn't checked out under this handle",
+                    handle.sound_id
+                )));
+            }
+        };
+        let metadata = self.get_sound(&held.sound_id).await?.metadata;
+        if metadata.hash != held.hash {
+            return Err(VaultError::InvalidOperation(format!(
+                "File of {} was replaced since it was checked out",
+                handle.sound_id
+            )));
+        }
+
+        let working = &held.working_path;
+        let changed = held.hash.as_deref() != Some(&*hash_file(working)?);
+        if changed {
+            check_working_copy(working, metadata.channels.is_some())?;
+            self.replace_file(&held.sound_id, working, false).await?;
+        }
+        self.clear_checkout(&held).await?;
+
+        Ok(changed)
+    }
+
+    /// List the sounds checked out, the longest held first
+    ///
+    /// # Arguments
+    ///
+    /// * `older_than` - Only list the checkouts held at least this long
+    pub async fn list_checkouts(&self, older_than: Option<Duration>) -> Result<Vec<CheckoutHandle>> {
+        let rows: Vec<CheckoutRow> = sqlx::query_as(&self.sql(
+            "SELECT sound_id, token, working_path, actor, hash, checked_out_at FROM checkouts ORDER BY checked_out_at, sound_id",
+        ))
+        .fetch_all(&self.reader)
+        .await?;
+
+        let now = Utc::now();
+        Ok(rows
+            .into_iter()
+            .map(CheckoutHandle::from)
+            .filter(|handle| {
+                older_than.is_none_or(|age| (now - handle.checked_out_at).to_std().is_ok_and(|held| held >= age))
+            })
+            .collect())
+    }
+
+    /// Release a sound's checkout without checking it in, deleting the
+    /// working copy
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if the sound isn't checked out
+    pub async fn release_checkout(&self, id: &str) -> Result<()> {
+        let handle = self
+            .find_checkout(id)
+            .await?
+            .ok_or_else(|| VaultError::NotFound(format!("No checkout of {}", id)))?;
+        self.clear_checkout(&handle).await
+    }
+
+    /// Current checkout of a sound
+    pub(crate) async fn find_checkout(&self, id: &str) -> Result<Option<CheckoutHandle>> {
+        let row: Option<CheckoutRow> = sqlx::query_as(&self.sql(
+            "SELECT sound_id, token, working_path, actor, hash, checked_out_at FROM checkouts WHERE sound_id = ?",
+        ))
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(CheckoutHandle::from))
+    }
+
+    /// Forget a checkout and delete its working copy
+    pub(crate) async fn clear_checkout(&self, handle: &CheckoutHandle) -> Result<()> {
+        sqlx::query(&self.sql("DELETE FROM checkouts WHERE sound_id = ? AND token = ?"))
+            .bind(&handle.sound_id)
+            .bind(&handle.token)
+            .execute(&self.db)
+            .await?;
+
+        let dir = self.library_path.join(CHECKOUT_DIR).join(&handle.token);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)
+                .map_err(|e| VaultError::FileSystem(format!("Failed to delete working copy: {}", e)))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fail unless an edited working copy is audio the vault can store
+fn check_working_copy(path: &Path, probed: bool) -> Result<()> {
+    if std::fs::metadata(path)?.len() == 0 {
+        return Err(VaultError::InvalidOperation(format!("Working copy is empty: {}", path.display())));
+    }
+    if sniff_format(path)?.is_none() {
+        return Err(VaultError::InvalidOperation(format!(
+            "Working copy isn't in a known audio format: {}",
+            path.display()
+        )));
+    }
+    if probed && let Err(e) = probe_file(path) {
+        return Err(VaultError::InvalidOperation(format!(
+            "Working copy can't be read: {}: {}",
+            path.display(),
+            e
+        )));
+    }
+
+    Ok(())
+}
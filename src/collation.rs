@@ -0,0 +1,124 @@
+//! Locale-aware ordering of sound and collection names
+
+use std::cmp::Ordering;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Locale that keeps SQLite's plain byte ordering
+pub const BYTE_ORDER_LOCALE: &str = "C";
+
+/// Produces sort keys that order names the way people expect
+///
+/// Names are compared first on their base letters ignoring accents and case,
+/// then on accents, then on case (lowercase first). A few locales move
+/// accented letters after `z` as their alphabets do.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::Collator;
+///
+/// let collator = Collator::new(None);
+/// let mut names = vec!["Zebra.wav", "échos.wav", "Echo.wav", "apple.wav"];
+/// names.sort_by(|a, b| collator.compare(a, b));
+/// assert_eq!(names, vec!["apple.wav", "Echo.wav", "échos.wav", "Zebra.wav"]);
+///
+/// let swedish = Collator::new(Some("sv-SE"));
+/// let mut names = vec!["öga", "zon", "ara"];
+/// names.sort_by(|a, b| swedish.compare(a, b));
+/// assert_eq!(names, vec!["ara", "zon", "öga"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collator {
+    /// Locale the collator was created for
+    locale: Option<String>,
+    /// Letters sorted after `z` in this locale, in order
+    tailored: &'static [char],
+}
+
+impl Collator {
+    /// Create a collator for a locale such as `fr`, `sv-SE` or `nb_NO`
+    ///
+    /// `None` uses language-neutral ordering and `"C"` keeps byte ordering.
+    pub fn new(locale: Option<&str>) -> Self {
+        let language = locale
+            .map(|l| l.split(['-', '_', '.']).next().unwrap_or_default().to_lowercase())
+            .unwrap_or_default();
+
+        let tailored: &'static [char] = match language.as_str() {
+            "sv" | "fi" => &['å', 'ä', 'ö'],
+            "da" | "nb" | "nn" | "no" => &['æ', 'ø', 'å'],
+            _ => &[],
+        };
+
+        Self {
+            locale: locale.map(str::to_string),
+            tailored,
+        }
+    }
+
+    /// Locale the collator was created for
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// Compute a binary sort key; comparing keys bytewise orders the texts
+    pub fn sort_key(&self, text: &str) -> Vec<u8> {
+        if self.locale() == Some(BYTE_ORDER_LOCALE) {
+            return text.as_bytes().to_vec();
+        }
+
+        let mut primary = String::new();
+        let mut secondary = String::new();
+        let mut tertiary = Vec::new();
+
+        for c in text.chars() {
+            let lower: String = c.to_lowercase().collect();
+            let is_upper = lower.chars().ne(std::iter::once(c));
+
+            // Letters tailored after `z` sort as distinct letters, not as accents
+            if let Some(position) = lower
+                .chars()
+                .next()
+                .and_then(|l| self.tailored.iter().position(|&t| t == l))
+            {
+                primary.push('z');
+                primary.push('\u{FFFF}');
+                primary.push(char::from(b'a' + position as u8));
+                secondary.push(c);
+                tertiary.push(u8::from(is_upper));
+                continue;
+            }
+
+            for d in lower.nfd() {
+                if is_combining_mark(d) {
+                    secondary.push(d);
+                } else {
+                    primary.push(d);
+                    secondary.push('\u{1}');
+                    tertiary.push(u8::from(is_upper));
+                }
+            }
+        }
+
+        let mut key = primary.into_bytes();
+        key.push(0);
+        key.extend(secondary.into_bytes());
+        key.push(0);
+        key.extend(tertiary);
+        key.push(0);
+        key.extend(text.as_bytes());
+        key
+    }
+
+    /// Compare two texts
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.sort_key(a).cmp(&self.sort_key(b))
+    }
+}
+
+impl Default for Collator {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
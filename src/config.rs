@@ -1,6 +1,18 @@
 //! Configuration for SoundVault
 
+use crate::artifacts::ArtifactPolicy;
+use crate::cache::DEFAULT_CAPACITY;
+use crate::cas::StorageLayout;
 use crate::error::{Result, VaultError};
+use crate::expiration::ExpireAction;
+use crate::human::{ByteSize, HumanDuration, deserialize_days};
+use crate::license::LicensePolicy;
+use crate::maintenance::AutoMaintenance;
+use crate::quota::QuotaAction;
+#[cfg(feature = "s3")]
+use crate::s3::S3Config;
+use crate::similar::SimilarityWeights;
+use crate::slug::SlugPolicy;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -18,6 +30,235 @@ pub struct VaultConfig {
 
     /// Default cache behavior for downloaded sounds
     pub cache_downloaded_sounds: bool,
+
+    /// Locale used to order names (e.g. `fr`, `sv-SE`); `None` uses
+    /// language-neutral ordering and `"C"` plain byte ordering
+    #[serde(default)]
+    pub sort_locale: Option<String>,
+
+    /// Background jobs, such as spectrogram rendering, run at once; `None`
+    /// uses half the available cores
+    #[serde(default)]
+    pub max_background_jobs: Option<usize>,
+
+    /// Downloads from remote sources run at once; `None` allows 4
+    #[serde(default)]
+    pub max_transfers: Option<usize>,
+
+    /// Rows background tasks, such as the scrubber, write per batch;
+    /// `None` uses 16
+    #[serde(default)]
+    pub write_batch_size: Option<usize>,
+
+    /// Start downloading the entries left in the download queue once the
+    /// vault is opened, as soon as its startup work is done
+    #[serde(default)]
+    pub resume_downloads: bool,
+
+    /// Queued downloads started per minute; `None` uses 60, Freesound's
+    /// rate limit
+    #[serde(default)]
+    pub downloads_per_minute: Option<u32>,
+
+    /// Analysis descriptors fetched with downloaded remote sounds and stored
+    /// in [`SoundMetadata::descriptors`](crate::SoundMetadata::descriptors),
+    /// e.g. `pitch` or `spectral_centroid`; empty fetches none
+    #[serde(default)]
+    pub remote_descriptors: Vec<String>,
+
+    /// Prefix of the names of the vault's tables, so they can live in a
+    /// database shared with the host application
+    #[serde(default = "default_table_prefix")]
+    pub table_prefix: String,
+
+    /// Record the name of this machine in the provenance of the files added
+    /// to the library
+    #[serde(default)]
+    pub record_hostname: bool,
+
+    /// Limits on the previews, spectrograms and waveform images kept, enforced by
+    /// [`SoundVault::gc_artifacts`](crate::SoundVault::gc_artifacts)
+    #[serde(default)]
+    pub artifacts: ArtifactPolicy,
+
+    /// Licenses remote sounds may be downloaded under; `None` allows any
+    #[serde(default)]
+    pub license_policy: Option<LicensePolicy>,
+
+    /// Name of a collection every downloaded sound is added to, created when
+    /// first needed; `None` adds them to none, leaving the collection as is
+    #[serde(default)]
+    pub auto_collect_downloads: Option<String>,
+
+    /// How long changes are kept in the feed of
+    /// [`SoundVault::changes_since`](crate::SoundVault::changes_since),
+    /// pruned in the background once the vault is opened; `None` keeps
+    /// them all. Read from `change_retention_days` too, and a bare number
+    /// counts days, as it used to
+    #[serde(default, alias = "change_retention_days", deserialize_with = "deserialize_days")]
+    pub change_retention: Option<HumanDuration>,
+
+    /// Name files after the format read from their header when their
+    /// extension disagrees: imported files are stored under the right
+    /// extension, and [`SoundVault::repair`](crate::SoundVault::repair)
+    /// renames those already stored
+    #[serde(default)]
+    pub fix_extensions: bool,
+
+    /// Store downloaded files under the name the source gives them, e.g.
+    /// `475832__someuser__wind-gust-03.flac`, and name their sounds as the
+    /// source does. By default sounds are named after the cleaned-up title
+    /// and their file after the sound
+    #[serde(default)]
+    pub keep_remote_file_names: bool,
+
+    /// Re-hash a small batch of the sounds verified longest ago this often,
+    /// in the background, to catch files rotting on disk; `None` only
+    /// scrubs on [`SoundVault::scrub_now`](crate::SoundVault::scrub_now)
+    #[serde(default)]
+    pub scrub_interval: Option<HumanDuration>,
+
+    /// Write the last-accessed time of the sounds opened since the last
+    /// write this often, in the background, as one batch; `None` only
+    /// writes them on [`SoundVault::close`](crate::SoundVault::close) and
+    /// [`SoundVault::flush_access_times`](crate::SoundVault::flush_access_times).
+    /// A crash loses the accesses since the last write
+    #[serde(default = "default_access_flush_interval")]
+    pub access_flush_interval: Option<HumanDuration>,
+
+    /// How much each feature counts when looking for similar sounds with
+    /// [`SoundVault::similar_local`](crate::SoundVault::similar_local)
+    #[serde(default)]
+    pub similarity_weights: SimilarityWeights,
+
+    /// Most bytes the sounds' files stored in the library and the generated
+    /// files may take; imports and downloads that would exceed it fail.
+    /// `None` is unlimited
+    #[serde(default)]
+    pub max_library_bytes: Option<ByteSize>,
+
+    /// What to do when a file would exceed
+    /// [`max_library_bytes`](Self::max_library_bytes)
+    #[serde(default)]
+    pub on_quota: QuotaAction,
+
+    /// Blob store the files of new imports and downloads move to once
+    /// stored; `None` keeps them in the library. The store must be
+    /// registered, e.g. with
+    /// [`SoundVault::add_blob_store`](crate::SoundVault::add_blob_store)
+    #[serde(default)]
+    pub default_blob_store: Option<String>,
+
+    /// Whether a sound's [`slug`](crate::SoundMetadata::slug) is made anew
+    /// when it's renamed
+    #[serde(default)]
+    pub slug_policy: SlugPolicy,
+
+    /// What to do with expired sounds when the vault opens; `None` leaves
+    /// them until [`SoundVault::enforce_expirations`](crate::SoundVault::enforce_expirations)
+    /// is called
+    #[serde(default)]
+    pub expire_on_open: Option<ExpireAction>,
+
+    /// Vacuum and tidy the database in the background once enough of it is
+    /// free, while no import, download or background job is under way;
+    /// `None` only maintains it on
+    /// [`SoundVault::maintenance`](crate::SoundVault::maintenance)
+    #[serde(default)]
+    pub auto_maintenance: Option<AutoMaintenance>,
+
+    /// Mirror the metadata of every sound and collection into JSON sidecar
+    /// files in the library, written in the background as they change, so
+    /// [`SoundVault::rebuild_from_sidecars`](crate::SoundVault::rebuild_from_sidecars)
+    /// can recover them if the database is lost
+    #[serde(default)]
+    pub write_sidecars: bool,
+
+    /// How files are laid out in the library. Content addressing stores each
+    /// content once, shared by the sounds holding it, at the cost of folders
+    /// that can't be browsed by sound; a vault keeps it once used, see
+    /// [`SoundVault::migrate_to_cas`](crate::SoundVault::migrate_to_cas)
+    #[serde(default)]
+    pub storage_layout: StorageLayout,
+
+    /// Most sounds [`SoundVault::get_sound`](crate::SoundVault::get_sound)
+    /// keeps in memory; 0 turns the cache off, e.g. when another process
+    /// writes to the same database
+    #[serde(default = "default_sound_cache_capacity")]
+    pub sound_cache_capacity: usize,
+
+    /// Key signing the [`PageCursor`](crate::PageCursor)s handed out, so
+    /// cursors that were altered or come from another vault are refused;
+    /// `None` leaves them unsigned
+    #[serde(default)]
+    pub page_cursor_secret: Option<String>,
+
+    /// Fingerprint imported sounds and report those resembling a sound
+    /// already fingerprinted at least this much, from 0 to 1, as
+    /// [`VaultEvent::PossibleDuplicate`](crate::VaultEvent::PossibleDuplicate)s;
+    /// about 0.6 catches most re-encodes. `None` checks nothing
+    #[cfg(feature = "analysis")]
+    #[serde(default)]
+    pub near_duplicate_threshold: Option<f64>,
+
+    /// S3-compatible bucket registered as a blob store when the vault opens
+    #[cfg(feature = "s3")]
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+}
+
+fn default_table_prefix() -> String {
+    "sv_".to_string()
+}
+
+fn default_access_flush_interval() -> Option<HumanDuration> {
+    Some(HumanDuration::from_secs(30))
+}
+
+fn default_sound_cache_capacity() -> usize {
+    DEFAULT_CAPACITY
+}
+
+/// How a size or duration field of the configuration is read
+#[derive(Clone, Copy)]
+enum HumanField {
+    Size,
+    Duration,
+    /// Durations whose bare numbers count days
+    Days,
+}
+
+/// Size and duration fields of the configuration, by path
+const HUMAN_FIELDS: &[(&str, HumanField)] = &[
+    ("change_retention", HumanField::Days),
+    ("change_retention_days", HumanField::Days),
+    ("scrub_interval", HumanField::Duration),
+    ("access_flush_interval", HumanField::Duration),
+    ("max_library_bytes", HumanField::Size),
+    ("auto_maintenance.check_interval", HumanField::Duration),
+    ("artifacts.previews.max_bytes", HumanField::Size),
+    ("artifacts.previews.max_age", HumanField::Days),
+    ("artifacts.previews.max_age_days", HumanField::Days),
+    ("artifacts.spectrograms.max_bytes", HumanField::Size),
+    ("artifacts.spectrograms.max_age", HumanField::Days),
+    ("artifacts.spectrograms.max_age_days", HumanField::Days),
+    ("artifacts.waveforms.max_bytes", HumanField::Size),
+    ("artifacts.waveforms.max_age", HumanField::Days),
+    ("artifacts.waveforms.max_age_days", HumanField::Days),
+];
+
+/// The first size or duration of a configuration document that can't be
+/// read, as `path: reason`
+fn human_field_error(document: &serde_json::Value) -> Option<String> {
+    HUMAN_FIELDS.iter().find_map(|(path, kind)| {
+        let value = path.split('.').try_fold(document, |value, key| value.get(key))?.clone();
+        let read = match kind {
+            HumanField::Size => Option::<ByteSize>::deserialize(value).map(drop),
+            HumanField::Duration => Option::<HumanDuration>::deserialize(value).map(drop),
+            HumanField::Days => deserialize_days(value).map(drop),
+        };
+        read.err().map(|e| format!("{}: {}", path, e))
+    })
 }
 
 impl VaultConfig {
@@ -46,9 +287,97 @@ impl VaultConfig {
             database_path: db_path,
             freesound_api_key,
             cache_downloaded_sounds: true,
+            sort_locale: None,
+            max_background_jobs: None,
+            max_transfers: None,
+            write_batch_size: None,
+            resume_downloads: false,
+            downloads_per_minute: None,
+            remote_descriptors: Vec::new(),
+            table_prefix: default_table_prefix(),
+            record_hostname: false,
+            artifacts: ArtifactPolicy::default(),
+            license_policy: None,
+            auto_collect_downloads: None,
+            change_retention: None,
+            fix_extensions: false,
+            keep_remote_file_names: false,
+            scrub_interval: None,
+            access_flush_interval: default_access_flush_interval(),
+            similarity_weights: SimilarityWeights::default(),
+            max_library_bytes: None,
+            on_quota: QuotaAction::Fail,
+            default_blob_store: None,
+            slug_policy: SlugPolicy::Stable,
+            expire_on_open: None,
+            auto_maintenance: None,
+            write_sidecars: false,
+            storage_layout: StorageLayout::Classic,
+            sound_cache_capacity: DEFAULT_CAPACITY,
+            page_cursor_secret: None,
+            #[cfg(feature = "analysis")]
+            near_duplicate_threshold: None,
+            #[cfg(feature = "s3")]
+            s3: None,
         }
     }
 
+    /// Parse a configuration written in either JSON or TOML
+    ///
+    /// Sizes and durations are written with their unit, see [`ByteSize`]
+    /// and [`HumanDuration`]; one that can't be read is reported with the
+    /// path of its field.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ByteSize, HumanDuration, VaultConfig};
+    ///
+    /// let config = VaultConfig::parse(r#"
+    ///     library_path = "./sounds"
+    ///     database_path = "./sounds/soundvault.db"
+    ///     cache_downloaded_sounds = true
+    ///     max_library_bytes = "500MB"
+    ///     scrub_interval = "2h"
+    ///     change_retention_days = 30
+    ///
+    ///     [artifacts.previews]
+    ///     max_bytes = "64MiB"
+    ///     max_age = "1w"
+    /// "#)?;
+    /// assert_eq!(config.max_library_bytes, Some(ByteSize::mb(500)));
+    /// assert_eq!(config.scrub_interval, Some(HumanDuration::from_hours(2)));
+    /// assert_eq!(config.change_retention, Some(HumanDuration::from_days(30)));
+    /// assert_eq!(config.artifacts.previews.max_age, Some(HumanDuration::from_days(7)));
+    ///
+    /// let error = VaultConfig::parse(r#"{
+    ///     "library_path": "./sounds",
+    ///     "database_path": "./sounds/soundvault.db",
+    ///     "cache_downloaded_sounds": true,
+    ///     "artifacts": { "waveforms": { "max_bytes": "12 parsecs" } }
+    /// }"#).unwrap_err();
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     r#"Configuration error: artifacts.waveforms.max_bytes: Invalid size "12 parsecs": unknown unit "parsecs""#
+    /// );
+    /// # Ok::<(), soundvault::VaultError>(())
+    /// ```
+    pub fn parse(config: &str) -> Result<Self> {
+        let json = config.trim_start().starts_with('{');
+        let parsed = match json {
+            true => serde_json::from_str(config).map_err(|e| e.to_string()),
+            false => toml::from_str(config).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|error| {
+            let document = match json {
+                true => serde_json::from_str(config).ok(),
+                false => toml::from_str::<toml::Value>(config).ok().and_then(|value| serde_json::to_value(value).ok()),
+            };
+            let field = document.and_then(|document| human_field_error(&document));
+            VaultError::Config(field.unwrap_or_else(|| format!("Invalid configuration: {}", error)))
+        })
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<()> {
         // Check if the library path exists or can be created
@@ -67,6 +396,20 @@ impl VaultConfig {
             )));
         }
 
+        if let Some(policy) = &self.auto_maintenance {
+            policy.validate()?;
+        }
+
+        #[cfg(feature = "analysis")]
+        if let Some(threshold) = self.near_duplicate_threshold
+            && !(0.0..=1.0).contains(&threshold)
+        {
+            return Err(VaultError::Config(format!(
+                "Near-duplicate threshold is not between 0 and 1: {}",
+                threshold
+            )));
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,155 @@
+//! Bounding long operations with a timeout or a cancellation token
+
+use crate::error::{Result, VaultError};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Limits on one long-running operation, given to the `*_with_context`
+/// methods of [`SoundVault`](crate::SoundVault)
+///
+/// Operations check the context between files, pages and database batches,
+/// and while waiting on remote sources. When time runs out they fail with
+/// [`VaultError::Timeout`], when the token is cancelled with
+/// [`VaultError::Cancelled`]; both tell how far the operation got. Whether
+/// the work done so far is kept is documented by each method. The default
+/// context never stops an operation.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{CancellationToken, OpContext, SoundVault, SyncOptions, VaultConfig, VaultError};
+/// use std::time::Duration;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let library = dir.path().join("library");
+/// std::fs::create_dir(&library)?;
+/// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+/// let field = dir.path().join("field");
+/// std::fs::create_dir(&field)?;
+/// for i in 0..3 {
+///     std::fs::write(field.join(format!("take-{}.wav", i)), format!("take {}", i))?;
+/// }
+///
+/// // The caller gave up before anything was synced
+/// let cancel = CancellationToken::new();
+/// cancel.cancel();
+/// let context = OpContext::new().with_cancel(cancel);
+/// let result = vault.sync_directory_with_context(&field, SyncOptions::default(), &context).await;
+/// assert!(matches!(result, Err(VaultError::Cancelled { progress, .. }) if progress == "0 of 3 files synced"));
+/// assert!(vault.search_local("take", None).await?.is_empty());
+///
+/// // Plenty of time
+/// let context = OpContext::new().with_timeout(Duration::from_secs(60));
+/// let report = vault.sync_directory_with_context(&field, SyncOptions::default(), &context).await?;
+/// assert_eq!(report.imported.len(), 3);
+///
+/// // No time at all
+/// let context = OpContext::new().with_timeout(Duration::ZERO);
+/// let result = vault.scan_integrity_with_context(&context).await;
+/// assert!(matches!(result, Err(VaultError::Timeout { .. })));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OpContext {
+    /// Time the operation may take; `None` lets it run to the end
+    pub timeout: Option<Duration>,
+
+    /// Token that stops the operation when cancelled
+    pub cancel: Option<CancellationToken>,
+}
+
+impl OpContext {
+    /// A context that never stops an operation
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the operation once it ran for `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stop the operation when `token` is cancelled
+    pub fn with_cancel(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Start timing an operation
+    pub(crate) fn start(&self, operation: &'static str) -> Deadline<'_> {
+        Deadline {
+            context: self,
+            operation,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// A running operation bounded by an [`OpContext`]
+pub(crate) struct Deadline<'a> {
+    context: &'a OpContext,
+    operation: &'static str,
+    started: Instant,
+}
+
+impl Deadline<'_> {
+    /// Fail if the operation was cancelled or ran out of time
+    ///
+    /// `progress` tells how far it got, e.g. `3 of 12 files synced`.
+    pub(crate) fn check(&self, progress: impl FnOnce() -> String) -> Result<()> {
+        if self.context.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(self.stopped(true, progress()));
+        }
+        if self.context.timeout.is_some_and(|timeout| self.started.elapsed() >= timeout) {
+            return Err(self.stopped(false, progress()));
+        }
+        Ok(())
+    }
+
+    /// Wait for `future`, giving up when the operation is cancelled or runs
+    /// out of time
+    pub(crate) async fn bound<T>(
+        &self,
+        future: impl Future<Output = Result<T>>,
+        progress: impl FnOnce() -> String,
+    ) -> Result<T> {
+        let timeout = async {
+            match self.context.timeout {
+                Some(timeout) => tokio::time::sleep(timeout.saturating_sub(self.started.elapsed())).await,
+                None => std::future::pending().await,
+            }
+        };
+        let cancelled = async {
+            match &self.context.cancel {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let cancelled = tokio::select! {
+            biased;
+            _ = cancelled => true,
+            _ = timeout => false,
+            result = future => return result,
+        };
+        Err(self.stopped(cancelled, progress()))
+    }
+
+    fn stopped(&self, cancelled: bool, progress: String) -> VaultError {
+        let operation = self.operation.to_string();
+        if cancelled {
+            VaultError::Cancelled { operation, progress }
+        } else {
+            VaultError::Timeout {
+                operation,
+                elapsed: self.started.elapsed(),
+                progress,
+            }
+        }
+    }
+}
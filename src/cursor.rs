@@ -0,0 +1,287 @@
+//! Lazy iteration over query results
+
+use crate::error::Result;
+use crate::local::LocalLibrary;
+use crate::models::Sound;
+use crate::query::SoundFilter;
+use sqlx::QueryBuilder;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sounds fetched from the database per page
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Rows examined per query while ranking a shuffled page
+const SCAN_CHUNK: i64 = 1000;
+
+/// Weight given to sounds without a duration in a duration-weighted shuffle
+const MIN_WEIGHT: f64 = 0.001;
+
+/// Order in which a cursor yields sounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CursorOrder {
+    /// By ID
+    Sequential,
+    /// By a keyed hash of the ID, optionally favouring long sounds
+    Shuffled { seed: u64, weighted: bool },
+}
+
+/// Async cursor over the sounds matching a filter
+///
+/// Results are fetched a page at a time. Sequential cursors yield sounds by
+/// ID; shuffled cursors yield a permutation derived from a seed, so the same
+/// seed replays the same order. Either way the position is tracked by key
+/// rather than offset, so sounds inserted while iterating never cause
+/// repeats or skips.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{SoundFilter, SoundVault};
+///
+/// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+/// let filter = SoundFilter {
+///     tags: vec!["wind".to_string()],
+///     ..Default::default()
+/// };
+///
+/// let mut cursor = vault.iter(filter).shuffled(Some(42));
+/// println!("about {} sounds", cursor.len_hint().await?);
+/// while let Some(sound) = cursor.next().await? {
+///     println!("{}", sound.metadata.name);
+/// }
+///
+/// // Same seed, same order
+/// cursor.reset();
+/// # Ok(())
+/// # }
+/// ```
+pub struct SoundCursor<'a> {
+    library: &'a LocalLibrary,
+    filter: SoundFilter,
+    order: CursorOrder,
+    page_size: usize,
+    /// Key and ID of the last sound fetched
+    position: Option<(f64, String)>,
+    buffer: VecDeque<Sound>,
+    exhausted: bool,
+    yielded: u64,
+    total: Option<u64>,
+}
+
+impl<'a> SoundCursor<'a> {
+    /// Create a sequential cursor
+    pub(crate) fn new(library: &'a LocalLibrary, filter: SoundFilter) -> Self {
+        Self {
+            library,
+            filter,
+            order: CursorOrder::Sequential,
+            page_size: DEFAULT_PAGE_SIZE,
+            position: None,
+            buffer: VecDeque::new(),
+            exhausted: false,
+            yielded: 0,
+            total: None,
+        }
+    }
+
+    /// Yield sounds in a reproducible random order
+    ///
+    /// `None` picks a fresh seed, available from [`SoundCursor::seed`].
+    pub fn shuffled(mut self, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        let weighted = matches!(self.order, CursorOrder::Shuffled { weighted: true, .. });
+        self.order = CursorOrder::Shuffled { seed, weighted };
+        self.reset();
+        self
+    }
+
+    /// Shuffle so that longer sounds tend to come first
+    ///
+    /// A sound twice as long is twice as likely to come before another.
+    /// Implies [`SoundCursor::shuffled`] with a fresh seed unless one was set.
+    pub fn weighted_by_duration(self) -> Self {
+        let mut cursor = match self.order {
+            CursorOrder::Sequential => self.shuffled(None),
+            CursorOrder::Shuffled { .. } => self,
+        };
+        if let CursorOrder::Shuffled { seed, .. } = cursor.order {
+            cursor.order = CursorOrder::Shuffled { seed, weighted: true };
+        }
+        cursor.reset();
+        cursor
+    }
+
+    /// Set how many sounds are fetched at a time
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Seed of a shuffled cursor
+    pub fn seed(&self) -> Option<u64> {
+        match self.order {
+            CursorOrder::Sequential => None,
+            CursorOrder::Shuffled { seed, .. } => Some(seed),
+        }
+    }
+
+    /// Estimate how many sounds are left
+    ///
+    /// The number of matching sounds is counted once; sounds inserted or
+    /// deleted afterwards are not reflected.
+    pub async fn len_hint(&mut self) -> Result<u64> {
+        let total = match self.total {
+            Some(total) => total,
+            None => {
+                let total = self.library.count(&self.filter).await?;
+                self.total = Some(total);
+                total
+            }
+        };
+        Ok(total.saturating_sub(self.yielded))
+    }
+
+    /// Start again from the first sound, keeping the order and seed
+    pub fn reset(&mut self) {
+        self.position = None;
+        self.buffer.clear();
+        self.exhausted = false;
+        self.yielded = 0;
+    }
+
+    /// Fetch the next sound
+    pub async fn next(&mut self) -> Result<Option<Sound>> {
+        if self.buffer.is_empty() && !self.exhausted {
+            self.fetch_page().await?;
+        }
+
+        let sound = self.buffer.pop_front();
+        if sound.is_some() {
+            self.yielded += 1;
+        }
+        Ok(sound)
+    }
+
+    /// Fill the buffer with the next page of sounds
+    async fn fetch_page(&mut self) -> Result<()> {
+        let page = match self.order {
+            CursorOrder::Sequential => self.next_sequential_page().await?,
+            CursorOrder::Shuffled { seed, weighted } => self.next_shuffled_page(seed, weighted).await?,
+        };
+
+        if page.len() < self.page_size {
+            self.exhausted = true;
+        }
+        if let Some(last) = page.last() {
+            self.position = Some(last.clone());
+        }
+        for (_, id) in page {
+            self.buffer.push_back(self.library.get_sound(&id).await?);
+        }
+
+        Ok(())
+    }
+
+    /// Next IDs in ID order
+    async fn next_sequential_page(&self) -> Result<Vec<(f64, String)>> {
+        let mut builder = QueryBuilder::new(self.library.sql("SELECT id FROM sounds"));
+        self.filter.push_where(&mut builder, &self.library.tables);
+        if let Some((_, id)) = &self.position {
+            builder.push(" AND id > ");
+            builder.push_bind(id.clone());
+        }
+        builder.push(" ORDER BY id LIMIT ");
+        builder.push_bind(self.page_size as i64);
+
+        let ids: Vec<String> = builder.build_query_scalar().fetch_all(&self.library.db).await?;
+        Ok(ids.into_iter().map(|id| (0.0, id)).collect())
+    }
+
+    /// Next IDs in shuffled order
+    ///
+    /// Every matching sound is ranked by its key, reading the table in chunks,
+    /// and the lowest keys past the current position are kept.
+    async fn next_shuffled_page(&self, seed: u64, weighted: bool) -> Result<Vec<(f64, String)>> {
+        let mut page: Vec<(f64, String)> = Vec::with_capacity(self.page_size + 1);
+        let mut last_id: Option<String> = None;
+
+        loop {
+            let mut builder = QueryBuilder::new(self.library.sql("SELECT id, duration FROM sounds"));
+            self.filter.push_where(&mut builder, &self.library.tables);
+            if let Some(id) = &last_id {
+                builder.push(" AND id > ");
+                builder.push_bind(id.clone());
+            }
+            builder.push(" ORDER BY id LIMIT ");
+            builder.push_bind(SCAN_CHUNK);
+
+            let rows: Vec<(String, Option<f64>)> = builder.build_query_as().fetch_all(&self.library.db).await?;
+            let done = (rows.len() as i64) < SCAN_CHUNK;
+            last_id = rows.last().map(|(id, _)| id.clone());
+
+            for (id, duration) in rows {
+                let key = shuffle_key(seed, &id, weighted.then(|| duration.unwrap_or_default()));
+                let entry = (key, id);
+                if self.position.as_ref().is_some_and(|position| !is_after(&entry, position)) {
+                    continue;
+                }
+                if page.len() == self.page_size && page.last().is_some_and(|last| !is_after(last, &entry)) {
+                    continue;
+                }
+
+                let index = page.partition_point(|existing| !is_after(existing, &entry));
+                page.insert(index, entry);
+                page.truncate(self.page_size);
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        Ok(page)
+    }
+}
+
+/// Whether `a` comes after `b` in cursor order
+fn is_after(a: &(f64, String), b: &(f64, String)) -> bool {
+    a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)).is_gt()
+}
+
+/// Position of a sound in a shuffled order
+///
+/// A keyed hash of the ID gives a uniform value in `(0, 1]`; with a duration
+/// the key becomes an exponential variate of rate `duration`, so longer sounds
+/// tend to sort first (weighted sampling without replacement).
+pub(crate) fn shuffle_key(seed: u64, id: &str, duration: Option<f64>) -> f64 {
+    // FNV-1a, then the SplitMix64 finalizer to spread the bits
+    let mut hash = 0xcbf2_9ce4_8422_2325u64 ^ seed;
+    for byte in id.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+
+    let uniform = ((hash >> 11) + 1) as f64 / (1u64 << 53) as f64;
+    match duration {
+        Some(duration) => -uniform.ln() / duration.max(MIN_WEIGHT),
+        None => uniform,
+    }
+}
+
+impl LocalLibrary {
+    /// Iterate lazily over the sounds matching a filter, in ID order
+    pub fn iter(&self, filter: SoundFilter) -> SoundCursor<'_> {
+        SoundCursor::new(self, filter)
+    }
+}
@@ -0,0 +1,181 @@
+//! Handing a collection to an editor as a folder a DAW opens
+//!
+//! The sounds are exported into a `Media` folder, attribution included,
+//! next to a session referencing them by relative path, so the folder can
+//! be moved or zipped as a whole.
+
+use crate::attribution::ExportOptions;
+use crate::audio::export_file_name;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::paths::{safe_file_name, write_atomic};
+use crate::schema::{DAW_TRACKS_SCHEMA, Envelope};
+use crate::sniff::FileFormat;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Folder of the session the sounds are exported into
+const MEDIA_DIR: &str = "Media";
+
+/// Name of the track list written with [`DawFlavor::Generic`]
+const TRACK_LIST: &str = "tracks.json";
+
+/// Kind of session [`SoundVault::export_daw_session`](crate::SoundVault::export_daw_session)
+/// writes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DawFlavor {
+    /// A Reaper project, `<collection>.rpp`, with a track per sound
+    Reaper,
+    /// Only the media, with a JSON track list, `tracks.json`, in an
+    /// [`Envelope`] of schema `soundvault.daw-tracks`
+    #[default]
+    Generic,
+}
+
+/// A track of an exported session, as listed in `tracks.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DawTrack {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// Name of the track, the sound's
+    pub name: String,
+
+    /// Path of the file, relative to the session folder, with `/` separators
+    pub file: String,
+
+    /// Where the item starts in the file, in seconds, if the sound is trimmed
+    pub offset: f32,
+
+    /// Length of the item in seconds, from the sound's metadata
+    pub length: f32,
+}
+
+impl DawTrack {
+    fn new(metadata: &SoundMetadata, file: String) -> Self {
+        let offset = metadata.trim.as_ref().map_or(0.0, |trim| trim.start);
+        let end = metadata
+            .trim
+            .as_ref()
+            .and_then(|trim| trim.end)
+            .unwrap_or(metadata.duration);
+        Self {
+            sound_id: metadata.id.clone(),
+            name: metadata.name.clone(),
+            file,
+            offset,
+            length: (end - offset).max(0.0),
+        }
+    }
+}
+
+/// Name of a sound's file in the media folder, unique among `taken`
+fn media_file_name(metadata: &SoundMetadata, taken: &mut HashSet<String>) -> String {
+    let exported = export_file_name(metadata);
+    let extension = exported.extension().map(|ext| ext.to_string_lossy().to_string());
+    let name = safe_file_name(&metadata.name, &metadata.id);
+
+    // Names often end with the extension already
+    let stem = match (Path::new(&name).extension(), &extension) {
+        (Some(own), Some(extension)) if own.eq_ignore_ascii_case(extension) => {
+            Path::new(&name).file_stem().unwrap_or_default().to_string_lossy().to_string()
+        }
+        _ => name,
+    };
+    let with_extension = |stem: &str| match &extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    };
+
+    let mut file_name = with_extension(&stem);
+    let mut number = 1;
+    while !taken.insert(file_name.to_lowercase()) {
+        number += 1;
+        file_name = with_extension(&format!("{} ({})", stem, number));
+    }
+    file_name
+}
+
+/// Quote a string for a Reaper project, which has no escapes: the first
+/// quote character the string doesn't contain is used
+fn rpp_string(text: &str) -> String {
+    match ['"', '\'', '`'].into_iter().find(|quote| !text.contains(*quote)) {
+        Some(quote) => format!("{}{}{}", quote, text, quote),
+        None => format!("`{}`", text.replace('`', "'")),
+    }
+}
+
+/// Reaper's name for the kind of source a file is
+fn rpp_source(format: Option<FileFormat>) -> &'static str {
+    match format {
+        Some(FileFormat::Flac) => "FLAC",
+        Some(FileFormat::Mp3) => "MP3",
+        Some(FileFormat::Ogg) => "VORBIS",
+        _ => "WAVE",
+    }
+}
+
+/// A Reaper project with one track per sound, each item at the start
+fn reaper_project(tracks: &[(DawTrack, Option<FileFormat>)]) -> String {
+    let mut project = String::from("<REAPER_PROJECT 0.1 \"6.0\" 0\n");
+    for (track, format) in tracks {
+        let name = rpp_string(&track.name);
+        let _ = write!(
+            project,
+            "  <TRACK\n    NAME {name}\n    <ITEM\n      POSITION 0\n      LENGTH {}\n      SOFFS {}\n      NAME {name}\n      <SOURCE {}\n        FILE {}\n      >\n    >\n  >\n",
+            track.length,
+            track.offset,
+            rpp_source(*format),
+            rpp_string(&track.file),
+        );
+    }
+    project.push_str(">\n");
+    project
+}
+
+impl LocalLibrary {
+    /// Export a collection's sounds into `target_dir` with a session of
+    /// the given flavor
+    pub async fn export_daw_session(&self, collection_id: &str, target_dir: &Path, flavor: DawFlavor) -> Result<()> {
+        let collection = self.get_collection(collection_id).await?;
+        let media = target_dir.join(MEDIA_DIR);
+        std::fs::create_dir_all(&media).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to create directory: {}", e))
+        })?;
+
+        let mut taken = HashSet::new();
+        let mut tracks = Vec::new();
+        for sound_id in &collection.sound_ids {
+            let metadata = self.get_sound(sound_id).await?.metadata;
+            if metadata.path.is_none() || self.exclude_expired(&metadata, "export_daw_session") {
+                continue;
+            }
+            let file_name = media_file_name(&metadata, &mut taken);
+            self.export_sound_with_options(sound_id, &media.join(&file_name), &ExportOptions::default())
+                .await?;
+            let track = DawTrack::new(&metadata, format!("{}/{}", MEDIA_DIR, file_name));
+            tracks.push((track, metadata.format));
+        }
+
+        let (file_name, session) = match flavor {
+            DawFlavor::Reaper => (
+                format!("{}.rpp", safe_file_name(&collection.name, "session")),
+                reaper_project(&tracks),
+            ),
+            DawFlavor::Generic => {
+                let tracks: Vec<&DawTrack> = tracks.iter().map(|(track, _)| track).collect();
+                let tracks = Envelope::wrap(DAW_TRACKS_SCHEMA, tracks)?;
+                (TRACK_LIST.to_string(), serde_json::to_string_pretty(&tracks)?)
+            }
+        };
+        write_atomic(&target_dir.join(file_name), session.as_bytes()).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to write session: {}", e))
+        })?;
+
+        Ok(())
+    }
+}
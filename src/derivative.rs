@@ -0,0 +1,240 @@
+//! Processed variants of sounds
+
+use crate::audio::{AudioInfo, decode, encode, writable_format};
+use crate::error::{Result, VaultError};
+use crate::journal::OpKind;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{SoundMetadata, SoundSource};
+use crate::paths::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Cursor;
+use std::ops::Range;
+use uuid::Uuid;
+
+/// Tag given to every derivative
+pub const DERIVATIVE_TAG: &str = "derivative";
+
+/// A processing step applied when creating a derivative
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AudioOp {
+    /// Change the level by this many decibels
+    Gain(f32),
+    /// Convert to this sample rate, keeping the pitch and speed
+    Resample(u32),
+    /// Play backwards
+    Reverse,
+    /// Fade in over this many milliseconds
+    FadeIn(u32),
+    /// Fade out over this many milliseconds
+    FadeOut(u32),
+    /// Play this many times faster; the pitch follows
+    SpeedChange(f32),
+}
+
+impl fmt::Display for AudioOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gain(db) => write!(f, "gain {:+} dB", db),
+            Self::Resample(rate) => write!(f, "{} Hz", rate),
+            Self::Reverse => write!(f, "reversed"),
+            Self::FadeIn(ms) => write!(f, "fade in {} ms", ms),
+            Self::FadeOut(ms) => write!(f, "fade out {} ms", ms),
+            Self::SpeedChange(factor) => write!(f, "{}x", factor),
+        }
+    }
+}
+
+/// Apply processing steps to interleaved samples
+///
+/// # Returns
+///
+/// The processed samples and their sample rate
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{AudioOp, apply_ops};
+///
+/// // Two mono frames at 8 kHz
+/// let (samples, rate) = apply_ops(&[0.25, 0.5], 1, 8000, &[AudioOp::Reverse, AudioOp::Gain(6.0206)]).unwrap();
+/// assert_eq!(rate, 8000);
+/// assert!((samples[0] - 1.0).abs() < 1e-4 && (samples[1] - 0.5).abs() < 1e-4);
+///
+/// // Twice as fast keeps the rate and halves the frames
+/// let (samples, rate) = apply_ops(&[0.0; 8], 1, 8000, &[AudioOp::SpeedChange(2.0)]).unwrap();
+/// assert_eq!((samples.len(), rate), (4, 8000));
+/// ```
+pub fn apply_ops(samples: &[f32], channels: u16, sample_rate: u32, ops: &[AudioOp]) -> Result<(Vec<f32>, u32)> {
+    let channels = channels.max(1) as usize;
+    let mut samples = samples.to_vec();
+    let mut sample_rate = sample_rate;
+
+    for op in ops {
+        match *op {
+            AudioOp::Gain(db) => {
+                if !db.is_finite() {
+                    return Err(invalid(op));
+                }
+                let factor = 10f32.powf(db / 20.0);
+                samples.iter_mut().for_each(|s| *s *= factor);
+            }
+            AudioOp::Resample(rate) => {
+                if rate == 0 {
+                    return Err(invalid(op));
+                }
+                samples = resample(&samples, channels, sample_rate as f64 / rate as f64);
+                sample_rate = rate;
+            }
+            AudioOp::Reverse => {
+                samples = samples.chunks_exact(channels).rev().flatten().copied().collect();
+            }
+            AudioOp::FadeIn(ms) => {
+                let frames = fade_frames(ms, sample_rate);
+                for (index, frame) in samples.chunks_exact_mut(channels).take(frames).enumerate() {
+                    let level = index as f32 / frames as f32;
+                    frame.iter_mut().for_each(|s| *s *= level);
+                }
+            }
+            AudioOp::FadeOut(ms) => {
+                let frames = fade_frames(ms, sample_rate);
+                for (index, frame) in samples.chunks_exact_mut(channels).rev().take(frames).enumerate() {
+                    let level = index as f32 / frames as f32;
+                    frame.iter_mut().for_each(|s| *s *= level);
+                }
+            }
+            AudioOp::SpeedChange(factor) => {
+                if !(factor.is_finite() && factor > 0.0) {
+                    return Err(invalid(op));
+                }
+                samples = resample(&samples, channels, factor as f64);
+            }
+        }
+    }
+
+    Ok((samples, sample_rate))
+}
+
+/// Read frames every `step` source frames, interpolating linearly
+fn resample(samples: &[f32], channels: usize, step: f64) -> Vec<f32> {
+    let frames = resampled_len(samples.len() / channels, step);
+    resample_frames(samples, channels, step, 0..frames)
+}
+
+/// Number of frames left after reading every `step` of `frames` frames
+pub(crate) fn resampled_len(frames: usize, step: f64) -> usize {
+    if frames == 0 {
+        return 0;
+    }
+    (frames as f64 / step).round().max(1.0) as usize
+}
+
+/// Frames `range` of the output of [`resample`]
+pub(crate) fn resample_frames(samples: &[f32], channels: usize, step: f64, range: Range<usize>) -> Vec<f32> {
+    let frames = samples.len() / channels;
+    if frames == 0 {
+        return Vec::new();
+    }
+
+    let mut out = Vec::with_capacity(range.len() * channels);
+    for frame in range {
+        let position = frame as f64 * step;
+        let index = (position.floor() as usize).min(frames - 1);
+        let next = (index + 1).min(frames - 1);
+        let fraction = (position - index as f64).clamp(0.0, 1.0) as f32;
+        for channel in 0..channels {
+            let a = samples[index * channels + channel];
+            let b = samples[next * channels + channel];
+            out.push(a + (b - a) * fraction);
+        }
+    }
+    out
+}
+
+/// Number of frames in a fade of `ms` milliseconds, at least one
+fn fade_frames(ms: u32, sample_rate: u32) -> usize {
+    ((ms as u64 * sample_rate as u64 / 1000) as usize).max(1)
+}
+
+fn invalid(op: &AudioOp) -> VaultError {
+    VaultError::InvalidOperation(format!("Invalid audio operation: {:?}", op))
+}
+
+impl LocalLibrary {
+    /// Create a processed copy of a sound as a new sound
+    ///
+    /// The derivative keeps the parent's description, license and tags, gets
+    /// the `derivative` tag and records its parent in `derived_from`.
+    ///
+    /// # Returns
+    ///
+    /// ID of the new sound
+    pub async fn create_derivative(&self, id: &str, ops: &[AudioOp]) -> Result<String> {
+        let parent = self.stored_sound(id).await?;
+        let source = Self::logical_path(&parent).ok_or_else(|| {
+            VaultError::InvalidOperation(format!("Sound has no file: {}", parent.id))
+        })?;
+        let (info, samples) = decode(&mut Cursor::new(self.sound_bytes(&parent)?))?;
+        let (samples, sample_rate) = apply_ops(&samples, info.channels, info.sample_rate, ops)?;
+
+        let output = AudioInfo::new(info.format, info.channels, sample_rate, writable_format(info.sample_format));
+        let bytes = encode(&output, &samples)?;
+
+        let derivative_id = Uuid::new_v4().to_string();
+        let file_name = source.file_name().ok_or_else(|| {
+            VaultError::FileSystem("Invalid source path".to_string())
+        })?;
+        let target_path = self.library_file(&self.library_path.join(&derivative_id).join(file_name))?;
+        let op = self.begin_op(OpKind::Derive, &[&target_path], &[]).await?;
+        let result = async {
+            std::fs::create_dir_all(target_path.parent().unwrap_or(&self.library_path)).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to create directory: {}", e))
+            })?;
+            write_atomic(&target_path, &bytes).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to write derivative: {}", e))
+            })?;
+
+            let mut tags = parent.tags.clone();
+            if !tags.iter().any(|t| t == DERIVATIVE_TAG) {
+                tags.push(DERIVATIVE_TAG.to_string());
+            }
+            let steps: Vec<String> = ops.iter().map(AudioOp::to_string).collect();
+            let name = if steps.is_empty() {
+                parent.name.clone()
+            } else {
+                format!("{} ({})", parent.name, steps.join(", "))
+            };
+
+            let frames = samples.len() / info.channels.max(1) as usize;
+            let metadata = SoundMetadata {
+                id: derivative_id.clone(),
+                name,
+                source: SoundSource::Local,
+                tags,
+                description: parent.description.clone(),
+                duration: if sample_rate == 0 { 0.0 } else { (frames as f64 / sample_rate as f64) as f32 },
+                channels: Some(info.channels),
+                sample_rate: Some(sample_rate),
+                license: parent.license.clone(),
+                hash: Some(hash_file(&target_path)?),
+                path: Some(target_path.clone()),
+                derived_from: Some(parent.id.clone()),
+                expires_at: parent.expires_at,
+                ..Default::default()
+            };
+            self.insert_sound(&metadata, Some(&op), None).await
+        }
+        .await;
+        self.settle_op(&op, result).await?;
+
+        Ok(derivative_id)
+    }
+
+    /// IDs of the sounds derived from a sound
+    pub async fn derivatives(&self, id: &str) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE derived_from = ? ORDER BY id"))
+            .bind(id)
+            .fetch_all(&self.reader)
+            .await?)
+    }
+}
@@ -0,0 +1,453 @@
+//! Downloads queued in the database and run one at a time in the background
+
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::models::Collection;
+use crate::source::{RemoteSource, SharedSources};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{QueryBuilder, Row, Sqlite};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Custom metadata key marking the collection downloads are added to, set
+/// to the configured [`VaultConfig::auto_collect_downloads`](crate::VaultConfig::auto_collect_downloads)
+pub const AUTO_COLLECT_KEY: &str = "auto_collect_downloads";
+
+/// Downloads started per minute when not configured
+const DEFAULT_DOWNLOADS_PER_MINUTE: u32 = 60;
+
+/// Attempts at a download failing for a reason that may pass, e.g. a rate
+/// limit, before it's marked failed
+const MAX_TRANSIENT_ATTEMPTS: u32 = 3;
+
+/// Wait before retrying a download after a transient failure, per attempt
+/// made, when the source didn't say how long
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Columns of `download_queue` read into a [`QueuedDownload`]
+const QUEUE_COLUMNS: &str = "id, source, remote_id, collection_id, requested_at, state, attempts, last_error, sound_id";
+
+/// Where a queued download stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadState {
+    /// Waiting to be downloaded
+    Pending,
+    /// Being downloaded
+    Running,
+    /// In the library
+    Done,
+    /// The last attempt failed
+    Failed,
+    /// Cancelled before it was downloaded
+    Cancelled,
+}
+
+impl DownloadState {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown download state: {}", name)))
+    }
+}
+
+/// An entry of the download queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedDownload {
+    /// ID of the entry
+    pub id: i64,
+
+    /// Name of the remote source
+    pub source: String,
+
+    /// ID of the sound at the source
+    pub remote_id: String,
+
+    /// Collection the sound is added to once downloaded
+    pub collection_id: Option<String>,
+
+    /// When the download was queued
+    pub requested_at: DateTime<Utc>,
+
+    /// Where the download stands
+    pub state: DownloadState,
+
+    /// Number of times the download was started
+    pub attempts: u32,
+
+    /// Why the last attempt failed
+    pub last_error: Option<String>,
+
+    /// ID of the local sound, once downloaded
+    pub sound_id: Option<String>,
+}
+
+impl QueuedDownload {
+    fn from_row(row: &SqliteRow) -> Result<Self> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            source: row.try_get("source")?,
+            remote_id: row.try_get("remote_id")?,
+            collection_id: row.try_get("collection_id")?,
+            requested_at: row.try_get("requested_at")?,
+            state: DownloadState::parse(row.try_get("state")?)?,
+            attempts: row.try_get("attempts")?,
+            last_error: row.try_get("last_error")?,
+            sound_id: row.try_get("sound_id")?,
+        })
+    }
+}
+
+impl LocalLibrary {
+    /// ID of the collection downloaded sounds are added to, when configured
+    ///
+    /// The collection is the one marked with [`AUTO_COLLECT_KEY`]; failing
+    /// that, a collection with the configured name is marked, or a new one
+    /// created. A unique index on the marker keeps downloads finishing
+    /// together from creating two.
+    pub async fn downloads_collection(&self) -> Result<Option<String>> {
+        let Some(name) = &self.auto_collect_downloads else {
+            return Ok(None);
+        };
+        if let Some(id) = self.find_downloads_collection(name).await? {
+            return Ok(Some(id));
+        }
+
+        let named: Option<String> =
+            sqlx::query_scalar(&self.sql("SELECT id FROM collections WHERE name = ? ORDER BY created_at, id LIMIT 1"))
+                .bind(name)
+                .fetch_optional(&self.reader)
+                .await?;
+        match named {
+            Some(id) => {
+                sqlx::query(&self.sql("INSERT OR IGNORE INTO metadata (object_id, object_type, key, value) VALUES (?, 'collection', ?, ?)"))
+                    .bind(&id)
+                    .bind(AUTO_COLLECT_KEY)
+                    .bind(name)
+                    .execute(&self.db)
+                    .await?;
+            }
+            None => {
+                let mut collection = Collection::new(name, "Sounds downloaded into the vault");
+                collection.custom.insert(AUTO_COLLECT_KEY.to_string(), name.clone());
+                match self.add_collection(&collection).await {
+                    Ok(_) => {}
+                    // Another download created it first
+                    Err(VaultError::Database(sqlx::Error::Database(e))) if e.is_unique_violation() => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        self.find_downloads_collection(name)
+            .await?
+            .map(Some)
+            .ok_or_else(|| VaultError::InvalidOperation(format!("Collection {:?} for downloads couldn't be created", name)))
+    }
+
+    async fn find_downloads_collection(&self, name: &str) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT object_id FROM metadata WHERE object_type = 'collection' AND key = ? AND value = ?"))
+            .bind(AUTO_COLLECT_KEY)
+            .bind(name)
+            .fetch_optional(&self.reader)
+            .await?)
+    }
+
+    /// Add a downloaded sound to the collection of downloads, if configured
+    pub(crate) async fn collect_download(&self, sound_id: &str) -> Result<()> {
+        if let Some(collection_id) = self.downloads_collection().await? {
+            self.add_sound_to_collection(sound_id, &collection_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Add a download to the queue
+    ///
+    /// # Returns
+    ///
+    /// ID of the queue entry
+    pub async fn enqueue_download(&self, source: &str, remote_id: &str, collection_id: Option<&str>) -> Result<i64> {
+        if let Some(collection_id) = collection_id {
+            self.collection_size(collection_id).await?;
+        }
+
+        let id = sqlx::query(
+            &self.sql("INSERT INTO download_queue (source, remote_id, collection_id, requested_at) VALUES (?, ?, ?, ?)"),
+        )
+        .bind(source)
+        .bind(remote_id)
+        .bind(collection_id)
+        .bind(Utc::now())
+        .execute(&self.db)
+        .await?
+        .last_insert_rowid();
+
+        self.emit(VaultEvent::DownloadQueued { queue_id: id });
+        Ok(id)
+    }
+
+    /// List the download queue, oldest entry first
+    pub async fn list_download_queue(&self) -> Result<Vec<QueuedDownload>> {
+        let rows = sqlx::query(&self.sql(&format!("SELECT {} FROM download_queue ORDER BY id", QUEUE_COLUMNS)))
+            .fetch_all(&self.reader)
+            .await?;
+
+        rows.iter().map(QueuedDownload::from_row).collect()
+    }
+
+    /// Cancel a pending or failed download
+    pub async fn cancel_queued(&self, id: i64) -> Result<()> {
+        let cancelled = sqlx::query(&self.sql("UPDATE download_queue SET state = ? WHERE id = ? AND state IN (?, ?)"))
+            .bind(DownloadState::Cancelled.as_str())
+            .bind(id)
+            .bind(DownloadState::Pending.as_str())
+            .bind(DownloadState::Failed.as_str())
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+
+        if cancelled == 0 {
+            let state: Option<String> = sqlx::query_scalar(&self.sql("SELECT state FROM download_queue WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.reader)
+                .await?;
+            return Err(match state {
+                Some(state) => VaultError::InvalidOperation(format!("Download {} is {}", id, state)),
+                None => VaultError::NotFound(format!("Queued download not found: {}", id)),
+            });
+        }
+
+        self.emit(VaultEvent::DownloadCancelled { queue_id: id });
+        Ok(())
+    }
+
+    /// Put failed downloads back in the queue
+    ///
+    /// # Returns
+    ///
+    /// Number of downloads queued again
+    pub async fn retry_failed(&self) -> Result<u64> {
+        self.set_download_state(DownloadState::Failed, DownloadState::Pending).await
+    }
+
+    /// Put downloads interrupted by a shutdown back in the queue
+    pub(crate) async fn requeue_interrupted_downloads(&self) -> Result<u64> {
+        self.set_download_state(DownloadState::Running, DownloadState::Pending).await
+    }
+
+    async fn set_download_state(&self, from: DownloadState, to: DownloadState) -> Result<u64> {
+        Ok(sqlx::query(&self.sql("UPDATE download_queue SET state = ? WHERE state = ?"))
+            .bind(to.as_str())
+            .bind(from.as_str())
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    /// Mark the oldest pending download of one of `sources` as running
+    async fn claim_download(&self, sources: &[String]) -> Result<Option<QueuedDownload>> {
+        if sources.is_empty() {
+            return Ok(None);
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new(self.sql("UPDATE download_queue SET state = "));
+        query.push_bind(DownloadState::Running.as_str());
+        query.push(self.sql(", attempts = attempts + 1 WHERE id = (SELECT id FROM download_queue WHERE state = "));
+        query.push_bind(DownloadState::Pending.as_str());
+        query.push(" AND source IN (");
+        let mut names = query.separated(", ");
+        for source in sources {
+            names.push_bind(source);
+        }
+        query.push(") ORDER BY id LIMIT 1) RETURNING ");
+        query.push(QUEUE_COLUMNS);
+
+        let row = query.build().fetch_optional(&self.db).await?;
+        row.as_ref().map(QueuedDownload::from_row).transpose()
+    }
+
+    /// Download a claimed entry and record the outcome
+    ///
+    /// A download failing for a reason that may pass, such as a rate limit
+    /// or a server error, goes back to the queue, up to
+    /// [`MAX_TRANSIENT_ATTEMPTS`] attempts.
+    ///
+    /// # Returns
+    ///
+    /// How long to wait before the next download, after such a failure
+    async fn run_download(&self, source: &dyn RemoteSource, entry: &QueuedDownload) -> Result<Option<Duration>> {
+        let _foreground = self.foreground();
+        self.emit(VaultEvent::DownloadStarted { queue_id: entry.id });
+
+        let downloaded = async {
+            let sound_id = self.download_remote(source, &entry.remote_id).await?;
+            if let Some(collection_id) = &entry.collection_id {
+                self.add_sound_to_collection(&sound_id, collection_id).await?;
+            }
+            Ok::<_, VaultError>(sound_id)
+        }
+        .await;
+
+        match downloaded {
+            Ok(sound_id) => {
+                sqlx::query(&self.sql("UPDATE download_queue SET state = ?, sound_id = ?, last_error = NULL WHERE id = ?"))
+                    .bind(DownloadState::Done.as_str())
+                    .bind(&sound_id)
+                    .bind(entry.id)
+                    .execute(&self.db)
+                    .await?;
+                self.emit(VaultEvent::DownloadFinished { queue_id: entry.id, sound_id });
+            }
+            Err(e) if e.is_transient() && entry.attempts < MAX_TRANSIENT_ATTEMPTS => {
+                sqlx::query(&self.sql("UPDATE download_queue SET state = ?, last_error = ? WHERE id = ?"))
+                    .bind(DownloadState::Pending.as_str())
+                    .bind(e.to_string())
+                    .bind(entry.id)
+                    .execute(&self.db)
+                    .await?;
+                return Ok(Some(e.retry_after().unwrap_or(RETRY_DELAY * entry.attempts)));
+            }
+            Err(e) => {
+                let error = e.to_string();
+                sqlx::query(&self.sql("UPDATE download_queue SET state = ?, last_error = ? WHERE id = ?"))
+                    .bind(DownloadState::Failed.as_str())
+                    .bind(&error)
+                    .bind(entry.id)
+                    .execute(&self.db)
+                    .await?;
+                self.emit(VaultEvent::DownloadFailed { queue_id: entry.id, error });
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Spaces out the start of downloads
+struct RateLimiter {
+    /// Time between two starts
+    interval: Duration,
+    /// Earliest time of the next start
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn per_minute(count: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / count.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the next start slot
+    async fn acquire(&self) {
+        let start = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+}
+
+/// Worker running queued downloads one at a time
+pub(crate) struct DownloadWorker {
+    /// Spaces out downloads
+    limiter: Arc<RateLimiter>,
+    /// Wakes the worker when entries may be pending
+    wake: Arc<Notify>,
+    /// The worker task, once started
+    task: Mutex<Option<JoinHandle<()>>>,
+    /// Set once stopped, so that a late wake doesn't start it again
+    stopped: AtomicBool,
+}
+
+impl DownloadWorker {
+    pub(crate) fn new(downloads_per_minute: Option<u32>) -> Self {
+        Self {
+            limiter: Arc::new(RateLimiter::per_minute(
+                downloads_per_minute.unwrap_or(DEFAULT_DOWNLOADS_PER_MINUTE),
+            )),
+            wake: Arc::new(Notify::new()),
+            task: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Start the worker if needed and have it look for pending entries
+    ///
+    /// Entries of sources that aren't registered stay pending. A stopped
+    /// worker stays stopped.
+    pub(crate) fn wake(&self, local: &Arc<LocalLibrary>, sources: &SharedSources) {
+        let mut task = self.task.lock().unwrap_or_else(|e| e.into_inner());
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        if task.as_ref().is_none_or(|task| task.is_finished()) {
+            let (local, sources) = (local.clone(), sources.clone());
+            let (limiter, wake) = (self.limiter.clone(), self.wake.clone());
+            *task = Some(tokio::spawn(async move {
+                // Database errors stop the worker until it's woken again
+                let _ = run(&local, &sources, &limiter, &wake).await;
+            }));
+        }
+        self.wake.notify_one();
+    }
+
+    /// Have a started worker look for pending entries
+    pub(crate) fn notify(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Stop the worker; an entry being downloaded is resumed on next open
+    pub(crate) fn stop(&self) {
+        let mut task = self.task.lock().unwrap_or_else(|e| e.into_inner());
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(task) = task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Download pending entries, sleeping while there are none
+async fn run(local: &LocalLibrary, sources: &SharedSources, limiter: &RateLimiter, wake: &Notify) -> Result<()> {
+    // Downloads cut short by the last shutdown are requeued first
+    local.warm_up().await?;
+    loop {
+        let available: Vec<Arc<dyn RemoteSource>> = sources.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let names: Vec<String> = available.iter().map(|source| source.name().to_string()).collect();
+
+        limiter.acquire().await;
+        match local.claim_download(&names).await? {
+            Some(entry) => {
+                let source = available
+                    .iter()
+                    .find(|source| source.name() == entry.source)
+                    .ok_or_else(|| VaultError::Config(format!("No remote source named {:?}", entry.source)))?;
+                if let Some(delay) = local.run_download(source.as_ref(), &entry).await? {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            None => wake.notified().await,
+        }
+    }
+}
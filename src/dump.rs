@@ -0,0 +1,360 @@
+//! Newline-delimited JSON dumps of the vault's metadata
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::context::OpContext;
+use crate::error::{Result, VaultError};
+use crate::fields::{FieldSpec, SchemaMode};
+use crate::groups::SoundGroup;
+use crate::import::SoundMetadataTemplate;
+use crate::local::{LocalLibrary, SCHEMA_VERSION};
+use crate::models::{Collection, SoundMetadata};
+use crate::provenance::ProvenanceEntry;
+use crate::schema::{DUMP_SCHEMA, check_version, generator};
+use crate::uses::ExternalUse;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Version of the dump format written by this library
+pub const DUMP_FORMAT_VERSION: u32 = 1;
+
+/// Sounds read from the database at a time while dumping
+const DUMP_BATCH: i64 = 256;
+
+/// A line of a metadata dump
+///
+/// Every dump starts with a [`DumpRecord::Header`]. Records are written with
+/// their keys sorted, so dumping the same metadata twice gives the same bytes
+/// but for the header's timestamp.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum DumpRecord {
+    /// Versions of the dump
+    Header {
+        /// Version of the dump format
+        format_version: u32,
+        /// Version of the database schema the dump was taken from
+        schema_version: i64,
+        /// When the dump was taken
+        created_at: DateTime<Utc>,
+        /// Library that took the dump, e.g. `soundvault 0.1.0`
+        #[serde(default)]
+        generator: Option<String>,
+    },
+    /// Declaration of a custom metadata field
+    CustomField {
+        /// Custom metadata key
+        key: String,
+        /// Declaration
+        spec: FieldSpec,
+    },
+    /// How custom metadata is checked
+    SchemaMode {
+        /// The mode
+        mode: SchemaMode,
+    },
+    /// A named import template
+    ImportTemplate {
+        /// Name of the template
+        name: String,
+        /// The template
+        template: SoundMetadataTemplate,
+    },
+    /// A sound's metadata
+    Sound(Box<SoundMetadata>),
+    /// A link of a sound's provenance chain, following the sound
+    Provenance {
+        /// ID of the sound
+        sound_id: String,
+        /// Where one of its files came from
+        entry: ProvenanceEntry,
+    },
+    /// A project or patch using a sound, following the sound
+    ExternalUse {
+        /// ID of the sound
+        sound_id: String,
+        /// The use
+        #[serde(rename = "use")]
+        context: ExternalUse,
+    },
+    /// A collection and its members
+    Collection(Collection),
+    /// A group and its members, in order
+    Group(SoundGroup),
+}
+
+/// How [`SoundVault::load_dump`](crate::SoundVault::load_dump) treats what's
+/// already in the vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadMode {
+    /// Load into a vault without sounds or collections
+    Restore,
+    /// Replace sounds, collections and groups with the same ID as a dumped
+    /// one and keep the others; collections keep their members missing from
+    /// the dump, groups get exactly the dumped members
+    Merge,
+}
+
+/// Number of records of each kind dumped or loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpStats {
+    /// Sounds
+    pub sounds: u64,
+    /// Collections
+    pub collections: u64,
+    /// Custom field declarations
+    pub custom_fields: u64,
+    /// Import templates
+    pub import_templates: u64,
+    /// Links of provenance chains
+    pub provenance_entries: u64,
+    /// Groups
+    #[serde(default)]
+    pub groups: u64,
+    /// Uses of sounds by projects and patches
+    #[serde(default)]
+    pub external_uses: u64,
+}
+
+impl LocalLibrary {
+    /// Write the vault's metadata as newline-delimited JSON records
+    ///
+    /// Sounds are read in batches, so memory use doesn't grow with the size
+    /// of the library. Files aren't included. If `context` stops the dump,
+    /// what was written is incomplete and should be discarded.
+    pub async fn dump_metadata<W: AsyncWrite + Unpin>(&self, mut writer: W, context: &OpContext) -> Result<DumpStats> {
+        let deadline = context.start("dump_metadata");
+        let mut stats = DumpStats::default();
+        let header = DumpRecord::Header {
+            format_version: DUMP_FORMAT_VERSION,
+            schema_version: SCHEMA_VERSION,
+            created_at: Utc::now(),
+            generator: Some(generator()),
+        };
+        write_record(&mut writer, &header).await?;
+
+        let mut conn = self.reader.acquire().await?;
+        for (key, spec) in self.field_specs(&mut conn).await? {
+            write_record(&mut writer, &DumpRecord::CustomField { key, spec }).await?;
+            stats.custom_fields += 1;
+        }
+        let mode = self.schema_mode(&mut conn).await?;
+        write_record(&mut writer, &DumpRecord::SchemaMode { mode }).await?;
+        drop(conn);
+
+        for (name, template) in self.list_import_templates().await? {
+            write_record(&mut writer, &DumpRecord::ImportTemplate { name, template }).await?;
+            stats.import_templates += 1;
+        }
+
+        let mut after = String::new();
+        loop {
+            deadline.check(|| format!("{} sounds dumped", stats.sounds))?;
+            let ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE id > ? ORDER BY id LIMIT ?"))
+                .bind(&after)
+                .bind(DUMP_BATCH)
+                .fetch_all(&self.reader)
+                .await?;
+            let Some(last) = ids.last() else {
+                break;
+            };
+            after = last.clone();
+
+            for id in ids {
+                let metadata = self.get_sound(&id).await?.metadata;
+                write_record(&mut writer, &DumpRecord::Sound(Box::new(metadata))).await?;
+                stats.sounds += 1;
+                for entry in self.provenance(&id).await? {
+                    let sound_id = id.clone();
+                    write_record(&mut writer, &DumpRecord::Provenance { sound_id, entry }).await?;
+                    stats.provenance_entries += 1;
+                }
+                for context in self.uses_of(&id).await? {
+                    let sound_id = id.clone();
+                    write_record(&mut writer, &DumpRecord::ExternalUse { sound_id, context }).await?;
+                    stats.external_uses += 1;
+                }
+            }
+        }
+
+        let ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM collections ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?;
+        for id in ids {
+            write_record(&mut writer, &DumpRecord::Collection(self.get_collection(&id).await?)).await?;
+            stats.collections += 1;
+        }
+
+        for group in self.list_groups().await? {
+            write_record(&mut writer, &DumpRecord::Group(group)).await?;
+            stats.groups += 1;
+        }
+
+        writer.flush().await?;
+        Ok(stats)
+    }
+
+    /// Load a dump written by [`dump_metadata`](Self::dump_metadata)
+    ///
+    /// The dump is loaded in one transaction: if a record is invalid, nothing
+    /// is loaded, and likewise if `context` stops the load. Sounds are
+    /// written as dumped, without checking custom metadata against the
+    /// declared fields.
+    pub async fn load_dump<R: AsyncRead + Unpin>(&self, reader: R, mode: LoadMode, context: &OpContext) -> Result<DumpStats> {
+        let deadline = context.start("load_dump");
+        let mut tx = self.db.begin().await?;
+        if mode == LoadMode::Restore {
+            let used: bool = sqlx::query_scalar(
+                &self.sql("SELECT EXISTS (SELECT 1 FROM sounds) OR EXISTS (SELECT 1 FROM collections)"),
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if used {
+                return Err(VaultError::InvalidOperation(
+                    "Can't restore a dump into a vault with sounds or collections".to_string(),
+                ));
+            }
+        }
+
+        let mut stats = DumpStats::default();
+        let mut lines = BufReader::new(reader).lines();
+        let mut number = 0;
+        while let Some(line) = lines.next_line().await? {
+            deadline.check(|| format!("{} lines read, nothing loaded", number))?;
+            number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: DumpRecord = serde_json::from_str(&line)
+                .map_err(|e| VaultError::InvalidOperation(format!("Invalid dump record on line {}: {}", number, e)))?;
+
+            match record {
+                DumpRecord::Header { format_version, generator, .. } if number == 1 => {
+                    check_version(DUMP_SCHEMA, format_version, generator.as_deref())?;
+                }
+                _ if number == 1 => {
+                    return Err(VaultError::InvalidOperation("Dump doesn't start with a header".to_string()));
+                }
+                DumpRecord::Header { .. } => {
+                    return Err(VaultError::InvalidOperation(format!("Unexpected header on line {}", number)));
+                }
+                DumpRecord::CustomField { key, spec } => {
+                    self.write_field_spec(&mut tx, &key, &spec).await?;
+                    stats.custom_fields += 1;
+                }
+                DumpRecord::SchemaMode { mode } => {
+                    self.write_schema_mode(&mut tx, mode).await?;
+                }
+                DumpRecord::ImportTemplate { name, template } => {
+                    self.write_import_template(&mut tx, &name, &template).await?;
+                    stats.import_templates += 1;
+                }
+                DumpRecord::Sound(metadata) => {
+                    self.load_sound(&mut tx, *metadata).await?;
+                    stats.sounds += 1;
+                }
+                DumpRecord::Provenance { sound_id, entry } => {
+                    self.write_provenance(&mut tx, &sound_id, &entry).await?;
+                    stats.provenance_entries += 1;
+                }
+                DumpRecord::ExternalUse { sound_id, context } => {
+                    self.write_external_use(&mut tx, &sound_id, &context).await?;
+                    stats.external_uses += 1;
+                }
+                DumpRecord::Collection(collection) => {
+                    self.load_collection(&mut tx, &collection).await?;
+                    stats.collections += 1;
+                }
+                DumpRecord::Group(group) => {
+                    self.load_group(&mut tx, &group).await?;
+                    stats.groups += 1;
+                }
+            }
+        }
+        if number == 0 {
+            return Err(VaultError::InvalidOperation("Dump is empty".to_string()));
+        }
+        tx.commit().await?;
+
+        Ok(stats)
+    }
+
+    async fn load_sound(&self, conn: &mut SqliteConnection, mut metadata: SoundMetadata) -> Result<()> {
+        metadata.normalize_tags();
+        let before = match self.fetch_sound(conn, &metadata.id).await {
+            Ok(sound) => Some(serde_json::to_value(&sound.metadata)?),
+            Err(VaultError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+
+        self.save_metadata(conn, &metadata).await?;
+        let operation = if before.is_some() { AuditOperation::UpdateSound } else { AuditOperation::CreateSound };
+        let changes = audit_diff(before.as_ref(), Some(&serde_json::to_value(&metadata)?));
+        self.audit(conn, operation, &metadata.id, changes).await
+    }
+
+    async fn load_collection(&self, conn: &mut SqliteConnection, collection: &Collection) -> Result<()> {
+        let id = collection.id.to_string();
+        let existed: bool = sqlx::query_scalar(&self.sql("SELECT EXISTS (SELECT 1 FROM collections WHERE id = ?)"))
+            .bind(&id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO collections (id, name, description, defaults, sort_key, expires_at) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                description = excluded.description,
+                defaults = excluded.defaults,
+                sort_key = excluded.sort_key,
+                expires_at = excluded.expires_at
+            "#),
+        )
+        .bind(&id)
+        .bind(&collection.name)
+        .bind(&collection.description)
+        .bind(serde_json::to_string(&collection.defaults)?)
+        .bind(self.collator.sort_key(&collection.name))
+        .bind(collection.expires_at)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(&self.sql("DELETE FROM metadata WHERE object_id = ? AND object_type = 'collection'"))
+            .bind(&id)
+            .execute(&mut *conn)
+            .await?;
+        for (key, value) in &collection.custom {
+            sqlx::query(&self.sql("INSERT INTO metadata (object_id, object_type, key, value) VALUES (?, 'collection', ?, ?)"))
+                .bind(&id)
+                .bind(key)
+                .bind(value)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        for sound_id in &collection.sound_ids {
+            let added = self.insert_member(conn, &id, sound_id).await?;
+            if existed && added {
+                let changes = serde_json::json!({ "sound_id": [null, sound_id] });
+                self.audit(conn, AuditOperation::AddToCollection, &id, changes).await?;
+            }
+        }
+
+        let changes = audit_diff(None, Some(&serde_json::to_value(collection)?));
+        let operation = if existed { AuditOperation::UpdateCollection } else { AuditOperation::CreateCollection };
+        self.audit(conn, operation, &id, changes).await
+    }
+}
+
+async fn write_record<W: AsyncWrite + Unpin>(writer: &mut W, record: &DumpRecord) -> Result<()> {
+    // Going through a value sorts the keys of maps
+    let mut line = serde_json::to_string(&serde_json::to_value(record)?)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    Ok(())
+}
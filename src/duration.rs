@@ -0,0 +1,147 @@
+//! Human-friendly durations
+
+use crate::error::{Result, VaultError};
+use crate::models::SoundMetadata;
+use serde::{Deserialize, Deserializer};
+
+/// Format a duration in seconds as `M:SS.t`, or `H:MM:SS` from an hour on
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::format_duration;
+///
+/// assert_eq!(format_duration(83.44), "1:23.4");
+/// assert_eq!(format_duration(59.96), "1:00.0");
+/// assert_eq!(format_duration(0.0), "0:00.0");
+/// assert_eq!(format_duration(3725.0), "1:02:05");
+/// ```
+pub fn format_duration(seconds: f32) -> String {
+    let tenths = (seconds.max(0.0) as f64 * 10.0).round() as u64;
+    if tenths < 36_000 {
+        return format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10);
+    }
+
+    let seconds = (seconds as f64).round() as u64;
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Parse a duration typed by a user into seconds
+///
+/// Accepts plain seconds (`90`, `2.5`), clock notation (`1:30`, `0:01.250`,
+/// `1:02:03`) and units (`1m30s`, `2h`, `1h 5m`, `250ms`). In clock notation
+/// two fields are minutes and seconds, three are hours, minutes and seconds.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::parse_duration;
+///
+/// assert_eq!(parse_duration("90").unwrap(), 90.0);
+/// assert_eq!(parse_duration("1:30").unwrap(), 90.0);
+/// assert_eq!(parse_duration("1m30s").unwrap(), 90.0);
+/// assert_eq!(parse_duration("0:01.250").unwrap(), 1.25);
+/// assert_eq!(parse_duration("1:02:03").unwrap(), 3723.0);
+/// assert_eq!(parse_duration(" 1h 5m ").unwrap(), 3900.0);
+/// assert_eq!(parse_duration("250ms").unwrap(), 0.25);
+/// assert_eq!(parse_duration(".5").unwrap(), 0.5);
+///
+/// // Negative, out-of-range and malformed input is rejected
+/// for bad in ["", "-5", "-0:30", "1:60", "1:60:00", "1:2:3:4", "1::30", "1:", ":30",
+///             "1m30", "30s1m", "1m1m", "1.5.2", "abc", "5 s", "1e3", "inf", "NaN"] {
+///     assert!(parse_duration(bad).is_err(), "{:?} should be rejected", bad);
+/// }
+/// ```
+pub fn parse_duration(text: &str) -> Result<f32> {
+    let text = text.trim();
+    let invalid = || VaultError::InvalidOperation(format!("Invalid duration: {:?}", text));
+
+    if text.is_empty() {
+        return Err(invalid());
+    }
+    if text.contains(':') {
+        let fields: Vec<&str> = text.split(':').collect();
+        if fields.len() > 3 {
+            return Err(invalid());
+        }
+
+        let (last, leading) = fields.split_last().ok_or_else(invalid)?;
+        let seconds = parse_number(last).filter(|s| *s < 60.0).ok_or_else(invalid)?;
+        let mut total = seconds;
+        for (position, field) in leading.iter().rev().enumerate() {
+            if field.is_empty() || !field.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let value: f64 = field.parse().map_err(|_| invalid())?;
+            // Minutes are bounded when hours precede them
+            if position == 0 && leading.len() == 2 && value >= 60.0 {
+                return Err(invalid());
+            }
+            total += value * 60f64.powi(position as i32 + 1);
+        }
+        return Ok(total as f32);
+    }
+
+    if let Some(seconds) = parse_number(text) {
+        return Ok(seconds as f32);
+    }
+
+    // Units, largest first, each at most once
+    let units = [("h", 3600.0), ("m", 60.0), ("s", 1.0), ("ms", 0.001)];
+    let mut rest = text;
+    let mut total = 0.0;
+    let mut next_unit = 0;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(invalid)?;
+        let value = parse_number(&rest[..number_len]).ok_or_else(invalid)?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        let index = units[next_unit..]
+            .iter()
+            .position(|(name, _)| *name == unit)
+            .ok_or_else(invalid)?;
+        total += value * units[next_unit + index].1;
+        next_unit += index + 1;
+
+        rest = rest[unit_len..].trim_start();
+    }
+
+    Ok(total as f32)
+}
+
+/// Parse a non-negative decimal number without sign or exponent
+fn parse_number(text: &str) -> Option<f64> {
+    let valid = !text.is_empty()
+        && text != "."
+        && text.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+        && text.bytes().filter(|&b| b == b'.').count() <= 1;
+    if valid { text.parse().ok() } else { None }
+}
+
+/// Deserialize an optional duration given as seconds or as a string
+///
+/// Strings use the syntax of [`parse_duration`].
+pub(crate) fn deserialize_optional<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<f32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Duration {
+        Seconds(f32),
+        Text(String),
+    }
+
+    match Option::<Duration>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Duration::Seconds(seconds)) if seconds >= 0.0 => Ok(Some(seconds)),
+        Some(Duration::Seconds(seconds)) => Err(serde::de::Error::custom(format!("negative duration: {}", seconds))),
+        Some(Duration::Text(text)) => parse_duration(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+impl SoundMetadata {
+    /// Duration formatted for display, as `M:SS.t` or `H:MM:SS`
+    pub fn duration_display(&self) -> String {
+        format_duration(self.duration)
+    }
+}
@@ -0,0 +1,308 @@
+//! Metadata embedded in WAV files by recorders: the Broadcast Wave `bext`
+//! chunk and iXML
+//!
+//! Embedded values are stored as custom metadata under `bwf.` and `ixml.`
+//! keys, so they can be filtered on like any other custom field.
+
+use crate::audio::{read_chunk, read_chunk_header};
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::journal::OpKind;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::paths::write_atomic;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Custom metadata key of the `bext` description
+pub const BWF_DESCRIPTION: &str = "bwf.description";
+
+/// Bytes of the description field of a `bext` chunk
+const DESCRIPTION_LEN: usize = 256;
+
+/// Bytes of a `bext` chunk without coding history
+const BEXT_LEN: usize = 602;
+
+/// Fields of a `bext` chunk read up to the time reference: key, offset, length
+const BEXT_FIELDS: [(&str, usize, usize); 5] = [
+    (BWF_DESCRIPTION, 0, DESCRIPTION_LEN),
+    ("bwf.originator", 256, 32),
+    ("bwf.originator_reference", 288, 32),
+    ("bwf.origination_date", 320, 10),
+    ("bwf.origination_time", 330, 8),
+];
+
+/// iXML elements read, with their custom metadata key
+const IXML_FIELDS: [(&str, &str); 4] = [
+    ("PROJECT", "ixml.project"),
+    ("SCENE", "ixml.scene"),
+    ("TAKE", "ixml.take"),
+    ("TAPE", "ixml.tape"),
+];
+
+/// Metadata read from a WAV file's `bext` and iXML chunks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmbeddedTags {
+    /// Values by custom metadata key, e.g. `bwf.originator` or `ixml.scene`
+    pub values: BTreeMap<String, String>,
+
+    /// Chunks that were skipped because they're malformed, with the reason
+    pub warnings: Vec<String>,
+}
+
+/// Read the `bext` and iXML metadata of a WAV file
+///
+/// Files other than WAV have none. Malformed chunks are skipped and
+/// reported in [`EmbeddedTags::warnings`].
+///
+/// | Key | Source |
+/// |-----|--------|
+/// | `bwf.description` | `bext` description |
+/// | `bwf.originator` | `bext` originator |
+/// | `bwf.originator_reference` | `bext` originator reference |
+/// | `bwf.origination_date` | `bext` origination date, `yyyy-mm-dd` |
+/// | `bwf.origination_time` | `bext` origination time, `hh:mm:ss` |
+/// | `bwf.time_reference` | `bext` time reference, in samples since midnight |
+/// | `ixml.project`, `ixml.scene`, `ixml.take`, `ixml.tape` | iXML elements of the same name |
+///
+/// # Errors
+///
+/// * `VaultError::Io` if the file can't be read
+pub fn read_embedded_tags(path: &Path) -> Result<EmbeddedTags> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut tags = EmbeddedTags::default();
+
+    let mut header = [0u8; 12];
+    if reader.read_exact(&mut header).is_err() || &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(tags);
+    }
+    let file_len = reader.get_ref().metadata()?.len();
+
+    while let Some((id, len)) = read_chunk_header(&mut reader, false)? {
+        let start = reader.stream_position()?;
+        if start + len > file_len {
+            tags.warnings.push(format!("{} chunk runs past the end of the file", chunk_name(&id)));
+            break;
+        }
+        match &id {
+            b"bext" | b"iXML" => match read_chunk(&mut reader, len) {
+                Ok(chunk) if &id == b"bext" => read_bext(&chunk, &mut tags),
+                Ok(chunk) => read_ixml(&chunk, &mut tags),
+                Err(e) => tags.warnings.push(format!("{} chunk skipped: {}", chunk_name(&id), e)),
+            },
+            _ => {}
+        }
+        reader.seek(SeekFrom::Start(start + len + len % 2))?;
+    }
+
+    Ok(tags)
+}
+
+/// Printable name of a chunk ID
+fn chunk_name(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).trim_end().to_string()
+}
+
+fn read_bext(chunk: &[u8], tags: &mut EmbeddedTags) {
+    if chunk.len() < 346 {
+        tags.warnings.push(format!("bext chunk skipped: {} bytes is too short", chunk.len()));
+        return;
+    }
+
+    for (key, offset, len) in BEXT_FIELDS {
+        let value = text_field(&chunk[offset..offset + len]);
+        if !value.is_empty() {
+            // Recorders write the time with '-', ':' or '.' separators
+            let value = if key == "bwf.origination_time" { value.replace(['-', '.'], ":") } else { value };
+            tags.values.insert(key.to_string(), value);
+        }
+    }
+    let low = u32::from_le_bytes([chunk[338], chunk[339], chunk[340], chunk[341]]) as u64;
+    let high = u32::from_le_bytes([chunk[342], chunk[343], chunk[344], chunk[345]]) as u64;
+    tags.values.insert("bwf.time_reference".to_string(), ((high << 32) | low).to_string());
+}
+
+/// Text of a fixed-size, NUL-padded field
+fn text_field(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+fn read_ixml(chunk: &[u8], tags: &mut EmbeddedTags) {
+    let Ok(xml) = std::str::from_utf8(chunk) else {
+        tags.warnings.push("iXML chunk skipped: not UTF-8".to_string());
+        return;
+    };
+    let xml = xml.trim_end_matches('\0');
+    if !xml.contains("<BWFXML") || !xml.contains("</BWFXML>") {
+        tags.warnings.push("iXML chunk skipped: no BWFXML element".to_string());
+        return;
+    }
+
+    for (element, key) in IXML_FIELDS {
+        let open = format!("<{}>", element);
+        let close = format!("</{}>", element);
+        let Some(start) = xml.find(&open).map(|i| i + open.len()) else {
+            continue;
+        };
+        let Some(len) = xml[start..].find(&close) else {
+            tags.warnings.push(format!("iXML element {} skipped: not closed", element));
+            continue;
+        };
+        let value = unescape(xml[start..start + len].trim());
+        if !value.is_empty() {
+            tags.values.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Replace the predefined XML entities
+pub(crate) fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// A WAV file with the description of its `bext` chunk replaced, adding the
+/// chunk if there's none
+///
+/// Every other chunk, the audio data included, is copied byte for byte.
+pub(crate) fn with_bext_description(bytes: &[u8], description: &str) -> Result<Vec<u8>> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(VaultError::InvalidOperation("Not a RIFF WAVE file".to_string()));
+    }
+    if description.len() > DESCRIPTION_LEN {
+        return Err(VaultError::InvalidOperation(format!(
+            "BWF description is {} bytes, at most {} fit",
+            description.len(),
+            DESCRIPTION_LEN
+        )));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len() + 8 + BEXT_LEN);
+    out.extend_from_slice(&bytes[0..12]);
+    let mut has_bext = false;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes([bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]]) as usize;
+        if pos + 8 + len > bytes.len() {
+            return Err(VaultError::InvalidOperation(format!(
+                "{} chunk runs past the end of the file",
+                String::from_utf8_lossy(id)
+            )));
+        }
+        let end = (pos + 8 + len + len % 2).min(bytes.len());
+
+        if id == b"bext" && len >= DESCRIPTION_LEN {
+            has_bext = true;
+            out.extend_from_slice(&bytes[pos..pos + 8]);
+            out.extend_from_slice(&description_field(description));
+            out.extend_from_slice(&bytes[pos + 8 + DESCRIPTION_LEN..end]);
+        } else {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+
+    if !has_bext {
+        // Broadcast Wave puts the bext chunk ahead of the format chunk
+        let mut chunk = Vec::with_capacity(8 + BEXT_LEN);
+        chunk.extend_from_slice(b"bext");
+        chunk.extend_from_slice(&(BEXT_LEN as u32).to_le_bytes());
+        chunk.extend_from_slice(&description_field(description));
+        chunk.resize(8 + BEXT_LEN, 0);
+        out.splice(12..12, chunk);
+    }
+
+    let riff_len = u32::try_from(out.len() - 8)
+        .map_err(|_| VaultError::InvalidOperation("WAV file would exceed 4 GiB".to_string()))?;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Ok(out)
+}
+
+fn description_field(description: &str) -> [u8; DESCRIPTION_LEN] {
+    let mut field = [0u8; DESCRIPTION_LEN];
+    field[..description.len()].copy_from_slice(description.as_bytes());
+    field
+}
+
+impl LocalLibrary {
+    /// Add the embedded metadata of a sound's file to its custom metadata,
+    /// keeping values already set
+    ///
+    /// # Returns
+    ///
+    /// Why chunks were skipped, to report once the sound is recorded
+    pub(crate) fn merge_embedded_tags(&self, metadata: &mut SoundMetadata) -> Vec<String> {
+        let Some(path) = &metadata.path else {
+            return Vec::new();
+        };
+        match read_embedded_tags(path) {
+            Ok(tags) => {
+                for (key, value) in tags.values {
+                    metadata.custom.entry(key).or_insert(value);
+                }
+                tags.warnings
+            }
+            Err(e) => vec![format!("Embedded metadata not read: {}", e)],
+        }
+    }
+
+    /// Tell subscribers about the chunks skipped while importing a sound
+    pub(crate) fn report_import_warnings(&self, id: &str, warnings: Vec<String>) {
+        for warning in warnings {
+            self.emit(VaultEvent::ImportWarning {
+                sound_id: id.to_string(),
+                warning,
+            });
+        }
+    }
+
+    /// Write a sound's description into the `bext` chunk of its WAV file
+    ///
+    /// The `bwf.description` custom value is written, or the sound's
+    /// description when it has none. Only the chunk changes; the file's new
+    /// hash is recorded.
+    pub async fn write_embedded_tags(&self, id: &str) -> Result<()> {
+        let before = self.get_sound(id).await?.metadata;
+        if before.external || before.archive.is_some() {
+            return Err(VaultError::InvalidOperation(format!(
+                "Only WAV files stored uncompressed in the library can be written to, not the file of {}",
+                id
+            )));
+        }
+        self.check_in_library(&before)?;
+        let path = before
+            .path
+            .as_ref()
+            .ok_or_else(|| VaultError::InvalidOperation(format!("Sound {} has no file", id)))?;
+        let path = self.library_file(path)?;
+
+        let description = before.get_custom(BWF_DESCRIPTION).unwrap_or(&before.description);
+        let bytes = with_bext_description(&std::fs::read(&path)?, description)?;
+
+        // The file is overwritten in place, which can't be rolled back; a
+        // content-addressed file may be shared, so its new content is written
+        // apart and stored in turn
+        let (target, op) = match self.object_hash(&path) {
+            Some(hash) => {
+                let target = self.library_path.join(id).join(format!("{}.wav", hash));
+                std::fs::create_dir_all(target.parent().unwrap_or(&self.library_path))?;
+                let op = self.begin_op(OpKind::ReplaceFile, &[&target], &[]).await?;
+                (target, op)
+            }
+            None => (path.clone(), self.begin_op(OpKind::ReplaceFile, &[], &[]).await?),
+        };
+        let result = async {
+            write_atomic(&target, &bytes)?;
+            self.record_file(&before, target.clone(), Some(&op), None).await
+        }
+        .await;
+        self.settle_op(&op, result).await
+    }
+}
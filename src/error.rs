@@ -1,7 +1,75 @@
 //! Error types for the SoundVault library
 
+use crate::acquire::AcquireStage;
+use crate::license::License;
+use crate::permissions::Role;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use thiserror::Error;
 
+/// What went wrong in a [`VaultError::Remote`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteErrorKind {
+    /// The API key is missing, wrong or not allowed to make the request
+    Auth,
+    /// The sound doesn't exist, or was removed
+    NotFound,
+    /// Too many requests were made; try again later
+    RateLimited,
+    /// The source failed or is down for maintenance
+    Server,
+    /// The source couldn't be reached, or the connection broke or timed out
+    Network,
+    /// The response wasn't what was expected
+    Decode,
+    /// The source refused the request for another reason
+    Other,
+}
+
+impl RemoteErrorKind {
+    /// Kind of the failure an HTTP status reports
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::RemoteErrorKind;
+    ///
+    /// assert_eq!(RemoteErrorKind::from_status(401), RemoteErrorKind::Auth);
+    /// assert_eq!(RemoteErrorKind::from_status(410), RemoteErrorKind::NotFound);
+    /// assert!(RemoteErrorKind::from_status(503).is_transient());
+    /// assert!(!RemoteErrorKind::from_status(400).is_transient());
+    /// ```
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => Self::Auth,
+            404 | 410 => Self::NotFound,
+            429 => Self::RateLimited,
+            500..=599 => Self::Server,
+            _ => Self::Other,
+        }
+    }
+
+    /// Name of the kind, in messages
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::NotFound => "not found",
+            Self::RateLimited => "rate limit",
+            Self::Server => "server",
+            Self::Network => "network",
+            Self::Decode => "decode",
+            Self::Other => "request",
+        }
+    }
+
+    /// Check whether the same request may succeed later
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::RateLimited | Self::Server | Self::Network)
+    }
+}
+
 /// Custom error type for SoundVault operations
 #[derive(Error, Debug)]
 pub enum VaultError {
@@ -13,9 +81,24 @@ pub enum VaultError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
-    /// Error related to Freesound API
-    #[error("Freesound API error: {0}")]
-    FreesoundApi(#[from] freesound_rs::FreesoundError),
+    /// A remote source, such as Freesound, failed a request
+    #[error(
+        "Remote {} error from {endpoint}{}: {message}",
+        kind.as_str(),
+        status.map(|status| format!(" (HTTP {})", status)).unwrap_or_default()
+    )]
+    Remote {
+        /// What went wrong
+        kind: RemoteErrorKind,
+        /// HTTP status of the response, if one came
+        status: Option<u16>,
+        /// URL requested
+        endpoint: String,
+        /// How long the source asked to wait before trying again
+        retry_after: Option<Duration>,
+        /// What the source or the HTTP client said
+        message: String,
+    },
 
     /// Error related to JSON serialization/deserialization
     #[error("JSON error: {0}")]
@@ -29,6 +112,10 @@ pub enum VaultError {
     #[error("Configuration error: {0}")]
     Config(String),
 
+    /// The database file is damaged or is not a SoundVault database
+    #[error("Corrupt database: {0}")]
+    Corrupt(String),
+
     /// Invalid operation
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
@@ -36,6 +123,246 @@ pub enum VaultError {
     /// Sound not found
     #[error("Sound not found: {0}")]
     NotFound(String),
+
+    /// The sound is locked against edits
+    #[error("Sound is locked: {0}")]
+    Locked(String),
+
+    /// The license policy doesn't allow downloading a sound
+    #[error("License {license} of {sound:?} is not allowed")]
+    LicensePolicy {
+        /// Name of the sound
+        sound: String,
+        /// Its license
+        license: License,
+    },
+
+    /// An operation ran out of the time its [`OpContext`](crate::OpContext) allowed
+    #[error("{operation} timed out after {elapsed:?}: {progress}")]
+    Timeout {
+        /// Name of the operation
+        operation: String,
+        /// Time it ran
+        elapsed: Duration,
+        /// How far it got
+        progress: String,
+    },
+
+    /// An operation was cancelled through its [`OpContext`](crate::OpContext)
+    #[error("{operation} cancelled: {progress}")]
+    Cancelled {
+        /// Name of the operation
+        operation: String,
+        /// How far it got
+        progress: String,
+    },
+
+    /// The library changed since a maintenance plan was made; nothing was
+    /// done, and a new plan must be made
+    #[error("The {operation} plan is outdated: the library changed since it was made")]
+    PlanOutdated {
+        /// Name of the operation
+        operation: String,
+    },
+
+    /// Changes after a cursor of the change feed were pruned; the client
+    /// must sync from scratch
+    #[error("Changes after {seq} were pruned, through {pruned_through}")]
+    ChangesPruned {
+        /// Sequence number of the cursor
+        seq: i64,
+        /// Last sequence number pruned
+        pruned_through: i64,
+    },
+
+    /// No result of an [`acquire`](crate::SoundVault::acquire) search
+    /// passed its filters
+    #[error("No sound found for {query:?} passed the filters ({searched} results searched)")]
+    NoMatch {
+        /// Search text
+        query: String,
+        /// Number of results searched
+        searched: usize,
+    },
+
+    /// [`acquire`](crate::SoundVault::acquire) picked a sound but couldn't
+    /// bring it into the vault
+    #[error("Picked {sound:?} ({remote_id}), but {stage} failed: {error}")]
+    AcquireFailed {
+        /// Name of the sound picked
+        sound: String,
+        /// Its ID at the remote source
+        remote_id: String,
+        /// Step that failed
+        stage: AcquireStage,
+        /// ID of the local sound, if it was imported before the failure
+        sound_id: Option<String>,
+        /// What went wrong
+        #[source]
+        error: Box<VaultError>,
+    },
+
+    /// A served vault couldn't be reached, refused the connection or
+    /// failed a request
+    #[error("RPC error: {0}")]
+    Rpc(String),
+
+    /// A file was written by a later version of the library, with a
+    /// version of its schema this one can't read
+    #[error("{schema} version {version} needs {required} or later; this library reads up to version {supported}")]
+    UnsupportedVersion {
+        /// Name of the schema, e.g. `soundvault.manifest`
+        schema: String,
+        /// Version of the file
+        version: u32,
+        /// Latest version this library reads
+        supported: u32,
+        /// Library that wrote the file
+        required: String,
+    },
+
+    /// A file would take the library over its size limit
+    #[error("Library size limit exceeded: {needed} bytes needed, {available} available")]
+    QuotaExceeded {
+        /// Size of the file
+        needed: u64,
+        /// Bytes left under the limit
+        available: u64,
+    },
+
+    /// The current actor's role doesn't allow the operation
+    #[error("{} needs the {} role", actor.as_deref().unwrap_or("An unset actor"), required_role.as_str())]
+    PermissionDenied {
+        /// Actor denied
+        actor: Option<String>,
+        /// Least role the operation needs
+        required_role: Role,
+    },
+
+    /// The sound is checked out for editing by someone else
+    #[error("Sound {sound_id} is checked out{}", actor.as_ref().map(|actor| format!(" by {}", actor)).unwrap_or_default())]
+    CheckedOut {
+        /// ID of the sound
+        sound_id: String,
+        /// Actor holding the checkout
+        actor: Option<String>,
+    },
+
+    /// A search query string couldn't be parsed
+    #[error("Invalid query at character {position}: {message}")]
+    InvalidQuery {
+        /// Offset of the problem in the query, in characters
+        position: usize,
+        /// What's wrong
+        message: String,
+        /// The query corrected as it was probably meant, if it can be guessed
+        suggestion: Option<String>,
+    },
+
+    /// Projects or patches outside the vault use the sound
+    #[error("Sound {sound_id} is used by {}", locations.join(", "))]
+    InUse {
+        /// ID of the sound
+        sound_id: String,
+        /// Paths or URIs of the projects and patches using it
+        locations: Vec<String>,
+    },
+
+    /// The sound's license ran out, so it may no longer be exported
+    #[error("Sound {sound_id} expired on {expires_at}")]
+    Expired {
+        /// ID of the sound
+        sound_id: String,
+        /// When it expired
+        expires_at: DateTime<Utc>,
+    },
+
+    /// A sound's file isn't where the vault expects it
+    #[error("File of sound {0} is missing")]
+    FileMissing(String),
+
+    /// A sound's file is in a format or codec no decoder reads
+    #[error("Unsupported codec: {0}")]
+    UnsupportedCodec(String),
+
+    /// The server speaks another version of the RPC protocol
+    #[error("Server speaks protocol version {server}, this client version {client}")]
+    IncompatibleProtocol {
+        /// Version of the server
+        server: u32,
+        /// Version of the client
+        client: u32,
+    },
+
+    /// A collection changed since the version a reorder was based on; read
+    /// it again and retry
+    #[error("Collection {collection_id} is at version {actual}, not {expected}")]
+    VersionConflict {
+        /// ID of the collection
+        collection_id: String,
+        /// Version the reorder expected
+        expected: i64,
+        /// Version of the collection now
+        actual: i64,
+    },
+
+    /// Undoing an operation would overwrite a change made after it
+    #[error("Can't undo {operation}: {field} of {entity_id} changed since")]
+    UndoConflict {
+        /// ID of the operation
+        operation: String,
+        /// ID of the sound or collection changed since
+        entity_id: String,
+        /// Field changed since, or `membership of <sound ID>`
+        field: String,
+    },
+
+    /// The vault doesn't hold what its spec declares
+    #[error("The vault drifted from its spec: {} missing, {} changed", missing.len(), changed.len())]
+    SpecDrift {
+        /// What the spec declares and the vault lacks
+        missing: Vec<String>,
+        /// What the vault holds differently from the spec
+        changed: Vec<String>,
+    },
+}
+
+impl VaultError {
+    /// Kind of a remote failure; `None` for other errors
+    pub fn remote_kind(&self) -> Option<RemoteErrorKind> {
+        match self {
+            Self::Remote { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    /// Check whether a remote source refused the API key
+    pub fn is_auth_error(&self) -> bool {
+        self.remote_kind() == Some(RemoteErrorKind::Auth)
+    }
+
+    /// Check whether a remote source doesn't have the sound
+    pub fn is_remote_not_found(&self) -> bool {
+        self.remote_kind() == Some(RemoteErrorKind::NotFound)
+    }
+
+    /// Check whether a remote source asked to slow down
+    pub fn is_rate_limited(&self) -> bool {
+        self.remote_kind() == Some(RemoteErrorKind::RateLimited)
+    }
+
+    /// Check whether a remote request may succeed if tried again later
+    pub fn is_transient(&self) -> bool {
+        self.remote_kind().is_some_and(|kind| kind.is_transient())
+    }
+
+    /// How long a remote source asked to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Remote { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 /// Convenience type alias for Result with VaultError
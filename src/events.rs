@@ -0,0 +1,386 @@
+//! Notifications of what happens in a vault
+
+use crate::error::Result;
+use crate::license::License;
+use crate::local::LocalLibrary;
+use crate::models::Availability;
+use crate::pack::split_packed;
+use crate::review::SoundStatus;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Events buffered per subscriber before the slowest ones start missing events
+pub(crate) const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened in the vault
+///
+/// Subscribe with [`SoundVault::subscribe`](crate::SoundVault::subscribe).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum VaultEvent {
+    /// A download was added to the queue
+    DownloadQueued {
+        /// ID of the queue entry
+        queue_id: i64,
+    },
+    /// The download queue started downloading an entry
+    DownloadStarted {
+        /// ID of the queue entry
+        queue_id: i64,
+    },
+    /// A queued download is in the library
+    DownloadFinished {
+        /// ID of the queue entry
+        queue_id: i64,
+        /// ID of the local sound
+        sound_id: String,
+    },
+    /// A queued download failed; it can be retried
+    DownloadFailed {
+        /// ID of the queue entry
+        queue_id: i64,
+        /// Why it failed
+        error: String,
+    },
+    /// A queued download was cancelled
+    DownloadCancelled {
+        /// ID of the queue entry
+        queue_id: i64,
+    },
+    /// Sounds were added to or removed from a collection
+    CollectionChanged {
+        /// ID of the collection
+        collection_id: String,
+    },
+    /// A session collection expired and was removed; its sounds are kept
+    SessionExpired {
+        /// ID of the collection
+        collection_id: String,
+    },
+    /// A sound's file went missing or came back
+    AvailabilityChanged {
+        /// ID of the sound
+        sound_id: String,
+        /// Its new availability
+        availability: Availability,
+    },
+    /// A sound was downloaded under a license the license policy doesn't
+    /// allow, and tagged for review
+    LicenseViolation {
+        /// ID of the sound
+        sound_id: String,
+        /// Its license
+        license: License,
+    },
+    /// Scrubbing found that a sound's file no longer matches its hash
+    SoundCorrupted {
+        /// ID of the sound
+        sound_id: String,
+    },
+    /// Part of an imported file was skipped, e.g. a malformed metadata
+    /// chunk; the sound was imported without it
+    ImportWarning {
+        /// ID of the sound
+        sound_id: String,
+        /// What was skipped, and why
+        warning: String,
+    },
+    /// An imported sound may hold the same audio as one already in the
+    /// vault, in another encoding; both were kept
+    PossibleDuplicate {
+        /// ID of the imported sound
+        sound_id: String,
+        /// ID of the sound it resembles
+        duplicate_of: String,
+    },
+    /// A sound was approved or rejected in the review of imports
+    SoundStatusChanged {
+        /// ID of the sound
+        sound_id: String,
+        /// Its new status
+        status: SoundStatus,
+    },
+    /// An expired sound was left out of an export or a manifest
+    ExpiredExcluded {
+        /// ID of the sound
+        sound_id: String,
+        /// Operation it was left out of, e.g. `export_daw_session`
+        operation: String,
+    },
+    /// A bulk edit of a collection's sounds went through another chunk of
+    /// them
+    BulkPatchProgress {
+        /// ID of the collection
+        collection_id: String,
+        /// Number of sounds done so far
+        done: u64,
+        /// Number of sounds in the collection
+        total: u64,
+    },
+    /// An event stream fell behind and events were dropped before reaching
+    /// it; they may include events its filter would have left out
+    Lagged {
+        /// Number of events dropped
+        missed: u64,
+    },
+}
+
+/// Kind of a [`VaultEvent`], to filter event streams by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum EventKind {
+    /// [`VaultEvent::DownloadQueued`]
+    DownloadQueued,
+    /// [`VaultEvent::DownloadStarted`]
+    DownloadStarted,
+    /// [`VaultEvent::DownloadFinished`]
+    DownloadFinished,
+    /// [`VaultEvent::DownloadFailed`]
+    DownloadFailed,
+    /// [`VaultEvent::DownloadCancelled`]
+    DownloadCancelled,
+    /// [`VaultEvent::CollectionChanged`]
+    CollectionChanged,
+    /// [`VaultEvent::SessionExpired`]
+    SessionExpired,
+    /// [`VaultEvent::AvailabilityChanged`]
+    AvailabilityChanged,
+    /// [`VaultEvent::LicenseViolation`]
+    LicenseViolation,
+    /// [`VaultEvent::SoundCorrupted`]
+    SoundCorrupted,
+    /// [`VaultEvent::ImportWarning`]
+    ImportWarning,
+    /// [`VaultEvent::PossibleDuplicate`]
+    PossibleDuplicate,
+    /// [`VaultEvent::SoundStatusChanged`]
+    SoundStatusChanged,
+    /// [`VaultEvent::ExpiredExcluded`]
+    ExpiredExcluded,
+    /// [`VaultEvent::BulkPatchProgress`]
+    BulkPatchProgress,
+    /// [`VaultEvent::Lagged`]
+    Lagged,
+}
+
+impl VaultEvent {
+    /// Kind of the event
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::DownloadQueued { .. } => EventKind::DownloadQueued,
+            Self::DownloadStarted { .. } => EventKind::DownloadStarted,
+            Self::DownloadFinished { .. } => EventKind::DownloadFinished,
+            Self::DownloadFailed { .. } => EventKind::DownloadFailed,
+            Self::DownloadCancelled { .. } => EventKind::DownloadCancelled,
+            Self::CollectionChanged { .. } => EventKind::CollectionChanged,
+            Self::SessionExpired { .. } => EventKind::SessionExpired,
+            Self::AvailabilityChanged { .. } => EventKind::AvailabilityChanged,
+            Self::LicenseViolation { .. } => EventKind::LicenseViolation,
+            Self::SoundCorrupted { .. } => EventKind::SoundCorrupted,
+            Self::ImportWarning { .. } => EventKind::ImportWarning,
+            Self::PossibleDuplicate { .. } => EventKind::PossibleDuplicate,
+            Self::SoundStatusChanged { .. } => EventKind::SoundStatusChanged,
+            Self::ExpiredExcluded { .. } => EventKind::ExpiredExcluded,
+            Self::BulkPatchProgress { .. } => EventKind::BulkPatchProgress,
+            Self::Lagged { .. } => EventKind::Lagged,
+        }
+    }
+
+    /// ID of what the event is about: the sound, the collection, or the
+    /// download queue entry, written in decimal
+    ///
+    /// [`VaultEvent::Lagged`] is about nothing in particular.
+    pub fn entity_id(&self) -> Option<String> {
+        match self {
+            Self::DownloadQueued { queue_id }
+            | Self::DownloadStarted { queue_id }
+            | Self::DownloadFinished { queue_id, .. }
+            | Self::DownloadFailed { queue_id, .. }
+            | Self::DownloadCancelled { queue_id } => Some(queue_id.to_string()),
+            Self::CollectionChanged { collection_id }
+            | Self::SessionExpired { collection_id }
+            | Self::BulkPatchProgress { collection_id, .. } => Some(collection_id.clone()),
+            Self::AvailabilityChanged { sound_id, .. }
+            | Self::LicenseViolation { sound_id, .. }
+            | Self::SoundCorrupted { sound_id }
+            | Self::ImportWarning { sound_id, .. }
+            | Self::PossibleDuplicate { sound_id, .. }
+            | Self::SoundStatusChanged { sound_id, .. }
+            | Self::ExpiredExcluded { sound_id, .. } => Some(sound_id.clone()),
+            Self::Lagged { .. } => None,
+        }
+    }
+}
+
+/// Which events an event stream from
+/// [`SoundVault::event_stream`](crate::SoundVault::event_stream) receives,
+/// and how many it buffers
+///
+/// [`VaultEvent::Lagged`] is always received.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventFilter {
+    /// Kinds of events received; all of them when empty
+    pub kinds: BTreeSet<EventKind>,
+
+    /// Only receive events about an entity whose ID starts with this, see
+    /// [`VaultEvent::entity_id`]
+    pub id_prefix: Option<String>,
+
+    /// Events buffered for the stream; once it's full, the vault holds 256
+    /// more events for it, then drops the oldest and sends a
+    /// [`VaultEvent::Lagged`]
+    pub buffer: usize,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self {
+            kinds: BTreeSet::new(),
+            id_prefix: None,
+            buffer: EVENT_CAPACITY,
+        }
+    }
+}
+
+impl EventFilter {
+    /// Receive only events of these kinds
+    pub fn kinds(mut self, kinds: impl IntoIterator<Item = EventKind>) -> Self {
+        self.kinds.extend(kinds);
+        self
+    }
+
+    /// Receive only events about entities whose ID starts with `prefix`
+    pub fn id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.id_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Buffer this many events for the stream
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Whether a stream with this filter receives an event
+    pub fn matches(&self, event: &VaultEvent) -> bool {
+        let kind = event.kind();
+        if kind == EventKind::Lagged {
+            return true;
+        }
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            return false;
+        }
+        match &self.id_prefix {
+            Some(prefix) => event.entity_id().is_some_and(|id| id.starts_with(prefix.as_str())),
+            None => true,
+        }
+    }
+}
+
+/// Forward the events matching `filter` to a stream, after `snapshot`
+///
+/// The forwarding task stops once the stream is dropped or the vault is.
+fn forward(
+    mut events: broadcast::Receiver<VaultEvent>,
+    filter: EventFilter,
+    snapshot: Vec<VaultEvent>,
+) -> ReceiverStream<VaultEvent> {
+    let (sender, receiver) = mpsc::channel(filter.buffer.max(1));
+    tokio::spawn(async move {
+        for event in snapshot {
+            if sender.send(event).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            let event = tokio::select! {
+                received = events.recv() => match received {
+                    Ok(event) if filter.matches(&event) => event,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => VaultEvent::Lagged { missed },
+                    Err(RecvError::Closed) => return,
+                },
+                _ = sender.closed() => return,
+            };
+            if sender.send(event).await.is_err() {
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+impl LocalLibrary {
+    /// Send an event to current subscribers, if any
+    pub(crate) fn emit(&self, event: VaultEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    /// Receive events from now on
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<VaultEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stream of the events matching `filter` from now on
+    ///
+    /// See [`SoundVault::event_stream`](crate::SoundVault::event_stream).
+    pub(crate) fn event_stream(&self, filter: EventFilter) -> ReceiverStream<VaultEvent> {
+        forward(self.subscribe(), filter, Vec::new())
+    }
+
+    /// Stream of the current state of the entities matching `filter`, then
+    /// of the events matching it
+    ///
+    /// See [`SoundVault::event_stream_with_snapshot`](crate::SoundVault::event_stream_with_snapshot).
+    pub(crate) async fn event_stream_with_snapshot(&self, filter: EventFilter) -> Result<ReceiverStream<VaultEvent>> {
+        // Subscribing before reading the state keeps changes made meanwhile
+        let events = self.subscribe();
+        let snapshot = self.event_snapshot(&filter).await?;
+        Ok(forward(events, filter, snapshot))
+    }
+
+    /// Current state of the sounds and collections as the events announcing
+    /// it, keeping those matching `filter`
+    async fn event_snapshot(&self, filter: &EventFilter) -> Result<Vec<VaultEvent>> {
+        let mut snapshot = Vec::new();
+
+        let rows = sqlx::query(&self.sql("SELECT id, path, packed, storage_backend FROM sounds ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?;
+        for row in rows {
+            let id: String = row.try_get("id")?;
+            let path: Option<String> = row.try_get("path")?;
+            let availability = match path {
+                Some(_) if row.try_get::<Option<String>, _>("storage_backend")?.is_some() => Availability::Available,
+                Some(path) => {
+                    let mut path = PathBuf::from(path);
+                    if row.try_get("packed")?
+                        && let Some((archive, _)) = split_packed(&path)
+                    {
+                        path = archive;
+                    }
+                    if path.exists() { Availability::Available } else { Availability::FileMissing }
+                }
+                None => Availability::NotDownloaded,
+            };
+            snapshot.push(VaultEvent::AvailabilityChanged { sound_id: id, availability });
+        }
+
+        let collection_ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM collections ORDER BY sort_key"))
+            .fetch_all(&self.reader)
+            .await?;
+        snapshot.extend(collection_ids.into_iter().map(|collection_id| VaultEvent::CollectionChanged { collection_id }));
+
+        snapshot.retain(|event| filter.matches(event));
+        Ok(snapshot)
+    }
+}
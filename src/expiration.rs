@@ -0,0 +1,184 @@
+//! Expiration dates of sounds licensed for a limited time
+//!
+//! A sound whose [`expires_at`](SoundMetadata::expires_at) has passed is
+//! left out of exports and manifests, each exclusion reported as a
+//! [`VaultEvent::ExpiredExcluded`], until
+//! [`enforce_expirations`](LocalLibrary::enforce_expirations) trashes,
+//! deletes or locks it.
+
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::lock::LOCK_REASON_KEY;
+use crate::models::SoundMetadata;
+use crate::patch::MetadataPatch;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Tag of the expired sounds locked by [`ExpireAction::LockAndFlag`]
+pub const EXPIRED_TAG: &str = "expired";
+
+/// Days ahead the health report lists the sounds about to expire
+const EXPIRATION_NOTICE_DAYS: i64 = 30;
+
+/// What [`enforce_expirations`](LocalLibrary::enforce_expirations) does
+/// with expired sounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpireAction {
+    /// Move the file into the quarantine directory, from which
+    /// [`restore_from_quarantine`](LocalLibrary::restore_from_quarantine)
+    /// can bring it back, and delete the sound
+    Trash,
+    /// Delete the sound and its file
+    Delete,
+    /// Lock the sound, tagged [`EXPIRED_TAG`], with its expiration as the
+    /// lock reason
+    LockAndFlag,
+}
+
+/// A sound that expired or is about to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expiration {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// Name of the sound
+    pub name: String,
+
+    /// When it expires
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SoundMetadata {
+    /// Check whether the sound's expiration date has passed
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+impl LocalLibrary {
+    /// Trash, delete or lock the sounds whose expiration date has passed
+    ///
+    /// Locked sounds are trashed or deleted too. Sounds locked and flagged
+    /// already are left alone.
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the sounds dealt with, the earliest expired first
+    pub async fn enforce_expirations(&self, action: ExpireAction) -> Result<Vec<String>> {
+        let mut handled = Vec::new();
+        for expiration in self.expirations(Utc::now()).await? {
+            let id = &expiration.sound_id;
+            match action {
+                ExpireAction::Trash => self.trash_sound(id).await?,
+                ExpireAction::Delete => self.delete_sound(id, true).await?,
+                ExpireAction::LockAndFlag => {
+                    let metadata = self.get_sound(id).await?.metadata;
+                    if metadata.locked && metadata.tags.iter().any(|tag| tag == EXPIRED_TAG) {
+                        continue;
+                    }
+                    let patch = MetadataPatch {
+                        add_tags: vec![EXPIRED_TAG.to_string()],
+                        override_lock: true,
+                        ..Default::default()
+                    };
+                    self.patch_metadata(id, patch).await?;
+                    let reason = metadata
+                        .custom
+                        .get(LOCK_REASON_KEY)
+                        .filter(|_| metadata.locked)
+                        .cloned()
+                        .unwrap_or_else(|| format!("Expired on {}", expiration.expires_at.format("%Y-%m-%d")));
+                    self.set_locked(id, true, Some(&reason)).await?;
+                }
+            }
+            handled.push(expiration.sound_id);
+        }
+
+        Ok(handled)
+    }
+
+    /// Sounds expiring by a date, expired ones included, the earliest first
+    pub(crate) async fn expirations(&self, by: DateTime<Utc>) -> Result<Vec<Expiration>> {
+        let rows: Vec<(String, String, DateTime<Utc>)> = sqlx::query_as(&self.sql(
+            "SELECT id, name, expires_at FROM sounds WHERE expires_at IS NOT NULL AND expires_at <= ? ORDER BY expires_at, id",
+        ))
+        .bind(by)
+        .fetch_all(&self.reader)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(sound_id, name, expires_at)| Expiration {
+                sound_id,
+                name,
+                expires_at,
+            })
+            .collect())
+    }
+
+    /// Number of expired sounds, and those expiring within the notice period
+    pub(crate) async fn expiration_notice(&self) -> Result<(u64, Vec<Expiration>)> {
+        let now = Utc::now();
+        let (expired, upcoming): (Vec<_>, Vec<_>) = self
+            .expirations(now + chrono::Duration::days(EXPIRATION_NOTICE_DAYS))
+            .await?
+            .into_iter()
+            .partition(|expiration| expiration.expires_at <= now);
+
+        Ok((expired.len() as u64, upcoming))
+    }
+
+    /// Fail if a sound expired
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Expired` if the sound's expiration date has passed
+    pub(crate) fn check_not_expired(metadata: &SoundMetadata) -> Result<()> {
+        match metadata.expires_at {
+            Some(expires_at) if metadata.is_expired() => Err(VaultError::Expired {
+                sound_id: metadata.id.clone(),
+                expires_at,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check whether a sound expired, telling subscribers it's left out of
+    /// an operation if so
+    pub(crate) fn exclude_expired(&self, metadata: &SoundMetadata, operation: &str) -> bool {
+        let expired = metadata.is_expired();
+        if expired {
+            self.emit(VaultEvent::ExpiredExcluded {
+                sound_id: metadata.id.clone(),
+                operation: operation.to_string(),
+            });
+        }
+        expired
+    }
+
+    /// Move a sound's file into quarantine and delete the sound
+    ///
+    /// A file held by a blob store is fetched first; a referenced file is
+    /// left where it is, and a content-addressed one, which other sounds
+    /// may share, is copied.
+    pub(crate) async fn trash_sound(&self, id: &str) -> Result<()> {
+        let metadata = self.get_sound(id).await?.metadata;
+        if metadata.path.is_some() && !metadata.external {
+            self.stage_blob(&metadata).await?;
+            let file = self.readable_file(&metadata)?;
+            if file.exists() {
+                let root = self.library_path.canonicalize()?;
+                let relative = file.strip_prefix(&root).unwrap_or(&file);
+                let date = Utc::now().format("%Y-%m-%d").to_string();
+                match metadata.path.as_deref().and_then(|path| self.object_hash(path)) {
+                    Some(_) => self.quarantine_copy(relative, &date)?,
+                    None => self.quarantine_file(relative, &date)?,
+                };
+            }
+        }
+
+        self.delete_sound(id, true).await
+    }
+}
@@ -0,0 +1,360 @@
+//! Declared types of custom metadata fields
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::patch::MetadataPatch;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+use std::collections::{BTreeMap, HashMap};
+
+/// Key of the schema mode in `vault_info`
+const SCHEMA_MODE_KEY: &str = "custom_schema_mode";
+
+/// Type of the values of a custom metadata field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "values", rename_all = "snake_case")]
+pub enum FieldType {
+    /// Any text
+    String,
+    /// A whole number, e.g. `-3`
+    Int,
+    /// A finite number, e.g. `0.5`
+    Float,
+    /// `true` or `false`
+    Bool,
+    /// One of the listed values
+    Enum(Vec<String>),
+}
+
+/// Declaration of a custom metadata field
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{FieldSpec, FieldType};
+///
+/// let spec = FieldSpec::new(FieldType::Enum(vec!["draft".into(), "final".into()]));
+/// assert!(spec.check("final").is_ok());
+/// assert!(spec.check("Final").is_err());
+///
+/// let take = FieldSpec::new(FieldType::Int);
+/// assert!(take.check("12").is_ok());
+/// assert!(take.check("12.5").is_err());
+/// assert!(FieldSpec::new(FieldType::Float).check("NaN").is_err());
+/// assert!(FieldSpec::new(FieldType::Bool).check("yes").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldSpec {
+    /// Type of the values
+    pub field_type: FieldType,
+
+    /// Whether every sound must have the field
+    #[serde(default)]
+    pub required: bool,
+
+    /// What the field means
+    #[serde(default)]
+    pub description: String,
+}
+
+impl FieldSpec {
+    /// Declare an optional field of the given type
+    pub fn new(field_type: FieldType) -> Self {
+        Self {
+            field_type,
+            required: false,
+            description: String::new(),
+        }
+    }
+
+    /// Check that a value has the field's type
+    pub fn check(&self, value: &str) -> Result<()> {
+        let valid = match &self.field_type {
+            FieldType::String => true,
+            FieldType::Int => value.parse::<i64>().is_ok(),
+            FieldType::Float => value.parse::<f64>().is_ok_and(f64::is_finite),
+            FieldType::Bool => value == "true" || value == "false",
+            FieldType::Enum(values) => values.iter().any(|allowed| allowed == value),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(VaultError::InvalidOperation(format!(
+                "Invalid value {:?} for a field of type {:?}",
+                value, self.field_type
+            )))
+        }
+    }
+}
+
+/// How custom metadata is checked against the declared fields
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaMode {
+    /// Only declared fields are checked
+    #[default]
+    Lax,
+    /// Undeclared fields are rejected too
+    Strict,
+}
+
+/// A sound whose custom metadata doesn't match the declared fields
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldViolation {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// Custom metadata key
+    pub key: String,
+
+    /// Current value, `None` when a required field is missing
+    pub value: Option<String>,
+
+    /// What's wrong
+    pub problem: String,
+}
+
+impl LocalLibrary {
+    /// Declare a custom metadata field, or change its declaration
+    ///
+    /// Existing values aren't checked; see
+    /// [`custom_field_violations`](Self::custom_field_violations).
+    pub async fn define_custom_field(&self, key: &str, spec: FieldSpec) -> Result<()> {
+        if key.is_empty() {
+            return Err(VaultError::InvalidOperation("Custom field key is empty".to_string()));
+        }
+
+        let mut conn = self.db.acquire().await?;
+        self.write_field_spec(&mut conn, key, &spec).await
+    }
+
+    pub(crate) async fn write_field_spec(&self, conn: &mut SqliteConnection, key: &str, spec: &FieldSpec) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO custom_fields (key, spec) VALUES (?, ?)
+            ON CONFLICT(key) DO UPDATE SET spec = excluded.spec
+            "#),
+        )
+        .bind(key)
+        .bind(serde_json::to_string(spec)?)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove the declaration of a custom metadata field, keeping its values
+    pub async fn remove_custom_field(&self, key: &str) -> Result<()> {
+        let removed = sqlx::query(&self.sql("DELETE FROM custom_fields WHERE key = ?"))
+            .bind(key)
+            .execute(&self.db)
+            .await?;
+        if removed.rows_affected() == 0 {
+            return Err(VaultError::NotFound(format!("Custom field not declared: {}", key)));
+        }
+
+        Ok(())
+    }
+
+    /// Declared custom metadata fields, by key
+    pub async fn list_custom_field_specs(&self) -> Result<BTreeMap<String, FieldSpec>> {
+        let mut conn = self.reader.acquire().await?;
+        self.field_specs(&mut conn).await
+    }
+
+    pub(crate) async fn field_specs(&self, conn: &mut SqliteConnection) -> Result<BTreeMap<String, FieldSpec>> {
+        let rows = sqlx::query(&self.sql("SELECT key, spec FROM custom_fields"))
+            .fetch_all(&mut *conn)
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let spec: String = row.try_get("spec")?;
+                Ok((row.try_get("key")?, serde_json::from_str(&spec)?))
+            })
+            .collect()
+    }
+
+    /// Set how custom metadata is checked against the declared fields
+    pub async fn set_custom_schema_mode(&self, mode: SchemaMode) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        self.write_schema_mode(&mut conn, mode).await
+    }
+
+    pub(crate) async fn write_schema_mode(&self, conn: &mut SqliteConnection, mode: SchemaMode) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO vault_info (key, value) VALUES (?, ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            "#),
+        )
+        .bind(SCHEMA_MODE_KEY)
+        .bind(serde_json::to_string(&mode)?)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// How custom metadata is checked against the declared fields
+    pub async fn custom_schema_mode(&self) -> Result<SchemaMode> {
+        let mut conn = self.reader.acquire().await?;
+        self.schema_mode(&mut conn).await
+    }
+
+    pub(crate) async fn schema_mode(&self, conn: &mut SqliteConnection) -> Result<SchemaMode> {
+        let mode: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(SCHEMA_MODE_KEY)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        Ok(match mode {
+            Some(mode) => serde_json::from_str(&mode)?,
+            None => SchemaMode::default(),
+        })
+    }
+
+    /// Check custom metadata being written against the declared fields
+    ///
+    /// Required fields can't be removed, but sounds may be created without
+    /// them, e.g. by imports.
+    pub(crate) async fn check_custom_fields(
+        &self, conn: &mut SqliteConnection,
+        set: &HashMap<String, String>,
+        removed: &[String],
+    ) -> Result<()> {
+        if set.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+
+        let specs = self.field_specs(conn).await?;
+        let strict = self.schema_mode(conn).await? == SchemaMode::Strict;
+        for (key, value) in set {
+            match specs.get(key) {
+                Some(spec) => spec
+                    .check(value)
+                    .map_err(|e| VaultError::InvalidOperation(format!("Custom field {:?}: {}", key, e)))?,
+                None if strict => {
+                    return Err(VaultError::InvalidOperation(format!("Undeclared custom field: {}", key)));
+                }
+                None => {}
+            }
+        }
+        for key in removed {
+            if specs.get(key).is_some_and(|spec| spec.required) && !set.contains_key(key) {
+                return Err(VaultError::InvalidOperation(format!("Custom field {:?} is required", key)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find custom metadata of existing sounds that doesn't match the
+    /// declared fields
+    ///
+    /// Undeclared keys are reported in strict mode only.
+    pub async fn custom_field_violations(&self) -> Result<Vec<FieldViolation>> {
+        let mut conn = self.reader.acquire().await?;
+        let specs = self.field_specs(&mut conn).await?;
+        let strict = self.schema_mode(&mut conn).await? == SchemaMode::Strict;
+        let mut violations = Vec::new();
+
+        let rows = sqlx::query(
+            &self.sql("SELECT object_id, key, value FROM metadata WHERE object_type = 'sound' ORDER BY object_id, key"),
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        for row in &rows {
+            let key: String = row.try_get("key")?;
+            let value: Option<String> = row.try_get("value")?;
+            let problem = match specs.get(&key) {
+                Some(spec) => spec.check(value.as_deref().unwrap_or_default()).err().map(|e| e.to_string()),
+                None if strict => Some("Undeclared custom field".to_string()),
+                None => None,
+            };
+            if let Some(problem) = problem {
+                violations.push(FieldViolation { sound_id: row.try_get("object_id")?, key, value, problem });
+            }
+        }
+
+        for (key, _) in specs.iter().filter(|(_, spec)| spec.required) {
+            let missing: Vec<String> = sqlx::query_scalar(
+                &self.sql(r#"
+                SELECT id FROM sounds WHERE NOT EXISTS (
+                    SELECT 1 FROM metadata
+                    WHERE object_id = sounds.id AND object_type = 'sound' AND key = ?
+                )
+                ORDER BY id
+                "#),
+            )
+            .bind(key)
+            .fetch_all(&mut *conn)
+            .await?;
+            violations.extend(missing.into_iter().map(|sound_id| FieldViolation {
+                sound_id,
+                key: key.clone(),
+                value: None,
+                problem: "Required custom field is missing".to_string(),
+            }));
+        }
+
+        Ok(violations)
+    }
+
+    /// Rename a custom metadata key on every sound that has it
+    ///
+    /// Runs in one transaction and is recorded in the audit log sound by
+    /// sound. A sound having both keys keeps its `new` value only if both
+    /// values are equal; otherwise nothing is renamed.
+    ///
+    /// # Returns
+    ///
+    /// Number of sounds changed
+    pub async fn rename_custom_key(&self, old: &str, new: &str) -> Result<u64> {
+        if new.is_empty() || old == new {
+            return Err(VaultError::InvalidOperation(format!("Can't rename custom key {:?} to {:?}", old, new)));
+        }
+
+        let mut tx = self.db.begin().await?;
+        let rows = sqlx::query(
+            &self.sql(r#"
+            SELECT old.object_id, old.value, new.value AS existing
+            FROM metadata old
+            LEFT JOIN metadata new
+                ON new.object_id = old.object_id AND new.object_type = 'sound' AND new.key = ?
+            WHERE old.object_type = 'sound' AND old.key = ?
+            ORDER BY old.object_id
+            "#),
+        )
+        .bind(new)
+        .bind(old)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for row in &rows {
+            let id: String = row.try_get("object_id")?;
+            let value: Option<String> = row.try_get("value")?;
+            let existing: Option<String> = row.try_get("existing")?;
+            let value = value.unwrap_or_default();
+
+            let mut patch = MetadataPatch { remove_custom: vec![old.to_string()], ..Default::default() };
+            match existing {
+                Some(existing) if existing != value => {
+                    return Err(VaultError::InvalidOperation(format!(
+                        "Sound {} has both {:?} and {:?} with different values",
+                        id, old, new
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    patch.set_custom.insert(new.to_string(), value);
+                }
+            }
+            self.apply_patch(&mut tx, &id, &patch).await?;
+        }
+        tx.commit().await?;
+
+        Ok(rows.len() as u64)
+    }
+}
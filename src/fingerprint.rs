@@ -0,0 +1,372 @@
+//! Acoustic fingerprints, to find the same audio in different encodings
+//!
+//! Content hashes only match byte-identical files. A fingerprint instead
+//! follows how the energy of 33 frequency bands between 300 Hz and 3 kHz
+//! rises and falls over time, one 32-bit word every 16 ms; lossy encodes,
+//! resampling and requantization of the same audio flip few of its bits,
+//! unrelated audio about half. Fingerprints are stored with the sound and
+//! computed again when its content changes.
+
+use crate::audio::{ChannelMix, downmix};
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::playback::decode_content;
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Length of the analysed windows, in seconds
+const WINDOW_SECONDS: f64 = 0.128;
+
+/// Time between the starts of two windows, in seconds
+const HOP_SECONDS: f64 = 0.016;
+
+/// Lowest and highest frequency compared, in hertz
+const BAND_RANGE: (f64, f64) = (300.0, 3000.0);
+
+/// Number of bands; each word compares neighbouring bands
+const BANDS: usize = 33;
+
+/// Most words one fingerprint may be shifted against another, absorbing
+/// the delay encoders add at the start
+const MAX_SHIFT: usize = 16;
+
+/// Fewest words a fingerprint must have, a quarter of a second
+const MIN_WORDS: usize = 16;
+
+/// Window energy under which a sound counts as silent
+const SILENCE: f32 = 1e-6;
+
+/// A sound that may hold the same audio as another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PossibleDuplicate {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// ID of the sound it resembles
+    pub duplicate_of: String,
+
+    /// Similarity of their fingerprints, from 0 for unrelated audio to 1
+    /// for identical audio
+    pub similarity: f64,
+}
+
+/// Sounds that may all hold the same audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    /// IDs of the sounds, sorted
+    pub sound_ids: Vec<String>,
+
+    /// Pairs of the group at least as similar as asked, most similar first
+    pub matches: Vec<PossibleDuplicate>,
+}
+
+/// Fingerprint of mono samples, empty if too short or silent
+fn fingerprint(samples: &[f32], rate: u32) -> Vec<u32> {
+    let window = (WINDOW_SECONDS * rate as f64).round() as usize;
+    let hop = (HOP_SECONDS * rate as f64).round() as usize;
+    if window == 0 || hop == 0 || samples.len() < window {
+        return Vec::new();
+    }
+
+    // Band edges in FFT bins, spaced evenly in octaves
+    let bin = |frequency: f64| ((frequency * window as f64 / rate as f64).round() as usize).min(window / 2);
+    let ratio = (BAND_RANGE.1 / BAND_RANGE.0).powf(1.0 / BANDS as f64);
+    let edges: Vec<usize> = (0..=BANDS).map(|band| bin(BAND_RANGE.0 * ratio.powi(band as i32))).collect();
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(window);
+    let hann: Vec<f32> = (0..window)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / window as f32).cos())
+        .collect();
+    let mut buffer = vec![Complex::new(0f32, 0f32); window];
+    let mut loud = false;
+    let mut previous: Option<[f32; BANDS]> = None;
+    let mut words = Vec::new();
+    for start in (0..=samples.len() - window).step_by(hop) {
+        for ((value, sample), weight) in buffer.iter_mut().zip(&samples[start..start + window]).zip(&hann) {
+            *value = Complex::new(sample * weight, 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut energies = [0f32; BANDS];
+        for (band, energy) in energies.iter_mut().enumerate() {
+            let bins = edges[band]..edges[band + 1].max(edges[band] + 1);
+            *energy = buffer[bins].iter().map(|c| c.norm_sqr()).sum();
+        }
+        loud |= energies.iter().sum::<f32>() > SILENCE * window as f32;
+
+        // Bit m is set when the energy difference of bands m and m + 1 grew
+        if let Some(previous) = previous {
+            let mut word = 0u32;
+            for band in 0..BANDS - 1 {
+                let now = energies[band] - energies[band + 1];
+                let before = previous[band] - previous[band + 1];
+                if now - before > 0.0 {
+                    word |= 1 << band;
+                }
+            }
+            words.push(word);
+        }
+        previous = Some(energies);
+    }
+
+    if !loud || words.len() < MIN_WORDS {
+        return Vec::new();
+    }
+    words
+}
+
+/// Similarity of two fingerprints at their best alignment, `None` if their
+/// lengths are too far apart to be the same audio
+fn similarity(a: &[u32], b: &[u32]) -> Option<f64> {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if short.is_empty() || long.len() - short.len() > MAX_SHIFT + long.len() / 10 {
+        return None;
+    }
+
+    let mut best = 0f64;
+    for shift in -(MAX_SHIFT as isize)..=MAX_SHIFT as isize {
+        let pairs = short
+            .iter()
+            .enumerate()
+            .filter_map(|(i, word)| Some((word, long.get(usize::try_from(i as isize + shift).ok()?)?)));
+        let (mut bits, mut differing) = (0u64, 0u64);
+        for (x, y) in pairs {
+            bits += BANDS as u64 - 1;
+            differing += (x ^ y).count_ones() as u64;
+        }
+        // Alignments leaving most of the shorter fingerprint out don't count
+        if bits * 4 < short.len() as u64 * (BANDS as u64 - 1) * 3 {
+            continue;
+        }
+        best = best.max(1.0 - 2.0 * differing as f64 / bits as f64);
+    }
+    Some(best.max(0.0))
+}
+
+fn to_blob(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<u32> {
+    blob.chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap_or_default()))
+        .collect()
+}
+
+/// Check that a threshold is a similarity
+fn check_threshold(threshold: f64) -> Result<()> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(VaultError::InvalidOperation(format!("Invalid similarity threshold: {}", threshold)));
+    }
+    Ok(())
+}
+
+impl LocalLibrary {
+    /// Fingerprint of a sound, computed and stored the first time
+    ///
+    /// # Returns
+    ///
+    /// The fingerprint, empty if the sound can't be decoded or is too short
+    /// or silent; `None` if its file isn't there to read
+    async fn fingerprint(&self, id: &str) -> Result<Option<Vec<u32>>> {
+        let stored: Option<Option<Vec<u8>>> =
+            sqlx::query_scalar(&self.sql("SELECT fingerprint FROM sounds WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.reader)
+                .await?;
+        match stored {
+            None => return Err(VaultError::NotFound(format!("Sound not found: {}", id))),
+            Some(Some(blob)) => return Ok(Some(from_blob(&blob))),
+            Some(None) => {}
+        }
+
+        let metadata = self.get_sound(id).await?.metadata;
+        let readable = metadata.path.is_some()
+            && self.stage_blob(&metadata).await.is_ok()
+            && self.readable_file(&metadata)?.exists();
+        if !readable {
+            return Ok(None);
+        }
+        let bytes = self.sound_bytes(&metadata)?;
+        let words = self
+            .jobs
+            .run(move || {
+                // Files no decoder reads get an empty fingerprint, so they
+                // aren't decoded again
+                let words = match decode_content(bytes) {
+                    Ok((rate, channels, samples)) => {
+                        fingerprint(&downmix(&samples, channels.max(1), ChannelMix::Mono), rate)
+                    }
+                    Err(_) => Vec::new(),
+                };
+                Ok(words)
+            })
+            .await?;
+
+        // The hash guards against content replaced meanwhile
+        sqlx::query(&self.sql("UPDATE sounds SET fingerprint = ? WHERE id = ? AND hash IS ?"))
+            .bind(to_blob(&words))
+            .bind(id)
+            .bind(&metadata.hash)
+            .execute(&self.db)
+            .await?;
+        Ok(Some(words))
+    }
+
+    /// Similarity of the audio of two sounds, from 0 for unrelated audio to
+    /// 1 for identical audio, e.g. to review a [`DuplicateGroup`]
+    ///
+    /// # Returns
+    ///
+    /// `None` if either sound can't be fingerprinted, or their durations are
+    /// too far apart to hold the same audio
+    pub async fn audio_similarity(&self, a: &str, b: &str) -> Result<Option<f64>> {
+        let (Some(a), Some(b)) = (self.fingerprint(a).await?, self.fingerprint(b).await?) else {
+            return Ok(None);
+        };
+        Ok(similarity(&a, &b))
+    }
+
+    /// Fingerprinted sounds, with the sound each derives from
+    async fn fingerprints(&self) -> Result<Vec<(String, Vec<u32>, Option<String>)>> {
+        let rows: Vec<(String, Vec<u8>, Option<String>)> = sqlx::query_as(
+            &self.sql("SELECT id, fingerprint, derived_from FROM sounds WHERE length(fingerprint) > 0 ORDER BY id"),
+        )
+        .fetch_all(&self.reader)
+        .await?;
+        Ok(rows.into_iter().map(|(id, blob, parent)| (id, from_blob(&blob), parent)).collect())
+    }
+
+    /// Fingerprinted sounds resembling one at least as much as `threshold`,
+    /// most similar first
+    ///
+    /// Derivatives of the sound and the sound it derives from are left out.
+    /// Sounds not fingerprinted yet aren't compared.
+    pub(crate) async fn possible_duplicates(&self, id: &str, threshold: f64) -> Result<Vec<PossibleDuplicate>> {
+        check_threshold(threshold)?;
+        let Some(words) = self.fingerprint(id).await?.filter(|words| !words.is_empty()) else {
+            return Ok(Vec::new());
+        };
+        let parent = self.get_sound(id).await?.metadata.derived_from;
+
+        let mut matches: Vec<PossibleDuplicate> = self
+            .fingerprints()
+            .await?
+            .into_iter()
+            .filter(|(other, _, other_parent)| {
+                other != id && other_parent.as_deref() != Some(id) && parent.as_deref() != Some(other.as_str())
+            })
+            .filter_map(|(other, other_words, _)| {
+                let similarity = similarity(&words, &other_words).filter(|similarity| *similarity >= threshold)?;
+                Some(PossibleDuplicate {
+                    sound_id: id.to_string(),
+                    duplicate_of: other,
+                    similarity,
+                })
+            })
+            .collect();
+        matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then_with(|| a.duplicate_of.cmp(&b.duplicate_of)));
+        Ok(matches)
+    }
+
+    /// Report the sounds a just imported one may duplicate, if imports are
+    /// checked, as [`VaultEvent::PossibleDuplicate`]s
+    ///
+    /// The import stands whatever the outcome; a failed check reports
+    /// nothing.
+    pub(crate) async fn flag_near_duplicates(&self, id: &str) -> Vec<PossibleDuplicate> {
+        let Some(threshold) = self.near_duplicate_threshold else {
+            return Vec::new();
+        };
+        let matches = self.possible_duplicates(id, threshold).await.unwrap_or_default();
+        for duplicate in &matches {
+            self.emit(VaultEvent::PossibleDuplicate {
+                sound_id: duplicate.sound_id.clone(),
+                duplicate_of: duplicate.duplicate_of.clone(),
+            });
+        }
+        matches
+    }
+
+    /// Group the sounds that may hold the same audio in different encodings
+    ///
+    /// Sounds are fingerprinted first if they weren't yet, which decodes
+    /// their file; files that aren't there are skipped. Pairs at least as
+    /// similar as `threshold` are linked, and linked sounds grouped. A sound
+    /// and its derivatives are never linked.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if `threshold` isn't between 0 and 1
+    pub async fn find_near_duplicates(&self, threshold: f64) -> Result<Vec<DuplicateGroup>> {
+        check_threshold(threshold)?;
+        let unprinted: Vec<String> =
+            sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE fingerprint IS NULL AND path IS NOT NULL ORDER BY id"))
+                .fetch_all(&self.reader)
+                .await?;
+        for id in unprinted {
+            self.fingerprint(&id).await?;
+        }
+
+        // Only fingerprints of close lengths are compared
+        let mut sounds = self.fingerprints().await?;
+        sounds.sort_by_key(|(_, words, _)| words.len());
+        let mut matches = Vec::new();
+        for (i, (a, a_words, a_parent)) in sounds.iter().enumerate() {
+            for (b, b_words, b_parent) in &sounds[i + 1..] {
+                if b_words.len() - a_words.len() > MAX_SHIFT + b_words.len() / 10 {
+                    break;
+                }
+                if a_parent.as_ref() == Some(b) || b_parent.as_ref() == Some(a) {
+                    continue;
+                }
+                if let Some(similarity) = similarity(a_words, b_words).filter(|similarity| *similarity >= threshold) {
+                    let (sound_id, duplicate_of) = if a < b { (a, b) } else { (b, a) };
+                    matches.push(PossibleDuplicate {
+                        sound_id: sound_id.clone(),
+                        duplicate_of: duplicate_of.clone(),
+                        similarity,
+                    });
+                }
+            }
+        }
+
+        // Linked sounds share the smallest ID of their group
+        let mut roots: HashMap<String, String> = HashMap::new();
+        fn root(roots: &HashMap<String, String>, id: &str) -> String {
+            let mut id = id;
+            while let Some(parent) = roots.get(id).filter(|parent| *parent != id) {
+                id = parent;
+            }
+            id.to_string()
+        }
+        for duplicate in &matches {
+            let (a, b) = (root(&roots, &duplicate.sound_id), root(&roots, &duplicate.duplicate_of));
+            let (low, high) = if a < b { (a, b) } else { (b, a) };
+            roots.insert(high, low.clone());
+            roots.entry(low.clone()).or_insert(low);
+        }
+
+        let mut groups: BTreeMap<String, DuplicateGroup> = BTreeMap::new();
+        for duplicate in matches {
+            let group = groups.entry(root(&roots, &duplicate.sound_id)).or_insert_with(|| DuplicateGroup {
+                sound_ids: Vec::new(),
+                matches: Vec::new(),
+            });
+            group.sound_ids.extend([duplicate.sound_id.clone(), duplicate.duplicate_of.clone()]);
+            group.matches.push(duplicate);
+        }
+        Ok(groups
+            .into_values()
+            .map(|mut group| {
+                group.sound_ids.sort();
+                group.sound_ids.dedup();
+                group.matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+                group
+            })
+            .collect())
+    }
+}
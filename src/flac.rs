@@ -0,0 +1,662 @@
+//! Minimal FLAC encoder and decoder for archived sounds
+//!
+//! The encoder writes fixed-predictor subframes with Rice-coded residuals,
+//! which keeps it short while compressing typical recordings well. The
+//! decoder reads any stream of up to 24 bits per sample.
+
+use crate::error::{Result, VaultError};
+use std::io::Write;
+
+/// Samples per channel in each frame
+const BLOCK_SIZE: usize = 4096;
+
+/// Largest Rice parameter of the 4-bit coding; 15 escapes a partition
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Largest Rice parameter of the 5-bit coding; 31 escapes a partition
+const MAX_RICE2_PARAMETER: u32 = 30;
+
+/// Metadata block types
+const STREAMINFO: u8 = 0;
+const APPLICATION: u8 = 2;
+
+/// Decoded FLAC stream
+pub(crate) struct FlacStream {
+    /// Number of channels
+    pub channels: u16,
+    /// Frames per second
+    pub sample_rate: u32,
+    /// Bits per sample
+    pub bits: u16,
+    /// Interleaved samples
+    pub samples: Vec<i32>,
+    /// Payload of the first APPLICATION block with the requested ID
+    pub application: Option<Vec<u8>>,
+}
+
+/// Encode interleaved integer samples as FLAC
+///
+/// # Arguments
+///
+/// * `channels` - Number of channels, 1 to 8
+/// * `sample_rate` - Frames per second
+/// * `bits` - Bits per sample, 4 to 24
+/// * `samples` - Interleaved samples
+/// * `application` - ID and payload of an APPLICATION block to embed
+pub(crate) fn encode(
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+    samples: &[i32],
+    application: Option<(&[u8; 4], &[u8])>,
+) -> Result<Vec<u8>> {
+    check_format(channels, sample_rate, bits)?;
+    let channels = channels as usize;
+    let total_frames = samples.len() / channels;
+
+    let mut out = Vec::with_capacity(samples.len() * bits as usize / 16 + 128);
+    out.extend(b"fLaC");
+    push_block(
+        &mut out,
+        STREAMINFO,
+        application.is_none(),
+        &streaminfo(channels as u16, sample_rate, bits, total_frames as u64),
+    )?;
+
+    if let Some((id, payload)) = application {
+        let mut block = id.to_vec();
+        block.extend(payload);
+        push_block(&mut out, APPLICATION, true, &block)?;
+    }
+
+    for (number, block) in samples[..total_frames * channels].chunks(BLOCK_SIZE * channels).enumerate() {
+        encode_frame(&mut out, number as u64, channels, bits, block);
+    }
+
+    Ok(out)
+}
+
+/// FLAC stream written a block at a time, so a sound of any length is
+/// encoded in bounded memory
+pub(crate) struct FlacWriter<W> {
+    writer: W,
+    channels: usize,
+    bits: u16,
+    /// Samples not encoded yet, less than a block
+    pending: Vec<i32>,
+    /// Number of the next frame
+    frame: u64,
+}
+
+impl<W: Write> FlacWriter<W> {
+    /// Start a stream of `total_frames` frames; the frames written must add
+    /// up to it
+    pub fn new(mut writer: W, channels: u16, sample_rate: u32, bits: u16, total_frames: u64) -> Result<Self> {
+        check_format(channels, sample_rate, bits)?;
+        let mut header = b"fLaC".to_vec();
+        push_block(&mut header, STREAMINFO, true, &streaminfo(channels, sample_rate, bits, total_frames))?;
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            channels: channels as usize,
+            bits,
+            pending: Vec::with_capacity(BLOCK_SIZE * channels as usize),
+            frame: 0,
+        })
+    }
+
+    /// Encode interleaved samples
+    pub fn write(&mut self, samples: &[i32]) -> Result<()> {
+        let block = BLOCK_SIZE * self.channels;
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let take = (block - self.pending.len()).min(samples.len());
+            self.pending.extend(&samples[..take]);
+            samples = &samples[take..];
+            if self.pending.len() == block {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the last, shorter block and hand back the writer
+    pub fn finish(mut self) -> Result<W> {
+        self.pending.truncate(self.pending.len() - self.pending.len() % self.channels);
+        if !self.pending.is_empty() {
+            self.flush()?;
+        }
+        Ok(self.writer)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut out = Vec::new();
+        encode_frame(&mut out, self.frame, self.channels, self.bits, &self.pending);
+        self.writer.write_all(&out)?;
+        self.pending.clear();
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+/// Decode a FLAC stream
+///
+/// # Arguments
+///
+/// * `bytes` - The whole stream
+/// * `application_id` - ID of the APPLICATION block to return
+pub(crate) fn decode(bytes: &[u8], application_id: &[u8; 4]) -> Result<FlacStream> {
+    if !bytes.starts_with(b"fLaC") {
+        return Err(corrupt("missing FLAC signature"));
+    }
+
+    let mut position = 4;
+    let mut streaminfo = None;
+    let mut application = None;
+    loop {
+        let header = bytes.get(position..position + 4).ok_or_else(|| corrupt("truncated metadata"))?;
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let block = bytes
+            .get(position + 4..position + 4 + len)
+            .ok_or_else(|| corrupt("truncated metadata"))?;
+
+        if kind == STREAMINFO {
+            let mut reader = BitReader::new(block);
+            reader.read(16 + 16)?;
+            reader.read(24 + 24)?;
+            let sample_rate = reader.read(20)? as u32;
+            let channels = reader.read(3)? as u16 + 1;
+            let bits = reader.read(5)? as u16 + 1;
+            let total = reader.read(36)?;
+            streaminfo = Some((sample_rate, channels, bits, total));
+        } else if kind == APPLICATION && application.is_none() && block.starts_with(application_id) {
+            application = Some(block[4..].to_vec());
+        }
+
+        position += 4 + len;
+        if last {
+            break;
+        }
+    }
+
+    let (sample_rate, channels, bits, total) = streaminfo.ok_or_else(|| corrupt("missing STREAMINFO"))?;
+    if bits > 24 {
+        return Err(unsupported("FLAC samples wider than 24 bits"));
+    }
+
+    let mut samples = Vec::with_capacity(total as usize * channels as usize);
+    let mut reader = BitReader::new(&bytes[position..]);
+    while (samples.len() / channels as usize) < total as usize && !reader.at_end() {
+        decode_frame(&mut reader, channels as usize, bits as u32, &mut samples)?;
+    }
+    samples.truncate(total as usize * channels as usize);
+
+    Ok(FlacStream {
+        channels,
+        sample_rate,
+        bits,
+        samples,
+        application,
+    })
+}
+
+fn check_format(channels: u16, sample_rate: u32, bits: u16) -> Result<()> {
+    if !(1..=8).contains(&channels) || !(4..=24).contains(&bits) || sample_rate == 0 || sample_rate >= 1 << 20 {
+        return Err(unsupported("FLAC needs 1 to 8 channels of 4 to 24 bits"));
+    }
+    Ok(())
+}
+
+/// Payload of the STREAMINFO block
+fn streaminfo(channels: u16, sample_rate: u32, bits: u16, total_frames: u64) -> Vec<u8> {
+    let mut info = BitWriter::default();
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(0, 24);
+    info.write(0, 24);
+    info.write(sample_rate as u64, 20);
+    info.write(channels as u64 - 1, 3);
+    info.write(bits as u64 - 1, 5);
+    info.write(total_frames, 36);
+    info.write(0, 64);
+    info.write(0, 64);
+    info.finish()
+}
+
+/// Append a metadata block
+fn push_block(out: &mut Vec<u8>, kind: u8, last: bool, data: &[u8]) -> Result<()> {
+    if data.len() >= 1 << 24 {
+        return Err(unsupported("metadata block too large"));
+    }
+    out.push(if last { 0x80 } else { 0 } | kind);
+    out.extend(&(data.len() as u32).to_be_bytes()[1..]);
+    out.extend(data);
+    Ok(())
+}
+
+fn encode_frame(out: &mut Vec<u8>, number: u64, channels: usize, bits: u16, block: &[i32]) {
+    let block_size = block.len() / channels;
+    let start = out.len();
+
+    out.extend([0xFF, 0xF8]);
+    out.push(0x70);
+    let size_code = match bits {
+        8 => 1,
+        16 => 4,
+        24 => 6,
+        _ => 0,
+    };
+    out.push(((channels as u8 - 1) << 4) | (size_code << 1));
+    push_utf8_number(out, number);
+    out.extend((block_size as u16 - 1).to_be_bytes());
+    out.push(crc8(&out[start..]));
+
+    let mut writer = BitWriter::default();
+    let mut channel = Vec::with_capacity(block_size);
+    for c in 0..channels {
+        channel.clear();
+        channel.extend(block.iter().skip(c).step_by(channels).map(|&s| s as i64));
+        encode_subframe(&mut writer, &channel, bits as u32);
+    }
+    out.extend(writer.finish());
+
+    let crc = crc16(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// Write the cheapest of a constant, fixed-predictor or verbatim subframe
+fn encode_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        writer.write(0, 8);
+        writer.write_signed(samples[0], bits);
+        return;
+    }
+
+    let mut best: Option<(u64, usize, u32, Vec<u64>)> = None;
+    for order in 0..=4.min(samples.len() - 1) {
+        let residuals: Vec<u64> = (order..samples.len())
+            .map(|i| {
+                let s = &samples[i - order..=i];
+                let residual = match order {
+                    0 => s[0],
+                    1 => s[1] - s[0],
+                    2 => s[2] - 2 * s[1] + s[0],
+                    3 => s[3] - 3 * s[2] + 3 * s[1] - s[0],
+                    _ => s[4] - 4 * s[3] + 6 * s[2] - 4 * s[1] + s[0],
+                };
+                ((residual << 1) ^ (residual >> 63)) as u64
+            })
+            .collect();
+
+        let (parameter, cost) = (0..=MAX_RICE2_PARAMETER)
+            .map(|p| (p, residuals.iter().map(|&u| (u >> p) + 1 + p as u64).sum::<u64>()))
+            .min_by_key(|&(_, cost)| cost)
+            .unwrap_or((0, u64::MAX));
+        let total = 8 + order as u64 * bits as u64 + 11 + cost;
+        if best.as_ref().is_none_or(|b| total < b.0) {
+            best = Some((total, order, parameter, residuals));
+        }
+    }
+
+    match best {
+        Some((total, order, parameter, residuals)) if total < 8 + samples.len() as u64 * bits as u64 => {
+            writer.write(0x08 | order as u64, 7);
+            writer.write(0, 1);
+            for &sample in &samples[..order] {
+                writer.write_signed(sample, bits);
+            }
+            if parameter <= MAX_RICE_PARAMETER {
+                writer.write(0, 2);
+                writer.write(0, 4);
+                writer.write(parameter as u64, 4);
+            } else {
+                writer.write(1, 2);
+                writer.write(0, 4);
+                writer.write(parameter as u64, 5);
+            }
+            for u in residuals {
+                writer.write_unary(u >> parameter);
+                writer.write(u & ((1 << parameter) - 1), parameter);
+            }
+        }
+        _ => {
+            writer.write(0x02, 8);
+            for &sample in samples {
+                writer.write_signed(sample, bits);
+            }
+        }
+    }
+}
+
+fn decode_frame(reader: &mut BitReader, channels: usize, bits: u32, out: &mut Vec<i32>) -> Result<()> {
+    let start = reader.byte_position();
+
+    if reader.read(15)? != 0x7FFC {
+        return Err(corrupt("lost frame sync"));
+    }
+    reader.read(1)?;
+    let size_code = reader.read(4)?;
+    let rate_code = reader.read(4)?;
+    let assignment = reader.read(4)? as usize;
+    reader.read(4)?;
+
+    // Frame or sample number, UTF-8 style
+    let first = reader.read(8)? as u8;
+    for _ in 1..first.leading_ones().max(1) {
+        reader.read(8)?;
+    }
+
+    let block_size = match size_code {
+        1 => 192,
+        2..=5 => 576 << (size_code - 2),
+        6 => reader.read(8)? as usize + 1,
+        7 => reader.read(16)? as usize + 1,
+        8..=15 => 256 << (size_code - 8),
+        _ => return Err(corrupt("reserved block size")),
+    };
+    match rate_code {
+        12 => reader.read(8)?,
+        13 | 14 => reader.read(16)?,
+        _ => 0,
+    };
+    let header_end = reader.byte_position();
+    if crc8(reader.bytes(start, header_end)) != reader.read(8)? as u8 {
+        return Err(corrupt("frame header checksum mismatch"));
+    }
+
+    let (count, side) = match assignment {
+        0..=7 => (assignment + 1, None),
+        8..=10 => (2, Some(assignment)),
+        _ => return Err(corrupt("reserved channel assignment")),
+    };
+    if count != channels {
+        return Err(corrupt("channel count changed"));
+    }
+
+    let mut decoded = Vec::with_capacity(count);
+    for c in 0..count {
+        let extra = match side {
+            Some(8) | Some(10) if c == 1 => 1,
+            Some(9) if c == 0 => 1,
+            _ => 0,
+        };
+        decoded.push(decode_subframe(reader, block_size, bits + extra)?);
+    }
+
+    reader.align();
+    let frame_end = reader.byte_position();
+    if crc16(reader.bytes(start, frame_end)) != reader.read(16)? as u16 {
+        return Err(corrupt("frame checksum mismatch"));
+    }
+
+    if let Some(mode) = side {
+        let (a, b) = decoded.split_at_mut(1);
+        for (x, y) in a[0].iter_mut().zip(b[0].iter_mut()) {
+            let (left, right) = match mode {
+                8 => (*x, *x - *y),
+                9 => (*x + *y, *y),
+                _ => {
+                    let mid = (*x << 1) | (*y & 1);
+                    ((mid + *y) >> 1, (mid - *y) >> 1)
+                }
+            };
+            *x = left;
+            *y = right;
+        }
+    }
+
+    for i in 0..block_size {
+        for channel in &decoded {
+            out.push(channel[i] as i32);
+        }
+    }
+
+    Ok(())
+}
+
+fn decode_subframe(reader: &mut BitReader, block_size: usize, bits: u32) -> Result<Vec<i64>> {
+    reader.read(1)?;
+    let kind = reader.read(6)?;
+    let wasted = if reader.read(1)? == 1 { reader.read_unary()? as u32 + 1 } else { 0 };
+    let bits = bits.checked_sub(wasted).ok_or_else(|| corrupt("too many wasted bits"))?;
+
+    let mut samples = match kind {
+        0 => vec![reader.read_signed(bits)?; block_size],
+        1 => (0..block_size).map(|_| reader.read_signed(bits)).collect::<Result<_>>()?,
+        8..=12 => {
+            let order = kind as usize - 8;
+            let mut samples = warmup(reader, order, block_size, bits)?;
+            let residuals = read_residuals(reader, block_size, order)?;
+            let coefficients: &[i64] = match order {
+                0 => &[],
+                1 => &[1],
+                2 => &[2, -1],
+                3 => &[3, -3, 1],
+                _ => &[4, -6, 4, -1],
+            };
+            predict(&mut samples, &residuals, coefficients, 0);
+            samples
+        }
+        32..=63 => {
+            let order = kind as usize - 31;
+            let mut samples = warmup(reader, order, block_size, bits)?;
+            let precision = reader.read(4)? as u32 + 1;
+            let shift = reader.read_signed(5)?;
+            let coefficients: Vec<i64> = (0..order).map(|_| reader.read_signed(precision)).collect::<Result<_>>()?;
+            let residuals = read_residuals(reader, block_size, order)?;
+            predict(&mut samples, &residuals, &coefficients, shift.max(0) as u32);
+            samples
+        }
+        _ => return Err(corrupt("reserved subframe type")),
+    };
+
+    if wasted > 0 {
+        samples.iter_mut().for_each(|s| *s <<= wasted);
+    }
+    Ok(samples)
+}
+
+fn warmup(reader: &mut BitReader, order: usize, block_size: usize, bits: u32) -> Result<Vec<i64>> {
+    if order > block_size {
+        return Err(corrupt("predictor order exceeds block size"));
+    }
+    let mut samples = Vec::with_capacity(block_size);
+    for _ in 0..order {
+        samples.push(reader.read_signed(bits)?);
+    }
+    Ok(samples)
+}
+
+/// Extend warm-up samples with predictions plus residuals
+///
+/// `coefficients[0]` applies to the most recent sample.
+fn predict(samples: &mut Vec<i64>, residuals: &[i64], coefficients: &[i64], shift: u32) {
+    for &residual in residuals {
+        let n = samples.len();
+        let prediction: i64 = coefficients.iter().enumerate().map(|(j, c)| c * samples[n - 1 - j]).sum();
+        samples.push((prediction >> shift) + residual);
+    }
+}
+
+fn read_residuals(reader: &mut BitReader, block_size: usize, order: usize) -> Result<Vec<i64>> {
+    let parameter_bits = match reader.read(2)? {
+        0 => 4,
+        1 => 5,
+        _ => return Err(corrupt("reserved residual coding")),
+    };
+    let escape = (1 << parameter_bits) - 1;
+    let partition_order = reader.read(4)? as u32;
+    let partitions = 1usize << partition_order;
+    let partition_size = block_size >> partition_order;
+    if partition_size * partitions != block_size || partition_size < order {
+        return Err(corrupt("invalid residual partitions"));
+    }
+
+    let mut residuals = Vec::with_capacity(block_size - order);
+    for partition in 0..partitions {
+        let count = if partition == 0 { partition_size - order } else { partition_size };
+        let parameter = reader.read(parameter_bits)? as u32;
+        if parameter == escape {
+            let raw_bits = reader.read(5)? as u32;
+            for _ in 0..count {
+                residuals.push(if raw_bits == 0 { 0 } else { reader.read_signed(raw_bits)? });
+            }
+        } else {
+            for _ in 0..count {
+                let u = (reader.read_unary()? << parameter) | reader.read(parameter)?;
+                residuals.push((u >> 1) as i64 ^ -((u & 1) as i64));
+            }
+        }
+    }
+
+    Ok(residuals)
+}
+
+/// Write a frame number in FLAC's UTF-8-like coding
+fn push_utf8_number(out: &mut Vec<u8>, number: u64) {
+    if number < 0x80 {
+        out.push(number as u8);
+        return;
+    }
+
+    let mut len = 2;
+    while number >= 1 << (5 * len + 1) {
+        len += 1;
+    }
+    out.push((0xFF00u16 >> len) as u8 | (number >> (6 * (len - 1))) as u8);
+    for i in (0..len - 1).rev() {
+        out.push(0x80 | ((number >> (6 * i)) & 0x3F) as u8);
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 }
+        })
+    })
+}
+
+fn unsupported(reason: &str) -> VaultError {
+    VaultError::InvalidOperation(format!("Unsupported audio: {}", reason))
+}
+
+fn corrupt(reason: &str) -> VaultError {
+    VaultError::InvalidOperation(format!("Invalid FLAC stream: {}", reason))
+}
+
+/// Big-endian bit writer
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    /// Write the low `bits` bits of `value`
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        if bits == 0 {
+            return;
+        }
+        self.buffer = (self.buffer << bits) | (value & ((1 << bits) - 1));
+        self.len += bits;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.bytes.push((self.buffer >> self.len) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Write `zeros` zero bits followed by a one
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Pad to a byte boundary with zeros and return the bytes
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+        self.bytes
+    }
+}
+
+/// Big-endian bit reader
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn at_end(&self) -> bool {
+        self.position / 8 >= self.bytes.len()
+    }
+
+    fn byte_position(&self) -> usize {
+        self.position / 8
+    }
+
+    fn bytes(&self, start: usize, end: usize) -> &'a [u8] {
+        &self.bytes[start..end]
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    /// Read `bits` bits, at most 64
+    fn read(&mut self, bits: u32) -> Result<u64> {
+        if self.position + bits as usize > self.bytes.len() * 8 {
+            return Err(corrupt("unexpected end of stream"));
+        }
+        let mut value = 0u64;
+        for _ in 0..bits {
+            let byte = self.bytes[self.position / 8];
+            value = (value << 1) | ((byte >> (7 - self.position % 8)) & 1) as u64;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn read_signed(&mut self, bits: u32) -> Result<i64> {
+        if bits == 0 {
+            return Ok(0);
+        }
+        let value = self.read(bits)?;
+        Ok(((value << (64 - bits)) as i64) >> (64 - bits))
+    }
+
+    /// Count zero bits up to the next one
+    fn read_unary(&mut self) -> Result<u64> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+        }
+        Ok(zeros)
+    }
+}
@@ -0,0 +1,293 @@
+//! Typo-tolerant text search over the words of names and tags
+//!
+//! The words of each sound's name and tags are indexed by trigram, so the
+//! candidates for a misspelled word are found without scanning the library;
+//! their edit distance to it is then computed exactly.
+
+use crate::error::Result;
+use crate::local::LocalLibrary;
+use crate::query::SoundFilter;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Near matches looked at, closest first, for one query
+const CANDIDATE_LIMIT: usize = 500;
+
+/// `vault_info` key recording that every sound's words are indexed
+const WORDS_INDEXED: &str = "search_words_indexed";
+
+/// How far the words of a text search may be from those of the sounds
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{Fuzziness, PageRequest, SoundFilter, SoundMetadata, SoundVault, VaultConfig};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+/// for (file, tags) in [("Thunder rumble.wav", &["storm"][..]), ("Rain on tin roof.wav", &["storm", "rain"][..])] {
+///     let path = dir.path().join(file);
+///     std::fs::write(&path, file)?;
+///     let metadata = SoundMetadata {
+///         name: file.to_string(),
+///         tags: tags.iter().map(|tag| tag.to_string()).collect(),
+///         ..Default::default()
+///     };
+///     vault.import_file(&path, Some(metadata)).await?;
+/// }
+///
+/// let mut filter = SoundFilter { text: Some("thnder".to_string()), ..Default::default() };
+/// assert!(vault.query_page(&filter, &PageRequest::default()).await?.sounds.is_empty());
+///
+/// filter.fuzziness = Some(Fuzziness::default());
+/// let page = vault.query_page(&filter, &PageRequest::default()).await?;
+/// assert_eq!(page.sounds.len(), 1);
+/// assert_eq!(page.sounds[0].metadata.name, "Thunder rumble.wav");
+/// assert!(page.matches[0].fuzzy);
+/// assert_eq!(page.matches[0].distance, 1);
+///
+/// // Tags count too
+/// filter.text = Some("storn".to_string());
+/// assert_eq!(vault.query_page(&filter, &PageRequest::default()).await?.sounds.len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fuzziness {
+    /// Most edits (insertions, deletions, substitutions) between a word of
+    /// the text and a word of a sound's name or tags
+    pub max_distance: u32,
+
+    /// Near matches are only added when fewer sounds than this match exactly
+    pub min_results: u64,
+}
+
+impl Default for Fuzziness {
+    fn default() -> Self {
+        Self {
+            max_distance: 2,
+            min_results: 10,
+        }
+    }
+}
+
+/// How a sound of a [`SoundPage`](crate::SoundPage) matched its filter
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{PageRequest, SnippetOptions, SoundFilter, SoundMetadata, SoundVault, VaultConfig};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+/// let path = dir.path().join("porch.wav");
+/// std::fs::write(&path, "porch")?;
+/// let description = "Recorded on the porch at dusk. Steady rain on the gutters, a dog barks twice, then the rain eases off";
+/// let metadata = SoundMetadata { name: "Porch".to_string(), description: description.to_string(), ..Default::default() };
+/// vault.import_file(&path, Some(metadata)).await?;
+///
+/// let filter = SoundFilter { text: Some("rain".to_string()), ..Default::default() };
+/// let options = SnippetOptions { context: 24, start_marker: "**".into(), end_marker: "**".into(), ..Default::default() };
+/// let page = PageRequest { snippets: Some(options), ..Default::default() };
+/// let page = vault.query_page(&filter, &page).await?;
+/// assert_eq!(page.matches[0].snippet.as_deref(), Some("…porch at dusk. Steady **rain** on the gutters, a dog…"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchMatch {
+    /// The sound is a near match of the text, added by [`Fuzziness`]
+    pub fuzzy: bool,
+
+    /// Edits between the words of the text and those of the sound, summed
+    /// over the words; 0 for exact matches
+    pub distance: u32,
+
+    /// Excerpt of the description around the text, with the matches marked,
+    /// when asked for with [`PageRequest::snippets`](crate::PageRequest::snippets)
+    /// and the description contains the text
+    #[serde(default)]
+    pub snippet: Option<String>,
+}
+
+/// Lowercase words of a text
+pub(crate) fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Trigrams of a word, padded so its start and end weigh more
+fn trigrams(word: &str) -> BTreeSet<String> {
+    let chars: Vec<char> = format!("  {} ", word).chars().collect();
+    chars.windows(3).map(|window| window.iter().collect()).collect()
+}
+
+/// Number of single-character edits turning `a` into `b`
+pub(crate) fn levenshtein(a: &str, b: &str) -> u32 {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<u32> = (0..=b.len() as u32).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i as u32 + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + u32::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A sound nearly matching a text search
+pub(crate) struct NearMatch {
+    pub(crate) id: String,
+    /// Edit distance to the words of the text
+    pub(crate) distance: u32,
+    /// Sort key of the sound's name
+    pub(crate) sort_key: Option<Vec<u8>>,
+}
+
+impl LocalLibrary {
+    /// Index the words of a sound's name and tags
+    pub(crate) async fn save_search_words(
+        &self,
+        conn: &mut SqliteConnection,
+        id: &str,
+        name: &str,
+        tags: &[String],
+    ) -> Result<()> {
+        sqlx::query(&self.sql("DELETE FROM sound_trigrams WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+        let mut indexed = words(name);
+        for tag in tags {
+            indexed.extend(words(tag));
+        }
+        for word in indexed {
+            for trigram in trigrams(&word) {
+                sqlx::query(&self.sql("INSERT INTO sound_trigrams (trigram, sound_id, word) VALUES (?, ?, ?)"))
+                    .bind(trigram)
+                    .bind(id)
+                    .bind(&word)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Index the words of every sound, once, for vaults created before the
+    /// index existed
+    pub(crate) async fn index_search_words(&self) -> Result<()> {
+        let indexed: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(WORDS_INDEXED)
+            .fetch_optional(&self.reader)
+            .await?;
+        if indexed.is_some() {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+        let rows: Vec<(String, String, Option<String>)> = sqlx::query_as(&self.sql("SELECT id, name, tags FROM sounds"))
+            .fetch_all(&mut *tx)
+            .await?;
+        for (id, name, tags) in rows {
+            let tags: Vec<String> = tags.and_then(|tags| serde_json::from_str(&tags).ok()).unwrap_or_default();
+            self.save_search_words(&mut tx, &id, &name, &tags).await?;
+        }
+        sqlx::query(&self.sql("INSERT INTO vault_info (key, value) VALUES (?, '1')"))
+            .bind(WORDS_INDEXED)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Sounds whose name or tags nearly match every word of `text` and that
+    /// match the rest of the filter, closest first, then by name
+    ///
+    /// # Returns
+    ///
+    /// IDs, distances and sort keys, leaving out the sounds in `exclude`
+    pub(crate) async fn near_matches(
+        &self,
+        filter: &SoundFilter,
+        text: &str,
+        fuzziness: Fuzziness,
+        exclude: &HashSet<String>,
+    ) -> Result<Vec<NearMatch>> {
+        // Distance of each sound to the words of the text matched so far
+        let mut distances: Option<HashMap<String, u32>> = None;
+        for word in words(text) {
+            let mut builder = QueryBuilder::new(self.sql("SELECT DISTINCT sound_id, word FROM sound_trigrams WHERE trigram IN ("));
+            let mut separated = builder.separated(", ");
+            for trigram in trigrams(&word) {
+                separated.push_bind(trigram);
+            }
+            builder.push(")");
+            let candidates: Vec<(String, String)> = builder.build_query_as().fetch_all(&self.reader).await?;
+
+            let mut best: HashMap<String, u32> = HashMap::new();
+            for (id, candidate) in candidates {
+                let distance = levenshtein(&word, &candidate);
+                if distance <= fuzziness.max_distance {
+                    best.entry(id).and_modify(|d| *d = (*d).min(distance)).or_insert(distance);
+                }
+            }
+            distances = Some(match distances {
+                None => best,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter_map(|(id, distance)| best.get(&id).map(|d| (id, distance + d)))
+                    .collect(),
+            });
+        }
+
+        let mut candidates: Vec<(String, u32)> =
+            distances.unwrap_or_default().into_iter().filter(|(id, _)| !exclude.contains(id)).collect();
+        candidates.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        candidates.truncate(CANDIDATE_LIMIT);
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // The other conditions of the filter still apply
+        let rest = SoundFilter {
+            text: None,
+            fuzziness: None,
+            ..filter.clone()
+        };
+        let mut builder = QueryBuilder::<Sqlite>::new(self.sql("SELECT id, sort_key FROM sounds"));
+        rest.push_where(&mut builder, &self.tables);
+        builder.push(" AND id IN (");
+        let mut separated = builder.separated(", ");
+        for (id, _) in &candidates {
+            separated.push_bind(id.clone());
+        }
+        builder.push(") ORDER BY sort_key, id");
+        let ordered: Vec<(String, Option<Vec<u8>>)> = builder.build_query_as().fetch_all(&self.reader).await?;
+
+        let distances: HashMap<String, u32> = candidates.into_iter().collect();
+        let mut matches: Vec<NearMatch> = ordered
+            .into_iter()
+            .filter_map(|(id, sort_key)| {
+                distances.get(&id).map(|&distance| NearMatch { id, distance, sort_key })
+            })
+            .collect();
+        matches.sort_by_key(|near| near.distance);
+
+        Ok(matches)
+    }
+}
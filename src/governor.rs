@@ -0,0 +1,207 @@
+//! Limits shared by everything the vault runs at once
+//!
+//! Downloads, imports, analysis and background tasks take permits from one
+//! [`ResourceGovernor`] rather than each running as much as it likes, so a
+//! pack download, a directory import and waveform rendering together don't
+//! fight over the network, the CPU and the database writer. Limits can be
+//! lowered at runtime, e.g. for a low priority background mode; work
+//! already running finishes, and new work waits for the lower limit.
+
+use crate::config::VaultConfig;
+use crate::error::{Result, VaultError};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Network transfers run at once when the configuration doesn't say
+const DEFAULT_TRANSFERS: usize = 4;
+
+/// Rows written per batch by background tasks when the configuration
+/// doesn't say
+const DEFAULT_WRITE_BATCH: usize = 16;
+
+/// How much of each resource the vault may use at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Downloads from remote sources run at once
+    pub transfers: usize,
+
+    /// CPU-heavy jobs, such as hashing, decoding and rendering, run at once
+    pub decodes: usize,
+
+    /// Rows background tasks, such as the scrubber, write per batch
+    pub write_batch: usize,
+}
+
+impl ResourceLimits {
+    /// Limits from the configuration, with defaults for those it leaves out
+    pub fn from_config(config: &VaultConfig) -> Self {
+        let decodes = config.max_background_jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism().map(|n| n.get() / 2).unwrap_or(1)
+        });
+        Self {
+            transfers: config.max_transfers.unwrap_or(DEFAULT_TRANSFERS).max(1),
+            decodes: decodes.max(1),
+            write_batch: config.write_batch_size.unwrap_or(DEFAULT_WRITE_BATCH).max(1),
+        }
+    }
+}
+
+/// Resources in use, from [`SoundVault::health`](crate::SoundVault::health)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Downloads running now
+    pub transfers: usize,
+
+    /// CPU-heavy jobs running now
+    pub decodes: usize,
+
+    /// Limits in force
+    pub limits: ResourceLimits,
+}
+
+/// Counted permits whose number can change while they're held
+pub(crate) struct Limiter {
+    state: Mutex<LimiterState>,
+    /// Notified when a permit is released or the limit raised
+    freed: Notify,
+}
+
+struct LimiterState {
+    limit: usize,
+    in_use: usize,
+    closed: bool,
+}
+
+/// Held while using a resource; released when dropped
+pub(crate) struct Permit(Arc<Limiter>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.lock().in_use -= 1;
+        self.0.freed.notify_waiters();
+    }
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(LimiterState {
+                limit: limit.max(1),
+                in_use: 0,
+                closed: false,
+            }),
+            freed: Notify::new(),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LimiterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait for a permit; `None` once the limiter is closed
+    pub(crate) async fn acquire(self: &Arc<Self>) -> Option<Permit> {
+        loop {
+            let freed = self.freed.notified();
+            {
+                let mut state = self.lock();
+                if state.closed {
+                    return None;
+                }
+                if state.in_use < state.limit {
+                    state.in_use += 1;
+                    return Some(Permit(self.clone()));
+                }
+            }
+            freed.await;
+        }
+    }
+
+    /// Permits held now
+    pub(crate) fn in_use(&self) -> usize {
+        self.lock().in_use
+    }
+
+    /// Change the number of permits; holders above a lowered limit keep
+    /// theirs until they're done
+    fn set_limit(&self, limit: usize) {
+        self.lock().limit = limit.max(1);
+        self.freed.notify_waiters();
+    }
+
+    /// Refuse permits from now on, failing those waited for
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.freed.notify_waiters();
+    }
+}
+
+/// Hands out permits for network transfers and CPU-heavy jobs, and sizes
+/// background write batches
+pub(crate) struct ResourceGovernor {
+    transfers: Arc<Limiter>,
+    pub(crate) decodes: Arc<Limiter>,
+    limits: Mutex<ResourceLimits>,
+}
+
+impl ResourceGovernor {
+    pub(crate) fn new(limits: ResourceLimits) -> Self {
+        Self {
+            transfers: Limiter::new(limits.transfers),
+            decodes: Limiter::new(limits.decodes),
+            limits: Mutex::new(limits),
+        }
+    }
+
+    /// Wait for a network transfer to be allowed
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the vault is shutting down
+    pub(crate) async fn transfer(&self) -> Result<Permit> {
+        self.transfers
+            .acquire()
+            .await
+            .ok_or_else(|| VaultError::InvalidOperation("Vault is shutting down".to_string()))
+    }
+
+    /// Rows background tasks write per batch
+    pub(crate) fn write_batch(&self) -> usize {
+        self.limits().write_batch
+    }
+
+    /// Limits in force
+    pub(crate) fn limits(&self) -> ResourceLimits {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Change the limits
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if a limit is zero
+    pub(crate) fn set_limits(&self, limits: ResourceLimits) -> Result<()> {
+        if limits.transfers == 0 || limits.decodes == 0 || limits.write_batch == 0 {
+            return Err(VaultError::InvalidOperation(format!("Resource limits must be at least 1: {:?}", limits)));
+        }
+
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+        self.transfers.set_limit(limits.transfers);
+        self.decodes.set_limit(limits.decodes);
+        Ok(())
+    }
+
+    /// Resources in use now
+    pub(crate) fn usage(&self) -> ResourceUsage {
+        ResourceUsage {
+            transfers: self.transfers.in_use(),
+            decodes: self.decodes.in_use(),
+            limits: self.limits(),
+        }
+    }
+
+    /// Refuse new transfers
+    pub(crate) fn shutdown(&self) {
+        self.transfers.close();
+    }
+}
@@ -0,0 +1,303 @@
+//! Groups of variations of one sound: round-robins, loop sets and layers
+//!
+//! Groups are independent of collections; a sound can belong to any number
+//! of both. Members are ordered, by the take number of their file for groups
+//! made while importing.
+
+use crate::cursor::shuffle_key;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::Sound;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// How the members of a group are played
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupKind {
+    /// Interchangeable variations, one played at a time, e.g. footsteps
+    #[default]
+    RoundRobin,
+    /// Segments played one after the other, e.g. the intro, loop and tail
+    /// of an engine
+    LoopSet,
+    /// Parts played together, e.g. the mechanics and tail of a gunshot
+    LayerSet,
+}
+
+impl GroupKind {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "round_robin",
+            Self::LoopSet => "loop_set",
+            Self::LayerSet => "layer_set",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown group kind: {}", name)))
+    }
+}
+
+/// A group of sounds and its members
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SoundGroup {
+    /// Unique identifier of the group
+    pub id: String,
+
+    /// Name of the group, e.g. `step_grass`
+    pub name: String,
+
+    /// How its members are played
+    pub kind: GroupKind,
+
+    /// IDs of its members, in order
+    pub sound_ids: Vec<String>,
+}
+
+/// Picks members of a group at random, from
+/// [`SoundVault::group_picker`](crate::SoundVault::group_picker)
+///
+/// Every member is picked once before any is picked again, and the same
+/// member never comes twice in a row. The same seed replays the same picks.
+#[derive(Debug, Clone)]
+pub struct GroupPicker {
+    sound_ids: Vec<String>,
+    seed: u64,
+    round: u64,
+    /// Members left in the current round, the next one last
+    bag: Vec<String>,
+    last: Option<String>,
+}
+
+impl GroupPicker {
+    fn new(sound_ids: Vec<String>, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        Self {
+            sound_ids,
+            seed,
+            round: 0,
+            bag: Vec::new(),
+            last: None,
+        }
+    }
+
+    /// Seed of the picks
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Pick the ID of the next member to play; `None` if the group is empty
+    pub fn pick(&mut self) -> Option<&str> {
+        if self.bag.is_empty() {
+            if self.sound_ids.is_empty() {
+                return None;
+            }
+            self.round += 1;
+            let seed = self.seed ^ self.round.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            let mut bag = self.sound_ids.clone();
+            bag.sort_by(|a, b| shuffle_key(seed, b, None).total_cmp(&shuffle_key(seed, a, None)));
+            // Don't start a round with the member that ended the last one
+            if bag.len() > 1 && bag.last() == self.last.as_ref() {
+                let end = bag.len() - 1;
+                bag.swap(0, end);
+            }
+            self.bag = bag;
+        }
+
+        self.last = self.bag.pop();
+        self.last.as_deref()
+    }
+}
+
+/// Base name and take number of a numbered variation, e.g. `step_grass`
+/// and 3 for `step_grass_03.wav`
+pub(crate) fn variation_of(path: &Path) -> Option<(String, u32)> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 || digits > 3 {
+        return None;
+    }
+    let (base, number) = stem.split_at(stem.len() - digits);
+    let base = base.strip_suffix(['_', '-', ' ', '.'])?;
+    if base.is_empty() {
+        return None;
+    }
+    Some((base.to_string(), number.parse().ok()?))
+}
+
+impl LocalLibrary {
+    /// Create a group of sounds, in the order given
+    ///
+    /// # Returns
+    ///
+    /// The ID of the group
+    pub async fn create_group(&self, name: &str, kind: GroupKind, sound_ids: &[String]) -> Result<String> {
+        for sound_id in sound_ids {
+            self.get_sound(sound_id).await?;
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let mut tx = self.db.begin().await?;
+        self.insert_group(&mut tx, &id, name, kind).await?;
+        for (position, sound_id) in sound_ids.iter().enumerate() {
+            self.add_group_member(&mut tx, &id, sound_id, position as i64).await?;
+        }
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    async fn insert_group(&self, conn: &mut SqliteConnection, id: &str, name: &str, kind: GroupKind) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+                INSERT INTO sound_groups (id, name, kind) VALUES (?, ?, ?)
+                ON CONFLICT(id) DO UPDATE SET name = excluded.name, kind = excluded.kind
+            "#),
+        )
+        .bind(id)
+        .bind(name)
+        .bind(kind.as_str())
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn add_group_member(&self, conn: &mut SqliteConnection, group_id: &str, sound_id: &str, position: i64) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+                INSERT INTO sound_group_members (group_id, sound_id, position) VALUES (?, ?, ?)
+                ON CONFLICT(group_id, sound_id) DO UPDATE SET position = excluded.position
+            "#),
+        )
+        .bind(group_id)
+        .bind(sound_id)
+        .bind(position)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get a group with its members
+    pub async fn get_group(&self, id: &str) -> Result<SoundGroup> {
+        let (name, kind): (String, String) = sqlx::query_as(&self.sql("SELECT name, kind FROM sound_groups WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(&self.reader)
+            .await?
+            .ok_or_else(|| VaultError::NotFound(format!("Group not found: {}", id)))?;
+        let sound_ids: Vec<String> = sqlx::query_scalar(
+            &self.sql("SELECT sound_id FROM sound_group_members WHERE group_id = ? ORDER BY position, sound_id"),
+        )
+        .bind(id)
+        .fetch_all(&self.reader)
+        .await?;
+
+        Ok(SoundGroup {
+            id: id.to_string(),
+            name,
+            kind: GroupKind::parse(&kind)?,
+            sound_ids,
+        })
+    }
+
+    /// List every group with its members, by name
+    pub async fn list_groups(&self) -> Result<Vec<SoundGroup>> {
+        let ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sound_groups ORDER BY name, id"))
+            .fetch_all(&self.reader)
+            .await?;
+
+        let mut groups = Vec::with_capacity(ids.len());
+        for id in ids {
+            groups.push(self.get_group(&id).await?);
+        }
+        Ok(groups)
+    }
+
+    /// Get the sounds of a group, in order
+    pub async fn get_group_members(&self, id: &str) -> Result<Vec<Sound>> {
+        let mut sounds = Vec::new();
+        for sound_id in self.get_group(id).await?.sound_ids {
+            sounds.push(self.get_sound(&sound_id).await?);
+        }
+        Ok(sounds)
+    }
+
+    /// Delete a group; its sounds are kept
+    pub async fn delete_group(&self, id: &str) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("DELETE FROM sound_group_members WHERE group_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = sqlx::query(&self.sql("DELETE FROM sound_groups WHERE id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(VaultError::NotFound(format!("Group not found: {}", id)));
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Pick the members of a group at random, without repeats
+    pub async fn group_picker(&self, id: &str, seed: Option<u64>) -> Result<GroupPicker> {
+        Ok(GroupPicker::new(self.get_group(id).await?.sound_ids, seed))
+    }
+
+    /// Add an imported sound to the group of its numbered variations, named
+    /// after the base name of its file, creating the group if needed
+    pub(crate) async fn group_variation(&self, sound_id: &str, source_path: &Path, kind: GroupKind) -> Result<()> {
+        let Some((name, take)) = variation_of(source_path) else {
+            return Ok(());
+        };
+
+        let mut tx = self.db.begin().await?;
+        let existing: Option<String> =
+            sqlx::query_scalar(&self.sql("SELECT id FROM sound_groups WHERE name = ? AND kind = ? ORDER BY id LIMIT 1"))
+                .bind(&name)
+                .bind(kind.as_str())
+                .fetch_optional(&mut *tx)
+                .await?;
+        let group_id = match existing {
+            Some(id) => id,
+            None => {
+                let id = Uuid::new_v4().to_string();
+                self.insert_group(&mut tx, &id, &name, kind).await?;
+                id
+            }
+        };
+        self.add_group_member(&mut tx, &group_id, sound_id, take as i64).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Write a dumped group, replacing the members of one with the same ID
+    pub(crate) async fn load_group(&self, conn: &mut SqliteConnection, group: &SoundGroup) -> Result<()> {
+        self.insert_group(conn, &group.id, &group.name, group.kind).await?;
+        sqlx::query(&self.sql("DELETE FROM sound_group_members WHERE group_id = ?"))
+            .bind(&group.id)
+            .execute(&mut *conn)
+            .await?;
+        for (position, sound_id) in group.sound_ids.iter().enumerate() {
+            self.add_group_member(conn, &group.id, sound_id, position as i64).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Summary of the state of a vault
+
+use crate::cache::SoundCacheStats;
+use crate::error::Result;
+use crate::expiration::Expiration;
+use crate::governor::ResourceUsage;
+use crate::local::LocalLibrary;
+use crate::query::SoundFilter;
+use crate::quota::QuotaUsage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+/// Optional capabilities compiled into this build
+const FEATURES: &[&str] = &[
+    "freesound",
+    #[cfg(feature = "analysis")]
+    "analysis",
+    #[cfg(feature = "rodio")]
+    "rodio",
+    #[cfg(feature = "s3")]
+    "s3",
+    #[cfg(feature = "server")]
+    "server",
+];
+
+/// State of a vault and of the library build, e.g. for bug reports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// Version of the SoundVault library
+    pub version: String,
+
+    /// Version of the database schema
+    pub schema_version: i64,
+
+    /// Optional capabilities compiled in
+    pub features: Vec<String>,
+
+    /// Number of sounds
+    pub sounds: u64,
+
+    /// Number of collections
+    pub collections: u64,
+
+    /// Background jobs waiting to run
+    pub pending_jobs: u64,
+
+    /// When the database backup was last written
+    pub last_backup: Option<DateTime<Utc>>,
+
+    /// Whether a Freesound API key is configured
+    pub remote_configured: bool,
+
+    /// Whether Freesound answered a probe; `None` when not probed
+    pub remote_reachable: Option<bool>,
+
+    /// When the last integrity scan ran
+    pub integrity_checked_at: Option<DateTime<Utc>>,
+
+    /// Problems found by the last integrity scan
+    pub integrity_warnings: Vec<String>,
+
+    /// Sounds whose file no longer matched its hash when last scrubbed
+    pub corrupt_sounds: u64,
+
+    /// Sounds locked against edits
+    pub locked_sounds: u64,
+
+    /// Sounds whose expiration date has passed but that are still in the
+    /// vault, flagged or not
+    pub expired_sounds: u64,
+
+    /// Sounds expiring within the next 30 days, the earliest first
+    pub upcoming_expirations: Vec<Expiration>,
+
+    /// Space taken by the library against its limit
+    pub quota: QuotaUsage,
+
+    /// Metadata values over their soft limit, one line each; see
+    /// [`SoundVault::set_soft_limit`](crate::SoundVault::set_soft_limit)
+    pub soft_limit_warnings: Vec<String>,
+
+    /// Transfers and jobs running, and the limits in force
+    pub resources: ResourceUsage,
+
+    /// Connections of the pool writes go through
+    pub write_pool: PoolStats,
+
+    /// Connections of the read-only pool queries go through; the same as
+    /// `write_pool` when the database is shared with the host application
+    pub read_pool: PoolStats,
+
+    /// Hits and misses of the cache of sounds fetched by ID
+    pub sound_cache: SoundCacheStats,
+}
+
+/// Connections of a database pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStats {
+    /// Connections open
+    pub connections: u32,
+
+    /// Connections open and not in use
+    pub idle: u32,
+
+    /// Most connections the pool opens
+    pub max_connections: u32,
+}
+
+impl PoolStats {
+    fn of(pool: &Pool<Sqlite>) -> Self {
+        Self {
+            connections: pool.size(),
+            idle: pool.num_idle() as u32,
+            max_connections: pool.options().get_max_connections(),
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Report the state of the local library, leaving remote fields unset
+    pub async fn health(&self) -> Result<HealthReport> {
+        let schema_version = Self::schema_version(&self.reader, &self.tables).await?;
+        let sounds = self.count(&SoundFilter::default()).await?;
+        let collections: i64 = sqlx::query_scalar(&self.sql("SELECT COUNT(*) FROM collections")).fetch_one(&self.reader).await?;
+        let corrupt_sounds: i64 = sqlx::query_scalar(&self.sql("SELECT COUNT(*) FROM sounds WHERE corrupt"))
+            .fetch_one(&self.reader)
+            .await?;
+        let locked_sounds: i64 = sqlx::query_scalar(&self.sql("SELECT COUNT(*) FROM sounds WHERE locked"))
+            .fetch_one(&self.reader)
+            .await?;
+
+        let (expired_sounds, upcoming_expirations) = self.expiration_notice().await?;
+
+        let mut backup = self.database_path.clone().into_os_string();
+        backup.push(".bak");
+        let last_backup = std::fs::metadata(backup)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+
+        let (integrity_checked_at, integrity_warnings) = match self.last_integrity_scan().await? {
+            Some((checked_at, report)) => (Some(checked_at), report.warnings()),
+            None => (None, Vec::new()),
+        };
+
+        Ok(HealthReport {
+            version: crate::VERSION.to_string(),
+            schema_version,
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+            sounds,
+            collections: collections as u64,
+            pending_jobs: self.jobs.pending() as u64,
+            last_backup,
+            remote_configured: false,
+            remote_reachable: None,
+            integrity_checked_at,
+            integrity_warnings,
+            corrupt_sounds: corrupt_sounds as u64,
+            locked_sounds: locked_sounds as u64,
+            expired_sounds,
+            upcoming_expirations,
+            quota: self.quota_usage().await?,
+            soft_limit_warnings: self.soft_limit_warnings().await?,
+            resources: self.governor.usage(),
+            write_pool: PoolStats::of(&self.db),
+            read_pool: PoolStats::of(&self.reader),
+            sound_cache: self.cache_stats(),
+        })
+    }
+}
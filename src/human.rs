@@ -0,0 +1,504 @@
+//! Sizes and durations as people write them, for configuration
+//!
+//! Both types read a string with a unit, like `"500MB"` or `"2h"`, or a bare
+//! number as older configurations stored them, and are written back in
+//! canonical form, the way [`Display`](fmt::Display) formats them.
+
+use crate::error::{Result, VaultError};
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Units of sizes, largest first, as written in canonical form
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("EiB", 1 << 60),
+    ("EB", 1_000_000_000_000_000_000),
+    ("PiB", 1 << 50),
+    ("PB", 1_000_000_000_000_000),
+    ("TiB", 1 << 40),
+    ("TB", 1_000_000_000_000),
+    ("GiB", 1 << 30),
+    ("GB", 1_000_000_000),
+    ("MiB", 1 << 20),
+    ("MB", 1_000_000),
+    ("KiB", 1 << 10),
+    ("KB", 1_000),
+];
+
+/// Units of durations in nanoseconds, largest first: the name written in
+/// canonical form, then the other names read
+const DURATION_UNITS: &[(&[&str], u64)] = &[
+    (&["w", "week", "weeks"], 7 * 86_400 * NANOS_PER_SEC),
+    (&["d", "day", "days"], 86_400 * NANOS_PER_SEC),
+    (&["h", "hr", "hrs", "hour", "hours"], 3_600 * NANOS_PER_SEC),
+    (&["m", "min", "mins", "minute", "minutes"], 60 * NANOS_PER_SEC),
+    (&["s", "sec", "secs", "second", "seconds"], NANOS_PER_SEC),
+    (&["ms", "msec", "msecs", "millisecond", "milliseconds"], 1_000_000),
+    (&["us", "µs", "usec", "usecs", "microsecond", "microseconds"], 1_000),
+    (&["ns", "nsec", "nsecs", "nanosecond", "nanoseconds"], 1),
+];
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A number of bytes, written like `"500MB"` or `"1.5GiB"`
+///
+/// `KB`, `MB`, `GB`, `TB`, `PB` and `EB` are powers of 1000; `KiB`, `MiB`
+/// and so on powers of 1024. Units ignore case, and a single letter (`k`,
+/// `M`, `G`…) is the power of 1000. A bare number, in a string or not, is
+/// bytes. The size must come to a whole number of bytes.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::ByteSize;
+///
+/// assert_eq!("500MB".parse::<ByteSize>().unwrap().as_u64(), 500_000_000);
+/// assert_eq!("1.5 GiB".parse::<ByteSize>().unwrap(), ByteSize::mib(1536));
+/// assert_eq!("64k".parse::<ByteSize>().unwrap().as_u64(), 64_000);
+/// assert_eq!("4096".parse::<ByteSize>().unwrap().as_u64(), 4096);
+///
+/// // Canonical form: the largest unit the size is a whole number of
+/// assert_eq!(ByteSize::new(524_288_000).to_string(), "500MiB");
+/// assert_eq!(ByteSize::new(1_500_000_000).to_string(), "1500MB");
+/// assert_eq!(ByteSize::new(1023).to_string(), "1023B");
+///
+/// for bad in ["", "MB", "-1MB", "1.5B", "1.2.3MB", "5 XB", "1e3", "20000EB", "1 000"] {
+///     assert!(bad.parse::<ByteSize>().is_err(), "{:?} should be rejected", bad);
+/// }
+///
+/// // Numbers still read as bytes
+/// let size: ByteSize = serde_json::from_str("10000")?;
+/// assert_eq!(serde_json::to_string(&size)?, r#""10KB""#);
+/// # Ok::<(), serde_json::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    /// A size of `bytes` bytes
+    pub const fn new(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    /// A size of `kb` kilobytes, 1000 bytes each
+    pub const fn kb(kb: u64) -> Self {
+        Self(kb * 1_000)
+    }
+
+    /// A size of `mb` megabytes, 1000² bytes each
+    pub const fn mb(mb: u64) -> Self {
+        Self(mb * 1_000_000)
+    }
+
+    /// A size of `gb` gigabytes, 1000³ bytes each
+    pub const fn gb(gb: u64) -> Self {
+        Self(gb * 1_000_000_000)
+    }
+
+    /// A size of `kib` kibibytes, 1024 bytes each
+    pub const fn kib(kib: u64) -> Self {
+        Self(kib << 10)
+    }
+
+    /// A size of `mib` mebibytes, 1024² bytes each
+    pub const fn mib(mib: u64) -> Self {
+        Self(mib << 20)
+    }
+
+    /// A size of `gib` gibibytes, 1024³ bytes each
+    pub const fn gib(gib: u64) -> Self {
+        Self(gib << 30)
+    }
+
+    /// The number of bytes
+    pub const fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> Self {
+        size.0
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SIZE_UNITS.iter().find(|(_, bytes)| self.0 != 0 && self.0.is_multiple_of(*bytes)) {
+            Some((unit, bytes)) => write!(f, "{}{}", self.0 / bytes, unit),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = VaultError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        Self::parse(text).map_err(VaultError::Config)
+    }
+}
+
+impl ByteSize {
+    /// Parse the text of a size, or tell what's wrong with it
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let invalid = |reason: &str| format!("Invalid size {:?}: {}", text, reason);
+
+        let trimmed = text.trim();
+        let number_len = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(trimmed.len());
+        let (number, unit) = (&trimmed[..number_len], trimmed[number_len..].trim_start());
+        let multiplier = match unit.to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "m" | "g" | "t" | "p" | "e" => SIZE_UNITS
+                .iter()
+                .find(|(name, _)| name.len() == 2 && name[..1].eq_ignore_ascii_case(unit))
+                .map_or(1, |(_, bytes)| *bytes),
+            lower => SIZE_UNITS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(lower))
+                .map(|(_, bytes)| *bytes)
+                .ok_or_else(|| invalid(&format!("unknown unit {:?}", unit)))?,
+        };
+
+        let bytes = scale(number, multiplier).ok_or_else(|| invalid("expected a number and a unit, like 500MB"))?;
+        if bytes.fract != 0 {
+            return Err(invalid("not a whole number of bytes"));
+        }
+        u64::try_from(bytes.whole).map(Self).map_err(|_| invalid("too large"))
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct SizeVisitor;
+
+        impl Visitor<'_> for SizeVisitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a size like \"500MB\", or a number of bytes")
+            }
+
+            fn visit_u64<E: de::Error>(self, bytes: u64) -> std::result::Result<ByteSize, E> {
+                Ok(ByteSize(bytes))
+            }
+
+            fn visit_i64<E: de::Error>(self, bytes: i64) -> std::result::Result<ByteSize, E> {
+                u64::try_from(bytes)
+                    .map(ByteSize)
+                    .map_err(|_| E::custom(format!("Invalid size {}: negative", bytes)))
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> std::result::Result<ByteSize, E> {
+                ByteSize::parse(text).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+/// A span of time, written like `"30s"`, `"2h"` or `"1h 30m"`
+///
+/// Units go from weeks (`w`) through days (`d`), hours (`h`), minutes
+/// (`m`), seconds (`s`) down to `ms`, `us` and `ns`, largest first and
+/// each at most once; their full names (`days`, `minutes`…) work too.
+/// Numbers may have decimals. A bare number, in a string or not, is
+/// seconds, and so is the `{ "secs", "nanos" }` form configurations
+/// used to store.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::HumanDuration;
+/// use std::time::Duration;
+///
+/// assert_eq!("2h".parse::<HumanDuration>().unwrap().as_duration(), Duration::from_secs(7200));
+/// assert_eq!("1h 30m".parse::<HumanDuration>().unwrap(), HumanDuration::from_mins(90));
+/// assert_eq!("1.5d".parse::<HumanDuration>().unwrap(), HumanDuration::from_hours(36));
+/// assert_eq!("250ms".parse::<HumanDuration>().unwrap().as_duration(), Duration::from_millis(250));
+/// assert_eq!("45".parse::<HumanDuration>().unwrap(), HumanDuration::from_secs(45));
+///
+/// // Canonical form: days down to nanoseconds, leaving out what's zero
+/// assert_eq!(HumanDuration::from_secs(5400).to_string(), "1h30m");
+/// assert_eq!(HumanDuration::from_days(14).to_string(), "14d");
+/// assert_eq!(HumanDuration::from(Duration::from_millis(1500)).to_string(), "1s500ms");
+/// assert_eq!(HumanDuration::default().to_string(), "0s");
+///
+/// for bad in ["", "h", "-5s", "5x", "30s 1m", "1m 1m", "1.2.3s", "1e3s", "5 s s"] {
+///     assert!(bad.parse::<HumanDuration>().is_err(), "{:?} should be rejected", bad);
+/// }
+///
+/// // Older configurations still read
+/// let interval: HumanDuration = serde_json::from_str(r#"{"secs": 3600, "nanos": 0}"#)?;
+/// assert_eq!(serde_json::to_string(&interval)?, r#""1h""#);
+/// assert_eq!(serde_json::from_str::<HumanDuration>("90")?, HumanDuration::from_secs(90));
+/// # Ok::<(), serde_json::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HumanDuration(Duration);
+
+impl HumanDuration {
+    /// A duration of `secs` seconds
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    /// A duration of `mins` minutes
+    pub const fn from_mins(mins: u64) -> Self {
+        Self(Duration::from_secs(mins * 60))
+    }
+
+    /// A duration of `hours` hours
+    pub const fn from_hours(hours: u64) -> Self {
+        Self(Duration::from_secs(hours * 3_600))
+    }
+
+    /// A duration of `days` days
+    pub const fn from_days(days: u64) -> Self {
+        Self(Duration::from_secs(days * 86_400))
+    }
+
+    /// The duration
+    pub const fn as_duration(&self) -> Duration {
+        self.0
+    }
+
+    /// Whole seconds of the duration
+    pub const fn as_secs(&self) -> u64 {
+        self.0.as_secs()
+    }
+
+    /// Whether the duration is zero
+    pub const fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// A duration of `number` times `unit` nanoseconds
+    fn from_number(number: &str, unit: u64) -> Option<Self> {
+        let nanos = scale(number, unit)?.whole;
+        let secs = u64::try_from(nanos / u128::from(NANOS_PER_SEC)).ok()?;
+        Some(Self(Duration::new(secs, (nanos % u128::from(NANOS_PER_SEC)) as u32)))
+    }
+
+    /// Read a duration, counting bare numbers in `unit` seconds
+    fn deserialize_in<'de, D: Deserializer<'de>>(deserializer: D, unit: u64) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(DurationVisitor { unit })
+    }
+}
+
+impl From<Duration> for HumanDuration {
+    fn from(duration: Duration) -> Self {
+        Self(duration)
+    }
+}
+
+impl From<HumanDuration> for Duration {
+    fn from(duration: HumanDuration) -> Self {
+        duration.0
+    }
+}
+
+impl From<HumanDuration> for chrono::Duration {
+    fn from(duration: HumanDuration) -> Self {
+        chrono::Duration::from_std(duration.0).unwrap_or(chrono::Duration::MAX)
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut nanos = self.0.as_nanos();
+        if nanos == 0 {
+            return f.write_str("0s");
+        }
+        // Weeks are read but not written: "14d" reads better than "2w"
+        for (names, unit) in &DURATION_UNITS[1..] {
+            let count = nanos / u128::from(*unit);
+            if count > 0 {
+                write!(f, "{}{}", count, names[0])?;
+                nanos %= u128::from(*unit);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = VaultError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        Self::parse(text).map_err(VaultError::Config)
+    }
+}
+
+impl HumanDuration {
+    /// Parse the text of a duration, or tell what's wrong with it
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let invalid = |reason: &str| format!("Invalid duration {:?}: {}", text, reason);
+
+        let mut rest = text.trim();
+        if rest.is_empty() {
+            return Err(invalid("expected a number and a unit, like 30s or 2h"));
+        }
+        if rest.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+            return Self::from_number(rest, NANOS_PER_SEC).ok_or_else(|| invalid("not a number of seconds"));
+        }
+
+        let mut total = Duration::ZERO;
+        let mut next_unit = 0;
+        while !rest.is_empty() {
+            let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+            let number = &rest[..number_len];
+            rest = rest[number_len..].trim_start();
+            let unit_len = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+            let unit = &rest[..unit_len];
+            rest = rest[unit_len..].trim_start();
+            if unit.is_empty() {
+                return Err(invalid("expected a number and a unit, like 30s or 2h"));
+            }
+
+            let lower = unit.to_lowercase();
+            let index = DURATION_UNITS
+                .iter()
+                .position(|(names, _)| names.contains(&lower.as_str()))
+                .ok_or_else(|| invalid(&format!("unknown unit {:?}", unit)))?;
+            if index < next_unit {
+                return Err(invalid("units go from the largest to the smallest, each once"));
+            }
+            next_unit = index + 1;
+
+            let part = Self::from_number(number, DURATION_UNITS[index].1).ok_or_else(|| invalid("not a number"))?;
+            total = total.checked_add(part.0).ok_or_else(|| invalid("too long"))?;
+        }
+
+        Ok(Self(total))
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Self::deserialize_in(deserializer, 1)
+    }
+}
+
+/// Reads a [`HumanDuration`], counting bare numbers in `unit` seconds
+struct DurationVisitor {
+    unit: u64,
+}
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = HumanDuration;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.unit == 86_400 { "days" } else { "seconds" };
+        write!(f, "a duration like \"30s\" or \"2h\", or a number of {}", unit)
+    }
+
+    fn visit_u64<E: de::Error>(self, count: u64) -> std::result::Result<HumanDuration, E> {
+        count
+            .checked_mul(self.unit)
+            .map(HumanDuration::from_secs)
+            .ok_or_else(|| E::custom(format!("Invalid duration {}: too long", count)))
+    }
+
+    fn visit_i64<E: de::Error>(self, count: i64) -> std::result::Result<HumanDuration, E> {
+        match u64::try_from(count) {
+            Ok(count) => self.visit_u64(count),
+            Err(_) => Err(E::custom(format!("Invalid duration {}: negative", count))),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, count: f64) -> std::result::Result<HumanDuration, E> {
+        Duration::try_from_secs_f64(count * self.unit as f64)
+            .map(HumanDuration)
+            .map_err(|_| E::custom(format!("Invalid duration {}: negative or too long", count)))
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> std::result::Result<HumanDuration, E> {
+        if self.unit != 1 && text.trim().bytes().all(|b| b.is_ascii_digit()) && !text.trim().is_empty() {
+            return text.trim().parse::<u64>().map_err(E::custom).and_then(|count| self.visit_u64(count));
+        }
+        HumanDuration::parse(text).map_err(E::custom)
+    }
+
+    /// The `{ "secs", "nanos" }` form of [`Duration`]
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<HumanDuration, A::Error> {
+        let (mut secs, mut nanos) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "secs" => secs = Some(map.next_value::<u64>()?),
+                "nanos" => nanos = Some(map.next_value::<u32>()?),
+                _ => return Err(de::Error::unknown_field(&key, &["secs", "nanos"])),
+            }
+        }
+        let secs = secs.ok_or_else(|| de::Error::missing_field("secs"))?;
+        let nanos = nanos.unwrap_or(0);
+        if nanos >= NANOS_PER_SEC as u32 {
+            return Err(de::Error::custom(format!("Invalid duration: {} nanoseconds past a second", nanos)));
+        }
+        Ok(HumanDuration(Duration::new(secs, nanos)))
+    }
+}
+
+/// Deserialize an optional [`HumanDuration`] whose bare numbers count days,
+/// for fields that used to hold days
+pub(crate) fn deserialize_days<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<HumanDuration>, D::Error> {
+    #[derive(Deserialize)]
+    struct Days(#[serde(deserialize_with = "days")] HumanDuration);
+
+    fn days<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<HumanDuration, D::Error> {
+        HumanDuration::deserialize_in(deserializer, 86_400)
+    }
+
+    Ok(Option::<Days>::deserialize(deserializer)?.map(|days| days.0))
+}
+
+/// A decimal number times a multiplier
+struct Scaled {
+    /// The whole part
+    whole: u128,
+    /// What's left, in units of the number's last decimal place
+    fract: u128,
+}
+
+/// Multiply a decimal number without sign or exponent by `multiplier`,
+/// without rounding
+fn scale(number: &str, multiplier: u64) -> Option<Scaled> {
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    let valid = !(int.is_empty() && frac.is_empty())
+        && int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        && frac.len() <= 18;
+    if !valid {
+        return None;
+    }
+
+    let multiplier = u128::from(multiplier);
+    let int: u128 = if int.is_empty() { 0 } else { int.parse().ok()? };
+    let denominator = 10u128.pow(frac.len() as u32);
+    let frac: u128 = if frac.is_empty() { 0 } else { frac.parse().ok()? };
+    let scaled_frac = frac.checked_mul(multiplier)?;
+    Some(Scaled {
+        whole: int.checked_mul(multiplier)?.checked_add(scaled_frac / denominator)?,
+        fract: scaled_frac % denominator,
+    })
+}
@@ -0,0 +1,258 @@
+//! Imports with metadata filled in from named templates
+
+use crate::error::{Result, VaultError};
+use crate::audio::probe_file;
+use crate::groups::GroupKind;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{SoundMetadata, SoundSource};
+use crate::provenance::ProvenanceMode;
+use crate::review::SoundStatus;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Options of [`SoundVault::import_file_with_options`](crate::SoundVault::import_file_with_options)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Name of the import template filling in the metadata
+    pub template: Option<String>,
+
+    /// Add numbered variations, such as `step_grass_01.wav` to
+    /// `step_grass_08.wav`, to a group of this kind named after their base
+    /// name, ordered by their number; `None` groups nothing
+    pub group_variations: Option<GroupKind>,
+
+    /// Whether the file is copied into the library
+    pub mode: ImportMode,
+
+    /// Put the sound in the [`SoundStatus::Inbox`] for review instead of
+    /// the library, see [`SoundVault::approve`](crate::SoundVault::approve)
+    pub inbox: bool,
+}
+
+/// How an imported file is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Copy the file into the library
+    #[default]
+    Copy,
+    /// Record the file where it lies, as an external sound; it shows as
+    /// [`Availability::FileMissing`](crate::Availability::FileMissing) while
+    /// its drive is unplugged
+    Reference,
+}
+
+/// Metadata given to every file imported with a template
+///
+/// The description may contain `{filename}`, replaced by the name of the
+/// imported file without its extension, and `{folder}`, replaced by the name
+/// of the folder holding it.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{SoundMetadata, SoundMetadataTemplate};
+/// use std::path::Path;
+///
+/// let mut template = SoundMetadataTemplate::default();
+/// template.tags = vec!["footsteps".to_string()];
+/// template.description = "Footsteps on {folder}, take {filename}".to_string();
+/// template.license = Some("CC0".to_string());
+///
+/// let metadata = template.metadata_for(Path::new("/rec/gravel/03.wav"), None);
+/// assert_eq!(metadata.name, "03.wav");
+/// assert_eq!(metadata.description, "Footsteps on gravel, take 03");
+/// assert_eq!(metadata.license, "CC0");
+///
+/// // Explicit values win over the template's
+/// let mut explicit = SoundMetadata::default();
+/// explicit.license = "CC-BY".to_string();
+/// let metadata = template.metadata_for(Path::new("/rec/gravel/04.wav"), Some(explicit));
+/// assert_eq!((metadata.license.as_str(), metadata.tags.len()), ("CC-BY", 1));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundMetadataTemplate {
+    /// Tags of sounds imported without tags
+    pub tags: Vec<String>,
+
+    /// Description of sounds imported without one, with placeholders
+    pub description: String,
+
+    /// License of sounds imported without one
+    pub license: Option<String>,
+
+    /// Custom metadata values of keys the imported sound doesn't set
+    pub custom: HashMap<String, String>,
+}
+
+impl SoundMetadataTemplate {
+    /// Metadata of a file imported with the template
+    ///
+    /// Fields set in `explicit` are kept; the template fills in the others.
+    pub fn metadata_for(&self, path: &Path, explicit: Option<SoundMetadata>) -> SoundMetadata {
+        let mut metadata = explicit.unwrap_or_default();
+        if metadata.name.is_empty() {
+            metadata.name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        }
+        if metadata.tags.is_empty() {
+            metadata.tags = self.tags.clone();
+            metadata.normalize_tags();
+        }
+        if metadata.description.is_empty() {
+            metadata.description = expand_placeholders(&self.description, path);
+        }
+        if metadata.license.is_empty() {
+            metadata.license = self.license.clone().unwrap_or_else(|| "Unknown".to_string());
+        }
+        for (key, value) in &self.custom {
+            metadata.custom.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        metadata
+    }
+}
+
+/// Replace `{filename}` and `{folder}` with the names of `path` and its folder
+fn expand_placeholders(text: &str, path: &Path) -> String {
+    let name = |part: Option<&std::ffi::OsStr>| part.unwrap_or_default().to_string_lossy().to_string();
+    text.replace("{filename}", &name(path.file_stem()))
+        .replace("{folder}", &name(path.parent().and_then(Path::file_name)))
+}
+
+impl LocalLibrary {
+    /// Save an import template, replacing any with the same name
+    pub async fn save_import_template(&self, name: &str, template: &SoundMetadataTemplate) -> Result<()> {
+        if name.is_empty() {
+            return Err(VaultError::InvalidOperation("Import template name is empty".to_string()));
+        }
+
+        let mut conn = self.db.acquire().await?;
+        self.write_import_template(&mut conn, name, template).await
+    }
+
+    pub(crate) async fn write_import_template(
+        &self, conn: &mut SqliteConnection,
+        name: &str,
+        template: &SoundMetadataTemplate,
+    ) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO import_templates (name, template) VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET template = excluded.template
+            "#),
+        )
+        .bind(name)
+        .bind(serde_json::to_string(template)?)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete an import template
+    pub async fn remove_import_template(&self, name: &str) -> Result<()> {
+        let removed = sqlx::query(&self.sql("DELETE FROM import_templates WHERE name = ?"))
+            .bind(name)
+            .execute(&self.db)
+            .await?;
+        if removed.rows_affected() == 0 {
+            return Err(VaultError::NotFound(format!("Import template not found: {}", name)));
+        }
+
+        Ok(())
+    }
+
+    /// Import templates, by name
+    pub async fn list_import_templates(&self) -> Result<BTreeMap<String, SoundMetadataTemplate>> {
+        let rows = sqlx::query(&self.sql("SELECT name, template FROM import_templates"))
+            .fetch_all(&self.reader)
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let template: String = row.try_get("template")?;
+                Ok((row.try_get("name")?, serde_json::from_str(&template)?))
+            })
+            .collect()
+    }
+
+    /// Get an import template by name
+    pub(crate) async fn import_template(&self, name: &str) -> Result<SoundMetadataTemplate> {
+        let template: Option<String> = sqlx::query_scalar(&self.sql("SELECT template FROM import_templates WHERE name = ?"))
+            .bind(name)
+            .fetch_optional(&self.reader)
+            .await?;
+
+        match template {
+            Some(template) => Ok(serde_json::from_str(&template)?),
+            None => Err(VaultError::NotFound(format!("Import template not found: {}", name))),
+        }
+    }
+
+    /// Import a sound file, filling in its metadata from a template
+    ///
+    /// Fields set in `metadata` win over the template's.
+    pub async fn import_file_with_options(
+        &self,
+        source_path: &Path,
+        metadata: Option<SoundMetadata>,
+        options: &ImportOptions,
+    ) -> Result<String> {
+        let mut metadata = match &options.template {
+            Some(name) => Some(self.import_template(name).await?.metadata_for(source_path, metadata)),
+            None => metadata,
+        };
+        if options.inbox {
+            let mut inbox = metadata.unwrap_or_else(|| SoundMetadataTemplate::default().metadata_for(source_path, None));
+            inbox.status = SoundStatus::Inbox;
+            metadata = Some(inbox);
+        }
+
+        let id = match options.mode {
+            ImportMode::Copy => self.import_file(source_path, metadata).await?,
+            ImportMode::Reference => self.reference_file(source_path, metadata).await?,
+        };
+        if let Some(kind) = options.group_variations {
+            self.group_variation(&id, source_path, kind).await?;
+        }
+        Ok(id)
+    }
+
+    /// Record a file where it lies, without copying it into the library
+    async fn reference_file(&self, source_path: &Path, metadata: Option<SoundMetadata>) -> Result<String> {
+        let _foreground = self.foreground();
+        if !source_path.is_file() {
+            return Err(VaultError::FileSystem(format!("Source file does not exist: {:?}", source_path)));
+        }
+
+        let mut metadata = metadata.unwrap_or_else(|| SoundMetadata {
+            name: source_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            license: "Unknown".to_string(),
+            ..Default::default()
+        });
+        metadata.id = Uuid::new_v4().to_string();
+        metadata.path = Some(std::path::absolute(source_path)?);
+        metadata.external = true;
+        metadata.source = SoundSource::Local;
+        metadata.hash = Some(hash_file(source_path)?);
+        if let Ok(info) = probe_file(source_path) {
+            if metadata.duration == 0.0 {
+                metadata.duration = info.duration();
+            }
+            metadata.channels = Some(info.channels);
+            metadata.sample_rate = Some(info.sample_rate);
+        }
+        let warnings = self.merge_embedded_tags(&mut metadata);
+
+        let provenance = self.local_provenance(ProvenanceMode::Reference, source_path);
+        self.insert_sound(&metadata, None, Some(&provenance)).await?;
+        self.report_import_warnings(&metadata.id, warnings);
+        #[cfg(feature = "analysis")]
+        self.flag_near_duplicates(&metadata.id).await;
+        Ok(metadata.id)
+    }
+}
@@ -0,0 +1,601 @@
+//! Consistency checks between the database and the library directory
+
+use crate::cas::ObjectRefMismatch;
+use crate::context::{Deadline, OpContext};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::pack::{read_packed, split_packed};
+use crate::paths::{TEMP_SUFFIX, resolve_within};
+use crate::plan::{PlannedFile, check_snapshot, snapshot_token};
+use crate::sidecar::{COLLECTIONS_DIR, SIDECAR_FILE};
+use crate::sniff::ExtensionMismatch;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Key of the last integrity scan's outcome in `vault_info`
+const LAST_INTEGRITY_SCAN: &str = "last_integrity_scan";
+
+/// Directory of the library holding quarantined files
+pub const QUARANTINE_DIR: &str = ".quarantine";
+
+/// Directory of the library holding generated previews
+pub const PREVIEW_DIR: &str = ".previews";
+
+/// Directory of the library holding working copies of checked-out sounds
+pub const CHECKOUT_DIR: &str = ".checkouts";
+
+/// Prefix of the names of cached spectrograms, kept next to sound files
+pub(crate) const SPECTROGRAM_PREFIX: &str = ".spectrogram-";
+
+/// Prefix of the names of cached waveform images, kept next to sound files
+pub(crate) const WAVEFORM_PREFIX: &str = ".waveform-";
+
+/// Age after which an unfinished temporary file is considered abandoned
+const STALE_TEMP_AGE: Duration = Duration::from_secs(3600);
+
+/// Prefix of the probe files written to check that the library is writable
+const WRITE_PROBE_PREFIX: &str = ".soundvault-write-test-";
+
+/// What [`SoundVault::repair`](crate::SoundVault::repair) does with problems it finds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepairPolicy {
+    /// Only report problems
+    #[default]
+    ReportOnly,
+    /// Move orphan files into the quarantine directory, and set the counts
+    /// of references of content-addressed files to the sounds sharing them
+    Quarantine,
+}
+
+/// Result of an integrity scan
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    /// IDs of sounds whose file is missing
+    pub missing_files: Vec<String>,
+
+    /// Files in the library without a sound, relative to the library
+    pub orphan_files: Vec<PathBuf>,
+
+    /// Orphan files moved into the quarantine directory by a repair
+    pub quarantined: Vec<QuarantinedFile>,
+
+    /// IDs of derivatives whose parent sound doesn't exist
+    #[serde(default)]
+    pub broken_derivations: Vec<String>,
+
+    /// Temporary files left by interrupted writes and deleted by the scan,
+    /// relative to the library
+    #[serde(default)]
+    pub removed_temp_files: Vec<PathBuf>,
+
+    /// Sounds whose file's extension disagrees with its format
+    #[serde(default)]
+    pub extension_mismatches: Vec<ExtensionMismatch>,
+
+    /// IDs of the sounds whose file a repair renamed after its format
+    #[serde(default)]
+    pub fixed_extensions: Vec<String>,
+
+    /// IDs of packed sounds whose archive entry no longer reads back, or
+    /// holds other content than was indexed
+    #[serde(default)]
+    pub damaged_entries: Vec<String>,
+
+    /// Content-addressed files whose recorded count of references disagrees
+    /// with the sounds referencing them
+    #[serde(default)]
+    pub object_ref_mismatches: Vec<ObjectRefMismatch>,
+
+    /// IDs of the collections whose members' positions have gaps or
+    /// duplicates
+    #[serde(default)]
+    pub misordered_collections: Vec<String>,
+}
+
+/// What [`SoundVault::apply_repair`](crate::SoundVault::apply_repair) does,
+/// from [`SoundVault::plan_repair`](crate::SoundVault::plan_repair)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RepairPlan {
+    /// Policy the plan was made with
+    pub policy: RepairPolicy,
+
+    /// Problems found; nothing is removed or quarantined yet
+    pub findings: IntegrityReport,
+
+    /// Orphan files to move into quarantine, relative to the library
+    pub quarantine: Vec<PlannedFile>,
+
+    /// Abandoned temporary files to delete, relative to the library
+    pub remove_temp_files: Vec<PlannedFile>,
+
+    /// Files of the library to rename after their format, when
+    /// [`VaultConfig::fix_extensions`](crate::VaultConfig::fix_extensions)
+    /// is set
+    #[serde(default)]
+    pub rename: Vec<ExtensionMismatch>,
+
+    /// Content-addressed files whose count of references to set to the
+    /// sounds referencing them, under [`RepairPolicy::Quarantine`]; those no
+    /// sound references are quarantined with the orphans
+    #[serde(default)]
+    pub recount_objects: Vec<ObjectRefMismatch>,
+
+    /// Collections whose members to number again in their current order,
+    /// under [`RepairPolicy::Quarantine`]
+    #[serde(default)]
+    pub renumber_collections: Vec<String>,
+
+    /// Bytes the deletions free
+    pub bytes_reclaimed: u64,
+
+    /// Token of the sounds' and library files' state the plan was made from
+    pub snapshot: String,
+}
+
+/// A file held in the quarantine directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedFile {
+    /// Current location of the file
+    pub path: PathBuf,
+
+    /// Former location, relative to the library
+    pub original_path: PathBuf,
+
+    /// Day the file was quarantined, as `YYYY-MM-DD`
+    pub date: String,
+
+    /// Size in bytes
+    pub size: u64,
+}
+
+impl IntegrityReport {
+    /// Check whether the database and the library directory agree
+    pub fn is_clean(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.orphan_files.is_empty()
+            && self.broken_derivations.is_empty()
+            && self.extension_mismatches.is_empty()
+            && self.damaged_entries.is_empty()
+            && self.object_ref_mismatches.is_empty()
+            && self.misordered_collections.is_empty()
+    }
+
+    /// Describe each kind of problem found, one line per kind
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::IntegrityReport;
+    ///
+    /// let report = IntegrityReport {
+    ///     missing_files: vec!["a".to_string(), "b".to_string()],
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(report.warnings(), vec!["2 sounds have a missing file"]);
+    /// ```
+    pub fn warnings(&self) -> Vec<String> {
+        [
+            (self.missing_files.len(), "sounds have a missing file"),
+            (self.orphan_files.len(), "files in the library belong to no sound"),
+            (self.broken_derivations.len(), "derivatives point to a missing parent"),
+            (self.extension_mismatches.len(), "files have an extension of another format"),
+            (self.damaged_entries.len(), "packed sounds have a damaged archive entry"),
+            (self.object_ref_mismatches.len(), "content-addressed files have a wrong count of references"),
+            (self.misordered_collections.len(), "collections have gaps or duplicates in the order of their sounds"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, problem)| format!("{} {}", count, problem))
+        .collect()
+    }
+}
+
+impl LocalLibrary {
+    /// Compare the sounds in the database with the files in the library
+    ///
+    /// If `context` stops the scan, its findings aren't kept for health
+    /// reports.
+    pub async fn scan_integrity(&self, context: &OpContext) -> Result<IntegrityReport> {
+        let deadline = context.start("scan_integrity");
+        let plan = self.plan(RepairPolicy::ReportOnly, &deadline).await?;
+        self.execute(&plan, &deadline).await
+    }
+
+    /// Find the problems of the library and what a repair under `policy`
+    /// does about them, changing nothing
+    async fn plan(&self, policy: RepairPolicy, deadline: &Deadline<'_>) -> Result<RepairPlan> {
+        let rows: Vec<(String, Option<String>, Option<String>, bool)> =
+            sqlx::query_as(&self.sql("SELECT id, path, storage_backend, packed FROM sounds ORDER BY id"))
+                .fetch_all(&self.reader)
+                .await?;
+
+        let mut findings = IntegrityReport::default();
+        let mut known = HashSet::new();
+        let sounds = rows.len();
+        for (done, (id, path, storage_backend, packed)) in rows.into_iter().enumerate() {
+            deadline.check(|| format!("{} of {} sounds checked", done, sounds))?;
+            if packed && let Some(path) = path.as_deref().map(PathBuf::from) {
+                // Entries are inflated, which checks their CRC, and hashed
+                let hash = self.get_sound(&id).await?.metadata.hash;
+                let archive = split_packed(&path).map(|(archive, _)| archive).unwrap_or_default();
+                match self.jobs.run(move || Ok(read_packed(&path))).await? {
+                    Ok(Some(bytes)) if hash.is_none_or(|hash| hash == format!("{:x}", Sha256::digest(&bytes))) => {}
+                    Ok(Some(_)) | Err(_) => findings.damaged_entries.push(id),
+                    Ok(None) => findings.missing_files.push(id),
+                }
+                known.insert(archive);
+            } else if let Some(path) = path.map(PathBuf::from) {
+                // Files of other stores are asked of the store; the copy in
+                // the library, if any, is only a cache
+                let present = match storage_backend {
+                    Some(_) => self.blob_exists(&self.get_sound(&id).await?.metadata).await,
+                    None => path.exists(),
+                };
+                if !present {
+                    findings.missing_files.push(id);
+                }
+                known.insert(path);
+            }
+        }
+
+        let mut files = Vec::new();
+        self.collect_files(&self.library_path, &mut files)?;
+        files.sort();
+        deadline.check(|| format!("{} sounds checked, library files listed", sounds))?;
+
+        let relative = |path: &Path| path.strip_prefix(&self.library_path).unwrap_or(path).to_path_buf();
+        let mut state = Vec::with_capacity(files.len());
+        for path in &files {
+            let metadata = std::fs::metadata(path).ok();
+            let size = metadata.as_ref().map(|m| m.len()).unwrap_or_default();
+            let modified = metadata.and_then(|m| m.modified().ok());
+            state.push((relative(path), size, modified));
+        }
+
+        // Files still being written are left alone; abandoned ones are deleted
+        let mut remove_temp_files = Vec::new();
+        let mut quarantine = Vec::new();
+        for ((path, size, modified), absolute) in state.iter().zip(&files) {
+            if path.to_string_lossy().ends_with(TEMP_SUFFIX) {
+                let stale = modified
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                    .is_some_and(|age| age >= STALE_TEMP_AGE);
+                if stale {
+                    remove_temp_files.push(PlannedFile {
+                        path: path.clone(),
+                        size: *size,
+                    });
+                }
+            } else if !known.contains(absolute) {
+                findings.orphan_files.push(path.clone());
+                if policy == RepairPolicy::Quarantine {
+                    quarantine.push(PlannedFile {
+                        path: path.clone(),
+                        size: *size,
+                    });
+                }
+            }
+        }
+
+        findings.broken_derivations = sqlx::query_scalar(
+            &self.sql(r#"
+            SELECT id FROM sounds
+            WHERE derived_from IS NOT NULL AND derived_from NOT IN (SELECT id FROM sounds)
+            ORDER BY id
+            "#),
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        findings.extension_mismatches = self.list_extension_mismatches().await?;
+        findings.object_ref_mismatches = self.object_ref_mismatches().await?;
+        findings.misordered_collections = self.misordered_collections().await?;
+        let (recount_objects, renumber_collections) = match policy {
+            RepairPolicy::Quarantine => (findings.object_ref_mismatches.clone(), findings.misordered_collections.clone()),
+            RepairPolicy::ReportOnly => (Vec::new(), Vec::new()),
+        };
+        let rename = match self.fix_extensions {
+            // Referenced files are never touched
+            true => findings
+                .extension_mismatches
+                .iter()
+                .filter(|mismatch| mismatch.path.starts_with(&self.library_path))
+                .cloned()
+                .collect(),
+            false => Vec::new(),
+        };
+
+        Ok(RepairPlan {
+            policy,
+            snapshot: snapshot_token(&(&findings, &state))?,
+            bytes_reclaimed: remove_temp_files.iter().map(|file| file.size).sum(),
+            findings,
+            quarantine,
+            remove_temp_files,
+            rename,
+            recount_objects,
+            renumber_collections,
+        })
+    }
+
+    /// Carry out a plan, keeping its findings for health reports
+    async fn execute(&self, plan: &RepairPlan, deadline: &Deadline<'_>) -> Result<IntegrityReport> {
+        let mut report = plan.findings.clone();
+        for file in &plan.remove_temp_files {
+            if std::fs::remove_file(self.library_path.join(&file.path)).is_ok() {
+                report.removed_temp_files.push(file.path.clone());
+            }
+        }
+
+        // Keep the outcome for health reports
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO vault_info (key, value) VALUES (?, ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            "#),
+        )
+        .bind(LAST_INTEGRITY_SCAN)
+        .bind(serde_json::to_string(&(Utc::now(), &report))?)
+        .execute(&self.db)
+        .await?;
+
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let orphans = plan.quarantine.len();
+        for file in &plan.quarantine {
+            deadline.check(|| format!("{} of {} orphan files quarantined", report.quarantined.len(), orphans))?;
+            report.quarantined.push(self.quarantine_file(&file.path, &date)?);
+        }
+
+        self.recount_objects(&plan.recount_objects).await?;
+        for collection_id in &plan.renumber_collections {
+            self.normalize_collection_positions(collection_id).await?;
+        }
+
+        let renames = plan.rename.len();
+        for mismatch in &plan.rename {
+            deadline.check(|| format!("{} of {} files renamed", report.fixed_extensions.len(), renames))?;
+            self.fix_extension(mismatch).await?;
+            report.fixed_extensions.push(mismatch.sound_id.clone());
+        }
+
+        Ok(report)
+    }
+
+    /// When the last integrity scan ran, and what it found
+    pub(crate) async fn last_integrity_scan(&self) -> Result<Option<(DateTime<Utc>, IntegrityReport)>> {
+        let value: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(LAST_INTEGRITY_SCAN)
+            .fetch_optional(&self.reader)
+            .await?;
+
+        Ok(value.and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    /// Scan the library and fix what the policy allows
+    ///
+    /// If `context` stops the repair, the files quarantined so far stay in
+    /// quarantine.
+    pub async fn repair(&self, policy: RepairPolicy, context: &OpContext) -> Result<IntegrityReport> {
+        let deadline = context.start("repair");
+        let plan = self.plan(policy, &deadline).await?;
+        self.execute(&plan, &deadline).await
+    }
+
+    /// Compute what [`repair`](Self::repair) would do, without changing
+    /// anything
+    pub async fn plan_repair(&self, policy: RepairPolicy) -> Result<RepairPlan> {
+        self.plan(policy, &OpContext::default().start("plan_repair")).await
+    }
+
+    /// Carry out exactly the deletions and moves of a plan
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PlanOutdated` if sounds or library files changed since
+    ///   the plan was made
+    pub async fn apply_repair(&self, plan: &RepairPlan, context: &OpContext) -> Result<IntegrityReport> {
+        let deadline = context.start("repair");
+        let current = self.plan(plan.policy, &deadline).await?;
+        check_snapshot("repair", &plan.snapshot, &current.snapshot)?;
+        self.execute(plan, &deadline).await
+    }
+
+    /// List the files held in the quarantine directory
+    pub fn list_quarantine(&self) -> Result<Vec<QuarantinedFile>> {
+        let root = self.library_path.join(QUARANTINE_DIR);
+        if !root.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut files = Vec::new();
+        walk_files(&root, &mut |_| true, &mut files)?;
+        files.sort();
+
+        files
+            .into_iter()
+            .map(|path| {
+                let relative = path.strip_prefix(&root).unwrap_or(&path);
+                let mut components = relative.components();
+                let date = components
+                    .next()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let original_path = components.as_path().to_path_buf();
+                let size = std::fs::metadata(&path)
+                    .map_err(|e| VaultError::FileSystem(format!("Failed to read {:?}: {}", path, e)))?
+                    .len();
+                Ok(QuarantinedFile {
+                    path,
+                    original_path,
+                    date,
+                    size,
+                })
+            })
+            .collect()
+    }
+
+    /// Import a quarantined file as a new sound and remove it from quarantine
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file, absolute or relative to the quarantine directory
+    ///
+    /// # Returns
+    ///
+    /// The ID of the imported sound
+    pub async fn restore_from_quarantine(&self, path: &Path) -> Result<String> {
+        let root = self.library_path.join(QUARANTINE_DIR);
+        let path = if path.is_absolute() { path.to_path_buf() } else { root.join(path) };
+
+        let canonical = resolve_within(&root, &path)?;
+        if !canonical.is_file() {
+            return Err(VaultError::NotFound(format!("Quarantined file not found: {:?}", path)));
+        }
+
+        let id = self.import_file(&canonical, None).await?;
+
+        std::fs::remove_file(&canonical).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to remove quarantined file: {}", e))
+        })?;
+        if let Ok(canonical_root) = root.canonicalize() {
+            remove_empty_parents(&canonical, &canonical_root);
+        }
+
+        Ok(id)
+    }
+
+    /// Move an orphan file into today's quarantine directory, keeping its relative path
+    pub(crate) fn quarantine_file(&self, relative: &Path, date: &str) -> Result<QuarantinedFile> {
+        let source = self.library_path.join(relative);
+        let target = self.quarantine_target(relative, date)?;
+        let size = std::fs::metadata(&source).map(|m| m.len()).unwrap_or_default();
+        std::fs::rename(&source, &target).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to quarantine {:?}: {}", source, e))
+        })?;
+        remove_empty_parents(&source, &self.library_path);
+
+        Ok(QuarantinedFile {
+            path: target,
+            original_path: relative.to_path_buf(),
+            date: date.to_string(),
+            size,
+        })
+    }
+
+    /// Copy a file still in use into today's quarantine directory, keeping
+    /// its relative path
+    pub(crate) fn quarantine_copy(&self, relative: &Path, date: &str) -> Result<QuarantinedFile> {
+        let source = self.library_path.join(relative);
+        let target = self.quarantine_target(relative, date)?;
+        let size = std::fs::copy(&source, &target).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to quarantine {:?}: {}", source, e))
+        })?;
+
+        Ok(QuarantinedFile {
+            path: target,
+            original_path: relative.to_path_buf(),
+            date: date.to_string(),
+            size,
+        })
+    }
+
+    /// Free path in today's quarantine directory for a file of the library,
+    /// with its directory created
+    fn quarantine_target(&self, relative: &Path, date: &str) -> Result<PathBuf> {
+        let base = self.library_path.join(QUARANTINE_DIR).join(date).join(relative);
+
+        // Never overwrite a file quarantined earlier the same day
+        let mut target = base.clone();
+        let mut attempt = 1;
+        while target.exists() {
+            let mut name = base.clone().into_os_string();
+            name.push(format!(".{}", attempt));
+            target = PathBuf::from(name);
+            attempt += 1;
+        }
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to create quarantine directory: {}", e))
+            })?;
+        }
+        Ok(target)
+    }
+
+    /// Collect the files of the library that the vault does not manage itself
+    fn collect_files(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        walk_files(dir, &mut |path| !self.is_managed_path(path), files)
+    }
+
+    /// Check whether a path belongs to the vault's own bookkeeping
+    ///
+    /// The database with its journals, backups and archives, write probes,
+    /// quarantined files, working copies of checked-out sounds, generated
+    /// previews and spectrograms and metadata sidecars must never be treated
+    /// as orphans.
+    fn is_managed_path(&self, path: &Path) -> bool {
+        if [QUARANTINE_DIR, PREVIEW_DIR, CHECKOUT_DIR, COLLECTIONS_DIR].iter().any(|dir| path == self.library_path.join(dir)) {
+            return true;
+        }
+
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        if name == SIDECAR_FILE && path.parent().and_then(Path::parent) == Some(&self.library_path) {
+            return true;
+        }
+        if name.starts_with(WRITE_PROBE_PREFIX)
+            || name.starts_with(SPECTROGRAM_PREFIX)
+            || name.starts_with(WAVEFORM_PREFIX)
+        {
+            return true;
+        }
+
+        match (self.database_path.parent(), self.database_path.file_name()) {
+            (Some(db_dir), Some(db_name)) => {
+                path.parent() == Some(db_dir) && name.starts_with(&*db_name.to_string_lossy())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Recursively collect the files under `dir` whose path passes `keep`
+///
+/// Directories failing `keep` are skipped entirely; symbolic links are not followed.
+fn walk_files(dir: &Path, keep: &mut dyn FnMut(&Path) -> bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| VaultError::FileSystem(format!("Failed to read directory {:?}: {}", dir, e)))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| VaultError::FileSystem(format!("Failed to read directory: {}", e)))?;
+        let path = entry.path();
+        if !keep(&path) {
+            continue;
+        }
+
+        let file_type = entry
+            .file_type()
+            .map_err(|e| VaultError::FileSystem(format!("Failed to read {:?}: {}", path, e)))?;
+        if file_type.is_dir() {
+            walk_files(&path, keep, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the directories left empty above `path`, stopping at `root`
+fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) || std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
@@ -0,0 +1,163 @@
+//! Search-as-you-type: only the latest query's results are delivered
+
+use crate::error::Result;
+use crate::local::LocalLibrary;
+use crate::query::{PageRequest, SoundFilter, SoundPage};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// Result batches buffered before the search waits for the caller
+const RESULT_CAPACITY: usize = 16;
+
+/// Results of one query of an [`InteractiveSearch`]
+#[derive(Debug)]
+pub struct SearchBatch {
+    /// Sequence number returned by
+    /// [`InteractiveSearch::set_query`]; it grows from batch to batch
+    pub seq: u64,
+
+    /// Filter the results are for
+    pub filter: SoundFilter,
+
+    /// The page of results, or why the query failed
+    pub result: Result<SoundPage>,
+}
+
+/// A search whose query changes as the user types, from
+/// [`SoundVault::interactive_search`](crate::SoundVault::interactive_search)
+///
+/// Setting a query cancels the one in flight: its statement is dropped
+/// mid-stream and its results are never delivered. With a debounce
+/// interval, a query only runs once it stayed unchanged that long. The
+/// search stops when the handle is dropped.
+pub struct InteractiveSearch {
+    library: Arc<LocalLibrary>,
+    debounce: Duration,
+    page: PageRequest,
+    seq: u64,
+    queries: watch::Sender<Option<(u64, SoundFilter)>>,
+    results: mpsc::Receiver<SearchBatch>,
+    /// Sender handed to the task when the first query starts it
+    sender: Option<mpsc::Sender<SearchBatch>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl InteractiveSearch {
+    pub(crate) fn new(library: Arc<LocalLibrary>) -> Self {
+        let (sender, results) = mpsc::channel(RESULT_CAPACITY);
+        Self {
+            library,
+            debounce: Duration::ZERO,
+            page: PageRequest::default(),
+            seq: 0,
+            queries: watch::channel(None).0,
+            results,
+            sender: Some(sender),
+            task: None,
+        }
+    }
+
+    /// Wait until a query stayed unchanged for `interval` before running it
+    ///
+    /// Takes effect if set before the first query.
+    pub fn debounce(mut self, interval: Duration) -> Self {
+        self.debounce = interval;
+        self
+    }
+
+    /// Page of results fetched for each query; the first 50 sounds by default
+    ///
+    /// Takes effect if set before the first query.
+    pub fn page(mut self, page: PageRequest) -> Self {
+        self.page = page;
+        self
+    }
+
+    /// Replace the query, cancelling the previous one if it's still running
+    ///
+    /// # Returns
+    ///
+    /// The sequence number of the query, carried by its [`SearchBatch`]
+    pub fn set_query(&mut self, filter: SoundFilter) -> u64 {
+        self.seq += 1;
+        self.queries.send_replace(Some((self.seq, filter)));
+
+        if let Some(results) = self.sender.take() {
+            let queries = self.queries.subscribe();
+            let library = self.library.clone();
+            let (debounce, page) = (self.debounce, self.page.clone());
+            self.task = Some(tokio::spawn(run(library, queries, results, debounce, page)));
+        }
+        self.seq
+    }
+
+    /// Wait for the results of the latest query
+    ///
+    /// Returns `None` once the search stopped.
+    pub async fn next(&mut self) -> Option<SearchBatch> {
+        self.results.recv().await
+    }
+}
+
+impl Drop for InteractiveSearch {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Run the latest query each time it changes, until the handle is dropped
+async fn run(
+    library: Arc<LocalLibrary>,
+    mut queries: watch::Receiver<Option<(u64, SoundFilter)>>,
+    results: mpsc::Sender<SearchBatch>,
+    debounce: Duration,
+    page: PageRequest,
+) {
+    // The query that started the task hasn't run yet
+    let mut superseded = true;
+    loop {
+        if !superseded && queries.changed().await.is_err() {
+            return;
+        }
+        superseded = false;
+
+        if !debounce.is_zero() {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(debounce) => break,
+                    changed = queries.changed() => if changed.is_err() {
+                        return;
+                    },
+                }
+            }
+        }
+
+        let Some((seq, filter)) = queries.borrow_and_update().clone() else {
+            continue;
+        };
+        // Dropping the query's future aborts its statement
+        tokio::select! {
+            result = library.query_page(&filter, &page) => {
+                match queries.has_changed() {
+                    Ok(true) => superseded = true,
+                    Ok(false) => {
+                        if results.send(SearchBatch { seq, filter, result }).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                }
+            }
+            changed = queries.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                superseded = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Background work run a few jobs at a time
+
+use crate::error::{Result, VaultError};
+use crate::governor::Limiter;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Interval at which [`JobQueue::wait_idle`] checks for finished jobs
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Runs CPU-heavy jobs on blocking threads, as many at once as the
+/// governor's decode limit allows
+pub(crate) struct JobQueue {
+    /// One permit per job allowed to run
+    permits: Arc<Limiter>,
+    /// Jobs waiting or running
+    pending: Arc<AtomicUsize>,
+}
+
+/// Counts a job as pending until dropped
+struct PendingJob(Arc<AtomicUsize>);
+
+impl Drop for PendingJob {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl JobQueue {
+    /// Create a queue running jobs with the permits of `permits`
+    pub(crate) fn new(permits: Arc<Limiter>) -> Self {
+        Self {
+            permits,
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of jobs waiting or running
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Number of jobs running now
+    pub(crate) fn running(&self) -> usize {
+        self.permits.in_use()
+    }
+
+    /// Refuse new jobs and fail the ones still waiting for a worker
+    ///
+    /// Running jobs finish normally. Calling this again has no effect.
+    pub(crate) fn shutdown(&self) {
+        self.permits.close();
+    }
+
+    /// Wait until no job is pending, or `timeout` elapsed
+    ///
+    /// # Returns
+    ///
+    /// Number of jobs still pending
+    pub(crate) async fn wait_idle(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        while self.pending() > 0 && Instant::now() < deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            tokio::time::sleep(IDLE_POLL_INTERVAL.min(remaining)).await;
+        }
+        self.pending()
+    }
+
+    /// Run a job once a worker is free and wait for its result
+    ///
+    /// A job keeps its worker until it finishes, even if the caller stops
+    /// waiting for it.
+    pub(crate) async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let pending = PendingJob(self.pending.clone());
+
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .ok_or_else(|| VaultError::InvalidOperation("Job queue is closed".to_string()))?;
+
+        tokio::task::spawn_blocking(move || {
+            let _held = (permit, pending);
+            job()
+        })
+        .await
+        .map_err(|e| VaultError::InvalidOperation(format!("Background job failed: {}", e)))?
+    }
+}
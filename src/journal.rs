@@ -0,0 +1,161 @@
+//! Journal of file operations, so a crash never leaves half of one behind
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::paths::temp_path;
+use crate::tables::Tables;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Row, Sqlite, SqliteConnection};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Kind of a journaled operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpKind {
+    Import,
+    Download,
+    Derive,
+    ReplaceFile,
+    Compress,
+    Decompress,
+}
+
+impl OpKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OpKind::Import => "import",
+            OpKind::Download => "download",
+            OpKind::Derive => "derive",
+            OpKind::ReplaceFile => "replace_file",
+            OpKind::Compress => "compress",
+            OpKind::Decompress => "decompress",
+        }
+    }
+}
+
+/// Operations an interrupted session left behind, settled when the vault
+/// was opened
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// Kinds of the operations that never committed, whose new files were
+    /// removed
+    pub rolled_back: Vec<String>,
+
+    /// Kinds of the operations that committed, whose replaced files were
+    /// removed
+    pub completed: Vec<String>,
+
+    /// Files that couldn't be removed, with the reason
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+impl LocalLibrary {
+    /// Journal an operation before it touches any file
+    ///
+    /// `created` are the files it writes, removed if it never commits;
+    /// `obsolete` the files it replaces, removed once it has.
+    pub(crate) async fn begin_op(&self, kind: OpKind, created: &[&Path], obsolete: &[&Path]) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+        sqlx::query(&self.sql("INSERT INTO pending_ops (id, kind, created, obsolete) VALUES (?, ?, ?, ?)"))
+            .bind(&id)
+            .bind(kind.as_str())
+            .bind(serde_json::to_string(created)?)
+            .bind(serde_json::to_string(obsolete)?)
+            .execute(&self.db)
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Add a file to those an operation writes, once its name is known
+    pub(crate) async fn add_created(&self, id: &str, path: &Path) -> Result<()> {
+        let created: String = sqlx::query_scalar(&self.sql("SELECT created FROM pending_ops WHERE id = ?"))
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?;
+        let mut created: Vec<PathBuf> = serde_json::from_str(&created)?;
+        created.push(path.to_path_buf());
+        sqlx::query(&self.sql("UPDATE pending_ops SET created = ? WHERE id = ?"))
+            .bind(serde_json::to_string(&created)?)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark an operation as committed, in the transaction writing its rows
+    pub(crate) async fn commit_op(&self, conn: &mut SqliteConnection, id: &str) -> Result<()> {
+        sqlx::query(&self.sql("UPDATE pending_ops SET committed = 1 WHERE id = ?"))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Finish an operation once it succeeded or failed, removing the files
+    /// it left behind
+    ///
+    /// Passes `result` through, unless clearing the journal or removing a
+    /// file fails.
+    pub(crate) async fn settle_op<T>(&self, id: &str, result: Result<T>) -> Result<T> {
+        let mut report = RecoveryReport::default();
+        Self::settle(&self.db, &self.tables, id, &mut report).await?;
+        let value = result?;
+        match report.errors.into_iter().next() {
+            Some((path, e)) => Err(VaultError::FileSystem(format!("Failed to delete {:?}: {}", path, e))),
+            None => Ok(value),
+        }
+    }
+
+    /// Settle the operations left in the journal by a previous session
+    pub(crate) async fn recover_ops(db: &Pool<Sqlite>, tables: &Tables) -> Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+        let ids: Vec<String> = sqlx::query_scalar(&tables.sql("SELECT id FROM pending_ops ORDER BY started_at, id"))
+            .fetch_all(db)
+            .await?;
+        for id in ids {
+            Self::settle(db, tables, &id, &mut report).await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn settle(db: &Pool<Sqlite>, tables: &Tables, id: &str, report: &mut RecoveryReport) -> Result<()> {
+        let Some(row) = sqlx::query(&tables.sql("SELECT kind, created, obsolete, committed FROM pending_ops WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(db)
+            .await?
+        else {
+            return Ok(());
+        };
+        let kind: String = row.try_get("kind")?;
+        let committed: bool = row.try_get("committed")?;
+
+        // A committed operation only has to drop what it replaced; an
+        // uncommitted one is undone, including files written halfway
+        let column = if committed { "obsolete" } else { "created" };
+        let files: Vec<PathBuf> = serde_json::from_str(&row.try_get::<String, _>(column)?)?;
+        for file in files {
+            for path in [temp_path(&file), file] {
+                match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => report.errors.push((path, e.to_string())),
+                    _ => {}
+                }
+            }
+        }
+        if committed {
+            report.completed.push(kind);
+        } else {
+            report.rolled_back.push(kind);
+        }
+
+        sqlx::query(&tables.sql("DELETE FROM pending_ops WHERE id = ?"))
+            .bind(id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,522 @@
+//! Levels of a sound measured while it's decoded, chunk by chunk
+//!
+//! Each measure keeps a running aggregate: the peak of each waveform bucket,
+//! a histogram of loudness gating blocks, the current run of silence. Memory
+//! stays the same however long the file is.
+
+use crate::audio::{ChannelMix, SampleChunks, downmix};
+use crate::error::Result;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::PathBuf;
+
+/// Frames decoded at a time
+const CHUNK_FRAMES: usize = 65536;
+
+/// Loudness of the quietest gating block counted, in LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Width of a bin of the histogram of gating blocks, in LU
+const BIN_WIDTH: f64 = 0.1;
+
+/// Bins of the histogram of gating blocks, from the absolute gate to +10 LUFS
+const BINS: usize = 800;
+
+/// Length of the windows checked for silence, in seconds
+const SILENCE_WINDOW: f64 = 0.01;
+
+/// What [`analyze_levels`] measures
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LevelOptions {
+    /// Number of waveform peaks
+    pub buckets: usize,
+
+    /// Level under which every channel must stay for the sound to be
+    /// silent, in dBFS
+    pub silence_threshold_db: f32,
+
+    /// Shortest silence reported, in seconds
+    pub min_silence: f64,
+}
+
+impl Default for LevelOptions {
+    fn default() -> Self {
+        Self {
+            buckets: 1000,
+            silence_threshold_db: -60.0,
+            min_silence: 0.5,
+        }
+    }
+}
+
+/// A stretch of silence, in seconds from the start of the sound
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Silence {
+    /// Start of the silence
+    pub start: f64,
+
+    /// End of the silence
+    pub end: f64,
+}
+
+/// Levels of a sound
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Levels {
+    /// Duration in seconds
+    pub duration: f64,
+
+    /// Highest absolute sample value, from 0.0 to 1.0
+    pub peak: f32,
+
+    /// Peak level of the mono mixdown over equal slices of the sound, as
+    /// computed by [`waveform`](crate::waveform)
+    pub waveform: Vec<f32>,
+
+    /// Integrated loudness per EBU R 128, in LUFS; `None` if the sound is
+    /// shorter than 400 ms or silent
+    pub loudness: Option<f64>,
+
+    /// Stretches of silence, in order
+    pub silences: Vec<Silence>,
+}
+
+/// Measure the levels of a WAV, AIFF or CAF file, decoding it a chunk at a
+/// time
+///
+/// Memory use depends on the options and the number of silences found, not
+/// on the length of the file.
+///
+/// # Examples
+///
+/// A one-hour recording is measured in a few megabytes, with the same
+/// waveform as decoding it whole:
+///
+/// ```standalone_crate
+/// use soundvault::{LevelOptions, analyze_levels, decode, waveform};
+/// use std::alloc::{GlobalAlloc, Layout, System};
+/// use std::io::{BufWriter, Write};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// # struct Counter;
+/// # static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+/// # static PEAK: AtomicUsize = AtomicUsize::new(0);
+/// # unsafe impl GlobalAlloc for Counter {
+/// #     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+/// #         let now = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+/// #         PEAK.fetch_max(now, Ordering::SeqCst);
+/// #         unsafe { System.alloc(layout) }
+/// #     }
+/// #     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+/// #         ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+/// #         unsafe { System.dealloc(ptr, layout) }
+/// #     }
+/// # }
+/// # #[global_allocator]
+/// # static COUNTER: Counter = Counter;
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// // An hour of 1 kHz tone at half scale, 8 kHz mono 16-bit, with a
+/// // second of silence every minute
+/// let (rate, seconds) = (8000u32, 3600u32);
+/// let dir = tempfile::tempdir()?;
+/// let path = dir.path().join("ambience.wav");
+/// let mut file = BufWriter::new(std::fs::File::create(&path)?);
+/// let data_len = rate * seconds * 2;
+/// file.write_all(b"RIFF")?;
+/// file.write_all(&(36 + data_len).to_le_bytes())?;
+/// file.write_all(b"WAVEfmt ")?;
+/// for field in [16u32, 0x0001_0001, rate, rate * 2, 0x0010_0002] {
+///     file.write_all(&field.to_le_bytes())?;
+/// }
+/// file.write_all(b"data")?;
+/// file.write_all(&data_len.to_le_bytes())?;
+/// let tone: Vec<u8> = (0..rate)
+///     .map(|i| ((i as f64 * std::f64::consts::PI / 4.0).sin() * 16384.0).round() as i16 as u16)
+///     .flat_map(|sample| sample.to_le_bytes())
+///     .collect();
+/// for second in 0..seconds {
+///     match second % 60 {
+///         30 => file.write_all(&vec![0; tone.len()])?,
+///         _ => file.write_all(&tone)?,
+///     }
+/// }
+/// drop(file);
+///
+/// let before = ALLOCATED.load(Ordering::SeqCst);
+/// PEAK.store(before, Ordering::SeqCst);
+/// let levels = analyze_levels(std::fs::File::open(&path)?, &LevelOptions::default())?;
+/// assert!(PEAK.load(Ordering::SeqCst) - before < 4 << 20);
+///
+/// assert_eq!(levels.duration, 3600.0);
+/// assert!((levels.peak - 0.5).abs() < 0.001);
+/// assert_eq!(levels.silences.len(), 60);
+/// assert!((levels.silences[0].start - 30.0).abs() < 0.02 && (levels.silences[0].end - 31.0).abs() < 0.02);
+/// // A sine at half scale is 6 dB under a full-scale one, at -3 LUFS
+/// assert!((levels.loudness.unwrap() + 9.0).abs() < 0.5);
+///
+/// // Decoding whole takes hundreds of megabytes, for the same waveform
+/// let (info, samples) = decode(&mut std::fs::File::open(&path)?)?;
+/// assert_eq!(levels.waveform, waveform(&samples, info.channels, 1000));
+/// # Ok(())
+/// # }
+/// ```
+pub fn analyze_levels<R: Read + Seek>(reader: R, options: &LevelOptions) -> Result<Levels> {
+    let mut chunks = SampleChunks::new(reader, CHUNK_FRAMES)?;
+    let info = chunks.info();
+    let channels = info.channels.max(1);
+    let rate = info.sample_rate.max(1);
+
+    let mut peaks = WaveformPeaks::new(options.buckets, chunks.frames());
+    let mut loudness = LoudnessMeter::new(rate, channels);
+    let mut silence = SilenceDetector::new(options, rate, channels);
+    let mut peak = 0.0f32;
+    let mut samples = Vec::new();
+    while chunks.next_chunk(&mut samples)? {
+        peak = samples.iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+        peaks.feed(&downmix(&samples, channels, ChannelMix::Mono));
+        loudness.feed(&samples);
+        silence.feed(&samples);
+    }
+
+    Ok(Levels {
+        duration: chunks.frames() as f64 / rate as f64,
+        peak,
+        waveform: peaks.peaks,
+        loudness: loudness.integrated(),
+        silences: silence.finish(),
+    })
+}
+
+/// [`waveform`](crate::waveform) of a file, decoding it a chunk at a time
+pub(crate) fn stream_waveform<R: Read + Seek>(reader: R, buckets: usize) -> Result<Vec<f32>> {
+    let mut chunks = SampleChunks::new(reader, CHUNK_FRAMES)?;
+    let channels = chunks.info().channels.max(1);
+    let mut peaks = WaveformPeaks::new(buckets, chunks.frames());
+    let mut samples = Vec::new();
+    while chunks.next_chunk(&mut samples)? {
+        peaks.feed(&downmix(&samples, channels, ChannelMix::Mono));
+    }
+    Ok(peaks.peaks)
+}
+
+/// Peak of each slice of a mono signal, slices cut as by
+/// [`waveform`](crate::waveform)
+struct WaveformPeaks {
+    peaks: Vec<f32>,
+    frames: u64,
+    /// Next frame fed
+    next: u64,
+    /// First bucket not ended before the next frame
+    bucket: usize,
+}
+
+impl WaveformPeaks {
+    fn new(buckets: usize, frames: u64) -> Self {
+        Self {
+            peaks: vec![0.0; buckets],
+            frames,
+            next: 0,
+            bucket: 0,
+        }
+    }
+
+    /// First and past-the-end frames of a bucket
+    fn bounds(&self, bucket: usize) -> (u64, u64) {
+        let buckets = self.peaks.len() as u64;
+        let start = bucket as u64 * self.frames / buckets;
+        let end = ((bucket as u64 + 1) * self.frames / buckets).max(start + 1).min(self.frames);
+        (start, end)
+    }
+
+    fn feed(&mut self, mono: &[f32]) {
+        for sample in mono {
+            let frame = self.next;
+            self.next += 1;
+            while self.bucket < self.peaks.len() && self.bounds(self.bucket).1 <= frame {
+                self.bucket += 1;
+            }
+            // With more buckets than frames, a frame is in several
+            let mut bucket = self.bucket;
+            while bucket < self.peaks.len() && self.bounds(bucket).0 <= frame {
+                self.peaks[bucket] = self.peaks[bucket].max(sample.abs());
+                bucket += 1;
+            }
+        }
+    }
+}
+
+/// Second-order filter, in transposed direct form II
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&self, state: &mut [f64; 2], x: f64) -> f64 {
+        let y = self.b[0] * x + state[0];
+        state[0] = self.b[1] * x - self.a[0] * y + state[1];
+        state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The K-weighting filters of ITU-R BS.1770 at a sample rate: a high shelf
+/// modelling the head, then a high-pass
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Loudness of a mean square, in LUFS
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness per EBU R 128, over 400 ms gating blocks overlapping
+/// by 75%
+///
+/// Blocks are kept as a histogram of their loudness, each bin with the sum of
+/// its blocks' mean squares, so only the relative gate is approximated, to
+/// the width of a bin.
+struct LoudnessMeter {
+    filters: [Biquad; 2],
+    /// Filter states of each channel
+    states: Vec<[[f64; 2]; 2]>,
+    weights: Vec<f64>,
+    /// Frames of a 100 ms sub-block
+    sub_block: usize,
+    /// Frames and weighted sum of squares of the current sub-block
+    filled: usize,
+    sum: f64,
+    /// Mean squares of the last three sub-blocks, oldest first
+    recent: [f64; 3],
+    sub_blocks: usize,
+    /// Number of blocks and sum of their mean squares, by loudness
+    histogram: Vec<(u64, f64)>,
+}
+
+impl LoudnessMeter {
+    fn new(rate: u32, channels: u16) -> Self {
+        // The surround channels of 5.1 weigh more, and its LFE not at all
+        let weight = |channel| match (channels, channel) {
+            (6, 3) => 0.0,
+            (6, 4 | 5) => 1.41,
+            _ => 1.0,
+        };
+
+        Self {
+            filters: k_weighting(rate),
+            states: vec![[[0.0; 2]; 2]; channels as usize],
+            weights: (0..channels).map(weight).collect(),
+            sub_block: ((rate as f64 * 0.1).round() as usize).max(1),
+            filled: 0,
+            sum: 0.0,
+            recent: [0.0; 3],
+            sub_blocks: 0,
+            histogram: vec![(0, 0.0); BINS],
+        }
+    }
+
+    fn feed(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.states.len()) {
+            for ((sample, state), weight) in frame.iter().zip(&mut self.states).zip(&self.weights) {
+                let shelved = self.filters[0].process(&mut state[0], *sample as f64);
+                let filtered = self.filters[1].process(&mut state[1], shelved);
+                self.sum += weight * filtered * filtered;
+            }
+            self.filled += 1;
+            if self.filled == self.sub_block {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        let mean_square = self.sum / self.sub_block as f64;
+        if self.sub_blocks >= 3 {
+            self.add_block((self.recent.iter().sum::<f64>() + mean_square) / 4.0);
+        }
+        self.recent = [self.recent[1], self.recent[2], mean_square];
+        self.sub_blocks += 1;
+        self.filled = 0;
+        self.sum = 0.0;
+    }
+
+    fn add_block(&mut self, mean_square: f64) {
+        let loudness = lufs(mean_square);
+        if loudness <= ABSOLUTE_GATE {
+            return;
+        }
+        let bin = (((loudness - ABSOLUTE_GATE) / BIN_WIDTH) as usize).min(BINS - 1);
+        self.histogram[bin].0 += 1;
+        self.histogram[bin].1 += mean_square;
+    }
+
+    fn integrated(&self) -> Option<f64> {
+        let mean = |bins: &mut dyn Iterator<Item = &(u64, f64)>| {
+            let (count, sum) = bins.fold((0, 0.0), |(count, sum), bin| (count + bin.0, sum + bin.1));
+            (count > 0).then(|| sum / count as f64)
+        };
+
+        let gate = lufs(mean(&mut self.histogram.iter())?) - 10.0;
+        let loud = mean(&mut self.histogram.iter().filter(|(count, sum)| *count > 0 && lufs(sum / *count as f64) > gate))?;
+        Some(lufs(loud))
+    }
+}
+
+/// Integrated loudness of interleaved samples in LUFS, `None` if they're
+/// all below the absolute gate
+#[cfg(feature = "rodio")]
+pub(crate) fn integrated_loudness(samples: &[f32], rate: u32, channels: u16) -> Option<f64> {
+    let mut meter = LoudnessMeter::new(rate, channels.max(1));
+    meter.feed(samples);
+    meter.integrated()
+}
+
+/// Runs of 10 ms windows whose samples all stay under a threshold
+struct SilenceDetector {
+    threshold: f32,
+    channels: usize,
+    /// Frames of a window
+    window: usize,
+    min_frames: u64,
+    rate: f64,
+    /// Frames fed so far
+    frame: u64,
+    /// Frames of the current window fed so far, and whether one was loud
+    filled: usize,
+    loud: bool,
+    /// First frame of the current run of silent windows
+    run: Option<u64>,
+    silences: Vec<Silence>,
+}
+
+impl SilenceDetector {
+    fn new(options: &LevelOptions, rate: u32, channels: u16) -> Self {
+        Self {
+            threshold: 10f32.powf(options.silence_threshold_db / 20.0),
+            channels: channels as usize,
+            window: ((rate as f64 * SILENCE_WINDOW).round() as usize).max(1),
+            min_frames: (options.min_silence.max(0.0) * rate as f64).round() as u64,
+            rate: rate as f64,
+            frame: 0,
+            filled: 0,
+            loud: false,
+            run: None,
+            silences: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.loud |= frame.iter().any(|sample| sample.abs() >= self.threshold);
+            self.filled += 1;
+            self.frame += 1;
+            if self.filled == self.window {
+                self.end_window();
+            }
+        }
+    }
+
+    fn end_window(&mut self) {
+        let start = self.frame - self.filled as u64;
+        match (self.loud, self.run) {
+            (true, Some(run)) => {
+                self.push(run, start);
+                self.run = None;
+            }
+            (false, None) => self.run = Some(start),
+            _ => {}
+        }
+        self.filled = 0;
+        self.loud = false;
+    }
+
+    fn push(&mut self, start: u64, end: u64) {
+        if end - start >= self.min_frames.max(1) {
+            self.silences.push(Silence {
+                start: start as f64 / self.rate,
+                end: end as f64 / self.rate,
+            });
+        }
+    }
+
+    fn finish(mut self) -> Vec<Silence> {
+        if self.filled > 0 {
+            self.end_window();
+        }
+        if let Some(run) = self.run {
+            self.push(run, self.frame);
+        }
+        self.silences
+    }
+}
+
+/// Where to read the original content of a sound from
+pub(crate) enum Content {
+    /// The sound's file
+    File(PathBuf),
+    /// The decompressed content of an archived sound
+    Bytes(Vec<u8>),
+}
+
+impl Content {
+    /// Run a measure over the content
+    pub(crate) fn read<T>(self, measure: impl FnOnce(&mut dyn ReadSeek) -> Result<T>) -> Result<T> {
+        match self {
+            Self::File(path) => measure(&mut File::open(path)?),
+            Self::Bytes(bytes) => measure(&mut Cursor::new(bytes)),
+        }
+    }
+}
+
+/// A reader that can seek
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl LocalLibrary {
+    /// Where to read the original content of a sound from, without loading
+    /// its file unless it's archived or packed
+    pub(crate) fn sound_stream(&self, metadata: &SoundMetadata) -> Result<Content> {
+        if metadata.archive.is_some() || metadata.packed {
+            Ok(Content::Bytes(self.sound_bytes(metadata)?))
+        } else {
+            Ok(Content::File(self.readable_file(metadata)?))
+        }
+    }
+
+    /// Measure the peak, waveform, loudness and silences of a sound
+    ///
+    /// The file is decoded a chunk at a time on the background job queue.
+    pub async fn sound_levels(&self, id: &str, options: LevelOptions) -> Result<Levels> {
+        let content = self.sound_stream(&self.stored_sound(id).await?)?;
+        self.jobs
+            .run(move || content.read(|reader| analyze_levels(reader, &options)))
+            .await
+    }
+}
@@ -3,17 +3,200 @@
 //! add your own audio files, search and download audio files from Freesound.org,
 //! and provide seamless access for playback in your applications.
 
+mod access;
+mod acquire;
+mod archive;
+mod artifacts;
+mod attribution;
+mod audio;
+mod audit;
+mod availability;
+mod blob;
+mod browse;
+mod bulk;
+mod cache;
+mod channels;
+mod cas;
+mod changes;
+mod checkout;
+mod collation;
 mod config;
+mod context;
+mod cursor;
+mod daw;
+mod derivative;
+mod downloads;
+mod dump;
+mod duration;
+mod embedded;
 mod error;
+mod events;
+mod expiration;
+mod fields;
+#[cfg(feature = "analysis")]
+mod fingerprint;
+mod flac;
+mod fuzzy;
+mod governor;
+mod groups;
+mod health;
+mod human;
+mod hmac;
+mod import;
+mod integrity;
+mod interactive;
+mod jobs;
+mod journal;
+mod levels;
+mod license;
 mod local;
+mod lock;
+mod maintenance;
+mod manifest;
+mod merge;
+mod mirror;
 mod models;
+mod ordering;
+mod pack;
+mod patch;
+mod paths;
+mod permissions;
+mod plan;
+mod playback;
+mod provenance;
+mod provision;
+mod query;
+mod quota;
 mod remote;
+mod replace;
+mod review;
+#[cfg(feature = "rodio")]
+mod rodio_source;
+#[cfg(feature = "server")]
+pub mod rpc;
+#[cfg(feature = "s3")]
+mod s3;
+mod schema;
+mod search;
+mod scrub;
+mod session;
+mod sidecar;
+mod similar;
+mod slice;
+mod slug;
+mod snippet;
+mod sniff;
+mod source;
+#[cfg(feature = "analysis")]
+mod spectrogram;
+mod stats;
+mod subscription;
+mod tables;
+mod tags;
+mod undo;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod uri;
+mod uses;
 mod vault;
+#[cfg(feature = "analysis")]
+mod waveform;
+mod zip;
 
+pub use acquire::{AcquirePick, AcquireRequest, AcquireStage};
+pub use archive::{ArchivalCodec, Archive, ColdStoragePolicy, ColdStorageReport};
+pub use artifacts::{ArtifactKind, ArtifactLimits, ArtifactPolicy, DiskUsage, GcPlan, GcReport};
+pub use attribution::{Attribution, ExportOptions, read_attribution};
+pub use audio::{
+    AudioFormat, AudioInfo, ChannelMix, SampleFormat, decode, decode_file, downmix, encode, probe_file, waveform,
+};
+pub use audit::{AuditEntry, AuditOperation, audit_diff};
+pub use availability::RelinkReport;
+pub use blob::{BlobFuture, BlobMigrationReport, BlobReader, BlobStore, FileSystemStore, LIBRARY_STORE};
+pub use browse::BrowseNode;
+pub use bulk::{BatchOutcome, BulkPatchOptions};
+pub use cache::SoundCacheStats;
+pub use channels::{ChannelAsset, ChannelFormat, ChannelOptions};
+pub use cas::{CasMigrationReport, ObjectRefMismatch, StorageLayout};
+pub use changes::{Change, ChangeCursor, ChangeEntity, ChangeKind};
+pub use checkout::CheckoutHandle;
+pub use collation::Collator;
 pub use config::VaultConfig;
-pub use error::{Result, VaultError};
-pub use models::{Collection, Sound, SoundMetadata, SoundSource};
-pub use vault::SoundVault;
+pub use context::OpContext;
+pub use cursor::SoundCursor;
+pub use daw::{DawFlavor, DawTrack};
+pub use derivative::{AudioOp, DERIVATIVE_TAG, apply_ops};
+pub use downloads::{AUTO_COLLECT_KEY, DownloadState, QueuedDownload};
+pub use dump::{DUMP_FORMAT_VERSION, DumpRecord, DumpStats, LoadMode};
+pub use duration::{format_duration, parse_duration};
+pub use embedded::{BWF_DESCRIPTION, EmbeddedTags, read_embedded_tags};
+pub use error::{RemoteErrorKind, Result, VaultError};
+pub use events::{EventFilter, EventKind, VaultEvent};
+pub use expiration::{EXPIRED_TAG, ExpireAction, Expiration};
+pub use fields::{FieldSpec, FieldType, FieldViolation, SchemaMode};
+#[cfg(feature = "analysis")]
+pub use fingerprint::{DuplicateGroup, PossibleDuplicate};
+pub use fuzzy::{Fuzziness, SearchMatch};
+pub use governor::{ResourceLimits, ResourceUsage};
+pub use groups::{GroupKind, GroupPicker, SoundGroup};
+pub use health::{HealthReport, PoolStats};
+pub use human::{ByteSize, HumanDuration};
+pub use import::{ImportMode, ImportOptions, SoundMetadataTemplate};
+pub use integrity::{IntegrityReport, QuarantinedFile, RepairPlan, RepairPolicy};
+pub use interactive::{InteractiveSearch, SearchBatch};
+pub use journal::RecoveryReport;
+pub use levels::{LevelOptions, Levels, Silence, analyze_levels};
+pub use license::{LICENSE_REVIEW_TAG, License, LicenseInfo, LicensePolicy, ViolationAction};
+pub use lock::LOCK_REASON_KEY;
+pub use maintenance::{AutoMaintenance, MaintenanceReport, MaintenanceTask, MaintenanceTasks, TaskTiming, VacuumMode};
+pub use manifest::{Manifest, ManifestChange, ManifestDiff, ManifestEntry, ManifestFormat};
+pub use merge::{MergeConflict, MergeDecision, MergePolicy, MergeReport};
+pub use mirror::{DirectorySyncReport, SyncOptions};
+pub use models::{
+    Availability, Collection, CollectionDefaults, CollectionSummary, Localization, LocalizedView, Sound, SoundMetadata,
+    SoundSource, Trim,
+};
+pub use patch::MetadataPatch;
+pub use paths::resolve_within;
+pub use permissions::Role;
+pub use plan::PlannedFile;
+pub use playback::{DecodedAudio, DecodedStream, OutputSpec};
+pub use provenance::{ProvenanceEntry, ProvenanceMode};
+pub use provision::{
+    CollectionSpec, ProvisionItem, ProvisionKind, ProvisionReport, SoundSpec, SoundSpecSource, TagVocabulary, VaultSpec,
+};
+pub use query::{CountEstimate, DescriptorRange, Direction, PageCursor, PageRequest, SoundFilter, SoundOrderField, SoundPage};
+pub use quota::{QuotaAction, QuotaUsage};
+pub use remote::PreviewStream;
+pub use replace::{MatchMode, ReplaceOptions, ReplaceReport, TextChange, TextEntity, TextScope};
+pub use review::{ApproveOptions, SoundStatus};
+#[cfg(feature = "rodio")]
+pub use rodio_source::RodioOptions;
+#[cfg(feature = "s3")]
+pub use s3::{S3Config, S3Store};
+pub use schema::{Envelope, current_version};
+pub use scrub::ScrubReport;
+pub use sidecar::SidecarRebuildReport;
+pub use similar::{ScoredSound, SimilarSounds, SimilarityWeights};
+pub use slice::{SLICE_CONSUMER, SLICE_INDEX_KEY, SLICE_TAG, SliceOptions, SliceStrategy};
+pub use slug::{SlugPolicy, slugify};
+pub use snippet::{SnippetOptions, snippet};
+pub use sniff::{ExtensionMismatch, FileFormat, sniff_format};
+pub use source::{REMOTE_FILE_NAME, RemoteFuture, RemoteSearchResults, RemoteSource};
+pub use stats::{DimensionStats, SoftLimit, StatsDimension};
+pub use subscription::{RemoteSubscription, SubscriptionSync, SyncReport};
+pub use tags::{TagCount, TagRename};
+pub use uri::{URI_SCHEME, VaultUri};
+#[cfg(feature = "ableton")]
+pub use uses::ABLETON_CONSUMER;
+pub use uses::ExternalUse;
+pub use vault::{DatabaseRecovery, ShutdownReport, SoundVault};
+#[cfg(feature = "analysis")]
+pub use waveform::{WaveformMode, WaveformStyle};
+pub use zip::{ZipCompression, ZipManifestEntry, ZipOptions, ZipReport};
+
+pub use tokio_util::sync::CancellationToken;
+pub use undo::{OperationId, OperationSummary, UndoReport};
 
 /// Version of the SoundVault library
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
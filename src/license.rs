@@ -0,0 +1,451 @@
+//! Licenses of sounds, and the policy on which ones may be downloaded
+
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::patch::MetadataPatch;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Tag given to sounds downloaded despite a license the policy doesn't allow
+pub const LICENSE_REVIEW_TAG: &str = "license-review";
+
+/// License of a sound, recognized from its name or Creative Commons URL
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::License;
+///
+/// assert_eq!(License::parse("http://creativecommons.org/publicdomain/zero/1.0/"), License::Cc0);
+/// assert_eq!(License::parse("Creative Commons 0"), License::Cc0);
+/// assert_eq!(License::parse("https://creativecommons.org/licenses/by-nc/4.0/"), License::CcByNc);
+/// assert_eq!(License::parse("Attribution"), License::CcBy);
+/// assert_eq!(License::parse("CC BY-SA 4.0"), License::CcBySa);
+/// assert_eq!(License::parse("Unknown"), License::Unknown { raw: "Unknown".to_string() });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum License {
+    /// Public domain dedication
+    Cc0,
+    /// Attribution
+    CcBy,
+    /// Attribution, share alike
+    CcBySa,
+    /// Attribution, no derivatives
+    CcByNd,
+    /// Attribution, non-commercial
+    CcByNc,
+    /// Attribution, non-commercial, share alike
+    CcByNcSa,
+    /// Attribution, non-commercial, no derivatives
+    CcByNcNd,
+    /// Creative Commons Sampling Plus
+    SamplingPlus,
+    /// Any other license, as written
+    Unknown {
+        /// The license as stored on the sound
+        raw: String,
+    },
+}
+
+/// Versions of the Attribution licenses Creative Commons published
+const BY_VERSIONS: [&str; 5] = ["1.0", "2.0", "2.5", "3.0", "4.0"];
+
+/// Version of the Sampling+ license and the CC0 dedication
+const FIRST_VERSION: [&str; 1] = ["1.0"];
+
+/// Write a version as `major.minor`, e.g. `3` as `3.0`
+fn normalize_version(version: &str) -> Option<String> {
+    let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+    let digits = |part: &str| !part.is_empty() && part.len() <= 2 && part.chars().all(|c| c.is_ascii_digit());
+    (digits(major) && digits(minor)).then(|| format!("{}.{}", major, minor))
+}
+
+/// Whether a license is written as an address rather than a name
+fn is_url(text: &str) -> bool {
+    let lower = text.trim().to_lowercase();
+    lower.contains("://") || lower.starts_with("www.") || lower.starts_with("creativecommons.org/")
+}
+
+impl License {
+    /// Recognize a license from its name, e.g. `CC BY-NC` or `Attribution`,
+    /// or from its address, like [`License::parse_url`]
+    pub fn parse(text: &str) -> Self {
+        if is_url(text) {
+            return Self::parse_url(text);
+        }
+        let lower = text.trim().to_lowercase();
+        if lower.contains("sampling+") || lower.contains("sampling plus") {
+            return Self::SamplingPlus;
+        }
+
+        let words: Vec<&str> = lower.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+        let has = |names: &[&str]| words.iter().any(|word| names.contains(word));
+        let zero = words
+            .windows(2)
+            .any(|pair| matches!(pair[0], "cc" | "commons") && pair[1] == "0");
+        if zero || has(&["cc0", "zero"]) {
+            return Self::Cc0;
+        }
+        if !has(&["by", "attribution"]) {
+            return Self::Unknown { raw: text.to_string() };
+        }
+
+        let nc = has(&["nc", "noncommercial"]) || lower.contains("non commercial");
+        let sa = has(&["sa", "sharealike"]) || lower.contains("share alike");
+        let nd = has(&["nd", "noderivatives", "noderivs"]) || lower.contains("no derivatives");
+        match (nc, sa, nd) {
+            (false, false, false) => Self::CcBy,
+            (false, true, _) => Self::CcBySa,
+            (false, false, true) => Self::CcByNd,
+            (true, false, false) => Self::CcByNc,
+            (true, true, _) => Self::CcByNcSa,
+            (true, false, true) => Self::CcByNcNd,
+        }
+    }
+
+    /// Recognize a license from its Creative Commons address
+    ///
+    /// Any scheme, `www.`, trailing slash, jurisdiction port, deed or legal
+    /// code page, query and fragment are accepted. Addresses of anything
+    /// else, or of a version Creative Commons never published, are
+    /// [`License::Unknown`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::License;
+    ///
+    /// for url in [
+    ///     "http://creativecommons.org/licenses/by/3.0/",
+    ///     "https://creativecommons.org/licenses/by/4.0",
+    ///     "https://www.creativecommons.org/licenses/by/2.5/scotland/deed.en",
+    ///     "creativecommons.org/licenses/by/4.0/legalcode#s1",
+    /// ] {
+    ///     assert_eq!(License::parse_url(url), License::CcBy);
+    /// }
+    /// assert_eq!(License::parse_url("http://creativecommons.org/publicdomain/zero/1.0/"), License::Cc0);
+    /// assert_eq!(License::parse_url("http://creativecommons.org/licenses/by-nd-nc/1.0/"), License::CcByNcNd);
+    /// assert_eq!(License::parse_url("http://creativecommons.org/licenses/sampling+/1.0/"), License::SamplingPlus);
+    ///
+    /// for url in [
+    ///     "http://creativecommons.org/publicdomain/mark/1.0/",
+    ///     "https://creativecommons.org/licenses/by/5.0/",
+    ///     "https://example.com/licenses/by/4.0/",
+    /// ] {
+    ///     assert_eq!(License::parse_url(url), License::Unknown { raw: url.to_string() });
+    /// }
+    /// ```
+    pub fn parse_url(url: &str) -> Self {
+        match Self::from_url(url) {
+            Some((license, _)) => license,
+            None => Self::Unknown { raw: url.to_string() },
+        }
+    }
+
+    /// License and version of a Creative Commons address
+    fn from_url(url: &str) -> Option<(Self, Option<String>)> {
+        let lower = url.trim().to_lowercase().replace("%2b", "+");
+        let rest = lower
+            .strip_prefix("https://")
+            .or_else(|| lower.strip_prefix("http://"))
+            .unwrap_or(&lower);
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let rest = rest.strip_prefix("www.").unwrap_or(rest);
+        let mut parts = rest.strip_prefix("creativecommons.org/")?.split('/').filter(|part| !part.is_empty());
+
+        let license = match (parts.next()?, parts.next()?) {
+            ("publicdomain", "zero") => Self::Cc0,
+            ("licenses", "by") => Self::CcBy,
+            ("licenses", "by-sa") => Self::CcBySa,
+            ("licenses", "by-nd") => Self::CcByNd,
+            ("licenses", "by-nc") => Self::CcByNc,
+            ("licenses", "by-nc-sa") => Self::CcByNcSa,
+            ("licenses", "by-nc-nd" | "by-nd-nc") => Self::CcByNcNd,
+            ("licenses", "sampling+") => Self::SamplingPlus,
+            _ => return None,
+        };
+        let Some(version) = parts.next() else {
+            return Some((license, None));
+        };
+        let version = normalize_version(version).filter(|version| license.versions().contains(&version.as_str()))?;
+
+        // A jurisdiction port, then the deed or legal code page
+        let page = |part: &str| part.starts_with("deed") || part.starts_with("legalcode");
+        let mut rest = parts.peekable();
+        if rest.peek().is_some_and(|part| !page(part)) {
+            rest.next().filter(|port| port.chars().all(|c| c.is_ascii_alphabetic() || c == '-'))?;
+        }
+        if rest.next().is_some_and(|part| !page(part)) || rest.next().is_some() {
+            return None;
+        }
+        Some((license, Some(version)))
+    }
+
+    /// Versions of the license Creative Commons published, oldest first;
+    /// none for unknown licenses
+    pub fn versions(&self) -> &'static [&'static str] {
+        match self {
+            Self::Cc0 | Self::SamplingPlus => &FIRST_VERSION,
+            Self::Unknown { .. } => &[],
+            _ => &BY_VERSIONS,
+        }
+    }
+
+    /// Address of the license's deed, in `version` or its latest version
+    ///
+    /// `None` for unknown licenses and versions Creative Commons never
+    /// published.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::License;
+    ///
+    /// assert_eq!(License::CcByNc.url(None).as_deref(), Some("https://creativecommons.org/licenses/by-nc/4.0/"));
+    /// assert_eq!(License::CcBy.url(Some("3.0")).as_deref(), Some("https://creativecommons.org/licenses/by/3.0/"));
+    /// assert_eq!(License::Cc0.url(Some("4.0")), None);
+    /// ```
+    pub fn url(&self, version: Option<&str>) -> Option<String> {
+        let versions = self.versions();
+        let version = match version {
+            Some(version) => normalize_version(version.trim()).filter(|version| versions.contains(&version.as_str()))?,
+            None => versions.last()?.to_string(),
+        };
+        let path = match self {
+            Self::Cc0 => "publicdomain/zero",
+            Self::CcBy => "licenses/by",
+            Self::CcBySa => "licenses/by-sa",
+            Self::CcByNd => "licenses/by-nd",
+            Self::CcByNc => "licenses/by-nc",
+            Self::CcByNcSa => "licenses/by-nc-sa",
+            // Version 1.0 named its terms in another order
+            Self::CcByNcNd if version == "1.0" => "licenses/by-nd-nc",
+            Self::CcByNcNd => "licenses/by-nc-nd",
+            Self::SamplingPlus => "licenses/sampling+",
+            Self::Unknown { .. } => return None,
+        };
+        Some(format!("https://creativecommons.org/{}/{}/", path, version))
+    }
+}
+
+/// License of a sound, with the version and address it was given with
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{License, LicenseInfo, SoundMetadata};
+///
+/// let metadata = SoundMetadata {
+///     license: "http://creativecommons.org/licenses/by/3.0/".to_string(),
+///     ..Default::default()
+/// };
+/// let info = metadata.license_info();
+/// assert_eq!(info.license, License::CcBy);
+/// assert_eq!(info.version.as_deref(), Some("3.0"));
+/// assert_eq!(info.url.as_deref(), Some("http://creativecommons.org/licenses/by/3.0/"));
+/// assert_eq!(info.canonical_url().as_deref(), Some("https://creativecommons.org/licenses/by/3.0/"));
+///
+/// assert_eq!(LicenseInfo::parse("CC BY-SA 4.0").version.as_deref(), Some("4.0"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LicenseInfo {
+    /// The license recognized
+    pub license: License,
+
+    /// Its version, e.g. `3.0`, when given
+    pub version: Option<String>,
+
+    /// The address the license was given as, unchanged
+    pub url: Option<String>,
+}
+
+impl LicenseInfo {
+    /// Recognize a license, its version and address from a name or address
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if is_url(text) {
+            let (license, version) = License::from_url(text)
+                .unwrap_or_else(|| (License::Unknown { raw: text.to_string() }, None));
+            return Self { license, version, url: Some(text.to_string()) };
+        }
+
+        let license = License::parse(text);
+        let version = text
+            .split_whitespace()
+            .filter(|word| word.contains('.'))
+            .find_map(normalize_version)
+            .filter(|version| license.versions().contains(&version.as_str()));
+        Self { license, version, url: None }
+    }
+
+    /// Address to credit the license with: the deed of a recognized license
+    /// in its version, or the address it was given as
+    pub fn canonical_url(&self) -> Option<String> {
+        self.license.url(self.version.as_deref()).or_else(|| self.url.clone())
+    }
+}
+
+impl SoundMetadata {
+    /// The sound's license, with its version and address
+    pub fn license_info(&self) -> LicenseInfo {
+        LicenseInfo::parse(&self.license)
+    }
+}
+
+impl fmt::Display for License {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Cc0 => "CC0",
+            Self::CcBy => "CC BY",
+            Self::CcBySa => "CC BY-SA",
+            Self::CcByNd => "CC BY-ND",
+            Self::CcByNc => "CC BY-NC",
+            Self::CcByNcSa => "CC BY-NC-SA",
+            Self::CcByNcNd => "CC BY-NC-ND",
+            Self::SamplingPlus => "Sampling+",
+            Self::Unknown { raw } => raw,
+        };
+        f.write_str(name)
+    }
+}
+
+/// What happens to a download whose license the policy doesn't allow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationAction {
+    /// Refuse it with [`VaultError::LicensePolicy`], before fetching the file
+    #[default]
+    Block,
+    /// Download it, tag the sound with [`LICENSE_REVIEW_TAG`] and send
+    /// [`VaultEvent::LicenseViolation`]
+    WarnAndTag,
+}
+
+/// Licenses remote sounds may be downloaded under
+///
+/// The policy applies to every download: single, queued or from a
+/// subscription. Queued downloads it blocks fail, and subscriptions track
+/// the blocked sounds without their file, listing them in
+/// [`SubscriptionSync::blocked`](crate::SubscriptionSync::blocked).
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{
+///     LICENSE_REVIEW_TAG, License, LicensePolicy, RemoteFuture, RemoteSource, SoundMetadata, SoundVault, VaultConfig,
+///     VaultError, VaultEvent, ViolationAction,
+/// };
+/// use std::time::Duration;
+/// # use std::path::Path;
+/// #
+/// # /// Take 1 is CC0, any other take CC BY-NC
+/// # struct Studio;
+/// #
+/// # impl RemoteSource for Studio {
+/// #     fn name(&self) -> &str { "studio" }
+/// #     fn search<'a>(&'a self, _: &'a str, _: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> { Box::pin(async { Ok(Vec::new()) }) }
+/// #     fn get_by_id<'a>(&'a self, id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+/// #         let license = match id {
+/// #             "1" => "http://creativecommons.org/publicdomain/zero/1.0/",
+/// #             _ => "http://creativecommons.org/licenses/by-nc/3.0/",
+/// #         };
+/// #         Box::pin(async move { Ok(SoundMetadata { name: format!("take-{}.wav", id), license: license.to_string(), ..Default::default() }) })
+/// #     }
+/// #     fn download<'a>(&'a self, _: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+/// #         Box::pin(async move { Ok(std::fs::write(target, b"take")?) })
+/// #     }
+/// # }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+/// config.license_policy = Some(LicensePolicy {
+///     allowed: vec![License::Cc0, License::CcBy],
+///     on_violation: ViolationAction::Block,
+/// });
+/// let mut vault = SoundVault::new(config.clone()).await?;
+/// vault.add_remote_source(Box::new(Studio))?;
+///
+/// vault.download_remote("studio", "1").await?;
+/// let blocked = vault.download_remote("studio", "2").await;
+/// assert!(matches!(blocked, Err(VaultError::LicensePolicy { license: License::CcByNc, .. })));
+/// assert!(vault.search_local("take-2", None).await?.is_empty());
+/// vault.close(Duration::from_secs(5)).await?;
+///
+/// // Downloading anyway, for review
+/// config.license_policy.as_mut().unwrap().on_violation = ViolationAction::WarnAndTag;
+/// let mut vault = SoundVault::new(config).await?;
+/// vault.add_remote_source(Box::new(Studio))?;
+/// let mut events = vault.subscribe();
+/// let id = vault.download_remote("studio", "2").await?;
+/// assert!(vault.get_sound(&id).await?.metadata.tags.contains(&LICENSE_REVIEW_TAG.to_string()));
+/// assert_eq!(events.recv().await?, VaultEvent::LicenseViolation { sound_id: id, license: License::CcByNc });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LicensePolicy {
+    /// Licenses allowed
+    pub allowed: Vec<License>,
+
+    /// What happens to sounds under other licenses
+    pub on_violation: ViolationAction,
+}
+
+impl LicensePolicy {
+    /// Check whether the policy allows a license, as stored on a sound
+    pub fn allows(&self, license: &str) -> bool {
+        self.allowed.contains(&License::parse(license))
+    }
+}
+
+impl LocalLibrary {
+    /// Check a sound about to be downloaded against the license policy
+    ///
+    /// # Returns
+    ///
+    /// Whether the sound must be tagged for review once downloaded
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::LicensePolicy` if the policy blocks its license
+    pub(crate) fn check_license(&self, metadata: &SoundMetadata) -> Result<bool> {
+        let Some(policy) = &self.license_policy else {
+            return Ok(false);
+        };
+        if policy.allows(&metadata.license) {
+            return Ok(false);
+        }
+
+        match policy.on_violation {
+            ViolationAction::Block => Err(VaultError::LicensePolicy {
+                sound: metadata.name.clone(),
+                license: License::parse(&metadata.license),
+            }),
+            ViolationAction::WarnAndTag => Ok(true),
+        }
+    }
+
+    /// Tag a downloaded sound whose license the policy doesn't allow, and
+    /// tell subscribers
+    pub(crate) async fn flag_license(&self, id: &str) -> Result<()> {
+        let patch = MetadataPatch {
+            add_tags: vec![LICENSE_REVIEW_TAG.to_string()],
+            ..Default::default()
+        };
+        self.patch_metadata(id, patch).await?;
+
+        let license = License::parse(&self.get_sound(id).await?.metadata.license);
+        self.emit(VaultEvent::LicenseViolation {
+            sound_id: id.to_string(),
+            license,
+        });
+        Ok(())
+    }
+}
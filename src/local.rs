@@ -1,94 +1,444 @@
 //! Module for managing the local sound library
 
+use crate::access::AccessLog;
+use crate::archive::{Archive, ArchivalCodec};
+use crate::artifacts::ArtifactPolicy;
+use crate::audio::probe_file;
+use crate::audit::{AuditOperation, audit_diff};
+use crate::blob::BlobStore;
+use crate::cache::SoundCache;
+use crate::changes::{ChangeEntity, ChangeKind};
+use crate::collation::Collator;
+use crate::config::VaultConfig;
 use crate::error::{Result, VaultError};
-use crate::models::{Collection, Sound, SoundMetadata, SoundSource};
-use sqlx::{Pool, Sqlite};
+use crate::events::{EVENT_CAPACITY, VaultEvent};
+use crate::expiration::ExpireAction;
+use crate::human::HumanDuration;
+use crate::governor::{ResourceGovernor, ResourceLimits};
+use crate::jobs::JobQueue;
+use crate::journal::{OpKind, RecoveryReport};
+use crate::license::LicensePolicy;
+use crate::ordering::{is_dense, renumber_all};
+use crate::models::{
+    Availability, Collection, CollectionDefaults, CollectionSummary, Localization, Sound, SoundMetadata, SoundSource,
+    Trim, canonical_tags, normalize_lang,
+};
+use crate::patch::MetadataPatch;
+use crate::paths::copy_atomic;
+use crate::provenance::{ProvenanceEntry, ProvenanceMode, hostname};
+use crate::query::SoundFilter;
+use crate::quota::QuotaAction;
+use crate::review::SoundStatus;
+#[cfg(feature = "s3")]
+use crate::s3::S3Store;
+use crate::similar::SimilarityWeights;
+use crate::slice::SLICE_CONSUMER;
+use crate::slug::SlugPolicy;
+use crate::sniff::{FileFormat, mismatched_format, sniff_format};
+use crate::tables::Tables;
+use crate::undo::OperationRecord;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Row, Sqlite, SqliteConnection};
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{Notify, OnceCell, broadcast};
 use uuid::Uuid;
 
+/// ID, name, description, defaults, expiry and version of a collection
+type CollectionRow = (String, String, Option<String>, Option<String>, Option<DateTime<Utc>>, i64);
+
 /// Manager for local sound files and metadata
 pub struct LocalLibrary {
-    /// Database connection pool
-    db: Pool<Sqlite>,
+    /// Database connection pool, used for writes
+    pub(crate) db: Pool<Sqlite>,
+    /// Read-only connection pool used by queries, so that reads don't wait
+    /// for writes; the same pool as `db` when the database is shared
+    pub(crate) reader: Pool<Sqlite>,
     /// Path to the library directory
-    library_path: PathBuf,
+    pub(crate) library_path: PathBuf,
+    /// Path to the database file
+    pub(crate) database_path: PathBuf,
+    /// Collator used to order names
+    pub(crate) collator: Collator,
+    /// Actor recorded in the audit log
+    pub(crate) actor: RwLock<Option<String>>,
+    /// UUID identifying the vault in URIs
+    pub(crate) vault_id: String,
+    /// Queue of CPU-heavy background jobs
+    pub(crate) jobs: JobQueue,
+    /// Limits on the transfers, jobs and write batches run at once
+    pub(crate) governor: ResourceGovernor,
+    /// Sender of events to subscribers
+    pub(crate) events: broadcast::Sender<VaultEvent>,
+    /// Analysis descriptors fetched with downloaded remote sounds
+    pub(crate) remote_descriptors: Vec<String>,
+    /// File operations settled when the library was opened
+    pub(crate) recovery: RecoveryReport,
+    /// Names of the tables, with the configured prefix
+    pub(crate) tables: Tables,
+    /// Name of this machine, recorded in provenance entries
+    pub(crate) hostname: Option<String>,
+    /// Availability of the files of sounds last seen, to notice changes
+    pub(crate) availability: Mutex<HashMap<String, Availability>>,
+    /// Sounds last fetched by ID
+    pub(crate) sound_cache: SoundCache,
+    /// Batch operations run by this process, oldest first
+    pub(crate) operations: Mutex<Vec<OperationRecord>>,
+    /// Limits on the generated files kept
+    pub(crate) artifact_policy: ArtifactPolicy,
+    /// Licenses remote sounds may be downloaded under
+    pub(crate) license_policy: Option<LicensePolicy>,
+    /// Name of the collection downloaded sounds are added to
+    pub(crate) auto_collect_downloads: Option<String>,
+    /// Days changes are kept in the change feed
+    pub(crate) change_retention: Option<HumanDuration>,
+    /// Key signing page cursors
+    pub(crate) page_cursor_secret: Option<String>,
+    /// Whether files are renamed after their true format
+    pub(crate) fix_extensions: bool,
+    /// Whether downloaded files keep the source's file name
+    pub(crate) keep_remote_file_names: bool,
+    /// Weights of the features compared by similarity search
+    pub(crate) similarity_weights: SimilarityWeights,
+    /// Most bytes the library may take
+    pub(crate) max_library_bytes: Option<u64>,
+    /// What to do when a file would exceed the limit
+    pub(crate) on_quota: QuotaAction,
+    /// Stores of sounds' files other than the library, by name
+    pub(crate) blob_stores: RwLock<BTreeMap<String, Arc<dyn BlobStore>>>,
+    /// Store new files move to, if not the library
+    pub(crate) default_blob_store: Option<String>,
+    /// Whether new files are stored content-addressed
+    pub(crate) content_addressed: AtomicBool,
+    /// Whether slugs follow renames
+    pub(crate) slug_policy: SlugPolicy,
+    /// What to do with expired sounds when the vault opens
+    pub(crate) expire_on_open: Option<ExpireAction>,
+    /// Similarity from which imports are reported as possible duplicates
+    #[cfg(feature = "analysis")]
+    pub(crate) near_duplicate_threshold: Option<f64>,
+    /// Number of imports and downloads under way
+    pub(crate) foreground: AtomicUsize,
+    /// Notified when the last import or download under way ends
+    pub(crate) foreground_idle: Notify,
+    /// Set once the startup work left for after opening is done
+    pub(crate) warmed: OnceCell<()>,
+    /// When the vault was opened, bounding the sessions warming up purges
+    pub(crate) opened_at: DateTime<Utc>,
+    /// Sounds accessed since the last flush of their last-accessed time
+    pub(crate) accesses: AccessLog,
+}
+
+/// Version of the database schema, stored in `vault_info`
+///
+/// Bump it when the tables change: vaults last opened with the same version
+/// and configuration skip creating them.
+pub(crate) const SCHEMA_VERSION: i64 = 5;
+
+/// Key in `vault_info` of the fingerprint of the schema and configuration
+/// the vault was last fully opened with
+const OPEN_FINGERPRINT: &str = "open_fingerprint";
+
+/// Fingerprint of what opening a vault prepares: its tables, and the data
+/// derived from the configuration, like sort keys and slugs
+fn open_fingerprint(config: &VaultConfig) -> String {
+    let inputs = format!(
+        "{}\n{}\n{}\n{:?}\n{:?}\n{:?}\n{}",
+        SCHEMA_VERSION,
+        env!("CARGO_PKG_VERSION"),
+        config.table_prefix,
+        config.sort_locale,
+        config.storage_layout,
+        config.slug_policy,
+        cfg!(feature = "analysis"),
+    );
+    format!("{:x}", Sha256::digest(inputs))
 }
 
 impl LocalLibrary {
     /// Create a new LocalLibrary
     ///
+    /// Unprefixed tables left by older versions are renamed to the configured
+    /// prefix, unless the database is `shared` with the host application.
+    ///
     /// # Arguments
     ///
     /// * `db` - SQLite connection pool
-    /// * `library_path` - Path to the directory where sound files are stored
-    pub async fn new(db: Pool<Sqlite>, library_path: PathBuf) -> Result<Self> {
+    /// * `reader` - SQLite connection pool for reads
+    /// * `config` - Vault configuration, providing the library path and sort locale
+    /// * `shared` - Whether the database belongs to the host application
+    pub async fn new(db: Pool<Sqlite>, reader: Pool<Sqlite>, config: &VaultConfig, shared: bool) -> Result<Self> {
+        let library_path = config.library_path.clone();
+        let tables = Tables::new(&config.table_prefix)?;
+
         // Ensure the library directory exists
         if !library_path.exists() {
             std::fs::create_dir_all(&library_path)
                 .map_err(|e| VaultError::FileSystem(format!("Failed to create library directory: {}", e)))?;
         }
 
-        // Initialize database schema if needed
-        Self::init_db_schema(&db).await?;
+        // A vault last opened with the same schema and configuration has its
+        // tables, and the data derived from them, ready
+        let fingerprint = open_fingerprint(config);
+        let ready = Self::opened_with(&db, &tables).await == Some((SCHEMA_VERSION, fingerprint.clone()));
+        if !ready {
+            Self::check_table_prefix(&db, &tables, shared).await?;
+            Self::init_db_schema(&db, &tables).await?;
+        }
+        let vault_id = Self::load_vault_id(&db, &tables).await?;
+
+        // Settle the file operations a crash interrupted
+        let recovery = Self::recover_ops(&db, &tables).await?;
+
+        let governor = ResourceGovernor::new(ResourceLimits::from_config(config));
+        let library = Self {
+            db,
+            reader,
+            library_path,
+            database_path: config.database_path.clone(),
+            collator: Collator::new(config.sort_locale.as_deref()),
+            actor: RwLock::new(None),
+            vault_id,
+            jobs: JobQueue::new(governor.decodes.clone()),
+            governor,
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            remote_descriptors: config.remote_descriptors.clone(),
+            hostname: if config.record_hostname { hostname() } else { None },
+            availability: Mutex::new(HashMap::new()),
+            sound_cache: SoundCache::new(config.sound_cache_capacity),
+            operations: Mutex::new(Vec::new()),
+            artifact_policy: config.artifacts.clone(),
+            license_policy: config.license_policy.clone(),
+            auto_collect_downloads: config.auto_collect_downloads.clone(),
+            change_retention: config.change_retention,
+            page_cursor_secret: config.page_cursor_secret.clone(),
+            fix_extensions: config.fix_extensions,
+            keep_remote_file_names: config.keep_remote_file_names,
+            similarity_weights: config.similarity_weights,
+            max_library_bytes: config.max_library_bytes.map(u64::from),
+            on_quota: config.on_quota,
+            blob_stores: RwLock::new(BTreeMap::new()),
+            default_blob_store: config.default_blob_store.clone(),
+            content_addressed: AtomicBool::new(false),
+            slug_policy: config.slug_policy,
+            expire_on_open: config.expire_on_open,
+            #[cfg(feature = "analysis")]
+            near_duplicate_threshold: config.near_duplicate_threshold,
+            foreground: AtomicUsize::new(0),
+            foreground_idle: Notify::new(),
+            warmed: OnceCell::new(),
+            opened_at: Utc::now(),
+            accesses: AccessLog::default(),
+            recovery,
+            tables,
+        };
+
+        #[cfg(feature = "s3")]
+        if let Some(s3) = &config.s3 {
+            library.add_blob_store(Arc::new(S3Store::new(s3.clone())))?;
+        }
+
+        library.load_storage_layout(config.storage_layout).await?;
+
+        if !ready {
+            // Sort keys depend on the locale they were computed for
+            library.refresh_sort_keys().await?;
+            library.index_search_words().await?;
+            library.sniff_stored_formats().await?;
+            library.build_feature_vectors().await?;
+            library.assign_missing_slugs().await?;
+        }
+        library.settle_quota().await?;
+        if let Some(action) = library.expire_on_open {
+            library.enforce_expirations(action).await?;
+        }
+        if !ready {
+            sqlx::query(&library.sql(
+                r#"
+                INSERT INTO vault_info (key, value) VALUES (?, ?)
+                ON CONFLICT(key) DO UPDATE SET value = excluded.value
+                "#,
+            ))
+            .bind(OPEN_FINGERPRINT)
+            .bind(fingerprint)
+            .execute(&library.db)
+            .await?;
+        }
+
+        Ok(library)
+    }
+
+    /// Schema version and fingerprint the vault was last fully opened with;
+    /// `None` before the tables exist
+    async fn opened_with(db: &Pool<Sqlite>, tables: &Tables) -> Option<(i64, String)> {
+        let rows: Vec<(String, String)> =
+            sqlx::query_as(&tables.sql("SELECT key, value FROM vault_info WHERE key IN ('schema_version', ?)"))
+                .bind(OPEN_FINGERPRINT)
+                .fetch_all(db)
+                .await
+                .ok()?;
+        let value = |key: &str| rows.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone());
+        Some((value("schema_version")?.parse().ok()?, value(OPEN_FINGERPRINT)?))
+    }
 
-        Ok(Self { db, library_path })
+    /// Do the startup work opening leaves for later, once
+    ///
+    /// Downloads cut short by the last shutdown go back in the queue,
+    /// sessions that had expired when the vault was opened are purged, so
+    /// those expiring afterwards are left to a purge the caller can see,
+    /// and the change feed is pruned.
+    pub(crate) async fn warm_up(&self) -> Result<()> {
+        self.warmed
+            .get_or_try_init(|| async {
+                self.requeue_interrupted_downloads().await?;
+                self.purge_sessions_expired_at(self.opened_at).await?;
+                self.apply_change_retention().await
+            })
+            .await?;
+        Ok(())
     }
 
     /// Initialize the database schema if needed
-    async fn init_db_schema(db: &Pool<Sqlite>) -> Result<()> {
+    async fn init_db_schema(db: &Pool<Sqlite>, tables: &Tables) -> Result<()> {
         // Create sounds table
         sqlx::query(
-            r#"
+            &tables.sql(r#"
             CREATE TABLE IF NOT EXISTS sounds (
                 id TEXT PRIMARY KEY,
                 name TEXT NOT NULL,
                 description TEXT,
                 tags TEXT,
                 duration REAL,
+                channels INTEGER,
+                sample_rate INTEGER,
+                rating INTEGER,
+                trim_start REAL,
+                trim_end REAL,
                 license TEXT,
                 path TEXT,
+                external BOOLEAN NOT NULL DEFAULT 0,
+                packed BOOLEAN NOT NULL DEFAULT 0,
                 freesound_id INTEGER,
+                remote_id TEXT,
+                source TEXT,
+                hash TEXT,
+                derived_from TEXT,
+                archive_codec TEXT,
+                archive_hash TEXT,
+                format TEXT,
+                last_played_at TIMESTAMP,
+                last_accessed_at TIMESTAMP,
+                last_verified_at TIMESTAMP,
+                corrupt BOOLEAN NOT NULL DEFAULT 0,
+                locked BOOLEAN NOT NULL DEFAULT 0,
+                features BLOB,
+                file_size INTEGER,
+                storage_backend TEXT,
+                fingerprint BLOB,
+                slug TEXT,
+                expires_at TIMESTAMP,
+                sort_key BLOB,
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
             )
-            "#,
+            "#),
         )
         .execute(db)
         .await?;
+        Self::ensure_column(db, tables, "sounds", "hash", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "sort_key", "BLOB").await?;
+        Self::ensure_column(db, tables, "sounds", "channels", "INTEGER").await?;
+        Self::ensure_column(db, tables, "sounds", "sample_rate", "INTEGER").await?;
+        Self::ensure_column(db, tables, "sounds", "source", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "remote_id", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "rating", "INTEGER").await?;
+        Self::ensure_column(db, tables, "sounds", "external", "BOOLEAN NOT NULL DEFAULT 0").await?;
+        Self::ensure_column(db, tables, "sounds", "derived_from", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "archive_codec", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "archive_hash", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "format", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "last_played_at", "TIMESTAMP").await?;
+        Self::ensure_column(db, tables, "sounds", "last_accessed_at", "TIMESTAMP").await?;
+        Self::ensure_column(db, tables, "sounds", "trim_start", "REAL").await?;
+        Self::ensure_column(db, tables, "sounds", "trim_end", "REAL").await?;
+        Self::ensure_column(db, tables, "sounds", "last_verified_at", "TIMESTAMP").await?;
+        Self::ensure_column(db, tables, "sounds", "corrupt", "BOOLEAN NOT NULL DEFAULT 0").await?;
+        Self::ensure_column(db, tables, "sounds", "locked", "BOOLEAN NOT NULL DEFAULT 0").await?;
+        Self::ensure_column(db, tables, "sounds", "features", "BLOB").await?;
+        Self::ensure_column(db, tables, "sounds", "file_size", "INTEGER").await?;
+        Self::ensure_column(db, tables, "sounds", "storage_backend", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "fingerprint", "BLOB").await?;
+        Self::ensure_column(db, tables, "sounds", "slug", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "expires_at", "TIMESTAMP").await?;
+        Self::ensure_column(db, tables, "sounds", "packed", "BOOLEAN NOT NULL DEFAULT 0").await?;
+        Self::ensure_column(db, tables, "sounds", "object_hash", "TEXT").await?;
+        Self::ensure_column(db, tables, "sounds", "status", "TEXT NOT NULL DEFAULT 'active'").await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS sounds_hash ON sounds (hash)"))
+            .execute(db)
+            .await?;
+        sqlx::query(&tables.sql("CREATE UNIQUE INDEX IF NOT EXISTS sounds_slug ON sounds (slug)"))
+            .execute(db)
+            .await?;
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS sounds_object_hash ON sounds (object_hash)"))
+            .execute(db)
+            .await?;
 
         // Create collections table
         sqlx::query(
-            r#"
+            &tables.sql(r#"
             CREATE TABLE IF NOT EXISTS collections (
                 id TEXT PRIMARY KEY,
                 name TEXT NOT NULL,
                 description TEXT,
+                defaults TEXT,
+                sort_key BLOB,
+                expires_at TIMESTAMP,
+                version INTEGER NOT NULL DEFAULT 0,
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
                 updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
             )
-            "#,
+            "#),
         )
         .execute(db)
         .await?;
+        Self::ensure_column(db, tables, "collections", "defaults", "TEXT").await?;
+        Self::ensure_column(db, tables, "collections", "sort_key", "BLOB").await?;
+        Self::ensure_column(db, tables, "collections", "expires_at", "TIMESTAMP").await?;
+        Self::ensure_column(db, tables, "collections", "version", "INTEGER NOT NULL DEFAULT 0").await?;
 
         // Create collection_sounds table for many-to-many relationship
         sqlx::query(
-            r#"
+            &tables.sql(r#"
             CREATE TABLE IF NOT EXISTS collection_sounds (
                 collection_id TEXT,
                 sound_id TEXT,
+                position INTEGER,
                 PRIMARY KEY (collection_id, sound_id),
                 FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE,
                 FOREIGN KEY (sound_id) REFERENCES sounds(id) ON DELETE CASCADE
             )
-            "#,
+            "#),
         )
         .execute(db)
         .await?;
+        Self::ensure_column(db, tables, "collection_sounds", "position", "INTEGER").await?;
+
+        // Members added before positions were kept keep the order they were added in
+        let unordered: bool =
+            sqlx::query_scalar(&tables.sql("SELECT EXISTS (SELECT 1 FROM collection_sounds WHERE position IS NULL)"))
+                .fetch_one(db)
+                .await?;
+        if unordered {
+            renumber_all(&mut *db.acquire().await?, tables).await?;
+        }
 
         // Create metadata table for custom metadata
         sqlx::query(
-            r#"
+            &tables.sql(r#"
             CREATE TABLE IF NOT EXISTS metadata (
                 object_id TEXT NOT NULL,
                 object_type TEXT NOT NULL,
@@ -96,11 +446,550 @@ impl LocalLibrary {
                 value TEXT,
                 PRIMARY KEY (object_id, object_type, key)
             )
-            "#,
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Only one collection may be marked as the one downloads go to
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE UNIQUE INDEX IF NOT EXISTS metadata_auto_collect ON metadata (value)
+            WHERE object_type = 'collection' AND key = 'auto_collect_downloads'
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create custom_fields table declaring custom metadata keys
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS custom_fields (
+                key TEXT PRIMARY KEY,
+                spec TEXT NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create import_templates table holding named import metadata
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS import_templates (
+                name TEXT PRIMARY KEY,
+                template TEXT NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create pending_ops table journaling file operations in progress
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS pending_ops (
+                id TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                created TEXT NOT NULL,
+                obsolete TEXT NOT NULL,
+                committed BOOLEAN NOT NULL DEFAULT 0,
+                started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create sound_descriptors table for remote analysis descriptors
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS sound_descriptors (
+                sound_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                value REAL NOT NULL,
+                PRIMARY KEY (sound_id, name)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS sound_descriptors_value ON sound_descriptors (name, value)"))
+            .execute(db)
+            .await?;
+
+        // Create provenance table chaining where the files of sounds came from
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS provenance (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                sound_id TEXT NOT NULL,
+                mode TEXT NOT NULL,
+                source_path TEXT,
+                source TEXT,
+                remote_id TEXT,
+                source_url TEXT,
+                hostname TEXT,
+                recorded_at TIMESTAMP NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS provenance_sound ON provenance (sound_id)"))
+            .execute(db)
+            .await?;
+
+        // Create artifacts table tracking generated files and when they were last used
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS artifacts (
+                path TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                sound_id TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                accessed_at TIMESTAMP NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create sound_trigrams table indexing the words of names and tags
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS sound_trigrams (
+                trigram TEXT NOT NULL,
+                sound_id TEXT NOT NULL,
+                word TEXT NOT NULL,
+                PRIMARY KEY (trigram, sound_id, word)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS sound_trigrams_sound ON sound_trigrams (sound_id)"))
+            .execute(db)
+            .await?;
+
+        // Create sound_groups and sound_group_members tables for ordered groups of variations
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS sound_groups (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS sound_group_members (
+                group_id TEXT NOT NULL,
+                sound_id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                PRIMARY KEY (group_id, sound_id)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS sound_group_members_sound ON sound_group_members (sound_id)"))
+            .execute(db)
+            .await?;
+
+        // Create tag_aliases and tag_parents tables for synonyms and the hierarchy of tags
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS tag_aliases (
+                alias TEXT PRIMARY KEY,
+                tag TEXT NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS tag_parents (
+                tag TEXT PRIMARY KEY,
+                parent TEXT NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create localized_text table for translated sound texts
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS localized_text (
+                object_id TEXT NOT NULL,
+                field TEXT NOT NULL,
+                lang TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (object_id, field, lang)
+            )
+            "#),
         )
         .execute(db)
         .await?;
 
+        // Create audit_log table recording mutations
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                operation TEXT NOT NULL,
+                entity_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                actor TEXT,
+                changes TEXT
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS audit_log_entity ON audit_log (entity_id)"))
+            .execute(db)
+            .await?;
+        Self::ensure_column(db, tables, "audit_log", "operation_id", "TEXT").await?;
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS audit_log_operation ON audit_log (operation_id)"))
+            .execute(db)
+            .await?;
+
+        // Create change_feed table listing the sounds and collections that changed
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS change_feed (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                entity TEXT NOT NULL,
+                entity_id TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                changed_at TEXT NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create subscriptions table for collections fed by a Freesound search
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS subscriptions (
+                id TEXT PRIMARY KEY,
+                collection_id TEXT NOT NULL,
+                query TEXT NOT NULL,
+                filter TEXT,
+                auto_download BOOLEAN NOT NULL DEFAULT 0,
+                last_synced_at TEXT,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create subscription_seen table remembering the results each subscription handled
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS subscription_seen (
+                subscription_id TEXT NOT NULL,
+                freesound_id INTEGER NOT NULL,
+                PRIMARY KEY (subscription_id, freesound_id),
+                FOREIGN KEY (subscription_id) REFERENCES subscriptions(id) ON DELETE CASCADE
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create sync_sources table linking mirrored files to their sounds
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS sync_sources (
+                source_root TEXT NOT NULL,
+                source_path TEXT NOT NULL,
+                sound_id TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                hash TEXT NOT NULL,
+                PRIMARY KEY (source_root, source_path)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create download_queue table persisting downloads until they're done
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS download_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                source TEXT NOT NULL,
+                remote_id TEXT NOT NULL,
+                collection_id TEXT,
+                requested_at TEXT NOT NULL,
+                state TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                sound_id TEXT
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create quota_reservations table holding room for files being added
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS quota_reservations (
+                id TEXT PRIMARY KEY,
+                bytes INTEGER NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create checkouts table recording sounds out for editing
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS checkouts (
+                sound_id TEXT PRIMARY KEY,
+                token TEXT NOT NULL,
+                working_path TEXT NOT NULL,
+                actor TEXT,
+                hash TEXT,
+                checked_out_at TIMESTAMP NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create permissions table holding the role of each actor
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS permissions (
+                actor TEXT PRIMARY KEY,
+                role TEXT NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create soft_limits table holding the most a metadata value should hold
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS soft_limits (
+                dimension TEXT NOT NULL,
+                value TEXT NOT NULL,
+                max_sounds INTEGER,
+                max_bytes INTEGER,
+                max_duration REAL,
+                PRIMARY KEY (dimension, value)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create objects table counting the sounds sharing each content-addressed file
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS objects (
+                hash TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                refs INTEGER NOT NULL
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create sound_uses table recording the projects and patches using sounds
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS sound_uses (
+                sound_id TEXT NOT NULL,
+                consumer TEXT NOT NULL,
+                location TEXT NOT NULL,
+                used_at TIMESTAMP NOT NULL,
+                PRIMARY KEY (sound_id, consumer, location)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(&tables.sql("CREATE INDEX IF NOT EXISTS sound_uses_location ON sound_uses (location)"))
+            .execute(db)
+            .await?;
+
+        // Create merged_sounds table recording the sounds merged from other vaults
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS merged_sounds (
+                source_vault TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                sound_id TEXT NOT NULL,
+                matched TEXT NOT NULL,
+                conflicts TEXT NOT NULL,
+                PRIMARY KEY (source_vault, source_id)
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Create vault_info table for vault-wide settings
+        sqlx::query(
+            &tables.sql(r#"
+            CREATE TABLE IF NOT EXISTS vault_info (
+                key TEXT PRIMARY KEY,
+                value TEXT
+            )
+            "#),
+        )
+        .execute(db)
+        .await?;
+
+        // Version 2 stores tags in canonical form
+        if Self::schema_version(db, tables).await? < 2 {
+            Self::canonicalize_stored_tags(db, tables).await?;
+        }
+
+        sqlx::query(&tables.sql(
+            r#"
+            INSERT INTO vault_info (key, value) VALUES ('schema_version', ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            "#,
+        ))
+        .bind(SCHEMA_VERSION.to_string())
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Rewrite stored tags in canonical form
+    async fn canonicalize_stored_tags(db: &Pool<Sqlite>, tables: &Tables) -> Result<()> {
+        let mut tx = db.begin().await?;
+        let rows: Vec<(String, String)> = sqlx::query_as(&tables.sql("SELECT id, tags FROM sounds WHERE tags IS NOT NULL"))
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for (id, tags) in rows {
+            // Unreadable tags are left for fetch_sound to skip
+            let Ok(parsed) = serde_json::from_str::<Vec<String>>(&tags) else {
+                continue;
+            };
+            let canonical = serde_json::to_string(&canonical_tags(parsed))?;
+            if canonical != tags {
+                sqlx::query(&tables.sql("UPDATE sounds SET tags = ? WHERE id = ?"))
+                    .bind(canonical)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Recompute name sort keys if the locale changed or some are missing
+    async fn refresh_sort_keys(&self) -> Result<()> {
+        let locale = self.collator.locale().unwrap_or_default();
+
+        let stored: Option<String> =
+            sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = 'sort_locale'"))
+                .fetch_optional(&self.reader)
+                .await?;
+        let missing: i64 = sqlx::query_scalar(
+            &self.sql(r#"
+            SELECT (SELECT COUNT(*) FROM sounds WHERE sort_key IS NULL)
+                 + (SELECT COUNT(*) FROM collections WHERE sort_key IS NULL)
+            "#),
+        )
+        .fetch_one(&self.reader)
+        .await?;
+
+        if stored.as_deref() == Some(locale) && missing == 0 {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+        for table in ["sounds", "collections"] {
+            let rows: Vec<(String, String)> = sqlx::query_as(&format!("SELECT id, name FROM {}", self.tables.name(table)))
+                .fetch_all(&mut *tx)
+                .await?;
+
+            for (id, name) in rows {
+                sqlx::query(&format!("UPDATE {} SET sort_key = ? WHERE id = ?", self.tables.name(table)))
+                    .bind(self.collator.sort_key(&name))
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO vault_info (key, value) VALUES ('sort_locale', ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            "#),
+        )
+        .bind(locale)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Version of the schema the database was last opened with
+    ///
+    /// Versions before 3 were kept in SQLite's `user_version`, which a
+    /// shared database leaves to the host application.
+    pub(crate) async fn schema_version(db: &Pool<Sqlite>, tables: &Tables) -> Result<i64> {
+        let stored: Option<String> =
+            sqlx::query_scalar(&tables.sql("SELECT value FROM vault_info WHERE key = 'schema_version'"))
+                .fetch_optional(db)
+                .await?;
+
+        match stored {
+            Some(version) => Ok(version.parse().unwrap_or_default()),
+            None => Ok(sqlx::query_scalar("PRAGMA user_version").fetch_one(db).await?),
+        }
+    }
+
+    /// Add a column to a table created by an older version of the schema
+    async fn ensure_column(db: &Pool<Sqlite>, tables: &Tables, table: &str, column: &str, definition: &str) -> Result<()> {
+        let table = tables.name(table);
+        let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(db)
+            .await?;
+
+        if !columns.iter().any(|row| row.get::<String, _>("name") == column) {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .execute(db)
+                .await?;
+        }
+
         Ok(())
     }
 
@@ -116,6 +1005,7 @@ impl LocalLibrary {
     /// The ID of the imported sound
     pub async fn import_file<P: AsRef<Path>>(&self, source_path: P, metadata: Option<SoundMetadata>) -> Result<String> {
         let source_path = source_path.as_ref();
+        let _foreground = self.foreground();
 
         // Check if file exists
         if !source_path.exists() {
@@ -133,92 +1023,507 @@ impl LocalLibrary {
             VaultError::FileSystem("Invalid source path".to_string())
         })?;
 
-        // Create target path
-        let target_path = self.library_path.join(&id).join(file_name);
+        // Create target path, named after the file's true format if asked
+        let mut target_path = self.library_path.join(&id).join(file_name);
+        if self.fix_extensions
+            && let Some(format) = mismatched_format(source_path)
+        {
+            target_path.set_extension(format.extension());
+        }
 
-        // Create directory for the sound
-        std::fs::create_dir_all(target_path.parent().unwrap()).map_err(|e| {
-            VaultError::FileSystem(format!("Failed to create directory: {}", e))
-        })?;
+        // Content already stored content-addressed isn't copied again
+        if self.content_addressed() {
+            let source = source_path.to_path_buf();
+            let hash = self.jobs.run(move || hash_file(&source)).await?;
+            if self.object_exists(&hash).await? {
+                target_path = self.object_path(&hash);
+            }
+        }
+        let copied = self.object_hash(&target_path).is_none();
 
-        // Copy file to library
-        std::fs::copy(source_path, &target_path).map_err(|e| {
-            VaultError::FileSystem(format!("Failed to copy file: {}", e))
-        })?;
+        let reservation = match copied {
+            true => self.reserve_space(std::fs::metadata(source_path)?.len()).await?,
+            false => None,
+        };
+        let result = async {
+            let created: &[&Path] = if copied { &[&target_path] } else { &[] };
+            let op = self.begin_op(OpKind::Import, created, &[]).await?;
+            let result = self.import_journaled(&id, source_path, target_path, metadata, &op).await;
+            self.settle_op(&op, result).await
+        }
+        .await;
+        self.release_space(reservation).await?;
+        result?;
+        #[cfg(feature = "analysis")]
+        self.flag_near_duplicates(&id).await;
+        self.offload_new_sound(&id).await;
+
+        Ok(id)
+    }
+
+    /// Copy a file into the library and record it, as journaled operation `op`
+    async fn import_journaled(
+        &self,
+        id: &str,
+        source_path: &Path,
+        target_path: PathBuf,
+        metadata: Option<SoundMetadata>,
+        op: &str,
+    ) -> Result<()> {
+        // A content-addressed file is named after its hash, not the source
+        let shared = self.object_hash(&target_path).is_some();
+        let file_name = if shared { source_path.file_name() } else { target_path.file_name() }.unwrap_or_default();
+
+        // Copy file to library, in a directory for the sound
+        if !shared {
+            std::fs::create_dir_all(target_path.parent().unwrap()).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to create directory: {}", e))
+            })?;
+            copy_atomic(source_path, &target_path).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to copy file: {}", e))
+            })?;
+        }
+        // Read duration and channel layout from the headers of supported formats
+        let stored = target_path.clone();
+        let (hash, info) = self.jobs.run(move || Ok((hash_file(&stored)?, probe_file(&stored).ok()))).await?;
 
         // Create metadata if not provided
-        let metadata = if let Some(mut meta) = metadata {
-            meta.id = id.clone();
+        let mut metadata = if let Some(mut meta) = metadata {
+            meta.id = id.to_string();
             meta.path = Some(target_path);
+            meta.external = false;
             meta.source = SoundSource::Local;
+            meta.hash = Some(hash);
             meta
         } else {
             // Extract basic metadata from file
             let name = file_name.to_string_lossy().to_string();
             SoundMetadata {
-                id: id.clone(),
+                id: id.to_string(),
                 name,
                 source: SoundSource::Local,
                 tags: Vec::new(),
                 description: String::new(),
-                duration: 0.0, // We'll need to implement audio file parsing to get this
+                duration: 0.0,
+                channels: None,
+                sample_rate: None,
+                rating: None,
+                trim: None,
                 license: "Unknown".to_string(),
                 path: Some(target_path),
+                external: false,
+                packed: false,
+                locked: false,
                 freesound_id: None,
+                remote_id: None,
+                hash: Some(hash),
+                derived_from: None,
+                archive: None,
+                format: None,
+                storage_backend: None,
+                slug: None,
+                expires_at: None,
+                last_accessed_at: None,
+                status: SoundStatus::Active,
                 custom: Default::default(),
+                localizations: Default::default(),
+                descriptors: Default::default(),
             }
         };
+        if let Some(info) = info {
+            if metadata.duration == 0.0 {
+                metadata.duration = info.duration();
+            }
+            metadata.channels = Some(info.channels);
+            metadata.sample_rate = Some(info.sample_rate);
+        }
+        let warnings = self.merge_embedded_tags(&mut metadata);
 
         // Insert into database
-        self.save_metadata(&metadata).await?;
+        let provenance = self.local_provenance(ProvenanceMode::Import, source_path);
+        self.insert_sound(&metadata, Some(op), Some(&provenance)).await?;
+        self.report_import_warnings(id, warnings);
+        Ok(())
+    }
 
-        Ok(id)
+    /// Replace the file of a sound, keeping its ID and metadata
+    ///
+    /// The new file is copied into the sound's folder of the library; its
+    /// hash and technical properties replace the old ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the sound
+    /// * `source_path` - Path to the new file
+    /// * `override_lock` - Replace the file of a locked sound too
+    pub async fn replace_file(&self, id: &str, source_path: &Path, override_lock: bool) -> Result<()> {
+        let before = self.get_sound(id).await?.metadata;
+        if before.locked && !override_lock {
+            return Err(VaultError::Locked(id.to_string()));
+        }
+        self.check_in_library(&before)?;
+
+        let file_name = source_path.file_name().ok_or_else(|| {
+            VaultError::FileSystem("Invalid source path".to_string())
+        })?;
+        let target_path = self.library_file(&self.library_path.join(id).join(file_name))?;
+
+        // Drop the old file once the new one is recorded, unless the new one
+        // overwrites it, which can't be undone; a content-addressed file goes
+        // with its last reference instead
+        let old = match &before.path {
+            Some(old) if !before.external && self.object_hash(old).is_none() => Some(self.library_file(old)?),
+            _ => None,
+        };
+        let op = match &old {
+            Some(old) if *old == target_path => self.begin_op(OpKind::ReplaceFile, &[], &[]).await?,
+            Some(old) => self.begin_op(OpKind::ReplaceFile, &[&target_path], &[old]).await?,
+            None => self.begin_op(OpKind::ReplaceFile, &[&target_path], &[]).await?,
+        };
+
+        let result = async {
+            std::fs::create_dir_all(target_path.parent().unwrap_or(&self.library_path)).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to create directory: {}", e))
+            })?;
+            copy_atomic(source_path, &target_path).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to copy file: {}", e))
+            })?;
+            let provenance = self.local_provenance(ProvenanceMode::ReplaceFile, source_path);
+            self.record_file(&before, target_path.clone(), Some(&op), Some(&provenance)).await
+        }
+        .await;
+        self.settle_op(&op, result).await
+    }
+
+    /// Point a sound at a new file inside the library, re-reading its hash
+    /// and technical properties
+    ///
+    /// `op` is the journaled operation the new file belongs to, committed
+    /// with the record; `provenance` tells where the file came from.
+    pub(crate) async fn record_file(
+        &self,
+        before: &SoundMetadata,
+        target_path: PathBuf,
+        op: Option<&str>,
+        provenance: Option<&ProvenanceEntry>,
+    ) -> Result<()> {
+        let id = &before.id;
+        let mut after = before.clone();
+        after.hash = Some(hash_file(&target_path)?);
+        after.external = false;
+        after.packed = false;
+        after.archive = None;
+        after.format = sniff_format(&target_path).ok().flatten();
+        match probe_file(&target_path) {
+            Ok(info) => {
+                after.duration = info.duration();
+                after.channels = Some(info.channels);
+                after.sample_rate = Some(info.sample_rate);
+            }
+            Err(_) => {
+                after.channels = None;
+                after.sample_rate = None;
+            }
+        }
+        let file_size = std::fs::metadata(&target_path).ok().map(|file| file.len() as i64);
+        after.path = Some(target_path);
+
+        let mut tx = self.db.begin().await?;
+        self.intern_file(&mut tx, &mut after).await?;
+        if let Some(old) = &before.path
+            && !before.external
+        {
+            self.release_object(&mut tx, old).await?;
+        }
+        sqlx::query(
+            &self.sql(r#"
+            UPDATE sounds
+            SET path = ?, hash = ?, external = 0, packed = 0, duration = ?, channels = ?, sample_rate = ?, format = ?,
+                archive_codec = NULL, archive_hash = NULL, file_size = ?, object_hash = ?, updated_at = CURRENT_TIMESTAMP,
+                fingerprint = CASE WHEN hash IS ? THEN fingerprint END
+            WHERE id = ?
+            "#),
+        )
+        .bind(after.path.as_ref().map(|p| p.to_string_lossy().to_string()))
+        .bind(&after.hash)
+        .bind(after.duration)
+        .bind(after.channels)
+        .bind(after.sample_rate)
+        .bind(after.format.map(|format| format.as_str()))
+        .bind(file_size)
+        .bind(after.path.as_deref().and_then(|path| self.object_hash(path)))
+        .bind(&after.hash)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        let mut changes = audit_diff(Some(&serde_json::to_value(before)?), Some(&serde_json::to_value(&after)?));
+        if let Some(entry) = provenance {
+            self.append_provenance(&mut tx, id, entry, &mut changes).await?;
+        }
+        self.audit(&mut tx, AuditOperation::UpdateSound, id, changes).await?;
+        if let Some(op) = op {
+            self.commit_op(&mut tx, op).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Add a new sound record
+    ///
+    /// `op` is the journaled operation that wrote its file, committed with
+    /// the record; `provenance` tells where the file came from.
+    pub(crate) async fn insert_sound(
+        &self,
+        metadata: &SoundMetadata,
+        op: Option<&str>,
+        provenance: Option<&ProvenanceEntry>,
+    ) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        self.insert_sound_on(&mut tx, metadata, op, provenance).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Add a new sound record on a given connection, e.g. within a
+    /// transaction that records more about it
+    pub(crate) async fn insert_sound_on(
+        &self,
+        conn: &mut SqliteConnection,
+        metadata: &SoundMetadata,
+        op: Option<&str>,
+        provenance: Option<&ProvenanceEntry>,
+    ) -> Result<()> {
+        let mut metadata = metadata.clone();
+        metadata.normalize_tags();
+        if metadata.archive.is_none()
+            && !metadata.packed
+            && let Some(path) = &metadata.path
+        {
+            metadata.format = sniff_format(path).ok().flatten();
+        }
+
+        self.check_custom_fields(conn, &metadata.custom, &[]).await?;
+        self.intern_file(conn, &mut metadata).await?;
+        self.save_metadata(conn, &metadata).await?;
+        let mut changes = audit_diff(None, Some(&serde_json::to_value(&metadata)?));
+        if let Some(entry) = provenance {
+            self.append_provenance(conn, &metadata.id, entry, &mut changes).await?;
+        }
+        self.audit(conn, AuditOperation::CreateSound, &metadata.id, changes).await?;
+        if let Some(op) = op {
+            self.commit_op(conn, op).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the local record of a Freesound sound
+    pub(crate) async fn find_freesound_sound(&self, freesound_id: i32) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE freesound_id = ? ORDER BY id LIMIT 1"))
+            .bind(freesound_id)
+            .fetch_optional(&self.reader)
+            .await?)
     }
 
     /// Save or update sound metadata in the database
-    async fn save_metadata(&self, metadata: &SoundMetadata) -> Result<()> {
+    pub(crate) async fn save_metadata(&self, conn: &mut SqliteConnection, metadata: &SoundMetadata) -> Result<()> {
+        self.forget_sound(&metadata.id);
+        if let Some(trim) = &metadata.trim {
+            trim.check()?;
+        }
+
         // Convert tags to JSON string
-        let tags_json = serde_json::to_string(&metadata.tags)
-            .map_err(|e| VaultError::Json(e))?;
+        let tags_json = serde_json::to_string(&metadata.tags)?;
+
+        // Size of a file stored in the library, counted against its limit
+        let file_size = match &metadata.path {
+            Some(path) if !metadata.external => std::fs::metadata(path).ok().map(|file| file.len() as i64),
+            _ => None,
+        };
+
+        // Keep the slug the metadata carries if no other sound has it
+        let slug = self.choose_slug(conn, &metadata.id, &metadata.name, metadata.slug.as_deref()).await?;
 
-        // Insert or update sound record
+        // Insert or update sound record (an upsert, since REPLACE would delete
+        // the row and cascade to its collection memberships)
         sqlx::query(
-            r#"
-            INSERT OR REPLACE INTO sounds
-            (id, name, description, tags, duration, license, path, freesound_id, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
-            "#,
+            &self.sql(r#"
+            INSERT INTO sounds
+            (id, name, description, tags, duration, channels, sample_rate, rating, trim_start, trim_end, license, path, external, packed, locked, freesound_id, remote_id, source, hash, derived_from, archive_codec, archive_hash, format, file_size, storage_backend, object_hash, slug, expires_at, status, sort_key, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                description = excluded.description,
+                tags = excluded.tags,
+                duration = excluded.duration,
+                channels = excluded.channels,
+                sample_rate = excluded.sample_rate,
+                rating = excluded.rating,
+                trim_start = excluded.trim_start,
+                trim_end = excluded.trim_end,
+                license = excluded.license,
+                path = excluded.path,
+                external = excluded.external,
+                packed = excluded.packed,
+                locked = excluded.locked,
+                freesound_id = excluded.freesound_id,
+                remote_id = excluded.remote_id,
+                source = excluded.source,
+                hash = excluded.hash,
+                derived_from = excluded.derived_from,
+                archive_codec = excluded.archive_codec,
+                archive_hash = excluded.archive_hash,
+                format = excluded.format,
+                file_size = excluded.file_size,
+                storage_backend = excluded.storage_backend,
+                object_hash = excluded.object_hash,
+                slug = excluded.slug,
+                expires_at = excluded.expires_at,
+                status = excluded.status,
+                fingerprint = CASE WHEN hash IS excluded.hash THEN fingerprint END,
+                sort_key = excluded.sort_key,
+                updated_at = excluded.updated_at
+            "#),
         )
         .bind(&metadata.id)
         .bind(&metadata.name)
         .bind(&metadata.description)
         .bind(tags_json)
         .bind(metadata.duration)
+        .bind(metadata.channels)
+        .bind(metadata.sample_rate)
+        .bind(metadata.rating)
+        .bind(metadata.trim.map(|trim| trim.start))
+        .bind(metadata.trim.and_then(|trim| trim.end))
         .bind(&metadata.license)
         .bind(metadata.path.as_ref().map(|p| p.to_string_lossy().to_string()))
+        .bind(metadata.external)
+        .bind(metadata.packed)
+        .bind(metadata.locked)
         .bind(metadata.freesound_id)
-        .execute(&self.db)
+        .bind(&metadata.remote_id)
+        .bind(metadata.source.as_str())
+        .bind(&metadata.hash)
+        .bind(&metadata.derived_from)
+        .bind(metadata.archive.as_ref().map(|a| a.codec.as_str()))
+        .bind(metadata.archive.as_ref().map(|a| a.hash.clone()))
+        .bind(metadata.format.map(|format| format.as_str()))
+        .bind(file_size)
+        .bind(&metadata.storage_backend)
+        .bind(metadata.path.as_deref().and_then(|path| self.object_hash(path)))
+        .bind(slug)
+        .bind(metadata.expires_at)
+        .bind(metadata.status.as_str())
+        .bind(self.collator.sort_key(&metadata.name))
+        .execute(&mut *conn)
         .await?;
 
-        // Update custom metadata
+        // Replace custom metadata, dropping keys no longer present
+        sqlx::query(&self.sql("DELETE FROM metadata WHERE object_id = ? AND object_type = 'sound'"))
+            .bind(&metadata.id)
+            .execute(&mut *conn)
+            .await?;
+
         for (key, value) in &metadata.custom {
             sqlx::query(
-                r#"
+                &self.sql(r#"
                 INSERT OR REPLACE INTO metadata
                 (object_id, object_type, key, value)
                 VALUES (?, 'sound', ?, ?)
-                "#,
+                "#),
             )
             .bind(&metadata.id)
             .bind(key)
             .bind(value)
-            .execute(&self.db)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        // Replace localized texts
+        sqlx::query(&self.sql("DELETE FROM localized_text WHERE object_id = ?"))
+            .bind(&metadata.id)
+            .execute(&mut *conn)
+            .await?;
+
+        for (lang, localization) in &metadata.localizations {
+            self.save_localization(conn, &metadata.id, lang, localization).await?;
+        }
+
+        self.save_search_words(conn, &metadata.id, &metadata.name, &metadata.tags).await?;
+        self.save_descriptors(conn, &metadata.id, &metadata.descriptors).await
+    }
+
+    /// Replace the analysis descriptors of a sound
+    pub(crate) async fn save_descriptors(
+        &self, conn: &mut SqliteConnection,
+        id: &str,
+        descriptors: &HashMap<String, f64>,
+    ) -> Result<()> {
+        sqlx::query(&self.sql("DELETE FROM sound_descriptors WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *conn)
             .await?;
+
+        // NaN can't be compared, so it's as good as missing
+        for (name, value) in descriptors.iter().filter(|(_, value)| value.is_finite()) {
+            sqlx::query(&self.sql("INSERT INTO sound_descriptors (sound_id, name, value) VALUES (?, ?, ?)"))
+                .bind(id)
+                .bind(name)
+                .bind(value)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        self.save_feature_vector(conn, id, descriptors).await
+    }
+
+    /// Insert or replace the localized texts of a sound in one language
+    pub(crate) async fn save_localization(
+        &self, conn: &mut SqliteConnection,
+        id: &str,
+        lang: &str,
+        localization: &Localization,
+    ) -> Result<()> {
+        let fields = [("name", &localization.name), ("description", &localization.description)];
+        for (field, value) in fields {
+            match value {
+                Some(value) => {
+                    sqlx::query(&self.sql("INSERT OR REPLACE INTO localized_text (object_id, field, lang, value) VALUES (?, ?, ?, ?)"))
+                        .bind(id)
+                        .bind(field)
+                        .bind(normalize_lang(lang))
+                        .bind(value)
+                        .execute(&mut *conn)
+                        .await?
+                }
+                None => {
+                    sqlx::query(&self.sql("DELETE FROM localized_text WHERE object_id = ? AND field = ? AND lang = ?"))
+                        .bind(id)
+                        .bind(field)
+                        .bind(normalize_lang(lang))
+                        .execute(&mut *conn)
+                        .await?
+                }
+            };
         }
 
         Ok(())
     }
 
+    /// Save changed sound metadata and record the change in the audit log
+    ///
+    /// Only the fields that differ between `before` and `after` are written.
+    async fn save_update(
+        &self,
+        conn: &mut SqliteConnection,
+        before: &SoundMetadata,
+        after: &SoundMetadata,
+    ) -> Result<()> {
+        self.apply_patch(conn, &after.id, &MetadataPatch::between(before, after)).await
+    }
+
     /// Get a sound by ID
     ///
     /// # Arguments
@@ -229,72 +1534,143 @@ impl LocalLibrary {
     ///
     /// The sound if found
     pub async fn get_sound(&self, id: &str) -> Result<Sound> {
+        let sound = self.sound_of(self.cached_metadata(id).await?);
+        self.note_availability(id, sound.availability);
+        Ok(sound)
+    }
+
+    /// Get a sound by ID on a given connection, e.g. within a transaction
+    pub(crate) async fn fetch_sound(&self, conn: &mut SqliteConnection, id: &str) -> Result<Sound> {
         // Fetch basic sound data
-        let sound_data = sqlx::query!(
+        let row = sqlx::query(&self.sql(
             r#"
-            SELECT id, name, description, tags, duration, license, path, freesound_id
+            SELECT id, name, description, tags, duration, channels, sample_rate, rating, trim_start, trim_end, license, path, external, packed, locked, freesound_id, remote_id, source, hash, derived_from, archive_codec, archive_hash, format, storage_backend, slug, status, expires_at, last_accessed_at
             FROM sounds WHERE id = ?
             "#,
-            id
-        )
-        .fetch_optional(&self.db)
+        ))
+        .bind(id)
+        .fetch_optional(&mut *conn)
         .await?
         .ok_or_else(|| VaultError::NotFound(format!("Sound not found: {}", id)))?;
 
         // Parse tags
-        let tags: Vec<String> = if let Some(tags_str) = &sound_data.tags {
-            serde_json::from_str(tags_str).unwrap_or_default()
+        let tags: Vec<String> = if let Some(tags_str) = row.try_get::<Option<String>, _>("tags")? {
+            canonical_tags(serde_json::from_str(&tags_str).unwrap_or_default())
         } else {
             Vec::new()
         };
 
         // Fetch custom metadata
-        let custom_meta = sqlx::query!(
+        let custom_meta: Vec<(String, Option<String>)> = sqlx::query_as(&self.sql(
             r#"
             SELECT key, value FROM metadata
             WHERE object_id = ? AND object_type = 'sound'
             "#,
-            id
-        )
-        .fetch_all(&self.db)
+        ))
+        .bind(id)
+        .fetch_all(&mut *conn)
         .await?;
 
         // Build custom metadata map
         let mut custom = std::collections::HashMap::new();
-        for meta in custom_meta {
-            if let (Some(key), Some(value)) = (meta.key, meta.value) {
+        for (key, value) in custom_meta {
+            if let Some(value) = value {
                 custom.insert(key, value);
             }
         }
 
+        // Fetch localized texts
+        let texts: Vec<(String, String, String)> =
+            sqlx::query_as(&self.sql("SELECT lang, field, value FROM localized_text WHERE object_id = ?"))
+                .bind(id)
+                .fetch_all(&mut *conn)
+                .await?;
+
+        let mut localizations: BTreeMap<String, Localization> = BTreeMap::new();
+        for (lang, field, value) in texts {
+            let localization = localizations.entry(lang).or_default();
+            match field.as_str() {
+                "name" => localization.name = Some(value),
+                "description" => localization.description = Some(value),
+                _ => {}
+            }
+        }
+
+        let descriptors: Vec<(String, f64)> =
+            sqlx::query_as(&self.sql("SELECT name, value FROM sound_descriptors WHERE sound_id = ?"))
+                .bind(id)
+                .fetch_all(&mut *conn)
+                .await?;
+
         // Create path from string if available
-        let path = sound_data.path.map(PathBuf::from);
+        let path = row.try_get::<Option<String>, _>("path")?.map(PathBuf::from);
+        let trim_end: Option<f64> = row.try_get("trim_end")?;
+        let archive_hash: Option<String> = row.try_get("archive_hash")?;
 
         // Create metadata
         let metadata = SoundMetadata {
-            id: sound_data.id,
-            name: sound_data.name,
-            source: SoundSource::Local,
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            source: row.try_get::<Option<String>, _>("source")?.as_deref().map(SoundSource::parse).unwrap_or_default(),
             tags,
-            description: sound_data.description.unwrap_or_default(),
-            duration: sound_data.duration.unwrap_or_default(),
-            license: sound_data.license.unwrap_or_default(),
+            description: row.try_get::<Option<String>, _>("description")?.unwrap_or_default(),
+            duration: row.try_get::<Option<f64>, _>("duration")?.unwrap_or_default() as f32,
+            channels: row.try_get::<Option<i64>, _>("channels")?.map(|c| c as u16),
+            sample_rate: row.try_get::<Option<i64>, _>("sample_rate")?.map(|r| r as u32),
+            rating: row.try_get::<Option<i64>, _>("rating")?.map(|r| r as u8),
+            trim: row.try_get::<Option<f64>, _>("trim_start")?.map(|start| Trim {
+                start: start as f32,
+                end: trim_end.map(|end| end as f32),
+            }),
+            license: row.try_get::<Option<String>, _>("license")?.unwrap_or_default(),
             path,
-            freesound_id: sound_data.freesound_id,
+            external: row.try_get("external")?,
+            packed: row.try_get("packed")?,
+            locked: row.try_get("locked")?,
+            freesound_id: row.try_get::<Option<i64>, _>("freesound_id")?.map(|id| id as i32),
+            remote_id: row.try_get("remote_id")?,
+            hash: row.try_get("hash")?,
+            derived_from: row.try_get("derived_from")?,
+            archive: row
+                .try_get::<Option<String>, _>("archive_codec")?
+                .as_deref()
+                .and_then(ArchivalCodec::parse)
+                .map(|codec| Archive {
+                    codec,
+                    hash: archive_hash.unwrap_or_default(),
+                }),
+            format: row.try_get::<Option<String>, _>("format")?.as_deref().and_then(FileFormat::parse),
+            storage_backend: row.try_get("storage_backend")?,
+            slug: row.try_get("slug")?,
+            expires_at: row.try_get("expires_at")?,
+            last_accessed_at: row.try_get("last_accessed_at")?,
+            status: SoundStatus::parse(&row.try_get::<String, _>("status")?),
             custom,
+            localizations,
+            descriptors: descriptors.into_iter().collect(),
         };
 
-        // Generate preview URL (file:// URL for local playback)
-        let preview_url = metadata.path.as_ref().map(|p| {
-            format!("file://{}", p.to_string_lossy())
+        Ok(self.sound_of(metadata))
+    }
+
+    /// Wrap a sound's metadata with what its file tells right now
+    fn sound_of(&self, metadata: SoundMetadata) -> Sound {
+        let is_cached = metadata.path.is_some();
+        let availability = Availability::of(&metadata);
+
+        // Generate preview URL (file:// URL for local playback, or a
+        // presigned one for a file held by another store)
+        let preview_url = self.blob_preview_url(&metadata).or_else(|| {
+            metadata.path.as_ref().map(|p| format!("file://{}", p.to_string_lossy()))
         });
 
-        Ok(Sound {
+        Sound {
             metadata,
             preview_url,
-            is_cached: true,
+            is_cached,
+            availability,
             download_url: None,
-        })
+        }
     }
 
     /// Search for sounds in local library
@@ -308,60 +1684,19 @@ impl LocalLibrary {
     ///
     /// List of matching sounds
     pub async fn search(&self, query: &str, tags: Option<&[&str]>) -> Result<Vec<Sound>> {
-        let mut conditions = Vec::new();
-        let mut params = Vec::new();
-
-        // Add query condition if not empty
-        if !query.is_empty() {
-            conditions.push("(name LIKE ? OR description LIKE ?)");
-            let query_pattern = format!("%{}%", query);
-            params.push(query_pattern.clone());
-            params.push(query_pattern);
-        }
-
-        // Add tag conditions if provided
-        if let Some(tags) = tags {
-            for tag in tags {
-                conditions.push("tags LIKE ?");
-                params.push(format!("%\"{}?\"%", tag));
-            }
-        }
-
-        // Build the final query
-        let where_clause = if conditions.is_empty() {
-            String::new()
-        } else {
-            format!("WHERE {}", conditions.join(" AND "))
+        let filter = SoundFilter {
+            text: Some(query.to_string()),
+            tags: tags
+                .unwrap_or_default()
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect(),
+            ..Default::default()
         };
 
-        let sql = format!(
-            r#"
-            SELECT id, name, description, tags, duration, license, path, freesound_id
-            FROM sounds
-            {}
-            ORDER BY name ASC
-            "#,
-            where_clause
-        );
-
-        // Execute query and collect IDs
-        let mut query = sqlx::query(&sql);
-        for param in params {
-            query = query.bind(param);
-        }
-
-        let rows = query.fetch_all(&self.db).await?;
-
-        // Convert rows to IDs
-        let mut ids = Vec::new();
-        for row in rows {
-            let id: &str = row.get(0);
-            ids.push(id.to_string());
-        }
-
         // Get full sound objects
         let mut sounds = Vec::new();
-        for id in ids {
+        for id in self.query_ids(&filter).await? {
             sounds.push(self.get_sound(&id).await?);
         }
 
@@ -370,6 +1705,10 @@ impl LocalLibrary {
 
     /// Update sound metadata
     ///
+    /// Only the fields the updater changes are written, as a
+    /// [`MetadataPatch`]; changes to the ID, file, source and technical
+    /// properties are ignored.
+    ///
     /// # Arguments
     ///
     /// * `id` - ID of the sound to update
@@ -379,13 +1718,14 @@ impl LocalLibrary {
         F: FnOnce(&mut SoundMetadata),
     {
         // Get current sound
-        let mut sound = self.get_sound(id).await?;
+        let before = self.get_sound(id).await?.metadata;
 
         // Update metadata
-        updater(&mut sound.metadata);
+        let mut after = before.clone();
+        updater(&mut after);
 
-        // Save updated metadata
-        self.save_metadata(&sound.metadata).await
+        // Save the changed fields
+        self.patch_metadata(id, MetadataPatch::between(&before, &after)).await
     }
 
     /// Delete a sound from the library
@@ -393,41 +1733,136 @@ impl LocalLibrary {
     /// # Arguments
     ///
     /// * `id` - ID of the sound to delete
-    pub async fn delete_sound(&self, id: &str) -> Result<()> {
+    /// * `override_lock` - Delete a locked sound too
+    pub async fn delete_sound(&self, id: &str, override_lock: bool) -> Result<()> {
         // Get sound to find the file path
         let sound = self.get_sound(id).await?;
+        if sound.metadata.locked && !override_lock {
+            return Err(VaultError::Locked(id.to_string()));
+        }
+        if !override_lock {
+            self.check_unused(id).await?;
+        }
+
+        // A working copy has nothing left to check in to
+        if let Some(checkout) = self.find_checkout(id).await? {
+            self.clear_checkout(&checkout).await?;
+        }
 
-        // Delete file if it exists
-        if let Some(path) = sound.metadata.path {
-            if path.exists() {
-                // Delete the parent directory (sound folder)
-                let parent = path.parent().unwrap_or(&path);
-                std::fs::remove_dir_all(parent).map_err(|e| {
+        // Delete the file if the vault owns it, in whichever store holds it
+        self.delete_blob(&sound.metadata).await?;
+        if let Some(path) = &sound.metadata.path
+            && !sound.metadata.external
+        {
+            let path = self.library_file(path)?;
+
+            // Imported files sit in a folder named after the sound; remove it
+            // whole. A content-addressed file goes with its last reference,
+            // below, leaving only the folder of the sound's sidecar
+            let shared = self.object_hash(&path).is_some();
+            let folder = match shared {
+                true => self.library_file(&self.library_path.join(id)).ok(),
+                false => path
+                    .parent()
+                    .filter(|dir| dir.file_name().is_some_and(|name| name == id))
+                    .and_then(|dir| self.library_file(dir).ok()),
+            };
+            match folder {
+                Some(folder) if folder.exists() => std::fs::remove_dir_all(&folder).map_err(|e| {
                     VaultError::FileSystem(format!("Failed to delete sound directory: {}", e))
-                })?;
+                })?,
+                _ if path.exists() && !shared => std::fs::remove_file(&path).map_err(|e| {
+                    VaultError::FileSystem(format!("Failed to delete sound file: {}", e))
+                })?,
+                _ => {}
             }
         }
 
         // Delete from database
-        sqlx::query!("DELETE FROM sounds WHERE id = ?", id)
-            .execute(&self.db)
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("DELETE FROM sounds WHERE id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
             .await?;
+        if let Some(path) = &sound.metadata.path
+            && !sound.metadata.external
+        {
+            self.release_object(&mut tx, path).await?;
+        }
 
         // Delete metadata
-        sqlx::query!(
-            "DELETE FROM metadata WHERE object_id = ? AND object_type = 'sound'",
-            id
-        )
-        .execute(&self.db)
-        .await?;
+        sqlx::query(&self.sql("DELETE FROM metadata WHERE object_id = ? AND object_type = 'sound'"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
 
-        // Delete from collections
-        sqlx::query!(
-            "DELETE FROM collection_sounds WHERE sound_id = ?",
-            id
-        )
-        .execute(&self.db)
-        .await?;
+        // Delete from collections, closing the gaps left
+        let collection_ids: Vec<String> =
+            sqlx::query_scalar(&self.sql("SELECT collection_id FROM collection_sounds WHERE sound_id = ?"))
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+        for collection_id in &collection_ids {
+            self.delete_member(&mut tx, collection_id, id).await?;
+        }
+
+        // Delete from groups
+        sqlx::query(&self.sql("DELETE FROM sound_group_members WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        // Delete localized texts
+        sqlx::query(&self.sql("DELETE FROM localized_text WHERE object_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&self.sql("DELETE FROM sound_descriptors WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&self.sql("DELETE FROM sound_trigrams WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&self.sql("DELETE FROM provenance WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&self.sql("DELETE FROM sound_uses WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&self.sql("DELETE FROM merged_sounds WHERE sound_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        // A deleted slice no longer holds its parent's file
+        sqlx::query(&self.sql("DELETE FROM sound_uses WHERE consumer = ? AND location = ?"))
+            .bind(SLICE_CONSUMER)
+            .bind(self.uri_for(id))
+            .execute(&mut *tx)
+            .await?;
+
+        // Derivatives outlive their parent
+        let derivatives: Vec<String> =
+            sqlx::query_scalar(&self.sql("UPDATE sounds SET derived_from = NULL WHERE derived_from = ? RETURNING id"))
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+        for derivative in derivatives {
+            self.record_change(&mut tx, ChangeEntity::Sound, &derivative, ChangeKind::Updated).await?;
+        }
+
+        let changes = audit_diff(Some(&serde_json::to_value(&sound.metadata)?), None);
+        self.audit(&mut tx, AuditOperation::DeleteSound, id, changes).await?;
+        tx.commit().await?;
 
         Ok(())
     }
@@ -444,49 +1879,50 @@ impl LocalLibrary {
     pub async fn add_collection(&self, collection: &Collection) -> Result<String> {
         // Convert collection ID to string
         let id = collection.id.to_string();
+        let defaults_json = serde_json::to_string(&collection.defaults)?;
+        let sort_key = self.collator.sort_key(&collection.name);
 
         // Insert collection
-        sqlx::query!(
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql(
             r#"
-            INSERT INTO collections (id, name, description)
-            VALUES (?, ?, ?)
+            INSERT INTO collections (id, name, description, defaults, sort_key, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
             "#,
-            id,
-            collection.name,
-            collection.description,
-        )
-        .execute(&self.db)
+        ))
+        .bind(&id)
+        .bind(&collection.name)
+        .bind(&collection.description)
+        .bind(defaults_json)
+        .bind(sort_key)
+        .bind(collection.expires_at)
+        .execute(&mut *tx)
         .await?;
 
         // Insert custom metadata
         for (key, value) in &collection.custom {
-            sqlx::query!(
+            sqlx::query(&self.sql(
                 r#"
                 INSERT INTO metadata (object_id, object_type, key, value)
                 VALUES (?, 'collection', ?, ?)
                 "#,
-                id,
-                key,
-                value,
-            )
-            .execute(&self.db)
+            ))
+            .bind(&id)
+            .bind(key)
+            .bind(value)
+            .execute(&mut *tx)
             .await?;
         }
 
         // Insert sounds
         for sound_id in &collection.sound_ids {
-            sqlx::query!(
-                r#"
-                INSERT OR IGNORE INTO collection_sounds (collection_id, sound_id)
-                VALUES (?, ?)
-                "#,
-                id,
-                sound_id,
-            )
-            .execute(&self.db)
-            .await?;
+            self.insert_member(&mut tx, &id, sound_id).await?;
         }
 
+        let changes = audit_diff(None, Some(&serde_json::to_value(collection)?));
+        self.audit(&mut tx, AuditOperation::CreateCollection, &id, changes).await?;
+        tx.commit().await?;
+
         Ok(id)
     }
 
@@ -501,128 +1937,278 @@ impl LocalLibrary {
     /// The collection if found
     pub async fn get_collection(&self, id: &str) -> Result<Collection> {
         // Fetch collection data
-        let collection_data = sqlx::query!(
-            r#"
-            SELECT id, name, description
-            FROM collections WHERE id = ?
-            "#,
-            id
+        let (collection_id, name, description, defaults, expires_at, version): CollectionRow = sqlx::query_as(
+            &self.sql("SELECT id, name, description, defaults, expires_at, version FROM collections WHERE id = ?"),
         )
-        .fetch_optional(&self.db)
+        .bind(id)
+        .fetch_optional(&self.reader)
         .await?
         .ok_or_else(|| VaultError::NotFound(format!("Collection not found: {}", id)))?;
 
-        // Fetch sound IDs
-        let sound_rows = sqlx::query!(
-            r#"
-            SELECT sound_id FROM collection_sounds WHERE collection_id = ?
-            "#,
-            id
-        )
-        .fetch_all(&self.db)
+        // Fetch sound IDs in order, numbering them again if their positions
+        // have gaps or duplicates
+        let sound_rows: Vec<(Option<String>, Option<i64>)> = sqlx::query_as(&self.sql(
+            "SELECT sound_id, position FROM collection_sounds WHERE collection_id = ? ORDER BY position IS NULL, position, rowid",
+        ))
+        .bind(id)
+        .fetch_all(&self.reader)
         .await?;
+        let positions: Vec<Option<i64>> = sound_rows.iter().map(|(_, position)| *position).collect();
+        if !is_dense(&positions) {
+            self.heal_positions(id);
+        }
 
-        let sound_ids: Vec<String> = sound_rows
-            .into_iter()
-            .filter_map(|row| row.sound_id)
-            .collect();
+        let sound_ids: Vec<String> = sound_rows.into_iter().filter_map(|(sound_id, _)| sound_id).collect();
 
         // Fetch custom metadata
-        let custom_meta = sqlx::query!(
+        let custom_meta: Vec<(String, Option<String>)> = sqlx::query_as(&self.sql(
             r#"
             SELECT key, value FROM metadata
             WHERE object_id = ? AND object_type = 'collection'
             "#,
-            id
-        )
-        .fetch_all(&self.db)
+        ))
+        .bind(id)
+        .fetch_all(&self.reader)
         .await?;
 
         // Build custom metadata map
         let mut custom = std::collections::HashMap::new();
-        for meta in custom_meta {
-            if let (Some(key), Some(value)) = (meta.key, meta.value) {
+        for (key, value) in custom_meta {
+            if let Some(value) = value {
                 custom.insert(key, value);
             }
         }
 
+        // Parse inherited metadata
+        let defaults = match &defaults {
+            Some(json) => serde_json::from_str(json)?,
+            None => Default::default(),
+        };
+
         // Parse UUID
-        let uuid = uuid::Uuid::parse_str(&collection_data.id)
+        let uuid = uuid::Uuid::parse_str(&collection_id)
             .map_err(|_| VaultError::Database(sqlx::Error::RowNotFound))?;
 
         Ok(Collection {
             id: uuid,
-            name: collection_data.name,
-            description: collection_data.description.unwrap_or_default(),
+            name,
+            description: description.unwrap_or_default(),
             sound_ids,
+            defaults,
             custom,
+            expires_at,
+            version,
         })
     }
 
+    /// Set the metadata a collection hands down to its sounds
+    ///
+    /// # Arguments
+    ///
+    /// * `collection_id` - ID of the collection
+    /// * `defaults` - Metadata inherited by member sounds
+    pub async fn set_collection_defaults(&self, collection_id: &str, defaults: &CollectionDefaults) -> Result<()> {
+        let collection = self.get_collection(collection_id).await?;
+        let defaults_json = serde_json::to_string(defaults)?;
+
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            &self.sql(r#"
+            UPDATE collections SET defaults = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            "#),
+        )
+        .bind(defaults_json)
+        .bind(collection_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let changes = audit_diff(
+            Some(&serde_json::json!({ "defaults": collection.defaults })),
+            Some(&serde_json::json!({ "defaults": defaults })),
+        );
+        self.audit(&mut tx, AuditOperation::UpdateCollection, collection_id, changes).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Apply a collection's default metadata to all of its current sounds
+    ///
+    /// # Arguments
+    ///
+    /// * `collection_id` - ID of the collection
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds whose metadata changed
+    pub async fn apply_collection_defaults(&self, collection_id: &str) -> Result<usize> {
+        let collection = self.get_collection(collection_id).await?;
+        if collection.defaults.is_empty() {
+            return Ok(0);
+        }
+
+        let mut changed = 0;
+        let mut tx = self.db.begin().await?;
+        for sound_id in &collection.sound_ids {
+            let before = self.get_sound(sound_id).await?.metadata;
+            let mut after = before.clone();
+            if collection.defaults.apply_to(&mut after) {
+                self.save_update(&mut tx, &before, &after).await?;
+                changed += 1;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(changed)
+    }
+
     /// Add a sound to a collection
     ///
+    /// If the collection's defaults have `apply_on_add` set, the sound
+    /// inherits them. Removing the sound later does not undo the inheritance.
+    ///
     /// # Arguments
     ///
     /// * `sound_id` - ID of the sound to add
     /// * `collection_id` - ID of the collection to add to
     pub async fn add_sound_to_collection(&self, sound_id: &str, collection_id: &str) -> Result<()> {
-        // Verify that both sound and collection exist
+        self.add_sound_to_collections(sound_id, &[collection_id.to_string()]).await?;
+
+        Ok(())
+    }
+
+    /// Add a sound to several collections in one transaction
+    ///
+    /// Each collection's defaults are inherited as with
+    /// [`add_sound_to_collection`](Self::add_sound_to_collection). Nothing is
+    /// added if the sound or one of the collections doesn't exist.
+    ///
+    /// # Returns
+    ///
+    /// Number of collections the sound wasn't in yet
+    pub async fn add_sound_to_collections(&self, sound_id: &str, collection_ids: &[String]) -> Result<usize> {
+        let mut collections = Vec::new();
+        for (index, collection_id) in collection_ids.iter().enumerate() {
+            if !collection_ids[..index].contains(collection_id) {
+                collections.push((collection_id, self.get_collection(collection_id).await?));
+            }
+        }
+
         self.get_sound(sound_id).await?;
-        self.get_collection(collection_id).await?;
 
-        // Add sound to collection
-        sqlx::query!(
-            r#"
-            INSERT OR IGNORE INTO collection_sounds (collection_id, sound_id)
-            VALUES (?, ?)
-            "#,
-            collection_id,
-            sound_id,
+        // Write before reading, so a concurrent writer is waited for rather
+        // than failing the transaction when it upgrades its lock
+        let mut tx = self.db.begin().await?;
+        let mut added = Vec::new();
+        for (collection_id, _) in &collections {
+            if self.insert_member(&mut tx, collection_id, sound_id).await? {
+                let changes = serde_json::json!({ "sound_id": [null, sound_id] });
+                self.audit(&mut tx, AuditOperation::AddToCollection, collection_id, changes).await?;
+                added.push((*collection_id).clone());
+            }
+        }
+
+        let mut metadata = self.fetch_sound(&mut tx, sound_id).await?.metadata;
+        for (_, collection) in collections {
+            // Inherit the collection's default metadata
+            let mut after = metadata.clone();
+            if collection.defaults.apply_on_add && collection.defaults.apply_to(&mut after) {
+                self.save_update(&mut tx, &metadata, &after).await?;
+                metadata = after;
+            }
+        }
+        tx.commit().await?;
+
+        for collection_id in &added {
+            self.emit(VaultEvent::CollectionChanged { collection_id: collection_id.clone() });
+        }
+        Ok(added.len())
+    }
+
+    /// Collections a sound is in, ordered by name
+    pub async fn collections_containing(&self, sound_id: &str) -> Result<Vec<CollectionSummary>> {
+        let exists: bool = sqlx::query_scalar(&self.sql("SELECT EXISTS (SELECT 1 FROM sounds WHERE id = ?)"))
+            .bind(sound_id)
+            .fetch_one(&self.reader)
+            .await?;
+        if !exists {
+            return Err(VaultError::NotFound(format!("Sound not found: {}", sound_id)));
+        }
+
+        let rows = sqlx::query(
+            &self.sql(r#"
+            SELECT c.id, c.name, c.description,
+                (SELECT COUNT(*) FROM collection_sounds WHERE collection_id = c.id) AS sound_count
+            FROM collections c
+            JOIN collection_sounds m ON m.collection_id = c.id
+            WHERE m.sound_id = ?
+            ORDER BY c.sort_key, c.id
+            "#),
         )
-        .execute(&self.db)
+        .bind(sound_id)
+        .fetch_all(&self.reader)
         .await?;
 
-        Ok(())
+        rows.iter()
+            .map(|row| {
+                let id: String = row.try_get("id")?;
+                Ok(CollectionSummary {
+                    id: Uuid::parse_str(&id).map_err(|_| VaultError::Corrupt(format!("Invalid collection ID: {}", id)))?,
+                    name: row.try_get("name")?,
+                    description: row.try_get::<Option<String>, _>("description")?.unwrap_or_default(),
+                    sound_count: row.try_get::<i64, _>("sound_count")? as u64,
+                })
+            })
+            .collect()
     }
 
     /// Remove a sound from a collection
     ///
+    /// Metadata the sound inherited from the collection is kept.
+    ///
     /// # Arguments
     ///
     /// * `sound_id` - ID of the sound to remove
     /// * `collection_id` - ID of the collection to remove from
     pub async fn remove_sound_from_collection(&self, sound_id: &str, collection_id: &str) -> Result<()> {
-        sqlx::query!(
-            r#"
-            DELETE FROM collection_sounds
-            WHERE collection_id = ? AND sound_id = ?
-            "#,
-            collection_id,
-            sound_id,
-        )
-        .execute(&self.db)
-        .await?;
+        let mut tx = self.db.begin().await?;
+        let removed = self.delete_member(&mut tx, collection_id, sound_id).await?;
+        if removed {
+            let changes = serde_json::json!({ "sound_id": [sound_id, null] });
+            self.audit(&mut tx, AuditOperation::RemoveFromCollection, collection_id, changes).await?;
+        }
+        tx.commit().await?;
+
+        if removed {
+            self.emit(VaultEvent::CollectionChanged { collection_id: collection_id.to_string() });
+        }
 
         Ok(())
     }
 
     /// List all collections
     ///
+    /// # Arguments
+    ///
+    /// * `include_sessions` - List session collections too
+    ///
     /// # Returns
     ///
     /// List of all collections
-    pub async fn list_collections(&self) -> Result<Vec<Collection>> {
+    pub async fn list_collections(&self, include_sessions: bool) -> Result<Vec<Collection>> {
         // Fetch all collection IDs
-        let collection_rows = sqlx::query!("SELECT id FROM collections")
-            .fetch_all(&self.db)
-            .await?;
+        let collection_rows: Vec<Option<String>> = sqlx::query_scalar(
+            &self.sql("SELECT id FROM collections WHERE ? OR expires_at IS NULL ORDER BY sort_key"),
+        )
+        .bind(include_sessions)
+        .fetch_all(&self.reader)
+        .await?;
 
         // Get each collection
         let mut collections = Vec::new();
-        for row in collection_rows {
-            if let Some(id) = row.id {
-                collections.push(self.get_collection(&id).await?);
-            }
+        for id in collection_rows.into_iter().flatten() {
+            collections.push(self.get_collection(&id).await?);
         }
 
         Ok(collections)
@@ -657,18 +2243,24 @@ impl LocalLibrary {
     /// List of all sounds
     pub async fn list_sounds(&self) -> Result<Vec<Sound>> {
         // Fetch all sound IDs
-        let sound_rows = sqlx::query!("SELECT id FROM sounds")
-            .fetch_all(&self.db)
+        let sound_rows: Vec<Option<String>> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds ORDER BY sort_key"))
+            .fetch_all(&self.reader)
             .await?;
 
         // Get each sound
         let mut sounds = Vec::new();
-        for row in sound_rows {
-            if let Some(id) = row.id {
-                sounds.push(self.get_sound(&id).await?);
-            }
+        for id in sound_rows.into_iter().flatten() {
+            sounds.push(self.get_sound(&id).await?);
         }
 
         Ok(sounds)
     }
 }
+
+/// Compute the SHA-256 content hash of a file as a hex string
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,85 @@
+//! Locking sounds approved for shipping against casual edits
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use serde_json::json;
+use sqlx::SqliteConnection;
+
+/// Custom metadata key recording why a sound was locked
+pub const LOCK_REASON_KEY: &str = "lock_reason";
+
+impl LocalLibrary {
+    /// Lock a sound against edits, or unlock it
+    ///
+    /// The reason is kept in the sound's custom metadata under
+    /// [`LOCK_REASON_KEY`] while it's locked. Locking and unlocking are
+    /// recorded in the audit log with the actor.
+    pub async fn set_locked(&self, id: &str, locked: bool, reason: Option<&str>) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        let before = self.fetch_sound(&mut tx, id).await?.metadata;
+        let before_reason = before.custom.get(LOCK_REASON_KEY).cloned();
+        let after_reason = reason.filter(|_| locked).map(str::to_string);
+        if before.locked == locked && before_reason == after_reason {
+            return Ok(());
+        }
+
+        sqlx::query(&self.sql("UPDATE sounds SET locked = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(locked)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        match &after_reason {
+            Some(reason) => {
+                sqlx::query(
+                    &self.sql(r#"
+                    INSERT INTO metadata (object_id, object_type, key, value)
+                    VALUES (?, 'sound', ?, ?)
+                    ON CONFLICT(object_id, object_type, key) DO UPDATE SET value = excluded.value
+                    "#),
+                )
+                .bind(id)
+                .bind(LOCK_REASON_KEY)
+                .bind(reason)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query(&self.sql("DELETE FROM metadata WHERE object_id = ? AND object_type = 'sound' AND key = ?"))
+                    .bind(id)
+                    .bind(LOCK_REASON_KEY)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        let changes = audit_diff(
+            Some(&json!({ "locked": before.locked, "lock_reason": before_reason })),
+            Some(&json!({ "locked": locked, "lock_reason": after_reason })),
+        );
+        let operation = match locked {
+            true => AuditOperation::LockSound,
+            false => AuditOperation::UnlockSound,
+        };
+        self.audit(&mut tx, operation, id, changes).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Fail if a sound is locked
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Locked` if it is
+    pub(crate) async fn check_unlocked(&self, conn: &mut SqliteConnection, id: &str) -> Result<()> {
+        let locked: Option<bool> = sqlx::query_scalar(&self.sql("SELECT locked FROM sounds WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        match locked {
+            Some(true) => Err(VaultError::Locked(id.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
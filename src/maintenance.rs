@@ -0,0 +1,336 @@
+//! Upkeep of the database: reclaiming space, refreshing statistics and
+//! removing leftovers
+//!
+//! Deleting sounds leaves free pages in the database file until it's
+//! vacuumed. [`AutoMaintenance`] runs the upkeep in the background once
+//! enough of the file is free, skipping its turn while imports, downloads
+//! or background jobs are under way.
+
+use crate::error::{Result, VaultError};
+use crate::human::HumanDuration;
+use crate::local::LocalLibrary;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// How [`MaintenanceTasks::vacuum`] gives free pages back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VacuumMode {
+    /// Rebuild the whole database file; needs room for a copy of it, and
+    /// holds the writer for as long as it takes
+    Full,
+    /// Only give back the free pages, which is quick. The first run
+    /// rebuilds the file as [`Full`](Self::Full) does, to switch the
+    /// database to incremental vacuuming
+    Incremental,
+}
+
+/// A step of [`SoundVault::maintenance`](crate::SoundVault::maintenance)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceTask {
+    /// Removing the expired session collections
+    PurgeSessions,
+    /// Removing the rows left by sounds and collections that are gone
+    RemoveOrphans,
+    /// Giving free pages back
+    Vacuum,
+    /// Refreshing the statistics the query planner relies on
+    Analyze,
+    /// Copying the write-ahead log into the database and truncating it
+    Checkpoint,
+}
+
+/// Steps [`SoundVault::maintenance`](crate::SoundVault::maintenance) runs
+///
+/// The default runs none of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceTasks {
+    /// Remove the session collections that expired, as
+    /// [`purge_expired_sessions`](LocalLibrary::purge_expired_sessions) does
+    pub purge_sessions: bool,
+
+    /// Remove the custom fields, collection and group memberships,
+    /// translations, descriptors, search trigrams and provenance entries
+    /// of sounds and collections that no longer exist
+    pub remove_orphans: bool,
+
+    /// Give free pages back to the file system; `None` leaves them for
+    /// new rows
+    pub vacuum: Option<VacuumMode>,
+
+    /// Refresh the query planner's statistics
+    pub analyze: bool,
+
+    /// Checkpoint and truncate the write-ahead log
+    pub checkpoint: bool,
+}
+
+impl MaintenanceTasks {
+    /// Every step, with a full vacuum
+    pub fn all() -> Self {
+        Self {
+            purge_sessions: true,
+            remove_orphans: true,
+            vacuum: Some(VacuumMode::Full),
+            analyze: true,
+            checkpoint: true,
+        }
+    }
+}
+
+/// Time a maintenance step took
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskTiming {
+    /// The step
+    pub task: MaintenanceTask,
+
+    /// How long it took
+    pub elapsed: Duration,
+}
+
+/// Outcome of [`SoundVault::maintenance`](crate::SoundVault::maintenance)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceReport {
+    /// Steps run, in order, with their time
+    pub timings: Vec<TaskTiming>,
+
+    /// Size of the database before, in bytes, not counting the
+    /// write-ahead log
+    pub size_before: u64,
+
+    /// Size of the database after, in bytes
+    pub size_after: u64,
+
+    /// Bytes given back to the file system
+    pub reclaimed_bytes: u64,
+
+    /// Number of session collections removed
+    pub sessions_purged: u64,
+
+    /// Number of leftover rows removed
+    pub orphans_removed: u64,
+
+    /// Whether the write-ahead log was fully checkpointed; `false` if a
+    /// reader kept it from completing or no checkpoint was asked for
+    pub wal_checkpointed: bool,
+}
+
+/// When the vault maintains its database by itself
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoMaintenance {
+    /// How often to check whether maintenance is due
+    pub check_interval: HumanDuration,
+
+    /// Share of the database file left free, from 0 to 1, from which
+    /// maintenance runs
+    pub min_free_ratio: f64,
+
+    /// Steps run
+    pub tasks: MaintenanceTasks,
+}
+
+impl Default for AutoMaintenance {
+    fn default() -> Self {
+        Self {
+            check_interval: HumanDuration::from_hours(1),
+            min_free_ratio: 0.25,
+            tasks: MaintenanceTasks {
+                vacuum: Some(VacuumMode::Incremental),
+                ..MaintenanceTasks::all()
+            },
+        }
+    }
+}
+
+/// Rows removed by [`MaintenanceTasks::remove_orphans`]: tables and the
+/// condition of their leftovers
+const ORPHANS: &[(&str, &str)] = &[
+    ("metadata", "object_type = 'sound' AND object_id NOT IN (SELECT id FROM sounds)"),
+    ("metadata", "object_type = 'collection' AND object_id NOT IN (SELECT id FROM collections)"),
+    (
+        "collection_sounds",
+        "sound_id NOT IN (SELECT id FROM sounds) OR collection_id NOT IN (SELECT id FROM collections)",
+    ),
+    (
+        "sound_group_members",
+        "sound_id NOT IN (SELECT id FROM sounds) OR group_id NOT IN (SELECT id FROM sound_groups)",
+    ),
+    ("localized_text", "object_id NOT IN (SELECT id FROM sounds)"),
+    ("sound_descriptors", "sound_id NOT IN (SELECT id FROM sounds)"),
+    ("sound_trigrams", "sound_id NOT IN (SELECT id FROM sounds)"),
+    ("provenance", "sound_id NOT IN (SELECT id FROM sounds)"),
+    ("merged_sounds", "sound_id NOT IN (SELECT id FROM sounds)"),
+];
+
+/// `PRAGMA auto_vacuum` value of a database vacuumed incrementally
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+impl LocalLibrary {
+    /// Run maintenance steps on the database
+    ///
+    /// Vacuuming, analyzing and checkpointing apply to the whole database
+    /// file, so a database shared with the host application is maintained
+    /// as a whole.
+    pub(crate) async fn maintenance(&self, tasks: MaintenanceTasks) -> Result<MaintenanceReport> {
+        let size_before = self.database_size().await?;
+        let mut report = MaintenanceReport {
+            size_before,
+            ..Default::default()
+        };
+
+        if tasks.purge_sessions {
+            let started = Instant::now();
+            report.sessions_purged = self.purge_expired_sessions().await?;
+            report.timed(MaintenanceTask::PurgeSessions, started);
+        }
+
+        if tasks.remove_orphans {
+            let started = Instant::now();
+            let mut tx = self.db.begin().await?;
+            for (table, condition) in ORPHANS {
+                report.orphans_removed += sqlx::query(&self.sql(&format!("DELETE FROM {} WHERE {}", table, condition)))
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            tx.commit().await?;
+            report.timed(MaintenanceTask::RemoveOrphans, started);
+        }
+
+        if let Some(mode) = tasks.vacuum {
+            let started = Instant::now();
+            let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum").fetch_one(&self.db).await?;
+            match mode {
+                VacuumMode::Incremental if auto_vacuum == AUTO_VACUUM_INCREMENTAL => {
+                    // Each step of the statement frees a page
+                    sqlx::query("PRAGMA incremental_vacuum").fetch_all(&self.db).await?;
+                }
+                VacuumMode::Incremental => {
+                    sqlx::query("PRAGMA auto_vacuum = INCREMENTAL").execute(&self.db).await?;
+                    sqlx::query("VACUUM").execute(&self.db).await?;
+                }
+                VacuumMode::Full => {
+                    sqlx::query("VACUUM").execute(&self.db).await?;
+                }
+            }
+            report.timed(MaintenanceTask::Vacuum, started);
+        }
+
+        if tasks.analyze {
+            let started = Instant::now();
+            sqlx::query("ANALYZE").execute(&self.db).await?;
+            report.timed(MaintenanceTask::Analyze, started);
+        }
+
+        if tasks.checkpoint {
+            let started = Instant::now();
+            // Columns are busy, log frames and checkpointed frames
+            let (busy, _, _): (i64, i64, i64) =
+                sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)").fetch_one(&self.db).await?;
+            report.wal_checkpointed = busy == 0;
+            report.timed(MaintenanceTask::Checkpoint, started);
+        }
+
+        report.size_after = self.database_size().await?;
+        report.reclaimed_bytes = size_before.saturating_sub(report.size_after);
+        Ok(report)
+    }
+
+    /// Size of the database in bytes, and the share of it left free
+    pub(crate) async fn database_usage(&self) -> Result<(u64, f64)> {
+        let page_size: i64 = sqlx::query_scalar("PRAGMA page_size").fetch_one(&self.db).await?;
+        let pages: i64 = sqlx::query_scalar("PRAGMA page_count").fetch_one(&self.db).await?;
+        let free: i64 = sqlx::query_scalar("PRAGMA freelist_count").fetch_one(&self.db).await?;
+        let ratio = if pages > 0 { free as f64 / pages as f64 } else { 0.0 };
+
+        Ok(((page_size * pages) as u64, ratio))
+    }
+
+    /// Size of the database in bytes
+    async fn database_size(&self) -> Result<u64> {
+        Ok(self.database_usage().await?.0)
+    }
+
+    /// Check whether imports, downloads or background jobs are under way
+    fn busy(&self) -> bool {
+        let usage = self.governor.usage();
+        self.foreground.load(Ordering::SeqCst) > 0 || usage.transfers > 0 || usage.decodes > 0
+    }
+}
+
+impl MaintenanceReport {
+    /// Record the time a step took
+    fn timed(&mut self, task: MaintenanceTask, started: Instant) {
+        self.timings.push(TaskTiming {
+            task,
+            elapsed: started.elapsed(),
+        });
+    }
+}
+
+impl AutoMaintenance {
+    /// Fail unless the policy can run
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.check_interval.is_zero() {
+            return Err(VaultError::Config("Maintenance check interval is zero".to_string()));
+        }
+        if !(0.0..=1.0).contains(&self.min_free_ratio) {
+            return Err(VaultError::Config(format!(
+                "Maintenance free ratio is not between 0 and 1: {}",
+                self.min_free_ratio
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Background task maintaining the database when it's due and the vault
+/// is idle
+pub(crate) struct Maintainer {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Maintainer {
+    /// Check every `policy.check_interval`, the first time one interval
+    /// from now; `None` never maintains
+    pub(crate) fn start(local: &Arc<LocalLibrary>, policy: Option<AutoMaintenance>) -> Self {
+        let task = policy.map(|policy| {
+            let local = local.clone();
+            tokio::spawn(async move {
+                let interval = policy.check_interval.as_duration();
+                let mut ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    ticks.tick().await;
+                    if local.busy() {
+                        continue;
+                    }
+                    // A failed run is retried at the next tick
+                    if let Ok((_, free)) = local.database_usage().await
+                        && free >= policy.min_free_ratio
+                        && free > 0.0
+                    {
+                        let _ = local.maintenance(policy.tasks).await;
+                    }
+                }
+            })
+        });
+
+        Self { task: Mutex::new(task) }
+    }
+
+    /// Stop maintaining; a run under way is abandoned
+    pub(crate) fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Manifests listing the sounds shipped with a build
+
+use crate::context::OpContext;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::Sound;
+use crate::schema::{Envelope, MANIFEST_SCHEMA};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Serialization format of a manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// Pretty-printed JSON
+    Json,
+    /// TOML
+    Toml,
+}
+
+/// Machine-readable list of the sounds a build depends on
+///
+/// Entries are keyed by sound ID and kept sorted so that regenerating a
+/// manifest for the same sounds yields the same text but for the version of
+/// the library, recorded in the [`Envelope`] it's written in.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{Manifest, ManifestEntry, ManifestFormat};
+///
+/// let mut manifest = Manifest::default();
+/// manifest.sounds.insert(
+///     "0b7c8a54".to_string(),
+///     ManifestEntry {
+///         path: "0b7c8a54/wind.wav".to_string(),
+///         hash: "9f86d081884c7d65".to_string(),
+///         duration: 2.5,
+///         format: "wav".to_string(),
+///         license: "CC0".to_string(),
+///     },
+/// );
+///
+/// for format in [ManifestFormat::Json, ManifestFormat::Toml] {
+///     let text = manifest.to_string(format).unwrap();
+///     assert_eq!(Manifest::parse(&text).unwrap(), manifest);
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Shipped sounds by ID
+    pub sounds: BTreeMap<String, ManifestEntry>,
+
+    /// IDs of the sounds of the collections left out because they expired
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expired: Vec<String>,
+}
+
+/// A sound listed in a manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the file relative to the library, with `/` separators
+    pub path: String,
+
+    /// SHA-256 hash of the file
+    pub hash: String,
+
+    /// Duration in seconds
+    pub duration: f32,
+
+    /// File format, read from the file or else taken from its extension
+    pub format: String,
+
+    /// License information
+    pub license: String,
+}
+
+/// Differences between a manifest and the current state of the vault
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ManifestDiff {
+    /// Sounds listed in the manifest that are gone from the vault or whose file is missing
+    pub missing: Vec<String>,
+
+    /// Sounds whose file changed since the manifest was generated
+    pub changed: Vec<ManifestChange>,
+}
+
+/// A sound whose stored file no longer matches its manifest entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestChange {
+    /// ID of the sound
+    pub id: String,
+
+    /// Entry recorded in the manifest
+    pub expected: ManifestEntry,
+
+    /// Entry describing the sound as it is now
+    pub actual: ManifestEntry,
+}
+
+impl Manifest {
+    /// Parse a manifest written in either JSON or TOML, by this version of
+    /// the library or an earlier one
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::UnsupportedVersion` if a later version wrote it
+    pub fn parse(manifest: &str) -> Result<Self> {
+        let value: Value = if manifest.trim_start().starts_with('{') {
+            serde_json::from_str(manifest)?
+        } else {
+            let value: toml::Value = toml::from_str(manifest)
+                .map_err(|e| VaultError::InvalidOperation(format!("Invalid manifest: {}", e)))?;
+            serde_json::to_value(value)?
+        };
+        Ok(Envelope::open(MANIFEST_SCHEMA, value)?.data)
+    }
+
+    /// Serialize the manifest in its [`Envelope`]
+    pub fn to_string(&self, format: ManifestFormat) -> Result<String> {
+        let envelope = Envelope::wrap(MANIFEST_SCHEMA, self)?;
+        match format {
+            ManifestFormat::Json => Ok(serde_json::to_string_pretty(&envelope)?),
+            ManifestFormat::Toml => toml::to_string(&envelope).map_err(|e| {
+                VaultError::InvalidOperation(format!("Failed to write manifest: {}", e))
+            }),
+        }
+    }
+}
+
+impl ManifestDiff {
+    /// Check whether the manifest matches the vault
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl LocalLibrary {
+    /// Build a manifest of every sound in the given collections
+    ///
+    /// Expired sounds are left out and listed in [`Manifest::expired`].
+    ///
+    /// # Arguments
+    ///
+    /// * `collection_ids` - Collections whose sounds ship with the build
+    pub async fn build_manifest(&self, collection_ids: &[String]) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+
+        for collection_id in collection_ids {
+            for sound in self.get_collection_sounds(collection_id).await? {
+                if manifest.sounds.contains_key(&sound.metadata.id) || manifest.expired.contains(&sound.metadata.id) {
+                    continue;
+                }
+                if self.exclude_expired(&sound.metadata, "build_manifest") {
+                    manifest.expired.push(sound.metadata.id);
+                    continue;
+                }
+                let entry = self.manifest_entry(&sound, sound.metadata.hash.clone())?;
+                manifest.sounds.insert(sound.metadata.id, entry);
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Compare a manifest against the files currently stored in the library
+    ///
+    /// Every referenced file is re-hashed, so the check catches files modified
+    /// outside the vault.
+    pub async fn verify_manifest(&self, manifest: &Manifest, context: &OpContext) -> Result<ManifestDiff> {
+        let deadline = context.start("verify_manifest");
+        let mut diff = ManifestDiff::default();
+
+        let total = manifest.sounds.len();
+        for (done, (id, expected)) in manifest.sounds.iter().enumerate() {
+            deadline.check(|| format!("{} of {} sounds verified", done, total))?;
+            let sound = match self.get_sound(id).await {
+                Ok(sound) => sound,
+                Err(VaultError::NotFound(_)) => {
+                    diff.missing.push(id.clone());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let hash = match self.readable_file(&sound.metadata) {
+                Ok(path) if path.exists() => self.content_hash(&sound.metadata)?,
+                Err(e @ VaultError::InvalidOperation(_)) if sound.metadata.path.is_some() => return Err(e),
+                _ => {
+                    diff.missing.push(id.clone());
+                    continue;
+                }
+            };
+
+            let actual = self.manifest_entry(&sound, Some(hash))?;
+            if actual.hash != expected.hash || actual.path != expected.path {
+                diff.changed.push(ManifestChange {
+                    id: id.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Describe a stored sound as a manifest entry
+    fn manifest_entry(&self, sound: &Sound, hash: Option<String>) -> Result<ManifestEntry> {
+        let metadata = &sound.metadata;
+        let path = Self::logical_path(metadata).ok_or_else(|| {
+            VaultError::InvalidOperation(format!("Sound has no file: {}", metadata.id))
+        })?;
+
+        let hash = match hash {
+            Some(hash) => hash,
+            None => self.content_hash(metadata)?,
+        };
+
+        Ok(ManifestEntry {
+            path: self.relative_path(&path),
+            hash,
+            duration: metadata.duration,
+            format: match metadata.format {
+                Some(format) => format.extension().to_string(),
+                None => path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .unwrap_or_default(),
+            },
+            license: metadata.license.clone(),
+        })
+    }
+
+    /// Express a path relative to the library with portable separators
+    fn relative_path(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.library_path).unwrap_or(path);
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
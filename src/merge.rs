@@ -0,0 +1,456 @@
+//! Merging the sounds and collections of another vault into this one
+//!
+//! Each sound of the other vault is matched with one of this vault by
+//! content hash, then by Freesound ID, or copied in as a new sound. Each
+//! sound is recorded as merged in the transaction that merges it, so a merge
+//! that was interrupted picks up where it stopped when run again; each file
+//! is copied as a journaled operation, so an interruption never leaves half
+//! of one behind.
+
+use crate::error::{Result, VaultError};
+use crate::journal::OpKind;
+use crate::levels::Content;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{Collection, SoundMetadata};
+use crate::patch::MetadataPatch;
+use crate::paths::{copy_atomic, write_atomic};
+use crate::provenance::ProvenanceMode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::SqliteConnection;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use uuid::Uuid;
+
+/// How [`SoundVault::merge_from`](crate::SoundVault::merge_from) settles the
+/// tags, custom metadata and descriptions two matching sounds disagree on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergePolicy {
+    /// Keep both: tags of either sound, custom keys of either, this vault's
+    /// value for keys both set, and both descriptions one after the other
+    #[default]
+    Union,
+    /// Keep this vault's values
+    Ours,
+    /// Take the other vault's values
+    Theirs,
+}
+
+/// What a merge kept of a field the two vaults disagreed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeDecision {
+    /// This vault's value
+    KeptOurs,
+    /// The other vault's value
+    TookTheirs,
+    /// Both values, combined
+    Combined,
+}
+
+/// A field two matching sounds disagreed on, and how the merge settled it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeConflict {
+    /// ID of the sound in this vault
+    pub sound_id: String,
+
+    /// ID of the sound in the other vault
+    pub other_id: String,
+
+    /// `tags`, `description`, or `custom.` and the key
+    pub field: String,
+
+    /// This vault's value before the merge; `null` if unset
+    pub ours: Value,
+
+    /// The other vault's value; `null` if unset
+    pub theirs: Value,
+
+    /// The value kept; `null` if unset
+    pub merged: Value,
+
+    /// How it was settled
+    pub decision: MergeDecision,
+}
+
+/// Outcome of [`SoundVault::merge_from`](crate::SoundVault::merge_from)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// IDs of the sounds of this vault with the content of one of the other
+    pub matched_by_hash: Vec<String>,
+
+    /// IDs of the sounds of this vault with the Freesound ID of one of the
+    /// other and a different content
+    pub matched_by_freesound_id: Vec<String>,
+
+    /// IDs of the sounds copied in from the other vault
+    pub imported: Vec<String>,
+
+    /// Sounds of the other vault left out, with the reason; merging again
+    /// retries them
+    pub skipped: Vec<(String, String)>,
+
+    /// Every field matching sounds disagreed on, and how it was settled
+    pub conflicts: Vec<MergeConflict>,
+
+    /// Names of the collections created
+    pub collections_created: Vec<String>,
+
+    /// Names of the collections of this vault that got sounds of the other's
+    /// collection of the same name
+    pub collections_merged: Vec<String>,
+
+    /// Sounds merged by an earlier, interrupted run, counted in the lists
+    /// above with the conflicts it settled
+    pub resumed: usize,
+}
+
+/// How a sound of the other vault was merged, as recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Matched {
+    Hash,
+    FreesoundId,
+    Imported,
+}
+
+impl Matched {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hash => "hash",
+            Self::FreesoundId => "freesound_id",
+            Self::Imported => "imported",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "hash" => Ok(Self::Hash),
+            "freesound_id" => Ok(Self::FreesoundId),
+            "imported" => Ok(Self::Imported),
+            _ => Err(VaultError::InvalidOperation(format!("Unknown merge match: {}", name))),
+        }
+    }
+}
+
+impl MergeReport {
+    fn record(&mut self, matched: Matched, sound_id: &str) {
+        let list = match matched {
+            Matched::Hash => &mut self.matched_by_hash,
+            Matched::FreesoundId => &mut self.matched_by_freesound_id,
+            Matched::Imported => &mut self.imported,
+        };
+        list.push(sound_id.to_string());
+    }
+}
+
+/// Settle one field two sounds disagree on
+///
+/// # Returns
+///
+/// The value kept and the decision, or `None` if they agree
+fn settle(policy: MergePolicy, ours: &Value, theirs: &Value, union: impl FnOnce() -> Value) -> Option<(Value, MergeDecision)> {
+    if ours == theirs {
+        return None;
+    }
+    Some(match policy {
+        MergePolicy::Ours => (ours.clone(), MergeDecision::KeptOurs),
+        MergePolicy::Theirs => (theirs.clone(), MergeDecision::TookTheirs),
+        MergePolicy::Union => match union() {
+            merged if merged == *ours => (merged, MergeDecision::KeptOurs),
+            merged if merged == *theirs => (merged, MergeDecision::TookTheirs),
+            merged => (merged, MergeDecision::Combined),
+        },
+    })
+}
+
+/// Merge the tags, custom metadata and description of the other vault's
+/// sound into ours
+///
+/// # Returns
+///
+/// The conflicts, with `sound_id` and `other_id` to fill in
+fn merge_fields(policy: MergePolicy, ours: &mut SoundMetadata, theirs: &SoundMetadata) -> Result<Vec<MergeConflict>> {
+    let mut conflicts = Vec::new();
+    let mut conflict = |field: String, ours: Value, theirs: Value, (merged, decision): (Value, MergeDecision)| {
+        conflicts.push(MergeConflict {
+            sound_id: String::new(),
+            other_id: String::new(),
+            field,
+            ours,
+            theirs,
+            merged,
+            decision,
+        });
+    };
+
+    let (our_tags, their_tags): (BTreeSet<&String>, BTreeSet<&String>) = (ours.tags.iter().collect(), theirs.tags.iter().collect());
+    let (before, other) = (Value::from(ours.tags.clone()), Value::from(theirs.tags.clone()));
+    let union = || {
+        let mut tags = ours.tags.clone();
+        tags.extend(theirs.tags.iter().filter(|tag| !our_tags.contains(tag)).cloned());
+        Value::from(tags)
+    };
+    if our_tags != their_tags
+        && let Some(settled) = settle(policy, &before, &other, union)
+    {
+        ours.tags = serde_json::from_value(settled.0.clone())?;
+        conflict("tags".to_string(), before, other, settled);
+    }
+
+    let keys: BTreeSet<String> = ours.custom.keys().chain(theirs.custom.keys()).cloned().collect();
+    for key in keys {
+        let before = ours.custom.get(&key).map_or(Value::Null, |value| Value::from(value.as_str()));
+        let other = theirs.custom.get(&key).map_or(Value::Null, |value| Value::from(value.as_str()));
+        let union = || if before.is_null() { other.clone() } else { before.clone() };
+        let Some(settled) = settle(policy, &before, &other, union) else {
+            continue;
+        };
+        match settled.0.as_str() {
+            Some(value) => ours.custom.insert(key.clone(), value.to_string()),
+            None => ours.custom.remove(&key),
+        };
+        conflict(format!("custom.{}", key), before, other, settled);
+    }
+
+    let (before, other) = (Value::from(ours.description.as_str()), Value::from(theirs.description.as_str()));
+    let union = || match (ours.description.trim(), theirs.description.trim()) {
+        ("", _) => other.clone(),
+        (_, "") => before.clone(),
+        (a, b) => Value::from(format!("{}\n\n{}", a, b)),
+    };
+    if let Some(settled) = settle(policy, &before, &other, union) {
+        ours.description = settled.0.as_str().unwrap_or_default().to_string();
+        conflict("description".to_string(), before, other, settled);
+    }
+
+    Ok(conflicts)
+}
+
+impl LocalLibrary {
+    /// Merge the sounds and collections of another vault into this one
+    ///
+    /// Sounds whose file can't be read, or that this vault rejects, are
+    /// skipped and tried again by the next merge.
+    pub(crate) async fn merge_from(&self, other: &LocalLibrary, policy: MergePolicy) -> Result<MergeReport> {
+        if other.vault_id == self.vault_id {
+            return Err(VaultError::InvalidOperation("Can't merge a vault into itself".to_string()));
+        }
+
+        let mut report = MergeReport::default();
+        let mut merged: HashMap<String, String> = HashMap::new();
+        let rows: Vec<(String, String, String, String)> = sqlx::query_as(&self.sql(
+            "SELECT source_id, sound_id, matched, conflicts FROM merged_sounds WHERE source_vault = ? ORDER BY rowid",
+        ))
+        .bind(&other.vault_id)
+        .fetch_all(&self.reader)
+        .await?;
+        for (source_id, sound_id, matched, conflicts) in rows {
+            report.record(Matched::parse(&matched)?, &sound_id);
+            report.conflicts.extend(serde_json::from_str::<Vec<MergeConflict>>(&conflicts)?);
+            report.resumed += 1;
+            merged.insert(source_id, sound_id);
+        }
+
+        // Originals first, so derived sounds can point at their copy
+        let mut ids: Vec<(bool, String)> = sqlx::query_as(&other.sql("SELECT derived_from IS NOT NULL, id FROM sounds"))
+            .fetch_all(&other.reader)
+            .await?;
+        ids.sort();
+        for (_, id) in ids {
+            if merged.contains_key(&id) {
+                continue;
+            }
+            match self.merge_sound(other, &id, policy, &merged).await {
+                Ok((sound_id, matched, conflicts)) => {
+                    report.record(matched, &sound_id);
+                    report.conflicts.extend(conflicts);
+                    merged.insert(id, sound_id);
+                }
+                Err(e @ VaultError::Database(_)) => return Err(e),
+                Err(e) => report.skipped.push((id, e.to_string())),
+            }
+        }
+
+        let ours = self.list_collections(false).await?;
+        for collection in other.list_collections(false).await? {
+            let members: Vec<String> = collection.sound_ids.iter().filter_map(|id| merged.get(id).cloned()).collect();
+            let (collection_id, created) = match ours.iter().find(|ours| ours.name == collection.name) {
+                Some(ours) => (ours.id.to_string(), false),
+                None => {
+                    let copy = Collection {
+                        id: Uuid::new_v4(),
+                        sound_ids: Vec::new(),
+                        expires_at: None,
+                        version: 0,
+                        ..collection.clone()
+                    };
+                    (self.add_collection(&copy).await?, true)
+                }
+            };
+            let mut added = 0;
+            for sound_id in &members {
+                added += self.add_sound_to_collections(sound_id, std::slice::from_ref(&collection_id)).await?;
+            }
+            if created {
+                report.collections_created.push(collection.name);
+            } else if added > 0 {
+                report.collections_merged.push(collection.name);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Merge a sound of another vault, recording it as merged
+    ///
+    /// `merged` maps the other vault's IDs of the sounds merged so far to
+    /// those of this vault.
+    async fn merge_sound(
+        &self,
+        other: &LocalLibrary,
+        id: &str,
+        policy: MergePolicy,
+        merged: &HashMap<String, String>,
+    ) -> Result<(String, Matched, Vec<MergeConflict>)> {
+        let mut theirs = other.stored_sound(id).await?;
+        if theirs.hash.is_none() && theirs.path.is_some() {
+            theirs.hash = Some(other.content_hash(&theirs)?);
+        }
+
+        let by_hash = match &theirs.hash {
+            Some(hash) => self.sound_with_hash(hash).await?,
+            None => None,
+        };
+        let by_freesound_id = match (&by_hash, theirs.freesound_id) {
+            (None, Some(freesound_id)) => self.find_freesound_sound(freesound_id).await?,
+            _ => None,
+        };
+        let (sound_id, matched) = match (by_hash, by_freesound_id) {
+            (Some(sound_id), _) => (sound_id, Matched::Hash),
+            (None, Some(sound_id)) => (sound_id, Matched::FreesoundId),
+            (None, None) => {
+                let sound_id = self.import_merged(other, id, &theirs, merged).await?;
+                return Ok((sound_id, Matched::Imported, Vec::new()));
+            }
+        };
+        let before = self.get_sound(&sound_id).await?.metadata;
+        let mut after = before.clone();
+        let mut conflicts = merge_fields(policy, &mut after, &theirs)?;
+        for conflict in &mut conflicts {
+            conflict.sound_id = sound_id.clone();
+            conflict.other_id = id.to_string();
+        }
+
+        // The settled fields and the record of the merge are written
+        // together, so a merge run again never settles them twice
+        let mut tx = self.db.begin().await?;
+        if !conflicts.is_empty() {
+            self.apply_patch(&mut tx, &sound_id, &MetadataPatch::between(&before, &after)).await?;
+        }
+        self.record_merged(&mut tx, other, id, &sound_id, matched, &conflicts).await?;
+        tx.commit().await?;
+
+        Ok((sound_id, matched, conflicts))
+    }
+
+    /// Record a sound of another vault as merged into `sound_id`, copying
+    /// its provenance chain along
+    async fn record_merged(
+        &self,
+        conn: &mut SqliteConnection,
+        other: &LocalLibrary,
+        other_id: &str,
+        sound_id: &str,
+        matched: Matched,
+        conflicts: &[MergeConflict],
+    ) -> Result<()> {
+        for entry in other.provenance(other_id).await? {
+            self.write_provenance(conn, sound_id, &entry).await?;
+        }
+        sqlx::query(&self.sql(
+            "INSERT INTO merged_sounds (source_vault, source_id, sound_id, matched, conflicts) VALUES (?, ?, ?, ?, ?)",
+        ))
+        .bind(&other.vault_id)
+        .bind(other_id)
+        .bind(sound_id)
+        .bind(matched.as_str())
+        .bind(serde_json::to_string(conflicts)?)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Copy a sound of another vault into this one, as a new sound recorded
+    /// as merged in the same transaction
+    async fn import_merged(
+        &self,
+        other: &LocalLibrary,
+        other_id: &str,
+        theirs: &SoundMetadata,
+        merged: &HashMap<String, String>,
+    ) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+        let mut metadata = SoundMetadata {
+            id: id.clone(),
+            external: false,
+            packed: false,
+            archive: None,
+            storage_backend: None,
+            slug: None,
+            derived_from: theirs.derived_from.as_ref().and_then(|parent| merged.get(parent).cloned()),
+            ..theirs.clone()
+        };
+        if theirs.path.is_none() {
+            let mut tx = self.db.begin().await?;
+            self.insert_sound_on(&mut tx, &metadata, None, None).await?;
+            self.record_merged(&mut tx, other, other_id, &id, Matched::Imported, &[]).await?;
+            tx.commit().await?;
+            return Ok(id);
+        }
+
+        let content = other.sound_stream(theirs)?;
+        let source = match &content {
+            Content::File(path) => path.clone(),
+            Content::Bytes(_) => Self::logical_path(theirs).unwrap_or_default(),
+        };
+        let file_name = source.file_name().map_or_else(|| id.clone(), |name| name.to_string_lossy().to_string());
+        let target_path = self.library_file(&self.library_path.join(&id).join(file_name))?;
+        let size = match &content {
+            Content::File(path) => std::fs::metadata(path)?.len(),
+            Content::Bytes(bytes) => bytes.len() as u64,
+        };
+
+        let reservation = self.reserve_space(size).await?;
+        let result = async {
+            let op = self.begin_op(OpKind::Import, &[&target_path], &[]).await?;
+            let result = async {
+                std::fs::create_dir_all(target_path.parent().unwrap_or(&self.library_path)).map_err(|e| {
+                    VaultError::FileSystem(format!("Failed to create directory: {}", e))
+                })?;
+                match &content {
+                    Content::File(path) => copy_atomic(path, &target_path),
+                    Content::Bytes(bytes) => write_atomic(&target_path, bytes),
+                }
+                .map_err(|e| VaultError::FileSystem(format!("Failed to copy file: {}", e)))?;
+
+                metadata.hash = Some(hash_file(&target_path)?);
+                metadata.path = Some(target_path.clone());
+                let provenance = self.local_provenance(ProvenanceMode::Merge, Path::new(&source));
+                let mut tx = self.db.begin().await?;
+                self.insert_sound_on(&mut tx, &metadata, Some(&op), Some(&provenance)).await?;
+                self.record_merged(&mut tx, other, other_id, &id, Matched::Imported, &[]).await?;
+                tx.commit().await?;
+                Ok(())
+            }
+            .await;
+            self.settle_op(&op, result).await
+        }
+        .await;
+        self.release_space(reservation).await?;
+        result?;
+
+        Ok(id)
+    }
+}
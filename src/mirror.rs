@@ -0,0 +1,317 @@
+//! One-way mirroring of a directory into the vault
+
+use crate::context::OpContext;
+use crate::error::{Result, VaultError};
+#[cfg(feature = "analysis")]
+use crate::fingerprint::PossibleDuplicate;
+use crate::import::ImportOptions;
+use crate::local::{LocalLibrary, hash_file};
+use crate::undo::OperationId;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Options of [`SoundVault::sync_directory`](crate::SoundVault::sync_directory)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncOptions {
+    /// Glob patterns of files to leave out, matched against paths relative to
+    /// the synced directory (`*` and `?` within a name, `**` across folders)
+    pub exclude: Vec<String>,
+
+    /// Delete the sounds whose source file was deleted
+    pub delete_missing: bool,
+
+    /// How new files are imported
+    pub import: ImportOptions,
+}
+
+/// Outcome of [`SoundVault::sync_directory`](crate::SoundVault::sync_directory)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DirectorySyncReport {
+    /// IDs of the sounds imported from new files
+    pub imported: Vec<String>,
+
+    /// IDs of existing sounds new files were linked to, having the same content
+    pub linked: Vec<String>,
+
+    /// IDs of the sounds whose file was replaced by a changed source file
+    pub updated: Vec<String>,
+
+    /// IDs of the sounds deleted because their source file was deleted
+    pub deleted: Vec<String>,
+
+    /// Number of files found unchanged
+    pub unchanged: usize,
+
+    /// Files that couldn't be synced, with the reason
+    pub errors: Vec<(PathBuf, String)>,
+
+    /// Operation to pass to [`SoundVault::undo_operation`](crate::SoundVault::undo_operation)
+    /// to take the sync back
+    #[serde(default)]
+    pub operation: Option<OperationId>,
+
+    /// Imported sounds that may hold the same audio as a sound already in
+    /// the vault, when [`VaultConfig::near_duplicate_threshold`](crate::VaultConfig::near_duplicate_threshold)
+    /// is set; both were kept
+    #[cfg(feature = "analysis")]
+    #[serde(default)]
+    pub possible_duplicates: Vec<PossibleDuplicate>,
+}
+
+/// What the last sync recorded about a source file
+struct SyncedFile {
+    sound_id: String,
+    size: i64,
+    mtime: i64,
+    hash: String,
+}
+
+impl LocalLibrary {
+    /// Mirror a directory into the vault
+    ///
+    /// New files are imported, or linked to an existing sound with the same
+    /// content; changed files replace the file of their sound. The link
+    /// between a source file and its sound survives renames in the vault.
+    /// Files whose size and modification time didn't change are not hashed.
+    ///
+    /// Each file is synced on its own: if `context` stops the sync, the
+    /// files synced so far stay synced. Sounds of deleted files are only
+    /// deleted once every file was synced.
+    pub async fn sync_directory(
+        &self,
+        dir: &Path,
+        options: &SyncOptions,
+        context: &OpContext,
+    ) -> Result<DirectorySyncReport> {
+        let deadline = context.start("sync_directory");
+        let root = dir
+            .canonicalize()
+            .map_err(|e| VaultError::FileSystem(format!("Failed to resolve {:?}: {}", dir, e)))?;
+        let root_key = root.to_string_lossy().to_string();
+        if let Some(template) = &options.import.template {
+            self.import_template(template).await?;
+        }
+        let library = self.library_path.canonicalize().unwrap_or_else(|_| self.library_path.clone());
+
+        let rows = sqlx::query(&self.sql("SELECT source_path, sound_id, size, mtime, hash FROM sync_sources WHERE source_root = ?"))
+            .bind(&root_key)
+            .fetch_all(&self.reader)
+            .await?;
+        let mut known = HashMap::new();
+        for row in rows {
+            let file = SyncedFile {
+                sound_id: row.try_get("sound_id")?,
+                size: row.try_get("size")?,
+                mtime: row.try_get("mtime")?,
+                hash: row.try_get("hash")?,
+            };
+            known.insert(row.try_get::<String, _>("source_path")?, file);
+        }
+
+        let mut files = Vec::new();
+        collect_files(&root, &mut files)?;
+        let files: Vec<(PathBuf, String)> = files
+            .into_iter()
+            // Never feed the vault its own files
+            .filter(|path| !path.starts_with(&library))
+            .map(|path| {
+                let relative = path
+                    .strip_prefix(&root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                (path, relative)
+            })
+            .filter(|(_, relative)| !options.exclude.iter().any(|pattern| glob_match(pattern, relative)))
+            .collect();
+
+        let mut report = DirectorySyncReport::default();
+        let total = files.len();
+        for (done, (path, relative)) in files.into_iter().enumerate() {
+            deadline.check(|| format!("{} of {} files synced", done, total))?;
+            let previous = known.remove(&relative);
+            if let Err(e) = self.sync_file(&root_key, &relative, &path, previous, options, &mut report).await {
+                report.errors.push((path, e.to_string()));
+            }
+        }
+
+        // What's left was deleted at the source
+        let deleted = known.len();
+        for (done, (relative, file)) in known.into_iter().enumerate() {
+            deadline.check(|| format!("{} files synced, {} of {} deleted files handled", total, done, deleted))?;
+            sqlx::query(&self.sql("DELETE FROM sync_sources WHERE source_root = ? AND source_path = ?"))
+                .bind(&root_key)
+                .bind(&relative)
+                .execute(&self.db)
+                .await?;
+
+            // Keep sounds other source files are still linked to
+            let linked: i64 = sqlx::query_scalar(&self.sql("SELECT COUNT(*) FROM sync_sources WHERE sound_id = ?"))
+                .bind(&file.sound_id)
+                .fetch_one(&self.reader)
+                .await?;
+            if options.delete_missing && linked == 0 {
+                match self.delete_sound(&file.sound_id, false).await {
+                    Ok(()) => report.deleted.push(file.sound_id),
+                    Err(VaultError::NotFound(_)) => {}
+                    Err(e) => report.errors.push((root.join(&relative), e.to_string())),
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Bring the sound of one source file up to date
+    async fn sync_file(
+        &self,
+        root: &str,
+        relative: &str,
+        path: &Path,
+        previous: Option<SyncedFile>,
+        options: &SyncOptions,
+        report: &mut DirectorySyncReport,
+    ) -> Result<()> {
+        let metadata = std::fs::metadata(path)?;
+        let size = metadata.len() as i64;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or_default();
+
+        let previous = match previous {
+            Some(file) if self.get_sound(&file.sound_id).await.is_ok() => Some(file),
+            _ => None,
+        };
+        if let Some(file) = &previous
+            && file.size == size
+            && file.mtime == mtime
+        {
+            report.unchanged += 1;
+            return Ok(());
+        }
+
+        let hash = hash_file(path)?;
+        let sound_id = match previous {
+            Some(file) if file.hash == hash => {
+                report.unchanged += 1;
+                file.sound_id
+            }
+            Some(file) => {
+                // A referenced file changed in place; a copied one is copied again
+                if self.get_sound(&file.sound_id).await?.metadata.external {
+                    self.relink(&file.sound_id, path, true).await?;
+                } else {
+                    self.replace_file(&file.sound_id, path, false).await?;
+                }
+                report.updated.push(file.sound_id.clone());
+                file.sound_id
+            }
+            None => {
+                match self.sound_with_hash(&hash).await? {
+                    Some(id) => {
+                        report.linked.push(id.clone());
+                        id
+                    }
+                    None => {
+                        let id = self.import_file_with_options(path, None, &options.import).await?;
+                        #[cfg(feature = "analysis")]
+                        if let Some(threshold) = self.near_duplicate_threshold {
+                            report.possible_duplicates.extend(self.possible_duplicates(&id, threshold).await.unwrap_or_default());
+                        }
+                        report.imported.push(id.clone());
+                        id
+                    }
+                }
+            }
+        };
+
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO sync_sources (source_root, source_path, sound_id, size, mtime, hash)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(source_root, source_path) DO UPDATE SET
+                sound_id = excluded.sound_id,
+                size = excluded.size,
+                mtime = excluded.mtime,
+                hash = excluded.hash
+            "#),
+        )
+        .bind(root)
+        .bind(relative)
+        .bind(&sound_id)
+        .bind(size)
+        .bind(mtime)
+        .bind(&hash)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Recursively list the files under a directory, skipping hidden entries
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| VaultError::FileSystem(format!("Failed to read directory {:?}: {}", dir, e)))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Match a `/`-separated relative path against a glob pattern
+///
+/// `*` and `?` don't cross `/`; `**` matches any number of folders. A pattern
+/// without `/` is matched against the file name alone.
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    if !pattern.contains('/') {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        return match_name(pattern, name);
+    }
+
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((first, rest)) => path
+            .split_first()
+            .is_some_and(|(name, path)| match_name(first, name) && match_segments(rest, path)),
+    }
+}
+
+fn match_name(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_chars(&pattern, &name)
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| match_chars(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && match_chars(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_chars(rest, &name[1..]),
+    }
+}
@@ -1,21 +1,30 @@
 //! Data models for the SoundVault library
 
+use crate::archive::Archive;
+use crate::error::VaultError;
+use crate::review::SoundStatus;
+use crate::sniff::FileFormat;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
 use std::path::PathBuf;
 use uuid::Uuid;
 
 /// Source of a sound (local or remote)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SoundSource {
     /// Sound is stored in the local library
+    #[default]
     Local,
     /// Sound is from Freesound.org
     Freesound,
+    /// Sound is from another remote source, identified by its name
+    Remote(String),
 }
 
 /// Metadata for a sound
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SoundMetadata {
     /// Unique identifier for the sound
     pub id: String,
@@ -23,10 +32,18 @@ pub struct SoundMetadata {
     /// Name of the sound
     pub name: String,
 
+    /// Short readable name unique in the vault, e.g. `wind-gust-03`; given
+    /// when the sound is stored, and kept or changed on rename as
+    /// [`VaultConfig::slug_policy`](crate::VaultConfig::slug_policy) says
+    #[serde(default)]
+    pub slug: Option<String>,
+
     /// Source of the sound
     pub source: SoundSource,
 
-    /// Tags associated with the sound
+    /// Tags associated with the sound, trimmed, deduplicated and sorted
+    /// when read from the vault
+    #[serde(deserialize_with = "deserialize_tags")]
     pub tags: Vec<String>,
 
     /// Description of the sound
@@ -35,17 +52,177 @@ pub struct SoundMetadata {
     /// Duration in seconds
     pub duration: f32,
 
+    /// Number of audio channels, if known
+    #[serde(default)]
+    pub channels: Option<u16>,
+
+    /// Sample rate in Hz, if known
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+
+    /// User rating, e.g. from 1 to 5
+    #[serde(default)]
+    pub rating: Option<u8>,
+
+    /// Part of the sound to play, if it was trimmed
+    #[serde(default)]
+    pub trim: Option<Trim>,
+
     /// License information
     pub license: String,
 
     /// Path to the file (for local sounds)
     pub path: Option<PathBuf>,
 
+    /// The file is referenced in place outside the library; the vault reads
+    /// it but never modifies or deletes it
+    #[serde(default)]
+    pub external: bool,
+
+    /// The file is an entry of a zip archive, read in place; `path` is
+    /// `<archive>!<entry>`, and the sound is [`external`](Self::external)
+    /// too. See [`SoundVault::index_archive`](crate::SoundVault::index_archive)
+    #[serde(default)]
+    pub packed: bool,
+
+    /// Edits, file replacement and deletion are refused, e.g. once the
+    /// sound is approved for shipping; see
+    /// [`SoundVault::set_locked`](crate::SoundVault::set_locked)
+    #[serde(default)]
+    pub locked: bool,
+
+    /// Where the sound stands in the review of imports; only active sounds
+    /// are searched and listed by default
+    #[serde(default)]
+    pub status: SoundStatus,
+
+    /// When the sound's license runs out; an expired sound is left out of
+    /// exports and manifests, and dealt with by
+    /// [`SoundVault::enforce_expirations`](crate::SoundVault::enforce_expirations)
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// When the sound was last opened, as of the last flush of accesses;
+    /// see [`SoundVault::access_sound`](crate::SoundVault::access_sound).
+    /// Listings and lookups don't count
+    #[serde(default)]
+    pub last_accessed_at: Option<DateTime<Utc>>,
+
     /// Freesound ID (for remote sounds)
     pub freesound_id: Option<i32>,
 
+    /// ID of the sound at its remote source, as a string for every source
+    #[serde(default)]
+    pub remote_id: Option<String>,
+
+    /// SHA-256 hash of the stored file (for local sounds)
+    #[serde(default)]
+    pub hash: Option<String>,
+
+    /// ID of the sound this one was derived from
+    ///
+    /// A derivative is a processed copy of its parent, so the two are related
+    /// by design rather than duplicates.
+    #[serde(default)]
+    pub derived_from: Option<String>,
+
+    /// Compression of the stored file, if it was archived
+    #[serde(default)]
+    pub archive: Option<Archive>,
+
+    /// Format read from the header of the file, which its extension may
+    /// disagree with; `None` if unknown
+    #[serde(default)]
+    pub format: Option<FileFormat>,
+
+    /// Blob store holding the file, if not the library; see
+    /// [`BlobStore`](crate::BlobStore)
+    #[serde(default)]
+    pub storage_backend: Option<String>,
+
     /// Additional custom metadata
     pub custom: HashMap<String, String>,
+
+    /// Translated name and description by language tag
+    #[serde(default)]
+    pub localizations: BTreeMap<String, Localization>,
+
+    /// Analysis descriptors fetched from the remote source, such as
+    /// `pitch` or `spectral_centroid`; descriptors the source didn't
+    /// compute are absent
+    #[serde(default)]
+    pub descriptors: HashMap<String, f64>,
+}
+
+/// Markers delimiting the part of a sound to play, in seconds from its start
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::Trim;
+///
+/// let trim = Trim { start: 0.5, end: Some(1.5) };
+/// assert!(trim.check().is_ok());
+/// // 8 kHz sound of 10000 frames
+/// assert_eq!(trim.frames(8000, 10_000), 4000..10_000);
+/// assert_eq!(Trim { start: 0.5, end: None }.frames(8000, 2000), 2000..2000);
+/// assert!(Trim { start: 2.0, end: Some(1.0) }.check().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Trim {
+    /// Where the part starts
+    pub start: f32,
+
+    /// Where the part ends; `None` keeps the rest of the sound
+    #[serde(default)]
+    pub end: Option<f32>,
+}
+
+impl Trim {
+    /// Check that the markers are finite, not negative and in order
+    pub fn check(&self) -> crate::error::Result<()> {
+        let valid = self.start.is_finite()
+            && self.start >= 0.0
+            && self.end.is_none_or(|end| end.is_finite() && end > self.start);
+
+        if valid {
+            Ok(())
+        } else {
+            Err(VaultError::InvalidOperation(format!("Invalid trim markers: {:?}", self)))
+        }
+    }
+
+    /// Frames between the markers in a sound of `frames` frames, clamped to
+    /// the sound
+    pub fn frames(&self, sample_rate: u32, frames: usize) -> Range<usize> {
+        let frame = |seconds: f32| ((seconds.max(0.0) as f64 * sample_rate as f64).round() as usize).min(frames);
+        let start = frame(self.start);
+        let end = self.end.map_or(frames, frame);
+        start..end.max(start)
+    }
+}
+
+/// Name and description of a sound in one language
+///
+/// Texts left unset fall back to the sound's default name and description.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Localization {
+    /// Translated name
+    pub name: Option<String>,
+
+    /// Translated description
+    pub description: Option<String>,
+}
+
+/// Texts of a sound as seen in one language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalizedView<'a> {
+    /// Name in the language, or the default name
+    pub name: &'a str,
+
+    /// Description in the language, or the default description
+    pub description: &'a str,
 }
 
 /// Sound object with metadata and content information
@@ -60,10 +237,41 @@ pub struct Sound {
     /// Whether the sound is available locally
     pub is_cached: bool,
 
+    /// Whether the sound's file can be read right now
+    #[serde(default)]
+    pub availability: Availability,
+
     /// URL for downloading the sound (only for remote sounds)
     pub download_url: Option<String>,
 }
 
+/// Whether a sound's file can be read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Availability {
+    /// The file is where the sound points
+    Available,
+    /// The sound points at a file that isn't there, e.g. on an unplugged
+    /// drive
+    FileMissing,
+    /// The sound has no file, like a remote sound not downloaded yet
+    #[default]
+    NotDownloaded,
+}
+
+impl Availability {
+    /// Availability of the file of a sound, checked on disk; a file in
+    /// another blob store counts as available
+    pub(crate) fn of(metadata: &SoundMetadata) -> Self {
+        match &metadata.path {
+            Some(_) if metadata.storage_backend.is_some() => Self::Available,
+            Some(path) if path.exists() => Self::Available,
+            Some(_) => Self::FileMissing,
+            None => Self::NotDownloaded,
+        }
+    }
+}
+
 /// Collection of sounds
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Collection {
@@ -76,11 +284,161 @@ pub struct Collection {
     /// Description of the collection
     pub description: String,
 
-    /// Sound IDs in the collection
+    /// Sound IDs in the collection, in order
     pub sound_ids: Vec<String>,
 
+    /// Metadata inherited by sounds added to the collection
+    #[serde(default)]
+    pub defaults: CollectionDefaults,
+
     /// Additional custom metadata
     pub custom: HashMap<String, String>,
+
+    /// When a session collection expires; `None` for a permanent one
+    ///
+    /// See [`SoundVault::create_session_collection`](crate::SoundVault::create_session_collection).
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// Number of changes to the members or their order, to reorder with
+    /// [`SoundVault::reorder_collection`](crate::SoundVault::reorder_collection);
+    /// ignored when adding a collection
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// A collection without its members
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionSummary {
+    /// Unique identifier for the collection
+    pub id: Uuid,
+
+    /// Name of the collection
+    pub name: String,
+
+    /// Description of the collection
+    pub description: String,
+
+    /// Number of sounds in the collection
+    pub sound_count: u64,
+}
+
+/// Default metadata a collection hands down to its sounds
+///
+/// Inheritance is additive: tags are merged, custom values and the license
+/// are only filled in when the sound doesn't already have one. Removing a
+/// sound from the collection keeps whatever it inherited.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{CollectionDefaults, SoundMetadata};
+///
+/// let mut defaults = CollectionDefaults::default();
+/// defaults.tags = vec!["ambience".to_string(), "ice".to_string()];
+/// defaults.set_custom("project", "Ice Cave");
+///
+/// let mut metadata = SoundMetadata::default();
+/// metadata.tags = vec!["ice".to_string()];
+/// assert!(defaults.apply_to(&mut metadata));
+/// assert_eq!(metadata.tags, vec!["ambience", "ice"]);
+/// assert_eq!(metadata.get_custom("project").map(String::as_str), Some("Ice Cave"));
+///
+/// // Applying again changes nothing
+/// assert!(!defaults.apply_to(&mut metadata));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionDefaults {
+    /// Apply the defaults automatically when a sound is added to the collection
+    pub apply_on_add: bool,
+
+    /// Tags added to member sounds
+    pub tags: Vec<String>,
+
+    /// Custom metadata set on member sounds lacking the key
+    pub custom: HashMap<String, String>,
+
+    /// License set on member sounds whose license is unknown
+    pub license: Option<String>,
+}
+
+impl SoundSource {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Local => "local",
+            Self::Freesound => "freesound",
+            Self::Remote(name) => name,
+        }
+    }
+
+    /// Parse a name stored in the database, defaulting to local
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::SoundSource;
+    ///
+    /// assert_eq!(SoundSource::parse("freesound"), SoundSource::Freesound);
+    /// assert_eq!(SoundSource::parse("studio"), SoundSource::Remote("studio".to_string()));
+    /// assert_eq!(SoundSource::parse(""), SoundSource::Local);
+    /// ```
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "freesound" => Self::Freesound,
+            "local" | "" => Self::Local,
+            name => Self::Remote(name.to_string()),
+        }
+    }
+
+    /// Source of the sounds of the remote source with this name
+    pub fn remote(name: &str) -> Self {
+        match Self::parse(name) {
+            Self::Local => Self::Remote(name.to_string()),
+            source => source,
+        }
+    }
+}
+
+impl CollectionDefaults {
+    /// Check whether there is nothing to inherit
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.custom.is_empty() && self.license.is_none()
+    }
+
+    /// Set a custom metadata value to inherit
+    pub fn set_custom(&mut self, key: &str, value: &str) {
+        self.custom.insert(key.to_string(), value.to_string());
+    }
+
+    /// Apply the defaults to sound metadata
+    ///
+    /// # Returns
+    ///
+    /// Whether the metadata was changed
+    pub fn apply_to(&self, metadata: &mut SoundMetadata) -> bool {
+        let mut changed = false;
+
+        for tag in &self.tags {
+            changed |= metadata.add_tag(tag);
+        }
+
+        for (key, value) in &self.custom {
+            if !metadata.custom.contains_key(key) {
+                metadata.custom.insert(key.clone(), value.clone());
+                changed = true;
+            }
+        }
+
+        if let Some(license) = &self.license
+            && (metadata.license.is_empty() || metadata.license == "Unknown")
+        {
+            metadata.license = license.clone();
+            changed = true;
+        }
+
+        changed
+    }
 }
 
 impl Collection {
@@ -102,7 +460,10 @@ impl Collection {
             name: name.to_string(),
             description: description.to_string(),
             sound_ids: Vec::new(),
+            defaults: CollectionDefaults::default(),
             custom: HashMap::new(),
+            expires_at: None,
+            version: 0,
         }
     }
 
@@ -158,6 +519,51 @@ impl Collection {
 }
 
 impl SoundMetadata {
+    /// View the name and description in a language
+    ///
+    /// A regional tag such as `fr-CA` falls back to `fr`, then to the default
+    /// texts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::SoundMetadata;
+    ///
+    /// let mut metadata = SoundMetadata::default();
+    /// metadata.name = "Door slam".to_string();
+    /// metadata.description = "Heavy wooden door".to_string();
+    /// metadata.set_localized_name("fr", "Claquement de porte");
+    ///
+    /// let french = metadata.localized("fr-CA");
+    /// assert_eq!(french.name, "Claquement de porte");
+    /// assert_eq!(french.description, "Heavy wooden door");
+    /// assert_eq!(metadata.localized("de").name, "Door slam");
+    /// ```
+    pub fn localized(&self, lang: &str) -> LocalizedView<'_> {
+        let lang = normalize_lang(lang);
+        let primary = lang.split('-').next().unwrap_or_default();
+        let candidates = [self.localizations.get(&lang), self.localizations.get(primary)];
+
+        let pick = |field: fn(&Localization) -> Option<&String>| {
+            candidates.iter().flatten().find_map(|l| field(l)).map(String::as_str)
+        };
+
+        LocalizedView {
+            name: pick(|l| l.name.as_ref()).unwrap_or(&self.name),
+            description: pick(|l| l.description.as_ref()).unwrap_or(&self.description),
+        }
+    }
+
+    /// Set the name shown in a language
+    pub fn set_localized_name(&mut self, lang: &str, name: &str) {
+        self.localizations.entry(normalize_lang(lang)).or_default().name = Some(name.to_string());
+    }
+
+    /// Set the description shown in a language
+    pub fn set_localized_description(&mut self, lang: &str, description: &str) {
+        self.localizations.entry(normalize_lang(lang)).or_default().description = Some(description.to_string());
+    }
+
     /// Set a custom metadata value
     pub fn set_custom(&mut self, key: &str, value: &str) {
         self.custom.insert(key.to_string(), value.to_string());
@@ -167,4 +573,90 @@ impl SoundMetadata {
     pub fn get_custom(&self, key: &str) -> Option<&String> {
         self.custom.get(key)
     }
+
+    /// Add a tag, keeping tags in canonical order
+    ///
+    /// # Returns
+    ///
+    /// Whether the tag was added; it's not when empty or already there
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::SoundMetadata;
+    ///
+    /// let mut metadata = SoundMetadata::default();
+    /// assert!(metadata.add_tag("wind"));
+    /// assert!(metadata.add_tag(" Ice "));
+    /// assert!(!metadata.add_tag("wind"));
+    /// assert_eq!(metadata.tags, vec!["Ice", "wind"]);
+    ///
+    /// assert!(metadata.has_tag("Ice"));
+    /// assert!(metadata.remove_tag("Ice"));
+    /// assert!(!metadata.has_tag("Ice"));
+    ///
+    /// // Stored tags are cleaned up when read
+    /// let metadata: SoundMetadata = serde_json::from_value(serde_json::json!({
+    ///     "id": "1", "name": "", "source": "Local", "tags": ["b", "a", "b", " "],
+    ///     "description": "", "duration": 0.0, "license": "", "path": null,
+    ///     "freesound_id": null, "custom": {},
+    /// })).unwrap();
+    /// assert_eq!(metadata.tags, vec!["a", "b"]);
+    /// ```
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let tag = tag.trim();
+        if tag.is_empty() || self.has_tag(tag) {
+            return false;
+        }
+
+        self.tags.push(tag.to_string());
+        self.normalize_tags();
+        true
+    }
+
+    /// Remove a tag
+    ///
+    /// # Returns
+    ///
+    /// Whether the sound had the tag
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let count = self.tags.len();
+        self.tags.retain(|t| t != tag.trim());
+        self.tags.len() != count
+    }
+
+    /// Check whether the sound has a tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag.trim())
+    }
+
+    /// Put tags in canonical form, e.g. after assigning them directly
+    pub fn normalize_tags(&mut self) {
+        self.tags = canonical_tags(std::mem::take(&mut self.tags));
+    }
+}
+
+/// Canonical form of tags: trimmed, without empty or duplicate tags, sorted
+/// by code point so the order doesn't depend on the locale
+pub(crate) fn canonical_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|tag| match tag.trim() {
+            trimmed if trimmed.len() == tag.len() => tag,
+            trimmed => trimmed.to_string(),
+        })
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort_unstable();
+    tags.dedup();
+    tags
+}
+
+fn deserialize_tags<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Vec::<String>::deserialize(deserializer).map(canonical_tags)
+}
+
+/// Normalize a language tag: lowercase, with `-` separators
+pub(crate) fn normalize_lang(lang: &str) -> String {
+    lang.trim().replace('_', "-").to_lowercase()
 }
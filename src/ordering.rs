@@ -0,0 +1,282 @@
+//! Order of the sounds in collections
+//!
+//! Members are numbered from 0 without gaps. Every change of membership or
+//! order bumps the collection's version, so a client reordering from what it
+//! read fails with [`VaultError::VersionConflict`] rather than overwriting a
+//! change made meanwhile. Positions left with gaps or duplicates, e.g. by an
+//! older version of the library, are numbered again in their current order.
+
+use crate::audit::AuditOperation;
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::tables::Tables;
+use sqlx::SqliteConnection;
+
+/// Number the members of collections again from 0, in their current order;
+/// members without a position go last, in the order they were added
+const RENUMBER: &str = r#"
+    UPDATE collection_sounds SET position = ranked.n
+    FROM (
+        SELECT collection_id AS c, sound_id AS s,
+            ROW_NUMBER() OVER (PARTITION BY collection_id ORDER BY position IS NULL, position, rowid) - 1 AS n
+        FROM collection_sounds WHERE ?1 IS NULL OR collection_id = ?1
+    ) AS ranked
+    WHERE collection_id = ranked.c AND sound_id = ranked.s AND position IS NOT ranked.n
+"#;
+
+/// Whether positions, in the order they're read, are 0, 1, 2…
+pub(crate) fn is_dense(positions: &[Option<i64>]) -> bool {
+    positions.iter().enumerate().all(|(index, position)| *position == Some(index as i64))
+}
+
+/// Number the members of every collection from 0, e.g. after adding the
+/// column of positions
+pub(crate) async fn renumber_all(conn: &mut SqliteConnection, tables: &Tables) -> Result<u64> {
+    Ok(sqlx::query(&tables.sql(RENUMBER))
+        .bind(None::<String>)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected())
+}
+
+impl LocalLibrary {
+    /// Add a sound at the end of a collection
+    ///
+    /// # Returns
+    ///
+    /// Whether it wasn't in the collection yet
+    pub(crate) async fn insert_member(&self, conn: &mut SqliteConnection, collection_id: &str, sound_id: &str) -> Result<bool> {
+        let result = sqlx::query(&self.sql(
+            r#"
+            INSERT OR IGNORE INTO collection_sounds (collection_id, sound_id, position)
+            VALUES (?1, ?2, (SELECT COALESCE(MAX(position) + 1, 0) FROM collection_sounds WHERE collection_id = ?1))
+            "#,
+        ))
+        .bind(collection_id)
+        .bind(sound_id)
+        .execute(&mut *conn)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.bump_collection_version(conn, collection_id).await?;
+        Ok(true)
+    }
+
+    /// Take a sound out of a collection, closing the gap it leaves
+    ///
+    /// # Returns
+    ///
+    /// Whether it was in the collection
+    pub(crate) async fn delete_member(&self, conn: &mut SqliteConnection, collection_id: &str, sound_id: &str) -> Result<bool> {
+        let removed: Option<Option<i64>> = sqlx::query_scalar(&self.sql(
+            "DELETE FROM collection_sounds WHERE collection_id = ? AND sound_id = ? RETURNING position",
+        ))
+        .bind(collection_id)
+        .bind(sound_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let Some(position) = removed else {
+            return Ok(false);
+        };
+
+        match position {
+            Some(position) => {
+                sqlx::query(&self.sql("UPDATE collection_sounds SET position = position - 1 WHERE collection_id = ? AND position > ?"))
+                    .bind(collection_id)
+                    .bind(position)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            None => {
+                self.renumber_positions(conn, collection_id).await?;
+            }
+        }
+        self.bump_collection_version(conn, collection_id).await?;
+        Ok(true)
+    }
+
+    /// Mark a collection's members or their order as changed
+    pub(crate) async fn bump_collection_version(&self, conn: &mut SqliteConnection, collection_id: &str) -> Result<()> {
+        sqlx::query(&self.sql("UPDATE collections SET version = version + 1 WHERE id = ?"))
+            .bind(collection_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Number a collection's members again from 0, in their current order
+    ///
+    /// # Returns
+    ///
+    /// The number of members whose position changed
+    async fn renumber_positions(&self, conn: &mut SqliteConnection, collection_id: &str) -> Result<u64> {
+        Ok(sqlx::query(&self.sql(RENUMBER))
+            .bind(collection_id)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected())
+    }
+
+    /// Number a collection's members again from 0 if their positions have
+    /// gaps or duplicates, keeping their order
+    ///
+    /// Ties are broken by the order the members were added.
+    ///
+    /// # Returns
+    ///
+    /// The number of members whose position changed
+    pub async fn normalize_collection_positions(&self, collection_id: &str) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let fixed = self.renumber_positions(&mut tx, collection_id).await?;
+        if fixed > 0 {
+            self.bump_collection_version(&mut tx, collection_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(fixed)
+    }
+
+    /// Number the members of a collection again in the background, after a
+    /// read found their positions with gaps or duplicates
+    ///
+    /// The write waits for the writer connection, which the reader may hold.
+    pub(crate) fn heal_positions(&self, collection_id: &str) {
+        let (db, sql, collection_id) = (self.db.clone(), self.sql(RENUMBER).into_owned(), collection_id.to_string());
+        tokio::spawn(async move {
+            let _ = sqlx::query(&sql).bind(collection_id).execute(&db).await;
+        });
+    }
+
+    /// IDs of the collections whose positions have gaps or duplicates
+    pub(crate) async fn misordered_collections(&self) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(&self.sql(
+            r#"
+            SELECT collection_id FROM collection_sounds
+            GROUP BY collection_id
+            HAVING COUNT(position) < COUNT(*) OR COUNT(DISTINCT position) < COUNT(*)
+                OR MIN(position) != 0 OR MAX(position) != COUNT(*) - 1
+            ORDER BY collection_id
+            "#,
+        ))
+        .fetch_all(&self.reader)
+        .await?)
+    }
+
+    /// Put the sounds of a collection in a new order
+    ///
+    /// `sound_ids` must hold every member exactly once. The order is written
+    /// in one transaction, and only if the collection is still at
+    /// `expected_version`.
+    ///
+    /// # Returns
+    ///
+    /// The new version of the collection
+    pub async fn reorder_collection(&self, collection_id: &str, sound_ids: &[String], expected_version: i64) -> Result<i64> {
+        let mut tx = self.db.begin().await?;
+        let before = self.check_version(&mut tx, collection_id, expected_version).await?;
+
+        let mut sorted = sound_ids.to_vec();
+        sorted.sort();
+        let mut members = before.clone();
+        members.sort();
+        if sorted != members {
+            return Err(VaultError::InvalidOperation(format!(
+                "A new order of collection {} must list each of its {} sounds once",
+                collection_id,
+                members.len()
+            )));
+        }
+
+        let version = self.write_order(&mut tx, collection_id, &before, sound_ids).await?;
+        tx.commit().await?;
+
+        if version != expected_version {
+            self.emit(VaultEvent::CollectionChanged { collection_id: collection_id.to_string() });
+        }
+        Ok(version)
+    }
+
+    /// Move a sound of a collection to `index`, shifting the sounds between
+    ///
+    /// The move is made only if the collection is still at
+    /// `expected_version`; an index past the end moves the sound last.
+    ///
+    /// # Returns
+    ///
+    /// The new version of the collection
+    pub async fn move_in_collection(&self, collection_id: &str, sound_id: &str, index: usize, expected_version: i64) -> Result<i64> {
+        let mut tx = self.db.begin().await?;
+        let before = self.check_version(&mut tx, collection_id, expected_version).await?;
+
+        let Some(from) = before.iter().position(|id| id == sound_id) else {
+            return Err(VaultError::NotFound(format!("Sound {} is not in collection {}", sound_id, collection_id)));
+        };
+        let mut after = before.clone();
+        let moved = after.remove(from);
+        after.insert(index.min(after.len()), moved);
+
+        let version = self.write_order(&mut tx, collection_id, &before, &after).await?;
+        tx.commit().await?;
+
+        if version != expected_version {
+            self.emit(VaultEvent::CollectionChanged { collection_id: collection_id.to_string() });
+        }
+        Ok(version)
+    }
+
+    /// Members of a collection in order, refusing the collection if it's no
+    /// longer at `expected_version`
+    async fn check_version(&self, conn: &mut SqliteConnection, collection_id: &str, expected_version: i64) -> Result<Vec<String>> {
+        // Write first, so the transaction holds the write lock while it reads
+        let version: Option<i64> = sqlx::query_scalar(&self.sql("UPDATE collections SET version = version WHERE id = ? RETURNING version"))
+            .bind(collection_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let version = version.ok_or_else(|| VaultError::NotFound(format!("Collection not found: {}", collection_id)))?;
+        if version != expected_version {
+            return Err(VaultError::VersionConflict {
+                collection_id: collection_id.to_string(),
+                expected: expected_version,
+                actual: version,
+            });
+        }
+
+        Ok(sqlx::query_scalar(&self.sql(
+            "SELECT sound_id FROM collection_sounds WHERE collection_id = ? ORDER BY position IS NULL, position, rowid",
+        ))
+        .bind(collection_id)
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Write the positions of a new order, unless it's the current one
+    ///
+    /// # Returns
+    ///
+    /// The version of the collection afterwards
+    async fn write_order(&self, conn: &mut SqliteConnection, collection_id: &str, before: &[String], after: &[String]) -> Result<i64> {
+        if before != after {
+            for (position, sound_id) in after.iter().enumerate() {
+                sqlx::query(&self.sql("UPDATE collection_sounds SET position = ? WHERE collection_id = ? AND sound_id = ?"))
+                    .bind(position as i64)
+                    .bind(collection_id)
+                    .bind(sound_id)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            self.bump_collection_version(conn, collection_id).await?;
+            let changes = serde_json::json!({ "order": [before, after] });
+            self.audit(conn, AuditOperation::UpdateCollection, collection_id, changes).await?;
+        } else {
+            // Positions read in order may still need closing up
+            self.renumber_positions(conn, collection_id).await?;
+        }
+
+        Ok(sqlx::query_scalar(&self.sql("SELECT version FROM collections WHERE id = ?"))
+            .bind(collection_id)
+            .fetch_one(&mut *conn)
+            .await?)
+    }
+}
@@ -0,0 +1,316 @@
+//! Sounds indexed inside zip archives, read in place
+//!
+//! Sound packs often come as zip archives. Indexing one records each audio
+//! entry as an external sound whose path is `<archive>!<entry>`; the entry
+//! is inflated whenever its content is needed, and the archive is never
+//! extracted or changed.
+
+use crate::audio::probe;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::{SoundMetadata, SoundSource};
+use crate::provenance::ProvenanceMode;
+use crate::sniff::FileFormat;
+use flate2::read::DeflateDecoder;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Separates the archive from the entry in the path of a packed sound
+pub(crate) const PACK_SEPARATOR: char = '!';
+
+/// Signature of the end of central directory record
+pub(crate) const END_SIGNATURE: u32 = 0x0605_4b50;
+
+/// Signature of a central directory header
+pub(crate) const CENTRAL_SIGNATURE: u32 = 0x0201_4b50;
+
+/// Signature of a local file header
+pub(crate) const LOCAL_SIGNATURE: u32 = 0x0403_4b50;
+
+/// Size of the end of central directory record, without its comment
+const END_SIZE: u64 = 22;
+
+/// Longest archive comment
+const MAX_COMMENT: u64 = u16::MAX as u64;
+
+/// Compression methods read: stored and deflated
+pub(crate) const STORED: u16 = 0;
+pub(crate) const DEFLATED: u16 = 8;
+
+/// An entry listed in a zip archive's central directory
+#[derive(Debug, Clone)]
+pub(crate) struct ZipEntry {
+    /// Path of the entry inside the archive
+    pub(crate) name: String,
+    method: u16,
+    encrypted: bool,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    header_offset: u64,
+}
+
+impl ZipEntry {
+    /// Whether the entry is a folder rather than a file
+    pub(crate) fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// A zip archive opened for reading entries one at a time
+pub(crate) struct ZipArchive {
+    path: PathBuf,
+    file: File,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipArchive {
+    /// Open an archive and read its central directory
+    ///
+    /// Zip64 archives, which hold files of 4 GiB or more, aren't read.
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < END_SIZE {
+            return Err(damaged(path, "too short to be a zip archive"));
+        }
+
+        // The end record sits before a comment of unknown length
+        let tail_len = len.min(END_SIZE + MAX_COMMENT);
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = vec![0; tail_len as usize];
+        file.read_exact(&mut tail)?;
+        let end = (0..=tail.len() - END_SIZE as usize)
+            .rev()
+            .find(|&at| u32_at(&tail, at) == END_SIGNATURE)
+            .ok_or_else(|| damaged(path, "no end of central directory"))?;
+        let count = u16_at(&tail, end + 10);
+        let directory_size = u32_at(&tail, end + 12);
+        let directory_offset = u32_at(&tail, end + 16);
+        if count == u16::MAX || directory_size == u32::MAX || directory_offset == u32::MAX {
+            return Err(VaultError::UnsupportedCodec(format!("Zip64 archive {:?}", path)));
+        }
+
+        let mut directory = vec![0; directory_size as usize];
+        file.seek(SeekFrom::Start(directory_offset as u64))?;
+        file.read_exact(&mut directory)
+            .map_err(|_| damaged(path, "central directory cut short"))?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut at = 0;
+        for _ in 0..count {
+            if directory.len() < at + 46 || u32_at(&directory, at) != CENTRAL_SIGNATURE {
+                return Err(damaged(path, "bad central directory header"));
+            }
+            let name_len = u16_at(&directory, at + 28) as usize;
+            let extra_len = u16_at(&directory, at + 30) as usize;
+            let comment_len = u16_at(&directory, at + 32) as usize;
+            let name = directory
+                .get(at + 46..at + 46 + name_len)
+                .ok_or_else(|| damaged(path, "entry name cut short"))?;
+            let entry = ZipEntry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(&directory, at + 10),
+                encrypted: u16_at(&directory, at + 8) & 1 != 0,
+                crc: u32_at(&directory, at + 16),
+                compressed_size: u32_at(&directory, at + 20) as u64,
+                size: u32_at(&directory, at + 24) as u64,
+                header_offset: u32_at(&directory, at + 42) as u64,
+            };
+            if entry.compressed_size == u32::MAX as u64 || entry.size == u32::MAX as u64 {
+                return Err(VaultError::UnsupportedCodec(format!("Zip64 entry {:?} in {:?}", entry.name, path)));
+            }
+            entries.push(entry);
+            at += 46 + name_len + extra_len + comment_len;
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            entries,
+        })
+    }
+
+    /// Entries of the archive, in the order of its central directory
+    pub(crate) fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Find an entry by its path inside the archive
+    pub(crate) fn find(&self, name: &str) -> Option<ZipEntry> {
+        self.entries.iter().find(|entry| entry.name == name).cloned()
+    }
+
+    /// Inflate an entry, checking its size and CRC
+    pub(crate) fn read(&mut self, entry: &ZipEntry) -> Result<Vec<u8>> {
+        if entry.encrypted {
+            return Err(VaultError::UnsupportedCodec(format!("Encrypted zip entry {:?}", entry.name)));
+        }
+
+        let mut header = [0; 30];
+        self.file.seek(SeekFrom::Start(entry.header_offset))?;
+        self.file
+            .read_exact(&mut header)
+            .map_err(|_| damaged(&self.path, "local header cut short"))?;
+        if u32_at(&header, 0) != LOCAL_SIGNATURE {
+            return Err(damaged(&self.path, &format!("bad local header for {:?}", entry.name)));
+        }
+        let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+        self.file.seek(SeekFrom::Current(skip))?;
+
+        let stored = (&mut self.file).take(entry.compressed_size);
+        let mut bytes = Vec::with_capacity(entry.size as usize);
+        match entry.method {
+            STORED => stored.take(entry.size).read_to_end(&mut bytes),
+            DEFLATED => DeflateDecoder::new(stored).take(entry.size + 1).read_to_end(&mut bytes),
+            method => {
+                return Err(VaultError::UnsupportedCodec(format!(
+                    "Zip compression method {} of {:?}",
+                    method, entry.name
+                )));
+            }
+        }
+        .map_err(|e| damaged(&self.path, &format!("{:?} doesn't inflate: {}", entry.name, e)))?;
+
+        if bytes.len() as u64 != entry.size || crc32fast::hash(&bytes) != entry.crc {
+            return Err(damaged(&self.path, &format!("{:?} fails its CRC check", entry.name)));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Path of an entry of an archive, as recorded for a packed sound
+pub(crate) fn packed_path(archive: &Path, entry: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_os_string();
+    path.push(PACK_SEPARATOR.to_string());
+    path.push(entry);
+    PathBuf::from(path)
+}
+
+/// Split the path of a packed sound into its archive and entry
+///
+/// The split is at the first `!` following a `.zip` extension, so archives
+/// in folders whose name holds a `!` are found too.
+pub(crate) fn split_packed(path: &Path) -> Option<(PathBuf, String)> {
+    let path = path.to_string_lossy();
+    let at = path
+        .match_indices(PACK_SEPARATOR)
+        .map(|(at, _)| at)
+        .find(|&at| path[..at].to_ascii_lowercase().ends_with(".zip"))
+        .or_else(|| path.find(PACK_SEPARATOR))?;
+
+    Some((PathBuf::from(&path[..at]), path[at + 1..].to_string()))
+}
+
+/// Content of the entry a packed sound's path points to
+///
+/// # Returns
+///
+/// `None` if the archive or the entry is gone, or an error if the archive
+/// can't be read or the entry is damaged
+pub(crate) fn read_packed(path: &Path) -> Result<Option<Vec<u8>>> {
+    let (archive, name) = split_packed(path)
+        .ok_or_else(|| VaultError::InvalidOperation(format!("Not the path of a packed sound: {:?}", path)))?;
+    if !archive.is_file() {
+        return Ok(None);
+    }
+
+    let mut zip = ZipArchive::open(&archive)?;
+    match zip.find(&name) {
+        Some(entry) => zip.read(&entry).map(Some),
+        None => Ok(None),
+    }
+}
+
+impl LocalLibrary {
+    /// Index the audio entries of a zip archive without extracting them
+    ///
+    /// Each entry holding audio is recorded as an external sound whose path
+    /// is `<archive>!<entry>`; entries indexed before are skipped. Folders,
+    /// macOS resource forks and entries of other kinds are ignored.
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the new sounds
+    pub(crate) async fn index_archive(&self, zip_path: &Path) -> Result<Vec<String>> {
+        let _foreground = self.foreground();
+        if !zip_path.is_file() {
+            return Err(VaultError::FileSystem(format!("Archive does not exist: {:?}", zip_path)));
+        }
+        let archive = std::path::absolute(zip_path)?;
+
+        let reading = archive.clone();
+        let found = self
+            .jobs
+            .run(move || {
+                let mut zip = ZipArchive::open(&reading)?;
+                let mut found = Vec::new();
+                for entry in zip.entries().to_vec() {
+                    let file_name = entry.name.rsplit('/').next().unwrap_or_default();
+                    if entry.is_dir() || entry.name.starts_with("__MACOSX/") || file_name.starts_with("._") {
+                        continue;
+                    }
+                    let bytes = zip.read(&entry)?;
+                    let Some(format) = FileFormat::sniff(&bytes) else {
+                        continue;
+                    };
+                    let hash = format!("{:x}", Sha256::digest(&bytes));
+                    let info = probe(&mut Cursor::new(&bytes)).ok();
+                    found.push((entry.name, format, hash, info));
+                }
+                Ok(found)
+            })
+            .await?;
+
+        let provenance = self.local_provenance(ProvenanceMode::Reference, &archive);
+        let mut ids = Vec::new();
+        for (name, format, hash, info) in found {
+            let path = packed_path(&archive, &name);
+            let known: Option<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE path = ?"))
+                .bind(path.to_string_lossy().to_string())
+                .fetch_optional(&self.reader)
+                .await?;
+            if known.is_some() {
+                continue;
+            }
+
+            let mut metadata = SoundMetadata {
+                id: Uuid::new_v4().to_string(),
+                name: name.rsplit('/').next().unwrap_or(&name).to_string(),
+                license: "Unknown".to_string(),
+                path: Some(path),
+                external: true,
+                packed: true,
+                source: SoundSource::Local,
+                hash: Some(hash),
+                format: Some(format),
+                ..Default::default()
+            };
+            if let Some(info) = info {
+                metadata.duration = info.duration();
+                metadata.channels = Some(info.channels);
+                metadata.sample_rate = Some(info.sample_rate);
+            }
+            self.insert_sound(&metadata, None, Some(&provenance)).await?;
+            ids.push(metadata.id);
+        }
+
+        Ok(ids)
+    }
+}
+
+/// Error for an archive that can't be read as zip
+fn damaged(path: &Path, reason: &str) -> VaultError {
+    VaultError::FileSystem(format!("Damaged zip archive {:?}: {}", path, reason))
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
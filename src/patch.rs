@@ -0,0 +1,335 @@
+//! Partial updates of sound metadata
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::{Localization, SoundMetadata, Trim, normalize_lang};
+use crate::slug::SlugPolicy;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+use std::collections::{BTreeMap, HashMap};
+
+/// A set of changes to a sound's metadata
+///
+/// Only the fields that are set are written, so concurrent patches touching
+/// different fields don't overwrite each other. Tags are removed before they
+/// are added.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{MetadataPatch, SoundMetadata};
+///
+/// let mut patch = MetadataPatch::default();
+/// patch.description = Some("Wind through a cracked window".to_string());
+/// patch.rating = Some(Some(4));
+/// patch.add_tags = vec!["wind".to_string()];
+/// patch.remove_tags = vec!["todo".to_string()];
+/// patch.remove_custom = vec!["draft".to_string()];
+///
+/// let mut metadata = SoundMetadata::default();
+/// metadata.tags = vec!["todo".to_string(), "interior".to_string()];
+/// metadata.set_custom("draft", "yes");
+/// patch.apply_to(&mut metadata);
+///
+/// assert_eq!(metadata.tags, vec!["interior", "wind"]);
+/// assert_eq!(metadata.rating, Some(4));
+/// assert!(metadata.custom.is_empty());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataPatch {
+    /// New name
+    pub name: Option<String>,
+
+    /// New slug, which must be lowercase letters and digits separated by
+    /// dashes, and unused by other sounds
+    pub slug: Option<String>,
+
+    /// New description
+    pub description: Option<String>,
+
+    /// New license
+    pub license: Option<String>,
+
+    /// New duration in seconds
+    pub duration: Option<f32>,
+
+    /// New rating; `Some(None)` clears it
+    pub rating: Option<Option<u8>>,
+
+    /// New trim markers; `Some(None)` removes them
+    pub trim: Option<Option<Trim>>,
+
+    /// New expiration date; `Some(None)` removes it
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+
+    /// Tags to add
+    pub add_tags: Vec<String>,
+
+    /// Tags to remove
+    pub remove_tags: Vec<String>,
+
+    /// Custom metadata values to insert or replace
+    pub set_custom: HashMap<String, String>,
+
+    /// Custom metadata keys to delete
+    pub remove_custom: Vec<String>,
+
+    /// Localized texts replacing those of each language; an empty
+    /// [`Localization`] removes the language
+    pub localized: BTreeMap<String, Localization>,
+
+    /// Apply the patch to a locked sound too, instead of failing with
+    /// [`VaultError::Locked`]
+    pub override_lock: bool,
+}
+
+impl MetadataPatch {
+    /// Check whether the patch changes nothing
+    pub fn is_empty(&self) -> bool {
+        Self { override_lock: false, ..self.clone() } == Self::default()
+    }
+
+    /// Compute the patch turning `before` into `after`
+    ///
+    /// Fields a patch can't express (ID, file, source, technical properties)
+    /// and the order of tags are not compared.
+    pub fn between(before: &SoundMetadata, after: &SoundMetadata) -> Self {
+        let changed = |a: &String, b: &String| (a != b).then(|| b.clone());
+
+        Self {
+            name: changed(&before.name, &after.name),
+            slug: (before.slug != after.slug).then(|| after.slug.clone()).flatten(),
+            description: changed(&before.description, &after.description),
+            license: changed(&before.license, &after.license),
+            duration: (before.duration != after.duration).then_some(after.duration),
+            rating: (before.rating != after.rating).then_some(after.rating),
+            trim: (before.trim != after.trim).then_some(after.trim),
+            expires_at: (before.expires_at != after.expires_at).then_some(after.expires_at),
+            add_tags: after.tags.iter().filter(|t| !before.tags.contains(t)).cloned().collect(),
+            remove_tags: before.tags.iter().filter(|t| !after.tags.contains(t)).cloned().collect(),
+            set_custom: after
+                .custom
+                .iter()
+                .filter(|(key, value)| before.custom.get(*key) != Some(*value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            remove_custom: before.custom.keys().filter(|key| !after.custom.contains_key(*key)).cloned().collect(),
+            localized: before
+                .localizations
+                .keys()
+                .chain(after.localizations.keys())
+                .filter(|lang| before.localizations.get(*lang) != after.localizations.get(*lang))
+                .map(|lang| (lang.clone(), after.localizations.get(lang).cloned().unwrap_or_default()))
+                .collect(),
+            override_lock: false,
+        }
+    }
+
+    /// Names of the fields the patch changes: `name`, `tags`,
+    /// `custom.<key>`, `localized.<lang>`...
+    pub(crate) fn fields(&self) -> Vec<String> {
+        let mut fields: Vec<String> = [
+            ("name", self.name.is_some()),
+            ("slug", self.slug.is_some()),
+            ("description", self.description.is_some()),
+            ("license", self.license.is_some()),
+            ("duration", self.duration.is_some()),
+            ("rating", self.rating.is_some()),
+            ("trim", self.trim.is_some()),
+            ("expires_at", self.expires_at.is_some()),
+            ("tags", !self.add_tags.is_empty() || !self.remove_tags.is_empty()),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(field, _)| field.to_string())
+        .collect();
+        let mut custom: Vec<&String> = self.set_custom.keys().chain(&self.remove_custom).collect();
+        custom.sort();
+        custom.dedup();
+        fields.extend(custom.into_iter().map(|key| format!("custom.{}", key)));
+        fields.extend(self.localized.keys().map(|lang| format!("localized.{}", lang)));
+        fields
+    }
+
+    /// Apply the patch to metadata in memory
+    pub fn apply_to(&self, metadata: &mut SoundMetadata) {
+        if let Some(name) = &self.name {
+            metadata.name = name.clone();
+        }
+        if let Some(slug) = &self.slug {
+            metadata.slug = Some(slug.clone());
+        }
+        if let Some(description) = &self.description {
+            metadata.description = description.clone();
+        }
+        if let Some(license) = &self.license {
+            metadata.license = license.clone();
+        }
+        if let Some(duration) = self.duration {
+            metadata.duration = duration;
+        }
+        if let Some(rating) = self.rating {
+            metadata.rating = rating;
+        }
+        if let Some(trim) = self.trim {
+            metadata.trim = trim;
+        }
+        if let Some(expires_at) = self.expires_at {
+            metadata.expires_at = expires_at;
+        }
+
+        for tag in &self.remove_tags {
+            metadata.remove_tag(tag);
+        }
+        for tag in &self.add_tags {
+            metadata.add_tag(tag);
+        }
+
+        for key in &self.remove_custom {
+            metadata.custom.remove(key);
+        }
+        for (key, value) in &self.set_custom {
+            metadata.custom.insert(key.clone(), value.clone());
+        }
+
+        for (lang, localization) in &self.localized {
+            if localization == &Localization::default() {
+                metadata.localizations.remove(&normalize_lang(lang));
+            } else {
+                metadata.localizations.insert(normalize_lang(lang), localization.clone());
+            }
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Update only the given fields of a sound's metadata
+    ///
+    /// The changes are written with a single UPDATE of the touched columns
+    /// plus targeted custom metadata statements, in one transaction.
+    pub async fn patch_metadata(&self, id: &str, patch: MetadataPatch) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        self.apply_patch(&mut tx, id, &patch).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Apply a patch within a transaction and record it in the audit log
+    pub(crate) async fn apply_patch(&self, conn: &mut SqliteConnection, id: &str, patch: &MetadataPatch) -> Result<()> {
+        self.forget_sound(id);
+        // Writing first takes the database lock, so the state read below
+        // can't change before the patch is applied
+        let touched = sqlx::query(&self.sql("UPDATE sounds SET updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        if touched.rows_affected() == 0 {
+            return Err(VaultError::NotFound(format!("Sound not found: {}", id)));
+        }
+        if patch.is_empty() {
+            return Ok(());
+        }
+        if !patch.override_lock {
+            self.check_unlocked(conn, id).await?;
+        }
+
+        if let Some(Some(trim)) = &patch.trim {
+            trim.check()?;
+        }
+        self.check_custom_fields(conn, &patch.set_custom, &patch.remove_custom).await?;
+        let before = self.fetch_sound(conn, id).await?.metadata;
+        let mut after = before.clone();
+        patch.apply_to(&mut after);
+        if let Some(slug) = &patch.slug {
+            if self.choose_slug(conn, id, &after.name, Some(slug)).await? != *slug {
+                return Err(VaultError::InvalidOperation(format!("Slug is malformed or taken: {}", slug)));
+            }
+        } else if after.name != before.name && self.slug_policy == SlugPolicy::FollowName {
+            after.slug = Some(self.choose_slug(conn, id, &after.name, None).await?);
+        }
+
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(self.sql("UPDATE sounds SET updated_at = CURRENT_TIMESTAMP"));
+        if let Some(name) = &patch.name {
+            builder.push(", name = ");
+            builder.push_bind(name.clone());
+            builder.push(", sort_key = ");
+            builder.push_bind(self.collator.sort_key(name));
+        }
+        if after.slug != before.slug {
+            builder.push(", slug = ");
+            builder.push_bind(after.slug.clone());
+        }
+        if let Some(description) = &patch.description {
+            builder.push(", description = ");
+            builder.push_bind(description.clone());
+        }
+        if let Some(license) = &patch.license {
+            builder.push(", license = ");
+            builder.push_bind(license.clone());
+        }
+        if let Some(duration) = patch.duration {
+            builder.push(", duration = ");
+            builder.push_bind(duration);
+        }
+        if let Some(rating) = patch.rating {
+            builder.push(", rating = ");
+            builder.push_bind(rating);
+        }
+        if let Some(trim) = patch.trim {
+            builder.push(", trim_start = ");
+            builder.push_bind(trim.map(|trim| trim.start));
+            builder.push(", trim_end = ");
+            builder.push_bind(trim.and_then(|trim| trim.end));
+        }
+        if let Some(expires_at) = patch.expires_at {
+            builder.push(", expires_at = ");
+            builder.push_bind(expires_at);
+        }
+        if after.tags != before.tags {
+            builder.push(", tags = ");
+            builder.push_bind(serde_json::to_string(&after.tags)?);
+        }
+        builder.push(" WHERE id = ");
+        builder.push_bind(id.to_string());
+        builder.build().execute(&mut *conn).await?;
+
+        for key in &patch.remove_custom {
+            sqlx::query(&self.sql("DELETE FROM metadata WHERE object_id = ? AND object_type = 'sound' AND key = ?"))
+                .bind(id)
+                .bind(key)
+                .execute(&mut *conn)
+                .await?;
+        }
+        for (key, value) in &patch.set_custom {
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO metadata (object_id, object_type, key, value)
+                VALUES (?, 'sound', ?, ?)
+                ON CONFLICT(object_id, object_type, key) DO UPDATE SET value = excluded.value
+                "#),
+            )
+            .bind(id)
+            .bind(key)
+            .bind(value)
+            .execute(&mut *conn)
+            .await?;
+        }
+        for (lang, localization) in &patch.localized {
+            self.save_localization(conn, id, lang, localization).await?;
+        }
+        if after.name != before.name || after.tags != before.tags {
+            self.save_search_words(conn, id, &after.name, &after.tags).await?;
+        }
+
+        let changes = audit_diff(Some(&serde_json::to_value(&before)?), Some(&serde_json::to_value(&after)?));
+        if changes.as_object().is_some_and(|c| !c.is_empty()) {
+            self.audit(conn, AuditOperation::UpdateSound, id, changes).await?;
+        }
+
+        Ok(())
+    }
+}
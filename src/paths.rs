@@ -0,0 +1,205 @@
+//! Containment of file operations within the library
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::pack::split_packed;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Suffix of files being written, renamed to their final name once complete
+pub(crate) const TEMP_SUFFIX: &str = ".tmp";
+
+/// Resolve a path and check that it lies strictly inside `root`
+///
+/// Relative paths are taken relative to `root`. Symbolic links are resolved,
+/// so a link pointing out of `root` is rejected like a `..` component or an
+/// absolute path elsewhere. The path itself need not exist yet.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::resolve_within;
+/// use std::path::Path;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let library = tempfile::tempdir()?;
+/// std::fs::create_dir(library.path().join("rain"))?;
+///
+/// let inside = resolve_within(library.path(), Path::new("rain/drops.wav"))?;
+/// assert!(inside.ends_with("rain/drops.wav"));
+///
+/// // Traversal, absolute paths elsewhere and the root itself are refused
+/// assert!(resolve_within(library.path(), Path::new("rain/../../etc/passwd")).is_err());
+/// assert!(resolve_within(library.path(), Path::new("/etc/passwd")).is_err());
+/// assert!(resolve_within(library.path(), library.path()).is_err());
+///
+/// // So are symbolic links escaping the library
+/// #[cfg(unix)]
+/// {
+///     let outside = tempfile::tempdir()?;
+///     std::os::unix::fs::symlink(outside.path(), library.path().join("escape"))?;
+///     assert!(resolve_within(library.path(), Path::new("escape/file.wav")).is_err());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn resolve_within(root: &Path, path: &Path) -> Result<PathBuf> {
+    let root = root
+        .canonicalize()
+        .map_err(|e| VaultError::FileSystem(format!("Failed to resolve {:?}: {}", root, e)))?;
+    let joined = if path.is_absolute() { path.to_path_buf() } else { root.join(path) };
+
+    // Resolve the deepest existing ancestor, then append the missing names;
+    // a missing path ending in `..` has no file name and is refused
+    let mut existing = joined.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return Err(escapes(path)),
+        }
+    }
+    let mut resolved = existing
+        .canonicalize()
+        .map_err(|e| VaultError::FileSystem(format!("Failed to resolve {:?}: {}", path, e)))?;
+
+    for name in rest.into_iter().rev() {
+        resolved.push(name);
+    }
+
+    if resolved.starts_with(&root) && resolved != root {
+        Ok(resolved)
+    } else {
+        Err(escapes(path))
+    }
+}
+
+fn escapes(path: &Path) -> VaultError {
+    VaultError::InvalidOperation(format!("Path is outside the library: {:?}", path))
+}
+
+/// Turn a sound name into a file name that stays in its folder
+///
+/// Separators and control characters become `_`; leading dots are dropped so
+/// the file isn't hidden. An empty result is replaced by `fallback`.
+pub(crate) fn safe_file_name(name: &str, fallback: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':') { '_' } else { c })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+
+    if name.is_empty() {
+        fallback.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Path in `dir` named `stem` with `extension`, numbered `stem (2)`,
+/// `stem (3)`... if a file of that name, in any case, is already there
+pub(crate) fn unique_path(dir: &Path, stem: &str, extension: Option<&str>) -> PathBuf {
+    let taken: HashSet<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default();
+    let with_extension = |stem: &str| match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    };
+
+    let mut file_name = with_extension(stem);
+    let mut number = 1;
+    while taken.contains(&file_name.to_lowercase()) {
+        number += 1;
+        file_name = with_extension(&format!("{} ({})", stem, number));
+    }
+    dir.join(file_name)
+}
+
+/// Temporary name a file is written under before it is complete
+pub(crate) fn temp_path(target: &Path) -> PathBuf {
+    let mut name = OsString::from(target.as_os_str());
+    name.push(TEMP_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Write a file so that it is either complete or absent, even after a crash
+pub(crate) fn write_atomic(target: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let temp = temp_path(target);
+    let written = File::create(&temp).and_then(|mut file| file.write_all(bytes));
+    match written {
+        Ok(()) => finish_temp(&temp, target),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Copy a file so that the copy is either complete or absent, even after a crash
+pub(crate) fn copy_atomic(source: &Path, target: &Path) -> std::io::Result<()> {
+    let temp = temp_path(target);
+    match std::fs::copy(source, &temp) {
+        Ok(_) => finish_temp(&temp, target),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Flush a completely written temporary file and move it over `target`
+///
+/// The rename replaces an existing target in one step; on Windows the
+/// standard library does so with `MoveFileExW` and `MOVEFILE_REPLACE_EXISTING`.
+/// The temporary file is removed on failure.
+pub(crate) fn finish_temp(temp: &Path, target: &Path) -> std::io::Result<()> {
+    let finished = File::options()
+        .write(true)
+        .open(temp)
+        .and_then(|file| file.sync_all())
+        .and_then(|()| std::fs::rename(temp, target));
+    if finished.is_err() {
+        let _ = std::fs::remove_file(temp);
+    }
+    finished
+}
+
+impl LocalLibrary {
+    /// Resolve a path the vault is about to read, write or delete
+    pub(crate) fn library_file(&self, path: &Path) -> Result<PathBuf> {
+        resolve_within(&self.library_path, path)
+    }
+
+    /// Resolve a sound's file for reading
+    ///
+    /// Files of external sounds may live anywhere; all others must be inside
+    /// the library. For a packed sound this is the archive holding it.
+    pub(crate) fn readable_file(&self, metadata: &SoundMetadata) -> Result<PathBuf> {
+        let path = metadata.path.as_deref().ok_or_else(|| {
+            VaultError::InvalidOperation(format!("Sound has no file: {}", metadata.id))
+        })?;
+
+        if metadata.packed {
+            split_packed(path)
+                .map(|(archive, _)| archive)
+                .ok_or_else(|| VaultError::InvalidOperation(format!("Sound has no archive entry: {}", metadata.id)))
+        } else if metadata.external {
+            Ok(path.to_path_buf())
+        } else {
+            self.library_file(path)
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Roles of the actors sharing a vault
+//!
+//! A vault without roles lets every actor do everything. Once a role is
+//! granted, each actor is limited to its own role, and actors without one,
+//! including an unset actor, may only read. Denied attempts are recorded in
+//! the audit log.
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::collections::BTreeMap;
+
+/// What an actor may do in a vault with roles
+///
+/// Roles are ordered, each allowing what the ones before it allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Read, search, play and export sounds
+    Viewer,
+    /// Also import, tag and edit sounds and collections
+    Editor,
+    /// Also delete sounds, repair the library, move files between stores,
+    /// prune history and manage roles
+    Admin,
+}
+
+impl Role {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "viewer" => Ok(Self::Viewer),
+            "editor" => Ok(Self::Editor),
+            "admin" => Ok(Self::Admin),
+            _ => Err(VaultError::Corrupt(format!("Unknown role: {}", name))),
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Fail unless the current actor has at least a role, recording the
+    /// denied attempt
+    ///
+    /// # Arguments
+    ///
+    /// * `required` - Role the operation needs
+    /// * `operation` - Name of the operation, as recorded
+    /// * `entity_id` - ID of the sound or collection operated on; empty for
+    ///   operations on the whole vault
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if roles are granted and the actor's
+    ///   doesn't reach `required`
+    pub(crate) async fn authorize(&self, required: Role, operation: &str, entity_id: &str) -> Result<()> {
+        let actor = self.actor();
+        let (granted, role): (i64, Option<String>) = sqlx::query_as(&self.sql(
+            "SELECT (SELECT COUNT(*) FROM permissions), (SELECT role FROM permissions WHERE actor = ?)",
+        ))
+        .bind(&actor)
+        .fetch_one(&self.reader)
+        .await?;
+        if granted == 0 {
+            return Ok(());
+        }
+        let role = role.as_deref().map(Role::parse).transpose()?.unwrap_or(Role::Viewer);
+        if role >= required {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+        let attempt = json!({ "operation": operation, "role": role, "required_role": required });
+        self.audit(&mut tx, AuditOperation::PermissionDenied, entity_id, attempt).await?;
+        tx.commit().await?;
+
+        Err(VaultError::PermissionDenied {
+            actor,
+            required_role: required,
+        })
+    }
+
+    /// Give an actor a role, replacing the one it had
+    ///
+    /// The first role granted must be [`Role::Admin`], so that roles stay
+    /// manageable.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    /// * `VaultError::InvalidOperation` if it would leave the vault without an
+    ///   admin
+    pub async fn grant_role(&self, actor: &str, role: Role) -> Result<()> {
+        self.authorize(Role::Admin, "grant_role", actor).await?;
+
+        let mut tx = self.db.begin().await?;
+        let before: Option<String> = sqlx::query_scalar(&self.sql("SELECT role FROM permissions WHERE actor = ?"))
+            .bind(actor)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if before.as_deref() == Some(role.as_str()) {
+            return Ok(());
+        }
+        sqlx::query(&self.sql(
+            "INSERT INTO permissions (actor, role) VALUES (?, ?) ON CONFLICT(actor) DO UPDATE SET role = excluded.role",
+        ))
+        .bind(actor)
+        .bind(role.as_str())
+        .execute(&mut *tx)
+        .await?;
+        self.check_admin_left(&mut tx).await?;
+
+        let changes = audit_diff(Some(&json!({ "role": before })), Some(&json!({ "role": role })));
+        self.audit(&mut tx, AuditOperation::GrantRole, actor, changes).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Take an actor's role away
+    ///
+    /// Revoking every role lifts all restrictions.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    /// * `VaultError::InvalidOperation` if it would leave the vault without an
+    ///   admin while other roles remain
+    pub async fn revoke_role(&self, actor: &str) -> Result<()> {
+        self.authorize(Role::Admin, "revoke_role", actor).await?;
+
+        let mut tx = self.db.begin().await?;
+        let before: Option<String> =
+            sqlx::query_scalar(&self.sql("DELETE FROM permissions WHERE actor = ? RETURNING role"))
+                .bind(actor)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if before.is_none() {
+            return Ok(());
+        }
+        self.check_admin_left(&mut tx).await?;
+
+        let changes = audit_diff(Some(&json!({ "role": before })), None);
+        self.audit(&mut tx, AuditOperation::RevokeRole, actor, changes).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Roles granted, by actor
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    pub async fn list_roles(&self) -> Result<BTreeMap<String, Role>> {
+        self.authorize(Role::Admin, "list_roles", "").await?;
+
+        let rows: Vec<(String, String)> = sqlx::query_as(&self.sql("SELECT actor, role FROM permissions"))
+            .fetch_all(&self.reader)
+            .await?;
+        rows.into_iter()
+            .map(|(actor, role)| Ok((actor, Role::parse(&role)?)))
+            .collect()
+    }
+
+    /// Fail if roles are granted but none is [`Role::Admin`]
+    async fn check_admin_left(&self, conn: &mut SqliteConnection) -> Result<()> {
+        let (granted, admins): (i64, i64) = sqlx::query_as(&self.sql(
+            "SELECT COUNT(*), COUNT(*) FILTER (WHERE role = 'admin') FROM permissions",
+        ))
+        .fetch_one(&mut *conn)
+        .await?;
+        if granted > 0 && admins == 0 {
+            return Err(VaultError::InvalidOperation(
+                "The vault would be left without an admin".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
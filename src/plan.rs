@@ -0,0 +1,37 @@
+//! Previewing destructive maintenance before running it
+//!
+//! Each destructive operation can be split in two: `plan_*` computes what it
+//! would do, without changing anything, and `apply_*` does exactly that. A
+//! plan carries a snapshot token of the state it was computed from; applying
+//! it first checks the token and fails with [`VaultError::PlanOutdated`] if
+//! the library changed since, so what was confirmed is what runs.
+
+use crate::error::{Result, VaultError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+/// A file a plan removes or moves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedFile {
+    /// Path of the file
+    pub path: PathBuf,
+
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Token identifying the state a plan was computed from
+pub(crate) fn snapshot_token(state: &impl Serialize) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(state)?)))
+}
+
+/// Fail unless the state is still the one a plan was computed from
+pub(crate) fn check_snapshot(operation: &str, planned: &str, current: &str) -> Result<()> {
+    if planned != current {
+        return Err(VaultError::PlanOutdated {
+            operation: operation.to_string(),
+        });
+    }
+    Ok(())
+}
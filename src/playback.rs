@@ -0,0 +1,335 @@
+//! Sounds decoded to the sample rate and channels a player asks for
+
+use crate::audio::{ChannelMix, downmix};
+use crate::derivative::{resample_frames, resampled_len};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::Trim;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "analysis")]
+use rubato::{Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction};
+
+/// Frames fed to the resampler at a time
+#[cfg(feature = "analysis")]
+const RESAMPLER_CHUNK: usize = 1024;
+
+/// Format to decode a sound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputSpec {
+    /// Frames per second
+    pub sample_rate: u32,
+
+    /// Number of channels
+    pub channels: u16,
+
+    /// Keep only the part between the sound's trim markers, if it has some
+    pub apply_trim: bool,
+
+    /// Mark the sound as played
+    pub record_play: bool,
+}
+
+impl OutputSpec {
+    /// Decode to `sample_rate` and `channels`, trimmed, recording a play
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            apply_trim: true,
+            record_play: true,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.sample_rate == 0 || self.channels == 0 {
+            return Err(VaultError::InvalidOperation(format!("Invalid output format: {:?}", self)));
+        }
+        Ok(())
+    }
+}
+
+/// A decoded sound
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedAudio {
+    /// Frames per second
+    pub sample_rate: u32,
+
+    /// Number of channels
+    pub channels: u16,
+
+    /// Interleaved samples between -1.0 and 1.0
+    pub samples: Vec<f32>,
+}
+
+impl DecodedAudio {
+    /// Read a stream decoded in a single chunk
+    pub(crate) fn read(mut stream: DecodedStream) -> Result<Self> {
+        let samples = stream.next().transpose()?.unwrap_or_default();
+
+        Ok(Self {
+            sample_rate: stream.sample_rate,
+            channels: stream.channels,
+            samples,
+        })
+    }
+
+    /// Number of frames (samples per channel)
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    /// Duration in seconds
+    pub fn duration(&self) -> f64 {
+        if self.sample_rate == 0 {
+            return 0.0;
+        }
+        self.frames() as f64 / self.sample_rate as f64
+    }
+}
+
+/// A decoded sound read a fixed number of frames at a time
+///
+/// Each item holds the interleaved samples of `frames_per_chunk` frames, the
+/// last one fewer. Samples are resampled as they're read, so a long file is
+/// never held at the output rate as a whole.
+pub struct DecodedStream {
+    sample_rate: u32,
+    channels: u16,
+    frames_per_chunk: usize,
+    converter: Converter,
+}
+
+impl DecodedStream {
+    /// Frames per second
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of channels
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Number of frames not read yet
+    pub fn remaining_frames(&self) -> usize {
+        self.converter.frames - self.converter.next
+    }
+}
+
+impl Iterator for DecodedStream {
+    type Item = Result<Vec<f32>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_frames() == 0 {
+            return None;
+        }
+        Some(self.converter.read(self.frames_per_chunk))
+    }
+}
+
+/// Converts the sample rate of samples held in memory, a few frames at a time
+struct Converter {
+    /// Interleaved source samples, already in the output channels
+    samples: Vec<f32>,
+    channels: usize,
+    /// Source frames per output frame
+    step: f64,
+    /// Next output frame
+    next: usize,
+    /// Number of output frames
+    frames: usize,
+    #[cfg(feature = "analysis")]
+    sinc: Option<SincState>,
+}
+
+impl Converter {
+    fn new(samples: Vec<f32>, channels: usize, source_rate: u32, target_rate: u32) -> Result<Self> {
+        let step = source_rate as f64 / target_rate as f64;
+        let frames = resampled_len(samples.len() / channels, step);
+
+        Ok(Self {
+            #[cfg(feature = "analysis")]
+            sinc: if source_rate == target_rate { None } else { Some(SincState::new(step, channels)?) },
+            samples,
+            channels,
+            step,
+            next: 0,
+            frames,
+        })
+    }
+
+    /// Read the next `count` output frames, fewer at the end
+    fn read(&mut self, count: usize) -> Result<Vec<f32>> {
+        let count = count.min(self.frames - self.next);
+        let range = self.next..self.next + count;
+        self.next += count;
+
+        #[cfg(feature = "analysis")]
+        if let Some(sinc) = &mut self.sinc {
+            return sinc.read(&self.samples, self.channels, count);
+        }
+        Ok(resample_frames(&self.samples, self.channels, self.step, range))
+    }
+}
+
+/// Band-limited resampling, feeding the source in chunks
+#[cfg(feature = "analysis")]
+struct SincState {
+    resampler: SincFixedIn<f32>,
+    /// Source frames fed so far
+    consumed: usize,
+    /// Frames of filter delay still to drop from the output
+    delay: usize,
+    /// Interleaved output not read yet
+    ready: Vec<f32>,
+}
+
+#[cfg(feature = "analysis")]
+impl SincState {
+    fn new(step: f64, channels: usize) -> Result<Self> {
+        let parameters = SincInterpolationParameters {
+            sinc_len: 256,
+            f_cutoff: 0.95,
+            oversampling_factor: 256,
+            interpolation: SincInterpolationType::Cubic,
+            window: WindowFunction::BlackmanHarris2,
+        };
+        let resampler = SincFixedIn::new(1.0 / step, 1.0, parameters, RESAMPLER_CHUNK, channels)
+            .map_err(|e| VaultError::InvalidOperation(format!("Can't resample: {}", e)))?;
+
+        Ok(Self {
+            delay: resampler.output_delay(),
+            resampler,
+            consumed: 0,
+            ready: Vec::new(),
+        })
+    }
+
+    fn read(&mut self, samples: &[f32], channels: usize, count: usize) -> Result<Vec<f32>> {
+        let source_frames = samples.len() / channels;
+        while self.ready.len() < count * channels {
+            let start = self.consumed;
+            let end = (start + self.resampler.input_frames_next()).min(source_frames);
+            let input: Vec<Vec<f32>> = (0..channels)
+                .map(|channel| (start..end).map(|frame| samples[frame * channels + channel]).collect())
+                .collect();
+            self.consumed = end;
+
+            // Past the end of the source, zeros push out the filter's tail
+            let output = if end - start == self.resampler.input_frames_next() {
+                self.resampler.process(&input, None)
+            } else if end > start {
+                self.resampler.process_partial(Some(&input), None)
+            } else {
+                self.resampler.process_partial(None::<&[Vec<f32>]>, None)
+            }
+            .map_err(|e| VaultError::InvalidOperation(format!("Can't resample: {}", e)))?;
+
+            let produced = output.first().map_or(0, Vec::len);
+            for frame in self.delay.min(produced)..produced {
+                self.ready.extend(output.iter().map(|channel| channel[frame]));
+            }
+            self.delay = self.delay.saturating_sub(produced);
+        }
+
+        Ok(self.ready.drain(..count * channels).collect())
+    }
+}
+
+/// Decode a file to its rate, channels and interleaved samples
+///
+/// Formats the vault's own decoder doesn't read, such as the MP3 and Ogg
+/// Vorbis of Freesound previews, need the `rodio` feature.
+pub(crate) fn decode_content(bytes: Vec<u8>) -> Result<(u32, u16, Vec<f32>)> {
+    #[cfg(feature = "rodio")]
+    return crate::rodio_source::decode_any(bytes);
+
+    #[cfg(not(feature = "rodio"))]
+    {
+        let (info, samples) = crate::audio::decode(&mut std::io::Cursor::new(bytes))?;
+        Ok((info.sample_rate, info.channels, samples))
+    }
+}
+
+/// Map interleaved samples to another number of channels
+///
+/// Channels are folded into one or two, and a mono sound is copied to every
+/// channel; other conversions are ambiguous.
+fn map_channels(samples: Vec<f32>, from: u16, to: u16) -> Result<Vec<f32>> {
+    match (from, to) {
+        _ if from == to => Ok(samples),
+        (_, 1) => Ok(downmix(&samples, from, ChannelMix::Mono)),
+        (1, _) => Ok(samples.iter().flat_map(|&sample| std::iter::repeat_n(sample, to as usize)).collect()),
+        (_, 2) => Ok(downmix(&samples, from, ChannelMix::Stereo)),
+        _ => Err(VaultError::InvalidOperation(format!("Can't map {} channels to {}", from, to))),
+    }
+}
+
+impl LocalLibrary {
+    /// Decode a sound to the given sample rate and channels
+    ///
+    /// Resampling is band-limited with the `analysis` feature, linear
+    /// otherwise. Decoding runs on the background job queue.
+    pub async fn decode_sound(&self, id: &str, spec: OutputSpec) -> Result<DecodedAudio> {
+        DecodedAudio::read(self.decode_sound_stream(id, spec, usize::MAX).await?)
+    }
+
+    /// Decode a sound to the given sample rate and channels, read
+    /// `frames_per_chunk` frames at a time
+    pub async fn decode_sound_stream(
+        &self,
+        id: &str,
+        spec: OutputSpec,
+        frames_per_chunk: usize,
+    ) -> Result<DecodedStream> {
+        spec.check()?;
+        let metadata = self.stored_sound(id).await?;
+        let bytes = self.sound_bytes(&metadata)?;
+        let stream = self.decode_content_stream(bytes, metadata.trim, spec, frames_per_chunk).await?;
+
+        if spec.record_play {
+            self.record_play(id).await?;
+        }
+        Ok(stream)
+    }
+
+    /// Decode the content of a file like
+    /// [`decode_sound_stream`](Self::decode_sound_stream), without
+    /// recording a play
+    pub(crate) async fn decode_content_stream(
+        &self,
+        bytes: Vec<u8>,
+        trim: Option<Trim>,
+        spec: OutputSpec,
+        frames_per_chunk: usize,
+    ) -> Result<DecodedStream> {
+        spec.check()?;
+        if frames_per_chunk == 0 {
+            return Err(VaultError::InvalidOperation("Chunks must hold at least one frame".to_string()));
+        }
+
+        let trim = trim.filter(|_| spec.apply_trim);
+        let converter = self
+            .jobs
+            .run(move || {
+                let (sample_rate, source_channels, mut samples) = decode_content(bytes)?;
+                let source_channels = source_channels.max(1);
+                if let Some(trim) = trim {
+                    let range = trim.frames(sample_rate, samples.len() / source_channels as usize);
+                    let channels = source_channels as usize;
+                    samples = samples[range.start * channels..range.end * channels].to_vec();
+                }
+                let samples = map_channels(samples, source_channels, spec.channels)?;
+                Converter::new(samples, spec.channels as usize, sample_rate.max(1), spec.sample_rate)
+            })
+            .await?;
+
+        Ok(DecodedStream {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            frames_per_chunk,
+            converter,
+        })
+    }
+}
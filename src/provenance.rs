@@ -0,0 +1,181 @@
+//! Where the files of sounds came from, kept across replacements
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::source::RemoteSource;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Row, SqliteConnection};
+use std::path::{Path, PathBuf};
+
+/// How a file entered the library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvenanceMode {
+    /// Copied from a local file by an import
+    Import,
+    /// Copied from a local file, replacing the sound's previous one
+    ReplaceFile,
+    /// Downloaded from a remote source
+    Download,
+    /// Referenced where it lies, without a copy
+    Reference,
+    /// Copied from another vault by a merge
+    Merge,
+}
+
+impl ProvenanceMode {
+    /// Name stored in the database
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Import => "import",
+            Self::ReplaceFile => "replace_file",
+            Self::Download => "download",
+            Self::Reference => "reference",
+            Self::Merge => "merge",
+        }
+    }
+
+    /// Parse a name stored in the database
+    fn parse(name: &str) -> Result<Self> {
+        serde_json::from_value(Value::String(name.to_string()))
+            .map_err(|_| VaultError::InvalidOperation(format!("Unknown provenance mode: {}", name)))
+    }
+}
+
+/// A link of a sound's provenance chain: where one of its files came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenanceEntry {
+    /// How the file entered the library
+    pub mode: ProvenanceMode,
+
+    /// Absolute path of the local file copied into the library
+    pub source_path: Option<PathBuf>,
+
+    /// Name of the remote source the file was downloaded from
+    pub source: Option<String>,
+
+    /// ID of the sound at the remote source
+    pub remote_id: Option<String>,
+
+    /// Page of the sound at the remote source, if it has one
+    pub source_url: Option<String>,
+
+    /// Machine the file was added on, with
+    /// [`VaultConfig::record_hostname`](crate::VaultConfig::record_hostname)
+    pub hostname: Option<String>,
+
+    /// When the file entered the library
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Name of this machine, from the environment or `/etc/hostname`
+pub(crate) fn hostname() -> Option<String> {
+    ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+impl LocalLibrary {
+    /// Provenance of a file copied from `source_path`
+    pub(crate) fn local_provenance(&self, mode: ProvenanceMode, source_path: &Path) -> ProvenanceEntry {
+        ProvenanceEntry {
+            mode,
+            source_path: Some(std::path::absolute(source_path).unwrap_or_else(|_| source_path.to_path_buf())),
+            source: None,
+            remote_id: None,
+            source_url: None,
+            hostname: self.hostname.clone(),
+            recorded_at: Utc::now(),
+        }
+    }
+
+    /// Provenance of a file downloaded from a remote source
+    pub(crate) fn remote_provenance(&self, source: &dyn RemoteSource, remote_id: &str) -> ProvenanceEntry {
+        ProvenanceEntry {
+            mode: ProvenanceMode::Download,
+            source_path: None,
+            source: Some(source.name().to_string()),
+            remote_id: Some(remote_id.to_string()),
+            source_url: source.sound_url(remote_id),
+            hostname: self.hostname.clone(),
+            recorded_at: Utc::now(),
+        }
+    }
+
+    /// Append an entry to a sound's provenance chain, noting it in the
+    /// `changes` audited in the same transaction
+    pub(crate) async fn append_provenance(
+        &self,
+        conn: &mut SqliteConnection,
+        sound_id: &str,
+        entry: &ProvenanceEntry,
+        changes: &mut Value,
+    ) -> Result<()> {
+        self.write_provenance(conn, sound_id, entry).await?;
+        changes["provenance"] = serde_json::json!([null, entry]);
+
+        Ok(())
+    }
+
+    /// Store an entry of a sound's provenance chain, unless it's there already
+    pub(crate) async fn write_provenance(&self, conn: &mut SqliteConnection, sound_id: &str, entry: &ProvenanceEntry) -> Result<()> {
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO provenance (sound_id, mode, source_path, source, remote_id, source_url, hostname, recorded_at)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8
+            WHERE NOT EXISTS (SELECT 1 FROM provenance WHERE sound_id = ?1 AND mode = ?2 AND recorded_at = ?8)
+            "#),
+        )
+        .bind(sound_id)
+        .bind(entry.mode.as_str())
+        .bind(entry.source_path.as_ref().map(|p| p.to_string_lossy().to_string()))
+        .bind(&entry.source)
+        .bind(&entry.remote_id)
+        .bind(&entry.source_url)
+        .bind(&entry.hostname)
+        .bind(entry.recorded_at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Where the files of a sound came from, oldest first
+    ///
+    /// Every import, download and file replacement appends an entry, so the
+    /// chain goes back to the original file even after it was replaced.
+    /// Sounds recorded before provenance was kept have an empty chain.
+    pub async fn provenance(&self, id: &str) -> Result<Vec<ProvenanceEntry>> {
+        let rows = sqlx::query(&self.sql(
+            r#"
+            SELECT mode, source_path, source, remote_id, source_url, hostname, recorded_at
+            FROM provenance WHERE sound_id = ? ORDER BY recorded_at, id
+            "#,
+        ))
+        .bind(id)
+        .fetch_all(&self.reader)
+        .await?;
+        if rows.is_empty() {
+            self.get_sound(id).await?;
+        }
+
+        rows.iter()
+            .map(|row| {
+                Ok(ProvenanceEntry {
+                    mode: ProvenanceMode::parse(row.try_get("mode")?)?,
+                    source_path: row.try_get::<Option<String>, _>("source_path")?.map(PathBuf::from),
+                    source: row.try_get("source")?,
+                    remote_id: row.try_get("remote_id")?,
+                    source_url: row.try_get("source_url")?,
+                    hostname: row.try_get("hostname")?,
+                    recorded_at: row.try_get("recorded_at")?,
+                })
+            })
+            .collect()
+    }
+}
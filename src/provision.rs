@@ -0,0 +1,501 @@
+//! Vaults populated from a declarative spec
+//!
+//! A [`VaultSpec`] lists the custom fields, import templates, tag aliases
+//! and parents, collections and sounds a vault should hold, so tests and
+//! demo environments can start from the same library every time.
+//! Provisioning only adds what's missing: things already there are left
+//! alone, even when they differ from the spec, and reported as changed.
+
+use crate::audio::{AudioFormat, AudioInfo, SampleFormat, encode};
+use crate::error::{Result, VaultError};
+use crate::fields::{FieldSpec, SchemaMode};
+use crate::import::{ImportOptions, SoundMetadataTemplate};
+use crate::local::LocalLibrary;
+use crate::models::{Collection, SoundMetadata};
+use crate::query::SoundFilter;
+use crate::remote::send;
+use crate::undo::OperationId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Sample rate of synthesized sounds
+const SYNTH_SAMPLE_RATE: u32 = 44100;
+
+/// Peak level of synthesized tones
+const TONE_AMPLITUDE: f32 = 0.5;
+
+/// Contents a vault is provisioned with
+///
+/// Written in TOML or JSON; every part may be left out.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::VaultSpec;
+///
+/// let spec = VaultSpec::parse(r#"
+///     [fields.bpm]
+///     field_type = { type = "float" }
+///
+///     [tags.aliases]
+///     rainfall = "rain"
+///
+///     [[collections]]
+///     name = "Weather"
+///     filter = { tags = ["rain"] }
+///
+///     [[sounds]]
+///     name = "Beep"
+///     source = { kind = "tone", duration = 0.5, frequency = 440 }
+///     tags = ["test"]
+/// "#).unwrap();
+/// assert_eq!(spec.sounds[0].name, "Beep");
+/// assert_eq!(spec.tags.aliases["rainfall"], "rain");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VaultSpec {
+    /// Custom metadata fields, by key
+    pub fields: BTreeMap<String, FieldSpec>,
+
+    /// How custom metadata is checked against the fields
+    pub schema_mode: Option<SchemaMode>,
+
+    /// Import templates, by name
+    pub templates: BTreeMap<String, SoundMetadataTemplate>,
+
+    /// Tag aliases and hierarchy
+    pub tags: TagVocabulary,
+
+    /// Collections, by name
+    pub collections: Vec<CollectionSpec>,
+
+    /// Sounds, by name
+    pub sounds: Vec<SoundSpec>,
+}
+
+/// Tag aliases and parents of a [`VaultSpec`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagVocabulary {
+    /// Tag each alias stands for
+    pub aliases: BTreeMap<String, String>,
+
+    /// Parent of each tag
+    pub parents: BTreeMap<String, String>,
+}
+
+/// Collection of a [`VaultSpec`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CollectionSpec {
+    /// Name, which tells whether the collection is there
+    pub name: String,
+
+    /// Description of a collection created
+    pub description: String,
+
+    /// Filter whose sounds the collection holds, once the spec's sounds are
+    /// there; a smart collection filled in each time the vault is
+    /// provisioned
+    pub filter: Option<SoundFilter>,
+}
+
+/// Sound of a [`VaultSpec`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoundSpec {
+    /// Name, which tells whether the sound is there
+    pub name: String,
+
+    /// Where the sound's audio comes from
+    pub source: SoundSpecSource,
+
+    /// Description
+    #[serde(default)]
+    pub description: String,
+
+    /// Tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// License
+    #[serde(default)]
+    pub license: String,
+
+    /// Rating
+    #[serde(default)]
+    pub rating: Option<u8>,
+
+    /// Custom metadata
+    #[serde(default)]
+    pub custom: HashMap<String, String>,
+
+    /// Import template filling in the metadata left out
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// Names of the collections the sound belongs to
+    #[serde(default)]
+    pub collections: Vec<String>,
+}
+
+/// Audio of a [`SoundSpec`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SoundSpecSource {
+    /// Silence lasting `duration` seconds
+    Silence { duration: f32 },
+    /// Sine wave of `frequency` Hz lasting `duration` seconds
+    Tone { duration: f32, frequency: f32 },
+    /// File imported from a path
+    Path { path: PathBuf },
+    /// File downloaded from a URL
+    Url { url: String },
+}
+
+/// Kind of thing a [`VaultSpec`] declares
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvisionKind {
+    /// A custom field
+    Field,
+    /// The schema mode
+    SchemaMode,
+    /// An import template
+    Template,
+    /// A tag alias
+    TagAlias,
+    /// A tag's parent
+    TagParent,
+    /// A collection
+    Collection,
+    /// A sound
+    Sound,
+    /// A sound in a collection
+    Membership,
+}
+
+/// Something a [`VaultSpec`] declares
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProvisionItem {
+    /// What it is
+    pub kind: ProvisionKind,
+
+    /// Its name; `<sound> in <collection>` for memberships
+    pub name: String,
+}
+
+/// Outcome of provisioning a vault
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisionReport {
+    /// Things added to the vault
+    pub created: Vec<ProvisionItem>,
+
+    /// Things already there as the spec declares them
+    pub present: Vec<ProvisionItem>,
+
+    /// Things already there but differing from the spec, left as they are
+    pub changed: Vec<ProvisionItem>,
+
+    /// Operation the additions were made in, to undo them with
+    /// [`SoundVault::undo_operation`](crate::SoundVault::undo_operation);
+    /// `None` if nothing was added
+    pub operation: Option<OperationId>,
+}
+
+impl VaultSpec {
+    /// Parse a spec written in either JSON or TOML
+    pub fn parse(spec: &str) -> Result<Self> {
+        if spec.trim_start().starts_with('{') {
+            Ok(serde_json::from_str(spec)?)
+        } else {
+            toml::from_str(spec).map_err(|e| VaultError::InvalidOperation(format!("Invalid vault spec: {}", e)))
+        }
+    }
+}
+
+impl ProvisionItem {
+    fn new(kind: ProvisionKind, name: impl Into<String>) -> Self {
+        Self { kind, name: name.into() }
+    }
+}
+
+impl fmt::Display for ProvisionItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ProvisionKind::Field => "field",
+            ProvisionKind::SchemaMode => "schema mode",
+            ProvisionKind::Template => "template",
+            ProvisionKind::TagAlias => "tag alias",
+            ProvisionKind::TagParent => "parent of tag",
+            ProvisionKind::Collection => "collection",
+            ProvisionKind::Sound => "sound",
+            ProvisionKind::Membership => "membership",
+        };
+        write!(f, "{} {}", kind, self.name)
+    }
+}
+
+/// Whether something declared is there
+enum State {
+    Missing,
+    Present,
+    Changed,
+}
+
+/// What provisioning found, and whether it may add what's missing
+struct Tally {
+    report: ProvisionReport,
+    missing: Vec<ProvisionItem>,
+    check: bool,
+}
+
+impl Tally {
+    /// Record an item, telling whether it should be created
+    fn record(&mut self, item: ProvisionItem, state: State) -> bool {
+        match state {
+            State::Present => self.report.present.push(item),
+            State::Changed => self.report.changed.push(item),
+            State::Missing if self.check => self.missing.push(item),
+            State::Missing => {
+                self.report.created.push(item);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// State of a value the spec declares, given the one in the vault
+fn state_of<T: PartialEq>(current: Option<&T>, declared: &T) -> State {
+    match current {
+        None => State::Missing,
+        Some(current) if current == declared => State::Present,
+        Some(_) => State::Changed,
+    }
+}
+
+/// Whether a sound has the metadata the spec declares for it, ignoring what
+/// the spec leaves out
+fn sound_matches(metadata: &SoundMetadata, spec: &SoundSpec) -> bool {
+    let mut tags = spec.tags.clone();
+    tags.sort_by_key(|tag| tag.to_lowercase());
+    let mut own = metadata.tags.clone();
+    own.sort_by_key(|tag| tag.to_lowercase());
+
+    (spec.description.is_empty() || spec.description == metadata.description)
+        && (spec.tags.is_empty() || tags.iter().map(|t| t.to_lowercase()).eq(own.iter().map(|t| t.to_lowercase())))
+        && (spec.license.is_empty() || spec.license == metadata.license)
+        && (spec.rating.is_none() || spec.rating == metadata.rating)
+        && spec.custom.iter().all(|(key, value)| metadata.custom.get(key) == Some(value))
+}
+
+/// Mono 16-bit WAV file of synthesized audio
+fn synthesize(duration: f32, frequency: Option<f32>) -> Result<Vec<u8>> {
+    if !duration.is_finite() || duration < 0.0 {
+        return Err(VaultError::InvalidOperation(format!("Invalid duration: {}", duration)));
+    }
+    let frames = (duration * SYNTH_SAMPLE_RATE as f32).round() as usize;
+    let samples: Vec<f32> = match frequency {
+        Some(frequency) => (0..frames)
+            .map(|i| {
+                let t = i as f32 / SYNTH_SAMPLE_RATE as f32;
+                TONE_AMPLITUDE * (2.0 * std::f32::consts::PI * frequency * t).sin()
+            })
+            .collect(),
+        None => vec![0.0; frames],
+    };
+    encode(&AudioInfo::new(AudioFormat::Wav, 1, SYNTH_SAMPLE_RATE, SampleFormat::Int(16)), &samples)
+}
+
+impl LocalLibrary {
+    /// Add what a spec declares and the vault lacks
+    ///
+    /// With `check`, nothing is added: the vault is only compared with the
+    /// spec.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::SpecDrift` with `check`, if something declared is
+    ///   missing or differs
+    pub async fn provision(&self, spec: &VaultSpec, check: bool) -> Result<ProvisionReport> {
+        let mut collections: HashMap<String, Collection> = HashMap::new();
+        for collection in self.list_collections(false).await? {
+            collections.entry(collection.name.clone()).or_insert(collection);
+        }
+        for sound in &spec.sounds {
+            for name in &sound.collections {
+                if !collections.contains_key(name) && !spec.collections.iter().any(|c| &c.name == name) {
+                    return Err(VaultError::NotFound(format!("Collection not found: {}", name)));
+                }
+            }
+        }
+
+        let mut tally = Tally {
+            report: ProvisionReport::default(),
+            missing: Vec::new(),
+            check,
+        };
+
+        let fields = self.list_custom_field_specs().await?;
+        for (key, field) in &spec.fields {
+            if tally.record(ProvisionItem::new(ProvisionKind::Field, key), state_of(fields.get(key), field)) {
+                self.define_custom_field(key, field.clone()).await?;
+            }
+        }
+
+        // The default mode is the one nobody chose
+        if let Some(mode) = spec.schema_mode {
+            let state = match self.custom_schema_mode().await? {
+                current if current == mode => State::Present,
+                SchemaMode::Lax => State::Missing,
+                _ => State::Changed,
+            };
+            if tally.record(ProvisionItem::new(ProvisionKind::SchemaMode, format!("{:?}", mode).to_lowercase()), state) {
+                self.set_custom_schema_mode(mode).await?;
+            }
+        }
+
+        let templates = self.list_import_templates().await?;
+        for (name, template) in &spec.templates {
+            if tally.record(ProvisionItem::new(ProvisionKind::Template, name), state_of(templates.get(name), template)) {
+                self.save_import_template(name, template).await?;
+            }
+        }
+
+        let aliases: HashMap<String, String> = sqlx::query_as(&self.sql("SELECT alias, tag FROM tag_aliases"))
+            .fetch_all(&self.reader)
+            .await?
+            .into_iter()
+            .collect();
+        for (alias, tag) in &spec.tags.aliases {
+            if tally.record(ProvisionItem::new(ProvisionKind::TagAlias, alias), state_of(aliases.get(alias), tag)) {
+                self.add_tag_alias(alias, tag).await?;
+            }
+        }
+
+        let parents: HashMap<String, String> = sqlx::query_as(&self.sql("SELECT tag, parent FROM tag_parents"))
+            .fetch_all(&self.reader)
+            .await?
+            .into_iter()
+            .collect();
+        for (tag, parent) in &spec.tags.parents {
+            if tally.record(ProvisionItem::new(ProvisionKind::TagParent, tag), state_of(parents.get(tag), parent)) {
+                self.set_tag_parent(tag, Some(parent)).await?;
+            }
+        }
+
+        for declared in &spec.collections {
+            let item = ProvisionItem::new(ProvisionKind::Collection, &declared.name);
+            let state = match collections.get(&declared.name) {
+                None => State::Missing,
+                Some(existing) if declared.description.is_empty() || existing.description == declared.description => {
+                    State::Present
+                }
+                Some(_) => State::Changed,
+            };
+            if tally.record(item, state) {
+                let collection = Collection::new(&declared.name, &declared.description);
+                self.add_collection(&collection).await?;
+                collections.insert(declared.name.clone(), collection);
+            }
+        }
+
+        let mut members: Vec<(String, String, String)> = Vec::new();
+        for declared in &spec.sounds {
+            let existing: Option<String> =
+                sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE name = ? ORDER BY id LIMIT 1"))
+                    .bind(&declared.name)
+                    .fetch_optional(&self.reader)
+                    .await?;
+            let state = match &existing {
+                None => State::Missing,
+                Some(id) if sound_matches(&self.get_sound(id).await?.metadata, declared) => State::Present,
+                Some(_) => State::Changed,
+            };
+            let id = if tally.record(ProvisionItem::new(ProvisionKind::Sound, &declared.name), state) {
+                Some(self.provision_sound(declared).await?)
+            } else {
+                existing
+            };
+            if let Some(id) = id {
+                for collection in &declared.collections {
+                    members.push((id.clone(), declared.name.clone(), collection.clone()));
+                }
+            }
+        }
+
+        for declared in &spec.collections {
+            if let Some(filter) = &declared.filter {
+                for id in self.query_ids(filter).await? {
+                    if !members.iter().any(|(member, _, collection)| *member == id && *collection == declared.name) {
+                        let name = self.get_sound(&id).await?.metadata.name;
+                        members.push((id, name, declared.name.clone()));
+                    }
+                }
+            }
+        }
+        for (id, name, collection) in members {
+            let item = ProvisionItem::new(ProvisionKind::Membership, format!("{} in {}", name, collection));
+            // A collection missing in a check is reported already
+            let Some(existing) = collections.get_mut(&collection) else { continue };
+            let state = if existing.sound_ids.contains(&id) { State::Present } else { State::Missing };
+            if tally.record(item, state) {
+                self.add_sound_to_collection(&id, &existing.id.to_string()).await?;
+                existing.sound_ids.push(id);
+            }
+        }
+
+        if !tally.missing.is_empty() || (check && !tally.report.changed.is_empty()) {
+            return Err(VaultError::SpecDrift {
+                missing: tally.missing.iter().map(ToString::to_string).collect(),
+                changed: tally.report.changed.iter().map(ToString::to_string).collect(),
+            });
+        }
+        Ok(tally.report)
+    }
+
+    /// Import a sound the spec declares
+    async fn provision_sound(&self, spec: &SoundSpec) -> Result<String> {
+        let (file_name, bytes) = match &spec.source {
+            SoundSpecSource::Silence { duration } => ("sound.wav".to_string(), synthesize(*duration, None)?),
+            SoundSpecSource::Tone { duration, frequency } => ("sound.wav".to_string(), synthesize(*duration, Some(*frequency))?),
+            SoundSpecSource::Path { path } => return self.import_declared(spec, path).await,
+            SoundSpecSource::Url { url } => {
+                let response = send(reqwest::Client::new().get(url), url).await?;
+                let bytes = response.bytes().await.map_err(|e| VaultError::FileSystem(format!("Failed to download {}: {}", url, e)))?;
+                let name = url.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+                let name = if name.is_empty() { "sound" } else { name };
+                (name.to_string(), bytes.to_vec())
+            }
+        };
+
+        let path = std::env::temp_dir().join(format!("soundvault-{}-{}", Uuid::new_v4(), file_name));
+        tokio::fs::write(&path, &bytes).await?;
+        let imported = self.import_declared(spec, &path).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        imported
+    }
+
+    /// Import a file with the metadata a spec declares
+    async fn import_declared(&self, spec: &SoundSpec, path: &Path) -> Result<String> {
+        let metadata = SoundMetadata {
+            name: spec.name.clone(),
+            description: spec.description.clone(),
+            tags: spec.tags.clone(),
+            license: spec.license.clone(),
+            rating: spec.rating,
+            custom: spec.custom.clone(),
+            ..Default::default()
+        };
+        let options = ImportOptions {
+            template: spec.template.clone(),
+            ..Default::default()
+        };
+        self.import_file_with_options(path, Some(metadata), &options).await
+    }
+}
@@ -0,0 +1,1026 @@
+//! Structured queries over the local library
+
+use crate::browse::push_field;
+use crate::error::{Result, VaultError};
+use crate::fuzzy::{Fuzziness, SearchMatch};
+use crate::hmac::{hex, hmac, unhex};
+use crate::local::LocalLibrary;
+use crate::models::{Sound, normalize_lang};
+use crate::review::SoundStatus;
+use crate::snippet::{SnippetOptions, snippet};
+use crate::tables::Tables;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{QueryBuilder, Row, Sqlite};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Rows counted exactly before a total is reported as a lower bound
+const DEFAULT_EXACT_COUNT_LIMIT: u64 = 1000;
+
+/// SQLite virtual machine instructions between two `max_scan` checks
+const SCAN_CHECK_INTERVAL: i32 = 1000;
+
+/// Approximate number of virtual machine instructions spent per examined row
+const STEPS_PER_ROW: u64 = 50;
+
+/// SQLite result code for an interrupted statement
+const SQLITE_INTERRUPT: &str = "9";
+
+/// Filter selecting sounds from the local library
+///
+/// Every field that is set must match; an empty filter matches all sounds.
+/// [`SoundFilter::parse`] reads one from a query string as typed into a
+/// search box.
+///
+/// # Examples
+///
+/// Durations can be written as seconds or as [`parse_duration`](crate::parse_duration) strings:
+///
+/// ```
+/// use soundvault::SoundFilter;
+///
+/// let filter: SoundFilter = serde_json::from_str(r#"{"min_duration": "0:01.5", "max_duration": 90}"#).unwrap();
+/// assert_eq!((filter.min_duration, filter.max_duration), (Some(1.5), Some(90.0)));
+/// assert!(serde_json::from_str::<SoundFilter>(r#"{"max_duration": "-1m"}"#).is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundFilter {
+    /// Text matched against names and descriptions
+    pub text: Option<String>,
+
+    /// Tags the sounds must all have; a tag matches its aliases and the
+    /// tag they stand for too
+    pub tags: Vec<String>,
+
+    /// Let each of `tags` also match the tags below it, as set by
+    /// [`SoundVault::set_tag_parent`](crate::SoundVault::set_tag_parent)
+    pub tag_descendants: bool,
+
+    /// Collection the sounds must belong to
+    pub collection_id: Option<String>,
+
+    /// Name of a collection the sounds must belong to
+    pub collection_name: Option<String>,
+
+    /// Group the sounds must belong to
+    pub group_id: Option<String>,
+
+    /// Minimum duration in seconds
+    #[serde(deserialize_with = "crate::duration::deserialize_optional")]
+    pub min_duration: Option<f32>,
+
+    /// Maximum duration in seconds
+    #[serde(deserialize_with = "crate::duration::deserialize_optional")]
+    pub max_duration: Option<f32>,
+
+    /// Lowest rating; unrated sounds don't match
+    pub min_rating: Option<u8>,
+
+    /// Highest rating; unrated sounds don't match
+    pub max_rating: Option<u8>,
+
+    /// Exact license
+    pub license: Option<String>,
+
+    /// Latest expiration date: sounds expiring by then match, expired ones
+    /// included, and sounds without an expiration date don't
+    pub expires_by: Option<DateTime<Utc>>,
+
+    /// Language whose localized names and descriptions `text` also matches
+    pub lang: Option<String>,
+
+    /// Values of built-in fields (`author`, `license`, `format`) or custom
+    /// metadata keys the sounds must have; `None` matches sounds without a
+    /// value, as grouped by [`SoundVault::browse`](crate::SoundVault::browse)
+    pub fields: BTreeMap<String, Option<String>>,
+
+    /// Ranges the sounds' analysis descriptors must fall in; sounds without
+    /// a descriptor don't match its range
+    pub descriptors: BTreeMap<String, DescriptorRange>,
+
+    /// Tolerate typos in `text`: when few sounds match it exactly, pages of
+    /// [`SoundVault::query_page`](crate::SoundVault::query_page) go on with
+    /// sounds whose name or tags nearly match it. Off by default
+    pub fuzziness: Option<Fuzziness>,
+
+    /// Filters the sounds must not match, each on its own
+    pub exclude: Vec<SoundFilter>,
+
+    /// Review statuses the sounds must have; empty matches
+    /// [`SoundStatus::Active`] sounds only, leaving out those in the inbox
+    pub statuses: Vec<SoundStatus>,
+}
+
+/// Bounds of a descriptor value, both included
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DescriptorRange {
+    /// Smallest value
+    pub min: Option<f64>,
+
+    /// Largest value
+    pub max: Option<f64>,
+}
+
+/// Paging parameters for [`SoundFilter`] queries
+///
+/// Pages are fetched either by offset or after a [`PageCursor`]. Offsets
+/// can jump to any page, but a sound inserted or deleted before the offset
+/// between two fetches shifts the rest, so a sound is repeated or skipped.
+/// Cursors only go forward, and never repeat or skip a sound that existed
+/// when the first page was fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PageRequest {
+    /// Number of matching sounds to skip; ignored with `after`
+    pub offset: u64,
+
+    /// Fetch the sounds following this cursor, as returned in
+    /// [`SoundPage::next`]
+    pub after: Option<PageCursor>,
+
+    /// Maximum number of sounds to return
+    pub limit: u64,
+
+    /// Totals up to this value are counted exactly, larger ones are reported
+    /// as [`CountEstimate::AtLeast`]
+    pub exact_count_limit: u64,
+
+    /// Approximate number of rows the query may examine before it is aborted
+    pub max_scan: Option<u64>,
+
+    /// Keys to order sounds by, most significant first, each field at most
+    /// once; sounds are ordered by name when empty, and by ID after the last
+    /// key. Sounds without a value for a field come after those with one,
+    /// whatever the direction. Cursors only continue pages of the order they
+    /// were made for
+    pub order: Vec<(SoundOrderField, Direction)>,
+
+    /// Give each exact match of the filter's text an excerpt of its
+    /// description, in [`SearchMatch::snippet`]
+    pub snippets: Option<SnippetOptions>,
+}
+
+/// Field [`PageRequest::order`] sorts sounds by
+///
+/// # Examples
+///
+/// Pages after a cursor go on where the previous one stopped, on every key:
+///
+/// ```
+/// use soundvault::{Direction, PageRequest, SoundFilter, SoundMetadata, SoundOrderField, SoundVault, VaultConfig};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let sources = tempfile::tempdir()?;
+/// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+/// for (name, rating) in [("a", Some(3)), ("b", None), ("c", Some(5)), ("d", Some(3)), ("e", None)] {
+///     let path = sources.path().join(name);
+///     std::fs::write(&path, name)?;
+///     let metadata = SoundMetadata { name: name.to_string(), rating, ..Default::default() };
+///     vault.import_file(&path, Some(metadata)).await?;
+/// }
+///
+/// let mut page = PageRequest::new(0, 2);
+/// page.order = vec![(SoundOrderField::Rating, Direction::Descending), (SoundOrderField::Name, Direction::Ascending)];
+/// let mut names = Vec::new();
+/// loop {
+///     let results = vault.query_page(&SoundFilter::default(), &page).await?;
+///     names.extend(results.sounds.into_iter().map(|sound| sound.metadata.name));
+///     match results.next {
+///         Some(next) => page.after = Some(next),
+///         None => break,
+///     }
+/// }
+/// // Unrated sounds come last
+/// assert_eq!(names, ["c", "a", "d", "b", "e"]);
+///
+/// page.order.push((SoundOrderField::Rating, Direction::Ascending));
+/// page.after = None;
+/// assert!(vault.query_page(&SoundFilter::default(), &page).await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoundOrderField {
+    /// Name, as sorted for the vault's collation
+    Name,
+    /// Duration
+    Duration,
+    /// Rating
+    Rating,
+    /// Tempo, from the `bpm` analysis descriptor
+    Bpm,
+    /// When the sound was imported
+    Imported,
+    /// When the sound was last played
+    LastPlayed,
+    /// Size of the sound's file
+    FileSize,
+}
+
+/// Direction of a sort key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Smallest first
+    #[default]
+    Ascending,
+    /// Largest first
+    Descending,
+}
+
+/// Total number of sounds matching a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CountEstimate {
+    /// Exactly this many sounds match
+    Exact(u64),
+    /// At least this many sounds match; use `count` for the exact number
+    AtLeast(u64),
+}
+
+/// A page of query results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoundPage {
+    /// Sounds on this page, in the order of [`PageRequest::order`]; near
+    /// matches come after the exact ones, closest first
+    pub sounds: Vec<Sound>,
+
+    /// How each sound matched, in the order of `sounds`
+    #[serde(default)]
+    pub matches: Vec<SearchMatch>,
+
+    /// Total number of matching sounds
+    pub total: CountEstimate,
+
+    /// Cursor to fetch the next page with, or `None` if no sound followed
+    /// this page when it was fetched
+    #[serde(default)]
+    pub next: Option<PageCursor>,
+}
+
+/// Opaque position in the results of a query, after which the next page
+/// starts
+///
+/// The cursor holds the sort key and ID of the last sound of a page, so it
+/// stays valid whatever is inserted or deleted meanwhile. Its text can be
+/// stored or handed to a client and turned back into a cursor with
+/// [`From<String>`]. When
+/// [`VaultConfig::page_cursor_secret`](crate::VaultConfig::page_cursor_secret)
+/// is set, cursors are signed and those altered are refused.
+///
+/// # Examples
+///
+/// Sounds imported while paging come in the pages still to be fetched, and
+/// the sounds already there come exactly once:
+///
+/// ```
+/// use soundvault::{PageRequest, SoundFilter, SoundMetadata, SoundVault, VaultConfig};
+/// use std::collections::HashSet;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+/// let import = |name: String| {
+///     let (vault, path) = (&vault, dir.path().join(&name));
+///     async move {
+///         std::fs::write(&path, &name)?;
+///         let metadata = SoundMetadata { name, ..Default::default() };
+///         Ok::<_, Box<dyn std::error::Error>>(vault.import_file(&path, Some(metadata)).await?)
+///     }
+/// };
+///
+/// let mut existing = HashSet::new();
+/// for i in 0..10 {
+///     existing.insert(import(format!("b{}.wav", i)).await?);
+/// }
+///
+/// let filter = SoundFilter::default();
+/// let mut page = PageRequest::new(0, 3);
+/// let mut seen = Vec::new();
+/// let mut i = 0;
+/// loop {
+///     let results = vault.query_page(&filter, &page).await?;
+///     seen.extend(results.sounds.into_iter().map(|sound| sound.metadata.id));
+///     // Sounds sorting before, among and after the ones already listed
+///     for name in ["a", "b4", "c"] {
+///         import(format!("{}{}.wav", name, i)).await?;
+///     }
+///     i += 1;
+///     match results.next {
+///         Some(next) => page.after = Some(next.to_string().into()),
+///         None => break,
+///     }
+/// }
+///
+/// assert_eq!(seen.iter().collect::<HashSet<_>>().len(), seen.len());
+/// assert!(existing.iter().all(|id| seen.contains(id)));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PageCursor(String);
+
+impl PageCursor {
+    /// Text of the cursor
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for PageCursor {
+    fn from(text: String) -> Self {
+        Self(text)
+    }
+}
+
+/// What a [`PageCursor`] holds: the last sound of a page and how it matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+enum CursorKey {
+    /// A sound matching the filter exactly, with its value for each key of
+    /// the order, names as hex sort keys
+    Exact {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        order: Vec<(SoundOrderField, Direction)>,
+        keys: Vec<Option<String>>,
+        id: String,
+    },
+    /// A near match, ordered by distance then name
+    Near { distance: u32, sort_key: Option<String>, id: String },
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            after: None,
+            limit: 50,
+            exact_count_limit: DEFAULT_EXACT_COUNT_LIMIT,
+            max_scan: None,
+            order: Vec::new(),
+            snippets: None,
+        }
+    }
+}
+
+impl PageRequest {
+    /// Request a page of `limit` sounds starting at `offset`
+    pub fn new(offset: u64, limit: u64) -> Self {
+        Self {
+            offset,
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Request the page of `limit` sounds following a cursor
+    pub fn after(cursor: PageCursor, limit: u64) -> Self {
+        Self {
+            after: Some(cursor),
+            limit,
+            ..Default::default()
+        }
+    }
+}
+
+impl SoundOrderField {
+    /// Name of the field in requests and error messages
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Duration => "duration",
+            Self::Rating => "rating",
+            Self::Bpm => "bpm",
+            Self::Imported => "imported",
+            Self::LastPlayed => "last_played",
+            Self::FileSize => "file_size",
+        }
+    }
+
+    /// SQL expression the field sorts by
+    fn expr(&self) -> &'static str {
+        match self {
+            Self::Name => "sort_key",
+            Self::Duration => "duration",
+            Self::Rating => "rating",
+            Self::Bpm => "(SELECT value FROM sound_descriptors WHERE sound_id = sounds.id AND name = 'bpm')",
+            Self::Imported => "created_at",
+            Self::LastPlayed => "last_played_at",
+            Self::FileSize => "file_size",
+        }
+    }
+
+    /// Whether sounds without a value come last in this direction; names
+    /// keep SQLite's order, where a missing sort key is the smallest
+    fn nulls_last(&self, direction: Direction) -> bool {
+        *self != Self::Name || direction == Direction::Descending
+    }
+
+    /// A sound's value for the field, as stored in a cursor
+    fn read(&self, row: &SqliteRow, index: usize) -> std::result::Result<Option<String>, sqlx::Error> {
+        Ok(match self {
+            Self::Name => row.try_get::<Option<Vec<u8>>, _>(index)?.as_deref().map(hex),
+            Self::Duration | Self::Bpm => row.try_get::<Option<f64>, _>(index)?.map(|value| value.to_string()),
+            Self::Rating | Self::FileSize => row.try_get::<Option<i64>, _>(index)?.map(|value| value.to_string()),
+            Self::Imported | Self::LastPlayed => row.try_get::<Option<String>, _>(index)?,
+        })
+    }
+
+    /// Parse a value read from a cursor, or `None` if it isn't one of the
+    /// field's
+    fn parse(&self, value: &str) -> Option<SortValue> {
+        Some(match self {
+            Self::Name => SortValue::Blob(unhex(value)?),
+            Self::Duration | Self::Bpm => SortValue::Real(value.parse().ok()?),
+            Self::Rating | Self::FileSize => SortValue::Integer(value.parse().ok()?),
+            Self::Imported | Self::LastPlayed => SortValue::Text(value.to_string()),
+        })
+    }
+}
+
+/// Value of a sort key, typed to compare with its column
+#[derive(Debug, Clone)]
+enum SortValue {
+    Blob(Vec<u8>),
+    Real(f64),
+    Integer(i64),
+    Text(String),
+}
+
+impl SortValue {
+    fn push_bind(&self, builder: &mut QueryBuilder<'_, Sqlite>) {
+        match self {
+            Self::Blob(value) => builder.push_bind(value.clone()),
+            Self::Real(value) => builder.push_bind(*value),
+            Self::Integer(value) => builder.push_bind(*value),
+            Self::Text(value) => builder.push_bind(value.clone()),
+        };
+    }
+}
+
+impl fmt::Display for SoundOrderField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PageRequest {
+    /// Keys the sounds are ordered by, refusing a field given twice
+    fn sort_keys(&self) -> Result<Vec<(SoundOrderField, Direction)>> {
+        if self.order.is_empty() {
+            return Ok(vec![(SoundOrderField::Name, Direction::Ascending)]);
+        }
+        for (i, (field, _)) in self.order.iter().enumerate() {
+            if self.order[..i].iter().any(|(other, _)| other == field) {
+                return Err(VaultError::InvalidOperation(format!("Sound order lists {} more than once", field)));
+            }
+        }
+        Ok(self.order.clone())
+    }
+}
+
+/// Append the sort keys' values to a SELECT list, timestamps as text
+fn push_key_columns(builder: &mut QueryBuilder<'_, Sqlite>, tables: &Tables, keys: &[(SoundOrderField, Direction)]) {
+    for (field, _) in keys {
+        builder.push(", ");
+        match field {
+            SoundOrderField::Imported | SoundOrderField::LastPlayed => {
+                builder.push(format!("CAST({} AS TEXT)", tables.sql(field.expr())));
+            }
+            _ => {
+                builder.push(tables.sql(field.expr()));
+            }
+        }
+    }
+}
+
+/// Append an ORDER BY clause for the sort keys, then the ID
+fn push_order_by(builder: &mut QueryBuilder<'_, Sqlite>, tables: &Tables, keys: &[(SoundOrderField, Direction)]) {
+    builder.push(" ORDER BY ");
+    for (field, direction) in keys {
+        let expr = tables.sql(field.expr());
+        // SQLite puts NULL first going up and last going down
+        if *direction == Direction::Ascending && field.nulls_last(*direction) {
+            builder.push(format!("{} IS NULL, ", expr));
+        }
+        match direction {
+            Direction::Ascending => builder.push(format!("{}, ", expr)),
+            Direction::Descending => builder.push(format!("{} DESC, ", expr)),
+        };
+    }
+    builder.push("id");
+}
+
+/// Append the condition for sounds following a cursor's values of the sort
+/// keys
+fn push_after(
+    builder: &mut QueryBuilder<'_, Sqlite>,
+    tables: &Tables,
+    keys: &[(SoundOrderField, Direction)],
+    values: &[Option<SortValue>],
+    id: &str,
+) {
+    // Each key breaks the ties of the ones before it: (k1 after OR (k1 same
+    // AND (k2 after OR (... AND id after))))
+    builder.push(" AND ");
+    for ((field, direction), value) in keys.iter().zip(values) {
+        let expr = tables.sql(field.expr());
+        let nulls_last = field.nulls_last(*direction);
+        builder.push("(");
+        match value {
+            None if nulls_last => {
+                builder.push("0");
+            }
+            None => {
+                builder.push(format!("{} IS NOT NULL", expr));
+            }
+            Some(value) => {
+                let op = match direction {
+                    Direction::Ascending => ">",
+                    Direction::Descending => "<",
+                };
+                builder.push(format!("({} {} ", expr, op));
+                value.push_bind(builder);
+                if nulls_last {
+                    builder.push(format!(" OR {} IS NULL", expr));
+                }
+                builder.push(")");
+            }
+        }
+        builder.push(" OR (");
+        match value {
+            None => {
+                builder.push(format!("{} IS NULL", expr));
+            }
+            Some(value) => {
+                builder.push(format!("{} = ", expr));
+                value.push_bind(builder);
+            }
+        }
+        builder.push(" AND ");
+    }
+    builder.push("id > ");
+    builder.push_bind(id.to_string());
+    builder.push("))".repeat(keys.len()));
+}
+
+impl CountEstimate {
+    /// The known lower bound of the total
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::CountEstimate;
+    ///
+    /// assert_eq!(CountEstimate::Exact(12).value(), 12);
+    /// assert!(!CountEstimate::AtLeast(1000).is_exact());
+    /// ```
+    pub fn value(&self) -> u64 {
+        match self {
+            Self::Exact(n) | Self::AtLeast(n) => *n,
+        }
+    }
+
+    /// Check whether the total is exact
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Self::Exact(_))
+    }
+}
+
+impl SoundFilter {
+    /// Append the filter's conditions to a query as a WHERE clause
+    pub(crate) fn push_where(&self, builder: &mut QueryBuilder<'_, Sqlite>, tables: &Tables) {
+        builder.push(" WHERE 1 = 1");
+
+        // Sounds under review are left out unless asked for
+        match self.statuses.as_slice() {
+            [] => {
+                builder.push(" AND status = 'active'");
+            }
+            statuses => {
+                builder.push(" AND status IN (");
+                let mut separated = builder.separated(", ");
+                for status in statuses {
+                    separated.push_bind(status.as_str());
+                }
+                builder.push(")");
+            }
+        }
+        self.push_conditions(builder, tables);
+    }
+
+    /// Append the filter's conditions other than statuses, all excluded
+    /// filters need since they only narrow down the sounds of this one
+    fn push_conditions(&self, builder: &mut QueryBuilder<'_, Sqlite>, tables: &Tables) {
+        if let Some(text) = self.text.as_deref().filter(|t| !t.is_empty()) {
+            let pattern = like_pattern(text);
+            builder.push(" AND (name LIKE ");
+            builder.push_bind(pattern.clone());
+            builder.push(" ESCAPE '\\' OR description LIKE ");
+            builder.push_bind(pattern.clone());
+            builder.push(" ESCAPE '\\'");
+
+            if let Some(lang) = self.lang.as_deref() {
+                let lang = normalize_lang(lang);
+                let primary = lang.split('-').next().unwrap_or_default().to_string();
+                builder.push(tables.sql(" OR id IN (SELECT object_id FROM localized_text WHERE lang IN ("));
+                builder.push_bind(lang.clone());
+                builder.push(", ");
+                builder.push_bind(primary);
+                builder.push(") AND value LIKE ");
+                builder.push_bind(pattern);
+                builder.push(" ESCAPE '\\')");
+            }
+            builder.push(")");
+        }
+
+        // Each tag stands for its canonical tag, the aliases of that, and
+        // with `tag_descendants` the same for every tag below it
+        for tag in &self.tags {
+            builder.push(tables.sql(
+                " AND EXISTS (WITH RECURSIVE wanted(name) AS (SELECT COALESCE((SELECT tag FROM tag_aliases WHERE alias = ",
+            ));
+            builder.push_bind(tag.clone());
+            builder.push("), ");
+            builder.push_bind(tag.clone());
+            builder.push(")");
+            if self.tag_descendants {
+                builder.push(tables.sql(" UNION SELECT tag_parents.tag FROM tag_parents JOIN wanted ON parent = wanted.name"));
+            }
+            builder.push(tables.sql(
+                "), spelled(name) AS (SELECT name FROM wanted \
+                 UNION SELECT alias FROM tag_aliases JOIN wanted ON tag_aliases.tag = wanted.name) \
+                 SELECT 1 FROM spelled WHERE instr(lower(tags), lower('\"' || spelled.name || '\"')) > 0)",
+            ));
+        }
+
+        if let Some(collection_id) = &self.collection_id {
+            builder.push(tables.sql(" AND id IN (SELECT sound_id FROM collection_sounds WHERE collection_id = "));
+            builder.push_bind(collection_id.clone());
+            builder.push(")");
+        }
+
+        if let Some(name) = &self.collection_name {
+            builder.push(tables.sql(
+                " AND id IN (SELECT sound_id FROM collection_sounds JOIN collections ON collections.id = collection_id WHERE collections.name = ",
+            ));
+            builder.push_bind(name.clone());
+            builder.push(")");
+        }
+
+        if let Some(group_id) = &self.group_id {
+            builder.push(tables.sql(" AND id IN (SELECT sound_id FROM sound_group_members WHERE group_id = "));
+            builder.push_bind(group_id.clone());
+            builder.push(")");
+        }
+
+        if let Some(min) = self.min_duration {
+            builder.push(" AND duration >= ");
+            builder.push_bind(min);
+        }
+
+        if let Some(max) = self.max_duration {
+            builder.push(" AND duration <= ");
+            builder.push_bind(max);
+        }
+
+        if let Some(min) = self.min_rating {
+            builder.push(" AND rating >= ");
+            builder.push_bind(min);
+        }
+
+        if let Some(max) = self.max_rating {
+            builder.push(" AND rating <= ");
+            builder.push_bind(max);
+        }
+
+        if let Some(license) = &self.license {
+            builder.push(" AND license = ");
+            builder.push_bind(license.clone());
+        }
+
+        if let Some(expires_by) = self.expires_by {
+            builder.push(" AND expires_at <= ");
+            builder.push_bind(expires_by);
+        }
+
+        for (field, value) in &self.fields {
+            builder.push(" AND ");
+            push_field(builder, tables, field);
+            match value {
+                Some(value) => {
+                    builder.push(" = ");
+                    builder.push_bind(value.clone());
+                }
+                None => {
+                    builder.push(" IS NULL");
+                }
+            }
+        }
+
+        for (name, range) in &self.descriptors {
+            builder.push(tables.sql(" AND id IN (SELECT sound_id FROM sound_descriptors WHERE name = "));
+            builder.push_bind(name.clone());
+            if let Some(min) = range.min {
+                builder.push(" AND value >= ");
+                builder.push_bind(min);
+            }
+            if let Some(max) = range.max {
+                builder.push(" AND value <= ");
+                builder.push_bind(max);
+            }
+            builder.push(")");
+        }
+
+        for excluded in &self.exclude {
+            builder.push(tables.sql(" AND id NOT IN (SELECT id FROM sounds WHERE 1 = 1"));
+            excluded.push_conditions(builder, tables);
+            builder.push(")");
+        }
+    }
+}
+
+/// Build a LIKE pattern matching `text` anywhere, with wildcards escaped
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+impl LocalLibrary {
+    /// Fetch a page of sounds matching a filter
+    ///
+    /// The total is only counted exactly up to `page.exact_count_limit`, so the
+    /// first page of a broad search returns quickly. Pages fetched after a
+    /// cursor always count it.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Conditions the sounds must match
+    /// * `page` - Which page to fetch
+    pub async fn query_page(&self, filter: &SoundFilter, page: &PageRequest) -> Result<SoundPage> {
+        let keys = page.sort_keys()?;
+        let after = page.after.as_ref().map(|cursor| self.decode_cursor(cursor)).transpose()?;
+        // A cursor only goes on with the order it was made for
+        let values = match &after {
+            Some(CursorKey::Exact { order, keys: values, .. }) => {
+                if *order != page.order || values.len() != keys.len() {
+                    return Err(VaultError::InvalidOperation("Page cursor was made for another order".to_string()));
+                }
+                let values = keys.iter().zip(values).map(|((field, _), value)| match value {
+                    Some(value) => field.parse(value).map(Some),
+                    None => Some(None),
+                });
+                values.collect::<Option<Vec<_>>>().ok_or_else(|| {
+                    let cursor = page.after.as_ref().map_or("", PageCursor::as_str);
+                    VaultError::InvalidOperation(format!("Invalid page cursor: {}", cursor))
+                })?
+            }
+            _ => Vec::new(),
+        };
+        let offset = if after.is_some() { 0 } else { page.offset };
+        let mut conn = self.reader.acquire().await?;
+
+        if let Some(max_scan) = page.max_scan {
+            let budget = max_scan.saturating_mul(STEPS_PER_ROW) / SCAN_CHECK_INTERVAL as u64 + 1;
+            let mut checks = 0u64;
+            conn.lock_handle().await?.set_progress_handler(SCAN_CHECK_INTERVAL, move || {
+                checks += 1;
+                checks <= budget
+            });
+        }
+
+        let result = async {
+            let mut builder = QueryBuilder::new("SELECT id");
+            push_key_columns(&mut builder, &self.tables, &keys);
+            builder.push(self.sql(" FROM sounds"));
+            filter.push_where(&mut builder, &self.tables);
+            match &after {
+                None => {}
+                Some(CursorKey::Exact { id, .. }) => {
+                    push_after(&mut builder, &self.tables, &keys, &values, id);
+                }
+                // Every exact match came before the near ones
+                Some(CursorKey::Near { .. }) => {
+                    builder.push(" AND 0");
+                }
+            }
+            push_order_by(&mut builder, &self.tables, &keys);
+            builder.push(" LIMIT ");
+            builder.push_bind(page.limit as i64);
+            builder.push(" OFFSET ");
+            builder.push_bind(offset as i64);
+            let rows = builder
+                .build()
+                .fetch_all(&mut *conn)
+                .await?
+                .iter()
+                .map(|row| {
+                    let values = keys.iter().enumerate().map(|(i, (field, _))| field.read(row, i + 1));
+                    Ok((row.try_get::<String, _>(0)?, values.collect::<std::result::Result<Vec<_>, sqlx::Error>>()?))
+                })
+                .collect::<std::result::Result<Vec<_>, sqlx::Error>>()?;
+
+            // A short page that isn't past the end tells the total for free
+            let fetched = rows.len() as u64;
+            let total = if after.is_none() && fetched < page.limit && (fetched > 0 || offset == 0) {
+                CountEstimate::Exact(offset + fetched)
+            } else {
+                let cap = page.exact_count_limit.max(offset + page.limit + 1);
+                let mut builder = QueryBuilder::new(self.sql("SELECT COUNT(*) FROM (SELECT 1 FROM sounds"));
+                filter.push_where(&mut builder, &self.tables);
+                builder.push(" LIMIT ");
+                builder.push_bind(cap as i64);
+                builder.push(")");
+                let count: i64 = builder.build_query_scalar().fetch_one(&mut *conn).await?;
+
+                if (count as u64) < cap {
+                    CountEstimate::Exact(count as u64)
+                } else {
+                    CountEstimate::AtLeast(count as u64)
+                }
+            };
+
+            Ok::<_, sqlx::Error>((rows, total))
+        }
+        .await;
+
+        if page.max_scan.is_some() {
+            conn.lock_handle().await?.remove_progress_handler();
+        }
+
+        let (rows, mut total) = result.map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some(SQLITE_INTERRUPT) => {
+                VaultError::InvalidOperation(format!(
+                    "Query examined more than {} rows; narrow the filter or raise max_scan",
+                    page.max_scan.unwrap_or_default()
+                ))
+            }
+            _ => VaultError::Database(e),
+        })?;
+        drop(conn);
+
+        let mut last = rows.last().map(|(id, values)| CursorKey::Exact {
+            order: page.order.clone(),
+            keys: values.clone(),
+            id: id.clone(),
+        });
+        let mut ids: Vec<String> = rows.into_iter().map(|(id, _)| id).collect();
+        let mut matches = vec![SearchMatch::default(); ids.len()];
+        let text = filter.text.as_deref().filter(|text| !text.is_empty());
+        if let (Some(fuzziness), Some(text), CountEstimate::Exact(exact)) = (filter.fuzziness, text, total)
+            && exact < fuzziness.min_results
+        {
+            let exact_ids = self.query_ids(filter).await?.into_iter().collect();
+            let near = self.near_matches(filter, text, fuzziness, &exact_ids).await?;
+            total = CountEstimate::Exact(exact + near.len() as u64);
+
+            let skip = match &after {
+                None => offset.saturating_sub(exact) as usize,
+                Some(CursorKey::Exact { .. }) => 0,
+                Some(CursorKey::Near { distance, sort_key, id }) => {
+                    let sort_key = sort_key.as_deref().and_then(unhex);
+                    let position = (*distance, sort_key.as_deref(), id.as_str());
+                    near.iter()
+                        .take_while(|near| (near.distance, near.sort_key.as_deref(), near.id.as_str()) <= position)
+                        .count()
+                }
+            };
+            let room = page.limit.saturating_sub(ids.len() as u64) as usize;
+            for near in near.into_iter().skip(skip).take(room) {
+                last = Some(CursorKey::Near {
+                    distance: near.distance,
+                    sort_key: near.sort_key.as_deref().map(hex),
+                    id: near.id.clone(),
+                });
+                ids.push(near.id);
+                matches.push(SearchMatch {
+                    fuzzy: true,
+                    distance: near.distance,
+                    snippet: None,
+                });
+            }
+        }
+
+        // A short page is the last one
+        let next = match last {
+            Some(last) if ids.len() as u64 == page.limit => Some(self.encode_cursor(&last)?),
+            _ => None,
+        };
+
+        let mut sounds = Vec::with_capacity(ids.len());
+        for id in ids {
+            sounds.push(self.get_sound(&id).await?);
+        }
+        if let (Some(options), Some(text)) = (&page.snippets, text) {
+            for (sound, found) in sounds.iter().zip(&mut matches).filter(|(_, found)| !found.fuzzy) {
+                found.snippet = snippet(&sound.metadata.description, text, options);
+            }
+        }
+
+        Ok(SoundPage {
+            sounds,
+            matches,
+            total,
+            next,
+        })
+    }
+
+    /// Cursor after a sound, signed if the vault has a secret
+    fn encode_cursor(&self, key: &CursorKey) -> Result<PageCursor> {
+        let payload = hex(&serde_json::to_vec(key)?);
+        Ok(PageCursor(match &self.page_cursor_secret {
+            Some(secret) => format!("{}.{}", payload, hex(&hmac(secret.as_bytes(), payload.as_bytes()))),
+            None => payload,
+        }))
+    }
+
+    /// Sound a cursor points after, checking its signature if the vault has
+    /// a secret
+    fn decode_cursor(&self, cursor: &PageCursor) -> Result<CursorKey> {
+        let invalid = || VaultError::InvalidOperation(format!("Invalid page cursor: {}", cursor));
+        let (payload, signature) = match cursor.0.split_once('.') {
+            Some((payload, signature)) => (payload, Some(signature)),
+            None => (cursor.0.as_str(), None),
+        };
+        if let Some(secret) = &self.page_cursor_secret
+            && signature != Some(hex(&hmac(secret.as_bytes(), payload.as_bytes())).as_str())
+        {
+            return Err(invalid());
+        }
+
+        let bytes = unhex(payload).ok_or_else(invalid)?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
+
+    /// Count exactly how many sounds match a filter
+    pub async fn count(&self, filter: &SoundFilter) -> Result<u64> {
+        let mut builder = QueryBuilder::new(self.sql("SELECT COUNT(*) FROM sounds"));
+        filter.push_where(&mut builder, &self.tables);
+        let count: i64 = builder.build_query_scalar().fetch_one(&self.reader).await?;
+        Ok(count as u64)
+    }
+
+    /// Whether any sound matches a filter, stopping at the first one
+    pub async fn exists(&self, filter: &SoundFilter) -> Result<bool> {
+        let mut builder = QueryBuilder::new(self.sql("SELECT EXISTS (SELECT 1 FROM sounds"));
+        filter.push_where(&mut builder, &self.tables);
+        builder.push(")");
+        Ok(builder.build_query_scalar().fetch_one(&self.reader).await?)
+    }
+
+    /// Number of sounds in a collection
+    pub async fn collection_size(&self, collection_id: &str) -> Result<u64> {
+        let size: Option<i64> = sqlx::query_scalar(
+            &self.sql("SELECT (SELECT COUNT(*) FROM collection_sounds WHERE collection_id = id) FROM collections WHERE id = ?"),
+        )
+        .bind(collection_id)
+        .fetch_optional(&self.reader)
+        .await?;
+
+        match size {
+            Some(size) => Ok(size as u64),
+            None => Err(VaultError::NotFound(format!("Collection not found: {}", collection_id))),
+        }
+    }
+
+    /// Whether a sound with this content hash exists
+    pub async fn contains_hash(&self, hash: &str) -> Result<bool> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT EXISTS (SELECT 1 FROM sounds WHERE hash = ?)"))
+            .bind(hash)
+            .fetch_one(&self.reader)
+            .await?)
+    }
+
+    /// ID of a sound with this content hash, the smallest if there are several
+    pub(crate) async fn sound_with_hash(&self, hash: &str) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE hash = ? ORDER BY id LIMIT 1"))
+            .bind(hash)
+            .fetch_optional(&self.reader)
+            .await?)
+    }
+
+    /// IDs of all sounds matching a filter, ordered by name
+    pub(crate) async fn query_ids(&self, filter: &SoundFilter) -> Result<Vec<String>> {
+        let mut builder = QueryBuilder::new(self.sql("SELECT id FROM sounds"));
+        filter.push_where(&mut builder, &self.tables);
+        builder.push(" ORDER BY sort_key, id");
+        Ok(builder.build_query_scalar().fetch_all(&self.reader).await?)
+    }
+}
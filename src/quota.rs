@@ -0,0 +1,167 @@
+//! A cap on the space the library takes
+//!
+//! Usage is the recorded size of the sounds' files stored in the library
+//! plus that of the generated files; referenced files, and files held by
+//! other blob stores, don't count, and content-addressed files count once
+//! however many sounds share them. Imports
+//! and downloads reserve the size of their file before it enters the
+//! library, in one statement that checks usage and the other reservations,
+//! so imports running at once can't jointly exceed the cap.
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// `vault_info` key recording that every sound's file size was read
+const SIZES_RECORDED: &str = "file_sizes_recorded";
+
+/// Bytes taken by the library, reservations left out
+const USED: &str = "(SELECT COALESCE(SUM(file_size), 0) FROM sounds \
+                     WHERE external = 0 AND storage_backend IS NULL AND object_hash IS NULL) \
+                    + (SELECT COALESCE(SUM(size), 0) FROM objects) \
+                    + (SELECT COALESCE(SUM(size), 0) FROM artifacts)";
+
+/// Bytes reserved by imports and downloads under way
+const RESERVED: &str = "(SELECT COALESCE(SUM(bytes), 0) FROM quota_reservations)";
+
+/// What to do when a file would take the library over its size limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Refuse the file
+    #[default]
+    Fail,
+    /// Evict the generated files the artifact policy doesn't keep, then try
+    /// again once
+    PruneThenRetry,
+}
+
+/// Space taken by the library against its limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    /// Bytes of the sounds' files stored in the library and of the
+    /// generated files, as recorded
+    pub used: u64,
+
+    /// Bytes reserved by imports and downloads under way
+    pub reserved: u64,
+
+    /// Most bytes the library may take; `None` if unlimited
+    pub limit: Option<u64>,
+}
+
+impl QuotaUsage {
+    /// Bytes left under the limit; `None` if unlimited
+    pub fn available(&self) -> Option<u64> {
+        self.limit.map(|limit| limit.saturating_sub(self.used + self.reserved))
+    }
+}
+
+impl LocalLibrary {
+    /// Space taken by the library against its limit
+    pub async fn quota_usage(&self) -> Result<QuotaUsage> {
+        let (used, reserved): (i64, i64) = sqlx::query_as(&self.sql(&format!("SELECT {}, {}", USED, RESERVED)))
+            .fetch_one(&self.reader)
+            .await?;
+
+        Ok(QuotaUsage {
+            used: used as u64,
+            reserved: reserved as u64,
+            limit: self.max_library_bytes,
+        })
+    }
+
+    /// Reserve room for a file of `bytes` about to enter the library
+    ///
+    /// # Returns
+    ///
+    /// The reservation to release once the file is recorded, `None` if the
+    /// library is unlimited
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::QuotaExceeded` if the file would take the library over
+    ///   its limit
+    pub(crate) async fn reserve_space(&self, bytes: u64) -> Result<Option<String>> {
+        let Some(limit) = self.max_library_bytes else {
+            return Ok(None);
+        };
+
+        let id = Uuid::new_v4().to_string();
+        let sql = format!(
+            "INSERT INTO quota_reservations (id, bytes) SELECT ?, ? WHERE {} + {} + ? <= ?",
+            USED, RESERVED
+        );
+        let mut pruned = false;
+        loop {
+            let reserved = sqlx::query(&self.sql(&sql))
+                .bind(&id)
+                .bind(bytes as i64)
+                .bind(bytes as i64)
+                .bind(limit as i64)
+                .execute(&self.db)
+                .await?;
+            if reserved.rows_affected() == 1 {
+                return Ok(Some(id));
+            }
+            if pruned || self.on_quota != QuotaAction::PruneThenRetry {
+                break;
+            }
+            self.gc_artifacts().await?;
+            pruned = true;
+        }
+
+        let usage = self.quota_usage().await?;
+        Err(VaultError::QuotaExceeded {
+            needed: bytes,
+            available: usage.available().unwrap_or_default(),
+        })
+    }
+
+    /// Release a reservation from [`reserve_space`](Self::reserve_space)
+    pub(crate) async fn release_space(&self, reservation: Option<String>) -> Result<()> {
+        if let Some(id) = reservation {
+            sqlx::query(&self.sql("DELETE FROM quota_reservations WHERE id = ?"))
+                .bind(id)
+                .execute(&self.db)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop the reservations of imports a previous session left unfinished,
+    /// and record the file sizes of sounds stored before sizes were
+    pub(crate) async fn settle_quota(&self) -> Result<()> {
+        sqlx::query(&self.sql("DELETE FROM quota_reservations")).execute(&self.db).await?;
+
+        let recorded: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(SIZES_RECORDED)
+            .fetch_optional(&self.reader)
+            .await?;
+        if recorded.is_some() {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("INSERT INTO vault_info (key, value) VALUES (?, '1')"))
+            .bind(SIZES_RECORDED)
+            .execute(&mut *tx)
+            .await?;
+        let rows: Vec<(String, String)> =
+            sqlx::query_as(&self.sql("SELECT id, path FROM sounds WHERE external = 0 AND path IS NOT NULL"))
+                .fetch_all(&mut *tx)
+                .await?;
+        for (id, path) in rows {
+            sqlx::query(&self.sql("UPDATE sounds SET file_size = ? WHERE id = ?"))
+                .bind(std::fs::metadata(&path).ok().map(|metadata| metadata.len() as i64))
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
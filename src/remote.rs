@@ -1,16 +1,74 @@
 //! Module for interacting with Freesound.org API
 
-use crate::error::{Result, VaultError};
-use crate::models::{Sound, SoundMetadata, SoundSource};
+use crate::error::{RemoteErrorKind, Result, VaultError};
+use crate::models::{SoundMetadata, SoundSource, canonical_tags};
+use crate::paths::{finish_temp, safe_file_name, temp_path};
+use crate::source::{RemoteFuture, RemoteSource};
 use freesound_rs::{FreesoundClient, SearchQueryBuilder, SortOption};
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+/// Root of Freesound's API
+const API_ROOT: &str = "https://freesound.org/apiv2";
+
+/// Previews Freesound offers, in order of preference
+const PREVIEW_FIELDS: [&str; 4] = ["preview-hq-mp3", "preview-hq-ogg", "preview-lq-mp3", "preview-lq-ogg"];
+
+/// Largest preview held in memory
+const MAX_PREVIEW_BYTES: usize = 16 * 1024 * 1024;
+
+/// Bytes of recent previews kept for replays
+const PREVIEW_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// A Freesound preview held in memory, read and seeked like a file
+///
+/// Nothing of it is written to disk.
+#[derive(Debug, Clone)]
+pub struct PreviewStream {
+    cursor: Cursor<Arc<[u8]>>,
+}
+
+impl PreviewStream {
+    /// Whole content of the preview, an MP3 or Ogg Vorbis file
+    pub fn bytes(&self) -> &[u8] {
+        self.cursor.get_ref()
+    }
+}
+
+impl AsyncRead for PreviewStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.cursor).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for PreviewStream {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.cursor).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.cursor).poll_complete(cx)
+    }
+}
 
 /// Manager for accessing sounds from Freesound.org
 pub struct FreesoundManager {
     /// Freesound API client
     client: FreesoundClient,
+    /// HTTP client for the calls `client` doesn't offer
+    http: reqwest::Client,
+    /// Freesound API key
+    api_key: String,
     /// Default download directory
     download_dir: PathBuf,
+    /// Previews fetched lately, by Freesound ID, oldest first
+    previews: Mutex<VecDeque<(i32, Arc<[u8]>)>>,
 }
 
 impl FreesoundManager {
@@ -22,10 +80,332 @@ impl FreesoundManager {
     /// * `download_dir` - Directory where downloaded sounds will be saved
     pub fn new(api_key: String, download_dir: PathBuf) -> Self {
         Self {
-            client: FreesoundClient::new(api_key, None),
+            client: FreesoundClient::new(api_key.clone(), None),
+            http: reqwest::Client::new(),
+            api_key,
             download_dir,
+            previews: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Run a text search, returning the first page of results
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Search text
+    /// * `filter` - Optional filter in Freesound's filter syntax, e.g. `license:"Creative Commons 0"`
+    /// * `sort` - Result order
+    /// * `page_size` - Number of results to return
+    pub async fn search(
+        &self,
+        query: &str,
+        filter: Option<&str>,
+        sort: SortOption,
+        page_size: usize,
+    ) -> Result<Vec<freesound_rs::Sound>> {
+        let mut builder = SearchQueryBuilder::new().query(query).sort(sort).page_size(page_size);
+        if let Some(filter) = filter {
+            builder = builder.filter(filter);
+        }
+
+        let response = self
+            .client
+            .search(&builder.build())
+            .await
+            .map_err(|e| remote_error(&format!("{}/search/text/", API_ROOT), &e))?;
+        Ok(response.results)
+    }
+
+    /// Check that Freesound answers a minimal search with this API key
+    pub async fn probe(&self) -> bool {
+        let query = SearchQueryBuilder::new().query("").page_size(1).build();
+        self.client.search(&query).await.is_ok()
+    }
+
+    /// Fetch analysis descriptors of a sound
+    ///
+    /// Names are Freesound descriptor fields such as `pitch` or
+    /// `spectral_centroid`; a dotted name reaches into a nested analysis,
+    /// e.g. `lowlevel.spectral_centroid.mean`. Descriptors Freesound didn't
+    /// compute, or that aren't numbers, are left out.
+    pub async fn descriptors(&self, freesound_id: i32, names: &[String]) -> Result<HashMap<String, f64>> {
+        let fields = names
+            .iter()
+            .map(|name| name.split('.').next().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(",");
+        let endpoint = format!("{}/sounds/{}/", API_ROOT, freesound_id);
+        let request = self
+            .http
+            .get(&endpoint)
+            .query(&[("fields", fields.as_str())])
+            .header("Authorization", format!("Token {}", self.api_key));
+        let response = send(request, &endpoint).await?;
+        let analysis: serde_json::Value = response.json().await.map_err(|e| remote_error(&endpoint, &e))?;
+
+        Ok(names
+            .iter()
+            .filter_map(|name| descriptor_value(&analysis, name).map(|value| (name.clone(), value)))
+            .collect())
+    }
+
+    /// Stream the preview of a sound, without storing anything
+    ///
+    /// The preview is held in memory; previews of the same sound fetched
+    /// lately are served again without a request.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if Freesound offers no preview of the sound
+    /// * `VaultError::InvalidOperation` if the preview is too large to hold
+    pub async fn stream_preview(&self, freesound_id: i32) -> Result<PreviewStream> {
+        Ok(PreviewStream {
+            cursor: Cursor::new(self.preview(freesound_id).await?),
+        })
+    }
+
+    /// Content of the preview of a sound, from the recent previews if there
+    pub(crate) async fn preview(&self, freesound_id: i32) -> Result<Arc<[u8]>> {
+        if let Some((_, bytes)) = self.recent_previews().iter().find(|(id, _)| *id == freesound_id) {
+            return Ok(bytes.clone());
+        }
+
+        let endpoint = format!("{}/sounds/{}/", API_ROOT, freesound_id);
+        let request = self
+            .http
+            .get(&endpoint)
+            .query(&[("fields", "previews")])
+            .header("Authorization", format!("Token {}", self.api_key));
+        let sound: serde_json::Value = send(request, &endpoint)
+            .await?
+            .json()
+            .await
+            .map_err(|e| remote_error(&endpoint, &e))?;
+        let url = PREVIEW_FIELDS
+            .iter()
+            .find_map(|field| sound.get("previews")?.get(field)?.as_str())
+            .ok_or_else(|| VaultError::NotFound(format!("Freesound has no preview of {}", freesound_id)))?;
+
+        let mut response = send(self.http.get(url), url).await?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| remote_error(url, &e))? {
+            if bytes.len() + chunk.len() > MAX_PREVIEW_BYTES {
+                return Err(VaultError::InvalidOperation(format!(
+                    "Preview of {} exceeds {} bytes",
+                    freesound_id, MAX_PREVIEW_BYTES
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        let bytes: Arc<[u8]> = bytes.into();
+
+        let mut previews = self.recent_previews();
+        previews.retain(|(id, _)| *id != freesound_id);
+        previews.push_back((freesound_id, bytes.clone()));
+        while previews.iter().map(|(_, bytes)| bytes.len()).sum::<usize>() > PREVIEW_CACHE_BYTES {
+            previews.pop_front();
+        }
+        Ok(bytes)
+    }
+
+    /// Previews fetched lately, by Freesound ID, oldest first
+    fn recent_previews(&self) -> MutexGuard<'_, VecDeque<(i32, Arc<[u8]>)>> {
+        self.previews.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Download a sound into its own folder of the download directory
+    ///
+    /// # Arguments
+    ///
+    /// * `freesound_id` - Freesound ID of the sound
+    /// * `id` - Local ID of the sound, naming its folder
+    /// * `file_name` - Name of the downloaded file
+    ///
+    /// # Returns
+    ///
+    /// The path of the downloaded file
+    pub async fn download(&self, freesound_id: i32, id: &str, file_name: &str) -> Result<PathBuf> {
+        let target_path = self.download_dir.join(id).join(file_name);
+        std::fs::create_dir_all(target_path.parent().unwrap_or(&self.download_dir)).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to create directory: {}", e))
+        })?;
+
+        // Only a complete download takes the final name
+        let temp = temp_path(&target_path);
+        if let Err(e) = self.client.download_sound(freesound_id, &temp).await {
+            let _ = std::fs::remove_file(&temp);
+            return Err(remote_error(&download_endpoint(freesound_id), &e));
+        }
+        finish_temp(&temp, &target_path).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to save download: {}", e))
+        })?;
+        Ok(target_path)
+    }
+}
+
+impl RemoteSource for FreesoundManager {
+    fn name(&self) -> &str {
+        SoundSource::Freesound.as_str()
+    }
+
+    fn search<'a>(&'a self, query: &'a str, page_size: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> {
+        Box::pin(async move {
+            let results = FreesoundManager::search(self, query, None, SortOption::Score, page_size).await?;
+            Ok(results.iter().map(to_metadata).collect())
+        })
+    }
+
+    fn get_by_id<'a>(&'a self, remote_id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+        Box::pin(async move {
+            let freesound_id = parse_id(remote_id)?;
+            let sound = self
+                .client
+                .get_sound(freesound_id)
+                .await
+                .map_err(|e| remote_error(&format!("{}/sounds/{}/", API_ROOT, freesound_id), &e))?;
+            Ok(to_metadata(&sound))
+        })
+    }
+
+    fn download<'a>(&'a self, remote_id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+        Box::pin(async move {
+            let freesound_id = parse_id(remote_id)?;
+            self.client
+                .download_sound(freesound_id, target)
+                .await
+                .map_err(|e| remote_error(&download_endpoint(freesound_id), &e))
+        })
+    }
+
+    fn descriptors<'a>(&'a self, remote_id: &'a str, names: &'a [String]) -> RemoteFuture<'a, HashMap<String, f64>> {
+        Box::pin(async move { FreesoundManager::descriptors(self, parse_id(remote_id)?, names).await })
+    }
+
+    fn sound_url(&self, remote_id: &str) -> Option<String> {
+        Some(format!("https://freesound.org/s/{}/", parse_id(remote_id).ok()?))
+    }
+
+    /// Freesound names originals `<id>__<uploader>__<sound name>`
+    fn file_name(&self, remote_id: &str, metadata: &SoundMetadata) -> String {
+        match metadata.get_custom("freesound_username") {
+            Some(username) => format!("{}__{}__{}", remote_id, username, metadata.name),
+            None => metadata.name.clone(),
+        }
+    }
+}
+
+/// Send a request, reporting failures and error statuses as
+/// `VaultError::Remote`
+pub(crate) async fn send(request: reqwest::RequestBuilder, endpoint: &str) -> Result<reqwest::Response> {
+    let response = request.send().await.map_err(|e| remote_error(endpoint, &e))?;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    Err(VaultError::Remote {
+        kind: RemoteErrorKind::from_status(status.as_u16()),
+        status: Some(status.as_u16()),
+        endpoint: endpoint.to_string(),
+        retry_after,
+        message: status.canonical_reason().unwrap_or("Request refused").to_string(),
+    })
+}
+
+/// Describe a failed request to a remote source
+///
+/// The kind comes from the HTTP client's or JSON parser's error among the
+/// error's sources. freesound-rs reports the API's refusals as text, so
+/// failing those, a status is read from the message.
+pub(crate) fn remote_error(endpoint: &str, error: &(dyn std::error::Error + 'static)) -> VaultError {
+    let mut source = Some(error);
+    let (kind, status) = loop {
+        let Some(e) = source else {
+            let status = error
+                .to_string()
+                .split(|c: char| !c.is_ascii_digit())
+                .filter_map(|word| word.parse::<u16>().ok())
+                .find(|status| (400..600).contains(status));
+            break (status.map_or(RemoteErrorKind::Other, RemoteErrorKind::from_status), status);
+        };
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            let status = e.status().map(|status| status.as_u16());
+            let kind = match status {
+                Some(status) => RemoteErrorKind::from_status(status),
+                None if e.is_decode() => RemoteErrorKind::Decode,
+                None if e.is_builder() => RemoteErrorKind::Other,
+                None => RemoteErrorKind::Network,
+            };
+            break (kind, status);
         }
+        if e.is::<serde_json::Error>() {
+            break (RemoteErrorKind::Decode, None);
+        }
+        source = e.source();
+    };
+
+    VaultError::Remote {
+        kind,
+        status,
+        endpoint: endpoint.to_string(),
+        retry_after: None,
+        message: error.to_string(),
     }
+}
+
+/// Wait a `Retry-After` header asks for, in seconds or until an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let until = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some((until.to_utc() - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// URL Freesound downloads a sound's file from
+fn download_endpoint(freesound_id: i32) -> String {
+    format!("{}/sounds/{}/download/", API_ROOT, freesound_id)
+}
+
+/// Scalar value of a descriptor in an analysis, following dotted names
+///
+/// A statistics object stands for its mean.
+fn descriptor_value(analysis: &serde_json::Value, name: &str) -> Option<f64> {
+    let value = name.split('.').try_fold(analysis, |value, key| value.get(key))?;
+    value.as_f64().or_else(|| value.get("mean")?.as_f64())
+}
+
+/// Parse the ID of a Freesound sound
+fn parse_id(remote_id: &str) -> Result<i32> {
+    remote_id
+        .parse()
+        .map_err(|_| VaultError::NotFound(format!("Not a Freesound ID: {}", remote_id)))
+}
+
+/// Describe a Freesound sound as local metadata, without a file
+pub fn to_metadata(sound: &freesound_rs::Sound) -> SoundMetadata {
+    let mut metadata = SoundMetadata {
+        name: sound.name.clone(),
+        source: SoundSource::Freesound,
+        tags: canonical_tags(sound.tags.clone()),
+        description: sound.description.clone(),
+        duration: sound.duration.unwrap_or_default() as f32,
+        license: sound.license.clone(),
+        freesound_id: Some(sound.id),
+        remote_id: Some(sound.id.to_string()),
+        ..Default::default()
+    };
+    metadata.set_custom("freesound_username", &sound.username);
+    metadata.set_custom("freesound_url", &sound.url);
+    metadata
+}
 
-    // This will be filled with the actual implementation
+/// Turn a Freesound sound name into a safe file name
+pub fn file_name(sound: &freesound_rs::Sound) -> String {
+    safe_file_name(&sound.name, &sound.id.to_string())
 }
@@ -0,0 +1,351 @@
+//! Find-and-replace across the text of sounds and collections
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::canonical_tags;
+use crate::patch::MetadataPatch;
+use crate::undo::OperationId;
+use regex::{NoExpand, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+/// Longest pattern accepted, in bytes
+const MAX_PATTERN_LEN: usize = 1024;
+
+/// Memory a compiled pattern may take, so a pattern such as `(a{1000}){1000}`
+/// is refused rather than built
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// Text fields searched by [`SoundVault::replace_text`](crate::SoundVault::replace_text)
+///
+/// The default searches them all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextScope {
+    /// Names of sounds
+    pub names: bool,
+
+    /// Descriptions of sounds
+    pub descriptions: bool,
+
+    /// Tags of sounds, each replaced as a whole string
+    pub tags: bool,
+
+    /// Values of the custom metadata of sounds
+    pub custom_values: bool,
+
+    /// Names of collections
+    pub collection_names: bool,
+}
+
+impl Default for TextScope {
+    fn default() -> Self {
+        Self {
+            names: true,
+            descriptions: true,
+            tags: true,
+            custom_values: true,
+            collection_names: true,
+        }
+    }
+}
+
+impl TextScope {
+    /// Check whether no field is searched
+    pub fn is_empty(&self) -> bool {
+        !(self.names || self.descriptions || self.tags || self.custom_values || self.collection_names)
+    }
+}
+
+/// How the pattern of a find-and-replace is matched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// The pattern is plain text
+    #[default]
+    Literal,
+    /// The pattern is plain text, matched only between word boundaries
+    WholeWord,
+    /// The pattern is a regular expression; the replacement may refer to its
+    /// groups as `$1` or `${name}`
+    Regex,
+}
+
+/// Options of [`SoundVault::replace_text`](crate::SoundVault::replace_text)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplaceOptions {
+    /// How the pattern is matched
+    pub mode: MatchMode,
+
+    /// Match letters in the case given only
+    pub case_sensitive: bool,
+
+    /// Only report what would change; on by default, so applying the
+    /// replacement always takes a second, explicit call
+    pub dry_run: bool,
+
+    /// Change locked sounds too, instead of failing with
+    /// [`VaultError::Locked`]
+    pub override_lock: bool,
+}
+
+impl Default for ReplaceOptions {
+    fn default() -> Self {
+        Self {
+            mode: MatchMode::Literal,
+            case_sensitive: true,
+            dry_run: true,
+            override_lock: false,
+        }
+    }
+}
+
+/// Kind of object whose text was replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextEntity {
+    /// A sound
+    Sound,
+    /// A collection
+    Collection,
+}
+
+/// A field whose text a find-and-replace changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextChange {
+    /// Kind of object
+    pub entity: TextEntity,
+
+    /// ID of the sound or collection
+    pub entity_id: String,
+
+    /// `name`, `description`, `tags` or `custom.<key>`
+    pub field: String,
+
+    /// Text before the replacement; one tag for `tags`
+    pub before: String,
+
+    /// Text after the replacement; an empty tag is removed
+    pub after: String,
+}
+
+/// Outcome of [`SoundVault::replace_text`](crate::SoundVault::replace_text)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplaceReport {
+    /// Whether the changes were written, rather than previewed
+    pub applied: bool,
+
+    /// Every field changed, sound by sound then collection by collection
+    pub changes: Vec<TextChange>,
+
+    /// Operation to pass to [`SoundVault::undo_operation`](crate::SoundVault::undo_operation)
+    /// to take applied changes back
+    #[serde(default)]
+    pub operation: Option<OperationId>,
+}
+
+impl ReplaceReport {
+    /// Number of sounds and collections changed
+    pub fn entities(&self) -> usize {
+        let mut entities: Vec<_> = self.changes.iter().map(|change| (change.entity, &change.entity_id)).collect();
+        entities.dedup();
+        entities.len()
+    }
+}
+
+/// Compile the pattern of a find-and-replace
+fn compile(pattern: &str, options: &ReplaceOptions) -> Result<Regex> {
+    if pattern.is_empty() || pattern.len() > MAX_PATTERN_LEN {
+        return Err(VaultError::InvalidOperation(format!(
+            "Patterns must hold between 1 and {} bytes",
+            MAX_PATTERN_LEN
+        )));
+    }
+
+    let source = match options.mode {
+        MatchMode::Literal => regex::escape(pattern),
+        MatchMode::WholeWord => format!(r"\b{}\b", regex::escape(pattern)),
+        MatchMode::Regex => pattern.to_string(),
+    };
+    RegexBuilder::new(&source)
+        .case_insensitive(!options.case_sensitive)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .dfa_size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+        .map_err(|e| VaultError::InvalidOperation(format!("Invalid pattern {:?}: {}", pattern, e)))
+}
+
+/// Replaces every match of a pattern in a text
+struct Replacer<'a> {
+    regex: Regex,
+    replacement: &'a str,
+    expand: bool,
+    override_lock: bool,
+}
+
+impl Replacer<'_> {
+    /// The replaced text, if anything matched
+    fn apply(&self, text: &str) -> Option<String> {
+        let replaced = if self.expand {
+            self.regex.replace_all(text, self.replacement)
+        } else {
+            self.regex.replace_all(text, NoExpand(self.replacement))
+        };
+        (replaced != text).then(|| replaced.into_owned())
+    }
+}
+
+impl LocalLibrary {
+    /// Replace text across the fields of sounds and collections in `scope`
+    ///
+    /// Every change is listed in the report. Unless `options.dry_run` is off,
+    /// nothing is written; otherwise the changes are applied in one
+    /// transaction, recorded in the audit log, and an invalid custom value
+    /// or a locked sound cancels them all.
+    pub async fn replace_text(
+        &self,
+        scope: TextScope,
+        pattern: &str,
+        replacement: &str,
+        options: ReplaceOptions,
+    ) -> Result<ReplaceReport> {
+        if scope.is_empty() {
+            return Err(VaultError::InvalidOperation("No field to replace text in".to_string()));
+        }
+        let replacer = Replacer {
+            regex: compile(pattern, &options)?,
+            replacement,
+            expand: options.mode == MatchMode::Regex,
+            override_lock: options.override_lock,
+        };
+
+        let mut report = ReplaceReport {
+            applied: !options.dry_run,
+            ..Default::default()
+        };
+        let mut tx = self.db.begin().await?;
+        self.replace_in_sounds(&mut tx, scope, &replacer, &mut report).await?;
+        if scope.collection_names {
+            self.replace_in_collections(&mut tx, &replacer, &mut report).await?;
+        }
+        if report.applied {
+            tx.commit().await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn replace_in_sounds(
+        &self,
+        conn: &mut SqliteConnection,
+        scope: TextScope,
+        replacer: &Replacer<'_>,
+        report: &mut ReplaceReport,
+    ) -> Result<()> {
+        if !(scope.names || scope.descriptions || scope.tags || scope.custom_values) {
+            return Ok(());
+        }
+
+        let ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds ORDER BY id"))
+            .fetch_all(&mut *conn)
+            .await?;
+        for id in ids {
+            let before = self.fetch_sound(conn, &id).await?.metadata;
+            let mut after = before.clone();
+            let mut change = |field: &str, old: &str, new: &str| {
+                report.changes.push(TextChange {
+                    entity: TextEntity::Sound,
+                    entity_id: id.clone(),
+                    field: field.to_string(),
+                    before: old.to_string(),
+                    after: new.to_string(),
+                });
+            };
+
+            if scope.names
+                && let Some(name) = replacer.apply(&before.name)
+            {
+                change("name", &before.name, &name);
+                after.name = name;
+            }
+            if scope.descriptions
+                && let Some(description) = replacer.apply(&before.description)
+            {
+                change("description", &before.description, &description);
+                after.description = description;
+            }
+            if scope.tags {
+                let tags = before.tags.iter().map(|tag| match replacer.apply(tag) {
+                    Some(new) => {
+                        change("tags", tag, new.trim());
+                        new
+                    }
+                    None => tag.clone(),
+                });
+                after.tags = canonical_tags(tags.collect());
+            }
+            if scope.custom_values {
+                let mut keys: Vec<&String> = before.custom.keys().collect();
+                keys.sort();
+                for key in keys {
+                    if let Some(value) = replacer.apply(&before.custom[key]) {
+                        change(&format!("custom.{}", key), &before.custom[key], &value);
+                        after.custom.insert(key.clone(), value);
+                    }
+                }
+            }
+
+            let patch = MetadataPatch {
+                override_lock: replacer.override_lock,
+                ..MetadataPatch::between(&before, &after)
+            };
+            if report.applied && !patch.is_empty() {
+                self.apply_patch(conn, &id, &patch).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn replace_in_collections(
+        &self,
+        conn: &mut SqliteConnection,
+        replacer: &Replacer<'_>,
+        report: &mut ReplaceReport,
+    ) -> Result<()> {
+        let rows: Vec<(String, String)> = sqlx::query_as(&self.sql("SELECT id, name FROM collections ORDER BY id"))
+            .fetch_all(&mut *conn)
+            .await?;
+        for (id, name) in rows {
+            let Some(renamed) = replacer.apply(&name) else {
+                continue;
+            };
+            report.changes.push(TextChange {
+                entity: TextEntity::Collection,
+                entity_id: id.clone(),
+                field: "name".to_string(),
+                before: name.clone(),
+                after: renamed.clone(),
+            });
+            if !report.applied {
+                continue;
+            }
+
+            sqlx::query(&self.sql("UPDATE collections SET name = ?, sort_key = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+                .bind(&renamed)
+                .bind(self.collator.sort_key(&renamed))
+                .bind(&id)
+                .execute(&mut *conn)
+                .await?;
+            let changes = audit_diff(
+                Some(&serde_json::json!({ "name": name })),
+                Some(&serde_json::json!({ "name": renamed })),
+            );
+            self.audit(conn, AuditOperation::UpdateCollection, &id, changes).await?;
+        }
+
+        Ok(())
+    }
+}
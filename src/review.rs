@@ -0,0 +1,156 @@
+//! Review of imported sounds before they join the library
+//!
+//! Sounds imported with [`ImportOptions::inbox`](crate::ImportOptions::inbox)
+//! wait in the [`SoundStatus::Inbox`] until approved or rejected. Searches,
+//! listings and statistics leave out the sounds that aren't
+//! [`SoundStatus::Active`] unless a filter asks for them with
+//! [`SoundFilter::statuses`](crate::SoundFilter::statuses).
+
+use crate::audit::AuditOperation;
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::patch::MetadataPatch;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Where a sound stands in the review of imports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoundStatus {
+    /// Part of the library
+    #[default]
+    Active,
+    /// Imported and waiting for review
+    Inbox,
+    /// Turned down in review, on its way to the trash
+    Rejected,
+}
+
+impl SoundStatus {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Inbox => "inbox",
+            Self::Rejected => "rejected",
+        }
+    }
+
+    /// Parse a name stored in the database, defaulting to active
+    pub(crate) fn parse(name: &str) -> Self {
+        match name {
+            "inbox" => Self::Inbox,
+            "rejected" => Self::Rejected,
+            _ => Self::Active,
+        }
+    }
+}
+
+/// What [`SoundVault::approve`](crate::SoundVault::approve) does besides
+/// making sounds active
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApproveOptions {
+    /// Name of an import template filling in the metadata the sounds lack
+    pub template: Option<String>,
+
+    /// Collection to add the sounds to
+    pub collection_id: Option<String>,
+}
+
+impl LocalLibrary {
+    /// Move sounds out of review into the library
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds that weren't active yet
+    pub async fn approve(&self, ids: &[String], options: &ApproveOptions) -> Result<usize> {
+        let template = match &options.template {
+            Some(name) => Some(self.import_template(name).await?),
+            None => None,
+        };
+
+        let mut approved = 0;
+        for id in ids {
+            let before = self.get_sound(id).await?.metadata;
+            let patch = match &template {
+                Some(template) => {
+                    // Placeholders stand for the name of the imported file,
+                    // which a content-addressed file no longer has
+                    let path = match before.path.as_deref().filter(|path| self.object_hash(path).is_none()) {
+                        Some(path) => path.to_path_buf(),
+                        None => PathBuf::from(&before.name),
+                    };
+                    MetadataPatch::between(&before, &template.metadata_for(&path, Some(before.clone())))
+                }
+                None => MetadataPatch::default(),
+            };
+            if self.set_status(id, SoundStatus::Active, &patch).await? {
+                approved += 1;
+            }
+            if let Some(collection_id) = &options.collection_id {
+                self.add_sound_to_collection(id, collection_id).await?;
+            }
+        }
+
+        Ok(approved)
+    }
+
+    /// Turn sounds down in review, moving them to the trash
+    ///
+    /// Their files go into the quarantine directory, from which
+    /// [`restore_from_quarantine`](Self::restore_from_quarantine) can bring
+    /// them back, and the sounds are deleted.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Locked` if a sound is locked; the sounds before it are
+    ///   rejected
+    pub async fn reject(&self, ids: &[String]) -> Result<usize> {
+        for id in ids {
+            if self.get_sound(id).await?.metadata.locked {
+                return Err(VaultError::Locked(id.to_string()));
+            }
+            self.set_status(id, SoundStatus::Rejected, &MetadataPatch::default()).await?;
+            self.trash_sound(id).await?;
+        }
+
+        Ok(ids.len())
+    }
+
+    /// Apply a patch and move a sound to a status, announcing the change
+    ///
+    /// # Returns
+    ///
+    /// Whether the status changed
+    pub(crate) async fn set_status(&self, id: &str, status: SoundStatus, patch: &MetadataPatch) -> Result<bool> {
+        let mut tx = self.db.begin().await?;
+        self.apply_patch(&mut tx, id, patch).await?;
+        let before: String = sqlx::query_scalar(&self.sql("SELECT status FROM sounds WHERE id = ?"))
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let before = SoundStatus::parse(&before);
+        if before == status {
+            tx.commit().await?;
+            return Ok(false);
+        }
+
+        sqlx::query(&self.sql("UPDATE sounds SET status = ? WHERE id = ?"))
+            .bind(status.as_str())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let changes = json!({ "status": [before, status] });
+        self.audit(&mut tx, AuditOperation::UpdateSound, id, changes).await?;
+        tx.commit().await?;
+
+        self.emit(VaultEvent::SoundStatusChanged {
+            sound_id: id.to_string(),
+            status,
+        });
+        Ok(true)
+    }
+}
@@ -0,0 +1,142 @@
+//! Sounds ready to hand to a rodio sink
+
+use crate::audio::decode;
+use crate::error::{Result, VaultError};
+use crate::levels::integrated_loudness;
+use crate::local::LocalLibrary;
+use crate::models::{Sound, Trim};
+use crate::remote::{remote_error, send};
+use crate::sniff::FileFormat;
+use rodio::Source;
+use rodio::buffer::SamplesBuffer;
+use rodio::decoder::DecoderError;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// How to prepare a sound for playback through rodio
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RodioOptions {
+    /// Keep only the part between the sound's trim markers, if it has some
+    pub apply_trim: bool,
+
+    /// Integrated loudness in LUFS to bring the sound to, e.g. `-23.0`
+    pub normalize_to: Option<f64>,
+
+    /// Mark the sound as played
+    pub record_play: bool,
+}
+
+impl Default for RodioOptions {
+    /// Trimmed, at its own loudness, recording a play
+    fn default() -> Self {
+        Self {
+            apply_trim: true,
+            normalize_to: None,
+            record_play: true,
+        }
+    }
+}
+
+/// Decode a file to its rate, channels and interleaved samples
+///
+/// PCM WAV, AIFF and CAF files go through the vault's own decoder; other
+/// formats, and PCM files it refuses, through rodio's.
+pub(crate) fn decode_any(bytes: Vec<u8>) -> Result<(u32, u16, Vec<f32>)> {
+    if let Some(FileFormat::Wav | FileFormat::Aiff | FileFormat::Caf) = FileFormat::sniff(&bytes)
+        && let Ok((info, samples)) = decode(&mut Cursor::new(&bytes))
+    {
+        return Ok((info.sample_rate, info.channels, samples));
+    }
+
+    let decoder = rodio::Decoder::new(Cursor::new(bytes)).map_err(|e| match e {
+        DecoderError::IoError(message) => VaultError::Io(std::io::Error::other(message)),
+        e => VaultError::UnsupportedCodec(e.to_string()),
+    })?;
+    let (rate, channels) = (decoder.sample_rate(), decoder.channels());
+    Ok((rate, channels, decoder.collect()))
+}
+
+/// Trim and normalize decoded samples
+fn prepare(
+    (rate, channels, mut samples): (u32, u16, Vec<f32>),
+    trim: Option<Trim>,
+    normalize_to: Option<f64>,
+) -> Result<SamplesBuffer> {
+    if rate == 0 || channels == 0 {
+        return Err(VaultError::UnsupportedCodec(format!(
+            "Stream of {} channels at {} Hz",
+            channels, rate
+        )));
+    }
+
+    if let Some(trim) = trim {
+        let width = channels as usize;
+        let range = trim.frames(rate, samples.len() / width);
+        samples = samples[range.start * width..range.end * width].to_vec();
+    }
+
+    if let Some(target) = normalize_to
+        && let Some(loudness) = integrated_loudness(&samples, rate, channels)
+    {
+        let gain = 10f64.powf((target - loudness) / 20.0) as f32;
+        samples.iter_mut().for_each(|sample| *sample *= gain);
+    }
+
+    Ok(SamplesBuffer::new(channels, rate, samples))
+}
+
+impl LocalLibrary {
+    /// Decode a sound into a rodio source
+    ///
+    /// A sound with a file is read from the vault; one without, such as a
+    /// remote search result, from its preview if that's an HTTP(S) URL.
+    /// Decoding runs on the background job queue; a play is recorded only
+    /// for sounds read from the vault.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::FileMissing` if the sound's file isn't on disk, or it
+    ///   has neither a file nor a preview to stream
+    /// * `VaultError::UnsupportedCodec` if no decoder reads the file
+    pub async fn rodio_source(&self, sound: &Sound, options: &RodioOptions) -> Result<SamplesBuffer> {
+        let metadata = &sound.metadata;
+        let options = *options;
+
+        let (bytes, local) = if metadata.path.is_some() {
+            self.stage_blob(metadata).await?;
+            if !self.readable_file(metadata)?.exists() {
+                return Err(VaultError::FileMissing(metadata.id.clone()));
+            }
+            (self.sound_bytes(metadata)?, true)
+        } else {
+            match sound.preview_url.as_deref() {
+                Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                    let _permit = self.governor.transfer().await?;
+                    let response = send(reqwest::Client::new().get(url), url).await?;
+                    (response.bytes().await.map_err(|e| remote_error(url, &e))?.to_vec(), false)
+                }
+                _ => return Err(VaultError::FileMissing(metadata.id.clone())),
+            }
+        };
+
+        let source = self.rodio_content(bytes, metadata.trim, &options).await?;
+
+        if local && options.record_play {
+            self.record_play(&metadata.id).await?;
+        }
+        Ok(source)
+    }
+
+    /// Decode the content of a file into a rodio source, without recording
+    /// a play
+    pub(crate) async fn rodio_content(
+        &self,
+        bytes: Vec<u8>,
+        trim: Option<Trim>,
+        options: &RodioOptions,
+    ) -> Result<SamplesBuffer> {
+        let trim = trim.filter(|_| options.apply_trim);
+        let normalize_to = options.normalize_to;
+        self.jobs.run(move || prepare(decode_any(bytes)?, trim, normalize_to)).await
+    }
+}
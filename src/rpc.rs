@@ -0,0 +1,681 @@
+//! Serving a vault to thin clients, with the `server` feature
+//!
+//! [`serve`] answers clients on a TCP or Unix socket, and
+//! [`RemoteVaultClient`] calls a served vault with the same methods as
+//! [`SoundVault`] for searching, reading sounds and collections and
+//! triggering downloads, plus streamed previews and events.
+//!
+//! Messages are bincode frames, each preceded by its length as a 4-byte
+//! big-endian integer. A connection opens with a handshake carrying the
+//! client's [`PROTOCOL_VERSION`] and the shared token, if the server wants
+//! one; the handshake keeps its layout across versions, so mismatched peers
+//! fail with [`VaultError::IncompatibleProtocol`] rather than garbage.
+//! Requests are then answered one at a time. Previews and events are
+//! streamed as chunk frames on a connection of their own.
+//!
+//! # Examples
+//!
+//! ```
+//! use soundvault::rpc::{RemoteVaultClient, serve_with_token};
+//! use soundvault::{AudioFormat, AudioInfo, Collection, SampleFormat, SoundVault, VaultConfig, VaultError, VaultEvent, encode};
+//! use std::sync::Arc;
+//! use tokio::net::TcpListener;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let dir = tempfile::tempdir()?;
+//! let vault = Arc::new(SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?);
+//! let file = dir.path().join("beep.wav");
+//! std::fs::write(&file, encode(&AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16)), &[0.25; 800])?)?;
+//! let id = vault.import_file(&file, None).await?;
+//!
+//! let listener = TcpListener::bind("127.0.0.1:0").await?;
+//! let address = listener.local_addr()?.to_string();
+//! let server = tokio::spawn(serve_with_token(vault.clone(), listener, Some("secret".to_string())));
+//!
+//! assert!(RemoteVaultClient::connect_tcp(&address, Some("guess")).await.is_err());
+//! let client = RemoteVaultClient::connect_tcp(&address, Some("secret")).await?;
+//! assert_eq!(client.vault_id(), vault.vault_id());
+//! assert_eq!(client.get_sound(&id).await?.metadata.duration, 0.1);
+//! assert_eq!(client.search_local("beep", None).await?.len(), 1);
+//! assert!(matches!(client.get_sound("nope").await, Err(VaultError::NotFound(_))));
+//!
+//! let mut events = client.subscribe().await?;
+//! let collection_id = client.add_collection(&Collection::new("Beeps", "")).await?;
+//! client.add_sound_to_collection(&id, &collection_id).await?;
+//! assert_eq!(events.next().await?, Some(VaultEvent::CollectionChanged { collection_id }));
+//!
+//! let preview = client.preview(&id).await?.read_to_end().await?;
+//! assert_eq!(&preview[..4], b"RIFF");
+//! server.abort();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::downloads::QueuedDownload;
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::models::{Collection, CollectionSummary, Sound};
+use crate::source::RemoteSearchResults;
+use crate::vault::SoundVault;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Version of the protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Largest frame accepted, so a confused peer can't make us allocate
+/// gigabytes
+const MAX_FRAME: usize = 16 << 20;
+
+/// Bytes of preview audio sent per chunk
+const PREVIEW_CHUNK: usize = 64 << 10;
+
+/// A connection to a peer, over TCP or a Unix socket
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Socket [`serve`] accepts clients on
+pub enum RpcListener {
+    /// A TCP listener
+    Tcp(TcpListener),
+    /// A Unix socket listener
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl From<TcpListener> for RpcListener {
+    fn from(listener: TcpListener) -> Self {
+        Self::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::UnixListener> for RpcListener {
+    fn from(listener: tokio::net::UnixListener) -> Self {
+        Self::Unix(listener)
+    }
+}
+
+impl RpcListener {
+    async fn accept(&self) -> Result<Box<dyn Connection>> {
+        Ok(match self {
+            Self::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => Box::new(listener.accept().await?.0),
+        })
+    }
+}
+
+/// First frame of a connection, from the client; its layout never changes
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    version: u32,
+    token: Option<String>,
+}
+
+/// Answer to [`Hello`]; its layout never changes
+#[derive(Serialize, Deserialize)]
+struct Welcome {
+    version: u32,
+    vault_id: String,
+    /// Why the connection was refused, if it was
+    refused: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Request {
+    SearchLocal { query: String, tags: Option<Vec<String>> },
+    GetSound { id: String },
+    GetCollection { id: String },
+    AddCollection { collection: Box<Collection> },
+    AddSoundToCollection { sound_id: String, collection_id: String },
+    RemoveSoundFromCollection { sound_id: String, collection_id: String },
+    CollectionsContaining { sound_id: String },
+    SearchRemote { query: String, page_size: u64 },
+    DownloadRemote { source: String, remote_id: String },
+    EnqueueDownload { source: String, remote_id: String, collection_id: Option<String> },
+    ListDownloadQueue,
+    /// Streamed as [`Response::Chunk`]s, then [`Response::End`]
+    Preview { id: String },
+    /// Acknowledged with [`Response::Done`], then streamed as
+    /// [`Response::Event`]s for as long as the connection lasts
+    Subscribe,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Done,
+    Id(String),
+    QueueId(i64),
+    Sound(Box<Sound>),
+    Sounds(Vec<Sound>),
+    Collection(Box<Collection>),
+    Summaries(Vec<CollectionSummary>),
+    RemoteResults(RemoteSearchResults),
+    Downloads(Vec<QueuedDownload>),
+    Chunk(Vec<u8>),
+    /// An event as JSON, since events are internally tagged, which bincode
+    /// can't read back
+    Event(String),
+    End,
+    Error { kind: ErrorKind, message: String },
+}
+
+/// Errors a client tells apart; the others arrive as [`VaultError::Rpc`]
+#[derive(Serialize, Deserialize)]
+enum ErrorKind {
+    NotFound,
+    Locked,
+    InvalidOperation,
+    Other,
+}
+
+impl Response {
+    fn error(error: &VaultError) -> Self {
+        let (kind, message) = match error {
+            VaultError::NotFound(id) => (ErrorKind::NotFound, id.clone()),
+            VaultError::Locked(id) => (ErrorKind::Locked, id.clone()),
+            VaultError::InvalidOperation(message) => (ErrorKind::InvalidOperation, message.clone()),
+            error => (ErrorKind::Other, error.to_string()),
+        };
+        Self::Error { kind, message }
+    }
+
+    /// The error the server answered, if it did
+    fn into_result(self) -> Result<Self> {
+        match self {
+            Self::Error { kind, message } => Err(match kind {
+                ErrorKind::NotFound => VaultError::NotFound(message),
+                ErrorKind::Locked => VaultError::Locked(message),
+                ErrorKind::InvalidOperation => VaultError::InvalidOperation(message),
+                ErrorKind::Other => VaultError::Rpc(message),
+            }),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected() -> VaultError {
+    VaultError::Rpc("Unexpected response from the server".to_string())
+}
+
+async fn write_frame<T: Serialize>(connection: &mut (impl AsyncWrite + Unpin + ?Sized), message: &T) -> Result<()> {
+    let body = bincode::serialize(message).map_err(|e| VaultError::Rpc(format!("Failed to encode message: {}", e)))?;
+    if body.len() > MAX_FRAME {
+        return Err(VaultError::Rpc(format!("Message of {} bytes is too large", body.len())));
+    }
+
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    connection.write_all(&frame).await?;
+    connection.flush().await?;
+    Ok(())
+}
+
+/// Read a frame; `None` if the peer closed the connection between frames
+async fn read_frame<T: DeserializeOwned>(connection: &mut (impl AsyncRead + Unpin + ?Sized)) -> Result<Option<T>> {
+    let length = match connection.read_u32().await {
+        Ok(length) => length as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if length > MAX_FRAME {
+        return Err(VaultError::Rpc(format!("Message of {} bytes is too large", length)));
+    }
+
+    let mut body = vec![0; length];
+    connection.read_exact(&mut body).await?;
+    bincode::deserialize(&body)
+        .map(Some)
+        .map_err(|e| VaultError::Rpc(format!("Failed to decode message: {}", e)))
+}
+
+/// Compare tokens in time independent of where they differ
+fn same_token(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Serve a vault to any client
+///
+/// See [`serve_with_token`].
+pub async fn serve(vault: Arc<SoundVault>, listener: impl Into<RpcListener>) -> Result<()> {
+    serve_with_token(vault, listener, None).await
+}
+
+/// Serve a vault to clients presenting `token`, if given
+///
+/// Each client is answered by a task of its own. Serving goes on until
+/// accepting a connection fails; abort the task running it to stop.
+pub async fn serve_with_token(vault: Arc<SoundVault>, listener: impl Into<RpcListener>, token: Option<String>) -> Result<()> {
+    let listener = listener.into();
+    let token: Option<Arc<str>> = token.map(Into::into);
+    loop {
+        let connection = listener.accept().await?;
+        let vault = vault.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            // A failed connection only concerns its client
+            let _ = handle_connection(&vault, connection, token.as_deref()).await;
+        });
+    }
+}
+
+async fn handle_connection(vault: &SoundVault, mut connection: Box<dyn Connection>, token: Option<&str>) -> Result<()> {
+    let Some(hello) = read_frame::<Hello>(&mut connection).await? else {
+        return Ok(());
+    };
+    let refused = if hello.version != PROTOCOL_VERSION {
+        Some(format!("Protocol version {} is not supported", hello.version))
+    } else {
+        match (token, hello.token.as_deref()) {
+            (Some(expected), Some(given)) if same_token(expected, given) => None,
+            (Some(_), _) => Some("Invalid token".to_string()),
+            (None, _) => None,
+        }
+    };
+    let welcome = Welcome {
+        version: PROTOCOL_VERSION,
+        vault_id: vault.vault_id().to_string(),
+        refused: refused.clone(),
+    };
+    write_frame(&mut connection, &welcome).await?;
+    if refused.is_some() {
+        return Ok(());
+    }
+
+    while let Some(request) = read_frame::<Request>(&mut connection).await? {
+        match request {
+            Request::Preview { id } => stream_preview(vault, &mut connection, &id).await?,
+            Request::Subscribe => return stream_events(vault, &mut connection).await,
+            request => {
+                let response = answer(vault, request).await.unwrap_or_else(|e| Response::error(&e));
+                write_frame(&mut connection, &response).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn answer(vault: &SoundVault, request: Request) -> Result<Response> {
+    Ok(match request {
+        Request::SearchLocal { query, tags } => {
+            let tags: Option<Vec<&str>> = tags.as_ref().map(|tags| tags.iter().map(String::as_str).collect());
+            Response::Sounds(vault.search_local(&query, tags.as_deref()).await?)
+        }
+        Request::GetSound { id } => Response::Sound(Box::new(vault.get_sound(&id).await?)),
+        Request::GetCollection { id } => Response::Collection(Box::new(vault.get_collection(&id).await?)),
+        Request::AddCollection { collection } => Response::Id(vault.add_collection(&collection).await?),
+        Request::AddSoundToCollection { sound_id, collection_id } => {
+            vault.add_sound_to_collection(&sound_id, &collection_id).await?;
+            Response::Done
+        }
+        Request::RemoveSoundFromCollection { sound_id, collection_id } => {
+            vault.remove_sound_from_collection(&sound_id, &collection_id).await?;
+            Response::Done
+        }
+        Request::CollectionsContaining { sound_id } => Response::Summaries(vault.collections_containing(&sound_id).await?),
+        Request::SearchRemote { query, page_size } => {
+            Response::RemoteResults(vault.search_remote(&query, page_size as usize).await?)
+        }
+        Request::DownloadRemote { source, remote_id } => Response::Id(vault.download_remote(&source, &remote_id).await?),
+        Request::EnqueueDownload { source, remote_id, collection_id } => {
+            Response::QueueId(vault.enqueue_download(&source, &remote_id, collection_id.as_deref()).await?)
+        }
+        Request::ListDownloadQueue => Response::Downloads(vault.list_download_queue().await?),
+        Request::Preview { .. } | Request::Subscribe => return Err(unexpected()),
+    })
+}
+
+async fn stream_preview(vault: &SoundVault, connection: &mut Box<dyn Connection>, id: &str) -> Result<()> {
+    let mut file = match vault.generate_preview(id).await {
+        Ok(path) => tokio::fs::File::open(path).await?,
+        Err(e) => return write_frame(connection, &Response::error(&e)).await,
+    };
+
+    let mut chunk = vec![0; PREVIEW_CHUNK];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return write_frame(connection, &Response::End).await;
+        }
+        write_frame(connection, &Response::Chunk(chunk[..read].to_vec())).await?;
+    }
+}
+
+async fn stream_events(vault: &SoundVault, connection: &mut Box<dyn Connection>) -> Result<()> {
+    let mut events = vault.subscribe();
+    write_frame(connection, &Response::Done).await?;
+    loop {
+        match events.recv().await {
+            Ok(event) => write_frame(connection, &Response::Event(serde_json::to_string(&event)?)).await?,
+            // A slow client misses events, and is told how many
+            Err(RecvError::Lagged(missed)) => {
+                write_frame(connection, &Response::Event(serde_json::to_string(&VaultEvent::Lagged { missed })?)).await?
+            }
+            Err(RecvError::Closed) => return write_frame(connection, &Response::End).await,
+        }
+    }
+}
+
+/// Where a client connects to
+enum Endpoint {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+/// Client of a vault served with [`serve`]
+///
+/// Requests share one connection, reopened if it breaks; previews and event
+/// subscriptions each open their own.
+pub struct RemoteVaultClient {
+    endpoint: Endpoint,
+    token: Option<String>,
+    vault_id: String,
+    connection: Mutex<Option<Box<dyn Connection>>>,
+}
+
+impl RemoteVaultClient {
+    /// Connect to a vault served on TCP, e.g. at `"nas.local:7070"`
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::IncompatibleProtocol` if the server speaks another
+    ///   version of the protocol
+    /// * `VaultError::Rpc` if the server refused the token
+    pub async fn connect_tcp(address: &str, token: Option<&str>) -> Result<Self> {
+        Self::connect(Endpoint::Tcp(address.to_string()), token).await
+    }
+
+    /// Connect to a vault served on a Unix socket
+    ///
+    /// # Errors
+    ///
+    /// See [`RemoteVaultClient::connect_tcp`].
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P, token: Option<&str>) -> Result<Self> {
+        Self::connect(Endpoint::Unix(path.as_ref().to_path_buf()), token).await
+    }
+
+    async fn connect(endpoint: Endpoint, token: Option<&str>) -> Result<Self> {
+        let mut client = Self {
+            endpoint,
+            token: token.map(str::to_string),
+            vault_id: String::new(),
+            connection: Mutex::new(None),
+        };
+        let (connection, vault_id) = client.open().await?;
+        client.vault_id = vault_id;
+        client.connection = Mutex::new(Some(connection));
+        Ok(client)
+    }
+
+    /// Open a connection and shake hands, returning the vault's ID
+    async fn open(&self) -> Result<(Box<dyn Connection>, String)> {
+        let mut connection: Box<dyn Connection> = match &self.endpoint {
+            Endpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+        };
+
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            token: self.token.clone(),
+        };
+        write_frame(&mut connection, &hello).await?;
+        let welcome: Welcome = read_frame(&mut connection)
+            .await?
+            .ok_or_else(|| VaultError::Rpc("The server closed the connection".to_string()))?;
+        if welcome.version != PROTOCOL_VERSION {
+            return Err(VaultError::IncompatibleProtocol {
+                server: welcome.version,
+                client: PROTOCOL_VERSION,
+            });
+        }
+        if let Some(reason) = welcome.refused {
+            return Err(VaultError::Rpc(format!("The server refused the connection: {}", reason)));
+        }
+
+        Ok((connection, welcome.vault_id))
+    }
+
+    /// Send a request on the shared connection and wait for the answer
+    async fn call(&self, request: Request) -> Result<Response> {
+        let mut shared = self.connection.lock().await;
+        let connection = match shared.take() {
+            Some(connection) => connection,
+            None => self.open().await?.0,
+        };
+        let connection = shared.insert(connection);
+
+        let result = async {
+            write_frame(connection, &request).await?;
+            read_frame::<Response>(connection)
+                .await?
+                .ok_or_else(|| VaultError::Rpc("The server closed the connection".to_string()))
+        }
+        .await;
+        // The next request reconnects
+        if result.is_err() {
+            *shared = None;
+        }
+        result?.into_result()
+    }
+
+    /// Open a connection of its own for a streamed request
+    async fn open_stream(&self, request: Request) -> Result<Box<dyn Connection>> {
+        let (mut connection, _) = self.open().await?;
+        write_frame(&mut connection, &request).await?;
+        Ok(connection)
+    }
+
+    /// UUID identifying the served vault
+    pub fn vault_id(&self) -> &str {
+        &self.vault_id
+    }
+
+    /// See [`SoundVault::search_local`]
+    pub async fn search_local(&self, query: &str, tags: Option<&[&str]>) -> Result<Vec<Sound>> {
+        let tags = tags.map(|tags| tags.iter().map(|tag| tag.to_string()).collect());
+        match self.call(Request::SearchLocal { query: query.to_string(), tags }).await? {
+            Response::Sounds(sounds) => Ok(sounds),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::get_sound`]
+    pub async fn get_sound(&self, id: &str) -> Result<Sound> {
+        match self.call(Request::GetSound { id: id.to_string() }).await? {
+            Response::Sound(sound) => Ok(*sound),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::get_collection`]
+    pub async fn get_collection(&self, collection_id: &str) -> Result<Collection> {
+        match self.call(Request::GetCollection { id: collection_id.to_string() }).await? {
+            Response::Collection(collection) => Ok(*collection),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::add_collection`]
+    pub async fn add_collection(&self, collection: &Collection) -> Result<String> {
+        match self.call(Request::AddCollection { collection: Box::new(collection.clone()) }).await? {
+            Response::Id(id) => Ok(id),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::add_sound_to_collection`]
+    pub async fn add_sound_to_collection(&self, sound_id: &str, collection_id: &str) -> Result<()> {
+        let request = Request::AddSoundToCollection {
+            sound_id: sound_id.to_string(),
+            collection_id: collection_id.to_string(),
+        };
+        match self.call(request).await? {
+            Response::Done => Ok(()),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::remove_sound_from_collection`]
+    pub async fn remove_sound_from_collection(&self, sound_id: &str, collection_id: &str) -> Result<()> {
+        let request = Request::RemoveSoundFromCollection {
+            sound_id: sound_id.to_string(),
+            collection_id: collection_id.to_string(),
+        };
+        match self.call(request).await? {
+            Response::Done => Ok(()),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::collections_containing`]
+    pub async fn collections_containing(&self, sound_id: &str) -> Result<Vec<CollectionSummary>> {
+        match self.call(Request::CollectionsContaining { sound_id: sound_id.to_string() }).await? {
+            Response::Summaries(summaries) => Ok(summaries),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::search_remote`]
+    pub async fn search_remote(&self, query: &str, page_size: usize) -> Result<RemoteSearchResults> {
+        let request = Request::SearchRemote {
+            query: query.to_string(),
+            page_size: page_size as u64,
+        };
+        match self.call(request).await? {
+            Response::RemoteResults(results) => Ok(results),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::download_remote`]; the server downloads the sound
+    pub async fn download_remote(&self, source: &str, remote_id: &str) -> Result<String> {
+        let request = Request::DownloadRemote {
+            source: source.to_string(),
+            remote_id: remote_id.to_string(),
+        };
+        match self.call(request).await? {
+            Response::Id(id) => Ok(id),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::enqueue_download`]
+    pub async fn enqueue_download(&self, source: &str, remote_id: &str, collection_id: Option<&str>) -> Result<i64> {
+        let request = Request::EnqueueDownload {
+            source: source.to_string(),
+            remote_id: remote_id.to_string(),
+            collection_id: collection_id.map(str::to_string),
+        };
+        match self.call(request).await? {
+            Response::QueueId(id) => Ok(id),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// See [`SoundVault::list_download_queue`]
+    pub async fn list_download_queue(&self) -> Result<Vec<QueuedDownload>> {
+        match self.call(Request::ListDownloadQueue).await? {
+            Response::Downloads(downloads) => Ok(downloads),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Stream the WAV preview of a sound, generated by the server with
+    /// [`SoundVault::generate_preview`]
+    ///
+    /// An error generating the preview, e.g. an unknown sound, is returned
+    /// by the first [`PreviewStream::next_chunk`].
+    pub async fn preview(&self, id: &str) -> Result<PreviewStream> {
+        let connection = self.open_stream(Request::Preview { id: id.to_string() }).await?;
+        Ok(PreviewStream {
+            connection,
+            done: false,
+        })
+    }
+
+    /// Receive the served vault's events from now on, like
+    /// [`SoundVault::subscribe`]
+    pub async fn subscribe(&self) -> Result<RemoteEvents> {
+        let mut connection = self.open_stream(Request::Subscribe).await?;
+        match read_frame::<Response>(&mut connection).await? {
+            Some(Response::Done) => Ok(RemoteEvents { connection }),
+            Some(response) => response.into_result().and(Err(unexpected())),
+            None => Err(VaultError::Rpc("The server closed the connection".to_string())),
+        }
+    }
+}
+
+/// Preview audio received from [`RemoteVaultClient::preview`]
+pub struct PreviewStream {
+    connection: Box<dyn Connection>,
+    done: bool,
+}
+
+impl PreviewStream {
+    /// The next bytes of the WAV file; `None` once it's all received
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let response = read_frame::<Response>(&mut self.connection)
+            .await?
+            .ok_or_else(|| VaultError::Rpc("The server closed the connection".to_string()))?;
+        match response.into_result()? {
+            Response::Chunk(chunk) => Ok(Some(chunk)),
+            Response::End => {
+                self.done = true;
+                Ok(None)
+            }
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// The whole WAV file
+    pub async fn read_to_end(mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Events received from [`RemoteVaultClient::subscribe`]
+///
+/// Like a local subscriber, one falling too far behind misses the oldest
+/// events.
+pub struct RemoteEvents {
+    connection: Box<dyn Connection>,
+}
+
+impl RemoteEvents {
+    /// Wait for the next event; `None` once the server is gone
+    pub async fn next(&mut self) -> Result<Option<VaultEvent>> {
+        match read_frame::<Response>(&mut self.connection).await? {
+            Some(Response::Event(event)) => Ok(Some(serde_json::from_str(&event)?)),
+            Some(Response::End) | None => Ok(None),
+            Some(response) => response.into_result().and(Err(unexpected())),
+        }
+    }
+}
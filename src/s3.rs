@@ -0,0 +1,267 @@
+//! Files of sounds kept in an S3-compatible bucket
+//!
+//! Requests are signed with AWS Signature Version 4 and address the bucket
+//! by path, `<endpoint>/<bucket>/<key>`, which S3 itself and the common
+//! self-hosted implementations accept.
+
+use crate::blob::{BlobFuture, BlobReader, BlobStore};
+use crate::error::{Result, VaultError};
+use crate::hmac::{hex, hmac};
+use chrono::{DateTime, Utc};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Payload hash of requests whose body isn't signed
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Longest lifetime S3 accepts for a presigned URL
+const MAX_PRESIGNED_LIFETIME: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Bytes of a fetched file buffered between the connection and the reader
+const STREAM_BUFFER: usize = 64 * 1024;
+
+/// Where an [`S3Store`] keeps its files, and the credentials to reach them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Config {
+    /// Name of the store, recorded with the sounds it holds
+    #[serde(default = "default_store_name")]
+    pub name: String,
+
+    /// Base URL of the service, e.g. `https://s3.eu-west-3.amazonaws.com`
+    /// or `http://localhost:9000`
+    pub endpoint: String,
+
+    /// Bucket holding the files
+    pub bucket: String,
+
+    /// Region the bucket is in, e.g. `eu-west-3`
+    pub region: String,
+
+    /// Access key ID
+    pub access_key_id: String,
+
+    /// Secret access key
+    pub secret_access_key: String,
+}
+
+fn default_store_name() -> String {
+    "s3".to_string()
+}
+
+/// Files kept in an S3-compatible bucket, under their key
+///
+/// Sounds it holds are previewed from presigned URLs, so players stream them
+/// from the bucket without credentials.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{BlobStore, S3Config, S3Store};
+/// use std::time::Duration;
+///
+/// let store = S3Store::new(S3Config {
+///     name: "s3".to_string(),
+///     endpoint: "http://localhost:9000".to_string(),
+///     bucket: "sounds".to_string(),
+///     region: "us-east-1".to_string(),
+///     access_key_id: "key".to_string(),
+///     secret_access_key: "secret".to_string(),
+/// });
+/// let url = store.presigned_url("0b1c/rain.wav", Duration::from_secs(60)).unwrap();
+/// assert!(url.starts_with("http://localhost:9000/sounds/0b1c/rain.wav?X-Amz-Algorithm=AWS4-HMAC-SHA256&"));
+/// assert!(url.contains("&X-Amz-Expires=60&") && url.contains("&X-Amz-Signature="));
+/// ```
+#[derive(Debug, Clone)]
+pub struct S3Store {
+    config: S3Config,
+    http: reqwest::Client,
+}
+
+impl S3Store {
+    /// Store files in the bucket of `config`
+    pub fn new(config: S3Config) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Host and port of the endpoint, as signed
+    fn host(&self) -> &str {
+        let endpoint = self.config.endpoint.split_once("://").map_or(&*self.config.endpoint, |(_, rest)| rest);
+        endpoint.split('/').next().unwrap_or_default()
+    }
+
+    /// Path of the file under `key`, encoded
+    fn object_path(&self, key: &str) -> String {
+        let mut path = format!("/{}", encode(&self.config.bucket));
+        for segment in key.split('/') {
+            path.push('/');
+            path.push_str(&encode(segment));
+        }
+        path
+    }
+
+    /// Scope of the signatures made at `now`
+    fn scope(&self, now: &DateTime<Utc>) -> String {
+        format!("{}/{}/s3/aws4_request", now.format("%Y%m%d"), self.config.region)
+    }
+
+    /// Signature of a request
+    fn signature(&self, now: &DateTime<Utc>, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            now.format("%Y%m%dT%H%M%SZ"),
+            self.scope(now),
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let mut key = format!("AWS4{}", self.config.secret_access_key).into_bytes();
+        for part in [&*now.format("%Y%m%d").to_string(), &self.config.region, "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes()).to_vec();
+        }
+        hex(&hmac(&key, string_to_sign.as_bytes()))
+    }
+
+    /// A request for the file under `key`, signed in its headers
+    fn request(&self, method: Method, key: &str, payload_hash: &str) -> RequestBuilder {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let path = self.object_path(key);
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method,
+            path,
+            self.host(),
+            payload_hash,
+            amz_date,
+            payload_hash
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.config.access_key_id,
+            self.scope(&now),
+            self.signature(&now, &canonical_request)
+        );
+
+        self.http
+            .request(method, format!("{}{}", self.config.endpoint.trim_end_matches('/'), path))
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+    }
+
+    /// Send a request with an empty body, failing on error statuses other
+    /// than 404
+    async fn send(&self, method: Method, key: &str) -> Result<Response> {
+        let empty = format!("{:x}", Sha256::digest([]));
+        let response = self.request(method, key, &empty).send().await.map_err(std::io::Error::other)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(response);
+        }
+        Ok(response.error_for_status().map_err(std::io::Error::other)?)
+    }
+}
+
+impl BlobStore for S3Store {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn put<'a>(&'a self, key: &'a str, source: &'a Path) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let body = tokio::fs::read(source).await?;
+            let payload_hash = format!("{:x}", Sha256::digest(&body));
+            self.request(Method::PUT, key, &payload_hash)
+                .body(body)
+                .send()
+                .await
+                .and_then(Response::error_for_status)
+                .map_err(std::io::Error::other)?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BlobFuture<'a, BlobReader> {
+        Box::pin(async move {
+            let mut response = self.send(Method::GET, key).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(VaultError::NotFound(format!("{} holds no {}", self.config.name, key)));
+            }
+
+            // The body is copied into a pipe as it arrives; a connection
+            // lost midway ends the stream early
+            let (reader, mut writer) = tokio::io::duplex(STREAM_BUFFER);
+            tokio::spawn(async move {
+                while let Ok(Some(chunk)) = response.chunk().await {
+                    if writer.write_all(&chunk).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(Box::pin(reader) as BlobReader)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            self.send(Method::DELETE, key).await?;
+            Ok(())
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BlobFuture<'a, bool> {
+        Box::pin(async move { Ok(self.send(Method::HEAD, key).await?.status() != StatusCode::NOT_FOUND) })
+    }
+
+    fn size<'a>(&'a self, key: &'a str) -> BlobFuture<'a, u64> {
+        Box::pin(async move {
+            let response = self.send(Method::HEAD, key).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(VaultError::NotFound(format!("{} holds no {}", self.config.name, key)));
+            }
+            response
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok()?.parse().ok())
+                .ok_or_else(|| VaultError::FileSystem(format!("{} gave no size for {}", self.config.name, key)))
+        })
+    }
+
+    fn presigned_url(&self, key: &str, lifetime: Duration) -> Option<String> {
+        let now = Utc::now();
+        let path = self.object_path(key);
+        let credential = format!("{}/{}", self.config.access_key_id, self.scope(&now));
+        // Parameters in the order they sort in
+        let query = format!(
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}&X-Amz-Date={}&X-Amz-Expires={}&X-Amz-SignedHeaders=host",
+            encode(&credential),
+            now.format("%Y%m%dT%H%M%SZ"),
+            lifetime.min(MAX_PRESIGNED_LIFETIME).as_secs().max(1)
+        );
+        let canonical_request = format!("GET\n{}\n{}\nhost:{}\n\nhost\n{}", path, query, self.host(), UNSIGNED_PAYLOAD);
+
+        Some(format!(
+            "{}{}?{}&X-Amz-Signature={}",
+            self.config.endpoint.trim_end_matches('/'),
+            path,
+            query,
+            self.signature(&now, &canonical_request)
+        ))
+    }
+}
+
+/// Percent-encode all but the characters SigV4 leaves alone
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
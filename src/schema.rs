@@ -0,0 +1,205 @@
+//! Versioned envelopes for the files the vault writes and reads back
+//!
+//! Files such as manifests are written as an [`Envelope`] naming their
+//! schema and its version, e.g.
+//! `{"schema": "soundvault.manifest", "version": 2, "generator": "soundvault 0.1.0", "data": ...}`,
+//! rather than as bare model types, so a change of the types doesn't
+//! silently break older files. Every schema has an upgrade function from
+//! each prior version to the next; reading a file upgrades it step by step
+//! to the current version. Files from before envelopes existed are read as
+//! version 1. A file written by a later version of the library is refused
+//! with [`VaultError::UnsupportedVersion`], naming the library that wrote
+//! it.
+//!
+//! # Examples
+//!
+//! Fixtures of each version of each schema are checked in under
+//! `fixtures/schema`; each must still read, and read the same as the
+//! current version.
+//!
+//! ```
+//! use soundvault::{DawTrack, Envelope, Manifest, VaultError, ZipManifestEntry, current_version};
+//!
+//! let fixture = |name: &str| {
+//!     std::fs::read_to_string(format!("{}/fixtures/schema/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+//! };
+//!
+//! let current = Manifest::parse(&fixture("manifest-v2.json")).unwrap();
+//! for old in ["manifest-v1.json", "manifest-v1.toml", "manifest-v2.toml"] {
+//!     assert_eq!(Manifest::parse(&fixture(old)).unwrap(), current);
+//! }
+//! assert_eq!(current.sounds["0b7c8a54"].format, "wav");
+//!
+//! // Writing and reading back gives the same manifest
+//! let written = current.to_string(soundvault::ManifestFormat::Json).unwrap();
+//! assert!(written.contains(r#""schema": "soundvault.manifest""#));
+//! assert_eq!(Manifest::parse(&written).unwrap(), current);
+//!
+//! let tracks: Envelope<Vec<DawTrack>> = Envelope::parse("soundvault.daw-tracks", &fixture("daw-tracks-v1.json")).unwrap();
+//! assert_eq!(tracks.data[0].file, "Media/Rain.wav");
+//!
+//! let zipped: Envelope<Vec<ZipManifestEntry>> = Envelope::parse("soundvault.zip-manifest", &fixture("zip-manifest-v1.json")).unwrap();
+//! assert_eq!(zipped.data[0].file, "rain.wav");
+//!
+//! // Files from a later library are refused, naming it
+//! match Manifest::parse(&fixture("manifest-future.json")) {
+//!     Err(VaultError::UnsupportedVersion { version: 99, required, .. }) => assert_eq!(required, "soundvault 9.0.0"),
+//!     other => panic!("{:?}", other),
+//! }
+//! assert_eq!(current_version("soundvault.manifest").unwrap(), 2);
+//! ```
+
+use crate::error::{Result, VaultError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Schema of manifests
+pub(crate) const MANIFEST_SCHEMA: &str = "soundvault.manifest";
+
+/// Schema of metadata dumps, whose header carries the version
+pub(crate) const DUMP_SCHEMA: &str = "soundvault.dump";
+
+/// Schema of the track lists of exported DAW sessions
+pub(crate) const DAW_TRACKS_SCHEMA: &str = "soundvault.daw-tracks";
+
+/// Schema of the manifests of zip archives of sounds
+pub(crate) const ZIP_MANIFEST_SCHEMA: &str = "soundvault.zip-manifest";
+
+/// Upgrade of a schema's data from one version to the next
+type Upgrade = fn(Value) -> Result<Value>;
+
+/// Upgrades of each schema, the first from version 1 to 2; a schema's
+/// current version is one more than its number of upgrades
+const UPGRADES: &[(&str, &[Upgrade])] = &[
+    (MANIFEST_SCHEMA, &[manifest_v1_to_v2]),
+    (DUMP_SCHEMA, &[]),
+    (DAW_TRACKS_SCHEMA, &[]),
+    (ZIP_MANIFEST_SCHEMA, &[]),
+];
+
+/// Name of this library, recorded in the files it writes
+pub(crate) fn generator() -> String {
+    format!("soundvault {}", crate::VERSION)
+}
+
+fn upgrades(schema: &str) -> Result<&'static [Upgrade]> {
+    UPGRADES
+        .iter()
+        .find(|(name, _)| *name == schema)
+        .map(|(_, upgrades)| *upgrades)
+        .ok_or_else(|| VaultError::InvalidOperation(format!("Unknown schema: {}", schema)))
+}
+
+/// Version of `schema` this library writes
+pub fn current_version(schema: &str) -> Result<u32> {
+    Ok(upgrades(schema)?.len() as u32 + 1)
+}
+
+/// Check that this library reads `version` of `schema`
+///
+/// # Errors
+///
+/// * `VaultError::UnsupportedVersion` if the version is later than the
+///   current one
+/// * `VaultError::InvalidOperation` if it's 0
+pub(crate) fn check_version(schema: &str, version: u32, generator: Option<&str>) -> Result<()> {
+    let supported = current_version(schema)?;
+    if version == 0 {
+        return Err(VaultError::InvalidOperation(format!("Invalid {} version: 0", schema)));
+    }
+    if version > supported {
+        return Err(VaultError::UnsupportedVersion {
+            schema: schema.to_string(),
+            version,
+            supported,
+            required: generator.unwrap_or("a later soundvault").to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Data with the name and version of its schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    /// Name of the schema, e.g. `soundvault.manifest`
+    pub schema: String,
+
+    /// Version of the schema the data follows
+    pub version: u32,
+
+    /// Library that wrote the data, e.g. `soundvault 0.1.0`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+
+    /// The data
+    pub data: T,
+}
+
+impl<T: Serialize> Envelope<T> {
+    /// Wrap data in the current version of `schema`
+    pub fn wrap(schema: &str, data: T) -> Result<Self> {
+        Ok(Self {
+            schema: schema.to_string(),
+            version: current_version(schema)?,
+            generator: Some(generator()),
+            data,
+        })
+    }
+}
+
+impl<T: DeserializeOwned> Envelope<T> {
+    /// Read data of `schema`, upgrading it to the current version
+    ///
+    /// A value without an envelope is taken for version 1 of the schema.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::UnsupportedVersion` if it was written by a later
+    ///   version of the library
+    /// * `VaultError::InvalidOperation` if it's another schema
+    pub fn open(schema: &str, value: Value) -> Result<Self> {
+        let mut envelope = match value {
+            Value::Object(ref object) if object.contains_key("schema") => serde_json::from_value::<Envelope<Value>>(value)?,
+            data => Envelope {
+                schema: schema.to_string(),
+                version: 1,
+                generator: None,
+                data,
+            },
+        };
+        if envelope.schema != schema {
+            return Err(VaultError::InvalidOperation(format!(
+                "Expected a {} file, got {}",
+                schema, envelope.schema
+            )));
+        }
+        check_version(schema, envelope.version, envelope.generator.as_deref())?;
+
+        let upgrades = upgrades(schema)?;
+        while let Some(upgrade) = upgrades.get(envelope.version as usize - 1) {
+            envelope.data = upgrade(envelope.data)?;
+            envelope.version += 1;
+        }
+
+        Ok(Envelope {
+            schema: envelope.schema,
+            version: envelope.version,
+            generator: envelope.generator,
+            data: serde_json::from_value(envelope.data)?,
+        })
+    }
+
+    /// Parse JSON data of `schema`; see [`Envelope::open`]
+    pub fn parse(schema: &str, json: &str) -> Result<Self> {
+        Self::open(schema, serde_json::from_str(json)?)
+    }
+}
+
+/// Manifests lost their inline version to the envelope
+fn manifest_v1_to_v2(mut data: Value) -> Result<Value> {
+    if let Value::Object(object) = &mut data {
+        object.remove("version");
+    }
+    Ok(data)
+}
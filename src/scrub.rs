@@ -0,0 +1,187 @@
+//! Re-hashing stored files to catch bit rot
+//!
+//! Each run checks the sounds verified longest ago against the hash recorded
+//! when they were stored, flagging those that no longer match. The background
+//! scrubber runs one write batch per interval and waits while imports or
+//! downloads are under way.
+
+use crate::error::Result;
+use crate::events::VaultEvent;
+use crate::local::{LocalLibrary, hash_file};
+use crate::pack::read_packed;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// Outcome of a scrub run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubReport {
+    /// Number of sounds whose file was hashed
+    pub checked: u64,
+
+    /// IDs of the sounds whose file no longer matches its hash
+    pub corrupt: Vec<String>,
+
+    /// IDs of the sounds whose file is missing
+    pub missing: Vec<String>,
+}
+
+/// Marks a foreground operation under way until dropped, so the background
+/// scrubber waits for it
+pub(crate) struct Foreground<'a>(&'a LocalLibrary);
+
+impl Drop for Foreground<'_> {
+    fn drop(&mut self) {
+        if self.0.foreground.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.foreground_idle.notify_waiters();
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Mark a foreground operation, e.g. an import, as under way
+    pub(crate) fn foreground(&self) -> Foreground<'_> {
+        self.foreground.fetch_add(1, Ordering::SeqCst);
+        Foreground(self)
+    }
+
+    /// Wait until no foreground operation is under way
+    async fn foreground_idle(&self) {
+        loop {
+            let idle = self.foreground_idle.notified();
+            if self.foreground.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Re-hash the files of the sounds verified longest ago
+    ///
+    /// Sounds whose file no longer matches its hash are flagged corrupt and
+    /// a [`VaultEvent::SoundCorrupted`] is sent the first time; a flagged
+    /// sound whose file matches again, e.g. after restoring it, is cleared.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Most sounds checked
+    /// * `yield_to_foreground` - Wait before each sound while imports or
+    ///   downloads are under way
+    pub(crate) async fn scrub(&self, limit: u64, yield_to_foreground: bool) -> Result<ScrubReport> {
+        let rows: Vec<(String, String, Option<String>, bool)> = sqlx::query_as(
+            &self.sql(r#"
+                SELECT id, hash, archive_hash, corrupt FROM sounds
+                WHERE hash IS NOT NULL AND path IS NOT NULL
+                ORDER BY last_verified_at IS NOT NULL, last_verified_at, id
+                LIMIT ?
+            "#),
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.reader)
+        .await?;
+
+        let mut report = ScrubReport::default();
+        for (id, hash, archive_hash, was_corrupt) in rows {
+            if yield_to_foreground {
+                self.foreground_idle().await;
+            }
+
+            // Archived files are checked against the hash of what's stored
+            let metadata = self.get_sound(&id).await?.metadata;
+            let expected = archive_hash.unwrap_or(hash);
+            let path = self.readable_file(&metadata)?;
+            let corrupt = if metadata.storage_backend.is_some() {
+                // Other stores answer for their files' content; only their
+                // presence is checked, not fetching every file
+                if !self.blob_exists(&metadata).await {
+                    report.missing.push(id.clone());
+                }
+                None
+            } else if metadata.packed {
+                // A damaged entry is as corrupt as a file that changed
+                let entry = metadata.path.clone().unwrap_or_default();
+                match self.jobs.run(move || read_packed(&entry)).await {
+                    Ok(Some(bytes)) => {
+                        report.checked += 1;
+                        Some(format!("{:x}", Sha256::digest(&bytes)) != expected)
+                    }
+                    Ok(None) => {
+                        report.missing.push(id.clone());
+                        None
+                    }
+                    Err(_) => {
+                        report.checked += 1;
+                        Some(true)
+                    }
+                }
+            } else if path.exists() {
+                let actual = self.jobs.run(move || hash_file(&path)).await?;
+                report.checked += 1;
+                Some(actual != expected)
+            } else {
+                report.missing.push(id.clone());
+                None
+            };
+
+            sqlx::query(&self.sql("UPDATE sounds SET last_verified_at = ?, corrupt = COALESCE(?, corrupt) WHERE id = ?"))
+                .bind(Utc::now())
+                .bind(corrupt)
+                .bind(&id)
+                .execute(&self.db)
+                .await?;
+            if corrupt == Some(true) {
+                if !was_corrupt {
+                    self.emit(VaultEvent::SoundCorrupted { sound_id: id.clone() });
+                }
+                report.corrupt.push(id);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// IDs of the sounds flagged corrupt by the last scrub of their file
+    pub async fn list_corrupt_sounds(&self) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE corrupt ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?)
+    }
+}
+
+/// Background task scrubbing a batch of sounds per interval
+pub(crate) struct Scrubber {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Scrubber {
+    /// Start scrubbing every `interval`, the first time one interval from
+    /// now; `None` never scrubs
+    pub(crate) fn start(local: &Arc<LocalLibrary>, interval: Option<Duration>) -> Self {
+        let task = interval.filter(|interval| !interval.is_zero()).map(|interval| {
+            let local = local.clone();
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval_at(Instant::now() + interval, interval);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    ticks.tick().await;
+                    // A failed batch is retried at the next tick
+                    let _ = local.scrub(local.governor.write_batch() as u64, true).await;
+                }
+            })
+        });
+
+        Self { task: Mutex::new(task) }
+    }
+
+    /// Stop scrubbing; a batch under way is abandoned
+    pub(crate) fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
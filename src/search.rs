@@ -0,0 +1,430 @@
+//! Query strings typed into a search box, read into a [`SoundFilter`]
+
+use crate::duration::parse_duration;
+use crate::error::{Result, VaultError};
+use crate::fuzzy::levenshtein;
+use crate::query::SoundFilter;
+use std::str::FromStr;
+
+/// Scopes a term may start with, before its colon
+const SCOPES: [&str; 8] = ["tag", "col", "author", "license", "format", "dur", "bpm", "rating"];
+
+/// Prefix of the scopes naming a custom metadata key
+const CUSTOM_PREFIX: &str = "custom.";
+
+/// Descriptor compared by `bpm:` terms
+const BPM: &str = "bpm";
+
+/// Most edits from a scope for which an unknown one is taken for a typo
+const MAX_TYPO: u32 = 2;
+
+/// A term of a query string
+struct Term {
+    /// Byte offset of the term, its leading `-` included
+    start: usize,
+    /// Byte offset of the scope, if any, else of the value
+    scope_start: usize,
+    /// Byte offset of the value
+    value_start: usize,
+    /// Byte offset of the end of the term
+    end: usize,
+    /// The term started with `-`
+    negated: bool,
+    /// Scope before the colon, as written
+    scope: Option<String>,
+    /// Value, unquoted and unescaped
+    value: String,
+    /// The value was quoted
+    quoted: bool,
+}
+
+impl FromStr for SoundFilter {
+    type Err = VaultError;
+
+    fn from_str(query: &str) -> Result<Self> {
+        Self::parse(query)
+    }
+}
+
+impl SoundFilter {
+    /// Parse a query string, as typed into a search box
+    ///
+    /// Terms are separated by spaces and must all match:
+    ///
+    /// * Bare words and `"quoted phrases"` are matched against names and
+    ///   descriptions, joined into one [`text`](Self::text)
+    /// * `tag:kick` matches a tag, `col:"Drum Kit"` a collection by name
+    /// * `author:`, `license:`, `format:` and `custom.<key>:` match a field
+    ///   exactly, as [`fields`](Self::fields) do; `author:""` matches
+    ///   sounds without one
+    /// * `dur:`, `bpm:` and `rating:` compare numbers with `<`, `<=`, `>`,
+    ///   `>=` or `=`, the default; durations are written as
+    ///   [`parse_duration`](crate::parse_duration) reads them, and bounds
+    ///   of durations and tempos are inclusive
+    /// * A leading `-` excludes the sounds a term matches
+    ///
+    /// Quoted values may hold spaces, with `\"` and `\\` escapes. A word
+    /// with a colon that isn't near any scope, such as `12:30`, is a bare
+    /// word.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidQuery` with the position of the problem, and
+    ///   the query as it was probably meant if that can be guessed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundFilter, VaultError};
+    ///
+    /// let filter = SoundFilter::parse(r#"tag:kick dur:<1 rating:>=4 col:"Drum Kit" -tag:loop punchy"#).unwrap();
+    /// assert_eq!(filter.tags, ["kick"]);
+    /// assert_eq!((filter.max_duration, filter.min_rating), (Some(1.0), Some(4)));
+    /// assert_eq!(filter.collection_name.as_deref(), Some("Drum Kit"));
+    /// assert_eq!(filter.exclude[0].tags, ["loop"]);
+    /// assert_eq!(filter.text.as_deref(), Some("punchy"));
+    ///
+    /// // Saved searches display back as text
+    /// let text = filter.to_query_string();
+    /// assert_eq!(text, r#"punchy tag:kick col:"Drum Kit" dur:<=1 rating:>=4 -tag:loop"#);
+    /// assert_eq!(SoundFilter::parse(&text).unwrap(), filter);
+    ///
+    /// match SoundFilter::parse("tga:kick dur:<1") {
+    ///     Err(VaultError::InvalidQuery { position, suggestion, .. }) => {
+    ///         assert_eq!(position, 0);
+    ///         assert_eq!(suggestion.as_deref(), Some("tag:kick dur:<1"));
+    ///     }
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    pub fn parse(query: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        for term in terms(query)? {
+            if term.negated {
+                let mut excluded = Self::default();
+                excluded.apply_term(query, term)?;
+                filter.exclude.push(excluded);
+            } else {
+                filter.apply_term(query, term)?;
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Write the filter as a query string [`parse`](Self::parse) reads back
+    ///
+    /// [`license`](Self::license) is written as a `license:` field.
+    /// Conditions the syntax can't express are left out: the collection
+    /// and group IDs, the language, tag descendants, fuzziness, expiration,
+    /// descriptors other than `bpm`, custom keys with spaces, quotes or
+    /// colons, and exclusions of more than one term.
+    pub fn to_query_string(&self) -> String {
+        let mut terms = self.terms();
+        for excluded in &self.exclude {
+            if let [term] = &excluded.terms()[..] {
+                terms.push(format!("-{}", term));
+            }
+        }
+        terms.join(" ")
+    }
+
+    /// Terms of the conditions the syntax expresses, but exclusions
+    fn terms(&self) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(text) = self.text.as_deref().filter(|text| !text.is_empty()) {
+            let plain = !text.starts_with('-') && !text.contains(':') && is_plain(text);
+            terms.push(if plain { text.to_string() } else { quote(text) });
+        }
+        for tag in &self.tags {
+            terms.push(format!("tag:{}", quote_value(tag)));
+        }
+        if let Some(name) = &self.collection_name {
+            terms.push(format!("col:{}", quote_value(name)));
+        }
+        for (field, value) in &self.fields {
+            let value = value.as_deref().map_or_else(|| "\"\"".to_string(), quote_value);
+            match field.as_str() {
+                "author" | "license" | "format" => terms.push(format!("{}:{}", field, value)),
+                key if is_plain(key) && !key.contains(':') => {
+                    terms.push(format!("{}{}:{}", CUSTOM_PREFIX, key, value))
+                }
+                _ => {}
+            }
+        }
+        if let Some(license) = self.license.as_deref().filter(|_| !self.fields.contains_key("license")) {
+            terms.push(format!("license:{}", quote_value(license)));
+        }
+        push_range(&mut terms, "dur", self.min_duration, self.max_duration);
+        if let Some(bpm) = self.descriptors.get(BPM) {
+            push_range(&mut terms, "bpm", bpm.min, bpm.max);
+        }
+        push_range(&mut terms, "rating", self.min_rating, self.max_rating);
+        terms
+    }
+
+    /// Add the condition of a term, without its negation
+    fn apply_term(&mut self, query: &str, term: Term) -> Result<()> {
+        let Some(scope) = scope_of(query, &term)? else {
+            let word = match &term.scope {
+                Some(scope) => format!("{}:{}", scope, term.value),
+                None => term.value,
+            };
+            self.text = Some(match self.text.take() {
+                Some(text) => format!("{} {}", text, word),
+                None => word,
+            });
+            return Ok(());
+        };
+
+        let field = scope
+            .strip_prefix(CUSTOM_PREFIX)
+            .or(["author", "license", "format"].contains(&scope.as_str()).then_some(scope.as_str()));
+        if term.value.is_empty() && !(term.quoted && field.is_some()) {
+            return Err(invalid(query, term.value_start, format!("`{}:` needs a value", scope), None));
+        }
+        let twice = || {
+            invalid(
+                query,
+                term.start,
+                format!("`{}:` is given twice", scope),
+                Some(format!("{} {}", query[..term.start].trim_end(), query[term.end..].trim_start()).trim().to_string()),
+            )
+        };
+
+        if let Some(field) = field {
+            let value = (!term.value.is_empty()).then(|| term.value.clone());
+            if self.fields.insert(field.to_string(), value).is_some() {
+                return Err(twice());
+            }
+            return Ok(());
+        }
+        let (op, number) = comparison(&term.value);
+        let bad = |example: &str| {
+            invalid(
+                query,
+                term.value_start + op.len(),
+                format!("Expected a number after `{}:{}`, e.g. `{}`", scope, op, example),
+                None,
+            )
+        };
+        let fresh = match scope.as_str() {
+            "tag" => {
+                self.tags.push(term.value.clone());
+                true
+            }
+            "col" => set_once(&mut self.collection_name, Some(term.value.clone())),
+            "dur" => {
+                let seconds = parse_duration(number).map_err(|_| bad("dur:<1.5"))?;
+                set_bounds(&mut self.min_duration, &mut self.max_duration, op, seconds)
+            }
+            "bpm" => {
+                let bpm: f64 = number.parse().ok().filter(|bpm: &f64| bpm.is_finite()).ok_or_else(|| bad("bpm:>=120"))?;
+                let range = self.descriptors.entry(BPM.to_string()).or_default();
+                set_bounds(&mut range.min, &mut range.max, op, bpm)
+            }
+            _ => {
+                let rating: u8 = number.parse().map_err(|_| bad("rating:>=4"))?;
+                // Ratings are whole, so strict bounds move to the next one
+                let rating = match op {
+                    ">" => rating.checked_add(1),
+                    "<" => rating.checked_sub(1),
+                    _ => Some(rating),
+                }
+                .ok_or_else(|| invalid(query, term.value_start, format!("No rating is {}{}", op, number), None))?;
+                set_bounds(&mut self.min_rating, &mut self.max_rating, op, rating)
+            }
+        };
+        if !fresh {
+            return Err(twice());
+        }
+
+        Ok(())
+    }
+}
+
+/// Split a query string into terms
+fn terms(query: &str) -> Result<Vec<Term>> {
+    let mut terms = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let negated = c == '-';
+        if negated {
+            chars.next();
+        }
+        let scope_start = chars.peek().map_or(query.len(), |&(at, _)| at);
+        let mut term = Term {
+            start,
+            scope_start,
+            value_start: scope_start,
+            end: query.len(),
+            negated,
+            scope: None,
+            value: String::new(),
+            quoted: false,
+        };
+        while let Some(&(at, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            match c {
+                '"' => {
+                    term.quoted = true;
+                    let unclosed = || {
+                        invalid(
+                            query,
+                            at,
+                            "Quote isn't closed".to_string(),
+                            Some(format!("{}\"", query.trim_end_matches('\\'))),
+                        )
+                    };
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => term.value.push(chars.next().ok_or_else(unclosed)?.1),
+                            Some((_, c)) => term.value.push(c),
+                            None => return Err(unclosed()),
+                        }
+                    }
+                }
+                ':' if term.scope.is_none() && !term.quoted && is_scope_name(&term.value) => {
+                    term.scope = Some(std::mem::take(&mut term.value));
+                    term.value_start = at + 1;
+                }
+                c => term.value.push(c),
+            }
+        }
+        term.end = chars.peek().map_or(query.len(), |&(at, _)| at);
+
+        // A lone dash is a word
+        if term.negated && term.scope.is_none() && term.value.is_empty() && !term.quoted {
+            term.negated = false;
+            term.value.push('-');
+        }
+        terms.push(term);
+    }
+
+    Ok(terms)
+}
+
+/// Scope of a term, lowercased but for custom keys; `None` for bare words
+///
+/// # Errors
+///
+/// * `VaultError::InvalidQuery` if the scope is a typo of a known one
+fn scope_of(query: &str, term: &Term) -> Result<Option<String>> {
+    let Some(scope) = &term.scope else {
+        return Ok(None);
+    };
+    if let Some(key) = scope.get(..CUSTOM_PREFIX.len()).filter(|prefix| prefix.eq_ignore_ascii_case(CUSTOM_PREFIX)) {
+        return Ok(Some(format!("{}{}", CUSTOM_PREFIX, &scope[key.len()..])));
+    }
+    let lower = scope.to_ascii_lowercase();
+    if SCOPES.contains(&lower.as_str()) {
+        return Ok(Some(lower));
+    }
+
+    let typed = lower.chars().count() as u32;
+    let nearest = SCOPES
+        .iter()
+        .map(|known| (levenshtein(&lower, known), *known))
+        .filter(|(distance, _)| *distance <= MAX_TYPO && *distance < typed)
+        .min();
+    match nearest {
+        Some((_, known)) => {
+            let scope_end = term.scope_start + scope.len();
+            Err(invalid(
+                query,
+                term.scope_start,
+                format!("Unknown scope `{}:`", scope),
+                Some(format!("{}{}{}", &query[..term.scope_start], known, &query[scope_end..])),
+            ))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Check whether text before a colon may name a scope: a word of ASCII
+/// letters, digits and underscores, or a custom key
+fn is_scope_name(text: &str) -> bool {
+    let custom = text
+        .get(..CUSTOM_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(CUSTOM_PREFIX))
+        && text.len() > CUSTOM_PREFIX.len();
+    let word = text.starts_with(|c: char| c.is_ascii_alphabetic())
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    custom || word
+}
+
+/// Comparison operator leading a numeric value, `=` if none, and the rest
+fn comparison(value: &str) -> (&'static str, &str) {
+    for op in ["<=", ">=", "<", ">", "="] {
+        if let Some(rest) = value.strip_prefix(op) {
+            return (op, rest);
+        }
+    }
+    ("=", value)
+}
+
+/// Set an unset value, telling whether it was unset
+fn set_once<T>(slot: &mut Option<T>, value: Option<T>) -> bool {
+    let fresh = slot.is_none();
+    if fresh {
+        *slot = value;
+    }
+    fresh
+}
+
+/// Set the bounds a comparison gives, telling whether they were unset
+fn set_bounds<T: Copy>(min: &mut Option<T>, max: &mut Option<T>, op: &str, value: T) -> bool {
+    match op {
+        "<" | "<=" => set_once(max, Some(value)),
+        ">" | ">=" => set_once(min, Some(value)),
+        _ => min.is_none() && max.is_none() && set_once(min, Some(value)) && set_once(max, Some(value)),
+    }
+}
+
+/// Add the terms of a range, a single one if its bounds are equal
+fn push_range<T: PartialEq + ToString>(terms: &mut Vec<String>, scope: &str, min: Option<T>, max: Option<T>) {
+    match (min, max) {
+        (Some(min), Some(max)) if min == max => terms.push(format!("{}:{}", scope, min.to_string())),
+        (min, max) => {
+            if let Some(min) = min {
+                terms.push(format!("{}:>={}", scope, min.to_string()));
+            }
+            if let Some(max) = max {
+                terms.push(format!("{}:<={}", scope, max.to_string()));
+            }
+        }
+    }
+}
+
+/// Check whether text can be written without quotes
+fn is_plain(text: &str) -> bool {
+    !text.is_empty() && !text.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\')
+}
+
+/// Quote a scoped value if it needs it
+fn quote_value(value: &str) -> String {
+    if is_plain(value) { value.to_string() } else { quote(value) }
+}
+
+/// Quote text, escaping quotes and backslashes
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Error at a byte offset of a query
+fn invalid(query: &str, at: usize, message: String, suggestion: Option<String>) -> VaultError {
+    VaultError::InvalidQuery {
+        position: query[..at].chars().count(),
+        message,
+        suggestion,
+    }
+}
@@ -0,0 +1,102 @@
+//! Scratch collections for a working session, removed once they expire
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::models::Collection;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::time::Duration;
+
+impl LocalLibrary {
+    /// Create a collection expiring `ttl` from now
+    ///
+    /// # Returns
+    ///
+    /// The ID of the collection
+    pub async fn create_session_collection(&self, name: &str, ttl: Duration) -> Result<String> {
+        let ttl = chrono::Duration::from_std(ttl)
+            .map_err(|_| VaultError::InvalidOperation(format!("Session lifetime is too long: {:?}", ttl)))?;
+        let mut collection = Collection::new(name, "");
+        collection.expires_at = Some(Utc::now() + ttl);
+        self.add_collection(&collection).await
+    }
+
+    /// Make a session collection permanent; a permanent one is left as is
+    pub async fn persist_session_collection(&self, id: &str) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        let expires_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar(&self.sql("SELECT expires_at FROM collections WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or_else(|| VaultError::NotFound(format!("Collection not found: {}", id)))?;
+        if expires_at.is_none() {
+            return Ok(());
+        }
+
+        sqlx::query(&self.sql("UPDATE collections SET expires_at = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let changes = audit_diff(Some(&json!({ "expires_at": expires_at })), Some(&json!({ "expires_at": null })));
+        self.audit(&mut tx, AuditOperation::UpdateCollection, id, changes).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Remove the session collections that expired, keeping their sounds
+    ///
+    /// A [`VaultEvent::SessionExpired`] is sent for each.
+    ///
+    /// # Returns
+    ///
+    /// The number of collections removed
+    pub async fn purge_expired_sessions(&self) -> Result<u64> {
+        self.purge_sessions_expired_at(Utc::now()).await
+    }
+
+    /// Remove the session collections that had expired at `at`
+    pub(crate) async fn purge_sessions_expired_at(&self, at: DateTime<Utc>) -> Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let ids: Vec<String> = sqlx::query_scalar(
+            &self.sql("SELECT id FROM collections WHERE expires_at IS NOT NULL AND expires_at <= ? ORDER BY id"),
+        )
+        .bind(at)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for id in &ids {
+            let collection = self.get_collection(id).await?;
+            self.remove_collection(&mut tx, &collection).await?;
+        }
+        tx.commit().await?;
+
+        for id in &ids {
+            self.emit(VaultEvent::SessionExpired { collection_id: id.clone() });
+        }
+        Ok(ids.len() as u64)
+    }
+
+    /// Delete a collection, keeping its sounds, within a transaction
+    pub(crate) async fn remove_collection(&self, conn: &mut SqliteConnection, collection: &Collection) -> Result<()> {
+        let id = collection.id.to_string();
+        sqlx::query(&self.sql("DELETE FROM collection_sounds WHERE collection_id = ?"))
+            .bind(&id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(&self.sql("DELETE FROM metadata WHERE object_id = ? AND object_type = 'collection'"))
+            .bind(&id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(&self.sql("DELETE FROM collections WHERE id = ?"))
+            .bind(&id)
+            .execute(&mut *conn)
+            .await?;
+        let changes = audit_diff(Some(&serde_json::to_value(collection)?), None);
+        self.audit(conn, AuditOperation::DeleteCollection, &id, changes).await
+    }
+}
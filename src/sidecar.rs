@@ -0,0 +1,385 @@
+//! JSON files mirroring the metadata of sounds and collections next to the
+//! audio, so the library folder stays meaningful without the database
+//!
+//! Each sound's metadata is written to `<library>/<sound id>/metadata.json`,
+//! the folder its file is imported into, and each collection to
+//! `<library>/.collections/<collection id>.json`. A background task follows
+//! the change feed and writes the sidecars of what changed in batches on the
+//! job queue, so imports don't wait for them; the sequence number written
+//! through is kept in `vault_info`.
+
+use crate::changes::{ChangeCursor, ChangeEntity, ChangeKind};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::{Collection, SoundMetadata};
+use crate::paths::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// Name of a sound's sidecar in its folder
+pub(crate) const SIDECAR_FILE: &str = "metadata.json";
+
+/// Folder of the library holding the collections' sidecars
+pub(crate) const COLLECTIONS_DIR: &str = ".collections";
+
+/// `vault_info` key of the last change whose sidecar was written
+const SIDECARS_WRITTEN_THROUGH: &str = "sidecars_written_through";
+
+/// How often the background task looks for changes
+const SIDECAR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Outcome of [`SoundVault::rebuild_from_sidecars`](crate::SoundVault::rebuild_from_sidecars)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SidecarRebuildReport {
+    /// IDs of the sounds recorded again from their sidecar
+    pub sounds: Vec<String>,
+
+    /// IDs of the collections recorded again from their sidecar
+    pub collections: Vec<String>,
+
+    /// Sidecars that couldn't be read or recorded, with the reason
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Sidecar writes and removals of one batch
+#[derive(Default)]
+struct SidecarBatch {
+    write: Vec<(PathBuf, Vec<u8>)>,
+    remove: Vec<PathBuf>,
+}
+
+impl SidecarBatch {
+    /// Write and remove the sidecars; a failure stops the batch
+    fn apply(self) -> Result<()> {
+        for (path, bytes) in self.write {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            write_atomic(&path, &bytes)?;
+        }
+        for path in self.remove {
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            // A sound's folder goes once its sidecar was the last file in it
+            if path.file_name().is_some_and(|name| name == SIDECAR_FILE)
+                && let Some(dir) = path.parent()
+            {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LocalLibrary {
+    /// Path of a sound's sidecar
+    fn sound_sidecar(&self, id: &str) -> PathBuf {
+        self.library_path.join(id).join(SIDECAR_FILE)
+    }
+
+    /// Path of a collection's sidecar
+    fn collection_sidecar(&self, id: &str) -> PathBuf {
+        self.library_path.join(COLLECTIONS_DIR).join(format!("{}.json", id))
+    }
+
+    /// Write the sidecars of everything changed since they were last
+    /// written
+    ///
+    /// The first time, every sidecar is written; sidecars already in the
+    /// library are kept, as they may be what the database is rebuilt from.
+    /// Whenever the changes since were pruned from the feed, every sidecar
+    /// is written anew and those of sounds and collections that are gone are
+    /// removed.
+    pub(crate) async fn write_sidecars(&self) -> Result<()> {
+        let written: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(SIDECARS_WRITTEN_THROUGH)
+            .fetch_optional(&self.reader)
+            .await?;
+        let Some(mut cursor) = written.and_then(|seq| seq.parse().ok()).map(|seq| ChangeCursor { seq }) else {
+            return self.write_all_sidecars(false).await;
+        };
+
+        loop {
+            let (changes, next) = match self.changes_since(cursor).await {
+                Ok(page) => page,
+                Err(VaultError::ChangesPruned { .. }) => return self.write_all_sidecars(true).await,
+                Err(e) => return Err(e),
+            };
+            if changes.is_empty() {
+                return Ok(());
+            }
+
+            // Only the latest state of each object matters
+            let latest: HashMap<_, _> = changes
+                .into_iter()
+                .map(|change| ((change.entity, change.entity_id), change.kind))
+                .collect();
+            let mut batch = SidecarBatch::default();
+            for ((entity, id), kind) in latest {
+                self.add_to_batch(&mut batch, entity, &id, kind).await?;
+            }
+            self.jobs.run(move || batch.apply()).await?;
+
+            self.save_sidecar_cursor(next).await?;
+            cursor = next;
+        }
+    }
+
+    /// Add the sidecar of an object to a batch, or its removal if it's gone
+    async fn add_to_batch(&self, batch: &mut SidecarBatch, entity: ChangeEntity, id: &str, kind: ChangeKind) -> Result<()> {
+        let (path, found) = match entity {
+            ChangeEntity::Sound => (
+                self.sound_sidecar(id),
+                match kind {
+                    ChangeKind::Deleted => None,
+                    _ => self.sidecar_sound(id).await?.map(|metadata| serde_json::to_vec_pretty(&metadata)).transpose()?,
+                },
+            ),
+            ChangeEntity::Collection => (
+                self.collection_sidecar(id),
+                match kind {
+                    ChangeKind::Deleted => None,
+                    _ => self.sidecar_collection(id).await?.map(|collection| serde_json::to_vec_pretty(&collection)).transpose()?,
+                },
+            ),
+        };
+        match found {
+            Some(bytes) => batch.write.push((path, bytes)),
+            None => batch.remove.push(path),
+        }
+        Ok(())
+    }
+
+    /// Metadata of a sound, or `None` if it's gone
+    async fn sidecar_sound(&self, id: &str) -> Result<Option<SoundMetadata>> {
+        match self.get_sound(id).await {
+            Ok(sound) => Ok(Some(sound.metadata)),
+            Err(VaultError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A collection, or `None` if it's gone
+    async fn sidecar_collection(&self, id: &str) -> Result<Option<Collection>> {
+        match self.get_collection(id).await {
+            Ok(collection) => Ok(Some(collection)),
+            Err(VaultError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the sidecar of every sound and collection, removing those of
+    /// the ones that are gone if `remove_stale`
+    async fn write_all_sidecars(&self, remove_stale: bool) -> Result<()> {
+        // Changes made while writing are caught by the next pass
+        let cursor = self.latest_change_cursor().await?;
+        let sounds: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?;
+        let collections: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM collections ORDER BY id"))
+            .fetch_all(&self.reader)
+            .await?;
+
+        let batch_size = self.governor.write_batch().max(1);
+        for chunk in sounds.chunks(batch_size) {
+            let mut batch = SidecarBatch::default();
+            for id in chunk {
+                self.add_to_batch(&mut batch, ChangeEntity::Sound, id, ChangeKind::Updated).await?;
+            }
+            self.jobs.run(move || batch.apply()).await?;
+        }
+        let mut batch = SidecarBatch::default();
+        for id in &collections {
+            self.add_to_batch(&mut batch, ChangeEntity::Collection, id, ChangeKind::Updated).await?;
+        }
+
+        // Sidecars of what was deleted while the changes were pruned
+        if remove_stale {
+            let sounds: HashSet<String> = sounds.into_iter().collect();
+            let collections: HashSet<String> = collections.into_iter().collect();
+            for (id, path) in self.find_sidecars()? {
+                if !sounds.contains(&id) {
+                    batch.remove.push(path);
+                }
+            }
+            for (id, path) in self.find_collection_sidecars()? {
+                if !collections.contains(&id) {
+                    batch.remove.push(path);
+                }
+            }
+        }
+        self.jobs.run(move || batch.apply()).await?;
+
+        self.save_sidecar_cursor(cursor).await
+    }
+
+    /// Remember the last change whose sidecar was written
+    async fn save_sidecar_cursor(&self, cursor: ChangeCursor) -> Result<()> {
+        sqlx::query(&self.sql(
+            "INSERT INTO vault_info (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        ))
+        .bind(SIDECARS_WRITTEN_THROUGH)
+        .bind(cursor.seq.to_string())
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sidecars of sounds in the library, with the ID of their folder
+    fn find_sidecars(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(&self.library_path)? {
+            let dir = entry?.path();
+            let sidecar = dir.join(SIDECAR_FILE);
+            if let Some(id) = dir.file_name().map(|name| name.to_string_lossy().to_string())
+                && !id.starts_with('.')
+                && sidecar.is_file()
+            {
+                found.push((id, sidecar));
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Sidecars of collections, with the collection ID they're named after
+    fn find_collection_sidecars(&self) -> Result<Vec<(String, PathBuf)>> {
+        let dir = self.library_path.join(COLLECTIONS_DIR);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json")
+                && let Some(id) = path.file_stem().map(|stem| stem.to_string_lossy().to_string())
+            {
+                found.push((id, path));
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Record again the sounds and collections found in sidecars, e.g.
+    /// after the database was lost
+    ///
+    /// Sounds and collections already in the database are left as they
+    /// are. A sound's file is looked for in the folder of its sidecar, or in
+    /// the objects directory if content-addressed, so a library moved
+    /// elsewhere is rebuilt with its new paths; collections only keep the
+    /// members that exist.
+    pub async fn rebuild_from_sidecars(&self) -> Result<SidecarRebuildReport> {
+        let mut report = SidecarRebuildReport::default();
+
+        for (_, path) in self.find_sidecars()? {
+            let restored = async {
+                let mut metadata: SoundMetadata = serde_json::from_slice(&std::fs::read(&path)?)?;
+                if self.sidecar_sound(&metadata.id).await?.is_some() {
+                    return Ok(None);
+                }
+                // A content-addressed file is looked for by its hash instead
+                let object = metadata.hash.as_deref().map(|hash| self.object_path(hash));
+                match object {
+                    Some(object)
+                        if !metadata.external
+                            && metadata.path.as_deref().and_then(Path::file_name) == object.file_name()
+                            && object.is_file() =>
+                    {
+                        metadata.path = Some(object)
+                    }
+                    _ => relocate(&mut metadata, path.parent().unwrap_or(&self.library_path)),
+                }
+                self.insert_sound(&metadata, None, None).await?;
+                Ok::<_, VaultError>(Some(metadata.id))
+            }
+            .await;
+            match restored {
+                Ok(Some(id)) => report.sounds.push(id),
+                Ok(None) => {}
+                Err(e) => report.skipped.push((path, e.to_string())),
+            }
+        }
+
+        for (_, path) in self.find_collection_sidecars()? {
+            let restored = async {
+                let mut collection: Collection = serde_json::from_slice(&std::fs::read(&path)?)?;
+                let id = collection.id.to_string();
+                if self.sidecar_collection(&id).await?.is_some() {
+                    return Ok(None);
+                }
+                let mut members = Vec::new();
+                for sound_id in collection.sound_ids {
+                    if self.sidecar_sound(&sound_id).await?.is_some() {
+                        members.push(sound_id);
+                    }
+                }
+                collection.sound_ids = members;
+                self.add_collection(&collection).await?;
+                Ok::<_, VaultError>(Some(id))
+            }
+            .await;
+            match restored {
+                Ok(Some(id)) => report.collections.push(id),
+                Ok(None) => {}
+                Err(e) => report.skipped.push((path, e.to_string())),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Point a sound copied into the library at its file in `dir`, where the
+/// sidecar was found
+fn relocate(metadata: &mut SoundMetadata, dir: &Path) {
+    if metadata.external {
+        return;
+    }
+    if let Some(name) = metadata.path.as_deref().and_then(Path::file_name)
+        && dir.join(name).is_file()
+    {
+        metadata.path = Some(dir.join(name));
+    }
+}
+
+/// Background task writing the sidecars of what changed
+pub(crate) struct SidecarWriter {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SidecarWriter {
+    /// Look for changes every second if `enabled`
+    pub(crate) fn start(local: &Arc<LocalLibrary>, enabled: bool) -> Self {
+        let task = enabled.then(|| {
+            let local = local.clone();
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(SIDECAR_INTERVAL);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    ticks.tick().await;
+                    // Failed writes are retried at the next tick
+                    let _ = local.write_sidecars().await;
+                }
+            })
+        });
+
+        Self { task: Mutex::new(task) }
+    }
+
+    /// Stop writing; a batch under way is abandoned, and written again by
+    /// the next pass
+    pub(crate) fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Sounds of the library that resemble a given one, by their analysis
+//!
+//! The descriptors compared are stored with each sound as a feature vector
+//! when they're saved, so a search reads one column of one table. Each
+//! feature is scaled by its spread across the library before weighing, so
+//! seconds and hertz compare alike; durations, pitches, centroids and tempos
+//! are compared in octaves rather than in their own units.
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::Sound;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+
+/// `vault_info` key recording that every sound's feature vector was built
+const FEATURES_BUILT: &str = "feature_vectors_built";
+
+/// Descriptors of a feature vector, in order; the duration comes from the
+/// sound itself
+const FEATURES: [&str; 7] = [
+    "loudness",
+    "dynamic_range",
+    "spectral_centroid",
+    "spectral_flatness",
+    "bpm",
+    "pitch",
+    "zero_crossing_rate",
+];
+
+/// Features compared on a logarithmic scale
+const LOG_FEATURES: [&str; 4] = ["duration", "spectral_centroid", "bpm", "pitch"];
+
+/// ID, duration, hash, parent and feature vector of a sound
+type FeatureRow = (String, Option<f64>, Option<String>, Option<String>, Option<Vec<u8>>);
+
+/// How much each feature counts in the distance between two sounds; 0 leaves
+/// it out
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimilarityWeights {
+    /// Duration of the sound
+    pub duration: f64,
+    /// Integrated loudness
+    pub loudness: f64,
+    /// Spread between loud and quiet passages
+    pub dynamic_range: f64,
+    /// Brightness
+    pub spectral_centroid: f64,
+    /// How noise-like, rather than tonal, the sound is
+    pub spectral_flatness: f64,
+    /// Tempo
+    pub bpm: f64,
+    /// Fundamental frequency
+    pub pitch: f64,
+    /// Rate of sign changes of the waveform
+    pub zero_crossing_rate: f64,
+}
+
+impl Default for SimilarityWeights {
+    /// Timbre first, then loudness and duration, tempo least
+    fn default() -> Self {
+        Self {
+            duration: 1.0,
+            loudness: 1.0,
+            dynamic_range: 0.5,
+            spectral_centroid: 2.0,
+            spectral_flatness: 2.0,
+            bpm: 0.5,
+            pitch: 1.0,
+            zero_crossing_rate: 1.0,
+        }
+    }
+}
+
+impl SimilarityWeights {
+    /// Weights in the order of a scaled vector: duration, then [`FEATURES`]
+    fn ordered(&self) -> [f64; FEATURES.len() + 1] {
+        [
+            self.duration,
+            self.loudness,
+            self.dynamic_range,
+            self.spectral_centroid,
+            self.spectral_flatness,
+            self.bpm,
+            self.pitch,
+            self.zero_crossing_rate,
+        ]
+    }
+
+    fn check(&self) -> Result<()> {
+        let weights = self.ordered();
+        if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) || weights.iter().all(|weight| *weight == 0.0) {
+            return Err(VaultError::InvalidOperation(format!("Invalid similarity weights: {:?}", self)));
+        }
+        Ok(())
+    }
+}
+
+/// A sound and its distance to the one searched from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoredSound {
+    /// The sound
+    pub sound: Sound,
+
+    /// Weighted distance in feature space; 0 for identical features
+    pub distance: f64,
+}
+
+/// Sounds resembling a given one, closest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarSounds {
+    /// The closest sounds
+    pub sounds: Vec<ScoredSound>,
+
+    /// Number of sounds left out for lack of analysis descriptors
+    pub skipped: u64,
+}
+
+/// Feature vector of descriptors, `None` if it has none of [`FEATURES`]
+fn feature_vector(descriptors: &HashMap<String, f64>) -> Option<Vec<u8>> {
+    let values = FEATURES.map(|name| descriptors.get(name).copied().filter(|value| value.is_finite()));
+    values.iter().any(Option::is_some).then(|| {
+        values
+            .iter()
+            .flat_map(|value| value.unwrap_or(f64::NAN).to_le_bytes())
+            .collect()
+    })
+}
+
+/// Duration and descriptors of a stored vector, on the scales they're
+/// compared on; NaN where missing
+fn scaled(duration: f64, vector: &[u8]) -> [f64; FEATURES.len() + 1] {
+    let mut values = [f64::NAN; FEATURES.len() + 1];
+    values[0] = duration;
+    for (value, bytes) in values[1..].iter_mut().zip(vector.chunks_exact(8)) {
+        *value = f64::from_le_bytes(bytes.try_into().unwrap_or_default());
+    }
+    for (value, name) in values.iter_mut().zip(std::iter::once("duration").chain(FEATURES)) {
+        if LOG_FEATURES.contains(&name) {
+            *value = if *value > 0.0 { value.log2() } else { f64::NAN };
+        }
+    }
+    values
+}
+
+/// Standard deviation of each feature over the vectors that have it
+fn spreads(vectors: &[[f64; FEATURES.len() + 1]]) -> [f64; FEATURES.len() + 1] {
+    std::array::from_fn(|feature| {
+        let values: Vec<f64> = vectors.iter().map(|vector| vector[feature]).filter(|value| !value.is_nan()).collect();
+        if values.len() < 2 {
+            return 0.0;
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt()
+    })
+}
+
+/// Weighted distance over the features both vectors have, `None` if they
+/// share none that varies
+fn distance(a: &[f64], b: &[f64], spreads: &[f64], weights: &[f64]) -> Option<f64> {
+    let (mut sum, mut total) = (0.0, 0.0);
+    for (((a, b), spread), weight) in a.iter().zip(b).zip(spreads).zip(weights) {
+        if a.is_nan() || b.is_nan() || *spread == 0.0 || *weight == 0.0 {
+            continue;
+        }
+        sum += weight * ((a - b) / spread).powi(2);
+        total += weight;
+    }
+    (total > 0.0).then(|| (sum / total).sqrt())
+}
+
+impl LocalLibrary {
+    /// Store the feature vector of a sound's descriptors
+    pub(crate) async fn save_feature_vector(
+        &self,
+        conn: &mut SqliteConnection,
+        id: &str,
+        descriptors: &HashMap<String, f64>,
+    ) -> Result<()> {
+        sqlx::query(&self.sql("UPDATE sounds SET features = ? WHERE id = ?"))
+            .bind(feature_vector(descriptors))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Build the feature vectors of sounds whose descriptors were stored
+    /// before vectors were
+    pub(crate) async fn build_feature_vectors(&self) -> Result<()> {
+        let built: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(FEATURES_BUILT)
+            .fetch_optional(&self.reader)
+            .await?;
+        if built.is_some() {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("INSERT INTO vault_info (key, value) VALUES (?, '1')"))
+            .bind(FEATURES_BUILT)
+            .execute(&mut *tx)
+            .await?;
+        let rows: Vec<(String, String, f64)> =
+            sqlx::query_as(&self.sql("SELECT sound_id, name, value FROM sound_descriptors ORDER BY sound_id"))
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut descriptors: HashMap<String, HashMap<String, f64>> = HashMap::new();
+        for (id, name, value) in rows {
+            descriptors.entry(id).or_default().insert(name, value);
+        }
+        for (id, descriptors) in descriptors {
+            self.save_feature_vector(&mut tx, &id, &descriptors).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Find the sounds closest to one in feature space
+    ///
+    /// The sound itself, its derivatives, the sound it derives from and
+    /// copies of its file are left out.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound has no analysis
+    ///   descriptors, or the weights are negative or all 0
+    pub async fn similar_sounds(&self, id: &str, limit: usize, weights: &SimilarityWeights) -> Result<SimilarSounds> {
+        weights.check()?;
+        let sound = self.get_sound(id).await?.metadata;
+
+        let rows: Vec<FeatureRow> =
+            sqlx::query_as(&self.sql("SELECT id, duration, hash, derived_from, features FROM sounds"))
+                .fetch_all(&self.reader)
+                .await?;
+
+        let mut skipped = 0;
+        let mut query = None;
+        let mut candidates = Vec::new();
+        for (candidate, duration, hash, derived_from, features) in rows {
+            let Some(features) = features else {
+                if candidate != id {
+                    skipped += 1;
+                }
+                continue;
+            };
+            let vector = scaled(duration.unwrap_or(f64::NAN), &features);
+            let related = derived_from.as_deref() == Some(id)
+                || sound.derived_from.as_deref() == Some(candidate.as_str())
+                || (hash.is_some() && hash == sound.hash);
+            if candidate == id {
+                query = Some(vector);
+            } else if !related {
+                candidates.push((candidate, vector));
+            }
+        }
+        let query = query.ok_or_else(|| VaultError::InvalidOperation(format!("Sound has no analysis: {}", id)))?;
+
+        let mut vectors: Vec<_> = candidates.iter().map(|(_, vector)| *vector).collect();
+        vectors.push(query);
+        let spreads = spreads(&vectors);
+        let weights = weights.ordered();
+
+        let mut scored: Vec<(f64, String)> = candidates
+            .into_iter()
+            .filter_map(|(candidate, vector)| Some((distance(&query, &vector, &spreads, &weights)?, candidate)))
+            .collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        let mut sounds = Vec::new();
+        for (distance, candidate) in scored.into_iter().take(limit) {
+            sounds.push(ScoredSound {
+                sound: self.get_sound(&candidate).await?,
+                distance,
+            });
+        }
+        Ok(SimilarSounds { sounds, skipped })
+    }
+}
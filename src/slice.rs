@@ -0,0 +1,316 @@
+//! Slicing loops into one-shots
+//!
+//! A slice is a new sound derived from its parent. By default it plays a
+//! region of the parent's file, through trim markers, rather than a copy:
+//! it records a use of the parent, so the parent can't be deleted while
+//! its slices point at its file.
+
+use crate::audio::{AudioInfo, decode, encode, probe, writable_format};
+use crate::error::{Result, VaultError};
+use crate::journal::OpKind;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{SoundMetadata, SoundSource, Trim};
+use crate::paths::write_atomic;
+use crate::uses::ExternalUse;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use uuid::Uuid;
+
+/// Tag given to every slice
+pub const SLICE_TAG: &str = "slice";
+
+/// Custom metadata key holding a slice's position in its parent, from 1
+pub const SLICE_INDEX_KEY: &str = "slice_index";
+
+/// Consumer of the uses slices record of their parent
+pub const SLICE_CONSUMER: &str = "SoundVault slice";
+
+/// Shortest slice kept, in seconds
+const MIN_SLICE: f32 = 0.001;
+
+/// How [`SoundVault::slice`](crate::SoundVault::slice) cuts a sound
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SliceStrategy {
+    /// A slice every this many beats, at the tempo of the sound's `bpm`
+    /// descriptor or custom value
+    EveryNBeats(f32),
+    /// A slice per hit, found where the spectrum changes suddenly;
+    /// `sensitivity` from 0 to 1 finds more hits as it grows
+    #[cfg(feature = "analysis")]
+    OnsetDetection {
+        /// How readily changes count as hits, from 0 to 1
+        sensitivity: f32,
+    },
+    /// This many slices of equal length
+    FixedCount(usize),
+}
+
+/// Options of [`SoundVault::slice_with_options`](crate::SoundVault::slice_with_options)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SliceOptions {
+    /// Write each slice to a file of its own, instead of pointing it at a
+    /// region of the parent's file
+    pub materialize: bool,
+}
+
+/// Tempo of a sound, from its analysis descriptors or custom metadata
+fn bpm(metadata: &SoundMetadata) -> Option<f64> {
+    metadata
+        .descriptors
+        .get("bpm")
+        .copied()
+        .or_else(|| metadata.get_custom("bpm").and_then(|bpm| bpm.trim().parse().ok()))
+        .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+}
+
+/// Cut `start..end` at `cuts`, dropping slices too short to hear
+fn regions(start: f32, end: f32, cuts: impl IntoIterator<Item = f32>) -> Vec<Trim> {
+    let mut bounds = vec![start];
+    bounds.extend(cuts.into_iter().filter(|cut| *cut > start && *cut < end));
+    bounds.push(end);
+    bounds
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] >= MIN_SLICE)
+        .map(|pair| Trim {
+            start: pair[0],
+            end: Some(pair[1]),
+        })
+        .collect()
+}
+
+/// Times of the hits in a mono signal, in seconds
+///
+/// Hits are peaks of the spectral flux, the growth of each frequency's
+/// magnitude from one frame to the next, above a threshold set by
+/// `sensitivity`; each is moved back to where its attack starts.
+#[cfg(feature = "analysis")]
+fn detect_onsets(mono: &[f32], sample_rate: u32, sensitivity: f32) -> Vec<f32> {
+    use rustfft::FftPlanner;
+    use rustfft::num_complex::Complex;
+
+    const FFT_SIZE: usize = 1024;
+    const HOP: usize = 512;
+    // Frames either side a peak must top
+    const NEIGHBORHOOD: usize = 3;
+    // Shortest time between hits, in seconds
+    const MIN_GAP: f32 = 0.05;
+
+    if mono.len() < FFT_SIZE || sample_rate == 0 {
+        return Vec::new();
+    }
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos())
+        .collect();
+
+    let frames = (mono.len() - FFT_SIZE) / HOP + 1;
+    let mut flux = vec![0f32; frames];
+    let mut previous = vec![0f32; FFT_SIZE / 2];
+    let mut buffer = vec![Complex::new(0f32, 0f32); FFT_SIZE];
+    for (frame, value) in flux.iter_mut().enumerate() {
+        let start = frame * HOP;
+        for (i, bin) in buffer.iter_mut().enumerate() {
+            *bin = Complex::new(mono[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        for (bin, previous) in buffer[..FFT_SIZE / 2].iter().zip(previous.iter_mut()) {
+            // Log magnitudes, so quiet hits count next to loud ones
+            let magnitude = (1.0 + 100.0 * bin.norm()).ln();
+            *value += (magnitude - *previous).max(0.0);
+            *previous = magnitude;
+        }
+    }
+    // The first frame grows from nothing
+    flux[0] = 0.0;
+
+    let loudest = flux.iter().copied().fold(0f32, f32::max);
+    if loudest <= 0.0 {
+        return Vec::new();
+    }
+    let threshold = 0.02 + 0.6 * (1.0 - sensitivity.clamp(0.0, 1.0));
+    let mut onsets: Vec<f32> = Vec::new();
+    for frame in 1..frames {
+        let level = flux[frame] / loudest;
+        let neighbors = frame.saturating_sub(NEIGHBORHOOD)..(frame + NEIGHBORHOOD + 1).min(frames);
+        if level < threshold || neighbors.clone().any(|other| flux[other] > flux[frame]) {
+            continue;
+        }
+
+        // The attack starts where the window first gets loud
+        let start = frame * HOP;
+        let span = &mono[start..start + FFT_SIZE];
+        let peak = span.iter().fold(0f32, |peak, sample| peak.max(sample.abs()));
+        let attack = span.iter().position(|sample| sample.abs() >= peak * 0.1).unwrap_or(0);
+        let time = (start + attack) as f32 / sample_rate as f32;
+        if onsets.last().is_none_or(|last| time - last >= MIN_GAP) {
+            onsets.push(time);
+        }
+    }
+    onsets
+}
+
+impl LocalLibrary {
+    /// Regions of a sound each slice would play, in seconds of its file
+    ///
+    /// Only the part between the sound's own trim markers is sliced.
+    pub async fn plan_slices(&self, id: &str, strategy: SliceStrategy) -> Result<Vec<Trim>> {
+        let parent = self.stored_sound(id).await?;
+        self.slice_regions(&parent, strategy).await
+    }
+
+    async fn slice_regions(&self, parent: &SoundMetadata, strategy: SliceStrategy) -> Result<Vec<Trim>> {
+        let duration = self.sound_stream(parent)?.read(|mut reader| probe(&mut reader))?.duration();
+        let start = parent.trim.as_ref().map_or(0.0, |trim| trim.start).min(duration);
+        let end = parent.trim.as_ref().and_then(|trim| trim.end).unwrap_or(duration).min(duration);
+
+        match strategy {
+            SliceStrategy::EveryNBeats(beats) => {
+                if !(beats.is_finite() && beats > 0.0) {
+                    return Err(VaultError::InvalidOperation(format!("Invalid number of beats per slice: {}", beats)));
+                }
+                let bpm = bpm(parent).ok_or_else(|| {
+                    VaultError::InvalidOperation(format!("Sound {} has no bpm to slice by beats", parent.id))
+                })?;
+                let step = (beats as f64 * 60.0 / bpm) as f32;
+                let count = ((end - start) / step).ceil() as usize;
+                Ok(regions(start, end, (1..count).map(|n| start + n as f32 * step)))
+            }
+            #[cfg(feature = "analysis")]
+            SliceStrategy::OnsetDetection { sensitivity } => {
+                use crate::audio::{ChannelMix, downmix};
+
+                let bytes = self.sound_bytes(parent)?;
+                let onsets = self
+                    .jobs
+                    .run(move || {
+                        let (info, samples) = decode(&mut Cursor::new(bytes))?;
+                        let mono = downmix(&samples, info.channels, ChannelMix::Mono);
+                        Ok(detect_onsets(&mono, info.sample_rate, sensitivity))
+                    })
+                    .await?;
+                Ok(regions(start, end, onsets))
+            }
+            SliceStrategy::FixedCount(count) => {
+                if count == 0 {
+                    return Err(VaultError::InvalidOperation("A sound can't be cut into 0 slices".to_string()));
+                }
+                let step = (end - start) / count as f32;
+                Ok(regions(start, end, (1..count).map(|n| start + n as f32 * step)))
+            }
+        }
+    }
+
+    /// Cut a sound into slices, each a new sound derived from it
+    ///
+    /// Slices keep the parent's description, license, tags and expiration,
+    /// get the `slice` tag and their position from 1 under
+    /// [`SLICE_INDEX_KEY`].
+    ///
+    /// # Returns
+    ///
+    /// IDs of the slices, in order
+    pub async fn slice(&self, id: &str, strategy: SliceStrategy, options: &SliceOptions) -> Result<Vec<String>> {
+        let parent = self.stored_sound(id).await?;
+        if !options.materialize && (parent.archive.is_some() || parent.storage_backend.is_some()) {
+            return Err(VaultError::InvalidOperation(format!(
+                "Sound {} isn't stored as a plain file; slice it with materialize",
+                id
+            )));
+        }
+        let regions = self.slice_regions(&parent, strategy).await?;
+
+        let decoded = match options.materialize {
+            true => {
+                let bytes = self.sound_bytes(&parent)?;
+                Some(self.jobs.run(move || decode(&mut Cursor::new(bytes))).await?)
+            }
+            false => None,
+        };
+
+        let mut ids = Vec::with_capacity(regions.len());
+        for (index, region) in regions.iter().enumerate() {
+            let mut tags = parent.tags.clone();
+            if !tags.iter().any(|t| t == SLICE_TAG) {
+                tags.push(SLICE_TAG.to_string());
+            }
+            let mut metadata = SoundMetadata {
+                id: Uuid::new_v4().to_string(),
+                name: format!("{} - slice {}", parent.name, index + 1),
+                source: SoundSource::Local,
+                tags,
+                description: parent.description.clone(),
+                duration: region.end.unwrap_or(region.start) - region.start,
+                channels: parent.channels,
+                sample_rate: parent.sample_rate,
+                license: parent.license.clone(),
+                derived_from: Some(parent.id.clone()),
+                expires_at: parent.expires_at,
+                ..Default::default()
+            };
+            metadata.set_custom(SLICE_INDEX_KEY, &(index + 1).to_string());
+
+            match &decoded {
+                Some((info, samples)) => self.write_slice(&parent, metadata.clone(), info, samples, region, index + 1).await?,
+                None => {
+                    metadata.path = match parent.packed || parent.external {
+                        true => parent.path.clone(),
+                        false => Some(self.readable_file(&parent)?),
+                    };
+                    metadata.external = true;
+                    metadata.packed = parent.packed;
+                    metadata.format = parent.format;
+                    metadata.hash = parent.hash.clone();
+                    metadata.trim = Some(*region);
+                    self.insert_sound(&metadata, None, None).await?;
+                    let context = ExternalUse::new(SLICE_CONSUMER, self.uri_for(&metadata.id));
+                    self.write_external_use(&mut *self.db.acquire().await?, &parent.id, &context).await?;
+                }
+            }
+            ids.push(metadata.id);
+        }
+
+        Ok(ids)
+    }
+
+    /// Write a slice to a file of its own and add it
+    async fn write_slice(
+        &self,
+        parent: &SoundMetadata,
+        mut metadata: SoundMetadata,
+        info: &AudioInfo,
+        samples: &[f32],
+        region: &Trim,
+        index: usize,
+    ) -> Result<()> {
+        let channels = info.channels.max(1) as usize;
+        let frames = region.frames(info.sample_rate, samples.len() / channels);
+        let output = AudioInfo::new(info.format, info.channels, info.sample_rate, writable_format(info.sample_format));
+        let bytes = encode(&output, &samples[frames.start * channels..frames.end * channels])?;
+
+        let source = Self::logical_path(parent).unwrap_or_default();
+        let stem = source.file_stem().map_or_else(|| parent.id.clone(), |stem| stem.to_string_lossy().to_string());
+        let mut file_name = format!("{} (slice {})", stem, index);
+        if let Some(extension) = source.extension() {
+            file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+        }
+        let target_path = self.library_file(&self.library_path.join(&metadata.id).join(file_name))?;
+        let op = self.begin_op(OpKind::Derive, &[&target_path], &[]).await?;
+        let result = async {
+            std::fs::create_dir_all(target_path.parent().unwrap_or(&self.library_path)).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to create directory: {}", e))
+            })?;
+            write_atomic(&target_path, &bytes).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to write slice: {}", e))
+            })?;
+
+            metadata.duration = frames.len() as f32 / info.sample_rate.max(1) as f32;
+            metadata.hash = Some(hash_file(&target_path)?);
+            metadata.path = Some(target_path.clone());
+            self.insert_sound(&metadata, Some(&op), None).await
+        }
+        .await;
+        self.settle_op(&op, result).await
+    }
+}
@@ -0,0 +1,150 @@
+//! Short readable names of sounds, to say aloud or put in file names
+//!
+//! A slug is made from the sound's name when it's stored: lowercase ASCII
+//! letters and digits separated by dashes, accents dropped. A slug another
+//! sound has already gets a suffix from the sound's ID.
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::models::Sound;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Longest slug made from a name, before any suffix
+const MAX_SLUG_LEN: usize = 48;
+
+/// Slug of sounds whose name has no letter or digit
+const FALLBACK_SLUG: &str = "sound";
+
+/// Characters of the sound's ID tried first as a suffix
+const SUFFIX_LEN: usize = 4;
+
+/// Whether a sound's slug changes when it's renamed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlugPolicy {
+    /// Keep the slug given when the sound was stored, so references to it
+    /// outside the vault keep working
+    #[default]
+    Stable,
+    /// Make a new slug from the new name
+    FollowName,
+}
+
+/// Slug made from a name
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::slugify;
+///
+/// assert_eq!(slugify("Wind Gust #03 (close mic)"), "wind-gust-03-close-mic");
+/// assert_eq!(slugify("Été à Åre"), "ete-a-are");
+/// assert_eq!(slugify("!!!"), "sound");
+/// ```
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.nfd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    if slug.len() > MAX_SLUG_LEN {
+        slug.truncate(MAX_SLUG_LEN);
+        if let Some(dash) = slug.rfind('-') {
+            slug.truncate(dash);
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() { FALLBACK_SLUG.to_string() } else { slug.to_string() }
+}
+
+impl LocalLibrary {
+    /// Get a sound by its slug
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if no sound has the slug
+    pub async fn get_sound_by_slug(&self, slug: &str) -> Result<Sound> {
+        let id: Option<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE slug = ?"))
+            .bind(slug)
+            .fetch_optional(&self.reader)
+            .await?;
+        let id = id.ok_or_else(|| VaultError::NotFound(format!("No sound with slug {}", slug)))?;
+        self.get_sound(&id).await
+    }
+
+    /// Slug for a sound, `wanted` if it's a free slug, otherwise made from
+    /// its name
+    pub(crate) async fn choose_slug(
+        &self,
+        conn: &mut SqliteConnection,
+        id: &str,
+        name: &str,
+        wanted: Option<&str>,
+    ) -> Result<String> {
+        if let Some(wanted) = wanted
+            && slugify(wanted) == wanted
+            && !self.slug_taken(conn, wanted, id).await?
+        {
+            return Ok(wanted.to_string());
+        }
+
+        let base = slugify(name);
+        if !self.slug_taken(conn, &base, id).await? {
+            return Ok(base);
+        }
+        let suffix: String = id.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect();
+        let whole = (!suffix.is_empty()).then_some(suffix.len());
+        for len in (SUFFIX_LEN..suffix.len()).step_by(2).chain(whole) {
+            let slug = format!("{}-{}", base, &suffix[..len]);
+            if !self.slug_taken(conn, &slug, id).await? {
+                return Ok(slug);
+            }
+        }
+        let mut number = 2;
+        loop {
+            let slug = format!("{}-{}", base, number);
+            if !self.slug_taken(conn, &slug, id).await? {
+                return Ok(slug);
+            }
+            number += 1;
+        }
+    }
+
+    /// Check whether a sound other than `id` has a slug
+    async fn slug_taken(&self, conn: &mut SqliteConnection, slug: &str, id: &str) -> Result<bool> {
+        let taken: Option<i64> = sqlx::query_scalar(&self.sql("SELECT 1 FROM sounds WHERE slug = ? AND id != ?"))
+            .bind(slug)
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        Ok(taken.is_some())
+    }
+
+    /// Give a slug to the sounds stored before slugs were
+    pub(crate) async fn assign_missing_slugs(&self) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        let rows: Vec<(String, String)> =
+            sqlx::query_as(&self.sql("SELECT id, name FROM sounds WHERE slug IS NULL ORDER BY created_at, id"))
+                .fetch_all(&mut *tx)
+                .await?;
+        for (id, name) in rows {
+            let slug = self.choose_slug(&mut tx, &id, &name, None).await?;
+            sqlx::query(&self.sql("UPDATE sounds SET slug = ? WHERE id = ?"))
+                .bind(slug)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Telling the true format of a file from its first bytes
+//!
+//! Files are often named after the wrong format, e.g. AIFF files ending in
+//! `.wav`. The format read from a file's header is stored with its sound;
+//! [`SoundVault::list_extension_mismatches`](crate::SoundVault::list_extension_mismatches)
+//! lists the files whose extension disagrees.
+
+use crate::error::{Result, VaultError};
+use crate::journal::OpKind;
+use crate::local::LocalLibrary;
+use crate::paths::copy_atomic;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// `vault_info` key recording that every sound's format was read
+const FORMATS_SNIFFED: &str = "file_formats_sniffed";
+
+/// Container format of a stored file, as read from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    /// RIFF WAVE, including RF64
+    Wav,
+    /// AIFF or AIFF-C
+    Aiff,
+    /// Core Audio Format
+    Caf,
+    /// FLAC
+    Flac,
+    /// Ogg, whatever the codec
+    Ogg,
+    /// MPEG audio layer I, II or III
+    Mp3,
+}
+
+impl FileFormat {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Aiff => "aiff",
+            Self::Caf => "caf",
+            Self::Flac => "flac",
+            Self::Ogg => "ogg",
+            Self::Mp3 => "mp3",
+        }
+    }
+
+    /// Extension files of this format are given
+    pub fn extension(&self) -> &'static str {
+        self.as_str()
+    }
+
+    /// MIME type of the format
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Wav => "audio/wav",
+            Self::Aiff => "audio/aiff",
+            Self::Caf => "audio/x-caf",
+            Self::Flac => "audio/flac",
+            Self::Ogg => "audio/ogg",
+            Self::Mp3 => "audio/mpeg",
+        }
+    }
+
+    /// Check whether a file extension, in any case, names this format
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::FileFormat;
+    ///
+    /// assert!(FileFormat::Aiff.matches_extension("AIF"));
+    /// assert!(FileFormat::Ogg.matches_extension("opus"));
+    /// assert!(!FileFormat::Wav.matches_extension("mp3"));
+    /// ```
+    pub fn matches_extension(&self, extension: &str) -> bool {
+        let extensions: &[&str] = match self {
+            Self::Wav => &["wav", "wave", "bwf"],
+            Self::Aiff => &["aiff", "aif", "aifc"],
+            Self::Caf => &["caf"],
+            Self::Flac => &["flac"],
+            Self::Ogg => &["ogg", "oga", "opus"],
+            Self::Mp3 => &["mp3", "mp2", "mpga"],
+        };
+        extensions.iter().any(|known| known.eq_ignore_ascii_case(extension))
+    }
+
+    /// Parse a name stored in the database
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+    }
+
+    /// Tell the format from the first bytes of a file; `None` if it isn't
+    /// one of the known formats
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        let tag = |range: std::ops::Range<usize>| header.get(range);
+        match (tag(0..4)?, tag(8..12)) {
+            (b"RIFF" | b"RF64" | b"BW64", Some(b"WAVE")) => Some(Self::Wav),
+            (b"FORM", Some(b"AIFF" | b"AIFC")) => Some(Self::Aiff),
+            (b"caff", _) => Some(Self::Caf),
+            (b"fLaC", _) => Some(Self::Flac),
+            (b"OggS", _) => Some(Self::Ogg),
+            _ if header.starts_with(b"ID3") => Some(Self::Mp3),
+            // A frame sync with a layer set; AAC's ADTS sync has none
+            _ if header[0] == 0xff && header[1] & 0xe0 == 0xe0 && header[1] & 0x06 != 0 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+}
+
+/// Read the true format of a file; `None` if it isn't one of the known
+/// formats
+///
+/// # Errors
+///
+/// * `VaultError::Io` if the file can't be read
+pub fn sniff_format(path: &Path) -> Result<Option<FileFormat>> {
+    let mut header = Vec::with_capacity(12);
+    std::fs::File::open(path)?.take(12).read_to_end(&mut header)?;
+    Ok(FileFormat::sniff(&header))
+}
+
+/// A sound whose file's extension disagrees with its format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionMismatch {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// Path of the file
+    pub path: PathBuf,
+
+    /// Format read from the file
+    pub format: FileFormat,
+}
+
+impl ExtensionMismatch {
+    /// Path of the file with the extension of its format
+    pub fn corrected_path(&self) -> PathBuf {
+        self.path.with_extension(self.format.extension())
+    }
+}
+
+/// Format of a file whose extension disagrees with it
+pub(crate) fn mismatched_format(path: &Path) -> Option<FileFormat> {
+    let format = sniff_format(path).ok()??;
+    let extension = path.extension().map(|ext| ext.to_string_lossy()).unwrap_or_default();
+    (!format.matches_extension(&extension)).then_some(format)
+}
+
+impl LocalLibrary {
+    /// List the sounds whose file's extension disagrees with the format read
+    /// from the file, by ID
+    ///
+    /// Archived sounds, content-addressed files, which have no extension, and
+    /// missing files are left out.
+    pub async fn list_extension_mismatches(&self) -> Result<Vec<ExtensionMismatch>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            &self.sql(r#"
+            SELECT id, path FROM sounds
+            WHERE path IS NOT NULL AND archive_codec IS NULL AND packed = 0 AND object_hash IS NULL
+            ORDER BY id
+            "#),
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(sound_id, path)| {
+                let path = PathBuf::from(path);
+                let format = mismatched_format(&path)?;
+                Some(ExtensionMismatch { sound_id, path, format })
+            })
+            .collect())
+    }
+
+    /// Store the format of every sound's file, once, for vaults created
+    /// before formats were read
+    pub(crate) async fn sniff_stored_formats(&self) -> Result<()> {
+        let sniffed: Option<String> = sqlx::query_scalar(&self.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(FORMATS_SNIFFED)
+            .fetch_optional(&self.reader)
+            .await?;
+        if sniffed.is_some() {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("INSERT INTO vault_info (key, value) VALUES (?, '1')"))
+            .bind(FORMATS_SNIFFED)
+            .execute(&mut *tx)
+            .await?;
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            &self.sql("SELECT id, path FROM sounds WHERE path IS NOT NULL AND archive_codec IS NULL AND format IS NULL"),
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (id, path) in rows {
+            if let Some(format) = sniff_format(Path::new(&path)).ok().flatten() {
+                sqlx::query(&self.sql("UPDATE sounds SET format = ? WHERE id = ?"))
+                    .bind(format.as_str())
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Rename a sound's file in the library after its format
+    ///
+    /// The file is copied under its new name and the old one removed once the
+    /// sound points at the copy, so a crash leaves one of the two.
+    pub(crate) async fn fix_extension(&self, mismatch: &ExtensionMismatch) -> Result<()> {
+        let before = self.get_sound(&mismatch.sound_id).await?.metadata;
+        self.check_in_library(&before)?;
+        let source = self.library_file(&mismatch.path)?;
+        let target = self.library_file(&mismatch.corrected_path())?;
+        if target.exists() {
+            return Err(VaultError::FileSystem(format!(
+                "Can't rename {:?} after its format: {:?} exists",
+                source, target
+            )));
+        }
+
+        let op = self.begin_op(OpKind::ReplaceFile, &[&target], &[&source]).await?;
+        let result = async {
+            copy_atomic(&source, &target).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to copy file: {}", e))
+            })?;
+            self.record_file(&before, target.clone(), Some(&op), None).await
+        }
+        .await;
+        self.settle_op(&op, result).await
+    }
+}
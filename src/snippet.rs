@@ -0,0 +1,202 @@
+//! Excerpts of descriptions around the text a search matched
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use unicode_normalization::char::is_combining_mark;
+
+/// How [`snippet`] cuts and marks an excerpt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnippetOptions {
+    /// Characters, as seen on screen, kept on each side of the first match;
+    /// the excerpt then starts and ends on a whole word when it can
+    pub context: usize,
+
+    /// Written before each match, e.g. `<b>`
+    pub start_marker: String,
+
+    /// Written after each match, e.g. `</b>`
+    pub end_marker: String,
+
+    /// Written where the text was cut
+    pub ellipsis: String,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        Self {
+            context: 40,
+            start_marker: "<b>".to_string(),
+            end_marker: "</b>".to_string(),
+            ellipsis: "…".to_string(),
+        }
+    }
+}
+
+/// Whether a character belongs to the cluster of the one before it
+fn extends(c: char) -> bool {
+    is_combining_mark(c)
+        || matches!(
+            c,
+            // Zero width joiner, variation selectors, skin tones, tags
+            '\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}' | '\u{E0020}'..='\u{E007F}' | '\u{E0100}'..='\u{E01EF}'
+        )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// Byte offsets where the characters seen on screen start, and the length
+///
+/// Combining marks, emoji modifiers and the characters a zero width joiner
+/// binds stay with the character before them, flags' regional indicators go
+/// in pairs and CR LF is one.
+fn cluster_bounds(text: &str) -> Vec<usize> {
+    let mut bounds = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut indicators = 0;
+    for (i, c) in text.char_indices() {
+        let joined = match previous {
+            None => false,
+            Some('\u{200D}') => true,
+            Some('\r') => c == '\n',
+            Some(_) if is_regional_indicator(c) => indicators % 2 == 1,
+            Some(_) => extends(c),
+        };
+        if !joined {
+            bounds.push(i);
+        }
+        indicators = if is_regional_indicator(c) { indicators + 1 } else { 0 };
+        previous = Some(c);
+    }
+    bounds.push(text.len());
+    bounds
+}
+
+/// Byte ranges where `query` occurs in `text`, ignoring case
+fn occurrences(text: &str, query: &str) -> Vec<Range<usize>> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut found = Vec::new();
+    let mut start = 0;
+    while let Some(first) = text[start..].chars().next() {
+        let mut matched = 0;
+        let mut end = None;
+        'text: for (i, c) in text[start..].char_indices() {
+            for lower in c.to_lowercase() {
+                if lower != query[matched] {
+                    break 'text;
+                }
+                matched += 1;
+                if matched == query.len() {
+                    end = Some(start + i + c.len_utf8());
+                    break 'text;
+                }
+            }
+        }
+        match end {
+            Some(end) => {
+                found.push(start..end);
+                start = end;
+            }
+            None => start += first.len_utf8(),
+        }
+    }
+    found
+}
+
+/// Text with each run of whitespace written as a single space
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c.is_whitespace() {
+            true if collapsed.ends_with(' ') => {}
+            true => collapsed.push(' '),
+            false => collapsed.push(c),
+        }
+    }
+    collapsed
+}
+
+/// Excerpt of `text` around the first occurrence of `query`, with every
+/// occurrence in it marked
+///
+/// Case is ignored. The excerpt never splits a character seen on screen,
+/// like an accented letter written with a combining mark or an emoji
+/// sequence: a match inside one marks all of it. Whitespace is written as
+/// single spaces, and nothing is escaped. `None` when `query` doesn't occur.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{SnippetOptions, snippet};
+///
+/// let options = SnippetOptions { context: 20, start_marker: "[".into(), end_marker: "]".into(), ..Default::default() };
+/// let text = "Light rain on a tin roof 🌧️, then heavy RAIN and distant thunder";
+/// assert_eq!(snippet(text, "rain", &options).as_deref(), Some("Light [rain] on a tin roof 🌧️,…"));
+/// assert_eq!(snippet(text, "thunder", &options).as_deref(), Some("…RAIN and distant [thunder]"));
+///
+/// // A combining accent stays with its letter
+/// let text = "Cafe\u{301} ambience, cups and chatter";
+/// assert_eq!(snippet(text, "cafe", &options).as_deref(), Some("[Cafe\u{301}] ambience, cups and…"));
+/// assert_eq!(snippet(text, "rain", &options), None);
+/// ```
+pub fn snippet(text: &str, query: &str, options: &SnippetOptions) -> Option<String> {
+    let bounds = cluster_bounds(text);
+    let cluster_start = |offset: usize| bounds.partition_point(|bound| *bound <= offset) - 1;
+    let cluster_end = |offset: usize| bounds.partition_point(|bound| *bound < offset);
+
+    // Matches grown to whole clusters, overlapping ones merged
+    let mut matches: Vec<Range<usize>> = Vec::new();
+    for found in occurrences(text, query.trim()) {
+        let (start, end) = (cluster_start(found.start), cluster_end(found.end));
+        match matches.last_mut() {
+            Some(last) if start <= last.end => last.end = last.end.max(end),
+            _ => matches.push(start..end),
+        }
+    }
+    let first = matches.first()?.clone();
+
+    // Cut on clusters, then at the word boundaries within the context
+    let mut start = bounds[first.start.saturating_sub(options.context)];
+    let mut end = bounds[(first.end + options.context).min(bounds.len() - 1)];
+    if start > 0 {
+        let previous = text[..start].chars().next_back().is_some_and(char::is_whitespace);
+        if !previous && let Some(space) = text[start..bounds[first.start]].find(char::is_whitespace) {
+            start += space;
+        }
+    }
+    if end < text.len() {
+        let next = text[end..].chars().next().is_some_and(char::is_whitespace);
+        if !next && let Some(space) = text[bounds[first.end]..end].rfind(char::is_whitespace) {
+            end = bounds[first.end] + space;
+        }
+    }
+
+    let mut excerpt = String::new();
+    let mut position = start;
+    for found in matches.iter().map(|found| bounds[found.start]..bounds[found.end]) {
+        if found.start < start || found.end > end {
+            continue;
+        }
+        excerpt.push_str(&collapse_whitespace(&text[position..found.start]));
+        excerpt.push_str(&options.start_marker);
+        excerpt.push_str(&collapse_whitespace(&text[found.clone()]));
+        excerpt.push_str(&options.end_marker);
+        position = found.end;
+    }
+    excerpt.push_str(&collapse_whitespace(&text[position..end]));
+
+    let mut excerpt = excerpt.trim().to_string();
+    if start > 0 {
+        excerpt.insert_str(0, &options.ellipsis);
+    }
+    if end < text.len() {
+        excerpt.push_str(&options.ellipsis);
+    }
+    Some(excerpt)
+}
@@ -0,0 +1,441 @@
+//! Remote sources of sounds, Freesound being one of them
+
+use crate::audio::probe_file;
+use crate::context::OpContext;
+use crate::error::{Result, VaultError};
+use crate::journal::OpKind;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::{Availability, Sound, SoundMetadata, SoundSource};
+use crate::paths::{finish_temp, safe_file_name, temp_path, unique_path};
+use crate::sniff::{FileFormat, sniff_format};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// Custom metadata key of the name the source gives a downloaded sound's
+/// file
+pub const REMOTE_FILE_NAME: &str = "remote_file_name";
+
+/// Formats whose extension is taken off names
+const AUDIO_FORMATS: [FileFormat; 6] = [
+    FileFormat::Wav,
+    FileFormat::Aiff,
+    FileFormat::Caf,
+    FileFormat::Flac,
+    FileFormat::Ogg,
+    FileFormat::Mp3,
+];
+
+/// Future returned by [`RemoteSource`] methods
+pub type RemoteFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Registered sources, shared with the download worker
+pub(crate) type SharedSources = Arc<RwLock<Vec<Arc<dyn RemoteSource>>>>;
+
+/// A service sounds can be searched on and downloaded from
+///
+/// Sounds are described as [`SoundMetadata`] without a path, with
+/// `remote_id` set to their ID at the source. Their `source` is set by the
+/// vault from [`RemoteSource::name`].
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::{DescriptorRange, RemoteFuture, RemoteSource, SoundFilter, SoundMetadata, SoundVault, VaultConfig};
+/// use std::collections::HashMap;
+/// use std::path::Path;
+///
+/// /// A source serving a single sound
+/// struct Studio;
+///
+/// impl RemoteSource for Studio {
+///     fn name(&self) -> &str {
+///         "studio"
+///     }
+///
+///     fn search<'a>(&'a self, _query: &'a str, _page_size: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> {
+///         Box::pin(async move { Ok(vec![self.get_by_id("1").await?]) })
+///     }
+///
+///     fn get_by_id<'a>(&'a self, remote_id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+///         Box::pin(async move {
+///             Ok(SoundMetadata {
+///                 name: "Room tone.wav".to_string(),
+///                 remote_id: Some(remote_id.to_string()),
+///                 ..Default::default()
+///             })
+///         })
+///     }
+///
+///     fn download<'a>(&'a self, _remote_id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+///         Box::pin(async move { Ok(std::fs::write(target, b"room tone")?) })
+///     }
+///
+///     fn descriptors<'a>(&'a self, _remote_id: &'a str, names: &'a [String]) -> RemoteFuture<'a, HashMap<String, f64>> {
+///         let analysis = HashMap::from([("pitch".to_string(), 55.0)]);
+///         Box::pin(async move { Ok(analysis.into_iter().filter(|(name, _)| names.contains(name)).collect()) })
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+/// config.remote_descriptors = vec!["pitch".to_string(), "loudness".to_string()];
+/// let mut vault = SoundVault::new(config).await?;
+/// vault.add_remote_source(Box::new(Studio))?;
+///
+/// let results = vault.search_remote("room", 10).await?;
+/// assert!(results.errors.is_empty() && !results.sounds[0].is_cached);
+///
+/// let id = vault.download_remote("studio", "1").await?;
+/// assert_eq!(vault.download_remote("studio", "1").await?, id);
+/// assert!(vault.search_remote("room", 10).await?.sounds[0].is_cached);
+///
+/// // Descriptors the source didn't compute are absent, not zero
+/// let descriptors = vault.get_sound(&id).await?.metadata.descriptors;
+/// assert_eq!(descriptors, HashMap::from([("pitch".to_string(), 55.0)]));
+/// let mut filter = SoundFilter::default();
+/// filter.descriptors.insert("pitch".to_string(), DescriptorRange { min: Some(50.0), max: Some(60.0) });
+/// assert_eq!(vault.count(&filter).await?, 1);
+/// filter.descriptors.insert("loudness".to_string(), DescriptorRange { min: None, max: Some(0.0) });
+/// assert_eq!(vault.count(&filter).await?, 0);
+/// # Ok(())
+/// # }
+/// ```
+pub trait RemoteSource: Send + Sync {
+    /// Name identifying the source, stored with its sounds
+    fn name(&self) -> &str;
+
+    /// Search the source, returning at most `page_size` sounds
+    fn search<'a>(&'a self, query: &'a str, page_size: usize) -> RemoteFuture<'a, Vec<SoundMetadata>>;
+
+    /// Describe one sound of the source
+    fn get_by_id<'a>(&'a self, remote_id: &'a str) -> RemoteFuture<'a, SoundMetadata>;
+
+    /// Write the file of a sound to `target`
+    fn download<'a>(&'a self, remote_id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()>;
+
+    /// Values of the analysis descriptors `names` of a sound
+    ///
+    /// Descriptors the source didn't compute are left out. The default
+    /// implementation returns none.
+    fn descriptors<'a>(&'a self, remote_id: &'a str, names: &'a [String]) -> RemoteFuture<'a, HashMap<String, f64>> {
+        let _ = (remote_id, names);
+        Box::pin(async { Ok(HashMap::new()) })
+    }
+
+    /// Address of the page of a sound, recorded in its provenance
+    ///
+    /// The default implementation has none.
+    fn sound_url(&self, remote_id: &str) -> Option<String> {
+        let _ = remote_id;
+        None
+    }
+
+    /// Name the source gives the file of a sound described by `metadata`
+    ///
+    /// The default implementation is the sound's name.
+    fn file_name(&self, remote_id: &str, metadata: &SoundMetadata) -> String {
+        let _ = remote_id;
+        metadata.name.clone()
+    }
+}
+
+impl<T: RemoteSource + ?Sized> RemoteSource for Arc<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn search<'a>(&'a self, query: &'a str, page_size: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> {
+        (**self).search(query, page_size)
+    }
+
+    fn get_by_id<'a>(&'a self, remote_id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+        (**self).get_by_id(remote_id)
+    }
+
+    fn download<'a>(&'a self, remote_id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+        (**self).download(remote_id, target)
+    }
+
+    fn descriptors<'a>(&'a self, remote_id: &'a str, names: &'a [String]) -> RemoteFuture<'a, HashMap<String, f64>> {
+        (**self).descriptors(remote_id, names)
+    }
+
+    fn sound_url(&self, remote_id: &str) -> Option<String> {
+        (**self).sound_url(remote_id)
+    }
+
+    fn file_name(&self, remote_id: &str, metadata: &SoundMetadata) -> String {
+        (**self).file_name(remote_id, metadata)
+    }
+}
+
+/// Sounds found across all remote sources
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteSearchResults {
+    /// Sounds found, source by source; sounds already in the vault are
+    /// returned as stored, with their local ID
+    pub sounds: Vec<Sound>,
+
+    /// Sources whose search failed, with the reason
+    pub errors: Vec<(String, String)>,
+}
+
+impl LocalLibrary {
+    /// Find the local record of a remote sound
+    pub(crate) async fn find_remote_sound(&self, source: &SoundSource, remote_id: &str) -> Result<Option<String>> {
+        let found: Option<String> =
+            sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE source = ? AND remote_id = ? ORDER BY id LIMIT 1"))
+                .bind(source.as_str())
+                .bind(remote_id)
+                .fetch_optional(&self.reader)
+                .await?;
+
+        // Freesound sounds recorded before remote IDs existed only have a Freesound ID
+        match (found, source, remote_id.parse::<i32>()) {
+            (None, SoundSource::Freesound, Ok(freesound_id)) => self.find_freesound_sound(freesound_id).await,
+            (found, _, _) => Ok(found),
+        }
+    }
+
+    /// Search every source, collecting failures instead of stopping at them
+    ///
+    /// Running out of the time `context` allows, or being cancelled, does stop
+    /// the search.
+    pub async fn search_remote(
+        &self,
+        sources: &[Arc<dyn RemoteSource>],
+        query: &str,
+        page_size: usize,
+        context: &OpContext,
+    ) -> Result<RemoteSearchResults> {
+        let deadline = context.start("search_remote");
+        let mut results = RemoteSearchResults::default();
+
+        for (done, source) in sources.iter().enumerate() {
+            let progress = || format!("{} of {} sources searched", done, sources.len());
+            let found = match deadline.bound(source.search(query, page_size), progress).await {
+                Err(e @ (VaultError::Timeout { .. } | VaultError::Cancelled { .. })) => return Err(e),
+                Ok(found) => found,
+                Err(e) => {
+                    results.errors.push((source.name().to_string(), e.to_string()));
+                    continue;
+                }
+            };
+
+            let kind = SoundSource::remote(source.name());
+            for mut metadata in found {
+                metadata.source = kind.clone();
+                let local = match &metadata.remote_id {
+                    Some(remote_id) => self.find_remote_sound(&kind, remote_id).await?,
+                    None => None,
+                };
+                let sound = match local {
+                    Some(id) => self.get_sound(&id).await?,
+                    None => Sound {
+                        metadata,
+                        preview_url: None,
+                        is_cached: false,
+                        availability: Availability::NotDownloaded,
+                        download_url: None,
+                    },
+                };
+                results.sounds.push(sound);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Fetch the configured analysis descriptors of a remote sound
+    ///
+    /// Descriptors are a bonus to the download: if they can't be fetched, the
+    /// sound goes without, like one the source never analyzed.
+    pub(crate) async fn fetch_descriptors(&self, source: &dyn RemoteSource, remote_id: &str) -> HashMap<String, f64> {
+        if self.remote_descriptors.is_empty() {
+            return HashMap::new();
+        }
+        source.descriptors(remote_id, &self.remote_descriptors).await.unwrap_or_default()
+    }
+
+    /// Download a remote sound into the library, unless it is already there
+    ///
+    /// A sound the vault tracks without a file, e.g. from a subscription,
+    /// gets the file; otherwise a new sound is created, named after the
+    /// cleaned-up title unless remote file names are kept. The file is named
+    /// after the sound with the extension of the format read from it, and
+    /// numbered if its folder already holds that name. The license policy
+    /// is checked before the file is fetched.
+    ///
+    /// # Returns
+    ///
+    /// ID of the local sound
+    pub async fn download_remote(&self, source: &dyn RemoteSource, remote_id: &str) -> Result<String> {
+        let kind = SoundSource::remote(source.name());
+        let existing = match self.find_remote_sound(&kind, remote_id).await? {
+            Some(id) => Some(self.get_sound(&id).await?.metadata),
+            None => None,
+        };
+        if let Some(metadata) = &existing
+            && metadata.path.as_ref().is_some_and(|path| path.exists())
+        {
+            return Ok(metadata.id.clone());
+        }
+
+        let mut metadata = match &existing {
+            Some(metadata) => metadata.clone(),
+            None => source.get_by_id(remote_id).await?,
+        };
+        let remote_file_name = source.file_name(remote_id, &metadata);
+        if existing.is_none() {
+            metadata.id = Uuid::new_v4().to_string();
+            let title = clean_title(&remote_file_name);
+            if !self.keep_remote_file_names && !title.is_empty() {
+                metadata.name = title;
+            }
+            metadata.set_custom(REMOTE_FILE_NAME, &remote_file_name);
+        }
+        let flag_license = self.check_license(&metadata)?;
+
+        let dir = self.library_file(&self.library_path.join(&metadata.id))?;
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to create directory: {}", e))
+        })?;
+        let file_name = match self.keep_remote_file_names {
+            true => safe_file_name(&remote_file_name, remote_id),
+            false => safe_file_name(&metadata.name, remote_id),
+        };
+        // The download is staged until its format names the file
+        let staging = dir.join(split_audio_extension(&file_name).0);
+
+        let op = self.begin_op(OpKind::Download, &[&staging], &[]).await?;
+        let mut reservation = None;
+        let result = async {
+            // Only a complete download takes the final name
+            let temp = temp_path(&staging);
+            let transfer = self.governor.transfer().await?;
+            source.download(remote_id, &temp).await?;
+            drop(transfer);
+            reservation = self
+                .reserve_space(std::fs::metadata(&temp)?.len())
+                .await
+                .inspect_err(|_| {
+                    let _ = std::fs::remove_file(&temp);
+                })?;
+
+            let format = sniff_format(&temp).ok().flatten();
+            let target_path = self.download_path(&dir, &file_name, format);
+            self.add_created(&op, &target_path).await?;
+            finish_temp(&temp, &target_path).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to save download: {}", e))
+            })?;
+
+            let provenance = self.remote_provenance(source, remote_id);
+            match &existing {
+                Some(before) => self.record_file(before, target_path.clone(), Some(&op), Some(&provenance)).await,
+                None => {
+                    metadata.source = kind;
+                    metadata.remote_id = Some(remote_id.to_string());
+                    metadata.external = false;
+                    metadata.hash = Some(hash_file(&target_path)?);
+                    if let Ok(info) = probe_file(&target_path) {
+                        if metadata.duration == 0.0 {
+                            metadata.duration = info.duration();
+                        }
+                        metadata.channels = Some(info.channels);
+                        metadata.sample_rate = Some(info.sample_rate);
+                    }
+                    metadata.path = Some(target_path.clone());
+                    metadata.descriptors = self.fetch_descriptors(source, remote_id).await;
+                    self.insert_sound(&metadata, Some(&op), Some(&provenance)).await
+                }
+            }
+        }
+        .await;
+        let result = self.settle_op(&op, result).await;
+        self.release_space(reservation).await?;
+        result?;
+
+        if let Some(before) = &existing
+            && before.descriptors.is_empty()
+        {
+            let descriptors = self.fetch_descriptors(source, remote_id).await;
+            let mut conn = self.db.acquire().await?;
+            self.save_descriptors(&mut conn, &before.id, &descriptors).await?;
+        }
+        if flag_license {
+            self.flag_license(&metadata.id).await?;
+        }
+        self.collect_download(&metadata.id).await?;
+        self.offload_new_sound(&metadata.id).await;
+
+        Ok(metadata.id)
+    }
+    /// Path of a downloaded file in `dir`, from the name it was to have and
+    /// the format read from it
+    ///
+    /// The extension is the format's, unless the remote file name is kept:
+    /// then it's only replaced if extensions are fixed and it disagrees. A
+    /// file of unknown format keeps the extension of the name.
+    fn download_path(&self, dir: &Path, file_name: &str, format: Option<FileFormat>) -> PathBuf {
+        let (stem, extension) = match self.keep_remote_file_names {
+            true => match file_name.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+                _ => (file_name, None),
+            },
+            false => split_audio_extension(file_name),
+        };
+        let extension = match format {
+            Some(format)
+                if !self.keep_remote_file_names
+                    || self.fix_extensions && !extension.is_some_and(|extension| format.matches_extension(extension)) =>
+            {
+                Some(format.extension())
+            }
+            _ => extension,
+        };
+        unique_path(dir, stem, extension)
+    }
+}
+
+/// Name of a sound from the name a source gives its file
+///
+/// The `<id>__<user>__` prefix of Freesound originals and an audio
+/// extension are dropped, and underscores, or the hyphens of a name without
+/// spaces, become spaces: `475832__someuser__wind-gust-03.flac` is named
+/// `wind gust 03`.
+fn clean_title(file_name: &str) -> String {
+    let mut title = split_audio_extension(file_name.trim()).0;
+    let mut parts = title.splitn(3, "__");
+    if let (Some(id), Some(_), Some(rest)) = (parts.next(), parts.next(), parts.next())
+        && !id.is_empty()
+        && id.bytes().all(|byte| byte.is_ascii_digit())
+    {
+        title = rest;
+    }
+
+    let title = title.replace('_', " ");
+    let title = match title.contains(' ') {
+        true => title,
+        false => title.replace('-', " "),
+    };
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Split a file name into its stem and audio extension, if it has one
+fn split_audio_extension(file_name: &str) -> (&str, Option<&str>) {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty() && AUDIO_FORMATS.iter().any(|format| format.matches_extension(extension)) =>
+        {
+            (stem, Some(extension))
+        }
+        _ => (file_name, None),
+    }
+}
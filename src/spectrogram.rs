@@ -0,0 +1,165 @@
+//! Spectrogram thumbnails
+
+use crate::artifacts::ArtifactKind;
+use crate::audio::{ChannelMix, decode, downmix};
+use crate::error::{Result, VaultError};
+use crate::integrity::{PREVIEW_DIR, SPECTROGRAM_PREFIX};
+use crate::local::LocalLibrary;
+use crate::paths::write_atomic;
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Largest width or height of a spectrogram, in pixels
+const MAX_DIMENSION: u32 = 4096;
+
+/// Level shown black, in decibels below the loudest bin
+const DYNAMIC_RANGE_DB: f32 = 90.0;
+
+/// Colors of the levels from silent to loudest
+const PALETTE: [[f32; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [60.0, 15.0, 110.0],
+    [180.0, 40.0, 110.0],
+    [250.0, 140.0, 30.0],
+    [255.0, 250.0, 190.0],
+];
+
+impl LocalLibrary {
+    /// Render a spectrogram of a sound as a PNG image
+    ///
+    /// Time runs left to right and frequency bottom to top, up to half the
+    /// sample rate; channels are mixed down first. Images are cached next to
+    /// the sound's file for each size and rendered again when the file's
+    /// content changes, or after [`gc_artifacts`](Self::gc_artifacts) evicted
+    /// them. Rendering runs on the background job queue.
+    pub async fn get_spectrogram(&self, id: &str, width: u32, height: u32) -> Result<Vec<u8>> {
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(VaultError::InvalidOperation(format!(
+                "Invalid spectrogram size: {}x{}",
+                width, height
+            )));
+        }
+
+        let metadata = self.get_sound(id).await?.metadata;
+        let hash = match &metadata.hash {
+            Some(hash) => hash.clone(),
+            None => self.content_hash(&metadata)?,
+        };
+
+        let dir = self.image_dir(id, metadata.path.as_deref())?;
+        let size_prefix = format!("{}{}x{}-", SPECTROGRAM_PREFIX, width, height);
+        let cached = dir.join(format!("{}{}.png", size_prefix, &hash[..hash.len().min(16)]));
+        if let Ok(bytes) = std::fs::read(&cached) {
+            self.touch_artifact(ArtifactKind::Spectrogram, id, &cached).await?;
+            return Ok(bytes);
+        }
+
+        self.stage_blob(&metadata).await?;
+        let bytes = self.sound_bytes(&metadata)?;
+        let png = self.jobs.run(move || render(&bytes, width, height)).await?;
+
+        // Drop images of older content at this size
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&size_prefix)
+                    && std::fs::remove_file(entry.path()).is_ok()
+                {
+                    self.forget_artifact(&entry.path()).await?;
+                }
+            }
+        }
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to create spectrogram directory: {}", e))
+        })?;
+        write_atomic(&cached, &png).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to write spectrogram: {}", e))
+        })?;
+        self.touch_artifact(ArtifactKind::Spectrogram, id, &cached).await?;
+
+        Ok(png)
+    }
+
+    /// Directory caching a sound's spectrograms and waveform images
+    ///
+    /// That's the folder named after the sound holding its file, or the
+    /// preview directory for files stored elsewhere.
+    pub(crate) fn image_dir(&self, id: &str, path: Option<&Path>) -> Result<PathBuf> {
+        let folder = path
+            .and_then(|path| path.parent())
+            .filter(|dir| dir.file_name().is_some_and(|name| name == id))
+            .and_then(|dir| self.library_file(dir).ok());
+
+        match folder {
+            Some(folder) => Ok(folder),
+            None => self.library_file(&self.library_path.join(PREVIEW_DIR).join(id)),
+        }
+    }
+}
+
+/// Decode a sound and draw its spectrogram as PNG
+fn render(bytes: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    let (info, samples) = decode(&mut Cursor::new(bytes))?;
+    let mono = downmix(&samples, info.channels, ChannelMix::Mono);
+
+    let fft_size = (height as usize * 2).next_power_of_two().clamp(256, 8192);
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(fft_size);
+    let window: Vec<f32> = (0..fft_size)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / fft_size as f32).cos())
+        .collect();
+
+    // Loudest bin per pixel, column by column
+    let bins = fft_size / 2;
+    let mut levels = vec![0f32; width as usize * height as usize];
+    let mut buffer = vec![Complex::new(0f32, 0f32); fft_size];
+    for x in 0..width as usize {
+        let center = (x as f64 + 0.5) / width as f64 * mono.len() as f64;
+        let start = center as isize - fft_size as isize / 2;
+        for (i, value) in buffer.iter_mut().enumerate() {
+            let sample = usize::try_from(start + i as isize).ok().and_then(|index| mono.get(index));
+            *value = Complex::new(sample.copied().unwrap_or(0.0) * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+
+        for y in 0..height as usize {
+            let low = y * bins / height as usize;
+            let high = ((y + 1) * bins / height as usize).max(low + 1);
+            let power = buffer[low..high].iter().map(|c| c.norm_sqr()).fold(0f32, f32::max);
+            // Row 0 is the top of the image
+            levels[(height as usize - 1 - y) * width as usize + x] = power;
+        }
+    }
+
+    // Silence stays black
+    let loudest = levels.iter().copied().fold(0f32, f32::max);
+    let mut pixels = Vec::with_capacity(levels.len() * 3);
+    for power in levels {
+        let level = if loudest > 0.0 {
+            1.0 + 10.0 * (power / loudest).log10() / DYNAMIC_RANGE_DB
+        } else {
+            0.0
+        };
+        pixels.extend(color(level));
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| VaultError::InvalidOperation(format!("Failed to encode spectrogram: {}", e)))?;
+
+    Ok(png)
+}
+
+/// Color of a level between 0 (silent) and 1 (loudest)
+fn color(level: f32) -> [u8; 3] {
+    let position = level.clamp(0.0, 1.0) * (PALETTE.len() - 1) as f32;
+    let index = (position.floor() as usize).min(PALETTE.len() - 2);
+    let fraction = position - index as f32;
+    let (a, b) = (PALETTE[index], PALETTE[index + 1]);
+    [0, 1, 2].map(|c| (a[c] + (b[c] - a[c]) * fraction).round() as u8)
+}
@@ -0,0 +1,276 @@
+//! Counts, sizes and durations of sounds grouped by a metadata value, with
+//! soft limits per value
+//!
+//! Soft limits are only reported, in the health report; nothing is refused
+//! for going over one.
+
+use crate::browse::{push_custom, push_field};
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::query::SoundFilter;
+use crate::review::SoundStatus;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Row};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What [`SoundVault::stats_by`](crate::SoundVault::stats_by) groups
+/// sounds by
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsDimension {
+    /// The custom `author` value, or the Freesound uploader
+    Author,
+    /// The license
+    License,
+    /// Where the sound came from: `local`, `freesound` or the name of a
+    /// remote source
+    Source,
+    /// The extension of the sound's file
+    Format,
+    /// The review status: `active`, `inbox` or `rejected`; the only
+    /// dimension counting the sounds under review
+    Status,
+    /// The value of a custom metadata key
+    Custom(String),
+}
+
+impl StatsDimension {
+    /// Name stored with the soft limits of the dimension
+    fn key(&self) -> String {
+        match self {
+            Self::Author => "author".to_string(),
+            Self::License => "license".to_string(),
+            Self::Source => "source".to_string(),
+            Self::Format => "format".to_string(),
+            Self::Status => "status".to_string(),
+            Self::Custom(key) => format!("custom:{}", key),
+        }
+    }
+
+    /// Dimension from its stored name
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "author" => Some(Self::Author),
+            "license" => Some(Self::License),
+            "source" => Some(Self::Source),
+            "format" => Some(Self::Format),
+            "status" => Some(Self::Status),
+            _ => key.strip_prefix("custom:").map(|key| Self::Custom(key.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for StatsDimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Custom(key) => f.write_str(key),
+            other => f.write_str(&other.key()),
+        }
+    }
+}
+
+/// Most a value of a dimension should hold; unset bounds don't apply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoftLimit {
+    /// Most sounds
+    pub max_sounds: Option<u64>,
+
+    /// Most bytes of recorded file size
+    pub max_bytes: Option<u64>,
+
+    /// Most seconds of audio
+    pub max_duration: Option<f64>,
+}
+
+/// Sounds sharing a value of a dimension
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DimensionStats {
+    /// The value; `None` groups the sounds without one
+    pub value: Option<String>,
+
+    /// Number of sounds
+    pub count: u64,
+
+    /// Recorded size of their files in bytes, referenced files included
+    pub bytes: u64,
+
+    /// Total duration in seconds
+    pub duration: f64,
+
+    /// Soft limit set for the value, if any
+    pub limit: Option<SoftLimit>,
+}
+
+impl DimensionStats {
+    /// Describe each bound of the soft limit the value is over, e.g.
+    /// `"12 sounds of 10"`; empty if none
+    pub fn exceeded(&self) -> Vec<String> {
+        let Some(limit) = self.limit else {
+            return Vec::new();
+        };
+
+        let mut exceeded = Vec::new();
+        if let Some(max) = limit.max_sounds
+            && self.count > max
+        {
+            exceeded.push(format!("{} sounds of {}", self.count, max));
+        }
+        if let Some(max) = limit.max_bytes
+            && self.bytes > max
+        {
+            exceeded.push(format!("{} bytes of {}", self.bytes, max));
+        }
+        if let Some(max) = limit.max_duration
+            && self.duration > max
+        {
+            exceeded.push(format!("{:.1} s of {:.1} s", self.duration, max));
+        }
+        exceeded
+    }
+
+    /// Check whether the value is over its soft limit
+    pub fn is_over_limit(&self) -> bool {
+        !self.exceeded().is_empty()
+    }
+}
+
+impl LocalLibrary {
+    /// Count the sounds, bytes and seconds under each value of a dimension
+    ///
+    /// # Returns
+    ///
+    /// One entry per value, ordered by value with the sounds lacking one
+    /// last
+    pub async fn stats_by(&self, dimension: &StatsDimension) -> Result<Vec<DimensionStats>> {
+        let mut builder = QueryBuilder::new("SELECT ");
+        match dimension {
+            StatsDimension::Author => push_field(&mut builder, &self.tables, "author"),
+            StatsDimension::License => push_field(&mut builder, &self.tables, "license"),
+            StatsDimension::Format => push_field(&mut builder, &self.tables, "format"),
+            // Sounds stored before sources were recorded are local
+            StatsDimension::Source => {
+                builder.push("COALESCE(source, 'local')");
+            }
+            StatsDimension::Status => {
+                builder.push("status");
+            }
+            StatsDimension::Custom(key) => push_custom(&mut builder, &self.tables, key),
+        }
+        builder.push(self.sql(
+            " AS value, COUNT(*) AS count, COALESCE(SUM(file_size), 0) AS bytes, \
+             COALESCE(SUM(duration), 0.0) AS duration FROM sounds",
+        ));
+        let filter = match dimension {
+            StatsDimension::Status => SoundFilter {
+                statuses: vec![SoundStatus::Active, SoundStatus::Inbox, SoundStatus::Rejected],
+                ..Default::default()
+            },
+            _ => SoundFilter::default(),
+        };
+        filter.push_where(&mut builder, &self.tables);
+        builder.push(" GROUP BY value ORDER BY value IS NULL, value");
+        let rows = builder.build().fetch_all(&self.reader).await?;
+
+        let limits = self.list_soft_limits(dimension).await?;
+        rows.iter()
+            .map(|row| {
+                let value: Option<String> = row.try_get("value")?;
+                Ok(DimensionStats {
+                    limit: value.as_ref().and_then(|value| limits.get(value).copied()),
+                    value,
+                    count: row.try_get::<i64, _>("count")? as u64,
+                    bytes: row.try_get::<i64, _>("bytes")? as u64,
+                    duration: row.try_get("duration")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Set the soft limit of a value of a dimension, replacing any
+    pub async fn set_soft_limit(&self, dimension: &StatsDimension, value: &str, limit: &SoftLimit) -> Result<()> {
+        if matches!(dimension, StatsDimension::Custom(key) if key.is_empty()) {
+            return Err(VaultError::InvalidOperation("Custom dimension has no key".to_string()));
+        }
+        if limit.max_duration.is_some_and(|max| max.is_nan() || max < 0.0) {
+            return Err(VaultError::InvalidOperation(format!(
+                "Invalid soft limit duration: {:?}",
+                limit.max_duration
+            )));
+        }
+
+        sqlx::query(&self.sql(
+            r#"
+            INSERT INTO soft_limits (dimension, value, max_sounds, max_bytes, max_duration) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(dimension, value) DO UPDATE SET
+                max_sounds = excluded.max_sounds,
+                max_bytes = excluded.max_bytes,
+                max_duration = excluded.max_duration
+            "#,
+        ))
+        .bind(dimension.key())
+        .bind(value)
+        .bind(limit.max_sounds.map(|max| max as i64))
+        .bind(limit.max_bytes.map(|max| max as i64))
+        .bind(limit.max_duration)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove the soft limit of a value of a dimension
+    ///
+    /// # Returns
+    ///
+    /// Whether there was one
+    pub async fn remove_soft_limit(&self, dimension: &StatsDimension, value: &str) -> Result<bool> {
+        let removed = sqlx::query(&self.sql("DELETE FROM soft_limits WHERE dimension = ? AND value = ?"))
+            .bind(dimension.key())
+            .bind(value)
+            .execute(&self.db)
+            .await?;
+        Ok(removed.rows_affected() > 0)
+    }
+
+    /// Soft limits set for the values of a dimension, by value
+    pub async fn list_soft_limits(&self, dimension: &StatsDimension) -> Result<BTreeMap<String, SoftLimit>> {
+        let rows = sqlx::query(&self.sql(
+            "SELECT value, max_sounds, max_bytes, max_duration FROM soft_limits WHERE dimension = ?",
+        ))
+        .bind(dimension.key())
+        .fetch_all(&self.reader)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let limit = SoftLimit {
+                    max_sounds: row.try_get::<Option<i64>, _>("max_sounds")?.map(|max| max as u64),
+                    max_bytes: row.try_get::<Option<i64>, _>("max_bytes")?.map(|max| max as u64),
+                    max_duration: row.try_get("max_duration")?,
+                };
+                Ok((row.try_get("value")?, limit))
+            })
+            .collect()
+    }
+
+    /// Describe each value over its soft limit, for the health report
+    pub(crate) async fn soft_limit_warnings(&self) -> Result<Vec<String>> {
+        let dimensions: Vec<String> =
+            sqlx::query_scalar(&self.sql("SELECT DISTINCT dimension FROM soft_limits ORDER BY dimension"))
+                .fetch_all(&self.reader)
+                .await?;
+
+        let mut warnings = Vec::new();
+        for dimension in dimensions.iter().filter_map(|key| StatsDimension::from_key(key)) {
+            for stats in self.stats_by(&dimension).await? {
+                let exceeded = stats.exceeded();
+                if let (Some(value), false) = (&stats.value, exceeded.is_empty()) {
+                    warnings.push(format!("{} {:?} is over its soft limit: {}", dimension, value, exceeded.join(", ")));
+                }
+            }
+        }
+        Ok(warnings)
+    }
+}
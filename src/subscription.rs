@@ -0,0 +1,297 @@
+//! Collections kept in sync with a saved Freesound search
+
+use crate::audio::probe_file;
+use crate::context::{Deadline, OpContext};
+use crate::error::{Result, VaultError};
+use crate::journal::OpKind;
+use crate::local::{LocalLibrary, hash_file};
+use crate::models::Collection;
+use crate::remote::{self, FreesoundManager};
+use chrono::{DateTime, Utc};
+use freesound_rs::SortOption;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use uuid::Uuid;
+
+/// Number of newest results checked on each sync
+const SUBSCRIPTION_PAGE_SIZE: usize = 50;
+
+/// A collection fed by a saved Freesound search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteSubscription {
+    /// Unique identifier of the subscription
+    pub id: String,
+
+    /// Collection receiving the sounds
+    pub collection_id: String,
+
+    /// Search text
+    pub query: String,
+
+    /// Filter in Freesound's filter syntax
+    pub filter: Option<String>,
+
+    /// Download new sounds instead of tracking them as remote members
+    pub auto_download: bool,
+
+    /// When the subscription was last synced
+    pub last_synced_at: Option<DateTime<Utc>>,
+}
+
+/// Outcome of [`SoundVault::sync_subscriptions`](crate::SoundVault::sync_subscriptions)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncReport {
+    /// One entry per subscription
+    pub subscriptions: Vec<SubscriptionSync>,
+}
+
+/// Outcome of syncing one subscription
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionSync {
+    /// ID of the subscription
+    pub subscription_id: String,
+
+    /// Collection receiving the sounds
+    pub collection_id: String,
+
+    /// IDs of the sounds added to the collection
+    pub added: Vec<String>,
+
+    /// Number of added sounds that were downloaded
+    pub downloaded: usize,
+
+    /// Added sounds tracked without their file because the license policy
+    /// blocked the download, with the reason
+    pub blocked: Vec<(String, String)>,
+
+    /// Number of Freesound API requests made
+    pub api_requests: u32,
+
+    /// Why the sync stopped early, if it did
+    pub error: Option<String>,
+}
+
+impl SyncReport {
+    /// Total number of sounds added
+    pub fn added(&self) -> usize {
+        self.subscriptions.iter().map(|s| s.added.len()).sum()
+    }
+
+    /// Total number of Freesound API requests made
+    pub fn api_requests(&self) -> u32 {
+        self.subscriptions.iter().map(|s| s.api_requests).sum()
+    }
+}
+
+impl LocalLibrary {
+    /// Create a collection fed by a Freesound search
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the collection
+    /// * `query` - Search text
+    /// * `filter` - Optional filter in Freesound's filter syntax
+    /// * `auto_download` - Download new sounds when syncing
+    pub async fn create_remote_subscription(
+        &self,
+        name: &str,
+        query: &str,
+        filter: Option<&str>,
+        auto_download: bool,
+    ) -> Result<RemoteSubscription> {
+        let collection_id = self.add_collection(&Collection::new(name, "")).await?;
+
+        let subscription = RemoteSubscription {
+            id: Uuid::new_v4().to_string(),
+            collection_id,
+            query: query.to_string(),
+            filter: filter.map(str::to_string),
+            auto_download,
+            last_synced_at: None,
+        };
+
+        sqlx::query(
+            &self.sql(r#"
+            INSERT INTO subscriptions (id, collection_id, query, filter, auto_download)
+            VALUES (?, ?, ?, ?, ?)
+            "#),
+        )
+        .bind(&subscription.id)
+        .bind(&subscription.collection_id)
+        .bind(&subscription.query)
+        .bind(&subscription.filter)
+        .bind(subscription.auto_download)
+        .execute(&self.db)
+        .await?;
+
+        Ok(subscription)
+    }
+
+    /// List saved remote subscriptions
+    pub async fn list_subscriptions(&self) -> Result<Vec<RemoteSubscription>> {
+        let rows = sqlx::query(
+            &self.sql(r#"
+            SELECT id, collection_id, query, filter, auto_download, last_synced_at
+            FROM subscriptions ORDER BY created_at, id
+            "#),
+        )
+        .fetch_all(&self.reader)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(RemoteSubscription {
+                    id: row.try_get("id")?,
+                    collection_id: row.try_get("collection_id")?,
+                    query: row.try_get("query")?,
+                    filter: row.try_get("filter")?,
+                    auto_download: row.try_get("auto_download")?,
+                    last_synced_at: row.try_get("last_synced_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Run every saved search and add the sounds that appeared since the last sync
+    ///
+    /// Sounds a subscription has seen before are never added again, so members
+    /// the user removed stay removed; nothing is ever removed by a sync.
+    /// Each sound is added on its own: if `context` stops the sync, the
+    /// sounds added so far are kept and the next sync picks up the rest.
+    pub async fn sync_subscriptions(&self, remote: &FreesoundManager, context: &OpContext) -> Result<SyncReport> {
+        let deadline = context.start("sync_subscriptions");
+        let mut report = SyncReport::default();
+
+        let subscriptions = self.list_subscriptions().await?;
+        let total = subscriptions.len();
+        for subscription in subscriptions {
+            let mut sync = SubscriptionSync {
+                subscription_id: subscription.id.clone(),
+                collection_id: subscription.collection_id.clone(),
+                ..Default::default()
+            };
+            let done = report.subscriptions.len();
+            let progress = |sync: &SubscriptionSync| {
+                format!("{} of {} subscriptions synced, {} sounds added to the next", done, total, sync.added.len())
+            };
+            match self.sync_subscription(remote, &subscription, &mut sync, &deadline, progress).await {
+                Ok(()) => {}
+                Err(e @ (VaultError::Timeout { .. } | VaultError::Cancelled { .. })) => return Err(e),
+                Err(e) => sync.error = Some(e.to_string()),
+            }
+            report.subscriptions.push(sync);
+        }
+
+        Ok(report)
+    }
+
+    /// Sync one subscription, recording progress as it goes
+    async fn sync_subscription(
+        &self,
+        remote: &FreesoundManager,
+        subscription: &RemoteSubscription,
+        sync: &mut SubscriptionSync,
+        deadline: &Deadline<'_>,
+        progress: impl Fn(&SubscriptionSync) -> String,
+    ) -> Result<()> {
+        deadline.check(|| progress(sync))?;
+        sync.api_requests += 1;
+        let search = async {
+            let results = remote
+                .search(
+                    &subscription.query,
+                    subscription.filter.as_deref(),
+                    SortOption::CreatedDesc,
+                    SUBSCRIPTION_PAGE_SIZE,
+                )
+                .await?;
+            Ok::<_, VaultError>(results)
+        };
+        let results = deadline.bound(search, || progress(sync)).await?;
+
+        for sound in results {
+            deadline.check(|| progress(sync))?;
+            let seen: Option<i64> = sqlx::query_scalar(
+                &self.sql("SELECT 1 FROM subscription_seen WHERE subscription_id = ? AND freesound_id = ?"),
+            )
+            .bind(&subscription.id)
+            .bind(sound.id)
+            .fetch_optional(&self.reader)
+            .await?;
+            if seen.is_some() {
+                continue;
+            }
+
+            let id = match self.find_freesound_sound(sound.id).await? {
+                Some(id) => id,
+                None => {
+                    let mut metadata = remote::to_metadata(&sound);
+                    metadata.id = Uuid::new_v4().to_string();
+                    if !self.remote_descriptors.is_empty() {
+                        sync.api_requests += 1;
+                        metadata.descriptors = self.fetch_descriptors(remote, &sound.id.to_string()).await;
+                    }
+
+                    let license = subscription.auto_download.then(|| self.check_license(&metadata));
+                    if let Some(Ok(flag_license)) = license {
+                        sync.api_requests += 1;
+                        let file_name = remote::file_name(&sound);
+                        let target_path = self.library_path.join(&metadata.id).join(&file_name);
+                        let op = self.begin_op(OpKind::Download, &[&target_path], &[]).await?;
+                        let result = async {
+                            let transfer = self.governor.transfer().await?;
+                            let path = remote.download(sound.id, &metadata.id, &file_name).await?;
+                            drop(transfer);
+                            metadata.hash = Some(hash_file(&path)?);
+                            if let Ok(info) = probe_file(&path) {
+                                metadata.channels = Some(info.channels);
+                                metadata.sample_rate = Some(info.sample_rate);
+                            }
+                            metadata.path = Some(path);
+                            let provenance = self.remote_provenance(remote, &sound.id.to_string());
+                            self.insert_sound(&metadata, Some(&op), Some(&provenance)).await
+                        }
+                        .await;
+                        self.settle_op(&op, result).await?;
+                        if flag_license {
+                            self.flag_license(&metadata.id).await?;
+                        }
+                        self.collect_download(&metadata.id).await?;
+                        sync.downloaded += 1;
+                    } else {
+                        if let Some(Err(e)) = license {
+                            sync.blocked.push((metadata.id.clone(), e.to_string()));
+                        }
+                        self.insert_sound(&metadata, None, None).await?;
+                    }
+                    metadata.id
+                }
+            };
+
+            let member: Option<i64> =
+                sqlx::query_scalar(&self.sql("SELECT 1 FROM collection_sounds WHERE collection_id = ? AND sound_id = ?"))
+                    .bind(&subscription.collection_id)
+                    .bind(&id)
+                    .fetch_optional(&self.reader)
+                    .await?;
+            if member.is_none() {
+                self.add_sound_to_collection(&id, &subscription.collection_id).await?;
+                sync.added.push(id);
+            }
+
+            sqlx::query(&self.sql("INSERT OR IGNORE INTO subscription_seen (subscription_id, freesound_id) VALUES (?, ?)"))
+                .bind(&subscription.id)
+                .bind(sound.id)
+                .execute(&self.db)
+                .await?;
+        }
+
+        sqlx::query(&self.sql("UPDATE subscriptions SET last_synced_at = ? WHERE id = ?"))
+            .bind(Utc::now())
+            .bind(&subscription.id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
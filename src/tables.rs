@@ -0,0 +1,186 @@
+//! Names of the vault's tables, prefixed so they can share a database
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use sqlx::{Pool, Sqlite};
+use std::borrow::Cow;
+
+/// Tables and indexes of the schema, as named in the SQL of this crate
+const SCHEMA_NAMES: &[&str] = &[
+    "artifacts",
+    "audit_log",
+    "audit_log_entity",
+    "audit_log_operation",
+    "change_feed",
+    "checkouts",
+    "collection_sounds",
+    "collections",
+    "custom_fields",
+    "download_queue",
+    "import_templates",
+    "localized_text",
+    "merged_sounds",
+    "metadata",
+    "metadata_auto_collect",
+    "objects",
+    "pending_ops",
+    "permissions",
+    "provenance",
+    "provenance_sound",
+    "quota_reservations",
+    "soft_limits",
+    "sound_descriptors",
+    "sound_descriptors_value",
+    "sound_group_members",
+    "sound_group_members_sound",
+    "sound_groups",
+    "sound_trigrams",
+    "sound_trigrams_sound",
+    "sound_uses",
+    "sound_uses_location",
+    "sounds",
+    "sounds_hash",
+    "sounds_object_hash",
+    "sounds_slug",
+    "subscription_seen",
+    "subscriptions",
+    "sync_sources",
+    "tag_aliases",
+    "tag_parents",
+    "vault_info",
+];
+
+/// Table every installation has, recognizing one and its prefix
+const MARKER_TABLE: &str = "collection_sounds";
+
+/// Table and index names with the configured prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tables {
+    prefix: String,
+}
+
+impl Tables {
+    /// Names starting with `prefix`, which must be empty or an identifier
+    pub(crate) fn new(prefix: &str) -> Result<Self> {
+        let valid = prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !prefix.starts_with(|c: char| c.is_ascii_digit());
+        if !valid {
+            return Err(VaultError::Config(format!(
+                "Invalid table prefix {:?}: use letters, digits and underscores",
+                prefix
+            )));
+        }
+
+        Ok(Self {
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// The prefix
+    pub(crate) fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Name of a table or index
+    pub(crate) fn name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// Prefix the names of tables and indexes in a statement
+    ///
+    /// Names are matched as whole identifiers outside quotes, so string
+    /// literals such as `'sound'` and quoted aliases are left alone.
+    pub(crate) fn sql<'a>(&self, sql: &'a str) -> Cow<'a, str> {
+        if self.prefix.is_empty() {
+            return Cow::Borrowed(sql);
+        }
+
+        let mut out = String::with_capacity(sql.len() + 4 * self.prefix.len());
+        let mut chars = sql.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '\'' | '"' => {
+                    out.push(c);
+                    for (_, next) in chars.by_ref() {
+                        out.push(next);
+                        if next == c {
+                            break;
+                        }
+                    }
+                }
+                c if c.is_ascii_alphanumeric() || c == '_' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(index, next)) = chars.peek() {
+                        if !(next.is_ascii_alphanumeric() || next == '_') {
+                            break;
+                        }
+                        end = index + next.len_utf8();
+                        chars.next();
+                    }
+                    let word = &sql[start..end];
+                    if SCHEMA_NAMES.contains(&word) {
+                        out.push_str(&self.prefix);
+                    }
+                    out.push_str(word);
+                }
+                c => out.push(c),
+            }
+        }
+
+        Cow::Owned(out)
+    }
+}
+
+impl LocalLibrary {
+    /// Prefix the names of tables and indexes in a statement
+    pub(crate) fn sql<'a>(&self, sql: &'a str) -> Cow<'a, str> {
+        self.tables.sql(sql)
+    }
+
+    /// Check that the database doesn't hold an installation under another
+    /// prefix, rather than creating a second one beside it
+    ///
+    /// Unprefixed tables, from before prefixes existed, are renamed to the
+    /// configured prefix, unless the database is `shared` with the host
+    /// application.
+    pub(crate) async fn check_table_prefix(db: &Pool<Sqlite>, tables: &Tables, shared: bool) -> Result<()> {
+        let names: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(db)
+            .await?;
+        let installed: Vec<&str> = names.iter().filter_map(|name| name.strip_suffix(MARKER_TABLE)).collect();
+
+        match installed.as_slice() {
+            [] => Ok(()),
+            _ if installed.contains(&tables.prefix()) => Ok(()),
+            [""] if !shared => Self::prefix_tables(db, tables, &names).await,
+            [prefix, ..] => Err(VaultError::Config(format!(
+                "The database holds SoundVault tables prefixed {:?}, not {:?}; set `table_prefix` to {:?} \
+                 to open them",
+                prefix,
+                tables.prefix(),
+                prefix
+            ))),
+        }
+    }
+
+    /// Rename unprefixed tables to the configured prefix
+    ///
+    /// Indexes are dropped, to be created again under prefixed names.
+    async fn prefix_tables(db: &Pool<Sqlite>, tables: &Tables, existing: &[String]) -> Result<()> {
+        let mut tx = db.begin().await?;
+        for name in SCHEMA_NAMES {
+            if existing.iter().any(|table| table == name) {
+                sqlx::query(&format!("ALTER TABLE {} RENAME TO {}", name, tables.name(name)))
+                    .execute(&mut *tx)
+                    .await?;
+            } else {
+                sqlx::query(&format!("DROP INDEX IF EXISTS {}", name))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+//! Synonyms and hierarchy of tags
+//!
+//! An alias maps a synonym such as `fx` to its canonical tag, `sfx`. Sounds
+//! keep the tags they were given; filtering by a tag matches its aliases
+//! too. A tag may also have a parent, e.g. `snare` under `drum`, so filters
+//! and counts can take in the tags below it.
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::patch::MetadataPatch;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// What [`SoundVault::rename_tag`](crate::SoundVault::rename_tag) does
+/// with the old tag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagRename {
+    /// Replace the tag in every sound that has it
+    #[default]
+    Replace,
+    /// Leave sounds alone and make the old tag an alias of the new one
+    MergeAsAlias,
+}
+
+/// A canonical tag and the sounds tagged with it, from
+/// [`SoundVault::list_tags`](crate::SoundVault::list_tags)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagCount {
+    /// The canonical tag
+    pub tag: String,
+
+    /// Its parent tag
+    pub parent: Option<String>,
+
+    /// Tags that mean the same, by name
+    pub aliases: Vec<String>,
+
+    /// Number of sounds with the tag or one of its aliases, and with
+    /// `roll_up` one of the tags below it
+    pub count: u64,
+}
+
+/// Tag with surrounding whitespace removed, as stored in sounds
+fn trimmed(tag: &str) -> Result<&str> {
+    match tag.trim() {
+        "" => Err(VaultError::InvalidOperation("Tag is empty".to_string())),
+        tag => Ok(tag),
+    }
+}
+
+impl LocalLibrary {
+    /// Canonical tag an alias stands for; the tag itself if it isn't one
+    async fn canonical_tag(&self, conn: &mut SqliteConnection, tag: &str) -> Result<String> {
+        let canonical: Option<String> = sqlx::query_scalar(&self.sql("SELECT tag FROM tag_aliases WHERE alias = ?"))
+            .bind(tag)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(canonical.unwrap_or_else(|| tag.to_string()))
+    }
+
+    /// Whether `ancestor` is `tag` or one of the tags above it
+    async fn is_under(&self, conn: &mut SqliteConnection, tag: &str, ancestor: &str) -> Result<bool> {
+        let mut seen = HashSet::new();
+        let mut current = tag.to_string();
+        while seen.insert(current.clone()) {
+            if current == ancestor {
+                return Ok(true);
+            }
+            let parent: Option<String> = sqlx::query_scalar(&self.sql("SELECT parent FROM tag_parents WHERE tag = ?"))
+                .bind(&current)
+                .fetch_optional(&mut *conn)
+                .await?;
+            match parent {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+        Ok(false)
+    }
+
+    /// Make a tag an alias of another
+    ///
+    /// An alias of `alias` becomes an alias of `tag`'s canonical tag, and so
+    /// do its place and children in the hierarchy.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if either tag is empty, or `tag`
+    ///   is `alias` or one of its aliases
+    pub async fn add_tag_alias(&self, alias: &str, tag: &str) -> Result<()> {
+        let (alias, tag) = (trimmed(alias)?, trimmed(tag)?);
+
+        let mut tx = self.db.begin().await?;
+        let canonical = self.canonical_tag(&mut tx, tag).await?;
+        if canonical == alias {
+            return Err(VaultError::InvalidOperation(format!(
+                "Can't make {:?} an alias of {:?}, which stands for it",
+                alias, tag
+            )));
+        }
+
+        sqlx::query(&self.sql("UPDATE tag_aliases SET tag = ? WHERE tag = ?"))
+            .bind(&canonical)
+            .bind(alias)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&self.sql(
+            "INSERT INTO tag_aliases (alias, tag) VALUES (?, ?) ON CONFLICT(alias) DO UPDATE SET tag = excluded.tag",
+        ))
+        .bind(alias)
+        .bind(&canonical)
+        .execute(&mut *tx)
+        .await?;
+
+        // The canonical tag keeps its own parent if it has one
+        sqlx::query(&self.sql("UPDATE OR IGNORE tag_parents SET tag = ? WHERE tag = ?"))
+            .bind(&canonical)
+            .bind(alias)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&self.sql("UPDATE tag_parents SET parent = ? WHERE parent = ?"))
+            .bind(&canonical)
+            .bind(alias)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&self.sql("DELETE FROM tag_parents WHERE tag = ? OR tag = parent"))
+            .bind(alias)
+            .execute(&mut *tx)
+            .await?;
+        self.check_tag_cycle(&mut tx, &canonical).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Stop a tag being an alias
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if the tag isn't an alias
+    pub async fn remove_tag_alias(&self, alias: &str) -> Result<()> {
+        let deleted = sqlx::query(&self.sql("DELETE FROM tag_aliases WHERE alias = ?"))
+            .bind(alias.trim())
+            .execute(&self.db)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(VaultError::NotFound(format!("Tag alias not found: {}", alias)));
+        }
+
+        Ok(())
+    }
+
+    /// Fail if a tag ended up under itself after its place in the hierarchy
+    /// was merged with another tag's
+    async fn check_tag_cycle(&self, conn: &mut SqliteConnection, tag: &str) -> Result<()> {
+        let parent: Option<String> = sqlx::query_scalar(&self.sql("SELECT parent FROM tag_parents WHERE tag = ?"))
+            .bind(tag)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(parent) = parent
+            && self.is_under(conn, &parent, tag).await?
+        {
+            return Err(VaultError::InvalidOperation(format!(
+                "Merging tags would put {:?} under itself",
+                tag
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Set the parent of a tag, or remove it with `None`
+    ///
+    /// Aliases are replaced with their canonical tags.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if either tag is empty, or the
+    ///   parent is the tag or below it
+    pub async fn set_tag_parent(&self, tag: &str, parent: Option<&str>) -> Result<()> {
+        let tag = trimmed(tag)?;
+
+        let mut tx = self.db.begin().await?;
+        let tag = self.canonical_tag(&mut tx, tag).await?;
+        match parent {
+            Some(parent) => {
+                let parent = self.canonical_tag(&mut tx, trimmed(parent)?).await?;
+                if self.is_under(&mut tx, &parent, &tag).await? {
+                    return Err(VaultError::InvalidOperation(format!(
+                        "Can't put {:?} under {:?}: {:?} is under {:?}",
+                        tag, parent, parent, tag
+                    )));
+                }
+                sqlx::query(&self.sql(
+                    "INSERT INTO tag_parents (tag, parent) VALUES (?, ?) ON CONFLICT(tag) DO UPDATE SET parent = excluded.parent",
+                ))
+                .bind(&tag)
+                .bind(&parent)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query(&self.sql("DELETE FROM tag_parents WHERE tag = ?"))
+                    .bind(&tag)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// List the canonical tags of sounds, and the tags above them, by name
+    ///
+    /// # Arguments
+    ///
+    /// * `roll_up` - Count the sounds of every tag in the tags above it
+    pub async fn list_tags(&self, roll_up: bool) -> Result<Vec<TagCount>> {
+        let aliases: HashMap<String, String> = sqlx::query_as(&self.sql("SELECT alias, tag FROM tag_aliases"))
+            .fetch_all(&self.reader)
+            .await?
+            .into_iter()
+            .collect();
+        let parents: HashMap<String, String> = sqlx::query_as(&self.sql("SELECT tag, parent FROM tag_parents"))
+            .fetch_all(&self.reader)
+            .await?
+            .into_iter()
+            .collect();
+        let tagged: Vec<(String, String)> =
+            sqlx::query_as(&self.sql("SELECT sounds.id, json_each.value FROM sounds, json_each(sounds.tags)"))
+                .fetch_all(&self.reader)
+                .await?;
+
+        let canonical = |tag: &str| aliases.get(tag).cloned().unwrap_or_else(|| tag.to_string());
+        let mut sounds: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+        for tag in parents.iter().flat_map(|(tag, parent)| [tag, parent]) {
+            sounds.entry(canonical(tag)).or_default();
+        }
+        for (sound_id, tag) in tagged {
+            let mut tag = canonical(&tag);
+            let mut seen = HashSet::new();
+            while seen.insert(tag.clone()) {
+                sounds.entry(tag.clone()).or_default().insert(sound_id.clone());
+                match parents.get(&tag) {
+                    Some(parent) if roll_up => tag = parent.clone(),
+                    _ => break,
+                }
+            }
+        }
+
+        let mut alias_lists: HashMap<&str, Vec<String>> = HashMap::new();
+        for (alias, tag) in &aliases {
+            alias_lists.entry(tag).or_default().push(alias.clone());
+        }
+        Ok(sounds
+            .into_iter()
+            .map(|(tag, sounds)| {
+                let mut aliases = alias_lists.remove(tag.as_str()).unwrap_or_default();
+                aliases.sort();
+                TagCount {
+                    parent: parents.get(&tag).cloned(),
+                    aliases,
+                    count: sounds.len() as u64,
+                    tag,
+                }
+            })
+            .collect())
+    }
+
+    /// Rename a tag across the library
+    ///
+    /// With [`TagRename::Replace`] every sound tagged `from` is tagged `to`
+    /// instead, in one transaction recorded in the audit log, and `from`'s
+    /// aliases and place in the hierarchy move to `to`. With
+    /// [`TagRename::MergeAsAlias`] sounds are left alone and `from` becomes
+    /// an alias of `to`.
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds changed
+    pub async fn rename_tag(&self, from: &str, to: &str, mode: TagRename) -> Result<u64> {
+        let (from, to) = (trimmed(from)?, trimmed(to)?);
+        if from == to {
+            return Ok(0);
+        }
+        if mode == TagRename::MergeAsAlias {
+            self.add_tag_alias(from, to).await?;
+            return Ok(0);
+        }
+
+        let mut tx = self.db.begin().await?;
+        let ids: Vec<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE instr(tags, ?) > 0 ORDER BY id"))
+            .bind(serde_json::to_string(from)?)
+            .fetch_all(&mut *tx)
+            .await?;
+        let mut renamed = 0;
+        for id in ids {
+            let before = self.fetch_sound(&mut tx, &id).await?.metadata;
+            if !before.tags.iter().any(|tag| tag == from) {
+                continue;
+            }
+            let patch = MetadataPatch {
+                add_tags: vec![to.to_string()],
+                remove_tags: vec![from.to_string()],
+                ..Default::default()
+            };
+            self.apply_patch(&mut tx, &id, &patch).await?;
+            renamed += 1;
+        }
+
+        for sql in [
+            "UPDATE tag_aliases SET tag = ? WHERE tag = ?",
+            "UPDATE OR IGNORE tag_parents SET tag = ? WHERE tag = ?",
+            "UPDATE tag_parents SET parent = ? WHERE parent = ?",
+        ] {
+            sqlx::query(&self.sql(sql)).bind(to).bind(from).execute(&mut *tx).await?;
+        }
+        sqlx::query(&self.sql("DELETE FROM tag_parents WHERE tag = ? OR tag = parent"))
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&self.sql("DELETE FROM tag_aliases WHERE alias = tag"))
+            .execute(&mut *tx)
+            .await?;
+        self.check_tag_cycle(&mut tx, to).await?;
+        tx.commit().await?;
+
+        Ok(renamed)
+    }
+}
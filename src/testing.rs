@@ -0,0 +1,405 @@
+//! Fixtures for testing code built on the vault, with the `test-util` feature
+//!
+//! [`TestVault`] opens a vault in a temporary directory removed when it's
+//! dropped, [`dummy_wav`] synthesizes small valid WAV files, and
+//! [`freesound_mock`] serves scripted sounds so remote searches and
+//! downloads run offline. The same input always gives the same file.
+//!
+//! # Examples
+//!
+//! ```
+//! use soundvault::Collection;
+//! use soundvault::testing::TestVault;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let vault = TestVault::new().await?;
+//! let rain = vault.add_dummy_sound("Rain on roof", 2.0, &["rain", "ambience"]).await?;
+//! let wind = vault.add_dummy_sound("Wind in trees", 0.5, &["wind", "ambience"]).await?;
+//!
+//! let sound = vault.get_sound(&rain).await?;
+//! assert_eq!((sound.metadata.duration, sound.metadata.channels), (2.0, Some(1)));
+//! assert_eq!(vault.search_local("rain", None).await?.len(), 1);
+//! assert_eq!(vault.search_local("", Some(&["ambience"])).await?.len(), 2);
+//! assert!(vault.search_local("", Some(&["ambience", "wind"])).await?.iter().all(|sound| sound.metadata.id == wind));
+//!
+//! let collection_id = vault.add_collection(&Collection::new("Weather", "")).await?;
+//! assert_eq!(vault.add_sound_to_collections(&rain, &[collection_id.clone()]).await?, 1);
+//! vault.add_sound_to_collection(&wind, &collection_id).await?;
+//! assert_eq!(vault.collection_size(&collection_id).await?, 2);
+//! vault.remove_sound_from_collection(&rain, &collection_id).await?;
+//! assert_eq!(vault.get_collection(&collection_id).await?.sound_ids, vec![wind]);
+//! assert!(vault.collections_containing(&rain).await?.is_empty());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::audio::{AudioFormat, AudioInfo, SampleFormat, encode};
+use crate::config::VaultConfig;
+use crate::error::{Result, VaultError};
+use crate::models::{SoundMetadata, SoundSource, canonical_tags};
+use crate::paths::safe_file_name;
+use crate::source::{RemoteFuture, RemoteSource};
+use crate::vault::SoundVault;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::f32::consts::TAU;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+/// Frames per second of the files made by [`dummy_wav`]
+pub const DUMMY_SAMPLE_RATE: u32 = 8000;
+
+/// A vault in a temporary directory, removed when it's dropped
+///
+/// Dereferences to the [`SoundVault`].
+pub struct TestVault {
+    /// Dropped before the directory holding its files
+    vault: SoundVault,
+    dir: TempDir,
+}
+
+impl TestVault {
+    /// Open an empty vault with its database in a temporary file
+    pub async fn new() -> Result<Self> {
+        Self::with_config(|_| {}).await
+    }
+
+    /// Open an empty vault, adjusting its configuration first
+    ///
+    /// The library path is the temporary directory; changing it isn't
+    /// supported.
+    pub async fn with_config<F: FnOnce(&mut VaultConfig)>(configure: F) -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+        configure(&mut config);
+
+        let vault = SoundVault::new(config).await?;
+        Ok(Self { vault, dir })
+    }
+
+    /// Open an empty vault with its database in memory
+    ///
+    /// Files still go to a temporary directory. The database lives as long
+    /// as the vault.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::testing::TestVault;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let vault = TestVault::in_memory().await?;
+    /// let id = vault.add_dummy_sound("Beep", 0.1, &[]).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.name, "Beep");
+    /// assert!(!vault.path().join("soundvault.db").exists());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn in_memory() -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let db = SqlitePoolOptions::new()
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+
+        let vault = SoundVault::with_pool(VaultConfig::new(dir.path().to_path_buf(), None), db).await?;
+        Ok(Self { vault, dir })
+    }
+
+    /// The temporary directory, which is also the library path
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Import a synthesized WAV file of `duration` seconds as a sound named
+    /// `name` with `tags`
+    ///
+    /// # Returns
+    ///
+    /// The ID of the new sound
+    pub async fn add_dummy_sound(&self, name: &str, duration: f32, tags: &[&str]) -> Result<String> {
+        let fixtures = self.dir.path().join("fixtures");
+        std::fs::create_dir_all(&fixtures)?;
+        let path = fixtures.join(format!("{}.wav", safe_file_name(name, "sound")));
+        std::fs::write(&path, dummy_wav(name, duration)?)?;
+
+        let metadata = SoundMetadata {
+            name: name.to_string(),
+            tags: canonical_tags(tags.iter().map(|tag| tag.to_string()).collect()),
+            license: "Creative Commons 0".to_string(),
+            ..Default::default()
+        };
+        let id = self.vault.import_file(&path, Some(metadata)).await;
+        std::fs::remove_file(&path)?;
+        id
+    }
+}
+
+impl Deref for TestVault {
+    type Target = SoundVault;
+
+    fn deref(&self) -> &SoundVault {
+        &self.vault
+    }
+}
+
+impl DerefMut for TestVault {
+    fn deref_mut(&mut self) -> &mut SoundVault {
+        &mut self.vault
+    }
+}
+
+/// A mono 16-bit WAV file of `duration` seconds at [`DUMMY_SAMPLE_RATE`]
+///
+/// The file holds a quiet tone whose pitch and noise are picked from `seed`,
+/// so different seeds give different hashes and the same seed the same
+/// bytes.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::probe_file;
+/// use soundvault::testing::dummy_wav;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let bytes = dummy_wav("click", 0.25)?;
+/// assert_eq!(bytes, dummy_wav("click", 0.25)?);
+/// assert_ne!(bytes, dummy_wav("clack", 0.25)?);
+///
+/// let dir = tempfile::tempdir()?;
+/// let path = dir.path().join("click.wav");
+/// std::fs::write(&path, &bytes)?;
+/// assert_eq!(probe_file(&path)?.frames, 2000);
+/// # Ok(())
+/// # }
+/// ```
+pub fn dummy_wav(seed: &str, duration: f32) -> Result<Vec<u8>> {
+    if !duration.is_finite() || duration < 0.0 {
+        return Err(VaultError::InvalidOperation(format!("Invalid duration: {}", duration)));
+    }
+
+    // FNV-1a, then a linear congruential generator for the noise
+    let mut state = seed
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3));
+    let frequency = 220.0 + (state % 660) as f32;
+    let frames = (duration * DUMMY_SAMPLE_RATE as f32).round() as usize;
+    let samples: Vec<f32> = (0..frames)
+        .map(|frame| {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            let noise = (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+            0.25 * (TAU * frequency * frame as f32 / DUMMY_SAMPLE_RATE as f32).sin() + 0.01 * noise
+        })
+        .collect();
+
+    encode(&AudioInfo::new(AudioFormat::Wav, 1, DUMMY_SAMPLE_RATE, SampleFormat::Int(16)), &samples)
+}
+
+/// A sound served by a [`MockRemoteSource`]
+struct MockSound {
+    metadata: SoundMetadata,
+    file: Vec<u8>,
+    descriptors: HashMap<String, f64>,
+}
+
+/// Scripted responses
+#[derive(Default)]
+struct MockScript {
+    sounds: BTreeMap<i32, MockSound>,
+    failures: VecDeque<VaultError>,
+    calls: Vec<String>,
+}
+
+/// A remote source answering from scripted sounds, made by [`freesound_mock`]
+///
+/// Keep it in an `Arc` to script it after it's added to a vault:
+/// `Arc<MockRemoteSource>` is a [`RemoteSource`] too.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::testing::{TestVault, freesound_mock};
+/// use soundvault::{DownloadState, VaultError, VaultEvent};
+/// use std::sync::Arc;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut vault = TestVault::new().await?;
+/// let freesound = Arc::new(
+///     freesound_mock()
+///         .with_sound(1234, "Door slam", 1.0, &["door", "impact"])
+///         .with_sound(5678, "Door creak", 3.0, &["door"])
+///         .with_descriptors(1234, &[("loudness", -12.0)]),
+/// );
+/// vault.add_remote_source(Box::new(freesound.clone()))?;
+///
+/// let results = vault.search_remote("slam", 10).await?;
+/// assert_eq!(results.sounds.len(), 1);
+/// assert_eq!(results.sounds[0].metadata.remote_id.as_deref(), Some("1234"));
+///
+/// // A failed search is reported per source
+/// freesound.fail_next(VaultError::InvalidOperation("rate limited".to_string()));
+/// assert_eq!(vault.search_remote("door", 10).await?.errors.len(), 1);
+/// assert_eq!(vault.search_remote("door", 10).await?.sounds.len(), 2);
+///
+/// let id = vault.download_remote("freesound", "1234").await?;
+/// let sound = vault.get_sound(&id).await?;
+/// assert_eq!(sound.metadata.duration, 1.0);
+/// assert!(vault.search_remote("slam", 10).await?.sounds[0].is_cached);
+/// assert_eq!(vault.provenance(&id).await?[0].source_url.as_deref(), Some("https://freesound.org/s/1234/"));
+///
+/// // A failed queued download can be retried
+/// let mut events = vault.subscribe();
+/// freesound.fail_next(VaultError::InvalidOperation("connection reset".to_string()));
+/// vault.enqueue_download("freesound", "5678", None).await?;
+/// while !matches!(events.recv().await?, VaultEvent::DownloadFailed { .. }) {}
+/// assert_eq!(vault.list_download_queue().await?[0].state, DownloadState::Failed);
+/// assert_eq!(vault.retry_failed().await?, 1);
+/// while !matches!(events.recv().await?, VaultEvent::DownloadFinished { .. }) {}
+/// assert_eq!(vault.list_download_queue().await?[0].state, DownloadState::Done);
+///
+/// assert!(freesound.calls().contains(&"download 5678".to_string()));
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockRemoteSource {
+    name: String,
+    script: Mutex<MockScript>,
+}
+
+/// A stand-in for Freesound serving no sounds yet
+///
+/// Its sounds are described the way Freesound describes them, with
+/// numeric IDs, a Creative Commons 0 license and an uploader.
+pub fn freesound_mock() -> MockRemoteSource {
+    MockRemoteSource {
+        name: SoundSource::Freesound.as_str().to_string(),
+        script: Mutex::default(),
+    }
+}
+
+impl MockRemoteSource {
+    /// Serve a sound with a [`dummy_wav`] file of `duration` seconds
+    pub fn with_sound(self, remote_id: i32, name: &str, duration: f32, tags: &[&str]) -> Self {
+        let mut metadata = SoundMetadata {
+            name: name.to_string(),
+            source: SoundSource::Freesound,
+            tags: canonical_tags(tags.iter().map(|tag| tag.to_string()).collect()),
+            duration,
+            license: "Creative Commons 0".to_string(),
+            freesound_id: Some(remote_id),
+            remote_id: Some(remote_id.to_string()),
+            ..Default::default()
+        };
+        metadata.set_custom("freesound_username", "mock");
+        let sound = MockSound {
+            metadata,
+            file: dummy_wav(&remote_id.to_string(), duration).unwrap_or_default(),
+            descriptors: HashMap::new(),
+        };
+
+        self.lock().sounds.insert(remote_id, sound);
+        self
+    }
+
+    /// Give a served sound analysis descriptors
+    pub fn with_descriptors(self, remote_id: i32, descriptors: &[(&str, f64)]) -> Self {
+        if let Some(sound) = self.lock().sounds.get_mut(&remote_id) {
+            sound
+                .descriptors
+                .extend(descriptors.iter().map(|(name, value)| (name.to_string(), *value)));
+        }
+        self
+    }
+
+    /// Make the next call fail with `error`
+    ///
+    /// Failures queue up, one per call.
+    pub fn fail_next(&self, error: VaultError) {
+        self.lock().failures.push_back(error);
+    }
+
+    /// Calls received so far, oldest first, as `"<method> <argument>"`
+    pub fn calls(&self) -> Vec<String> {
+        self.lock().calls.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockScript> {
+        self.script.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a call, failing it if a failure is scripted
+    fn answer<T>(&self, method: &str, argument: &str, respond: impl FnOnce(&MockScript) -> Result<T>) -> Result<T> {
+        let mut script = self.lock();
+        script.calls.push(format!("{} {}", method, argument));
+        match script.failures.pop_front() {
+            Some(error) => Err(error),
+            None => respond(&script),
+        }
+    }
+
+    fn sound<'a>(script: &'a MockScript, remote_id: &str) -> Result<&'a MockSound> {
+        remote_id
+            .parse()
+            .ok()
+            .and_then(|id: i32| script.sounds.get(&id))
+            .ok_or_else(|| VaultError::NotFound(format!("No mock sound {}", remote_id)))
+    }
+}
+
+impl RemoteSource for MockRemoteSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn search<'a>(&'a self, query: &'a str, page_size: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> {
+        let query = query.to_lowercase();
+        let result = self.answer("search", &query, |script| {
+            Ok(script
+                .sounds
+                .values()
+                .filter(|sound| {
+                    sound.metadata.name.to_lowercase().contains(&query)
+                        || sound.metadata.tags.iter().any(|tag| tag.contains(&query))
+                })
+                .take(page_size)
+                .map(|sound| sound.metadata.clone())
+                .collect())
+        });
+        Box::pin(async move { result })
+    }
+
+    fn get_by_id<'a>(&'a self, remote_id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+        let result = self.answer("get_by_id", remote_id, |script| {
+            Ok(Self::sound(script, remote_id)?.metadata.clone())
+        });
+        Box::pin(async move { result })
+    }
+
+    fn download<'a>(&'a self, remote_id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+        let result = self.answer("download", remote_id, |script| Ok(Self::sound(script, remote_id)?.file.clone()));
+        Box::pin(async move { Ok(std::fs::write(target, result?)?) })
+    }
+
+    fn descriptors<'a>(&'a self, remote_id: &'a str, names: &'a [String]) -> RemoteFuture<'a, HashMap<String, f64>> {
+        let result = self.answer("descriptors", remote_id, |script| {
+            Ok(Self::sound(script, remote_id)?
+                .descriptors
+                .iter()
+                .filter(|(name, _)| names.contains(name))
+                .map(|(name, value)| (name.clone(), *value))
+                .collect())
+        });
+        Box::pin(async move { result })
+    }
+
+    fn sound_url(&self, remote_id: &str) -> Option<String> {
+        Some(format!("https://freesound.org/s/{}/", remote_id))
+    }
+}
@@ -0,0 +1,536 @@
+//! Undo of batch operations run in this session
+//!
+//! Batch operations, such as
+//! [`SoundVault::sync_directory`](crate::SoundVault::sync_directory), tag
+//! the audit log entries they write with an [`OperationId`]. Undoing one
+//! replays its entries backwards: the sounds and collections it created are
+//! removed, the metadata it changed is set back from the recorded diffs, and
+//! the sounds it added to or removed from collections are taken out or put
+//! back. Operations are remembered by the process that ran them.
+
+use crate::audit::{AuditOperation, audit_diff};
+use crate::error::{Result, VaultError};
+use crate::events::VaultEvent;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::patch::MetadataPatch;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use sqlx::Row;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::MutexGuard;
+use uuid::Uuid;
+
+tokio::task_local! {
+    /// Operation the mutations of the current task belong to
+    static OPERATION: OperationId;
+}
+
+/// Fields of a sound an undo sets back; the others, like its file, can't be
+/// restored from a diff
+const RESTORABLE_FIELDS: &[&str] = &[
+    "name",
+    "slug",
+    "description",
+    "license",
+    "duration",
+    "rating",
+    "trim",
+    "expires_at",
+    "tags",
+    "custom",
+    "localizations",
+    "status",
+];
+
+/// ID of a batch operation, tagging the audit log entries it wrote
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OperationId(String);
+
+impl OperationId {
+    /// The ID as stored in the audit log
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn from_stored(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for OperationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A batch operation of this session that can be undone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationSummary {
+    /// ID to pass to [`SoundVault::undo_operation`](crate::SoundVault::undo_operation)
+    pub id: OperationId,
+
+    /// Name of the method that ran it, e.g. `sync_directory`
+    pub name: String,
+
+    /// When it started
+    pub started_at: DateTime<Utc>,
+
+    /// What it changed, e.g. `3 sounds created, 1 sound added to a collection`
+    pub summary: String,
+}
+
+/// What [`SoundVault::undo_operation`](crate::SoundVault::undo_operation) set back
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UndoReport {
+    /// IDs of the sounds the operation created, moved to the trash
+    pub trashed: Vec<String>,
+
+    /// IDs of the sounds whose metadata was set back
+    pub restored: Vec<String>,
+
+    /// IDs of the collections the operation created, removed
+    pub removed_collections: Vec<String>,
+
+    /// IDs of the collections whose name was set back
+    pub restored_collections: Vec<String>,
+
+    /// Number of sounds taken out of or put back into collections
+    pub memberships: usize,
+
+    /// IDs of the sounds and collections deleted since, left as they are
+    pub missing: Vec<String>,
+}
+
+/// A batch operation run by this process
+pub(crate) struct OperationRecord {
+    id: OperationId,
+    name: String,
+    started_at: DateTime<Utc>,
+    undone: bool,
+}
+
+/// What an operation did to one sound or collection
+#[derive(Default)]
+struct Touched {
+    collection: bool,
+    created: bool,
+    /// Deleted again by the operation itself
+    deleted: bool,
+    /// Fields before the operation, and as it left them
+    before: Map<String, Value>,
+    after: Map<String, Value>,
+    /// Sounds in the collection before the operation, and as it left them
+    members_before: BTreeMap<String, bool>,
+    members_after: BTreeMap<String, bool>,
+    /// Last audit log entry of the operation about it
+    last_entry: i64,
+}
+
+impl Touched {
+    fn record(&mut self, changes: &Value) {
+        for (field, change) in changes.as_object().into_iter().flatten() {
+            let (old, new) = match change.as_array().map(Vec::as_slice) {
+                Some([old, new]) => (old.clone(), new.clone()),
+                _ => continue,
+            };
+            self.before.entry(field.clone()).or_insert(old);
+            self.after.insert(field.clone(), new);
+        }
+    }
+
+    fn record_member(&mut self, sound_id: String, added: bool) {
+        self.members_before.entry(sound_id.clone()).or_insert(!added);
+        self.members_after.insert(sound_id, added);
+    }
+}
+
+/// ID of the sound an `AddToCollection` or `RemoveFromCollection` entry is about
+fn member(changes: &Value) -> Option<String> {
+    let change = changes.get("sound_id")?.as_array()?;
+    change.iter().find_map(|id| id.as_str()).map(str::to_string)
+}
+
+/// Describe how many entities an operation changed one way
+fn describe(operation: AuditOperation, count: i64) -> String {
+    let (one, many) = match operation {
+        AuditOperation::CreateSound => ("sound created", "sounds created"),
+        AuditOperation::UpdateSound => ("sound changed", "sounds changed"),
+        AuditOperation::DeleteSound => ("sound deleted", "sounds deleted"),
+        AuditOperation::CreateCollection => ("collection created", "collections created"),
+        AuditOperation::UpdateCollection => ("collection changed", "collections changed"),
+        AuditOperation::DeleteCollection => ("collection deleted", "collections deleted"),
+        AuditOperation::AddToCollection => ("sound added to a collection", "sounds added to collections"),
+        AuditOperation::RemoveFromCollection => ("sound removed from a collection", "sounds removed from collections"),
+        AuditOperation::LockSound => ("sound locked", "sounds locked"),
+        AuditOperation::UnlockSound => ("sound unlocked", "sounds unlocked"),
+        AuditOperation::GrantRole => ("role granted", "roles granted"),
+        AuditOperation::RevokeRole => ("role revoked", "roles revoked"),
+        AuditOperation::PermissionDenied => ("operation denied", "operations denied"),
+    };
+    format!("{} {}", count, if count == 1 { one } else { many })
+}
+
+/// Operation the mutations of the current task belong to, if any
+pub(crate) fn current_operation() -> Option<OperationId> {
+    OPERATION.try_with(OperationId::clone).ok()
+}
+
+impl LocalLibrary {
+    fn operations(&self) -> MutexGuard<'_, Vec<OperationRecord>> {
+        self.operations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run a batch operation, tagging the audit log entries it writes
+    ///
+    /// An operation run within another belongs to the outer one.
+    pub(crate) async fn in_operation<T>(&self, name: &str, work: impl Future<Output = Result<T>>) -> Result<(T, OperationId)> {
+        if let Some(id) = current_operation() {
+            return Ok((work.await?, id));
+        }
+
+        let id = OperationId(Uuid::new_v4().to_string());
+        self.operations().push(OperationRecord {
+            id: id.clone(),
+            name: name.to_string(),
+            started_at: Utc::now(),
+            undone: false,
+        });
+        let value = OPERATION.scope(id.clone(), work).await?;
+        Ok((value, id))
+    }
+
+    /// List the batch operations of this session that changed something and
+    /// weren't undone, newest first
+    ///
+    /// # Arguments
+    ///
+    /// * `since` - Only operations started at or after this time
+    pub async fn list_operations(&self, since: Option<DateTime<Utc>>) -> Result<Vec<OperationSummary>> {
+        let records: Vec<(OperationId, String, DateTime<Utc>)> = self
+            .operations()
+            .iter()
+            .rev()
+            .filter(|record| !record.undone && since.is_none_or(|since| record.started_at >= since))
+            .map(|record| (record.id.clone(), record.name.clone(), record.started_at))
+            .collect();
+
+        let mut operations = Vec::new();
+        for (id, name, started_at) in records {
+            let rows = sqlx::query(&self.sql(
+                r#"
+                SELECT operation, COUNT(*) AS entries, COUNT(DISTINCT entity_id) AS entities
+                FROM audit_log WHERE operation_id = ?
+                GROUP BY operation ORDER BY MIN(id)
+                "#,
+            ))
+            .bind(id.as_str())
+            .fetch_all(&self.reader)
+            .await?;
+            if rows.is_empty() {
+                continue;
+            }
+
+            let mut summary = Vec::new();
+            for row in rows {
+                let operation = AuditOperation::parse(row.try_get("operation")?)?;
+                // Membership entries are about the collection, one per sound
+                let count = match operation {
+                    AuditOperation::AddToCollection | AuditOperation::RemoveFromCollection => row.try_get("entries")?,
+                    _ => row.try_get("entities")?,
+                };
+                summary.push(describe(operation, count));
+            }
+            operations.push(OperationSummary {
+                id,
+                name,
+                started_at,
+                summary: summary.join(", "),
+            });
+        }
+
+        Ok(operations)
+    }
+
+    /// Undo a batch operation of this session
+    ///
+    /// Nothing is changed when the operation can't be undone as a whole.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if this session ran no such operation
+    /// * `VaultError::InvalidOperation` if it was undone already, or deleted
+    ///   something or changed a file, which can't be set back
+    /// * `VaultError::UndoConflict` if a later change to a field the
+    ///   operation set would be lost
+    /// * `VaultError::Locked` if a sound to set back or trash is locked
+    pub async fn undo_operation(&self, id: &OperationId) -> Result<UndoReport> {
+        match self.operations().iter().find(|record| record.id == *id).map(|record| record.undone) {
+            None => return Err(VaultError::NotFound(format!("Operation not found in this session: {}", id))),
+            Some(true) => return Err(VaultError::InvalidOperation(format!("Operation already undone: {}", id))),
+            Some(false) => {}
+        }
+
+        let touched = self.operation_entries(id).await?;
+
+        // Check everything before changing anything
+        let mut current = BTreeMap::new();
+        for (entity_id, entity) in &touched {
+            if entity.deleted {
+                continue;
+            }
+            if let Some(value) = self.current_state(entity_id, entity.collection).await? {
+                self.check_undo(id, entity_id, entity, &value).await?;
+                current.insert(entity_id.as_str(), value);
+            }
+        }
+
+        let created = |entity_id: &str| touched.get(entity_id).is_some_and(|entity| entity.created);
+        let mut report = UndoReport::default();
+        for (entity_id, entity) in &touched {
+            if entity.deleted {
+                continue;
+            }
+            let Some(value) = current.get(entity_id.as_str()) else {
+                report.missing.push(entity_id.clone());
+                continue;
+            };
+
+            match (entity.collection, entity.created) {
+                (false, true) => {
+                    self.trash_sound(entity_id).await?;
+                    report.trashed.push(entity_id.clone());
+                }
+                (false, false) => {
+                    self.restore_sound(entity_id, &entity.before).await?;
+                    report.restored.push(entity_id.clone());
+                }
+                (true, true) => {
+                    let collection = self.get_collection(entity_id).await?;
+                    let mut tx = self.db.begin().await?;
+                    self.remove_collection(&mut tx, &collection).await?;
+                    tx.commit().await?;
+                    self.emit(VaultEvent::CollectionChanged { collection_id: entity_id.clone() });
+                    report.removed_collections.push(entity_id.clone());
+                }
+                (true, false) => {
+                    if let Some(name) = entity.before.get("name").and_then(Value::as_str) {
+                        self.restore_collection_name(entity_id, name, value).await?;
+                        report.restored_collections.push(entity_id.clone());
+                    }
+                    // Trashing the sounds the operation created takes them
+                    // out of their collections
+                    for (sound_id, &member) in &entity.members_before {
+                        if !created(sound_id) && self.set_member(entity_id, sound_id, member).await? {
+                            report.memberships += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(record) = self.operations().iter_mut().find(|record| record.id == *id) {
+            record.undone = true;
+        }
+        Ok(report)
+    }
+
+    /// What an operation did, by sound and collection
+    async fn operation_entries(&self, id: &OperationId) -> Result<BTreeMap<String, Touched>> {
+        let rows = sqlx::query(&self.sql("SELECT id, operation, entity_id, changes FROM audit_log WHERE operation_id = ? ORDER BY id"))
+            .bind(id.as_str())
+            .fetch_all(&self.reader)
+            .await?;
+
+        let mut touched: BTreeMap<String, Touched> = BTreeMap::new();
+        for row in rows {
+            let operation = AuditOperation::parse(row.try_get("operation")?)?;
+            let entity_id: String = row.try_get("entity_id")?;
+            let changes: Value = serde_json::from_str(row.try_get::<Option<&str>, _>("changes")?.unwrap_or("null"))?;
+            let entity = touched.entry(entity_id.clone()).or_default();
+            entity.last_entry = row.try_get("id")?;
+
+            match operation {
+                AuditOperation::CreateSound | AuditOperation::CreateCollection => {
+                    entity.created = true;
+                    entity.collection = operation == AuditOperation::CreateCollection;
+                    entity.record(&changes);
+                }
+                AuditOperation::UpdateSound => entity.record(&changes),
+                AuditOperation::UpdateCollection => {
+                    entity.collection = true;
+                    entity.record(&changes);
+                }
+                AuditOperation::AddToCollection | AuditOperation::RemoveFromCollection => {
+                    let sound_id = member(&changes)
+                        .ok_or_else(|| VaultError::Corrupt(format!("Audit log entry names no sound: {}", entity_id)))?;
+                    entity.collection = true;
+                    entity.record_member(sound_id, operation == AuditOperation::AddToCollection);
+                }
+                AuditOperation::DeleteSound | AuditOperation::DeleteCollection if entity.created => entity.deleted = true,
+                _ => {
+                    return Err(VaultError::InvalidOperation(format!(
+                        "Can't undo {}: {} of {} can't be reversed",
+                        id,
+                        operation.as_str(),
+                        entity_id
+                    )));
+                }
+            }
+        }
+
+        for (entity_id, entity) in &touched {
+            if entity.created {
+                continue;
+            }
+            let restorable = |field: &str| match entity.collection {
+                true => field == "name",
+                false => RESTORABLE_FIELDS.contains(&field),
+            };
+            if let Some(field) = entity.before.keys().find(|field| !restorable(field)) {
+                return Err(VaultError::InvalidOperation(format!(
+                    "Can't undo {}: {} of {} can't be set back",
+                    id, field, entity_id
+                )));
+            }
+        }
+
+        Ok(touched)
+    }
+
+    /// A sound or collection as JSON, `None` once deleted
+    async fn current_state(&self, entity_id: &str, collection: bool) -> Result<Option<Value>> {
+        let found = match collection {
+            true => self.get_collection(entity_id).await.and_then(|collection| Ok(serde_json::to_value(collection)?)),
+            false => self.get_sound(entity_id).await.and_then(|sound| Ok(serde_json::to_value(sound.metadata)?)),
+        };
+        match found {
+            Ok(value) => Ok(Some(value)),
+            Err(VaultError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Refuse to undo what was changed again since the operation
+    ///
+    /// A later change conflicts when it touched a field the operation set,
+    /// or any field of what it created, and the field no longer holds what
+    /// the operation left; a change undone since doesn't conflict.
+    async fn check_undo(&self, id: &OperationId, entity_id: &str, entity: &Touched, current: &Value) -> Result<()> {
+        if !entity.collection && current.get("locked") == Some(&Value::Bool(true)) {
+            return Err(VaultError::Locked(entity_id.to_string()));
+        }
+
+        let rows = sqlx::query(&self.sql(
+            "SELECT operation, changes FROM audit_log WHERE entity_id = ? AND id > ? AND operation_id IS NOT ? ORDER BY id",
+        ))
+        .bind(entity_id)
+        .bind(entity.last_entry)
+        .bind(id.as_str())
+        .fetch_all(&self.reader)
+        .await?;
+        let conflict = |field: String| VaultError::UndoConflict {
+            operation: id.to_string(),
+            entity_id: entity_id.to_string(),
+            field,
+        };
+
+        for row in rows {
+            let operation = AuditOperation::parse(row.try_get("operation")?)?;
+            let changes: Value = serde_json::from_str(row.try_get::<Option<&str>, _>("changes")?.unwrap_or("null"))?;
+
+            if matches!(operation, AuditOperation::AddToCollection | AuditOperation::RemoveFromCollection) {
+                let Some(sound_id) = member(&changes) else { continue };
+                let left = entity.members_after.get(&sound_id).copied();
+                if entity.created || left.is_some() {
+                    let is_member = current
+                        .get("sound_ids")
+                        .and_then(Value::as_array)
+                        .is_some_and(|ids| ids.iter().any(|id| id.as_str() == Some(sound_id.as_str())));
+                    if is_member != left.unwrap_or(false) {
+                        return Err(conflict(format!("membership of {}", sound_id)));
+                    }
+                }
+                continue;
+            }
+
+            for field in changes.as_object().into_iter().flat_map(Map::keys) {
+                let left = entity.after.get(field);
+                if (entity.created || left.is_some())
+                    && current.get(field).unwrap_or(&Value::Null) != left.unwrap_or(&Value::Null)
+                {
+                    return Err(conflict(field.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set fields of a sound back to what they were
+    async fn restore_sound(&self, id: &str, before: &Map<String, Value>) -> Result<()> {
+        let current = self.get_sound(id).await?.metadata;
+        let mut value = serde_json::to_value(&current)?;
+        if let Some(fields) = value.as_object_mut() {
+            fields.extend(before.iter().map(|(field, old)| (field.clone(), old.clone())));
+        }
+        let restored: SoundMetadata = serde_json::from_value(value)?;
+
+        self.set_status(id, restored.status, &MetadataPatch::between(&current, &restored)).await?;
+        Ok(())
+    }
+
+    /// Set the name of a collection back to what it was
+    async fn restore_collection_name(&self, id: &str, name: &str, current: &Value) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(&self.sql("UPDATE collections SET name = ?, sort_key = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+            .bind(name)
+            .bind(self.collator.sort_key(name))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let changes = audit_diff(Some(&json!({ "name": current.get("name") })), Some(&json!({ "name": name })));
+        self.audit(&mut tx, AuditOperation::UpdateCollection, id, changes).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Put a sound back into a collection or take it out, leaving its
+    /// metadata alone
+    ///
+    /// # Returns
+    ///
+    /// Whether the collection changed
+    async fn set_member(&self, collection_id: &str, sound_id: &str, member: bool) -> Result<bool> {
+        let (operation, changes) = match member {
+            true => (AuditOperation::AddToCollection, json!({ "sound_id": [null, sound_id] })),
+            false => (AuditOperation::RemoveFromCollection, json!({ "sound_id": [sound_id, null] })),
+        };
+
+        let mut tx = self.db.begin().await?;
+        let changed = match member {
+            // A sound deleted since isn't put back
+            true => {
+                let exists: bool = sqlx::query_scalar(&self.sql("SELECT EXISTS (SELECT 1 FROM sounds WHERE id = ?)"))
+                    .bind(sound_id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                exists && self.insert_member(&mut tx, collection_id, sound_id).await?
+            }
+            false => self.delete_member(&mut tx, collection_id, sound_id).await?,
+        };
+        if !changed {
+            return Ok(false);
+        }
+        self.audit(&mut tx, operation, collection_id, changes).await?;
+        tx.commit().await?;
+
+        self.emit(VaultEvent::CollectionChanged { collection_id: collection_id.to_string() });
+        Ok(true)
+    }
+}
@@ -0,0 +1,246 @@
+//! Durable `soundvault://` references to sounds and collections
+
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+use crate::tables::Tables;
+use crate::models::{Collection, Sound};
+use sqlx::{Pool, Sqlite};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Scheme of vault URIs
+pub const URI_SCHEME: &str = "soundvault";
+
+/// Key of the vault's UUID in `vault_info`
+const VAULT_ID_KEY: &str = "vault_id";
+
+/// A parsed `soundvault://` URI
+///
+/// Sounds are `soundvault://<vault-uuid>/sound/<sound-id>`, or the sound's
+/// slug in place of its ID, optionally followed by `?hash=<sha256>` so the
+/// sound can be found by content in another vault; collections are
+/// `soundvault://<vault-uuid>/collection/<id>`.
+///
+/// # Examples
+///
+/// ```
+/// use soundvault::VaultUri;
+///
+/// let uri: VaultUri = "soundvault://67e55044-10b1-426f-9247-bb680e5fe0c8/sound/kick%2001?hash=ab12".parse().unwrap();
+/// assert_eq!(uri, VaultUri::Sound {
+///     vault_id: "67e55044-10b1-426f-9247-bb680e5fe0c8".to_string(),
+///     id: "kick 01".to_string(),
+///     hash: Some("ab12".to_string()),
+/// });
+/// assert_eq!(uri.to_string(), "soundvault://67e55044-10b1-426f-9247-bb680e5fe0c8/sound/kick%2001?hash=ab12");
+///
+/// assert!("soundvault://not-a-uuid/sound/1".parse::<VaultUri>().is_err());
+/// assert!("soundvault://67e55044-10b1-426f-9247-bb680e5fe0c8/tag/1".parse::<VaultUri>().is_err());
+/// assert!("https://67e55044-10b1-426f-9247-bb680e5fe0c8/sound/1".parse::<VaultUri>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VaultUri {
+    /// A sound
+    Sound {
+        /// UUID of the vault the sound belongs to
+        vault_id: String,
+        /// ID of the sound
+        id: String,
+        /// SHA-256 hash of the sound's content, if known
+        hash: Option<String>,
+    },
+    /// A collection
+    Collection {
+        /// UUID of the vault the collection belongs to
+        vault_id: String,
+        /// ID of the collection
+        id: String,
+    },
+}
+
+impl VaultUri {
+    /// UUID of the vault the URI points into
+    pub fn vault_id(&self) -> &str {
+        match self {
+            Self::Sound { vault_id, .. } | Self::Collection { vault_id, .. } => vault_id,
+        }
+    }
+}
+
+impl fmt::Display for VaultUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sound { vault_id, id, hash } => {
+                write!(f, "{}://{}/sound/{}", URI_SCHEME, vault_id, percent_encode(id))?;
+                if let Some(hash) = hash {
+                    write!(f, "?hash={}", percent_encode(hash))?;
+                }
+                Ok(())
+            }
+            Self::Collection { vault_id, id } => {
+                write!(f, "{}://{}/collection/{}", URI_SCHEME, vault_id, percent_encode(id))
+            }
+        }
+    }
+}
+
+impl FromStr for VaultUri {
+    type Err = VaultError;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let invalid = |reason: &str| VaultError::InvalidOperation(format!("Invalid vault URI {:?}: {}", uri, reason));
+
+        let rest = uri
+            .split_once("://")
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(URI_SCHEME))
+            .map(|(_, rest)| rest)
+            .ok_or_else(|| invalid("expected a soundvault:// URI"))?;
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+
+        let parts: Vec<&str> = path.split('/').collect();
+        let [vault_id, kind, id] = parts[..] else {
+            return Err(invalid("expected <vault>/<kind>/<id>"));
+        };
+        let vault_id = Uuid::parse_str(vault_id)
+            .map_err(|_| invalid("the vault is not a UUID"))?
+            .to_string();
+        let id = percent_decode(id).filter(|id| !id.is_empty()).ok_or_else(|| invalid("bad ID"))?;
+
+        let mut hash = None;
+        for pair in query.into_iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            match pair.split_once('=') {
+                Some(("hash", value)) => hash = Some(percent_decode(value).ok_or_else(|| invalid("bad hash"))?),
+                _ => return Err(invalid("unknown parameter")),
+            }
+        }
+
+        match kind {
+            "sound" => Ok(Self::Sound { vault_id, id, hash }),
+            "collection" if hash.is_none() => Ok(Self::Collection { vault_id, id }),
+            "collection" => Err(invalid("collections have no hash")),
+            _ => Err(invalid("expected sound or collection")),
+        }
+    }
+}
+
+/// Escape everything but unreserved characters
+fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            out.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+impl LocalLibrary {
+    /// Read the vault's UUID, generating it on first use
+    pub(crate) async fn load_vault_id(db: &Pool<Sqlite>, tables: &Tables) -> Result<String> {
+        sqlx::query(&tables.sql("INSERT OR IGNORE INTO vault_info (key, value) VALUES (?, ?)"))
+            .bind(VAULT_ID_KEY)
+            .bind(Uuid::new_v4().to_string())
+            .execute(db)
+            .await?;
+
+        Ok(sqlx::query_scalar(&tables.sql("SELECT value FROM vault_info WHERE key = ?"))
+            .bind(VAULT_ID_KEY)
+            .fetch_one(db)
+            .await?)
+    }
+
+    /// URI of a sound in this vault
+    pub fn uri_for(&self, id: &str) -> String {
+        VaultUri::Sound { vault_id: self.vault_id.clone(), id: id.to_string(), hash: None }.to_string()
+    }
+
+    /// URI of a sound carrying its content hash, resolvable in other vaults
+    pub async fn portable_uri_for(&self, id: &str) -> Result<String> {
+        let metadata = self.get_sound(id).await?.metadata;
+        Ok(VaultUri::Sound { vault_id: self.vault_id.clone(), id: metadata.id, hash: metadata.hash }.to_string())
+    }
+
+    /// URI of a collection in this vault
+    pub fn collection_uri_for(&self, id: &str) -> String {
+        VaultUri::Collection { vault_id: self.vault_id.clone(), id: id.to_string() }.to_string()
+    }
+
+    /// Find the sound a URI points to, by ID or else by slug
+    ///
+    /// With `by_hash`, a URI of another vault, or of a sound that no longer
+    /// exists, resolves to a sound with the content hash it carries.
+    pub async fn resolve_uri(&self, uri: &str, by_hash: bool) -> Result<Sound> {
+        let VaultUri::Sound { vault_id, id, hash } = uri.parse()? else {
+            return Err(VaultError::InvalidOperation(format!("Not a sound URI: {}", uri)));
+        };
+
+        if vault_id == self.vault_id {
+            let found = match self.get_sound(&id).await {
+                Err(VaultError::NotFound(_)) => self.get_sound_by_slug(&id).await,
+                result => result,
+            };
+            match found {
+                Err(VaultError::NotFound(_)) if by_hash && hash.is_some() => {}
+                result => return result,
+            }
+        } else if !by_hash {
+            return Err(VaultError::NotFound(format!("URI belongs to another vault: {}", uri)));
+        }
+
+        let hash = hash.ok_or_else(|| VaultError::NotFound(format!("URI has no content hash: {}", uri)))?;
+        let found = self.sound_with_hash(&hash).await?.ok_or_else(|| VaultError::NotFound(format!("No sound with the content of {}", uri)))?;
+        self.get_sound(&found).await
+    }
+
+    /// Path of the file of the sound a URI points to
+    ///
+    /// Archived sounds must be decompressed first, since their file doesn't
+    /// hold the original content, and packed sounds have no file of their
+    /// own.
+    pub async fn resolve_uri_to_path(&self, uri: &str, by_hash: bool) -> Result<PathBuf> {
+        let metadata = self.resolve_uri(uri, by_hash).await?.metadata;
+        if metadata.archive.is_some() {
+            return Err(VaultError::InvalidOperation(format!("Sound is archived: {}", metadata.id)));
+        }
+        if metadata.packed {
+            return Err(VaultError::InvalidOperation(format!("Sound is packed in an archive: {}", metadata.id)));
+        }
+        self.stage_blob(&metadata).await?;
+        self.readable_file(&metadata)
+    }
+
+    /// Find the collection a URI points to
+    pub async fn resolve_collection_uri(&self, uri: &str) -> Result<Collection> {
+        let VaultUri::Collection { vault_id, id } = uri.parse()? else {
+            return Err(VaultError::InvalidOperation(format!("Not a collection URI: {}", uri)));
+        };
+        if vault_id != self.vault_id {
+            return Err(VaultError::NotFound(format!("URI belongs to another vault: {}", uri)));
+        }
+        self.get_collection(&id).await
+    }
+}
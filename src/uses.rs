@@ -0,0 +1,214 @@
+//! Projects and sampler patches outside the vault that use its sounds
+//!
+//! A sound with recorded uses is protected from deletion, like a locked one:
+//! deleting it would break the projects still pointing at its file.
+
+#[cfg(feature = "ableton")]
+use crate::embedded::unescape;
+use crate::error::{Result, VaultError};
+use crate::local::LocalLibrary;
+#[cfg(feature = "ableton")]
+use crate::local::hash_file;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+#[cfg(feature = "ableton")]
+use std::path::Path;
+
+/// Name recorded as the consumer of the uses found by
+/// [`SoundVault::scan_ableton_file`](crate::SoundVault::scan_ableton_file)
+#[cfg(feature = "ableton")]
+pub const ABLETON_CONSUMER: &str = "Ableton Live";
+
+/// A project or patch using a sound
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalUse {
+    /// Program or person using the sound, e.g. `Ableton Live` or `Kontakt`
+    pub consumer: String,
+
+    /// Path or URI of the project or patch
+    pub location: String,
+
+    /// When the use was last seen
+    pub used_at: DateTime<Utc>,
+}
+
+impl ExternalUse {
+    /// Use by `consumer` in the project or patch at `location`, seen now
+    pub fn new(consumer: impl Into<String>, location: impl Into<String>) -> Self {
+        Self {
+            consumer: consumer.into(),
+            location: location.into(),
+            used_at: Utc::now(),
+        }
+    }
+}
+
+impl LocalLibrary {
+    /// Record that a project or patch uses a sound
+    ///
+    /// A use already recorded for the same consumer and location only has
+    /// its time updated.
+    pub async fn register_external_use(&self, sound_id: &str, context: &ExternalUse) -> Result<()> {
+        self.get_sound(sound_id).await?;
+        self.write_external_use(&mut *self.db.acquire().await?, sound_id, context).await
+    }
+
+    /// Store a use of a sound, or update the time of the same use
+    pub(crate) async fn write_external_use(&self, conn: &mut SqliteConnection, sound_id: &str, context: &ExternalUse) -> Result<()> {
+        sqlx::query(&self.sql(
+            r#"
+            INSERT INTO sound_uses (sound_id, consumer, location, used_at) VALUES (?, ?, ?, ?)
+            ON CONFLICT (sound_id, consumer, location) DO UPDATE SET used_at = excluded.used_at
+            "#,
+        ))
+        .bind(sound_id)
+        .bind(&context.consumer)
+        .bind(&context.location)
+        .bind(context.used_at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Projects and patches using a sound, most recently seen first
+    pub async fn list_uses(&self, sound_id: &str) -> Result<Vec<ExternalUse>> {
+        let uses = self.uses_of(sound_id).await?;
+        if uses.is_empty() {
+            self.get_sound(sound_id).await?;
+        }
+        Ok(uses)
+    }
+
+    /// Uses recorded for a sound, most recently seen first, without checking
+    /// that the sound exists
+    pub(crate) async fn uses_of(&self, sound_id: &str) -> Result<Vec<ExternalUse>> {
+        let rows = sqlx::query(&self.sql(
+            "SELECT consumer, location, used_at FROM sound_uses WHERE sound_id = ? ORDER BY used_at DESC, location",
+        ))
+        .bind(sound_id)
+        .fetch_all(&self.reader)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(ExternalUse {
+                    consumer: row.try_get("consumer")?,
+                    location: row.try_get("location")?,
+                    used_at: row.try_get("used_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// IDs of the sounds a project or patch uses
+    pub async fn sounds_used_by(&self, location: &str) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(&self.sql("SELECT DISTINCT sound_id FROM sound_uses WHERE location = ? ORDER BY sound_id"))
+            .bind(location)
+            .fetch_all(&self.reader)
+            .await?)
+    }
+
+    /// Forget the uses recorded for a project or patch, e.g. once it's
+    /// deleted
+    ///
+    /// # Returns
+    ///
+    /// The number of uses forgotten
+    pub async fn forget_external_uses(&self, location: &str) -> Result<u64> {
+        Ok(sqlx::query(&self.sql("DELETE FROM sound_uses WHERE location = ?"))
+            .bind(location)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    /// Refuse deleting a sound some project or patch uses
+    pub(crate) async fn check_unused(&self, sound_id: &str) -> Result<()> {
+        let locations: Vec<String> =
+            sqlx::query_scalar(&self.sql("SELECT DISTINCT location FROM sound_uses WHERE sound_id = ? ORDER BY location"))
+                .bind(sound_id)
+                .fetch_all(&self.reader)
+                .await?;
+        if !locations.is_empty() {
+            return Err(VaultError::InUse {
+                sound_id: sound_id.to_string(),
+                locations,
+            });
+        }
+        Ok(())
+    }
+
+    /// Record the uses of vault sounds by an Ableton Live set (`.als`) or
+    /// device preset (`.adg`), replacing those recorded for it before
+    ///
+    /// A sample is a vault sound when its path is the sound's file, or when
+    /// the file there has the content of a sound, e.g. after Live copied it
+    /// into the project.
+    ///
+    /// # Returns
+    ///
+    /// IDs of the sounds used
+    #[cfg(feature = "ableton")]
+    pub async fn scan_ableton_file(&self, path: &Path) -> Result<Vec<String>> {
+        let path = std::path::absolute(path)?;
+        let samples = ableton_sample_paths(&std::fs::read(&path)?)?;
+
+        let mut ids = Vec::new();
+        for sample in samples {
+            let by_path: Option<String> = sqlx::query_scalar(&self.sql("SELECT id FROM sounds WHERE path = ?"))
+                .bind(&sample)
+                .fetch_optional(&self.reader)
+                .await?;
+            let id = match by_path {
+                Some(id) => Some(id),
+                None if Path::new(&sample).is_file() => {
+                    self.sound_with_hash(&hash_file(Path::new(&sample))?).await?
+                }
+                None => None,
+            };
+            if let Some(id) = id
+                && !ids.contains(&id)
+            {
+                ids.push(id);
+            }
+        }
+
+        let location = path.to_string_lossy().to_string();
+        self.forget_external_uses(&location).await?;
+        let context = ExternalUse::new(ABLETON_CONSUMER, location);
+        for id in &ids {
+            self.register_external_use(id, &context).await?;
+        }
+
+        Ok(ids)
+    }
+}
+
+/// Paths of the samples an Ableton Live file points at
+///
+/// Live writes its sets and presets as gzipped XML, naming each sample in a
+/// `<Path Value="..."/>` element of its file reference.
+#[cfg(feature = "ableton")]
+fn ableton_sample_paths(bytes: &[u8]) -> Result<Vec<String>> {
+    use std::io::Read;
+
+    let mut xml = String::new();
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_string(&mut xml)
+            .map_err(|e| VaultError::InvalidOperation(format!("Unreadable Ableton file: {}", e)))?;
+    } else {
+        xml = String::from_utf8_lossy(bytes).into_owned();
+    }
+
+    let pattern = regex::Regex::new(r#"<Path\s+Value="([^"]*)""#).expect("valid regex");
+    let mut paths: Vec<String> = pattern
+        .captures_iter(&xml)
+        .map(|captures| unescape(&captures[1]))
+        .filter(|path| !path.is_empty())
+        .collect();
+    paths.dedup();
+    Ok(paths)
+}
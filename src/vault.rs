@@ -1,21 +1,143 @@
 //! Main module for SoundVault
 
+use crate::access::AccessFlusher;
+use crate::acquire::AcquireRequest;
+use crate::archive::{ArchivalCodec, ColdStoragePolicy, ColdStorageReport};
+use crate::artifacts::{DiskUsage, GcPlan, GcReport};
+use crate::attribution::ExportOptions;
+use crate::audio::ChannelMix;
+use crate::audit::AuditEntry;
+use crate::availability::RelinkReport;
+use crate::blob::{BlobMigrationReport, BlobStore};
+use crate::browse::BrowseNode;
+use crate::bulk::{BatchOutcome, BulkPatchOptions};
+use crate::cas::CasMigrationReport;
+use crate::channels::{ChannelAsset, ChannelOptions};
+use crate::changes::{Change, ChangeCursor};
+use crate::checkout::CheckoutHandle;
 use crate::config::VaultConfig;
+use crate::context::OpContext;
+use crate::cursor::SoundCursor;
+use crate::daw::DawFlavor;
+use crate::derivative::AudioOp;
+use crate::downloads::{DownloadWorker, QueuedDownload};
+use crate::dump::{DumpStats, LoadMode};
 use crate::error::{Result, VaultError};
+use crate::events::{EventFilter, VaultEvent};
+use crate::expiration::ExpireAction;
+use crate::fields::{FieldSpec, FieldViolation, SchemaMode};
+#[cfg(feature = "analysis")]
+use crate::fingerprint::DuplicateGroup;
+use crate::governor::ResourceLimits;
+use crate::groups::{GroupKind, GroupPicker, SoundGroup};
+use crate::health::HealthReport;
+use crate::import::{ImportOptions, SoundMetadataTemplate};
+use crate::integrity::{IntegrityReport, QuarantinedFile, RepairPlan, RepairPolicy};
+use crate::interactive::InteractiveSearch;
+use crate::journal::RecoveryReport;
+use crate::levels::{LevelOptions, Levels};
 use crate::local::LocalLibrary;
-use crate::models::{Collection, Sound, SoundMetadata, SoundSource};
-use crate::remote::FreesoundManager;
-use sqlx::sqlite::SqlitePoolOptions;
-use std::path::Path;
+use crate::maintenance::{Maintainer, MaintenanceReport, MaintenanceTasks};
+use crate::manifest::{Manifest, ManifestDiff, ManifestFormat};
+use crate::merge::{MergePolicy, MergeReport};
+use crate::mirror::{DirectorySyncReport, SyncOptions};
+use crate::models::{Collection, CollectionDefaults, CollectionSummary, Sound, SoundMetadata, SoundSource, Trim};
+use crate::patch::MetadataPatch;
+use crate::paths::copy_atomic;
+use crate::permissions::Role;
+use crate::playback::{DecodedAudio, DecodedStream, OutputSpec};
+use crate::provenance::ProvenanceEntry;
+use crate::provision::{ProvisionReport, VaultSpec};
+use crate::similar::{SimilarSounds, SimilarityWeights};
+use crate::slice::{SliceOptions, SliceStrategy};
+use crate::sniff::ExtensionMismatch;
+use crate::query::{PageRequest, SoundFilter, SoundPage};
+use crate::quota::QuotaUsage;
+use crate::remote::{FreesoundManager, PreviewStream};
+use crate::replace::{ReplaceOptions, ReplaceReport, TextScope};
+use crate::review::ApproveOptions;
+use crate::scrub::{ScrubReport, Scrubber};
+use crate::sidecar::{SidecarRebuildReport, SidecarWriter};
+use crate::source::{RemoteSearchResults, RemoteSource, SharedSources};
+use crate::stats::{DimensionStats, SoftLimit, StatsDimension};
+use crate::subscription::{RemoteSubscription, SyncReport};
+use crate::tags::{TagCount, TagRename};
+use crate::undo::{OperationId, OperationSummary, UndoReport};
+use crate::uses::ExternalUse;
+use crate::zip::{ZipOptions, ZipReport};
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+use tokio_stream::Stream;
+
+/// Number of times opening a locked database is retried before giving up
+const OPEN_RETRIES: u32 = 5;
+
+/// Connections of the read-only pool queries run on
+const READ_CONNECTIONS: u32 = 4;
+
+/// Header found at the start of every SQLite database file
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Outcome of [`SoundVault::recover_database`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseRecovery {
+    /// The damaged file was archived and replaced by the backup copy
+    RestoredFromBackup {
+        /// Backup file the database was restored from
+        backup: PathBuf,
+        /// Where the damaged database file was moved
+        archived: PathBuf,
+    },
+    /// The damaged file was archived and a fresh database will be created on next open
+    Reinitialized {
+        /// Where the damaged database file was moved
+        archived: PathBuf,
+    },
+}
+
+/// Outcome of [`SoundVault::close`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Background jobs that were waiting for a worker and were cancelled
+    pub cancelled_jobs: usize,
+
+    /// Background jobs still running when the timeout elapsed
+    pub abandoned_jobs: usize,
+
+    /// Whether the write-ahead log was fully checkpointed into the database
+    pub wal_checkpointed: bool,
+}
 
 /// Main entry point for SoundVault functionality
 pub struct SoundVault {
-    /// Local library manager
-    local: LocalLibrary,
+    /// Local library manager, shared with the download worker
+    local: Arc<LocalLibrary>,
     /// Freesound manager (optional)
-    remote: Option<FreesoundManager>,
+    remote: Option<Arc<FreesoundManager>>,
+    /// Remote sources searched and downloaded from, Freesound included
+    sources: SharedSources,
+    /// Worker running the download queue
+    downloads: Arc<DownloadWorker>,
+    /// Background task re-hashing stored files
+    scrubber: Scrubber,
+    /// Background task maintaining the database
+    maintainer: Maintainer,
+    /// Background task writing last-accessed times
+    access_flusher: AccessFlusher,
+    /// Background task writing metadata sidecars
+    sidecars: SidecarWriter,
     /// Configuration
     config: VaultConfig,
+    /// Whether the database pool belongs to the host application, which
+    /// closes it
+    shared_database: bool,
 }
 
 impl SoundVault {
@@ -37,6 +159,27 @@ impl SoundVault {
     /// # Ok(())
     /// # }
     /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Corrupt` if the database file is empty or damaged; see
+    ///   [`SoundVault::recover_database`]
+    /// * `VaultError::Config` if the database directory is missing or read-only,
+    ///   or the database stays locked by another process
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// config.database_path = dir.path().join("missing").join("soundvault.db");
+    ///
+    /// assert!(matches!(SoundVault::new(config).await, Err(VaultError::Config(_))));
+    /// # Ok(())
+    /// # }
+    /// ```
     pub async fn new(config: VaultConfig) -> Result<Self> {
         // Validate configuration
         config.validate()?;
@@ -48,26 +191,5300 @@ impl SoundVault {
         }
 
         // Connect to SQLite database
-        let db = SqlitePoolOptions::new()
-            .max_connections(5)
-            .connect(&format!("sqlite:{}", config.database_path.display()))
-            .await
-            .map_err(|e| VaultError::Database(e))?;
+        let db = Self::open_database(&config).await?;
+        let reader = Self::open_reader(&config);
+
+        Self::start(config, db, reader, false).await
+    }
+
+    /// Create a SoundVault in a database the host application already uses
+    ///
+    /// The vault's tables are named with `config.table_prefix`, so they sit
+    /// beside the host's own. The pool stays the host's:
+    /// [`close`](Self::close) doesn't close it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, VaultConfig, VaultError};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}?mode=rwc", dir.path().join("app.db").display())).await?;
+    /// sqlx::query("CREATE TABLE sounds (title TEXT)").execute(&pool).await?;
+    ///
+    /// let config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// let vault = SoundVault::with_pool(config.clone(), pool.clone()).await?;
+    /// vault.close(Duration::from_secs(5)).await?;
+    ///
+    /// // The host's table is untouched, and its pool still open
+    /// let tables: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE name LIKE '%sounds'")
+    ///     .fetch_all(&pool)
+    ///     .await?;
+    /// assert!(tables.contains(&"sounds".to_string()) && tables.contains(&"sv_sounds".to_string()));
+    ///
+    /// // Opening the vault's tables under another prefix is refused
+    /// let mut other = config;
+    /// other.table_prefix = "media_".to_string();
+    /// assert!(matches!(SoundVault::with_pool(other, pool).await, Err(VaultError::Config(_))));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Config` if the prefix isn't an identifier, or the
+    ///   database holds the vault's tables under another prefix
+    pub async fn with_pool(config: VaultConfig, db: Pool<Sqlite>) -> Result<Self> {
+        config.validate()?;
+        Self::start(config, db.clone(), db, true).await
+    }
 
+    /// Start the vault on an open database
+    async fn start(config: VaultConfig, db: Pool<Sqlite>, reader: Pool<Sqlite>, shared_database: bool) -> Result<Self> {
         // Initialize local library
-        let local = LocalLibrary::new(db, config.library_path.clone()).await?;
+        let local = Arc::new(LocalLibrary::new(db, reader, &config, shared_database).await?);
 
         // Initialize remote manager if API key is provided
         let remote = config.freesound_api_key.clone().map(|api_key| {
-            FreesoundManager::new(api_key, config.library_path.clone())
+            Arc::new(FreesoundManager::new(api_key, config.library_path.clone()))
+        });
+        let sources: Vec<Arc<dyn RemoteSource>> = match &remote {
+            Some(remote) => vec![remote.clone()],
+            None => Vec::new(),
+        };
+        let sources = Arc::new(RwLock::new(sources));
+
+        let downloads = Arc::new(DownloadWorker::new(config.downloads_per_minute));
+        let scrubber = Scrubber::start(&local, config.scrub_interval.map(Duration::from));
+        let maintainer = Maintainer::start(&local, config.auto_maintenance);
+        let access_flusher = AccessFlusher::start(&local, config.access_flush_interval.map(Duration::from));
+        let sidecars = SidecarWriter::start(&local, config.write_sidecars);
+
+        // The rest of the startup work doesn't hold up opening; a failed
+        // warm-up is retried by warm_up, close or the download worker, which
+        // warms up before taking its first download
+        tokio::spawn({
+            let (local, downloads, sources) = (local.clone(), downloads.clone(), sources.clone());
+            let resume = config.resume_downloads;
+            async move {
+                let _ = local.warm_up().await;
+                if resume {
+                    downloads.wake(&local, &sources);
+                }
+            }
         });
 
         Ok(Self {
             local,
             remote,
+            sources,
+            downloads,
+            scrubber,
+            maintainer,
+            access_flusher,
+            sidecars,
             config,
+            shared_database,
         })
     }
 
-    // This will be filled with the actual implementation
+    /// Import a sound file into the local library
+    ///
+    /// Duration, channel count and sample rate are read from WAV, AIFF and CAF
+    /// headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `source_path` - Path to the sound file to import
+    /// * `metadata` - Optional metadata to set for the sound
+    ///
+    /// # Returns
+    ///
+    /// The ID of the imported sound
+    pub async fn import_file<P: AsRef<Path>>(&self, source_path: P, metadata: Option<SoundMetadata>) -> Result<String> {
+        self.local.authorize(Role::Editor, "import_file", "").await?;
+        self.local.import_file(source_path, metadata).await
+    }
+
+    /// Write the vault's metadata as newline-delimited JSON, e.g. for backups
+    ///
+    /// The dump starts with a header giving the format and schema versions,
+    /// followed by one [`DumpRecord`](crate::DumpRecord) per line: custom field declarations,
+    /// import templates, sounds with their provenance and collections. Memory use doesn't grow with
+    /// the size of the library. Files aren't included.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, LoadMode, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("rain.wav");
+    /// std::fs::write(&file, b"rain")?;
+    /// let id = vault.import_file(&file, None).await?;
+    /// let collection_id = vault.add_collection(&Collection::new("Weather", "")).await?;
+    /// vault.add_sound_to_collection(&id, &collection_id).await?;
+    ///
+    /// let mut dump = Vec::new();
+    /// let stats = vault.dump_metadata(&mut dump).await?;
+    /// assert_eq!((stats.sounds, stats.provenance_entries, stats.collections), (1, 1, 1));
+    ///
+    /// let copy_dir = tempfile::tempdir()?;
+    /// let copy = SoundVault::new(VaultConfig::new(copy_dir.path().to_path_buf(), None)).await?;
+    /// copy.load_dump(dump.as_slice(), LoadMode::Restore).await?;
+    /// assert_eq!(copy.get_collection(&collection_id).await?.sound_ids, vec![id]);
+    ///
+    /// // Only the header's timestamp differs
+    /// let mut again = Vec::new();
+    /// copy.dump_metadata(&mut again).await?;
+    /// let body = |dump: &[u8]| String::from_utf8(dump.to_vec()).unwrap().lines().skip(1).collect::<Vec<_>>().join("\n");
+    /// assert_eq!(body(&dump), body(&again));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn dump_metadata<W: AsyncWrite + Unpin>(&self, writer: W) -> Result<DumpStats> {
+        self.dump_metadata_with_context(writer, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::dump_metadata`], stopped by `context` between batches
+    /// of sounds
+    ///
+    /// A stopped dump is incomplete and should be discarded.
+    pub async fn dump_metadata_with_context<W: AsyncWrite + Unpin>(&self, writer: W, context: &OpContext) -> Result<DumpStats> {
+        self.local.dump_metadata(writer, context).await
+    }
+
+    /// Load a dump written by [`SoundVault::dump_metadata`]
+    ///
+    /// The dump is loaded in one transaction, so an invalid record leaves the
+    /// vault unchanged. Sounds are written as dumped, without checking their
+    /// custom metadata against the declared fields.
+    pub async fn load_dump<R: AsyncRead + Unpin>(&self, reader: R, mode: LoadMode) -> Result<DumpStats> {
+        self.load_dump_with_context(reader, mode, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::load_dump`], stopped by `context` between records
+    ///
+    /// A stopped load leaves the vault unchanged.
+    pub async fn load_dump_with_context<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        mode: LoadMode,
+        context: &OpContext,
+    ) -> Result<DumpStats> {
+        self.local.authorize(Role::Admin, "load_dump", "").await?;
+        Ok(self.local.in_operation("load_dump", self.local.load_dump(reader, mode, context)).await?.0)
+    }
+
+    /// Merge the sounds and collections of another vault into this one
+    ///
+    /// Each sound of `other` is matched with a sound of this vault with the
+    /// same content, or else the same Freesound ID, whose tags, custom
+    /// metadata and description are then merged by `policy`; unmatched
+    /// sounds are copied in. Provenance chains come along. Collections are
+    /// matched by name and get the members of both, and the other vault's
+    /// other collections are created.
+    ///
+    /// Every field matched sounds disagreed on is listed in the report, with
+    /// the decision taken. Progress is recorded as the merge goes: merging
+    /// the same vault again, e.g. after an interruption, only merges the
+    /// sounds not merged yet, and reports the earlier ones as they were.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, MergeDecision, MergePolicy, SoundMetadata, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let files = tempfile::tempdir()?;
+    /// let (ours_dir, theirs_dir) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    /// let ours = SoundVault::new(VaultConfig::new(ours_dir.path().to_path_buf(), None)).await?;
+    /// let theirs = SoundVault::new(VaultConfig::new(theirs_dir.path().to_path_buf(), None)).await?;
+    /// let (rain, thunder) = (files.path().join("rain.wav"), files.path().join("thunder.wav"));
+    /// std::fs::write(&rain, "rain")?;
+    /// std::fs::write(&thunder, "thunder")?;
+    ///
+    /// let mut metadata = SoundMetadata { name: "Rain".to_string(), tags: vec!["rain".to_string()], ..Default::default() };
+    /// metadata.set_custom("mic", "MKH 416");
+    /// let ours_rain = ours.import_file(&rain, Some(metadata)).await?;
+    /// let weather = ours.add_collection(&Collection::new("Weather", "")).await?;
+    /// ours.add_sound_to_collection(&ours_rain, &weather).await?;
+    ///
+    /// let mut metadata = SoundMetadata { name: "Rain".to_string(), tags: vec!["storm".to_string()], ..Default::default() };
+    /// metadata.set_custom("mic", "NTG3");
+    /// metadata.set_custom("room", "porch");
+    /// theirs.import_file(&rain, Some(metadata)).await?;
+    /// let theirs_thunder = theirs.import_file(&thunder, None).await?;
+    /// let their_weather = theirs.add_collection(&Collection::new("Weather", "")).await?;
+    /// theirs.add_sound_to_collection(&theirs_thunder, &their_weather).await?;
+    ///
+    /// let report = ours.merge_from(&theirs, MergePolicy::Union).await?;
+    /// assert_eq!(report.matched_by_hash, vec![ours_rain.clone()]);
+    /// assert_eq!(report.imported.len(), 1);
+    /// assert_eq!(report.collections_merged, vec!["Weather".to_string()]);
+    /// let decisions: Vec<(&str, MergeDecision)> = report.conflicts.iter().map(|c| (c.field.as_str(), c.decision)).collect();
+    /// assert_eq!(decisions, vec![
+    ///     ("tags", MergeDecision::Combined),
+    ///     ("custom.mic", MergeDecision::KeptOurs),
+    ///     ("custom.room", MergeDecision::TookTheirs),
+    /// ]);
+    ///
+    /// let rain = ours.get_sound(&ours_rain).await?.metadata;
+    /// assert_eq!(rain.tags, vec!["rain".to_string(), "storm".to_string()]);
+    /// assert_eq!(rain.get_custom("mic").map(String::as_str), Some("MKH 416"));
+    /// assert_eq!(ours.get_collection(&weather).await?.sound_ids, vec![ours_rain.clone(), report.imported[0].clone()]);
+    /// let provenance = (ours.provenance(&ours_rain).await?, ours.provenance(&report.imported[0]).await?);
+    ///
+    /// // Merging again picks up what was merged, and changes nothing
+    /// let again = ours.merge_from(&theirs, MergePolicy::Union).await?;
+    /// assert_eq!(again.resumed, 2);
+    /// assert_eq!(
+    ///     (&again.matched_by_hash, &again.imported, &again.conflicts),
+    ///     (&report.matched_by_hash, &report.imported, &report.conflicts),
+    /// );
+    /// assert!(again.collections_merged.is_empty());
+    /// assert_eq!((ours.provenance(&ours_rain).await?, ours.provenance(&report.imported[0]).await?), provenance);
+    /// assert_eq!(ours.get_sound(&ours_rain).await?.metadata.tags, rain.tags);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn merge_from(&self, other: &SoundVault, policy: MergePolicy) -> Result<MergeReport> {
+        self.local.authorize(Role::Editor, "merge_from", other.vault_id()).await?;
+        Ok(self.local.in_operation("merge_from", self.local.merge_from(&other.local, policy)).await?.0)
+    }
+
+    /// Import a sound file, filling in its metadata from an import template,
+    /// or reference it where it lies
+    ///
+    /// Fields set in `metadata` win over the template's.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ImportOptions, SoundMetadataTemplate, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// let mut footsteps = SoundMetadataTemplate::default();
+    /// footsteps.description = "Footsteps on {folder}, take {filename}".to_string();
+    /// footsteps.license = Some("CC0".to_string());
+    /// footsteps.custom.insert("recordist".to_string(), "Ana".to_string());
+    /// vault.save_import_template("footsteps", &footsteps).await?;
+    ///
+    /// std::fs::create_dir(dir.path().join("snow"))?;
+    /// let file = dir.path().join("snow").join("take1.wav");
+    /// std::fs::write(&file, b"crunch")?;
+    /// let options = ImportOptions { template: Some("footsteps".to_string()), ..Default::default() };
+    /// let id = vault.import_file_with_options(&file, None, options).await?;
+    ///
+    /// let metadata = vault.get_sound(&id).await?.metadata;
+    /// assert_eq!(metadata.description, "Footsteps on snow, take take1");
+    /// assert_eq!(metadata.get_custom("recordist").map(String::as_str), Some("Ana"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn import_file_with_options<P: AsRef<Path>>(
+        &self,
+        source_path: P,
+        metadata: Option<SoundMetadata>,
+        options: ImportOptions,
+    ) -> Result<String> {
+        self.local.authorize(Role::Editor, "import_file_with_options", "").await?;
+        self.local.import_file_with_options(source_path.as_ref(), metadata, &options).await
+    }
+
+    /// Move sounds out of the review inbox into the library
+    ///
+    /// Sounds imported with [`ImportOptions::inbox`] are left out of
+    /// searches and listings until approved. The template, if any, fills in
+    /// the metadata the sounds lack, as it does on import. Each sound whose
+    /// status changes is announced as a [`VaultEvent::SoundStatusChanged`].
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds that weren't active yet
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     ApproveOptions, Collection, ImportOptions, SoundFilter, SoundMetadataTemplate, SoundStatus, SoundVault,
+    ///     StatsDimension, VaultConfig, VaultEvent,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    ///
+    /// let options = ImportOptions { inbox: true, ..Default::default() };
+    /// let mut ids = Vec::new();
+    /// for (file, content) in [("rain.wav", "drops"), ("hum.wav", "buzz")] {
+    ///     let path = dir.path().join(file);
+    ///     std::fs::write(&path, content)?;
+    ///     ids.push(vault.import_file_with_options(&path, None, options.clone()).await?);
+    /// }
+    ///
+    /// // Sounds under review are only found when asked for
+    /// assert!(vault.search_local("rain", None).await?.is_empty());
+    /// let inbox = SoundFilter { statuses: vec![SoundStatus::Inbox], ..Default::default() };
+    /// assert_eq!(vault.count(&inbox).await?, 2);
+    ///
+    /// let mut weather = SoundMetadataTemplate::default();
+    /// weather.tags = vec!["weather".to_string()];
+    /// vault.save_import_template("weather", &weather).await?;
+    /// let collection = vault.add_collection(&Collection::new("Weather", "")).await?;
+    ///
+    /// let mut events = vault.subscribe();
+    /// let approve = ApproveOptions { template: Some("weather".to_string()), collection_id: Some(collection.clone()) };
+    /// assert_eq!(vault.approve(&ids[..1], approve).await?, 1);
+    /// vault.reject(&ids[1..]).await?;
+    ///
+    /// let found = vault.search_local("rain", Some(&["weather"])).await?;
+    /// assert_eq!(found[0].metadata.status, SoundStatus::Active);
+    /// assert_eq!(vault.get_collection(&collection).await?.sound_ids, ids[..1]);
+    /// assert!(matches!(
+    ///     events.recv().await?,
+    ///     VaultEvent::SoundStatusChanged { status: SoundStatus::Active, .. }
+    /// ));
+    ///
+    /// // Rejected sounds wait in the quarantine directory
+    /// assert!(vault.get_sound(&ids[1]).await.is_err());
+    /// assert_eq!(vault.list_quarantine()?.len(), 1);
+    /// let stats = vault.stats_by(StatsDimension::Status).await?;
+    /// assert_eq!(stats.iter().map(|s| (s.value.as_deref(), s.count)).collect::<Vec<_>>(), [(Some("active"), 1)]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn approve(&self, ids: &[String], options: ApproveOptions) -> Result<usize> {
+        self.local.authorize(Role::Editor, "approve", "").await?;
+        Ok(self.local.in_operation("approve", self.local.approve(ids, &options)).await?.0)
+    }
+
+    /// Turn sounds down in review, moving them to the trash
+    ///
+    /// Their files go into the quarantine directory, from which
+    /// [`SoundVault::restore_from_quarantine`] can bring them back, and the
+    /// sounds are deleted. Each is announced as a
+    /// [`VaultEvent::SoundStatusChanged`] to
+    /// [`SoundStatus::Rejected`](crate::SoundStatus::Rejected)
+    /// first. See [`SoundVault::approve`] for an example.
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds rejected
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Locked` if a sound is locked; the sounds before it are
+    ///   rejected
+    pub async fn reject(&self, ids: &[String]) -> Result<usize> {
+        self.local.authorize(Role::Admin, "reject", "").await?;
+        self.local.reject(ids).await
+    }
+
+    /// Index the sounds of a zip archive, e.g. a sound pack, without
+    /// extracting them
+    ///
+    /// Each audio entry becomes an external sound whose path is
+    /// `<archive>!<entry>`, with its duration and hash read from the entry.
+    /// Playing, decoding and exporting a packed sound inflate its entry on
+    /// demand; deleting it only removes it from the index. Entries indexed
+    /// before are skipped, so a grown pack can be indexed again.
+    ///
+    /// Stored and deflated entries are read; Zip64 and encrypted archives
+    /// aren't.
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the new sounds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundVault, VaultConfig, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let wav = encode(&AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16)), &[0.25; 8000])?;
+    /// # // A zip archive holding kit/kick.wav uncompressed
+    /// # let (name, crc, size) = (b"kit/kick.wav", crc32fast::hash(&wav), wav.len() as u32);
+    /// # let mut zip = 0x04034b50u32.to_le_bytes().to_vec();
+    /// # zip.extend([20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    /// # zip.extend([crc, size, size].map(u32::to_le_bytes).concat());
+    /// # zip.extend([name.len() as u8, 0, 0, 0]);
+    /// # zip.extend(name);
+    /// # zip.extend(&wav);
+    /// # let directory = zip.len() as u32;
+    /// # zip.extend(0x02014b50u32.to_le_bytes());
+    /// # zip.extend([20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    /// # zip.extend([crc, size, size].map(u32::to_le_bytes).concat());
+    /// # zip.extend([name.len() as u8, 0]);
+    /// # zip.extend([0; 16]);
+    /// # zip.extend(name);
+    /// # let directory_size = zip.len() as u32 - directory;
+    /// # zip.extend(0x06054b50u32.to_le_bytes());
+    /// # zip.extend([0, 0, 0, 0, 1, 0, 1, 0]);
+    /// # zip.extend([directory_size, directory].map(u32::to_le_bytes).concat());
+    /// # zip.extend([0, 0]);
+    /// let pack = dir.path().join("drums.zip");
+    /// std::fs::write(&pack, zip)?;
+    ///
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    /// let ids = vault.index_archive(&pack).await?;
+    /// assert_eq!(ids.len(), 1);
+    ///
+    /// let sound = vault.get_sound(&ids[0]).await?;
+    /// assert!(sound.metadata.packed);
+    /// assert_eq!(sound.metadata.duration, 1.0);
+    /// assert_eq!(vault.open_sound(&ids[0]).await?, wav);
+    ///
+    /// // Indexing again finds nothing new
+    /// assert!(vault.index_archive(&pack).await?.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn index_archive<P: AsRef<Path>>(&self, zip_path: P) -> Result<Vec<String>> {
+        self.local.authorize(Role::Editor, "index_archive", "").await?;
+        self.local.index_archive(zip_path.as_ref()).await
+    }
+
+    /// Save a named import template, replacing any with the same name
+    pub async fn save_import_template(&self, name: &str, template: &SoundMetadataTemplate) -> Result<()> {
+        self.local.authorize(Role::Editor, "save_import_template", "").await?;
+        self.local.save_import_template(name, template).await
+    }
+
+    /// Delete an import template
+    pub async fn remove_import_template(&self, name: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "remove_import_template", "").await?;
+        self.local.remove_import_template(name).await
+    }
+
+    /// Import templates, by name
+    pub async fn list_import_templates(&self) -> Result<BTreeMap<String, SoundMetadataTemplate>> {
+        self.local.list_import_templates().await
+    }
+
+    /// Replace the file of a sound, keeping its ID and metadata
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the sound
+    /// * `source_path` - Path to the new file
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Locked` if the sound is locked
+    pub async fn replace_file<P: AsRef<Path>>(&self, id: &str, source_path: P) -> Result<()> {
+        self.local.authorize(Role::Editor, "replace_file", id).await?;
+        self.local.replace_file(id, source_path.as_ref(), false).await
+    }
+
+    /// Replace the file of a sound like [`SoundVault::replace_file`], even
+    /// if it's locked
+    pub async fn replace_file_overriding_lock<P: AsRef<Path>>(&self, id: &str, source_path: P) -> Result<()> {
+        self.local.authorize(Role::Admin, "replace_file_overriding_lock", id).await?;
+        self.local.replace_file(id, source_path.as_ref(), true).await
+    }
+
+    /// Check a sound out for editing in another program
+    ///
+    /// Its file is copied to the handle's working path, which may be edited
+    /// freely; [`SoundVault::checkin`] then replaces the sound's file with
+    /// it like [`SoundVault::replace_file`]. The checkout is recorded with
+    /// the actor, and holds until checked in or released with
+    /// [`SoundVault::release_checkout`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundVault, VaultConfig, VaultError, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let file = dir.path().join("hit.wav");
+    /// std::fs::write(&file, encode(&info, &[0.5; 8000])?)?;
+    /// let id = vault.import_file(&file, None).await?;
+    /// let before = vault.get_sound(&id).await?.metadata;
+    ///
+    /// let handle = vault.checkout(&id).await?;
+    /// assert!(matches!(vault.checkout(&id).await, Err(VaultError::CheckedOut { .. })));
+    ///
+    /// // Halve the length in the working copy, then check it in
+    /// std::fs::write(&handle.working_path, encode(&info, &[0.5; 4000])?)?;
+    /// assert!(vault.checkin(&handle).await?);
+    /// let after = vault.get_sound(&id).await?.metadata;
+    /// assert_ne!(after.hash, before.hash);
+    /// assert_eq!(after.duration, 0.5);
+    /// assert!(!handle.working_path.exists());
+    /// assert!(vault.list_checkouts(None).await?.is_empty());
+    ///
+    /// // A stale checkout is released without touching the sound
+    /// vault.checkout(&id).await?;
+    /// vault.release_checkout(&id).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.hash, after.hash);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::CheckedOut` if the sound is checked out already
+    /// * `VaultError::Locked` if the sound is locked
+    /// * `VaultError::InvalidOperation` if the sound's file is referenced
+    ///   rather than stored
+    pub async fn checkout(&self, id: &str) -> Result<CheckoutHandle> {
+        self.local.authorize(Role::Editor, "checkout", id).await?;
+        self.local.checkout(id).await
+    }
+
+    /// Check a sound back in from [`SoundVault::checkout`], replacing its
+    /// file with the edited working copy, and release it
+    ///
+    /// The hash, technical properties and waveform of the sound follow the
+    /// new file. An unchanged working copy leaves the file alone.
+    ///
+    /// # Returns
+    ///
+    /// Whether the sound's file was replaced
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the handle isn't the sound's
+    ///   current checkout, the working copy isn't valid audio, or the
+    ///   sound's file was replaced since it was checked out
+    pub async fn checkin(&self, handle: &CheckoutHandle) -> Result<bool> {
+        self.local.authorize(Role::Editor, "checkin", &handle.sound_id).await?;
+        self.local.checkin(handle).await
+    }
+
+    /// Sounds checked out, the longest held first
+    ///
+    /// # Arguments
+    ///
+    /// * `older_than` - Only list the checkouts held at least this long
+    pub async fn list_checkouts(&self, older_than: Option<Duration>) -> Result<Vec<CheckoutHandle>> {
+        self.local.list_checkouts(older_than).await
+    }
+
+    /// Release a sound's checkout without checking it in, deleting the
+    /// working copy
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if the sound isn't checked out
+    pub async fn release_checkout(&self, id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "release_checkout", id).await?;
+        self.local.release_checkout(id).await
+    }
+
+    /// Write a sound's description into the `bext` chunk of its WAV file
+    ///
+    /// Imports read the `bext` and iXML chunks of WAV files into custom
+    /// metadata under `bwf.` and `ixml.` keys, as listed by
+    /// [`read_embedded_tags`](crate::read_embedded_tags); malformed chunks
+    /// are skipped and reported with [`VaultEvent::ImportWarning`]. This
+    /// writes the `bwf.description` value back, or the sound's description
+    /// when it has none, adding a `bext` chunk if needed. The audio data is
+    /// left untouched.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound's file is referenced,
+    ///   archived or not a WAV file, or the description exceeds 256 bytes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{BWF_DESCRIPTION, MetadataPatch, PageRequest, SoundFilter, SoundVault, VaultConfig, VaultEvent};
+    /// use std::collections::BTreeMap;
+    ///
+    /// # /// A WAV file with a bext chunk, an iXML chunk and a few samples
+    /// # fn field_recording(description: &str, scene: &str) -> Vec<u8> {
+    /// #     let mut bext = vec![0u8; 602];
+    /// #     bext[..description.len()].copy_from_slice(description.as_bytes());
+    /// #     bext[256..260].copy_from_slice(b"SD-8");
+    /// #     bext[320..330].copy_from_slice(b"2024-05-01");
+    /// #     bext[330..338].copy_from_slice(b"14-30-00");
+    /// #     bext[338..342].copy_from_slice(&48_000u32.to_le_bytes());
+    /// #     let ixml = format!("<?xml version=\"1.0\"?><BWFXML><PROJECT>Harbor</PROJECT><SCENE>{}</SCENE><TAKE>3</TAKE></BWFXML>", scene);
+    /// #     let mut fmt = Vec::new();
+    /// #     for field in [1u16, 1] { fmt.extend_from_slice(&field.to_le_bytes()); }
+    /// #     fmt.extend_from_slice(&48_000u32.to_le_bytes());
+    /// #     fmt.extend_from_slice(&96_000u32.to_le_bytes());
+    /// #     for field in [2u16, 16] { fmt.extend_from_slice(&field.to_le_bytes()); }
+    /// #     let mut body = b"WAVE".to_vec();
+    /// #     for (id, chunk) in [(b"bext", bext), (b"fmt ", fmt), (b"iXML", ixml.into_bytes()), (b"data", vec![7u8; 64])] {
+    /// #         body.extend_from_slice(id);
+    /// #         body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    /// #         body.extend_from_slice(&chunk);
+    /// #         if chunk.len() % 2 == 1 { body.push(0); }
+    /// #     }
+    /// #     let mut wav = b"RIFF".to_vec();
+    /// #     wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    /// #     wav.extend_from_slice(&body);
+    /// #     wav
+    /// # }
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("gulls.wav");
+    /// std::fs::write(&file, field_recording("Gulls over the pier", "12A"))?;
+    ///
+    /// let id = vault.import_file(&file, None).await?;
+    /// let metadata = vault.get_sound(&id).await?.metadata;
+    /// assert_eq!(metadata.get_custom(BWF_DESCRIPTION).map(String::as_str), Some("Gulls over the pier"));
+    /// assert_eq!(metadata.get_custom("bwf.originator").map(String::as_str), Some("SD-8"));
+    /// assert_eq!(metadata.get_custom("bwf.origination_time").map(String::as_str), Some("14:30:00"));
+    /// assert_eq!(metadata.get_custom("bwf.time_reference").map(String::as_str), Some("48000"));
+    /// assert_eq!(metadata.get_custom("ixml.take").map(String::as_str), Some("3"));
+    ///
+    /// // Embedded values are filtered on like any custom field
+    /// let filter = SoundFilter {
+    ///     fields: BTreeMap::from([("ixml.scene".to_string(), Some("12A".to_string()))]),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(vault.query_page(&filter, &PageRequest::default()).await?.sounds.len(), 1);
+    ///
+    /// // Edit the description and write it back into the file
+    /// let patch = MetadataPatch {
+    ///     set_custom: [(BWF_DESCRIPTION.to_string(), "Gulls and a foghorn".to_string())].into(),
+    ///     ..Default::default()
+    /// };
+    /// vault.patch_metadata(&id, patch).await?;
+    /// vault.write_embedded_tags(&id).await?;
+    ///
+    /// let written = std::fs::read(vault.get_sound(&id).await?.metadata.path.unwrap())?;
+    /// assert_eq!(written.len(), std::fs::read(&file)?.len());
+    /// assert!(written.ends_with(&[7u8; 64]));
+    /// let reread = soundvault::read_embedded_tags(&vault.get_sound(&id).await?.metadata.path.unwrap())?;
+    /// assert_eq!(reread.values[BWF_DESCRIPTION], "Gulls and a foghorn");
+    ///
+    /// // A truncated bext chunk is skipped, not fatal
+    /// let mut events = vault.subscribe();
+    /// let mut broken = field_recording("Gulls", "12B");
+    /// broken[16..20].copy_from_slice(&100u32.to_le_bytes());
+    /// let broken_file = dir.path().join("broken.wav");
+    /// std::fs::write(&broken_file, broken)?;
+    /// let id = vault.import_file(&broken_file, None).await?;
+    /// assert!(matches!(events.recv().await?, VaultEvent::ImportWarning { sound_id, .. } if sound_id == id));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn write_embedded_tags(&self, id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "write_embedded_tags", id).await?;
+        self.local.write_embedded_tags(id).await
+    }
+
+    /// Where the files of a sound came from, oldest first
+    ///
+    /// Imports, downloads and file replacements each append an entry, which
+    /// is also noted in the audit log. With
+    /// [`VaultConfig::record_hostname`] entries name the machine they were
+    /// recorded on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ProvenanceMode, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let (take, retake) = (dir.path().join("take.wav"), dir.path().join("retake.wav"));
+    /// std::fs::write(&take, b"take")?;
+    /// std::fs::write(&retake, b"retake")?;
+    ///
+    /// let id = vault.import_file(&take, None).await?;
+    /// vault.replace_file(&id, &retake).await?;
+    ///
+    /// let chain = vault.provenance(&id).await?;
+    /// let links: Vec<_> = chain.iter().map(|entry| (entry.mode, entry.source_path.clone())).collect();
+    /// assert_eq!(links, vec![(ProvenanceMode::Import, Some(take)), (ProvenanceMode::ReplaceFile, Some(retake))]);
+    /// assert!(vault.audit_history(Some(&id), None, 10).await?[0].changes.get("provenance").is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn provenance(&self, id: &str) -> Result<Vec<ProvenanceEntry>> {
+        self.local.provenance(id).await
+    }
+
+    /// Mirror a directory into the vault
+    ///
+    /// Each run imports new files, replaces the file of sounds whose source
+    /// changed and, if asked, deletes the sounds whose source was deleted.
+    /// Files already in the vault with the same content are linked rather
+    /// than imported twice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, SyncOptions};
+    /// use std::path::Path;
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// let options = SyncOptions {
+    ///     exclude: vec!["*.tmp".to_string(), "drafts/**".to_string()],
+    ///     delete_missing: true,
+    ///     ..Default::default()
+    /// };
+    /// let report = vault.sync_directory(Path::new("/mnt/recordings"), options).await?;
+    /// println!("{} imported, {} updated", report.imported.len(), report.updated.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn sync_directory<P: AsRef<Path>>(&self, dir: P, options: SyncOptions) -> Result<DirectorySyncReport> {
+        self.sync_directory_with_context(dir, options, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::sync_directory`], stopped by `context` between files
+    ///
+    /// Files synced before the sync stopped stay synced. Sounds whose source
+    /// file was deleted are only deleted once every file was synced.
+    pub async fn sync_directory_with_context<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        options: SyncOptions,
+        context: &OpContext,
+    ) -> Result<DirectorySyncReport> {
+        self.local.authorize(Role::Editor, "sync_directory", "").await?;
+        let sync = self.local.sync_directory(dir.as_ref(), &options, context);
+        let (mut report, operation) = self.local.in_operation("sync_directory", sync).await?;
+        report.operation = Some(operation);
+        Ok(report)
+    }
+
+    /// Get a local sound by its slug
+    ///
+    /// Sounds get a slug made from their name when stored, suffixed with
+    /// part of their ID if another sound has it already. Whether renaming a
+    /// sound changes its slug depends on [`VaultConfig::slug_policy`]; a
+    /// slug can also be set through [`MetadataPatch::slug`]. Vault URIs may
+    /// name a sound by its slug.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundMetadata, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let mut ids = Vec::new();
+    /// for take in ["take1.wav", "take2.wav"] {
+    ///     let file = dir.path().join(take);
+    ///     std::fs::write(&file, b"RIFF\x24\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\x40\x1f\0\0\x80\x3e\0\0\x02\0\x10\0data\0\0\0\0")?;
+    ///     let metadata = SoundMetadata { name: "Wind Gust #03".to_string(), ..Default::default() };
+    ///     ids.push(vault.import_file(&file, Some(metadata)).await?);
+    /// }
+    ///
+    /// let first = vault.get_sound(&ids[0]).await?.metadata;
+    /// let second = vault.get_sound(&ids[1]).await?.metadata;
+    /// assert_eq!(first.slug.as_deref(), Some("wind-gust-03"));
+    /// assert!(second.slug.as_deref().unwrap().starts_with("wind-gust-03-"));
+    ///
+    /// // Slugs are stable across renames by default
+    /// vault.update_metadata(&ids[0], |metadata| metadata.name = "Gust".to_string()).await?;
+    /// assert_eq!(vault.get_sound_by_slug("wind-gust-03").await?.metadata.id, ids[0]);
+    ///
+    /// let uri = format!("soundvault://{}/sound/wind-gust-03", vault.vault_id());
+    /// assert_eq!(vault.resolve_uri(&uri).await?.metadata.id, ids[0]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if no sound has the slug
+    pub async fn get_sound_by_slug(&self, slug: &str) -> Result<Sound> {
+        self.local.get_sound_by_slug(slug).await
+    }
+
+    /// Get a local sound by ID
+    ///
+    /// Sounds fetched by ID are cached, up to
+    /// [`VaultConfig::sound_cache_capacity`] of them; every change to a sound
+    /// drops it from the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{MetadataPatch, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    ///
+    /// let rain = dir.path().join("rain.wav");
+    /// std::fs::write(&rain, "drops")?;
+    /// let id = vault.import_file(&rain, None).await?;
+    /// let before = vault.get_sound(&id).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.hash, before.metadata.hash);
+    /// let cache = vault.health().await?.sound_cache;
+    /// assert_eq!(cache.hits + cache.misses, 2);
+    ///
+    /// // Changes show up at once
+    /// let patch = MetadataPatch { name: Some("Downpour".to_string()), ..Default::default() };
+    /// vault.patch_metadata(&id, patch).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.name, "Downpour");
+    ///
+    /// std::fs::write(&rain, "heavier drops")?;
+    /// vault.replace_file(&id, &rain).await?;
+    /// let replaced = vault.get_sound(&id).await?.metadata.hash;
+    /// assert_ne!(replaced, before.metadata.hash);
+    ///
+    /// // Rejecting trashes the sound; restoring it makes a new one
+    /// vault.reject(&[id.clone()]).await?;
+    /// assert!(vault.get_sound(&id).await.is_err());
+    /// let quarantined = vault.list_quarantine()?.remove(0);
+    /// let restored = vault.restore_from_quarantine(&quarantined.path).await?;
+    /// assert_eq!(vault.get_sound(&restored).await?.metadata.hash, replaced);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_sound(&self, id: &str) -> Result<Sound> {
+        self.local.get_sound(id).await
+    }
+
+    /// Get a local sound by ID to use it, e.g. to open it in an editor,
+    /// recording the access
+    ///
+    /// The access is noted in memory and its time written with the others
+    /// every [`VaultConfig::access_flush_interval`], so reads never wait on
+    /// a write. [`SoundVault::get_sound`] and listings don't count as access;
+    /// [`SoundVault::open_sound`] does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{PageRequest, SoundFilter, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let sources = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// let rain = sources.path().join("rain.wav");
+    /// std::fs::write(&rain, "drops")?;
+    /// let id = vault.import_file(&rain, None).await?;
+    /// vault.warm_up().await?;
+    ///
+    /// // Listing isn't access, and writes nothing
+    /// let db = sqlx::SqlitePool::connect(&format!("sqlite:{}", dir.path().join("soundvault.db").display())).await?;
+    /// let mut watcher = db.acquire().await?;
+    /// let before: i64 = sqlx::query_scalar("PRAGMA data_version").fetch_one(&mut *watcher).await?;
+    /// vault.query_page(&SoundFilter::default(), &PageRequest::new(0, 25)).await?;
+    /// vault.get_sound(&id).await?;
+    /// assert_eq!(vault.flush_access_times().await?, 0);
+    /// let after: i64 = sqlx::query_scalar("PRAGMA data_version").fetch_one(&mut *watcher).await?;
+    /// assert_eq!(before, after);
+    ///
+    /// let sound = vault.access_sound(&id).await?;
+    /// assert_eq!(sound.metadata.last_accessed_at, None);
+    /// assert_eq!(vault.flush_access_times().await?, 1);
+    /// assert!(vault.get_sound(&id).await?.metadata.last_accessed_at.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn access_sound(&self, id: &str) -> Result<Sound> {
+        let sound = self.local.get_sound(id).await?;
+        self.local.accesses.note(id);
+        Ok(sound)
+    }
+
+    /// Write the last-accessed time of the sounds accessed since the last
+    /// write now, rather than at the next
+    /// [`VaultConfig::access_flush_interval`]; returns how many sounds were
+    /// written
+    pub async fn flush_access_times(&self) -> Result<usize> {
+        self.local.flush_accesses().await
+    }
+
+    /// Empty the cache of sounds fetched by ID
+    ///
+    /// The vault keeps the cache up to date with its own changes; changes
+    /// made to the database by another process aren't seen until the cache
+    /// is emptied.
+    pub fn clear_cache(&self) {
+        self.local.clear_cache()
+    }
+
+    /// Update a sound's metadata with a closure
+    ///
+    /// Only the fields the closure changes are written. A locked sound is
+    /// refused with [`VaultError::Locked`]; see
+    /// [`MetadataPatch::override_lock`] to change one anyway.
+    pub async fn update_metadata<F>(&self, id: &str, updater: F) -> Result<()>
+    where
+        F: FnOnce(&mut SoundMetadata),
+    {
+        self.local.authorize(Role::Editor, "update_metadata", id).await?;
+        self.local.update_metadata(id, updater).await
+    }
+
+    /// Update only the given fields of a sound's metadata
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{MetadataPatch, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault, sound_id: String) -> soundvault::Result<()> {
+    /// let mut patch = MetadataPatch::default();
+    /// patch.license = Some("CC0".to_string());
+    /// patch.add_tags = vec!["reviewed".to_string()];
+    /// vault.patch_metadata(&sound_id, patch).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn patch_metadata(&self, id: &str, patch: MetadataPatch) -> Result<()> {
+        self.local.authorize(Role::Editor, "patch_metadata", id).await?;
+        self.local.patch_metadata(id, patch).await
+    }
+
+    /// Apply a patch to every sound of a collection, e.g. to set the
+    /// license and custom fields of a whole pack
+    ///
+    /// Locked sounds are skipped unless [`MetadataPatch::override_lock`] is
+    /// set, and custom values must fit the declared fields. The outcome
+    /// lists what changed on each sound, or would with
+    /// [`BulkPatchOptions::dry_run`], and counts the sounds changed per
+    /// field. Large collections are written in chunks of
+    /// [`BulkPatchOptions::chunk_size`] sounds, each in its own
+    /// transaction, with a [`VaultEvent::BulkPatchProgress`] after each;
+    /// the changes can be taken back with [`SoundVault::undo_operation`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{BulkPatchOptions, Collection, MetadataPatch, SoundMetadata, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// let collection_id = vault.add_collection(&Collection::new("Forest pack", "")).await?;
+    /// let mut ids = Vec::new();
+    /// for (name, license) in [("birds", "CC BY 4.0"), ("creek", "CC0"), ("wind", "CC0")] {
+    ///     let file = sources.path().join(format!("{}.wav", name));
+    ///     std::fs::write(&file, name)?;
+    ///     let metadata = SoundMetadata { name: name.to_string(), license: license.to_string(), ..Default::default() };
+    ///     let id = vault.import_file(&file, Some(metadata)).await?;
+    ///     vault.add_sound_to_collection(&id, &collection_id).await?;
+    ///     ids.push(id);
+    /// }
+    /// vault.set_locked(&ids[2], true, None).await?;
+    ///
+    /// let mut patch = MetadataPatch::default();
+    /// patch.license = Some("CC0".to_string());
+    /// patch.set_custom.insert("pack".to_string(), "forest".to_string());
+    ///
+    /// // Preview first
+    /// let options = BulkPatchOptions { dry_run: true, ..Default::default() };
+    /// let preview = vault.patch_collection_sounds(&collection_id, patch.clone(), options).await?;
+    /// assert_eq!(preview.changed.len(), 2);
+    /// assert_eq!(preview.changed[0].1.license.as_deref(), Some("CC0"));
+    /// assert_eq!(preview.changed[1].1.license, None);
+    /// assert_eq!(preview.fields["license"], 1);
+    /// assert_eq!(preview.fields["custom.pack"], 2);
+    /// assert_eq!(preview.skipped, vec![(ids[2].clone(), "locked".to_string())]);
+    /// assert!(vault.get_sound(&ids[0]).await?.metadata.get_custom("pack").is_none());
+    ///
+    /// let outcome = vault.patch_collection_sounds(&collection_id, patch, BulkPatchOptions::default()).await?;
+    /// assert_eq!(outcome.changed, preview.changed);
+    /// assert_eq!(vault.get_sound(&ids[0]).await?.metadata.license, "CC0");
+    ///
+    /// vault.undo_operation(outcome.operation.unwrap()).await?;
+    /// assert_eq!(vault.get_sound(&ids[0]).await?.metadata.license, "CC BY 4.0");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn patch_collection_sounds(
+        &self,
+        collection_id: &str,
+        patch: MetadataPatch,
+        options: BulkPatchOptions,
+    ) -> Result<BatchOutcome<String>> {
+        self.local.authorize(Role::Editor, "patch_collection_sounds", collection_id).await?;
+        let work = self.local.patch_collection_sounds(collection_id, &patch, &options);
+        let (mut outcome, operation) = self.local.in_operation("patch_collection_sounds", work).await?;
+        if outcome.applied {
+            outcome.operation = Some(operation);
+        }
+        Ok(outcome)
+    }
+
+    /// Declare a custom metadata field, or change its declaration
+    ///
+    /// Custom metadata written to sounds from then on must have the field's
+    /// type, and required fields can't be removed; in
+    /// [`SchemaMode::Strict`], undeclared keys are rejected too. Values
+    /// already stored are left as they are and reported by
+    /// [`SoundVault::custom_field_violations`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{FieldSpec, FieldType, SchemaMode, SoundMetadata, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("step.wav");
+    /// std::fs::write(&file, b"step")?;
+    /// let mut metadata = SoundMetadata::default();
+    /// metadata.set_custom("proj", "Ice Cave");
+    /// let id = vault.import_file(&file, Some(metadata)).await?;
+    ///
+    /// let mut project = FieldSpec::new(FieldType::String);
+    /// project.required = true;
+    /// vault.define_custom_field("project", project).await?;
+    /// vault.define_custom_field("take", FieldSpec::new(FieldType::Int)).await?;
+    /// vault.set_custom_schema_mode(SchemaMode::Strict).await?;
+    /// assert_eq!(vault.custom_field_violations().await?.len(), 2);
+    ///
+    /// assert_eq!(vault.rename_custom_key("proj", "project").await?, 1);
+    /// assert!(vault.custom_field_violations().await?.is_empty());
+    ///
+    /// assert!(vault.update_metadata(&id, |m| m.set_custom("take", "two")).await.is_err());
+    /// assert!(vault.update_metadata(&id, |m| m.set_custom("mood", "calm")).await.is_err());
+    /// vault.update_metadata(&id, |m| m.set_custom("take", "2")).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn define_custom_field(&self, key: &str, spec: FieldSpec) -> Result<()> {
+        self.local.authorize(Role::Editor, "define_custom_field", "").await?;
+        self.local.define_custom_field(key, spec).await
+    }
+
+    /// Remove the declaration of a custom metadata field, keeping its values
+    pub async fn remove_custom_field(&self, key: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "remove_custom_field", "").await?;
+        self.local.remove_custom_field(key).await
+    }
+
+    /// Declared custom metadata fields, by key
+    pub async fn list_custom_field_specs(&self) -> Result<BTreeMap<String, FieldSpec>> {
+        self.local.list_custom_field_specs().await
+    }
+
+    /// Set how custom metadata is checked against the declared fields;
+    /// the default is [`SchemaMode::Lax`]
+    pub async fn set_custom_schema_mode(&self, mode: SchemaMode) -> Result<()> {
+        self.local.authorize(Role::Editor, "set_custom_schema_mode", "").await?;
+        self.local.set_custom_schema_mode(mode).await
+    }
+
+    /// How custom metadata is checked against the declared fields
+    pub async fn custom_schema_mode(&self) -> Result<SchemaMode> {
+        self.local.custom_schema_mode().await
+    }
+
+    /// Find custom metadata of existing sounds that doesn't match the
+    /// declared fields, including missing required fields
+    pub async fn custom_field_violations(&self) -> Result<Vec<FieldViolation>> {
+        self.local.custom_field_violations().await
+    }
+
+    /// Rename a custom metadata key on every sound, e.g. to merge `proj`
+    /// into `project`
+    ///
+    /// A sound having both keys with different values fails the whole
+    /// rename.
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds changed
+    pub async fn rename_custom_key(&self, old: &str, new: &str) -> Result<u64> {
+        self.local.authorize(Role::Editor, "rename_custom_key", "").await?;
+        Ok(self.local.in_operation("rename_custom_key", self.local.rename_custom_key(old, new)).await?.0)
+    }
+
+    /// Replace text across the names, descriptions, tags and custom values of
+    /// sounds and the names of collections
+    ///
+    /// `scope` selects the fields searched. By default the pattern is plain
+    /// text matched case-sensitively, and nothing is written: the report
+    /// previews every change, sound by sound. Turning
+    /// [`ReplaceOptions::dry_run`] off applies them in one transaction,
+    /// recorded in the audit log.
+    ///
+    /// Regular expressions run in linear time; patterns longer than 1 KiB or
+    /// compiling to a large automaton are refused.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, MatchMode, ReplaceOptions, SoundMetadata, SoundVault, TextScope, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("hum.wav");
+    /// std::fs::write(&file, b"hum")?;
+    /// let mut metadata = SoundMetadata::default();
+    /// metadata.description = "Engine hum for ProjectX, take 2 (projectx-b)".to_string();
+    /// metadata.tags = vec!["projectx".to_string(), "engine".to_string()];
+    /// let id = vault.import_file(&file, Some(metadata)).await?;
+    /// let collection_id = vault.add_collection(&Collection::new("ProjectX ambiences", "")).await?;
+    ///
+    /// let mut options = ReplaceOptions { mode: MatchMode::WholeWord, case_sensitive: false, ..Default::default() };
+    /// let preview = vault.replace_text(TextScope::default(), "projectx", "Nebula", options).await?;
+    /// assert_eq!((preview.applied, preview.changes.len(), preview.entities()), (false, 3, 2));
+    /// assert!(vault.get_sound(&id).await?.metadata.description.contains("ProjectX"));
+    ///
+    /// options.dry_run = false;
+    /// vault.replace_text(TextScope::default(), "projectx", "Nebula", options).await?;
+    /// let metadata = vault.get_sound(&id).await?.metadata;
+    /// assert_eq!(metadata.description, "Engine hum for Nebula, take 2 (Nebula-b)");
+    /// assert_eq!(metadata.tags, vec!["Nebula", "engine"]);
+    /// assert_eq!(vault.get_collection(&collection_id).await?.name, "Nebula ambiences");
+    ///
+    /// // Groups of regular expressions can be reused
+    /// let options = ReplaceOptions { mode: MatchMode::Regex, dry_run: false, ..Default::default() };
+    /// let scope = TextScope { names: false, tags: false, custom_values: false, collection_names: false, ..TextScope::default() };
+    /// vault.replace_text(scope, r"take (\d+)", "take #$1", options).await?;
+    /// assert!(vault.get_sound(&id).await?.metadata.description.contains("take #2"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn replace_text(
+        &self,
+        scope: TextScope,
+        pattern: &str,
+        replacement: &str,
+        options: ReplaceOptions,
+    ) -> Result<ReplaceReport> {
+        self.local.authorize(Role::Editor, "replace_text", "").await?;
+        let replace = self.local.replace_text(scope, pattern, replacement, options);
+        let (mut report, operation) = self.local.in_operation("replace_text", replace).await?;
+        if report.applied {
+            report.operation = Some(operation);
+        }
+        Ok(report)
+    }
+
+    /// Make a tag a synonym of another, so filtering by either matches both
+    ///
+    /// Sounds keep their tags; see [`SoundVault::rename_tag`] to change them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundFilter, SoundMetadata, SoundVault, TagRename, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// for (name, tag) in [("whoosh", "fx"), ("snare", "snare"), ("kick", "drum")] {
+    ///     let file = dir.path().join(format!("{}.wav", name));
+    ///     std::fs::write(&file, name)?;
+    ///     let mut metadata = SoundMetadata::default();
+    ///     metadata.tags = vec![tag.to_string()];
+    ///     vault.import_file(&file, Some(metadata)).await?;
+    /// }
+    ///
+    /// vault.add_tag_alias("fx", "sfx").await?;
+    /// vault.rename_tag("sound-effect", "sfx", TagRename::MergeAsAlias).await?;
+    /// vault.set_tag_parent("snare", Some("drum")).await?;
+    ///
+    /// let tagged = |tag: &str| SoundFilter { tags: vec![tag.to_string()], ..Default::default() };
+    /// assert_eq!(vault.count(&tagged("sfx")).await?, 1);
+    /// assert_eq!(vault.count(&tagged("sound-effect")).await?, 1);
+    /// assert_eq!(vault.count(&tagged("drum")).await?, 1);
+    /// assert_eq!(vault.count(&SoundFilter { tag_descendants: true, ..tagged("drum") }).await?, 2);
+    ///
+    /// let tags = vault.list_tags(true).await?;
+    /// let names: Vec<_> = tags.iter().map(|tag| (tag.tag.as_str(), tag.count)).collect();
+    /// assert_eq!(names, [("drum", 2), ("sfx", 1), ("snare", 1)]);
+    /// assert_eq!(tags[1].aliases, ["fx", "sound-effect"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn add_tag_alias(&self, alias: &str, tag: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "add_tag_alias", "").await?;
+        self.local.add_tag_alias(alias, tag).await
+    }
+
+    /// Stop a tag being a synonym of another
+    pub async fn remove_tag_alias(&self, alias: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "remove_tag_alias", "").await?;
+        self.local.remove_tag_alias(alias).await
+    }
+
+    /// Put a tag under another, e.g. `snare` under `drum`, or take it out of
+    /// the hierarchy with `None`
+    ///
+    /// Filters with [`SoundFilter::tag_descendants`] set match the tags under
+    /// theirs.
+    pub async fn set_tag_parent(&self, tag: &str, parent: Option<&str>) -> Result<()> {
+        self.local.authorize(Role::Editor, "set_tag_parent", "").await?;
+        self.local.set_tag_parent(tag, parent).await
+    }
+
+    /// List the tags of sounds, and the tags above them, with their aliases
+    /// and the number of sounds tagged
+    ///
+    /// With `roll_up` a tag also counts the sounds of the tags below it.
+    pub async fn list_tags(&self, roll_up: bool) -> Result<Vec<TagCount>> {
+        self.local.list_tags(roll_up).await
+    }
+
+    /// Rename a tag in every sound, or merge it into another as an alias
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds changed
+    pub async fn rename_tag(&self, from: &str, to: &str, mode: TagRename) -> Result<u64> {
+        self.local.authorize(Role::Editor, "rename_tag", "").await?;
+        Ok(self.local.in_operation("rename_tag", self.local.rename_tag(from, to, mode)).await?.0)
+    }
+
+    /// Sounds pointing at a file that isn't there, e.g. referenced on an
+    /// unplugged drive
+    ///
+    /// Such sounds still load, with [`Availability::FileMissing`](crate::Availability::FileMissing). Checking
+    /// their files also sends a [`VaultEvent::AvailabilityChanged`] for each
+    /// sound whose file went missing or came back since it was last loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Availability, ImportMode, ImportOptions, SoundVault, VaultConfig, VaultEvent};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let drive = tempfile::tempdir()?;
+    /// std::fs::create_dir(drive.path().join("sfx"))?;
+    /// std::fs::write(drive.path().join("sfx").join("door.wav"), b"door")?;
+    /// let options = ImportOptions { mode: ImportMode::Reference, ..Default::default() };
+    /// let id = vault.import_file_with_options(drive.path().join("sfx").join("door.wav"), None, options).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.availability, Availability::Available);
+    /// let mut events = vault.subscribe();
+    ///
+    /// // The drive is unplugged, then mounted elsewhere
+    /// let mounted = tempfile::tempdir()?;
+    /// std::fs::rename(drive.path().join("sfx"), mounted.path().join("sfx"))?;
+    /// assert_eq!(vault.list_missing().await?[0].availability, Availability::FileMissing);
+    /// let availability = Availability::FileMissing;
+    /// assert_eq!(events.recv().await?, VaultEvent::AvailabilityChanged { sound_id: id.clone(), availability });
+    ///
+    /// let report = vault.relink_by_prefix(drive.path(), mounted.path()).await?;
+    /// assert_eq!(report.relinked, vec![id.clone()]);
+    /// assert!(vault.list_missing().await?.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn list_missing(&self) -> Result<Vec<Sound>> {
+        self.local.list_missing().await
+    }
+
+    /// Point a referenced sound at its file's new location
+    ///
+    /// The file must have the content the sound was recorded with, unless
+    /// `force` is set: its hash and technical properties are then read again.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound's file was copied into
+    ///   the library, or the content differs without `force`
+    /// * `VaultError::FileSystem` if there's no file at `new_path`
+    pub async fn relink<P: AsRef<Path>>(&self, id: &str, new_path: P, force: bool) -> Result<()> {
+        self.local.authorize(Role::Editor, "relink", id).await?;
+        self.local.relink(id, new_path.as_ref(), force).await
+    }
+
+    /// Point the referenced sounds whose file was under `old_prefix` at the
+    /// same file under `new_prefix`, e.g. when a drive letter changes
+    ///
+    /// Sounds whose file isn't found under the new prefix with the same
+    /// content are skipped and listed in the report; the others are relinked
+    /// in one transaction. Packed sounds follow their archive when
+    /// `old_prefix` is the archive itself.
+    pub async fn relink_by_prefix<P: AsRef<Path>, Q: AsRef<Path>>(&self, old_prefix: P, new_prefix: Q) -> Result<RelinkReport> {
+        self.relink_by_prefix_with_context(old_prefix, new_prefix, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::relink_by_prefix`], stopped by `context` between files
+    ///
+    /// A stopped relink relinks no sound.
+    pub async fn relink_by_prefix_with_context<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        old_prefix: P,
+        new_prefix: Q,
+        context: &OpContext,
+    ) -> Result<RelinkReport> {
+        self.local.authorize(Role::Editor, "relink_by_prefix", "").await?;
+        self.local.relink_by_prefix(old_prefix.as_ref(), new_prefix.as_ref(), context).await
+    }
+
+    /// Delete a sound and its file
+    ///
+    /// Files outside the library are never deleted: external sounds only lose
+    /// their record, and a stored path escaping the library is refused with
+    /// [`VaultError::InvalidOperation`]. A locked sound is refused with
+    /// [`VaultError::Locked`], and a sound with
+    /// [external uses](SoundVault::register_external_use) with
+    /// [`VaultError::InUse`].
+    pub async fn delete_sound(&self, id: &str) -> Result<()> {
+        self.local.authorize(Role::Admin, "delete_sound", id).await?;
+        self.local.delete_sound(id, false).await
+    }
+
+    /// Delete a sound like [`SoundVault::delete_sound`], even if it's locked
+    /// or used by projects outside the vault; its recorded uses go with it
+    pub async fn delete_sound_overriding_lock(&self, id: &str) -> Result<()> {
+        self.local.authorize(Role::Admin, "delete_sound_overriding_lock", id).await?;
+        self.local.delete_sound(id, true).await
+    }
+
+    /// Record that a project or sampler patch outside the vault uses a sound
+    ///
+    /// The sound can't be deleted while it has uses, short of
+    /// [`SoundVault::delete_sound_overriding_lock`]. Registering the same
+    /// consumer and location again only updates the time of the use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ExternalUse, SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// std::fs::write(sources.path().join("kick.wav"), b"kick")?;
+    /// let id = vault.import_file(sources.path().join("kick.wav"), None).await?;
+    ///
+    /// vault.register_external_use(&id, &ExternalUse::new("Kontakt", "/patches/Drums.nki")).await?;
+    /// assert_eq!(vault.list_uses(&id).await?[0].consumer, "Kontakt");
+    /// assert_eq!(vault.sounds_used_by("/patches/Drums.nki").await?, vec![id.clone()]);
+    /// assert!(matches!(vault.delete_sound(&id).await, Err(VaultError::InUse { .. })));
+    ///
+    /// vault.forget_external_uses("/patches/Drums.nki").await?;
+    /// vault.delete_sound(&id).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn register_external_use(&self, sound_id: &str, context: &ExternalUse) -> Result<()> {
+        self.local.authorize(Role::Editor, "register_external_use", sound_id).await?;
+        self.local.register_external_use(sound_id, context).await
+    }
+
+    /// Projects and patches using a sound, most recently seen first
+    pub async fn list_uses(&self, sound_id: &str) -> Result<Vec<ExternalUse>> {
+        self.local.list_uses(sound_id).await
+    }
+
+    /// IDs of the sounds a project or patch uses, by its path or URI as
+    /// registered
+    pub async fn sounds_used_by(&self, location: &str) -> Result<Vec<String>> {
+        self.local.sounds_used_by(location).await
+    }
+
+    /// Forget the uses recorded for a project or patch, e.g. once it's
+    /// deleted
+    ///
+    /// # Returns
+    ///
+    /// The number of uses forgotten
+    pub async fn forget_external_uses(&self, location: &str) -> Result<u64> {
+        self.local.authorize(Role::Editor, "forget_external_uses", location).await?;
+        self.local.forget_external_uses(location).await
+    }
+
+    /// Record the vault sounds an Ableton Live set (`.als`) or device
+    /// preset (`.adg`) uses, replacing the uses recorded for it before
+    ///
+    /// Samples are matched by path, or by content when Live copied them
+    /// into the project. Uses are registered under [`ABLETON_CONSUMER`](crate::ABLETON_CONSUMER),
+    /// located at the file's absolute path.
+    ///
+    /// # Returns
+    ///
+    /// IDs of the sounds used
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flate2::{Compression, write::GzEncoder};
+    /// use soundvault::{SoundVault, VaultConfig};
+    /// use std::io::Write;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// std::fs::write(sources.path().join("snare.wav"), b"snare")?;
+    /// let id = vault.import_file(sources.path().join("snare.wav"), None).await?;
+    ///
+    /// // Live collected the sample into the project
+    /// let project = tempfile::tempdir()?;
+    /// std::fs::write(project.path().join("snare.wav"), b"snare")?;
+    /// let xml = format!(
+    ///     r#"<Ableton><FileRef><Path Value="{}" /></FileRef></Ableton>"#,
+    ///     project.path().join("snare.wav").display()
+    /// );
+    /// let mut set = GzEncoder::new(Vec::new(), Compression::default());
+    /// set.write_all(xml.as_bytes())?;
+    /// std::fs::write(project.path().join("Beat.als"), set.finish()?)?;
+    ///
+    /// assert_eq!(vault.scan_ableton_file(project.path().join("Beat.als")).await?, vec![id.clone()]);
+    /// assert_eq!(vault.list_uses(&id).await?.len(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "ableton")]
+    pub async fn scan_ableton_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<String>> {
+        self.local.authorize(Role::Editor, "scan_ableton_file", "").await?;
+        self.local.scan_ableton_file(path.as_ref()).await
+    }
+
+    /// Lock a sound against casual edits, e.g. once it's approved for
+    /// shipping, or unlock it
+    ///
+    /// Patching or updating the metadata of a locked sound, replacing its
+    /// file and deleting it fail with [`VaultError::Locked`], and so do
+    /// operations changing many sounds, such as [`SoundVault::replace_text`]
+    /// or [`SoundVault::rename_tag`], as a whole. Set
+    /// [`MetadataPatch::override_lock`] or [`ReplaceOptions::override_lock`],
+    /// or call the `*_overriding_lock` methods, to change one anyway.
+    ///
+    /// The reason is kept in custom metadata under
+    /// [`LOCK_REASON_KEY`](crate::LOCK_REASON_KEY) while the sound is
+    /// locked. Locking and unlocking are recorded in the audit log with the
+    /// actor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AuditOperation, MetadataPatch, ReplaceOptions, SoundVault, TextScope, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("logo.wav");
+    /// std::fs::write(&file, b"logo sting")?;
+    /// let id = vault.import_file(&file, None).await?;
+    ///
+    /// vault.set_actor(Some("mia"));
+    /// vault.set_locked(&id, true, Some("Approved for v1.0")).await?;
+    /// let metadata = vault.get_sound(&id).await?.metadata;
+    /// assert!(metadata.locked);
+    /// assert_eq!(metadata.custom["lock_reason"], "Approved for v1.0");
+    /// assert_eq!(vault.health().await?.locked_sounds, 1);
+    ///
+    /// let patch = MetadataPatch { add_tags: vec!["logo".to_string()], ..Default::default() };
+    /// assert!(matches!(vault.patch_metadata(&id, patch.clone()).await, Err(VaultError::Locked(_))));
+    /// assert!(matches!(vault.update_metadata(&id, |m| m.rating = Some(5)).await, Err(VaultError::Locked(_))));
+    /// assert!(matches!(vault.replace_file(&id, &file).await, Err(VaultError::Locked(_))));
+    /// assert!(matches!(vault.delete_sound(&id).await, Err(VaultError::Locked(_))));
+    /// let options = ReplaceOptions { dry_run: false, ..Default::default() };
+    /// let result = vault.replace_text(TextScope::default(), "logo", "sting", options).await;
+    /// assert!(matches!(result, Err(VaultError::Locked(_))));
+    ///
+    /// // Deliberate edits go through
+    /// vault.patch_metadata(&id, MetadataPatch { override_lock: true, ..patch }).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.tags, vec!["logo"]);
+    ///
+    /// let history = vault.audit_history(Some(&id), None, 10).await?;
+    /// let lock = history.iter().find(|entry| entry.operation == AuditOperation::LockSound).unwrap();
+    /// assert_eq!(lock.actor.as_deref(), Some("mia"));
+    ///
+    /// vault.set_locked(&id, false, None).await?;
+    /// assert!(!vault.get_sound(&id).await?.metadata.custom.contains_key("lock_reason"));
+    /// vault.delete_sound(&id).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_locked(&self, id: &str, locked: bool, reason: Option<&str>) -> Result<()> {
+        self.local.authorize(Role::Editor, "set_locked", id).await?;
+        self.local.set_locked(id, locked, reason).await
+    }
+
+    /// Trash, delete or lock the sounds whose expiration date has passed
+    ///
+    /// Until then, expired sounds stay in the vault but are left out of
+    /// exports and manifests. [`VaultConfig::expire_on_open`] runs this
+    /// whenever the vault opens.
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the sounds dealt with, the earliest expired first
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chrono::{Duration, Utc};
+    /// use soundvault::{
+    ///     AudioFormat, AudioInfo, EXPIRED_TAG, ExpireAction, MetadataPatch, SampleFormat, SoundFilter, SoundVault,
+    ///     VaultConfig, VaultError, encode,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let file = dir.path().join("drone.wav");
+    /// std::fs::write(&file, encode(&info, &[0.0; 800])?)?;
+    /// let id = vault.import_file(&file, None).await?;
+    /// let patch = MetadataPatch { expires_at: Some(Some(Utc::now() - Duration::days(1))), ..Default::default() };
+    /// vault.patch_metadata(&id, patch).await?;
+    ///
+    /// let expired = SoundFilter { expires_by: Some(Utc::now()), ..Default::default() };
+    /// assert_eq!(vault.count(&expired).await?, 1);
+    /// assert_eq!(vault.health().await?.expired_sounds, 1);
+    /// let export = vault.export_sound_with_options(&id, dir.path(), &Default::default()).await;
+    /// assert!(matches!(export, Err(VaultError::Expired { .. })));
+    ///
+    /// assert_eq!(vault.enforce_expirations(ExpireAction::LockAndFlag).await?, vec![id.clone()]);
+    /// let sound = vault.get_sound(&id).await?;
+    /// assert!(sound.metadata.locked && sound.metadata.tags.contains(&EXPIRED_TAG.to_string()));
+    ///
+    /// // Trashed files wait in quarantine
+    /// vault.enforce_expirations(ExpireAction::Trash).await?;
+    /// assert!(matches!(vault.get_sound(&id).await, Err(VaultError::NotFound(_))));
+    /// assert_eq!(vault.list_quarantine()?.len(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn enforce_expirations(&self, action: ExpireAction) -> Result<Vec<String>> {
+        self.local.authorize(Role::Admin, "enforce_expirations", "").await?;
+        self.local.enforce_expirations(action).await
+    }
+
+    /// Search the local library by text and tags
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Text matched against names and descriptions
+    /// * `tags` - Optional tags the sounds must all have
+    pub async fn search_local(&self, query: &str, tags: Option<&[&str]>) -> Result<Vec<Sound>> {
+        self.local.search(query, tags).await
+    }
+
+    /// Fetch a page of local sounds matching a filter
+    ///
+    /// The total is only counted exactly for small result sets; use
+    /// [`SoundVault::count`] when the exact number is needed. To page
+    /// through results that change meanwhile, fetch each page after the
+    /// [`SoundPage::next`] cursor of the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{PageRequest, SoundFilter, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// let filter = SoundFilter {
+    ///     text: Some("rain".to_string()),
+    ///     ..Default::default()
+    /// };
+    /// let mut page = PageRequest::new(0, 25);
+    /// page.max_scan = Some(100_000);
+    ///
+    /// let results = vault.query_page(&filter, &page).await?;
+    /// println!("{} sounds, {:?} in total", results.sounds.len(), results.total);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn query_page(&self, filter: &SoundFilter, page: &PageRequest) -> Result<SoundPage> {
+        self.local.query_page(filter, page).await
+    }
+
+    /// Start a search whose query changes as the user types
+    ///
+    /// Each [`InteractiveSearch::set_query`] cancels the query in flight, so
+    /// only the results of the latest one arrive, in sequence order. The
+    /// search runs on the Tokio runtime until the handle is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundFilter, SoundVault, VaultConfig};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}?mode=rwc", dir.path().join("app.db").display())).await?;
+    /// let vault = SoundVault::with_pool(VaultConfig::new(dir.path().to_path_buf(), None), pool.clone()).await?;
+    ///
+    /// // Takes 1 to 20000
+    /// sqlx::query(
+    ///     "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 20000)
+    ///      INSERT INTO sv_sounds (id, name, tags) SELECT 'take-' || i, 'Take ' || i, '[]' FROM n",
+    /// )
+    /// .execute(&pool)
+    /// .await?;
+    ///
+    /// let mut search = vault.interactive_search().debounce(Duration::from_millis(5));
+    /// let mut last = 0;
+    /// for typed in ["T", "Ta", "Tak", "Take", "Take ", "Take 1", "Take 12", "Take 123"] {
+    ///     last = search.set_query(SoundFilter { text: Some(typed.to_string()), ..Default::default() });
+    /// }
+    ///
+    /// // Results only come for the latest query, in order
+    /// let mut seq = 0;
+    /// while seq < last {
+    ///     let batch = search.next().await.unwrap();
+    ///     assert!(batch.seq > seq);
+    ///     seq = batch.seq;
+    /// }
+    /// let batch = search.next();
+    /// assert!(tokio::time::timeout(Duration::from_millis(50), batch).await.is_err());
+    ///
+    /// search.set_query(SoundFilter { text: Some("Take 12345".to_string()), ..Default::default() });
+    /// let batch = search.next().await.unwrap();
+    /// assert_eq!((batch.seq, batch.result?.total.value()), (last + 1, 1));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn interactive_search(&self) -> InteractiveSearch {
+        InteractiveSearch::new(self.local.clone())
+    }
+
+    /// Iterate lazily over the local sounds matching a filter
+    ///
+    /// The cursor yields sounds by ID; call [`SoundCursor::shuffled`] for a
+    /// reproducible random order.
+    pub fn iter(&self, filter: SoundFilter) -> SoundCursor<'_> {
+        self.local.iter(filter)
+    }
+
+    /// Count exactly how many local sounds match a filter
+    pub async fn count(&self, filter: &SoundFilter) -> Result<u64> {
+        self.local.count(filter).await
+    }
+
+    /// Find the local sounds most like one, by their analysis descriptors
+    ///
+    /// Sounds are compared on their duration and the descriptors of
+    /// [`SimilarityWeights`](crate::SimilarityWeights), weighed as configured
+    /// in [`VaultConfig::similarity_weights`]. The sound itself, its
+    /// derivatives, its parent and copies of its file are left out; sounds
+    /// without descriptors are skipped and counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundMetadata, SoundVault, VaultConfig};
+    /// use std::collections::HashMap;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let mut import = |name: &str, descriptors: &[(&str, f64)]| {
+    ///     let file = dir.path().join(format!("{}.wav", name));
+    ///     std::fs::write(&file, name).unwrap();
+    ///     let mut metadata = SoundMetadata::default();
+    ///     metadata.name = name.to_string();
+    ///     metadata.descriptors = descriptors.iter().map(|(name, value)| (name.to_string(), *value)).collect::<HashMap<_, _>>();
+    ///     let vault = &vault;
+    ///     async move { vault.import_file(&file, Some(metadata)).await }
+    /// };
+    /// let hiss = import("hiss", &[("spectral_centroid", 6000.0), ("spectral_flatness", 0.8)]).await?;
+    /// import("rain", &[("spectral_centroid", 5000.0), ("spectral_flatness", 0.7)]).await?;
+    /// import("hum", &[("spectral_centroid", 120.0), ("spectral_flatness", 0.05)]).await?;
+    /// import("notes", &[]).await?;
+    ///
+    /// let similar = vault.similar_local(&hiss, 10).await?;
+    /// let names: Vec<_> = similar.sounds.iter().map(|scored| scored.sound.metadata.name.as_str()).collect();
+    /// assert_eq!(names, ["rain", "hum"]);
+    /// assert!(similar.sounds[0].distance < similar.sounds[1].distance);
+    /// assert_eq!(similar.skipped, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn similar_local(&self, id: &str, limit: usize) -> Result<SimilarSounds> {
+        self.local.similar_sounds(id, limit, &self.local.similarity_weights).await
+    }
+
+    /// Find the local sounds most like one like
+    /// [`SoundVault::similar_local`], with other weights
+    pub async fn similar_local_with(&self, id: &str, limit: usize, weights: &SimilarityWeights) -> Result<SimilarSounds> {
+        self.local.similar_sounds(id, limit, weights).await
+    }
+
+    /// Group the sounds that may hold the same audio in different
+    /// encodings, e.g. a WAV file and an MP3 made from it
+    ///
+    /// Sounds are compared by acoustic fingerprint, computed the first time
+    /// from their file and kept until its content changes; files in formats
+    /// other than WAV, AIFF and CAF are read with the `rodio` feature only.
+    /// Pairs at least as similar as `threshold`, from 0 to 1, are linked and
+    /// linked sounds grouped; review a pair with
+    /// [`SoundVault::audio_similarity`]. A sound and its derivatives are
+    /// never linked. Nothing is deleted.
+    ///
+    /// Setting [`VaultConfig::near_duplicate_threshold`] checks each import
+    /// against the sounds fingerprinted so far.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundVault, VaultConfig, VaultEvent, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// config.near_duplicate_threshold = Some(0.6);
+    /// let vault = SoundVault::new(config).await?;
+    /// let mut events = vault.subscribe();
+    ///
+    /// // Two seconds of noise, as 16-bit WAV and as slightly noisier 24-bit AIFF
+    /// let noise = |seed: u32| {
+    ///     let mut state = seed;
+    ///     (0..16_000).map(move |_| {
+    ///         state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+    ///         (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+    ///     })
+    /// };
+    /// let take: Vec<f32> = noise(1).collect();
+    /// let reencoded: Vec<f32> = take.iter().zip(noise(2)).map(|(sample, hiss)| sample + hiss * 0.01).collect();
+    /// let other: Vec<f32> = noise(3).collect();
+    /// let mut import = |name: &str, format: AudioFormat, bits: u16, samples: &[f32]| {
+    ///     let file = dir.path().join(name);
+    ///     std::fs::write(&file, encode(&AudioInfo::new(format, 1, 8000, SampleFormat::Int(bits)), samples).unwrap()).unwrap();
+    ///     let vault = &vault;
+    ///     async move { vault.import_file(&file, None).await }
+    /// };
+    /// let wav = import("take.wav", AudioFormat::Wav, 16, &take).await?;
+    /// let aiff = import("take.aiff", AudioFormat::Aiff, 24, &reencoded).await?;
+    /// let other = import("other.wav", AudioFormat::Wav, 16, &other).await?;
+    ///
+    /// // The import of the copy was reported, and kept
+    /// assert_eq!(
+    ///     events.try_recv()?,
+    ///     VaultEvent::PossibleDuplicate { sound_id: aiff.clone(), duplicate_of: wav.clone() }
+    /// );
+    /// assert!(events.try_recv().is_err());
+    ///
+    /// let groups = vault.find_near_duplicates(0.6).await?;
+    /// assert_eq!(groups.len(), 1);
+    /// let mut pair = vec![wav.clone(), aiff.clone()];
+    /// pair.sort();
+    /// assert_eq!(groups[0].sound_ids, pair);
+    /// assert!(groups[0].matches[0].similarity > 0.6);
+    /// assert!(vault.audio_similarity(&wav, &other).await?.unwrap() < 0.3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "analysis")]
+    pub async fn find_near_duplicates(&self, threshold: f64) -> Result<Vec<DuplicateGroup>> {
+        self.local.find_near_duplicates(threshold).await
+    }
+
+    /// Similarity of the audio of two sounds by acoustic fingerprint, from
+    /// 0 for unrelated audio to 1 for identical audio
+    ///
+    /// `None` if either sound can't be fingerprinted, e.g. its file can't be
+    /// decoded, or their durations are too far apart to be the same audio.
+    #[cfg(feature = "analysis")]
+    pub async fn audio_similarity(&self, a: &str, b: &str) -> Result<Option<f64>> {
+        self.local.audio_similarity(a, b).await
+    }
+
+    /// Whether any local sound matches a filter
+    ///
+    /// Cheaper than [`SoundVault::count`] when only a yes or no is needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, SoundFilter, SoundMetadata, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("door.wav");
+    /// std::fs::write(&file, b"door")?;
+    /// let mut metadata = SoundMetadata::default();
+    /// metadata.tags = vec!["door".to_string()];
+    /// let id = vault.import_file(&file, Some(metadata)).await?;
+    ///
+    /// let tagged = |tag: &str| SoundFilter { tags: vec![tag.to_string()], ..Default::default() };
+    /// assert!(vault.exists(&tagged("door")).await?);
+    /// assert!(!vault.exists(&tagged("window")).await?);
+    ///
+    /// let hash = vault.get_sound(&id).await?.metadata.hash.unwrap();
+    /// assert!(vault.contains_hash(&hash).await?);
+    ///
+    /// let collection_id = vault.add_collection(&Collection::new("Doors", "")).await?;
+    /// vault.add_sound_to_collection(&id, &collection_id).await?;
+    /// assert_eq!(vault.collection_size(&collection_id).await?, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn exists(&self, filter: &SoundFilter) -> Result<bool> {
+        self.local.exists(filter).await
+    }
+
+    /// Number of sounds in a collection, without loading them
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if the collection doesn't exist
+    pub async fn collection_size(&self, collection_id: &str) -> Result<u64> {
+        self.local.collection_size(collection_id).await
+    }
+
+    /// Whether a local sound has this SHA-256 content hash
+    pub async fn contains_hash(&self, hash: &str) -> Result<bool> {
+        self.local.contains_hash(hash).await
+    }
+
+    /// Browse sounds as virtual folders, grouped by field values level by level
+    ///
+    /// Fields are custom metadata keys or the built-ins `author` (the custom
+    /// `author` value, or the Freesound uploader), `license` and `format`
+    /// (the file extension). Only the first level is loaded; expand deeper
+    /// nodes with [`SoundVault::expand_browse_node`] and list a node's sounds
+    /// with its [`BrowseNode::filter`]. Grouping and counting run in SQL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{PageRequest, SoundMetadata, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// for (file, project, action) in [("a.wav", "Ice Cave", "walk"), ("b.wav", "Ice Cave", "jump"), ("c.aif", "Forest", "walk")] {
+    ///     let path = dir.path().join(file);
+    ///     std::fs::write(&path, file)?;
+    ///     let mut metadata = SoundMetadata::default();
+    ///     metadata.set_custom("project", project);
+    ///     metadata.set_custom("action", action);
+    ///     vault.import_file(&path, Some(metadata)).await?;
+    /// }
+    ///
+    /// let root = vault.browse(&["project", "action"]).await?;
+    /// let projects = root.children.as_ref().unwrap();
+    /// assert_eq!(projects.iter().map(|n| (n.value(), n.count)).collect::<Vec<_>>(), [(Some("Forest"), 1), (Some("Ice Cave"), 2)]);
+    ///
+    /// let mut ice_cave = projects[1].clone();
+    /// vault.expand_browse_node(&mut ice_cave).await?;
+    /// let jump = &ice_cave.children.as_ref().unwrap()[0];
+    /// assert_eq!((jump.value(), jump.count, jump.is_leaf()), (Some("jump"), 1, true));
+    /// let page = vault.query_page(&jump.filter(), &PageRequest::default()).await?;
+    /// assert_eq!(page.sounds.len(), 1);
+    ///
+    /// let formats = vault.browse(&["format"]).await?.children.unwrap();
+    /// assert_eq!(formats.iter().map(|n| n.value()).collect::<Vec<_>>(), [Some("aif"), Some("wav")]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn browse(&self, hierarchy: &[&str]) -> Result<BrowseNode> {
+        self.local.browse(hierarchy).await
+    }
+
+    /// Load the next level below a node returned by [`SoundVault::browse`]
+    pub async fn expand_browse_node(&self, node: &mut BrowseNode) -> Result<()> {
+        self.local.expand_browse_node(node).await
+    }
+
+    /// Count the sounds, bytes and seconds of audio under each value of a
+    /// dimension, e.g. per project for billing
+    ///
+    /// Bytes are the recorded file sizes, referenced files included. Each
+    /// entry carries the soft limit set for its value with
+    /// [`SoundVault::set_soft_limit`]. Grouping and summing run in SQL.
+    ///
+    /// # Returns
+    ///
+    /// One entry per value, ordered by value with the sounds lacking one
+    /// last
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoftLimit, SoundMetadata, SoundVault, StatsDimension, VaultConfig, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    /// for (file, project) in [("a.wav", "Ice Cave"), ("b.wav", "Ice Cave"), ("c.wav", "Forest")] {
+    ///     let path = dir.path().join(file);
+    ///     std::fs::write(&path, encode(&AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16)), &[0.25; 8000])?)?;
+    ///     let mut metadata = SoundMetadata::default();
+    ///     metadata.set_custom("project", project);
+    ///     vault.import_file(&path, Some(metadata)).await?;
+    /// }
+    ///
+    /// let project = StatsDimension::Custom("project".to_string());
+    /// let limit = SoftLimit { max_sounds: Some(1), ..Default::default() };
+    /// vault.set_soft_limit(&project, "Ice Cave", &limit).await?;
+    ///
+    /// let stats = vault.stats_by(project).await?;
+    /// assert_eq!(stats.iter().map(|s| (s.value.as_deref(), s.count, s.duration)).collect::<Vec<_>>(), [(Some("Forest"), 1, 1.0), (Some("Ice Cave"), 2, 2.0)]);
+    /// assert!(stats[1].bytes > 32000);
+    /// assert!(stats[1].is_over_limit());
+    ///
+    /// // Going over is only reported
+    /// assert_eq!(vault.health().await?.soft_limit_warnings, ["project \"Ice Cave\" is over its soft limit: 2 sounds of 1"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn stats_by(&self, dimension: StatsDimension) -> Result<Vec<DimensionStats>> {
+        self.local.stats_by(&dimension).await
+    }
+
+    /// Set the soft limit of a value of a dimension, replacing any
+    ///
+    /// Values over their limit are listed in the
+    /// [`HealthReport::soft_limit_warnings`]; nothing is refused.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if a custom dimension has no key or
+    ///   the duration is negative
+    pub async fn set_soft_limit(&self, dimension: &StatsDimension, value: &str, limit: &SoftLimit) -> Result<()> {
+        self.local.authorize(Role::Admin, "set_soft_limit", "").await?;
+        self.local.set_soft_limit(dimension, value, limit).await
+    }
+
+    /// Remove the soft limit of a value of a dimension
+    ///
+    /// # Returns
+    ///
+    /// Whether there was one
+    pub async fn remove_soft_limit(&self, dimension: &StatsDimension, value: &str) -> Result<bool> {
+        self.local.authorize(Role::Admin, "remove_soft_limit", "").await?;
+        self.local.remove_soft_limit(dimension, value).await
+    }
+
+    /// Soft limits set for the values of a dimension, by value
+    pub async fn list_soft_limits(&self, dimension: &StatsDimension) -> Result<BTreeMap<String, SoftLimit>> {
+        self.local.list_soft_limits(dimension).await
+    }
+
+    /// Write a sound's file to `destination`
+    ///
+    /// Channels are kept as recorded with [`ChannelMix::Preserve`], or folded
+    /// explicitly into stereo or mono, e.g. for an ambisonic source.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ChannelMix, SoundVault};
+    /// use std::path::Path;
+    ///
+    /// # async fn example(vault: SoundVault, sound_id: String) -> soundvault::Result<()> {
+    /// vault.export_sound(&sound_id, Path::new("ambience_ambix.aif"), ChannelMix::Preserve).await?;
+    /// vault.export_sound(&sound_id, Path::new("ambience_stereo.aif"), ChannelMix::Stereo).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn export_sound(&self, id: &str, destination: &Path, mix: ChannelMix) -> Result<()> {
+        self.local.export_sound(id, destination, mix).await
+    }
+
+    /// Write a sound's file to `destination`, embedding its attribution or
+    /// stripping its tags
+    ///
+    /// By default the sound's title, author, license URL and source URL
+    /// replace the file's tags, so credits travel with the file. The author
+    /// is the custom `author` value, or the Freesound uploader; the license
+    /// URL is the deed of a recognized license in the version it names, or
+    /// the license itself when it's another address. WAV, AIFF and CAF files are tagged; files in
+    /// other formats are written as stored.
+    ///
+    /// If `destination` is a directory, the file is written into it, named
+    /// after the stored file with the extension of its true format.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::Expired` if the sound's expiration date has passed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     AudioFormat, AudioInfo, ExportOptions, SampleFormat, SoundMetadata, SoundVault, VaultConfig, encode,
+    ///     read_attribution,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// for (format, file) in [(AudioFormat::Wav, "creek.wav"), (AudioFormat::Aiff, "creek.aif"), (AudioFormat::Caf, "creek.caf")] {
+    ///     let path = dir.path().join(file);
+    ///     std::fs::write(&path, encode(&AudioInfo::new(format, 1, 8000, SampleFormat::Int(16)), &[0.0, 0.5, -0.5])?)?;
+    ///     let mut metadata = SoundMetadata {
+    ///         name: "Creek".to_string(),
+    ///         license: "CC BY 4.0".to_string(),
+    ///         ..Default::default()
+    ///     };
+    ///     metadata.set_custom("author", "ana");
+    ///     let id = vault.import_file(&path, Some(metadata)).await?;
+    ///
+    ///     let exported = dir.path().join(format!("export-{}", file));
+    ///     vault.export_sound_with_options(&id, &exported, &ExportOptions::default()).await?;
+    ///     let attribution = read_attribution(&exported)?;
+    ///     assert_eq!(attribution.title.as_deref(), Some("Creek"));
+    ///     assert_eq!(attribution.author.as_deref(), Some("ana"));
+    ///     assert_eq!(attribution.license_url.as_deref(), Some("https://creativecommons.org/licenses/by/4.0/"));
+    ///
+    ///     let options = ExportOptions { attribution: false, strip_metadata: true, ..Default::default() };
+    ///     vault.export_sound_with_options(&id, &exported, &options).await?;
+    ///     assert_eq!(read_attribution(&exported)?, Default::default());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn export_sound_with_options(&self, id: &str, destination: &Path, options: &ExportOptions) -> Result<()> {
+        self.local.export_sound_with_options(id, destination, options).await
+    }
+
+    /// Export a collection as a folder ready to open in a DAW
+    ///
+    /// The sounds are written into `target_dir/Media`, named after the
+    /// sounds and with their attribution embedded as by
+    /// [`SoundVault::export_sound_with_options`]. Next to it,
+    /// [`DawFlavor::Reaper`] writes a project named after the collection
+    /// with one track per sound, and
+    /// [`DawFlavor::Generic`] a `tracks.json` list of [`DawTrack`]s. Files
+    /// are referenced by relative path so the folder can be moved, and
+    /// items take their length, and trim, from the sounds' metadata.
+    ///
+    /// Sounds without a file, such as remote members, are left out, and so
+    /// are expired sounds, each reported as a
+    /// [`VaultEvent::ExpiredExcluded`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, Collection, DawFlavor, DawTrack, Envelope, SampleFormat, SoundMetadata, SoundVault, VaultConfig, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    /// let collection_id = vault.add_collection(&Collection::new("Storm", "")).await?;
+    /// for (name, frames) in [("Rain.wav", 8000), ("Thunder", 4000), ("Rain.wav", 2000)] {
+    ///     let path = dir.path().join("take.wav");
+    ///     std::fs::write(&path, encode(&AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16)), &vec![0.25; frames])?)?;
+    ///     let metadata = SoundMetadata { name: name.to_string(), ..Default::default() };
+    ///     let id = vault.import_file(&path, Some(metadata)).await?;
+    ///     vault.add_sound_to_collection(&id, &collection_id).await?;
+    /// }
+    ///
+    /// let session = dir.path().join("session");
+    /// vault.export_daw_session(&collection_id, &session, DawFlavor::Reaper).await?;
+    /// let project = std::fs::read_to_string(session.join("Storm.rpp"))?;
+    /// assert!(project.contains("NAME \"Thunder\""));
+    /// assert!(project.contains("LENGTH 0.5"));
+    /// assert!(project.contains("FILE \"Media/Rain (2).wav\""));
+    /// assert!(session.join("Media/Rain (2).wav").exists());
+    ///
+    /// vault.export_daw_session(&collection_id, &session, DawFlavor::Generic).await?;
+    /// let tracks: Vec<DawTrack> = Envelope::parse("soundvault.daw-tracks", &std::fs::read_to_string(session.join("tracks.json"))?)?.data;
+    /// let mut files: Vec<_> = tracks.iter().map(|track| track.file.as_str()).collect();
+    /// files.sort();
+    /// assert_eq!(files, ["Media/Rain (2).wav", "Media/Rain.wav", "Media/Thunder.wav"]);
+    /// let thunder = tracks.iter().find(|track| track.name == "Thunder").unwrap();
+    /// assert_eq!(thunder.length, 0.5);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn export_daw_session(&self, collection_id: &str, target_dir: &Path, flavor: DawFlavor) -> Result<()> {
+        self.local.export_daw_session(collection_id, target_dir, flavor).await
+    }
+
+    /// Stream a zip archive of sounds to `writer`, e.g. to a browser,
+    /// without temporary files
+    ///
+    /// Each file is read and written a chunk at a time, so memory use
+    /// doesn't grow with the size of the sounds, and only a little with
+    /// their number. Entries are named from [`ZipOptions::name_template`];
+    /// a `manifest.json` and an `ATTRIBUTION.txt` follow them unless turned
+    /// off. Sounds that aren't in the vault, were never downloaded, expired
+    /// or whose file is missing are left out and listed in
+    /// [`ZipReport::skipped`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundMetadata, SoundVault, VaultConfig, ZipOptions, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let mut ids = Vec::new();
+    /// for (name, level) in [("Rain", 0.1), ("Rain", 0.2), ("Thunder", 0.5)] {
+    ///     let file = sources.path().join(format!("{}.wav", level));
+    ///     std::fs::write(&file, encode(&info, &vec![level; 800])?)?;
+    ///     let metadata = SoundMetadata { name: name.to_string(), license: "CC0".to_string(), ..Default::default() };
+    ///     ids.push(vault.import_file(&file, Some(metadata)).await?);
+    /// }
+    /// ids.push("gone".to_string());
+    ///
+    /// let options = ZipOptions { name_template: "sfx/{name}".to_string(), ..Default::default() };
+    /// let mut archive = Vec::new();
+    /// let report = vault.zip_sounds(&ids, &mut archive, &options).await?;
+    /// let names: Vec<&str> = report.entries.iter().map(|(_, name)| name.as_str()).collect();
+    /// assert_eq!(names, ["sfx/Rain.wav", "sfx/Rain (2).wav", "sfx/Thunder.wav"]);
+    /// assert_eq!(report.skipped, vec![("gone".to_string(), "not in the vault".to_string())]);
+    /// assert_eq!(report.bytes, archive.len() as u64);
+    ///
+    /// // The archive indexes back as the same sounds
+    /// let zip = sources.path().join("sounds.zip");
+    /// std::fs::write(&zip, &archive)?;
+    /// let copy_dir = tempfile::tempdir()?;
+    /// let copy = SoundVault::new(VaultConfig::new(copy_dir.path().to_path_buf(), None)).await?;
+    /// let packed = copy.index_archive(&zip).await?;
+    /// assert_eq!(packed.len(), 3);
+    /// for (packed, id) in packed.iter().zip(&ids) {
+    ///     assert_eq!(copy.get_sound(packed).await?.metadata.hash, vault.get_sound(id).await?.metadata.hash);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn zip_sounds<W: AsyncWrite + Unpin>(&self, ids: &[String], writer: W, options: &ZipOptions) -> Result<ZipReport> {
+        self.zip_sounds_with_context(ids, writer, options, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::zip_sounds`], stopped by `context` between chunks
+    ///
+    /// A stopped archive is closed with the entries written in full so far,
+    /// leaving out the one cut short, the manifest and the attribution, so
+    /// the writer holds a valid archive of those entries.
+    pub async fn zip_sounds_with_context<W: AsyncWrite + Unpin>(
+        &self,
+        ids: &[String],
+        writer: W,
+        options: &ZipOptions,
+        context: &OpContext,
+    ) -> Result<ZipReport> {
+        self.local.zip_sounds(ids, writer, options, context).await
+    }
+
+    /// Generate a stereo preview of a sound, downmixing extra channels
+    ///
+    /// # Returns
+    ///
+    /// The path of the preview file
+    pub async fn generate_preview(&self, id: &str) -> Result<PathBuf> {
+        self.local.generate_preview(id).await
+    }
+
+    /// Compute a waveform of a sound's mono mixdown
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the sound
+    /// * `buckets` - Number of peak values to return
+    pub async fn sound_waveform(&self, id: &str, buckets: usize) -> Result<Vec<f32>> {
+        self.local.sound_waveform(id, buckets).await
+    }
+
+    /// Measure the peak, waveform, integrated loudness and silences of a
+    /// sound
+    ///
+    /// The file is decoded a chunk at a time, so memory stays bounded however
+    /// long the sound is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, LevelOptions, SampleFormat, SoundVault, VaultConfig, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// // A second of noise, then a second of silence
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let mut samples: Vec<f32> = (0..8000).map(|i| if i % 2 == 0 { 0.5 } else { -0.5 }).collect();
+    /// samples.resize(16000, 0.0);
+    /// let file = dir.path().join("burst.wav");
+    /// std::fs::write(&file, encode(&info, &samples)?)?;
+    /// let id = vault.import_file(&file, None).await?;
+    ///
+    /// let levels = vault.sound_levels(&id, LevelOptions { buckets: 2, ..Default::default() }).await?;
+    /// assert_eq!(levels.duration, 2.0);
+    /// assert_eq!(levels.waveform, vec![0.5, 0.0]);
+    /// assert_eq!((levels.silences[0].start, levels.silences[0].end), (1.0, 2.0));
+    /// assert!(levels.loudness.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn sound_levels(&self, id: &str, options: LevelOptions) -> Result<Levels> {
+        self.local.sound_levels(id, options).await
+    }
+
+    /// Render a spectrogram of a sound's mono mixdown as PNG bytes
+    ///
+    /// Images are cached per size until the sound's content changes or
+    /// [`SoundVault::gc_artifacts`] evicts them.
+    /// Rendering runs on the background job queue, limited by
+    /// [`VaultConfig::max_background_jobs`] or [`SoundVault::set_limits`].
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the sound
+    /// * `width` - Width of the image in pixels, spanning the sound's duration
+    /// * `height` - Height of the image in pixels, spanning frequencies up to
+    ///   half the sample rate
+    #[cfg(feature = "analysis")]
+    pub async fn get_spectrogram(&self, id: &str, width: u32, height: u32) -> Result<Vec<u8>> {
+        self.local.get_spectrogram(id, width, height).await
+    }
+
+    /// Render the waveform of a sound's mono mixdown as PNG bytes, for
+    /// clients that would rather not draw [`SoundVault::sound_waveform`]
+    /// themselves
+    ///
+    /// Images are cached per size and style until the sound's content
+    /// changes or [`SoundVault::gc_artifacts`] evicts them.
+    /// Rendering runs on the background job queue, limited by
+    /// [`VaultConfig::max_background_jobs`] or [`SoundVault::set_limits`].
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the sound
+    /// * `width` - Width of the image in pixels, one peak per column
+    /// * `height` - Height of the image in pixels
+    /// * `style` - How the waveform is drawn, and in which colors
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundVault, VaultConfig, WaveformMode, WaveformStyle, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let samples: Vec<f32> = (0..8000).map(|i| (i as f32 / 40.0).sin() * 0.8).collect();
+    /// std::fs::write(sources.path().join("hum.wav"), encode(&info, &samples)?)?;
+    /// let id = vault.import_file(sources.path().join("hum.wav"), None).await?;
+    ///
+    /// let style = WaveformStyle { mode: WaveformMode::Line, background: [255, 255, 255, 255], ..Default::default() };
+    /// let png = vault.waveform_png(&id, 200, 48, &style).await?;
+    /// assert!(png.starts_with(b"\x89PNG"));
+    /// // Served from the cache the second time
+    /// assert_eq!(vault.waveform_png(&id, 200, 48, &style).await?, png);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "analysis")]
+    pub async fn waveform_png(&self, id: &str, width: u32, height: u32, style: &crate::WaveformStyle) -> Result<Vec<u8>> {
+        self.local.waveform_png(id, width, height, style).await
+    }
+
+    /// Read the original file of a sound for playback
+    ///
+    /// Compressed sounds are decompressed transparently. Opening a sound
+    /// keeps it out of cold storage and records the access, as
+    /// [`SoundVault::access_sound`] does.
+    pub async fn open_sound(&self, id: &str) -> Result<Vec<u8>> {
+        self.local.open_sound(id).await
+    }
+
+    /// Decode a sound to interleaved samples at the given sample rate and
+    /// channel count
+    ///
+    /// Only the part between the sound's trim markers is kept, and the sound
+    /// is marked as played, unless `spec` says otherwise. Channels are folded
+    /// into one or two, or a mono sound is copied to every channel.
+    /// Resampling is band-limited with the `analysis` feature and linear
+    /// otherwise. Decoding runs on the background job queue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     AudioFormat, AudioInfo, MetadataPatch, OutputSpec, SampleFormat, SoundVault, Trim, VaultConfig, encode,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// // One second of mono audio at 8 kHz
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let file = dir.path().join("hum.wav");
+    /// std::fs::write(&file, encode(&info, &vec![0.25; 8000])?)?;
+    /// let id = vault.import_file(&file, None).await?;
+    ///
+    /// let patch = MetadataPatch { trim: Some(Some(Trim { start: 0.5, end: None })), ..Default::default() };
+    /// vault.patch_metadata(&id, patch).await?;
+    ///
+    /// // The last half second, at 16 kHz in stereo
+    /// let audio = vault.decode(&id, OutputSpec::new(16000, 2)).await?;
+    /// assert_eq!((audio.frames(), audio.channels), (8000, 2));
+    ///
+    /// let stream = vault.decode_stream(&id, OutputSpec::new(16000, 1), 3000).await?;
+    /// let sizes = stream.map(|chunk| chunk.map(|samples| samples.len())).collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(sizes, vec![3000, 3000, 2000]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn decode(&self, id: &str, spec: OutputSpec) -> Result<DecodedAudio> {
+        self.local.decode_sound(id, spec).await
+    }
+
+    /// Decode a sound like [`SoundVault::decode`], reading
+    /// `frames_per_chunk` frames at a time
+    ///
+    /// Suits long files: samples are converted as chunks are read.
+    pub async fn decode_stream(&self, id: &str, spec: OutputSpec, frames_per_chunk: usize) -> Result<DecodedStream> {
+        self.local.decode_sound_stream(id, spec, frames_per_chunk).await
+    }
+
+    /// Decode a sound like [`SoundVault::decode`], whether it's stored or a
+    /// remote search result
+    ///
+    /// Sounds with a file are read from the vault. Freesound sounds without
+    /// one are decoded from their preview, which is held in memory only:
+    /// nothing is written to the library and no play is recorded.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound has no file and isn't
+    ///   from Freesound
+    /// * `VaultError::Config` if it needs a preview but no Freesound API key
+    ///   is configured
+    /// * `VaultError::UnsupportedCodec` if no decoder reads the preview;
+    ///   MP3 and Ogg Vorbis need the `rodio` feature
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, OutputSpec, SampleFormat, SoundVault, VaultConfig, VaultError, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let file = dir.path().join("hum.wav");
+    /// std::fs::write(&file, encode(&info, &vec![0.25; 8000])?)?;
+    /// let mut sound = vault.get_sound(&vault.import_file(&file, None).await?).await?;
+    /// let audio = vault.decode_sound(&sound, OutputSpec::new(8000, 1)).await?;
+    /// assert_eq!(audio.frames(), 8000);
+    ///
+    /// // A search result from Freesound needs an API key to fetch its preview
+    /// sound.metadata.path = None;
+    /// sound.metadata.freesound_id = Some(1234);
+    /// let result = vault.decode_sound(&sound, OutputSpec::new(8000, 1)).await;
+    /// assert!(matches!(result, Err(VaultError::Config(_))));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn decode_sound(&self, sound: &Sound, spec: OutputSpec) -> Result<DecodedAudio> {
+        DecodedAudio::read(self.decode_sound_stream(sound, spec, usize::MAX).await?)
+    }
+
+    /// Decode a sound like [`SoundVault::decode_sound`], reading
+    /// `frames_per_chunk` frames at a time
+    pub async fn decode_sound_stream(
+        &self,
+        sound: &Sound,
+        spec: OutputSpec,
+        frames_per_chunk: usize,
+    ) -> Result<DecodedStream> {
+        if sound.metadata.path.is_some() {
+            return self.decode_stream(&sound.metadata.id, spec, frames_per_chunk).await;
+        }
+        let bytes = self.freesound_preview(sound).await?;
+        self.local
+            .decode_content_stream(bytes.to_vec(), sound.metadata.trim, spec, frames_per_chunk)
+            .await
+    }
+
+    /// Stream the Freesound preview of a sound, such as a remote search
+    /// result, without storing anything
+    ///
+    /// The preview is held in memory, so the stream can seek; previewing
+    /// the same sound again in this session reuses it.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound isn't from Freesound
+    /// * `VaultError::Config` if no Freesound API key is configured
+    /// * `VaultError::NotFound` if Freesound offers no preview of the sound
+    pub async fn stream_preview(&self, sound: &Sound) -> Result<PreviewStream> {
+        let freesound_id = Self::freesound_id(sound)?;
+        self.remote()?.stream_preview(freesound_id).await
+    }
+
+    /// Decode a sound into a source to play through a rodio sink
+    ///
+    /// Sounds with a file are read from the vault, others streamed from
+    /// their HTTP(S) preview; with a Freesound API key, Freesound previews
+    /// are held in memory like [`SoundVault::stream_preview`]'s. Trim markers and loudness normalization are
+    /// applied as `options` ask; see [`RodioOptions`](crate::RodioOptions).
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::FileMissing` if there's no file to read, so a caller
+    ///   can offer to download or relink it
+    /// * `VaultError::UnsupportedCodec` if no decoder reads the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rodio::Source;
+    /// use soundvault::{
+    ///     AudioFormat, AudioInfo, ImportMode, ImportOptions, MetadataPatch, RodioOptions, SampleFormat, SoundVault,
+    ///     Trim, VaultConfig, VaultError, encode,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// // One second of a mono tone at 8 kHz, of which the last half plays
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let tone: Vec<f32> = (0..8000).map(|i| 0.5 * (i as f32 * 0.3).sin()).collect();
+    /// let drive = tempfile::tempdir()?;
+    /// let file = drive.path().join("hum.wav");
+    /// std::fs::write(&file, encode(&info, &tone)?)?;
+    /// let options = ImportOptions { mode: ImportMode::Reference, ..Default::default() };
+    /// let id = vault.import_file_with_options(&file, None, options.clone()).await?;
+    /// let patch = MetadataPatch { trim: Some(Some(Trim { start: 0.5, end: None })), ..Default::default() };
+    /// vault.patch_metadata(&id, patch).await?;
+    ///
+    /// let sound = vault.get_sound(&id).await?;
+    /// let source = vault.rodio_source(&sound, &RodioOptions::default()).await?;
+    /// assert_eq!((source.channels(), source.sample_rate()), (1, 8000));
+    /// assert_eq!(source.count(), 4000);
+    ///
+    /// // The tone is around -10 LUFS; brought down to -30 its peak drops
+    /// let quiet = RodioOptions { normalize_to: Some(-30.0), ..Default::default() };
+    /// let peak = vault.rodio_source(&sound, &quiet).await?.fold(0.0, |peak: f32, sample| peak.max(sample.abs()));
+    /// assert!(peak > 0.03 && peak < 0.1);
+    ///
+    /// std::fs::remove_file(&file)?;
+    /// assert!(matches!(vault.rodio_source(&sound, &quiet).await, Err(VaultError::FileMissing(_))));
+    ///
+    /// let notes = drive.path().join("notes.wav");
+    /// std::fs::write(&notes, b"not audio")?;
+    /// let notes = vault.import_file_with_options(&notes, None, options).await?;
+    /// let result = vault.rodio_source(&vault.get_sound(&notes).await?, &RodioOptions::default()).await;
+    /// assert!(matches!(result, Err(VaultError::UnsupportedCodec(_))));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "rodio")]
+    pub async fn rodio_source(
+        &self,
+        sound: &Sound,
+        options: &crate::RodioOptions,
+    ) -> Result<rodio::buffer::SamplesBuffer> {
+        if sound.metadata.path.is_none() && sound.metadata.freesound_id.is_some() && self.remote.is_some() {
+            let bytes = self.freesound_preview(sound).await?;
+            return self.local.rodio_content(bytes.to_vec(), sound.metadata.trim, options).await;
+        }
+        self.local.rodio_source(sound, options).await
+    }
+
+    /// Replace the stored file of a sound by a compressed copy
+    ///
+    /// Exports, previews and [`SoundVault::open_sound`] still see the original
+    /// file, decompressed on access. Content-addressed files, which other
+    /// sounds may share, aren't compressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the sound
+    /// * `codec` - Compression to use
+    pub async fn compress_sound(&self, id: &str, codec: ArchivalCodec) -> Result<()> {
+        self.local.authorize(Role::Editor, "compress_sound", id).await?;
+        self.local.compress_sound(id, codec).await
+    }
+
+    /// Restore the original file of a compressed sound
+    pub async fn decompress_sound(&self, id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "decompress_sound", id).await?;
+        self.local.decompress_sound(id).await
+    }
+
+    /// Compress the sounds that haven't been opened for a while
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ColdStoragePolicy, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// let policy = ColdStoragePolicy {
+    ///     unused_days: 180,
+    ///     ..Default::default()
+    /// };
+    /// let report = vault.compress_cold_sounds(&policy).await?;
+    /// println!("{} sounds archived, {} bytes saved", report.compressed.len(), report.bytes_saved);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn compress_cold_sounds(&self, policy: &ColdStoragePolicy) -> Result<ColdStorageReport> {
+        self.compress_cold_sounds_with_context(policy, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::compress_cold_sounds`], stopped by `context` between
+    /// sounds
+    ///
+    /// Sounds compressed before the run stopped stay compressed.
+    pub async fn compress_cold_sounds_with_context(
+        &self,
+        policy: &ColdStoragePolicy,
+        context: &OpContext,
+    ) -> Result<ColdStorageReport> {
+        self.local.authorize(Role::Editor, "compress_cold_sounds", "").await?;
+        self.local.compress_cold_sounds(policy, context).await
+    }
+
+    /// Evict the previews, spectrograms and waveform images that
+    /// [`VaultConfig::artifacts`] doesn't keep
+    ///
+    /// Files of deleted sounds and files unused for too long go first, then
+    /// the least recently used of each kind until the rest fits its size
+    /// limit. Use times are recorded in the database whenever a file is
+    /// generated or served. An evicted file is generated again the next
+    /// time it's asked for.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     ArtifactKind, ArtifactLimits, AudioFormat, AudioInfo, ByteSize, HumanDuration, SampleFormat, SoundVault, VaultConfig, encode,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// config.artifacts.previews = ArtifactLimits { max_bytes: Some(ByteSize::kb(20)), max_age: Some(HumanDuration::from_days(30)) };
+    /// let vault = SoundVault::new(config).await?;
+    ///
+    /// // Two files of one second of mono audio at 8 kHz
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let mut ids = Vec::new();
+    /// for (name, level) in [("hum.wav", 0.25), ("buzz.wav", 0.5)] {
+    ///     let file = dir.path().join(name);
+    ///     std::fs::write(&file, encode(&info, &vec![level; 8000])?)?;
+    ///     ids.push(vault.import_file(&file, None).await?);
+    /// }
+    /// let older = vault.generate_preview(&ids[0]).await?;
+    /// let newer = vault.generate_preview(&ids[1]).await?;
+    /// let size = std::fs::metadata(&older)?.len();
+    ///
+    /// // Both previews don't fit in 20 kB
+    /// let usage = vault.disk_usage().await?;
+    /// assert_eq!(usage.originals, 2 * size);
+    /// assert_eq!((usage.artifacts[&ArtifactKind::Preview], usage.reclaimable), (2 * size, size));
+    ///
+    /// let report = vault.gc_artifacts().await?;
+    /// assert_eq!((report.evicted, report.bytes_freed), (vec![older.clone()], size));
+    /// assert!(!older.exists() && newer.exists());
+    /// assert_eq!(vault.disk_usage().await?.reclaimable, 0);
+    ///
+    /// assert_eq!(vault.generate_preview(&ids[0]).await?, older);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn gc_artifacts(&self) -> Result<GcReport> {
+        self.local.authorize(Role::Editor, "gc_artifacts", "").await?;
+        self.local.gc_artifacts().await
+    }
+
+    /// Space taken by the sounds' files and, apart, by generated files
+    ///
+    /// [`DiskUsage::reclaimable`] tells what
+    /// [`SoundVault::gc_artifacts`] would free now.
+    pub async fn disk_usage(&self) -> Result<DiskUsage> {
+        self.local.disk_usage().await
+    }
+
+    /// Space taken by the library against
+    /// [`VaultConfig::max_library_bytes`]
+    ///
+    /// Sizes are those recorded when files entered the library, so this is
+    /// cheap enough to check before every import.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, ByteSize, SampleFormat, SoundVault, VaultConfig, VaultError, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// config.max_library_bytes = Some(ByteSize::kb(10));
+    /// let vault = SoundVault::new(config).await?;
+    ///
+    /// // Two files of half a second of mono audio at 8 kHz, only one fits
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let (hum, buzz) = (dir.path().join("hum.wav"), dir.path().join("buzz.wav"));
+    /// for file in [&hum, &buzz] {
+    ///     std::fs::write(file, encode(&info, &vec![0.25; 4000])?)?;
+    /// }
+    /// let size = std::fs::metadata(&hum)?.len();
+    ///
+    /// // Imports running at once can't both take the room left
+    /// let (first, second) = tokio::join!(vault.import_file(&hum, None), vault.import_file(&buzz, None));
+    /// assert!(first.is_ok() != second.is_ok());
+    ///
+    /// match vault.import_file(&hum, None).await {
+    ///     Err(VaultError::QuotaExceeded { needed, available }) => assert_eq!((needed, available), (size, 10_000 - size)),
+    ///     other => panic!("{:?}", other),
+    /// }
+    ///
+    /// let usage = vault.quota_usage().await?;
+    /// assert_eq!((usage.used, usage.reserved, usage.available()), (size, 0, Some(10_000 - size)));
+    /// assert_eq!(vault.health().await?.quota, usage);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn quota_usage(&self) -> Result<QuotaUsage> {
+        self.local.quota_usage().await
+    }
+
+    /// Compute what [`SoundVault::gc_artifacts`] would evict, without
+    /// removing anything
+    ///
+    /// The plan can be shown for confirmation, then carried out with
+    /// [`SoundVault::apply_gc_artifacts`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundVault, VaultConfig, VaultError, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let mut ids = Vec::new();
+    /// for name in ["hum.wav", "buzz.wav"] {
+    ///     let file = dir.path().join(name);
+    ///     std::fs::write(&file, encode(&info, &vec![0.25; 8000])?)?;
+    ///     ids.push(vault.import_file(&file, None).await?);
+    /// }
+    ///
+    /// // The preview of a deleted sound is evicted
+    /// let preview = vault.generate_preview(&ids[0]).await?;
+    /// vault.delete_sound(&ids[0]).await?;
+    /// let plan = vault.plan_gc_artifacts().await?;
+    /// assert_eq!(plan.evictions.len(), 1);
+    /// assert_eq!(plan.bytes_reclaimed, std::fs::metadata(&preview)?.len());
+    /// assert!(preview.exists());
+    ///
+    /// // Another preview was generated meanwhile
+    /// vault.generate_preview(&ids[1]).await?;
+    /// let result = vault.apply_gc_artifacts(&plan).await;
+    /// assert!(matches!(result, Err(VaultError::PlanOutdated { .. })));
+    /// assert!(preview.exists());
+    ///
+    /// let plan = vault.plan_gc_artifacts().await?;
+    /// let report = vault.apply_gc_artifacts(&plan).await?;
+    /// assert_eq!(report.evicted, vec![preview.clone()]);
+    /// assert!(!preview.exists());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn plan_gc_artifacts(&self) -> Result<GcPlan> {
+        self.local.plan_gc_artifacts().await
+    }
+
+    /// Evict exactly the files of a plan from [`SoundVault::plan_gc_artifacts`]
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PlanOutdated` if generated files were written, served
+    ///   or removed, or their sounds deleted, since the plan was made;
+    ///   nothing is evicted
+    pub async fn apply_gc_artifacts(&self, plan: &GcPlan) -> Result<GcReport> {
+        self.local.authorize(Role::Editor, "apply_gc_artifacts", "").await?;
+        self.local.apply_gc_artifacts(plan).await
+    }
+
+    /// Create a processed copy of a sound as a new sound
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the parent sound
+    /// * `ops` - Processing steps, applied in order
+    ///
+    /// # Returns
+    ///
+    /// ID of the derivative
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioOp, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault, id: &str) -> soundvault::Result<()> {
+    /// let slow = vault.create_derivative(id, &[AudioOp::SpeedChange(0.5), AudioOp::FadeOut(200)]).await?;
+    /// assert_eq!(vault.get_sound(&slow).await?.metadata.derived_from.as_deref(), Some(id));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_derivative(&self, id: &str, ops: &[AudioOp]) -> Result<String> {
+        self.local.authorize(Role::Editor, "create_derivative", id).await?;
+        self.local.create_derivative(id, ops).await
+    }
+
+    /// List the IDs of the sounds derived from a sound
+    pub async fn derivatives(&self, id: &str) -> Result<Vec<String>> {
+        self.local.derivatives(id).await
+    }
+
+    /// Regions of a sound each slice of [`SoundVault::slice`] would play
+    ///
+    /// Lets a UI preview the cuts before committing to them.
+    ///
+    /// # Returns
+    ///
+    /// Start and end of each slice, in seconds of the sound's file
+    pub async fn plan_slices(&self, id: &str, strategy: SliceStrategy) -> Result<Vec<Trim>> {
+        self.local.plan_slices(id, strategy).await
+    }
+
+    /// Cut a loop into one-shots, each a new sound derived from it
+    ///
+    /// Slices play regions of the parent's file through trim markers, and
+    /// keep the parent from being deleted while they do; see
+    /// [`SoundVault::slice_with_options`] to write them to files instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the loop
+    /// * `strategy` - Where to cut; [`SliceStrategy::EveryNBeats`] needs a
+    ///   `bpm` descriptor or custom value
+    ///
+    /// # Returns
+    ///
+    /// IDs of the slices, in order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     AudioFormat, AudioInfo, MetadataPatch, SLICE_INDEX_KEY, SampleFormat, SliceOptions, SliceStrategy, SoundVault,
+    ///     VaultConfig, VaultError, encode,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let sources = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// // Two seconds at 120 bpm: four beats
+    /// let info = AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16));
+    /// let file = sources.path().join("loop.wav");
+    /// std::fs::write(&file, encode(&info, &vec![0.25; 16000])?)?;
+    /// let id = vault.import_file(&file, None).await?;
+    /// let mut patch = MetadataPatch::default();
+    /// patch.set_custom.insert("bpm".to_string(), "120".to_string());
+    /// vault.patch_metadata(&id, patch).await?;
+    ///
+    /// let cuts = vault.plan_slices(&id, SliceStrategy::EveryNBeats(1.0)).await?;
+    /// assert_eq!(cuts.iter().map(|trim| trim.start).collect::<Vec<_>>(), vec![0.0, 0.5, 1.0, 1.5]);
+    ///
+    /// let slices = vault.slice(&id, SliceStrategy::EveryNBeats(1.0)).await?;
+    /// let third = vault.get_sound(&slices[2]).await?.metadata;
+    /// assert!(third.tags.contains(&"slice".to_string()));
+    /// assert_eq!(third.get_custom(SLICE_INDEX_KEY).map(String::as_str), Some("3"));
+    /// assert_eq!(third.derived_from.as_deref(), Some(id.as_str()));
+    ///
+    /// // The slices play the loop's file, so it stays
+    /// assert!(matches!(vault.delete_sound(&id).await, Err(VaultError::InUse { .. })));
+    ///
+    /// // Or as files of their own
+    /// let options = SliceOptions { materialize: true };
+    /// let halves = vault.slice_with_options(&id, SliceStrategy::FixedCount(2), &options).await?;
+    /// assert_eq!(vault.get_sound(&halves[1]).await?.metadata.duration, 1.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn slice(&self, id: &str, strategy: SliceStrategy) -> Result<Vec<String>> {
+        self.slice_with_options(id, strategy, &SliceOptions::default()).await
+    }
+
+    /// Cut a loop into one-shots like [`SoundVault::slice`]
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the loop is archived or in a blob
+    ///   store and `options.materialize` isn't set, or has no `bpm` to slice
+    ///   by beats
+    pub async fn slice_with_options(&self, id: &str, strategy: SliceStrategy, options: &SliceOptions) -> Result<Vec<String>> {
+        self.local.authorize(Role::Editor, "slice", id).await?;
+        self.local.slice(id, strategy, options).await
+    }
+
+    /// Make one mono WAV sound of each channel of a sound
+    ///
+    /// See [`SoundVault::split_channels_with_options`].
+    ///
+    /// # Returns
+    ///
+    /// IDs of the new sounds, in channel order
+    pub async fn split_channels(&self, id: &str) -> Result<Vec<String>> {
+        let assets = self.split_channels_with_options(id, ChannelOptions::default()).await?;
+        Ok(assets.into_iter().map(|asset| asset.id).collect())
+    }
+
+    /// Make one mono sound of each channel of a sound
+    ///
+    /// The new sounds are named `<name>.L` and `<name>.R` for a stereo sound
+    /// and `<name>.ch1` to `<name>.chN` for other layouts. Like
+    /// [`SoundVault::create_derivative`], they keep the sound's description,
+    /// license and tags, get the `derivative` tag and record it in
+    /// `derived_from`. The sound is decoded and the files written a chunk at
+    /// a time on the background job queue.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound has a single channel or
+    ///   its file is compressed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     AudioFormat, AudioInfo, ChannelFormat, ChannelOptions, SampleFormat, SoundMetadata, SoundVault, VaultConfig,
+    ///     decode, encode,
+    /// };
+    /// use std::io::Cursor;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let sources = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let path = sources.path().join("field.wav");
+    /// let frames: Vec<f32> = (0..8000).flat_map(|_| [0.5, -0.25]).collect();
+    /// std::fs::write(&path, encode(&AudioInfo::new(AudioFormat::Wav, 2, 8000, SampleFormat::Int(16)), &frames)?)?;
+    /// let metadata = SoundMetadata { name: "Field".to_string(), tags: vec!["forest".to_string()], ..Default::default() };
+    /// let id = vault.import_file(&path, Some(metadata)).await?;
+    ///
+    /// let sides = vault.split_channels(&id).await?;
+    /// let right = vault.get_sound(&sides[1]).await?.metadata;
+    /// assert_eq!(right.name, "Field.R");
+    /// assert_eq!((right.channels, right.derived_from.as_deref()), (Some(1), Some(id.as_str())));
+    /// assert!(right.tags.contains(&"forest".to_string()));
+    /// let (info, samples) = decode(&mut Cursor::new(vault.open_sound(&sides[1]).await?))?;
+    /// assert_eq!((info.channels, samples.len()), (1, 8000));
+    /// assert!(samples.iter().all(|&sample| sample == -0.25));
+    ///
+    /// let options = ChannelOptions { format: ChannelFormat::Flac };
+    /// let mono = vault.fold_to_mono_with_options(&id, options).await?;
+    /// assert_eq!((mono.name.as_str(), mono.duration), ("Field (mono)", 1.0));
+    /// assert!(mono.file_size > 0 && vault.open_sound(&mono.id).await?.starts_with(b"fLaC"));
+    ///
+    /// // A mono sound has nothing to split
+    /// assert!(vault.split_channels(&sides[0]).await.is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn split_channels_with_options(&self, id: &str, options: ChannelOptions) -> Result<Vec<ChannelAsset>> {
+        self.local.authorize(Role::Editor, "split_channels", id).await?;
+        self.local.split_channels(id, &options).await
+    }
+
+    /// Make a mono WAV sound averaging the channels of a sound
+    ///
+    /// See [`SoundVault::fold_to_mono_with_options`].
+    ///
+    /// # Returns
+    ///
+    /// ID of the new sound
+    pub async fn fold_to_mono(&self, id: &str) -> Result<String> {
+        Ok(self.fold_to_mono_with_options(id, ChannelOptions::default()).await?.id)
+    }
+
+    /// Make a mono sound averaging the channels of a sound
+    ///
+    /// The new sound is named `<name> (mono)` and linked to the sound as
+    /// [`SoundVault::split_channels_with_options`] describes, which has an
+    /// example.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the sound has a single channel or
+    ///   its file is compressed
+    pub async fn fold_to_mono_with_options(&self, id: &str, options: ChannelOptions) -> Result<ChannelAsset> {
+        self.local.authorize(Role::Editor, "fold_to_mono", id).await?;
+        self.local.fold_to_mono(id, &options).await
+    }
+
+    /// UUID identifying this vault, generated when the vault was created
+    pub fn vault_id(&self) -> &str {
+        &self.local.vault_id
+    }
+
+    /// Get a `soundvault://` URI referencing a sound
+    ///
+    /// The URI stays valid when the sound's file is moved or renamed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::SoundVault;
+    ///
+    /// # async fn example(vault: SoundVault, id: &str) -> soundvault::Result<()> {
+    /// let uri = vault.uri_for(id);
+    /// assert_eq!(vault.resolve_uri(&uri).await?.metadata.id, id);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn uri_for(&self, id: &str) -> String {
+        self.local.uri_for(id)
+    }
+
+    /// Get a URI referencing a sound that also carries its content hash
+    ///
+    /// Such URIs can be resolved in other vaults with
+    /// [`resolve_uri_by_hash`](Self::resolve_uri_by_hash).
+    pub async fn portable_uri_for(&self, id: &str) -> Result<String> {
+        self.local.portable_uri_for(id).await
+    }
+
+    /// Get a `soundvault://` URI referencing a collection
+    pub fn collection_uri_for(&self, id: &str) -> String {
+        self.local.collection_uri_for(id)
+    }
+
+    /// Find the sound a URI references
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if the URI belongs to another vault or the sound
+    ///   doesn't exist
+    /// * `VaultError::InvalidOperation` if the URI is malformed or not a sound URI
+    pub async fn resolve_uri(&self, uri: &str) -> Result<Sound> {
+        self.local.resolve_uri(uri, false).await
+    }
+
+    /// Find the sound a URI references, falling back to its content hash
+    ///
+    /// A URI of another vault, or of a deleted sound, resolves to a sound with
+    /// the same content when it carries a hash.
+    pub async fn resolve_uri_by_hash(&self, uri: &str) -> Result<Sound> {
+        self.local.resolve_uri(uri, true).await
+    }
+
+    /// Get the path of the file of the sound a URI references
+    ///
+    /// Archived sounds must be decompressed first.
+    pub async fn resolve_uri_to_path(&self, uri: &str) -> Result<PathBuf> {
+        self.local.resolve_uri_to_path(uri, false).await
+    }
+
+    /// Find the collection a URI references
+    pub async fn resolve_collection_uri(&self, uri: &str) -> Result<Collection> {
+        self.local.resolve_collection_uri(uri).await
+    }
+
+    /// Create a new collection
+    ///
+    /// # Returns
+    ///
+    /// The ID of the created collection
+    pub async fn add_collection(&self, collection: &Collection) -> Result<String> {
+        self.local.authorize(Role::Editor, "add_collection", "").await?;
+        self.local.add_collection(collection).await
+    }
+
+    /// Get a collection by ID
+    pub async fn get_collection(&self, collection_id: &str) -> Result<Collection> {
+        self.local.get_collection(collection_id).await
+    }
+
+    /// List collections by name, session collections only if
+    /// `include_sessions`
+    pub async fn list_collections(&self, include_sessions: bool) -> Result<Vec<Collection>> {
+        self.local.list_collections(include_sessions).await
+    }
+
+    /// Create a scratch collection for a working session, e.g. candidates
+    /// for a mix, expiring `ttl` from now
+    ///
+    /// Session collections are left out of
+    /// [`list_collections`](Self::list_collections) unless asked for. Once
+    /// expired, they're removed after the vault is opened (see
+    /// [`warm_up`](Self::warm_up)) or by
+    /// [`purge_expired_sessions`](Self::purge_expired_sessions); their
+    /// sounds are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, SoundVault, VaultConfig, VaultEvent};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("whoosh.wav");
+    /// std::fs::write(&file, b"whoosh")?;
+    /// let id = vault.import_file(&file, None).await?;
+    ///
+    /// vault.add_collection(&Collection::new("Transitions", "")).await?;
+    /// let keep = vault.create_session_collection("Mix candidates", Duration::ZERO).await?;
+    /// let scratch = vault.create_session_collection("Maybe", Duration::ZERO).await?;
+    /// vault.add_sound_to_collection(&id, &scratch).await?;
+    /// assert_eq!(vault.list_collections(false).await?.len(), 1);
+    /// assert_eq!(vault.list_collections(true).await?.len(), 3);
+    ///
+    /// vault.persist_session_collection(&keep).await?;
+    /// assert!(vault.get_collection(&keep).await?.expires_at.is_none());
+    ///
+    /// let mut events = vault.subscribe();
+    /// assert_eq!(vault.purge_expired_sessions().await?, 1);
+    /// assert_eq!(events.recv().await?, VaultEvent::SessionExpired { collection_id: scratch.clone() });
+    /// assert!(vault.get_collection(&scratch).await.is_err());
+    /// assert!(vault.get_sound(&id).await.is_ok());
+    /// assert_eq!(vault.list_collections(true).await?.len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_session_collection(&self, name: &str, ttl: Duration) -> Result<String> {
+        self.local.authorize(Role::Editor, "create_session_collection", "").await?;
+        self.local.create_session_collection(name, ttl).await
+    }
+
+    /// Make a session collection permanent
+    pub async fn persist_session_collection(&self, collection_id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "persist_session_collection", collection_id).await?;
+        self.local.persist_session_collection(collection_id).await
+    }
+
+    /// Remove the session collections that expired, keeping their sounds
+    ///
+    /// Subscribers get a [`VaultEvent::SessionExpired`] for each.
+    ///
+    /// # Returns
+    ///
+    /// The number of collections removed
+    pub async fn purge_expired_sessions(&self) -> Result<u64> {
+        self.local.authorize(Role::Editor, "purge_expired_sessions", "").await?;
+        self.local.purge_expired_sessions().await
+    }
+
+    /// Add a sound to a collection, applying the collection's defaults if enabled
+    pub async fn add_sound_to_collection(&self, sound_id: &str, collection_id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "add_sound_to_collection", collection_id).await?;
+        self.local.add_sound_to_collection(sound_id, collection_id).await
+    }
+
+    /// Add a sound to several collections in one transaction
+    ///
+    /// Nothing is added if the sound or one of the collections doesn't
+    /// exist. Subscribers get a [`VaultEvent::CollectionChanged`] for each
+    /// collection the sound joined.
+    ///
+    /// # Returns
+    ///
+    /// Number of collections the sound wasn't in yet
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("gong.wav");
+    /// std::fs::write(&file, b"gong")?;
+    /// let id = vault.import_file(&file, None).await?;
+    ///
+    /// let metal = vault.add_collection(&Collection::new("Metal", "")).await?;
+    /// let temple = vault.add_collection(&Collection::new("Temple", "")).await?;
+    /// vault.add_sound_to_collection(&id, &metal).await?;
+    ///
+    /// assert_eq!(vault.add_sound_to_collections(&id, &[metal, temple]).await?, 1);
+    /// let names: Vec<String> = vault.collections_containing(&id).await?.into_iter().map(|c| c.name).collect();
+    /// assert_eq!(names, vec!["Metal", "Temple"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn add_sound_to_collections(&self, sound_id: &str, collection_ids: &[String]) -> Result<usize> {
+        self.local.authorize(Role::Editor, "add_sound_to_collections", sound_id).await?;
+        self.local.add_sound_to_collections(sound_id, collection_ids).await
+    }
+
+    /// Collections a sound is in, ordered by name
+    pub async fn collections_containing(&self, sound_id: &str) -> Result<Vec<CollectionSummary>> {
+        self.local.collections_containing(sound_id).await
+    }
+
+    /// Remove a sound from a collection
+    ///
+    /// Metadata the sound inherited from the collection is kept.
+    pub async fn remove_sound_from_collection(&self, sound_id: &str, collection_id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "remove_sound_from_collection", collection_id).await?;
+        self.local.remove_sound_from_collection(sound_id, collection_id).await
+    }
+
+    /// Put the sounds of a collection in a new order
+    ///
+    /// `sound_ids` must list every member exactly once. The order is written
+    /// in one transaction, and only if the collection is still at
+    /// `expected_version`, the [`Collection::version`] it was read at: two
+    /// clients reordering the same collection can't interleave their writes.
+    ///
+    /// # Returns
+    ///
+    /// The new version of the collection
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::VersionConflict` if the collection changed since it was
+    ///   read; read it again and retry
+    /// * `VaultError::InvalidOperation` if `sound_ids` aren't the members
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// let mut ids = Vec::new();
+    /// for name in ["kick", "snare", "hat"] {
+    ///     std::fs::write(sources.path().join(name), name)?;
+    ///     ids.push(vault.import_file(sources.path().join(name), None).await?);
+    /// }
+    /// let mut kit = Collection::new("Kit", "");
+    /// kit.sound_ids = ids.clone();
+    /// let kit = vault.add_collection(&kit).await?;
+    ///
+    /// let read = vault.get_collection(&kit).await?;
+    /// let order = vec![ids[2].clone(), ids[0].clone(), ids[1].clone()];
+    /// vault.reorder_collection(&kit, &order, read.version).await?;
+    /// assert_eq!(vault.get_collection(&kit).await?.sound_ids, order);
+    ///
+    /// // Another client still holds the order it read before
+    /// let stale = vault.move_in_collection(&kit, &ids[1], 0, read.version).await;
+    /// assert!(matches!(stale, Err(VaultError::VersionConflict { .. })));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn reorder_collection(&self, collection_id: &str, sound_ids: &[String], expected_version: i64) -> Result<i64> {
+        self.local.authorize(Role::Editor, "reorder_collection", collection_id).await?;
+        self.local.reorder_collection(collection_id, sound_ids, expected_version).await
+    }
+
+    /// Move a sound of a collection to `index`, shifting the sounds between,
+    /// like [`SoundVault::reorder_collection`]
+    ///
+    /// An index past the end moves the sound last.
+    ///
+    /// # Returns
+    ///
+    /// The new version of the collection
+    pub async fn move_in_collection(&self, collection_id: &str, sound_id: &str, index: usize, expected_version: i64) -> Result<i64> {
+        self.local.authorize(Role::Editor, "move_in_collection", collection_id).await?;
+        self.local.move_in_collection(collection_id, sound_id, index, expected_version).await
+    }
+
+    /// Number the sounds of a collection again from 0 if their positions
+    /// have gaps or duplicates, keeping their order
+    ///
+    /// Reads already return such a collection in a stable order and fix it
+    /// in the background; [`SoundVault::repair`] fixes every collection.
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds whose position changed
+    pub async fn normalize_collection_positions(&self, collection_id: &str) -> Result<u64> {
+        self.local.authorize(Role::Editor, "normalize_collection_positions", collection_id).await?;
+        self.local.normalize_collection_positions(collection_id).await
+    }
+
+    /// Set the metadata a collection hands down to its sounds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, CollectionDefaults, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// let collection_id = vault.add_collection(&Collection::new("Ice Cave Ambience", "")).await?;
+    ///
+    /// let mut defaults = CollectionDefaults::default();
+    /// defaults.apply_on_add = true;
+    /// defaults.tags = vec!["ambience".to_string(), "ice".to_string()];
+    /// defaults.set_custom("project", "Frozen");
+    /// vault.set_collection_defaults(&collection_id, &defaults).await?;
+    ///
+    /// // Bring sounds added before the defaults existed up to date
+    /// vault.apply_collection_defaults(&collection_id).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_collection_defaults(&self, collection_id: &str, defaults: &CollectionDefaults) -> Result<()> {
+        self.local.authorize(Role::Editor, "set_collection_defaults", collection_id).await?;
+        self.local.set_collection_defaults(collection_id, defaults).await
+    }
+
+    /// Apply a collection's default metadata to all of its current sounds
+    ///
+    /// # Returns
+    ///
+    /// The number of sounds whose metadata changed
+    pub async fn apply_collection_defaults(&self, collection_id: &str) -> Result<usize> {
+        self.local.authorize(Role::Editor, "apply_collection_defaults", collection_id).await?;
+        let apply = self.local.apply_collection_defaults(collection_id);
+        Ok(self.local.in_operation("apply_collection_defaults", apply).await?.0)
+    }
+
+    /// Create a group of sounds, such as the variations of a footstep
+    ///
+    /// Groups are independent of collections. Numbered variations can also
+    /// be grouped while importing, with [`ImportOptions::group_variations`].
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the group
+    /// * `kind` - How its members are played
+    /// * `sound_ids` - Its members, in order
+    ///
+    /// # Returns
+    ///
+    /// The ID of the group
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{GroupKind, ImportOptions, SoundFilter, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let options = ImportOptions { group_variations: Some(GroupKind::RoundRobin), ..Default::default() };
+    /// for file in ["step_grass_03.wav", "step_grass_01.wav", "step_grass_02.wav", "ambience.wav"] {
+    ///     let path = dir.path().join(file);
+    ///     std::fs::write(&path, file)?;
+    ///     vault.import_file_with_options(&path, None, options.clone()).await?;
+    /// }
+    ///
+    /// let groups = vault.list_groups().await?;
+    /// assert_eq!(groups.len(), 1);
+    /// assert_eq!((groups[0].name.as_str(), groups[0].kind), ("step_grass", GroupKind::RoundRobin));
+    /// let members = vault.get_group_members(&groups[0].id).await?;
+    /// let names: Vec<_> = members.iter().map(|sound| sound.metadata.name.as_str()).collect();
+    /// assert_eq!(names, ["step_grass_01.wav", "step_grass_02.wav", "step_grass_03.wav"]);
+    ///
+    /// // Each variation once per round, never the same twice in a row
+    /// let mut picker = vault.group_picker(&groups[0].id, Some(7)).await?;
+    /// let picks: Vec<String> = (0..9).map(|_| picker.pick().unwrap().to_string()).collect();
+    /// for round in picks.chunks(3) {
+    ///     assert!(members.iter().all(|sound| round.contains(&sound.metadata.id)));
+    /// }
+    /// assert!(picks.windows(2).all(|pair| pair[0] != pair[1]));
+    ///
+    /// // Layers of a gunshot, in a group of their own
+    /// let layers = vault.create_group("rifle", GroupKind::LayerSet, &[members[0].metadata.id.clone()]).await?;
+    /// assert_eq!(vault.list_groups().await?.len(), 2);
+    /// let filter = SoundFilter { group_id: Some(layers), ..Default::default() };
+    /// assert_eq!(vault.count(&filter).await?, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_group(&self, name: &str, kind: GroupKind, sound_ids: &[String]) -> Result<String> {
+        self.local.authorize(Role::Editor, "create_group", "").await?;
+        self.local.create_group(name, kind, sound_ids).await
+    }
+
+    /// Get a group with the IDs of its members
+    pub async fn get_group(&self, group_id: &str) -> Result<SoundGroup> {
+        self.local.get_group(group_id).await
+    }
+
+    /// List every group with the IDs of its members, by name
+    pub async fn list_groups(&self) -> Result<Vec<SoundGroup>> {
+        self.local.list_groups().await
+    }
+
+    /// Get the sounds of a group, in order
+    pub async fn get_group_members(&self, group_id: &str) -> Result<Vec<Sound>> {
+        self.local.get_group_members(group_id).await
+    }
+
+    /// Delete a group; its sounds are kept
+    pub async fn delete_group(&self, group_id: &str) -> Result<()> {
+        self.local.authorize(Role::Editor, "delete_group", group_id).await?;
+        self.local.delete_group(group_id).await
+    }
+
+    /// Pick the members of a group at random, without repeats
+    ///
+    /// `None` picks a fresh seed, available from [`GroupPicker::seed`]. To
+    /// go through a group once in a random order, iterate with a
+    /// [`SoundFilter::group_id`] filter and [`SoundCursor::shuffled`].
+    pub async fn group_picker(&self, group_id: &str, seed: Option<u64>) -> Result<GroupPicker> {
+        self.local.group_picker(group_id, seed).await
+    }
+
+    /// Generate a manifest of the sounds in the given collections
+    ///
+    /// The manifest maps sound IDs to their file path relative to the library,
+    /// content hash, duration, format and license, and is meant to be committed
+    /// next to the assets of a build. Expired sounds are left out and listed
+    /// in [`Manifest::expired`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ManifestFormat, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault, collection_id: String) -> soundvault::Result<()> {
+    /// let manifest = vault.generate_manifest(&[collection_id], ManifestFormat::Toml).await?;
+    /// std::fs::write("sounds.toml", &manifest)?;
+    ///
+    /// // Later, in CI
+    /// let diff = vault.verify_manifest(&std::fs::read_to_string("sounds.toml")?).await?;
+    /// assert!(diff.is_clean(), "shipped sounds changed: {:?}", diff);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn generate_manifest(&self, collection_ids: &[String], format: ManifestFormat) -> Result<String> {
+        self.local.build_manifest(collection_ids).await?.to_string(format)
+    }
+
+    /// Check that every sound referenced by a manifest still exists with a matching hash
+    ///
+    /// # Arguments
+    ///
+    /// * `manifest` - Manifest text, in JSON or TOML
+    pub async fn verify_manifest(&self, manifest: &str) -> Result<ManifestDiff> {
+        self.verify_manifest_with_context(manifest, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::verify_manifest`], stopped by `context` between sounds
+    pub async fn verify_manifest_with_context(&self, manifest: &str, context: &OpContext) -> Result<ManifestDiff> {
+        let manifest = Manifest::parse(manifest)?;
+        self.local.verify_manifest(&manifest, context).await
+    }
+
+    /// Compare the database with the files in the library directory
+    ///
+    /// Reports sounds whose file is missing and files that no sound refers to.
+    /// Files are written under a temporary `.tmp` name and renamed once
+    /// complete; temporary files left over an hour by an interrupted write
+    /// are deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundFilter, SoundVault, VaultConfig};
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// // An import interrupted mid-copy, long ago, and one still running
+    /// std::fs::create_dir(dir.path().join("a1"))?;
+    /// let abandoned = dir.path().join("a1").join("kick.wav.tmp");
+    /// std::fs::write(&abandoned, b"RIFF\x24\x00")?;
+    /// std::fs::File::options()
+    ///     .write(true)
+    ///     .open(&abandoned)?
+    ///     .set_modified(SystemTime::now() - Duration::from_secs(7200))?;
+    /// std::fs::write(dir.path().join("a1").join("snare.wav.tmp"), b"RIFF")?;
+    ///
+    /// let report = vault.scan_integrity().await?;
+    /// assert_eq!(report.removed_temp_files, vec![std::path::Path::new("a1/kick.wav.tmp")]);
+    /// assert!(report.is_clean());
+    /// assert!(!abandoned.exists());
+    /// assert_eq!(vault.count(&SoundFilter::default()).await?, 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scan_integrity(&self) -> Result<IntegrityReport> {
+        self.scan_integrity_with_context(&OpContext::default()).await
+    }
+
+    /// [`SoundVault::scan_integrity`], stopped by `context` between sounds
+    ///
+    /// The findings of a stopped scan aren't kept for health reports.
+    pub async fn scan_integrity_with_context(&self, context: &OpContext) -> Result<IntegrityReport> {
+        self.local.scan_integrity(context).await
+    }
+
+    /// List the sounds whose file's extension disagrees with the format read
+    /// from its header, e.g. AIFF files named `.wav`
+    ///
+    /// Integrity scans report them too. With
+    /// [`VaultConfig::fix_extensions`], imported files are stored under the
+    /// right extension and [`repair`](Self::repair) renames those already
+    /// stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, FileFormat, RepairPolicy, SampleFormat, SoundVault, VaultConfig, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let aiff = encode(&AudioInfo::new(AudioFormat::Aiff, 1, 8000, SampleFormat::Int(16)), &[0.0; 800])?;
+    /// let path = dir.path().join("hit.wav");
+    /// std::fs::write(&path, aiff)?;
+    ///
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library.clone(), None)).await?;
+    /// let id = vault.import_file(&path, None).await?;
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.format, Some(FileFormat::Aiff));
+    ///
+    /// let mismatches = vault.list_extension_mismatches().await?;
+    /// assert_eq!(mismatches[0].format, FileFormat::Aiff);
+    /// assert_eq!(vault.scan_integrity().await?.extension_mismatches, mismatches);
+    ///
+    /// // Exports into a directory are named after the true format
+    /// vault.export_sound(&id, dir.path(), Default::default()).await?;
+    /// assert!(dir.path().join("hit.aiff").exists());
+    ///
+    /// // Rename the stored file
+    /// drop(vault);
+    /// let mut config = VaultConfig::new(library, None);
+    /// config.fix_extensions = true;
+    /// let vault = SoundVault::new(config).await?;
+    /// let report = vault.repair(RepairPolicy::ReportOnly).await?;
+    /// assert_eq!(report.fixed_extensions, vec![id.clone()]);
+    /// let stored = vault.get_sound(&id).await?.metadata.path.unwrap();
+    /// assert_eq!(stored.extension().unwrap(), "aiff");
+    /// assert!(vault.list_extension_mismatches().await?.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn list_extension_mismatches(&self) -> Result<Vec<ExtensionMismatch>> {
+        self.local.list_extension_mismatches().await
+    }
+
+    /// Re-hash the files of the sounds verified longest ago, to catch files
+    /// rotting on disk
+    ///
+    /// Files that no longer match the hash recorded when they were stored
+    /// are flagged corrupt, and a [`VaultEvent::SoundCorrupted`] is sent the
+    /// first time. Unlike the background scrubber run every
+    /// [`VaultConfig::scrub_interval`], this doesn't wait for imports or
+    /// downloads under way.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Most sounds checked
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, SampleFormat, SoundVault, VaultConfig, encode};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let path = dir.path().join("hit.wav");
+    /// std::fs::write(&path, encode(&AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16)), &[0.25; 800])?)?;
+    ///
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    /// let id = vault.import_file(&path, None).await?;
+    /// assert!(vault.scrub_now(10).await?.corrupt.is_empty());
+    ///
+    /// // Flip a byte of the stored file
+    /// let stored = vault.get_sound(&id).await?.metadata.path.unwrap();
+    /// let mut bytes = std::fs::read(&stored)?;
+    /// *bytes.last_mut().unwrap() ^= 0xff;
+    /// std::fs::write(&stored, bytes)?;
+    ///
+    /// let report = vault.scrub_now(10).await?;
+    /// assert_eq!((report.checked, report.corrupt), (1, vec![id.clone()]));
+    /// assert_eq!(vault.list_corrupt_sounds().await?, vec![id]);
+    /// assert_eq!(vault.health().await?.corrupt_sounds, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scrub_now(&self, limit: u64) -> Result<ScrubReport> {
+        self.local.authorize(Role::Editor, "scrub_now", "").await?;
+        self.local.scrub(limit, false).await
+    }
+
+    /// IDs of the sounds flagged corrupt by the last scrub of their file
+    pub async fn list_corrupt_sounds(&self) -> Result<Vec<String>> {
+        self.local.list_corrupt_sounds().await
+    }
+
+    /// Maintain the database: purge expired sessions, remove leftover rows,
+    /// vacuum, analyze and checkpoint, as `tasks` selects
+    ///
+    /// Unlike the background maintenance set by
+    /// [`VaultConfig::auto_maintenance`], this runs at once, even while
+    /// imports are under way; a full vacuum holds the writer until it's
+    /// done. Vacuuming applies to the whole database file, including the
+    /// host application's tables when the vault was opened
+    /// [`with_pool`](Self::with_pool).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{MaintenanceTask, MaintenanceTasks, SoundVault, VaultConfig};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// for i in 0..50 {
+    ///     vault.create_session_collection(&format!("{} {}", i, "take ".repeat(400)), Duration::ZERO).await?;
+    /// }
+    ///
+    /// let report = vault.maintenance(MaintenanceTasks::all()).await?;
+    /// assert_eq!(report.sessions_purged, 50);
+    /// assert!(report.reclaimed_bytes > 0 && report.size_after < report.size_before);
+    /// assert_eq!(report.timings.last().map(|timing| timing.task), Some(MaintenanceTask::Checkpoint));
+    /// assert!(report.wal_checkpointed);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    pub async fn maintenance(&self, tasks: MaintenanceTasks) -> Result<MaintenanceReport> {
+        self.local.authorize(Role::Admin, "maintenance", "").await?;
+        self.local.maintenance(tasks).await
+    }
+
+    /// Scan the library and fix what the policy allows
+    ///
+    /// With [`RepairPolicy::Quarantine`], orphan files are moved to
+    /// `library_path/.quarantine/<date>/` under their former relative path, and
+    /// collections whose order has gaps or duplicates are numbered again. The
+    /// database file, its backups and generated previews are never touched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{RepairPolicy, SoundVault};
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// let report = vault.repair(RepairPolicy::Quarantine).await?;
+    /// println!("{} files quarantined", report.quarantined.len());
+    ///
+    /// // Bring back a file that was wanted after all
+    /// for file in vault.list_quarantine()? {
+    ///     if file.original_path.ends_with("keeper.wav") {
+    ///         vault.restore_from_quarantine(&file.path).await?;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn repair(&self, policy: RepairPolicy) -> Result<IntegrityReport> {
+        self.repair_with_context(policy, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::repair`], stopped by `context` between files
+    ///
+    /// Files quarantined before the repair stopped stay in quarantine.
+    pub async fn repair_with_context(&self, policy: RepairPolicy, context: &OpContext) -> Result<IntegrityReport> {
+        self.local.authorize(Role::Admin, "repair", "").await?;
+        self.local.repair(policy, context).await
+    }
+
+    /// Compute what [`SoundVault::repair`] would do, without changing
+    /// anything
+    ///
+    /// The plan lists the orphan files to quarantine and the abandoned
+    /// temporary files to delete, and can be carried out with
+    /// [`SoundVault::apply_repair`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{RepairPolicy, SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// std::fs::write(dir.path().join("stray.wav"), b"RIFF")?;
+    ///
+    /// let plan = vault.plan_repair(RepairPolicy::Quarantine).await?;
+    /// assert_eq!(plan.quarantine.len(), 1);
+    /// assert!(dir.path().join("stray.wav").exists());
+    /// let json = serde_json::to_string(&plan)?;
+    ///
+    /// // Another stray file appeared since
+    /// std::fs::write(dir.path().join("other.wav"), b"RIFF")?;
+    /// let result = vault.apply_repair(&serde_json::from_str(&json)?).await;
+    /// assert!(matches!(result, Err(VaultError::PlanOutdated { .. })));
+    ///
+    /// std::fs::remove_file(dir.path().join("other.wav"))?;
+    /// let report = vault.apply_repair(&serde_json::from_str(&json)?).await?;
+    /// assert_eq!(report.quarantined.len(), 1);
+    /// assert!(!dir.path().join("stray.wav").exists());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn plan_repair(&self, policy: RepairPolicy) -> Result<RepairPlan> {
+        self.local.plan_repair(policy).await
+    }
+
+    /// Carry out exactly the deletions and moves of a plan from
+    /// [`SoundVault::plan_repair`]
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PlanOutdated` if sounds or library files changed since
+    ///   the plan was made; nothing is changed
+    pub async fn apply_repair(&self, plan: &RepairPlan) -> Result<IntegrityReport> {
+        self.local.authorize(Role::Admin, "apply_repair", "").await?;
+        self.local.apply_repair(plan, &OpContext::default()).await
+    }
+
+    /// List the files held in quarantine
+    pub fn list_quarantine(&self) -> Result<Vec<QuarantinedFile>> {
+        self.local.list_quarantine()
+    }
+
+    /// Import a quarantined file as a new sound
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file, absolute or relative to the quarantine directory
+    ///
+    /// # Returns
+    ///
+    /// The ID of the imported sound
+    pub async fn restore_from_quarantine(&self, path: &Path) -> Result<String> {
+        self.local.authorize(Role::Editor, "restore_from_quarantine", "").await?;
+        self.local.restore_from_quarantine(path).await
+    }
+
+    /// Create a collection that follows a Freesound search
+    ///
+    /// Call [`SoundVault::sync_subscriptions`] to fetch matching sounds.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the collection
+    /// * `query` - Search text
+    /// * `filter` - Optional filter in Freesound's filter syntax
+    /// * `auto_download` - Download new sounds; otherwise they are tracked as uncached remote members
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::SoundVault;
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// vault
+    ///     .create_remote_subscription("New CC0 rain", "rain", Some("license:\"Creative Commons 0\""), false)
+    ///     .await?;
+    ///
+    /// let report = vault.sync_subscriptions().await?;
+    /// println!("{} new sounds, {} API requests", report.added(), report.api_requests());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_remote_subscription(
+        &self,
+        name: &str,
+        query: &str,
+        filter: Option<&str>,
+        auto_download: bool,
+    ) -> Result<RemoteSubscription> {
+        self.local.authorize(Role::Editor, "create_remote_subscription", "").await?;
+        self.local.create_remote_subscription(name, query, filter, auto_download).await
+    }
+
+    /// List saved remote subscriptions
+    pub async fn list_subscriptions(&self) -> Result<Vec<RemoteSubscription>> {
+        self.local.list_subscriptions().await
+    }
+
+    /// Run every saved Freesound search and add newly appearing sounds
+    ///
+    /// Sounds are only ever added; members added by hand are left alone, and
+    /// sounds removed from a subscribed collection are not added back.
+    pub async fn sync_subscriptions(&self) -> Result<SyncReport> {
+        self.sync_subscriptions_with_context(&OpContext::default()).await
+    }
+
+    /// [`SoundVault::sync_subscriptions`], stopped by `context` between
+    /// sounds and while waiting on Freesound
+    ///
+    /// Sounds added before the sync stopped are kept; the next sync adds the
+    /// rest.
+    pub async fn sync_subscriptions_with_context(&self, context: &OpContext) -> Result<SyncReport> {
+        self.local.authorize(Role::Editor, "sync_subscriptions", "").await?;
+        let remote = self.remote()?;
+        self.local.sync_subscriptions(remote, context).await
+    }
+
+    /// Summarize the state of the vault and of the library build
+    ///
+    /// Cheap enough to call at startup; the integrity warnings come from the
+    /// last [`SoundVault::scan_integrity`] rather than a new scan.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::SoundVault;
+    ///
+    /// # async fn example(vault: SoundVault) -> soundvault::Result<()> {
+    /// let health = vault.health().await?;
+    /// println!("{}", serde_json::to_string_pretty(&health)?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Queries run on their own read-only connections, so they don't wait
+    /// for a long write, even from another process:
+    ///
+    /// ```
+    /// use soundvault::{Collection, SoundVault, VaultConfig};
+    /// use sqlx::Connection;
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// let vault = SoundVault::new(config.clone()).await?;
+    /// let id = vault.add_collection(&Collection::new("Foley", "")).await?;
+    ///
+    /// let mut writer = sqlx::SqliteConnection::connect(&format!("sqlite:{}", config.database_path.display())).await?;
+    /// sqlx::query("BEGIN EXCLUSIVE").execute(&mut writer).await?;
+    /// sqlx::query("UPDATE sv_collections SET name = 'Renamed'").execute(&mut writer).await?;
+    ///
+    /// // Reads see the last commit at once
+    /// let collection = tokio::time::timeout(Duration::from_secs(1), vault.get_collection(&id)).await??;
+    /// assert_eq!(collection.name, "Foley");
+    /// let health = tokio::time::timeout(Duration::from_secs(1), vault.health()).await??;
+    /// assert_eq!((health.write_pool.max_connections, health.read_pool.max_connections), (1, 4));
+    /// sqlx::query("ROLLBACK").execute(&mut writer).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn health(&self) -> Result<HealthReport> {
+        let mut report = self.local.health().await?;
+        report.remote_configured = self.remote.is_some();
+        Ok(report)
+    }
+
+    /// Like [`SoundVault::health`], also checking that Freesound answers
+    ///
+    /// Makes one API request when an API key is configured.
+    pub async fn health_with_remote_probe(&self) -> Result<HealthReport> {
+        let mut report = self.health().await?;
+        if let Some(remote) = &self.remote {
+            report.remote_reachable = Some(remote.probe().await);
+        }
+        Ok(report)
+    }
+
+    /// Limits on the downloads and CPU-heavy jobs run at once, and on
+    /// background write batches
+    pub fn resource_limits(&self) -> ResourceLimits {
+        self.local.governor.limits()
+    }
+
+    /// Change the limits on the downloads and CPU-heavy jobs run at once,
+    /// and on background write batches
+    ///
+    /// Downloads, imports, analysis and background tasks all take their
+    /// share from these limits. Work already running when a limit is
+    /// lowered finishes; new work waits until it fits.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if a limit is zero
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ResourceLimits, SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// config.max_transfers = Some(2);
+    /// config.max_background_jobs = Some(4);
+    /// let vault = SoundVault::new(config).await?;
+    /// let normal = vault.resource_limits();
+    /// assert_eq!((normal.transfers, normal.decodes), (2, 4));
+    ///
+    /// // Low priority background mode
+    /// vault.set_limits(ResourceLimits { transfers: 1, decodes: 1, write_batch: 4 })?;
+    /// let resources = vault.health().await?.resources;
+    /// assert_eq!(resources.limits.decodes, 1);
+    /// assert_eq!((resources.transfers, resources.decodes), (0, 0));
+    ///
+    /// assert!(vault.set_limits(ResourceLimits { decodes: 0, ..normal }).is_err());
+    /// vault.set_limits(normal)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_limits(&self, limits: ResourceLimits) -> Result<()> {
+        self.local.governor.set_limits(limits)
+    }
+
+    /// File operations a crash interrupted, settled when the vault was opened
+    ///
+    /// Imports, downloads, derivatives, file replacements and compressions
+    /// are journaled before they touch a file. One that never committed is
+    /// rolled back, removing the files it wrote, even partly; one that
+    /// committed is completed, removing the files it replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ArchivalCodec, SoundVault, VaultConfig};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// let vault = SoundVault::new(config.clone()).await?;
+    /// std::fs::write(dir.path().join("take.raw"), b"take")?;
+    /// let id = vault.import_file(dir.path().join("take.raw"), None).await?;
+    /// let original = vault.get_sound(&id).await?.metadata.path.unwrap();
+    /// vault.compress_sound(&id, ArchivalCodec::Zstd).await?;
+    /// assert!(vault.interrupted_operations().rolled_back.is_empty());
+    /// vault.close(Duration::from_secs(5)).await?;
+    ///
+    /// // Leave the journal as three crashes would have
+    /// let orphan = dir.path().join("orphan").join("take.raw");
+    /// std::fs::create_dir(orphan.parent().unwrap())?;
+    /// std::fs::write(&orphan, b"copied, never recorded")?;
+    /// std::fs::write(dir.path().join("orphan").join("half.raw.tmp"), b"half cop")?;
+    /// std::fs::write(&original, b"take")?;
+    /// let db = sqlx::SqlitePool::connect(&format!("sqlite:{}", config.database_path.display())).await?;
+    /// let journal = "INSERT INTO sv_pending_ops (id, kind, created, obsolete, committed) VALUES (?, ?, ?, ?, ?)";
+    /// let half = dir.path().join("orphan").join("half.raw");
+    /// for (op, kind, created, obsolete, committed) in [
+    ///     ("1", "import", vec![&orphan], vec![], false),
+    ///     ("2", "import", vec![&half], vec![], false),
+    ///     ("3", "compress", vec![], vec![&original], true),
+    /// ] {
+    ///     sqlx::query(journal)
+    ///         .bind(op)
+    ///         .bind(kind)
+    ///         .bind(serde_json::to_string(&created)?)
+    ///         .bind(serde_json::to_string(&obsolete)?)
+    ///         .bind(committed)
+    ///         .execute(&db)
+    ///         .await?;
+    /// }
+    /// db.close().await;
+    ///
+    /// let vault = SoundVault::new(config).await?;
+    /// let report = vault.interrupted_operations();
+    /// assert_eq!((report.rolled_back.len(), report.completed.len()), (2, 1));
+    /// assert!(!orphan.exists() && !dir.path().join("orphan").join("half.raw.tmp").exists());
+    /// assert!(!original.exists());
+    /// assert_eq!(vault.open_sound(&id).await?, b"take");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn interrupted_operations(&self) -> &RecoveryReport {
+        &self.local.recovery
+    }
+
+    /// Register a remote source to search and download from
+    ///
+    /// Freesound is registered when an API key is configured. Sounds are
+    /// stored with the source's name, so it must stay the same across runs.
+    /// Queued downloads of the source can start once it's registered.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the name is empty, `local`, or
+    ///   already registered
+    pub fn add_remote_source(&mut self, source: Box<dyn RemoteSource>) -> Result<()> {
+        let name = source.name();
+        if name.is_empty() || name == SoundSource::Local.as_str() || self.remote_source(name).is_ok() {
+            return Err(VaultError::InvalidOperation(format!("Invalid or duplicate remote source name: {:?}", name)));
+        }
+
+        self.sources.write().unwrap_or_else(|e| e.into_inner()).push(source.into());
+        if self.config.resume_downloads {
+            self.downloads.wake(&self.local, &self.sources);
+        } else {
+            self.downloads.notify();
+        }
+        Ok(())
+    }
+
+    /// Names of the registered remote sources
+    pub fn remote_sources(&self) -> Vec<String> {
+        let sources = self.sources.read().unwrap_or_else(|e| e.into_inner());
+        sources.iter().map(|source| source.name().to_string()).collect()
+    }
+
+    /// Register a store the files of sounds can be kept in
+    ///
+    /// Sounds record the name of the store holding their file, so it must
+    /// stay the same across runs, and the store must be registered before
+    /// they're read. With the `s3` feature, the bucket of
+    /// [`VaultConfig`]'s `s3` is registered when the vault opens.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::InvalidOperation` if the name is empty,
+    ///   [`LIBRARY_STORE`](crate::LIBRARY_STORE), or already registered
+    pub fn add_blob_store(&mut self, store: Box<dyn BlobStore>) -> Result<()> {
+        self.local.add_blob_store(store.into())
+    }
+
+    /// Names of the stores the files of sounds can be kept in, the
+    /// library's first
+    pub fn blob_stores(&self) -> Vec<String> {
+        self.local.blob_store_names()
+    }
+
+    /// Move the files of the sounds matching a filter from one store to
+    /// another, e.g. from [`LIBRARY_STORE`](crate::LIBRARY_STORE) to a bucket
+    ///
+    /// Each file is copied and its size checked before the sound records its
+    /// new store; the old copy is removed only then. Files that fail to move
+    /// are reported and stay where they were. Referenced files never move.
+    /// See [`FileSystemStore`](crate::FileSystemStore) for an example.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if either store isn't registered
+    pub async fn migrate_blobs(&self, from: &str, to: &str, filter: &SoundFilter) -> Result<BlobMigrationReport> {
+        self.local.authorize(Role::Admin, "migrate_blobs", "").await?;
+        self.local.migrate_blobs(from, to, filter).await
+    }
+
+    /// Move the files of the library into the content-addressed layout,
+    /// where sounds with the same content share one file, and store new
+    /// files that way from now on
+    ///
+    /// Each file is checked against its recorded hash before it moves and
+    /// named after it, at `objects/<hash[0..2]>/<hash>`; a copy of content
+    /// already stored is removed. Compressed and packed sounds, referenced
+    /// files and files held by other blob stores keep their path. The vault
+    /// keeps the layout even if later opened with
+    /// [`StorageLayout::Classic`](crate::StorageLayout::Classic).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, VaultConfig};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let path = dir.path().join("rain.wav");
+    /// std::fs::write(&path, b"drops")?;
+    ///
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let first = vault.import_file(&path, None).await?;
+    /// let second = vault.import_file(&path, None).await?;
+    ///
+    /// let report = vault.migrate_to_cas().await?;
+    /// assert_eq!(report.migrated.len(), 2);
+    /// assert_eq!((report.deduplicated.len(), report.bytes_reclaimed), (1, 5));
+    ///
+    /// // Both sounds share one file, and so does the next import
+    /// let third = vault.import_file(&path, None).await?;
+    /// let stored = vault.get_sound(&first).await?.metadata.path.unwrap();
+    /// assert!(stored.starts_with(dir.path().join("objects")));
+    /// assert_eq!(vault.get_sound(&second).await?.metadata.path, Some(stored.clone()));
+    /// assert_eq!(vault.get_sound(&third).await?.metadata.path, Some(stored.clone()));
+    ///
+    /// // The file goes with the last sound sharing it
+    /// vault.delete_sound(&first).await?;
+    /// vault.delete_sound(&second).await?;
+    /// assert!(stored.exists());
+    /// vault.delete_sound(&third).await?;
+    /// assert!(!stored.exists());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn migrate_to_cas(&self) -> Result<CasMigrationReport> {
+        self.local.authorize(Role::Admin, "migrate_to_cas", "").await?;
+        self.local.migrate_to_cas().await
+    }
+
+    /// Search every registered remote source
+    ///
+    /// A failing source doesn't fail the search; its error is reported in
+    /// [`RemoteSearchResults::errors`]. Sounds already in the vault are
+    /// returned as stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Search text
+    /// * `page_size` - Number of results to ask each source for
+    pub async fn search_remote(&self, query: &str, page_size: usize) -> Result<RemoteSearchResults> {
+        self.search_remote_with_context(query, page_size, &OpContext::default()).await
+    }
+
+    /// [`SoundVault::search_remote`], stopped by `context` while waiting on
+    /// a source
+    ///
+    /// Unlike a failing source, running out of time or being cancelled
+    /// fails the whole search.
+    pub async fn search_remote_with_context(
+        &self,
+        query: &str,
+        page_size: usize,
+        context: &OpContext,
+    ) -> Result<RemoteSearchResults> {
+        let sources = self.sources.read().unwrap_or_else(|e| e.into_inner()).clone();
+        self.local.search_remote(&sources, query, page_size, context).await
+    }
+
+    /// Download a sound from a remote source into the library
+    ///
+    /// A sound already downloaded is not downloaded again. New sounds are
+    /// named after the cleaned-up title of the remote file, whose name is
+    /// kept as [`REMOTE_FILE_NAME`](crate::REMOTE_FILE_NAME), and the file
+    /// takes the extension of the format read from it; see
+    /// [`VaultConfig::keep_remote_file_names`].
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Name of the remote source
+    /// * `remote_id` - ID of the sound at the source
+    ///
+    /// # Returns
+    ///
+    /// The ID of the local sound
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AudioFormat, AudioInfo, REMOTE_FILE_NAME, SampleFormat, SoundVault, VaultConfig, encode};
+    /// # use soundvault::{RemoteFuture, RemoteSource, SoundMetadata};
+    /// # use std::path::Path;
+    /// # struct Studio;
+    /// # impl RemoteSource for Studio {
+    /// #     fn name(&self) -> &str { "studio" }
+    /// #     fn search<'a>(&'a self, _: &'a str, _: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> {
+    /// #         Box::pin(async move { Ok(vec![self.get_by_id("475832").await?]) })
+    /// #     }
+    /// #     fn get_by_id<'a>(&'a self, id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+    /// #         Box::pin(async move { Ok(SoundMetadata { name: format!("{}__someuser__wind-gust-03.flac", id), remote_id: Some(id.to_string()), ..Default::default() }) })
+    /// #     }
+    /// #     fn download<'a>(&'a self, _: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+    /// #         let wav = encode(&AudioInfo::new(AudioFormat::Wav, 1, 8000, SampleFormat::Int(16)), &[0.0; 800]);
+    /// #         Box::pin(async move { Ok(std::fs::write(target, wav?)?) })
+    /// #     }
+    /// # }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// // The source serves a WAV file under a FLAC name
+    /// vault.add_remote_source(Box::new(Studio))?;
+    ///
+    /// let sound = vault.get_sound(&vault.download_remote("studio", "475832").await?).await?;
+    /// assert_eq!(sound.metadata.name, "wind gust 03");
+    /// assert_eq!(sound.metadata.path.as_ref().unwrap().file_name().unwrap(), "wind gust 03.wav");
+    /// assert_eq!(
+    ///     sound.metadata.get_custom(REMOTE_FILE_NAME).unwrap(),
+    ///     "475832__someuser__wind-gust-03.flac"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_remote(&self, source: &str, remote_id: &str) -> Result<String> {
+        self.local.authorize(Role::Editor, "download_remote", "").await?;
+        self.local.download_remote(self.remote_source(source)?.as_ref(), remote_id).await
+    }
+
+    /// Search a remote source, pick a result passing the request's filters,
+    /// download it and add it to the request's collection
+    ///
+    /// A sound already in the vault isn't downloaded again.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if the collection doesn't exist; nothing is
+    ///   searched
+    /// * The source's error if the search fails
+    /// * `VaultError::NoMatch` if no result passes the filters
+    /// * `VaultError::AcquireFailed` if the picked sound couldn't be
+    ///   downloaded, or added to the collection once imported
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AcquirePick, AcquireRequest, AcquireStage, Collection, License, SoundVault, VaultConfig, VaultError};
+    /// # use soundvault::{RemoteFuture, RemoteSource, SoundMetadata};
+    /// # use std::path::Path;
+    /// # struct Studio;
+    /// # impl RemoteSource for Studio {
+    /// #     fn name(&self) -> &str { "studio" }
+    /// #     fn search<'a>(&'a self, _: &'a str, _: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> {
+    /// #         Box::pin(async move { Ok(vec![self.get_by_id("1").await?, self.get_by_id("2").await?, self.get_by_id("3").await?]) })
+    /// #     }
+    /// #     fn get_by_id<'a>(&'a self, id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+    /// #         let (duration, license, rating) = match id { "1" => (60.0, "Attribution", 5), "2" => (12.0, "Creative Commons 0", 3), _ => (20.0, "Creative Commons 0", 4) };
+    /// #         Box::pin(async move { Ok(SoundMetadata { name: format!("Rain {}.wav", id), remote_id: Some(id.to_string()), tags: vec!["rain".to_string()], duration, license: license.to_string(), rating: Some(rating), ..Default::default() }) })
+    /// #     }
+    /// #     fn download<'a>(&'a self, id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+    /// #         Box::pin(async move { if id == "2" { return Err(VaultError::FileSystem("Gone".to_string())) } Ok(std::fs::write(target, id)?) })
+    /// #     }
+    /// # }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// vault.add_remote_source(Box::new(Studio))?;
+    /// let ambiences = vault.add_collection(&Collection::new("Ambiences", "")).await?;
+    ///
+    /// // The best rated rain loop under 30 seconds that's CC0
+    /// let mut request = AcquireRequest::new("rain");
+    /// request.source = "studio".to_string();
+    /// request.max_duration = Some(30.0);
+    /// request.licenses = vec![License::Cc0];
+    /// request.pick = AcquirePick::HighestRated;
+    /// request.collection_id = Some(ambiences.clone());
+    /// let sound = vault.acquire(request.clone()).await?;
+    /// assert_eq!(sound.metadata.name, "Rain 3");
+    /// assert!(sound.is_cached);
+    /// assert_eq!(vault.get_collection(&ambiences).await?.sound_ids, vec![sound.metadata.id]);
+    ///
+    /// // The picked sound can't be downloaded
+    /// request.pick = AcquirePick::First;
+    /// let error = vault.acquire(request.clone()).await.unwrap_err();
+    /// assert!(matches!(error, VaultError::AcquireFailed { stage: AcquireStage::Download, sound_id: None, .. }));
+    ///
+    /// // Nothing passes the filters
+    /// request.max_duration = Some(5.0);
+    /// let error = vault.acquire(request).await.unwrap_err();
+    /// assert!(matches!(error, VaultError::NoMatch { searched: 3, .. }));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn acquire(&self, request: AcquireRequest) -> Result<Sound> {
+        self.local.authorize(Role::Editor, "acquire", "").await?;
+        self.local.acquire(self.remote_source(&request.source)?.as_ref(), &request).await
+    }
+
+    /// ID of the collection downloaded sounds are added to, creating it if
+    /// needed; `None` unless [`VaultConfig::auto_collect_downloads`] is set
+    ///
+    /// Sounds downloaded with [`SoundVault::download_remote`], through the
+    /// download queue or by subscriptions join the collection. It's marked
+    /// with [`AUTO_COLLECT_KEY`](crate::AUTO_COLLECT_KEY) in its custom
+    /// metadata, so renaming it doesn't lead to a second one, and downloads
+    /// finishing together never create two.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, VaultConfig};
+    /// # use soundvault::{RemoteFuture, RemoteSource, SoundMetadata};
+    /// # use std::path::Path;
+    /// # struct Studio;
+    /// # impl RemoteSource for Studio {
+    /// #     fn name(&self) -> &str { "studio" }
+    /// #     fn search<'a>(&'a self, _: &'a str, _: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> { Box::pin(async { Ok(Vec::new()) }) }
+    /// #     fn get_by_id<'a>(&'a self, id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+    /// #         Box::pin(async move { Ok(SoundMetadata { name: format!("{}.wav", id), ..Default::default() }) })
+    /// #     }
+    /// #     fn download<'a>(&'a self, id: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+    /// #         Box::pin(async move { Ok(std::fs::write(target, id)?) })
+    /// #     }
+    /// # }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// config.auto_collect_downloads = Some("All Freesound Downloads".to_string());
+    /// let mut vault = SoundVault::new(config.clone()).await?;
+    /// vault.add_remote_source(Box::new(Studio))?;
+    ///
+    /// // Two downloads finishing together share one new collection
+    /// let (first, second) = tokio::join!(vault.download_remote("studio", "take-1"), vault.download_remote("studio", "take-2"));
+    /// let (first, second) = (first?, second?);
+    /// let collection_id = vault.downloads_collection().await?.unwrap();
+    /// let mut members = vault.get_collection(&collection_id).await?.sound_ids;
+    /// members.sort();
+    /// let mut expected = vec![first.clone(), second];
+    /// expected.sort();
+    /// assert_eq!(members, expected);
+    /// assert_eq!(vault.collections_containing(&first).await?.len(), 1);
+    ///
+    /// // Without the setting, downloads go to no collection and it stays
+    /// drop(vault);
+    /// config.auto_collect_downloads = None;
+    /// let mut vault = SoundVault::new(config).await?;
+    /// vault.add_remote_source(Box::new(Studio))?;
+    /// let third = vault.download_remote("studio", "take-3").await?;
+    /// assert!(vault.collections_containing(&third).await?.is_empty());
+    /// assert_eq!(vault.get_collection(&collection_id).await?.sound_ids.len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn downloads_collection(&self) -> Result<Option<String>> {
+        self.local.downloads_collection().await
+    }
+
+    /// Queue a download from a remote source
+    ///
+    /// Queued downloads run one at a time in the background, at most
+    /// [`VaultConfig::downloads_per_minute`] per minute, and are kept in the
+    /// database: entries left when the vault closes are downloaded after it's
+    /// opened again, right away with [`VaultConfig::resume_downloads`] or
+    /// once the queue is used. Progress is reported as [`VaultEvent`]s.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Name of the remote source
+    /// * `remote_id` - ID of the sound at the source
+    /// * `collection_id` - Collection to add the sound to once downloaded
+    ///
+    /// # Returns
+    ///
+    /// The ID of the queue entry
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{DownloadState, SoundVault, VaultConfig, VaultEvent};
+    /// # use soundvault::{RemoteFuture, RemoteSource, SoundMetadata};
+    /// # use std::path::Path;
+    /// # struct Studio;
+    /// # impl RemoteSource for Studio {
+    /// #     fn name(&self) -> &str { "studio" }
+    /// #     fn search<'a>(&'a self, _: &'a str, _: usize) -> RemoteFuture<'a, Vec<SoundMetadata>> { Box::pin(async { Ok(Vec::new()) }) }
+    /// #     fn get_by_id<'a>(&'a self, id: &'a str) -> RemoteFuture<'a, SoundMetadata> {
+    /// #         Box::pin(async move { Ok(SoundMetadata { name: format!("{}.wav", id), ..Default::default() }) })
+    /// #     }
+    /// #     fn download<'a>(&'a self, _: &'a str, target: &'a Path) -> RemoteFuture<'a, ()> {
+    /// #         Box::pin(async move { Ok(std::fs::write(target, b"take")?) })
+    /// #     }
+    /// # }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let mut vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// vault.add_remote_source(Box::new(Studio))?;
+    /// let mut events = vault.subscribe();
+    ///
+    /// let queue_id = vault.enqueue_download("studio", "take-1", None).await?;
+    /// assert_eq!(events.recv().await?, VaultEvent::DownloadQueued { queue_id });
+    /// assert_eq!(events.recv().await?, VaultEvent::DownloadStarted { queue_id });
+    /// let VaultEvent::DownloadFinished { sound_id, .. } = events.recv().await? else { panic!() };
+    ///
+    /// let entry = &vault.list_download_queue().await?[0];
+    /// assert_eq!(entry.state, DownloadState::Done);
+    /// assert_eq!(entry.sound_id.as_ref(), Some(&sound_id));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn enqueue_download(&self, source: &str, remote_id: &str, collection_id: Option<&str>) -> Result<i64> {
+        self.local.authorize(Role::Editor, "enqueue_download", "").await?;
+        self.remote_source(source)?;
+        let id = self.local.enqueue_download(source, remote_id, collection_id).await?;
+        self.downloads.wake(&self.local, &self.sources);
+        Ok(id)
+    }
+
+    /// List the download queue, oldest entry first, finished entries included
+    pub async fn list_download_queue(&self) -> Result<Vec<QueuedDownload>> {
+        self.local.list_download_queue().await
+    }
+
+    /// Cancel a pending or failed download
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if there is no such entry
+    /// * `VaultError::InvalidOperation` if it's being downloaded or finished
+    pub async fn cancel_queued(&self, id: i64) -> Result<()> {
+        self.local.authorize(Role::Editor, "cancel_queued", "").await?;
+        self.local.cancel_queued(id).await
+    }
+
+    /// Queue failed downloads again
+    ///
+    /// # Returns
+    ///
+    /// The number of downloads queued again
+    pub async fn retry_failed(&self) -> Result<u64> {
+        self.local.authorize(Role::Editor, "retry_failed", "").await?;
+        let retried = self.local.retry_failed().await?;
+        self.downloads.wake(&self.local, &self.sources);
+        Ok(retried)
+    }
+
+    /// Start downloading the entries left in the queue by a previous run
+    ///
+    /// That's done when opening the vault with
+    /// [`VaultConfig::resume_downloads`].
+    pub fn resume_downloads(&self) {
+        self.downloads.wake(&self.local, &self.sources);
+    }
+
+    /// Receive the vault's events from now on
+    ///
+    /// A receiver that falls too far behind misses the oldest events.
+    pub fn subscribe(&self) -> broadcast::Receiver<VaultEvent> {
+        self.local.subscribe()
+    }
+
+    /// Stream of the vault's events matching `filter`, from now on
+    ///
+    /// Events are filtered before they're buffered, so those left out don't
+    /// take room. When the stream falls too far behind, the oldest events are
+    /// dropped and a [`VaultEvent::Lagged`] tells how many. Dropping the
+    /// stream unsubscribes it.
+    ///
+    /// Must be called within a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{EventFilter, EventKind, SoundVault, VaultConfig, VaultEvent};
+    /// use std::time::Duration;
+    /// use tokio_stream::StreamExt;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// std::fs::write(sources.path().join("door.wav"), b"door")?;
+    /// let id = vault.import_file(sources.path().join("door.wav"), None).await?;
+    ///
+    /// let filter = EventFilter::default().kinds([EventKind::CollectionChanged]).buffer(16);
+    /// let mut events = vault.event_stream(filter);
+    /// let collection_id = vault.create_session_collection("Take 1", Duration::from_secs(60)).await?;
+    /// vault.add_sound_to_collection(&id, &collection_id).await?;
+    /// assert_eq!(events.next().await, Some(VaultEvent::CollectionChanged { collection_id }));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn event_stream(&self, filter: EventFilter) -> impl Stream<Item = VaultEvent> + Send + Unpin + 'static {
+        self.local.event_stream(filter)
+    }
+
+    /// Stream of the current state of the vault's entities matching
+    /// `filter`, then of its events matching it, like
+    /// [`SoundVault::event_stream`]
+    ///
+    /// The state comes first as events: a
+    /// [`VaultEvent::AvailabilityChanged`] for each sound, then a
+    /// [`VaultEvent::CollectionChanged`] for each collection. The stream
+    /// subscribes before reading it, so a change made meanwhile is received
+    /// after it rather than lost.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Availability, EventFilter, SoundVault, VaultConfig, VaultEvent};
+    /// use tokio_stream::StreamExt;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let sources = tempfile::tempdir()?;
+    /// std::fs::write(sources.path().join("door.wav"), b"door")?;
+    /// std::fs::write(sources.path().join("rain.wav"), b"rain")?;
+    /// let door = vault.import_file(sources.path().join("door.wav"), None).await?;
+    /// vault.import_file(sources.path().join("rain.wav"), None).await?;
+    ///
+    /// let mut events = vault.event_stream_with_snapshot(EventFilter::default().id_prefix(&door)).await?;
+    /// let availability = Availability::Available;
+    /// assert_eq!(events.next().await, Some(VaultEvent::AvailabilityChanged { sound_id: door, availability }));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn event_stream_with_snapshot(
+        &self,
+        filter: EventFilter,
+    ) -> Result<impl Stream<Item = VaultEvent> + Send + Unpin + 'static> {
+        self.local.event_stream_with_snapshot(filter).await
+    }
+
+    /// A registered remote source, by name
+    fn remote_source(&self, name: &str) -> Result<Arc<dyn RemoteSource>> {
+        self.sources
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|source| source.name() == name)
+            .cloned()
+            .ok_or_else(|| VaultError::Config(format!("No remote source named {:?}", name)))
+    }
+
+    /// The Freesound manager, if an API key is configured
+    fn remote(&self) -> Result<&FreesoundManager> {
+        self.remote
+            .as_deref()
+            .ok_or_else(|| VaultError::Config("No Freesound API key configured".to_string()))
+    }
+
+    /// Freesound ID of a sound
+    fn freesound_id(sound: &Sound) -> Result<i32> {
+        sound.metadata.freesound_id.ok_or_else(|| {
+            VaultError::InvalidOperation(format!("Sound {} isn't from Freesound", sound.metadata.id))
+        })
+    }
+
+    /// Content of the Freesound preview of a sound, held in memory
+    async fn freesound_preview(&self, sound: &Sound) -> Result<Arc<[u8]>> {
+        let freesound_id = Self::freesound_id(sound)?;
+        self.remote()?.preview(freesound_id).await
+    }
+
+    /// Set who is recorded in the audit log for subsequent changes
+    ///
+    /// `None` records changes without an actor.
+    pub fn set_actor(&self, actor: Option<&str>) {
+        self.local.set_actor(actor)
+    }
+
+    /// Give an actor a role, limiting what the actors sharing the vault may
+    /// do
+    ///
+    /// Without roles, every actor may do everything. Once one is granted,
+    /// [`Role::Viewer`]s and actors without a role, including an unset
+    /// actor, may only read; [`Role::Editor`]s may also import and edit, but
+    /// not delete sounds, repair the library, move files between blob
+    /// stores, load dumps or prune history, which takes an
+    /// [`Role::Admin`]. Denied attempts fail with
+    /// `VaultError::PermissionDenied` and are recorded in the audit log.
+    ///
+    /// Only admins manage roles, and the first role granted must be an
+    /// admin's.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{AuditOperation, Role, SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let file = dir.path().join("hit.wav");
+    /// std::fs::write(&file, b"RIFF\x24\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\x40\x1f\0\0\x80\x3e\0\0\x02\0\x10\0data\0\0\0\0")?;
+    ///
+    /// vault.grant_role("lead", Role::Admin).await?;
+    /// vault.set_actor(Some("lead"));
+    /// vault.grant_role("intern", Role::Editor).await?;
+    /// let id = vault.import_file(&file, None).await?;
+    ///
+    /// // The intern may tag, but not delete
+    /// vault.set_actor(Some("intern"));
+    /// vault.update_metadata(&id, |metadata| metadata.tags.push("impact".to_string())).await?;
+    /// let denied = vault.delete_sound(&id).await;
+    /// assert!(matches!(denied, Err(VaultError::PermissionDenied { required_role: Role::Admin, .. })));
+    /// assert!(vault.list_roles().await.is_err());
+    ///
+    /// vault.set_actor(Some("lead"));
+    /// let history = vault.audit_history(Some(&id), None, 10).await?;
+    /// assert_eq!(history[0].operation, AuditOperation::PermissionDenied);
+    /// assert_eq!(history[0].actor.as_deref(), Some("intern"));
+    /// assert_eq!(vault.list_roles().await?.len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    /// * `VaultError::InvalidOperation` if it would leave the vault without an
+    ///   admin
+    pub async fn grant_role(&self, actor: &str, role: Role) -> Result<()> {
+        self.local.grant_role(actor, role).await
+    }
+
+    /// Take an actor's role away; revoking every role lifts all
+    /// restrictions
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    /// * `VaultError::InvalidOperation` if it would leave the vault without an
+    ///   admin while other roles remain
+    pub async fn revoke_role(&self, actor: &str) -> Result<()> {
+        self.local.revoke_role(actor).await
+    }
+
+    /// Roles granted, by actor
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::PermissionDenied` if the current actor isn't an admin
+    pub async fn list_roles(&self) -> Result<BTreeMap<String, Role>> {
+        self.local.list_roles().await
+    }
+
+    /// Get audit log entries, newest first
+    ///
+    /// Every import, metadata change, deletion and collection change is logged
+    /// with the fields it changed; reads are not logged.
+    ///
+    /// # Arguments
+    ///
+    /// * `entity_id` - Only entries about this sound or collection
+    /// * `since` - Only entries recorded at or after this time
+    /// * `limit` - Maximum number of entries to return
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chrono::{Duration, Utc};
+    /// use soundvault::SoundVault;
+    ///
+    /// # async fn example(vault: SoundVault, sound_id: String) -> soundvault::Result<()> {
+    /// vault.set_actor(Some("alice"));
+    ///
+    /// let since = Utc::now() - Duration::days(7);
+    /// for entry in vault.audit_history(Some(&sound_id), Some(since), 20).await? {
+    ///     println!("{} {:?} by {:?}: {}", entry.timestamp, entry.operation, entry.actor, entry.changes);
+    /// }
+    ///
+    /// // Keep a year of history
+    /// vault.prune_audit_log(Utc::now() - Duration::days(365)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn audit_history(
+        &self,
+        entity_id: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<AuditEntry>> {
+        self.local.audit_history(entity_id, since, limit).await
+    }
+
+    /// List the batch operations of this session that can be undone, newest
+    /// first
+    ///
+    /// Batch operations are [`sync_directory`](Self::sync_directory),
+    /// [`replace_text`](Self::replace_text), [`rename_tag`](Self::rename_tag),
+    /// [`rename_custom_key`](Self::rename_custom_key),
+    /// [`apply_collection_defaults`](Self::apply_collection_defaults),
+    /// [`approve`](Self::approve) and [`load_dump`](Self::load_dump). Those
+    /// that changed nothing or were undone are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `since` - Only operations started at or after this time
+    pub async fn list_operations(&self, since: Option<DateTime<Utc>>) -> Result<Vec<OperationSummary>> {
+        self.local.list_operations(since).await
+    }
+
+    /// Undo a batch operation of this session
+    ///
+    /// Sounds it created go to the trash and collections it created are
+    /// removed; metadata and collection names it changed are set back from
+    /// the audit log, and sounds it added to or removed from collections are
+    /// taken out or put back. Nothing is changed when the operation can't be
+    /// undone as a whole.
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if this session ran no such operation
+    /// * `VaultError::InvalidOperation` if it was undone already, or it
+    ///   deleted something or replaced a file, which can't be set back
+    /// * `VaultError::UndoConflict` if a field it set was changed again since
+    /// * `VaultError::Locked` if a sound to set back or trash is locked
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{
+    ///     ImportOptions, MetadataPatch, ReplaceOptions, SoundFilter, SoundMetadataTemplate, SoundVault, SyncOptions,
+    ///     TextScope, VaultConfig, VaultError,
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let vault = SoundVault::new(VaultConfig::new(library, None)).await?;
+    ///
+    /// let mut drums = SoundMetadataTemplate::default();
+    /// drums.tags = vec!["drums".to_string()];
+    /// vault.save_import_template("drums", &drums).await?;
+    /// let field = dir.path().join("field");
+    /// std::fs::create_dir(&field)?;
+    /// for name in ["rain", "wind"] {
+    ///     std::fs::write(field.join(format!("{}.wav", name)), name)?;
+    /// }
+    ///
+    /// // A bulk import with the wrong template
+    /// let import = ImportOptions { template: Some("drums".to_string()), ..Default::default() };
+    /// let report = vault.sync_directory(&field, SyncOptions { import, ..Default::default() }).await?;
+    /// assert_eq!(vault.list_operations(None).await?[0].summary, "2 sounds created");
+    ///
+    /// let undone = vault.undo_operation(report.operation.unwrap()).await?;
+    /// assert_eq!(undone.trashed.len(), 2);
+    /// assert_eq!(vault.count(&SoundFilter::default()).await?, 0);
+    /// assert!(vault.list_operations(None).await?.is_empty());
+    ///
+    /// // Undoing leaves later edits alone
+    /// let hum = dir.path().join("hum.wav");
+    /// std::fs::write(&hum, "hum")?;
+    /// let id = vault.import_file(&hum, None).await?;
+    /// let scope = TextScope { names: true, ..Default::default() };
+    /// let options = ReplaceOptions { dry_run: false, ..Default::default() };
+    /// let report = vault.replace_text(scope, "hum", "drone", options).await?;
+    /// let patch = MetadataPatch { name: Some("Low drone".to_string()), ..Default::default() };
+    /// vault.patch_metadata(&id, patch).await?;
+    ///
+    /// let result = vault.undo_operation(report.operation.unwrap()).await;
+    /// assert!(matches!(result, Err(VaultError::UndoConflict { field, .. }) if field == "name"));
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.name, "Low drone");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn undo_operation(&self, op: OperationId) -> Result<UndoReport> {
+        self.local.authorize(Role::Editor, "undo_operation", op.as_str()).await?;
+        self.local.undo_operation(&op).await
+    }
+
+    /// Add what a spec declares and the vault lacks
+    ///
+    /// Running it again adds nothing: things are matched by name, and those
+    /// already there are left alone even when they differ from the spec.
+    /// Sounds the spec synthesizes are mono 16-bit WAV files at 44.1 kHz.
+    /// The sounds, collections and memberships added can be undone with
+    /// [`SoundVault::undo_operation`].
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::NotFound` if a sound belongs to a collection neither
+    ///   declared nor in the vault
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ProvisionKind, SoundVault, VaultConfig, VaultError, VaultSpec};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let spec = VaultSpec::parse(r#"
+    ///     [tags.aliases]
+    ///     beeps = "beep"
+    ///
+    ///     [[collections]]
+    ///     name = "Test tones"
+    ///     filter = { tags = ["beep"] }
+    ///
+    ///     [[sounds]]
+    ///     name = "A4"
+    ///     source = { kind = "tone", duration = 0.25, frequency = 440 }
+    ///     tags = ["beep"]
+    ///
+    ///     [[sounds]]
+    ///     name = "Gap"
+    ///     source = { kind = "silence", duration = 1 }
+    /// "#)?;
+    ///
+    /// let report = vault.provision(&spec).await?;
+    /// assert_eq!(report.created.len(), 5);
+    /// assert!(report.created.iter().any(|item| item.kind == ProvisionKind::Membership && item.name == "A4 in Test tones"));
+    ///
+    /// // A second run finds everything there
+    /// let again = vault.provision(&spec).await?;
+    /// assert!(again.created.is_empty() && again.operation.is_none());
+    /// assert_eq!(again.present.len(), 5);
+    /// vault.check_provision(&spec).await?;
+    ///
+    /// let collection = &vault.list_collections(false).await?[0];
+    /// vault.remove_sound_from_collection(&collection.sound_ids[0], &collection.id.to_string()).await?;
+    /// assert!(matches!(
+    ///     vault.check_provision(&spec).await,
+    ///     Err(VaultError::SpecDrift { missing, .. }) if missing == ["membership A4 in Test tones"]
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn provision(&self, spec: &VaultSpec) -> Result<ProvisionReport> {
+        self.local.authorize(Role::Editor, "provision", "").await?;
+        let provision = self.local.provision(spec, false);
+        let (mut report, operation) = self.local.in_operation("provision", provision).await?;
+        if !report.created.is_empty() {
+            report.operation = Some(operation);
+        }
+        Ok(report)
+    }
+
+    /// Check that the vault holds what a spec declares, changing nothing
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::SpecDrift` if something declared is missing or differs
+    ///   from the spec
+    pub async fn check_provision(&self, spec: &VaultSpec) -> Result<ProvisionReport> {
+        self.local.provision(spec, true).await
+    }
+
+    /// Delete audit log entries older than the given time
+    ///
+    /// # Returns
+    ///
+    /// The number of deleted entries
+    pub async fn prune_audit_log(&self, older_than: DateTime<Utc>) -> Result<u64> {
+        self.local.authorize(Role::Admin, "prune_audit_log", "").await?;
+        self.local.prune_audit_log(older_than).await
+    }
+
+    /// Get the sounds and collections created, updated or deleted after a
+    /// cursor, to keep an external copy in sync
+    ///
+    /// Changes come oldest first, at most 1000 at a time; call again with
+    /// the returned cursor until none are left. Store the cursor between
+    /// runs: it stays valid across restarts. Deleted sounds are reported
+    /// after they're gone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{ChangeCursor, ChangeEntity, ChangeKind, SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    /// let path = dir.path().join("door.wav");
+    /// std::fs::write(&path, b"door")?;
+    /// let id = vault.import_file(&path, None).await?;
+    ///
+    /// let (changes, cursor) = vault.changes_since(ChangeCursor::default()).await?;
+    /// assert_eq!(changes.len(), 1);
+    /// assert_eq!((changes[0].entity, changes[0].kind), (ChangeEntity::Sound, ChangeKind::Created));
+    ///
+    /// vault.delete_sound(&id).await?;
+    /// let (changes, cursor) = vault.changes_since(cursor).await?;
+    /// assert_eq!(changes[0].entity_id, id);
+    /// assert_eq!(changes[0].kind, ChangeKind::Deleted);
+    /// assert!(vault.changes_since(cursor).await?.0.is_empty());
+    ///
+    /// // Clients behind pruned changes must sync from scratch
+    /// vault.prune_changes(chrono::Utc::now() + chrono::Duration::seconds(1)).await?;
+    /// let result = vault.changes_since(ChangeCursor::default()).await;
+    /// assert!(matches!(result, Err(VaultError::ChangesPruned { .. })));
+    /// assert_eq!(vault.latest_change_cursor().await?, cursor);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * `VaultError::ChangesPruned` if changes after the cursor were pruned;
+    ///   sync from scratch, then follow the feed from
+    ///   [`latest_change_cursor`](Self::latest_change_cursor)
+    pub async fn changes_since(&self, cursor: ChangeCursor) -> Result<(Vec<Change>, ChangeCursor)> {
+        self.local.changes_since(cursor).await
+    }
+
+    /// Cursor of the last change made
+    ///
+    /// Take it before a full sync and follow the feed from it afterwards.
+    pub async fn latest_change_cursor(&self) -> Result<ChangeCursor> {
+        self.local.latest_change_cursor().await
+    }
+
+    /// Delete changes of the change feed recorded before the given time
+    ///
+    /// [`VaultConfig::change_retention`] does so whenever the vault is
+    /// opened.
+    ///
+    /// # Returns
+    ///
+    /// The number of deleted changes
+    pub async fn prune_changes(&self, older_than: DateTime<Utc>) -> Result<u64> {
+        self.local.authorize(Role::Admin, "prune_changes", "").await?;
+        self.local.prune_changes(older_than).await
+    }
+
+    /// Finish the startup work opening the vault leaves for later
+    ///
+    /// Opening a vault whose tables and derived data were prepared by the
+    /// last open, with the same schema and configuration, skips preparing
+    /// them again. Requeuing the downloads cut short by the last shutdown,
+    /// purging expired sessions and pruning the change feed then run in the
+    /// background, followed by resuming downloads when
+    /// [`VaultConfig::resume_downloads`] is set; this waits for them,
+    /// running them if they haven't. [`close`](Self::close) and the download
+    /// worker do too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, VaultConfig};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let config = VaultConfig::new(dir.path().to_path_buf(), None);
+    /// SoundVault::new(config.clone()).await?.close(Duration::from_secs(5)).await?;
+    ///
+    /// // Opened again, the vault is ready in a few milliseconds
+    /// let mut fastest = Duration::MAX;
+    /// for _ in 0..5 {
+    ///     let started = Instant::now();
+    ///     let vault = SoundVault::new(config.clone()).await?;
+    ///     fastest = fastest.min(started.elapsed());
+    ///     vault.warm_up().await?;
+    ///     vault.close(Duration::from_secs(5)).await?;
+    /// }
+    /// assert!(fastest < Duration::from_millis(10), "opened in {:?}", fastest);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn warm_up(&self) -> Result<()> {
+        self.local.warm_up().await
+    }
+
+    /// Shut the vault down cleanly
+    ///
+    /// Startup work still left is finished first; see
+    /// [`warm_up`](Self::warm_up). New background jobs are refused and queued ones cancelled; running
+    /// jobs get up to `timeout` to finish. The download worker stops at once,
+    /// and a download it was running starts over when the vault is opened
+    /// again. Access times not flushed yet and metadata sidecars still due
+    /// are written first, the latter when [`VaultConfig::write_sidecars`] is
+    /// set. The write-ahead log is then
+    /// checkpointed and the database
+    /// closed, unless the host application gave it to
+    /// [`with_pool`](Self::with_pool). Dropping a vault without calling this only stops the job queue
+    /// and the download worker.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{SoundVault, VaultConfig};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let vault = SoundVault::new(VaultConfig::new(dir.path().to_path_buf(), None)).await?;
+    ///
+    /// let report = vault.close(Duration::from_secs(5)).await?;
+    /// assert_eq!(report.abandoned_jobs, 0);
+    /// assert!(report.wal_checkpointed);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn close(self, timeout: Duration) -> Result<ShutdownReport> {
+        self.local.warm_up().await?;
+        self.downloads.stop();
+        self.scrubber.stop();
+        self.maintainer.stop();
+        self.access_flusher.stop();
+        self.sidecars.stop();
+        self.local.flush_accesses().await?;
+        if self.config.write_sidecars {
+            self.local.write_sidecars().await?;
+        }
+        self.local.governor.shutdown();
+        let jobs = &self.local.jobs;
+        let queued = jobs.pending().saturating_sub(jobs.running());
+        jobs.shutdown();
+        let abandoned_jobs = jobs.wait_idle(timeout).await;
+
+        // Columns are busy, log frames and checkpointed frames; busy is 1 when
+        // a reader kept the checkpoint from completing
+        if !self.shared_database {
+            self.local.reader.close().await;
+        }
+        let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(&self.local.db)
+            .await?;
+        if !self.shared_database {
+            self.local.db.close().await;
+        }
+
+        Ok(ShutdownReport {
+            cancelled_jobs: queued,
+            abandoned_jobs,
+            wal_checkpointed: busy == 0,
+        })
+    }
+
+    /// Recover from a damaged database file
+    ///
+    /// The damaged file (and its WAL/SHM companions) is renamed to
+    /// `<database>.corrupt-<timestamp>` so nothing is lost. If a valid backup
+    /// exists at `<database>.bak` it is copied into place, otherwise the vault
+    /// starts with a fresh database on the next [`SoundVault::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{DatabaseRecovery, SoundVault, VaultConfig, VaultError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let config = VaultConfig::new(dir.path().to_path_buf(), None);
+    ///
+    /// // A zero-byte file left behind by a crashed run
+    /// std::fs::write(&config.database_path, b"")?;
+    /// assert!(matches!(SoundVault::new(config.clone()).await, Err(VaultError::Corrupt(_))));
+    ///
+    /// let recovery = SoundVault::recover_database(&config)?;
+    /// assert!(matches!(recovery, DatabaseRecovery::Reinitialized { .. }));
+    /// assert!(SoundVault::new(config).await.is_ok());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn recover_database(config: &VaultConfig) -> Result<DatabaseRecovery> {
+        let db_path = &config.database_path;
+        if !db_path.exists() {
+            return Err(VaultError::InvalidOperation(format!(
+                "No database file to recover at {:?}",
+                db_path
+            )));
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let archived = suffixed(db_path, &format!(".corrupt-{}", timestamp));
+
+        std::fs::rename(db_path, &archived).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to archive damaged database: {}", e))
+        })?;
+
+        // Stale WAL/SHM files belong to the damaged database and must not be
+        // replayed into whatever replaces it
+        for companion in ["-wal", "-shm"] {
+            let path = suffixed(db_path, companion);
+            if path.exists() {
+                std::fs::rename(&path, suffixed(&archived, companion)).map_err(|e| {
+                    VaultError::FileSystem(format!("Failed to archive {:?}: {}", path, e))
+                })?;
+            }
+        }
+
+        let backup = suffixed(db_path, ".bak");
+        if backup.exists() && check_database_file(&backup).is_ok() {
+            copy_atomic(&backup, db_path).map_err(|e| {
+                VaultError::FileSystem(format!("Failed to restore database backup: {}", e))
+            })?;
+            return Ok(DatabaseRecovery::RestoredFromBackup { backup, archived });
+        }
+
+        Ok(DatabaseRecovery::Reinitialized { archived })
+    }
+
+    /// Record again the sounds and collections whose metadata sidecars are
+    /// in the library, e.g. after the database was lost
+    ///
+    /// Sidecars are written when [`VaultConfig::write_sidecars`] is set.
+    /// Sounds and collections still in the database are left as they are; a
+    /// sound's file is looked for next to its sidecar, and collections keep
+    /// only the members that exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use soundvault::{Collection, SoundVault, VaultConfig};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = tempfile::tempdir()?;
+    /// let path = dir.path().join("rain.wav");
+    /// std::fs::write(&path, b"drops")?;
+    ///
+    /// let library = dir.path().join("library");
+    /// std::fs::create_dir(&library)?;
+    /// let mut config = VaultConfig::new(library, None);
+    /// config.write_sidecars = true;
+    /// let vault = SoundVault::new(config.clone()).await?;
+    /// let id = vault.import_file(&path, None).await?;
+    /// let mut weather = Collection::new("Weather", "");
+    /// weather.sound_ids.push(id.clone());
+    /// vault.add_collection(&weather).await?;
+    /// // Closing writes the sidecars still due
+    /// vault.close(Duration::from_secs(5)).await?;
+    ///
+    /// // The database is lost
+    /// std::fs::remove_file(&config.database_path)?;
+    /// let vault = SoundVault::new(config).await?;
+    /// let report = vault.rebuild_from_sidecars().await?;
+    /// assert_eq!(report.sounds, [id.clone()]);
+    /// assert_eq!(vault.get_sound(&id).await?.metadata.name, "rain.wav");
+    /// assert_eq!(vault.get_collection(&weather.id.to_string()).await?.sound_ids, [id]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn rebuild_from_sidecars(&self) -> Result<SidecarRebuildReport> {
+        self.local.authorize(Role::Admin, "rebuild_from_sidecars", "").await?;
+        self.local.rebuild_from_sidecars().await
+    }
+
+    /// Open the SQLite database, turning common startup failures into actionable errors
+    async fn open_database(config: &VaultConfig) -> Result<Pool<Sqlite>> {
+        let db_path = &config.database_path;
+
+        // A custom database path must point into an existing directory
+        let parent = db_path.parent().filter(|p| !p.as_os_str().is_empty());
+        if let Some(parent) = parent {
+            if !parent.is_dir() {
+                return Err(VaultError::Config(format!(
+                    "Database directory does not exist: {:?}; create it or change `database_path`",
+                    parent
+                )));
+            }
+            check_writable(parent)?;
+        }
+
+        // Catch files left by crashed runs before sqlx reports them cryptically
+        check_database_file(db_path)?;
+
+        // In WAL mode readers see the last commit while a write is under way
+        let options = SqliteConnectOptions::new()
+            .filename(db_path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+
+        let mut attempt = 0;
+        loop {
+            let result = async {
+                // One writer at a time: writes queue for the connection
+                // instead of failing on each other's locks
+                let db = SqlitePoolOptions::new()
+                    .max_connections(1)
+                    .connect_with(options.clone())
+                    .await?;
+                // SQLite only reads the file lazily, so force a read to surface corruption now
+                sqlx::query("PRAGMA schema_version").execute(&db).await?;
+                Ok::<_, sqlx::Error>(db)
+            }
+            .await;
+
+            match result {
+                Ok(db) => return Ok(db),
+                Err(e) if sqlite_code(&e) == Some(SQLITE_BUSY) && attempt < OPEN_RETRIES => {
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_millis(100 * u64::from(attempt))).await;
+                }
+                Err(e) => return Err(startup_error(db_path, e)),
+            }
+        }
+    }
+
+    /// Pool of read-only connections to the database, opened as queries
+    /// need them
+    fn open_reader(config: &VaultConfig) -> Pool<Sqlite> {
+        let options = SqliteConnectOptions::new()
+            .filename(&config.database_path)
+            .read_only(true)
+            .busy_timeout(Duration::from_secs(5));
+
+        SqlitePoolOptions::new()
+            .max_connections(READ_CONNECTIONS)
+            .connect_lazy_with(options)
+    }
+}
+
+impl Drop for SoundVault {
+    /// Stop the job queue, download worker, scrubber, maintainer, access
+    /// flusher and sidecar writer so that nothing waits on a dropped vault;
+    /// accesses not flushed yet are lost
+    fn drop(&mut self) {
+        self.downloads.stop();
+        self.scrubber.stop();
+        self.maintainer.stop();
+        self.access_flusher.stop();
+        self.sidecars.stop();
+        self.local.governor.shutdown();
+        self.local.jobs.shutdown();
+    }
+}
+
+/// SQLite primary result codes relevant at startup
+const SQLITE_CORRUPT: i32 = 11;
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_READONLY: i32 = 8;
+const SQLITE_CANTOPEN: i32 = 14;
+const SQLITE_NOTADB: i32 = 26;
+
+/// Append a suffix to the file name of a path
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Check that an existing database file looks like a SQLite database
+fn check_database_file(path: &Path) -> Result<()> {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        // Missing files are created on connect; other open errors surface there too
+        return Ok(());
+    };
+
+    let mut header = [0u8; 16];
+    let read = std::io::Read::read(&mut file, &mut header)?;
+    if read == 0 {
+        return Err(VaultError::Corrupt(format!(
+            "Database file {:?} is empty, probably left by an interrupted run; \
+             call SoundVault::recover_database to archive it and start fresh",
+            path
+        )));
+    }
+    if read < header.len() || &header != SQLITE_HEADER {
+        return Err(VaultError::Corrupt(format!(
+            "{:?} is not a SQLite database; \
+             call SoundVault::recover_database to archive it and restore a backup",
+            path
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check that SQLite will be able to create its journal files in a directory
+fn check_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(format!(".soundvault-write-test-{}", std::process::id()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) if matches!(
+            e.kind(),
+            std::io::ErrorKind::ReadOnlyFilesystem | std::io::ErrorKind::PermissionDenied
+        ) =>
+        {
+            Err(VaultError::Config(format!(
+                "Database directory {:?} is read-only; SoundVault needs write access \
+                 to the database and its journal files",
+                dir
+            )))
+        }
+        Err(e) => Err(VaultError::Io(e)),
+    }
+}
+
+/// Extract the primary SQLite result code from a sqlx error
+fn sqlite_code(error: &sqlx::Error) -> Option<i32> {
+    match error {
+        sqlx::Error::Database(e) => e
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .map(|code| code & 0xff),
+        _ => None,
+    }
+}
+
+/// Map an error raised while opening the database to a descriptive `VaultError`
+fn startup_error(path: &Path, error: sqlx::Error) -> VaultError {
+    match sqlite_code(&error) {
+        Some(SQLITE_NOTADB) | Some(SQLITE_CORRUPT) => VaultError::Corrupt(format!(
+            "Database file {:?} is damaged ({}); \
+             call SoundVault::recover_database to archive it and restore a backup",
+            path, error
+        )),
+        Some(SQLITE_BUSY) | Some(SQLITE_LOCKED) => VaultError::Config(format!(
+            "Database {:?} is locked by another process; \
+             close other applications using this vault and try again",
+            path
+        )),
+        Some(SQLITE_READONLY) => VaultError::Config(format!(
+            "Database {:?} is read-only; check file permissions and that the filesystem is mounted read-write",
+            path
+        )),
+        Some(SQLITE_CANTOPEN) => VaultError::Config(format!(
+            "Unable to open database {:?}; check that its directory exists and is writable",
+            path
+        )),
+        _ => VaultError::Database(error),
+    }
 }
@@ -0,0 +1,179 @@
+//! Waveform thumbnails
+
+use crate::artifacts::ArtifactKind;
+use crate::error::{Result, VaultError};
+use crate::integrity::WAVEFORM_PREFIX;
+use crate::levels::stream_waveform;
+use crate::local::LocalLibrary;
+use crate::paths::write_atomic;
+use serde::{Deserialize, Serialize};
+
+/// Largest width or height of a waveform image, in pixels
+const MAX_DIMENSION: u32 = 4096;
+
+/// How the peaks of a waveform image are drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaveformMode {
+    /// A bar per column, from the negative to the positive peak
+    #[default]
+    Filled,
+    /// The outline of the peaks only
+    Line,
+}
+
+impl WaveformMode {
+    /// Name used in the names of cached images
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Filled => "filled",
+            Self::Line => "line",
+        }
+    }
+}
+
+/// Look of the images rendered by
+/// [`SoundVault::waveform_png`](crate::SoundVault::waveform_png)
+///
+/// Colors are RGBA; a background with an alpha of 0 is transparent, which is
+/// the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaveformStyle {
+    /// How peaks are drawn
+    pub mode: WaveformMode,
+
+    /// Color of the waveform
+    pub foreground: [u8; 4],
+
+    /// Color of the rest of the image
+    pub background: [u8; 4],
+}
+
+impl Default for WaveformStyle {
+    fn default() -> Self {
+        Self {
+            mode: WaveformMode::Filled,
+            foreground: [40, 120, 220, 255],
+            background: [0, 0, 0, 0],
+        }
+    }
+}
+
+impl WaveformStyle {
+    /// Part of the names of cached images telling their style apart
+    fn key(&self) -> String {
+        let hex = |color: [u8; 4]| color.iter().map(|c| format!("{:02x}", c)).collect::<String>();
+        format!("{}-{}-{}", self.mode.as_str(), hex(self.foreground), hex(self.background))
+    }
+}
+
+impl LocalLibrary {
+    /// Render the waveform of a sound as a PNG image
+    ///
+    /// Each column shows the peak of the sound's mono mixdown over its share
+    /// of the duration, mirrored around the middle. Images are cached next to
+    /// the sound's file for each size and style and rendered again when the
+    /// file's content changes, or after [`gc_artifacts`](Self::gc_artifacts)
+    /// evicted them. Rendering runs on the background job queue.
+    pub async fn waveform_png(&self, id: &str, width: u32, height: u32, style: &WaveformStyle) -> Result<Vec<u8>> {
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(VaultError::InvalidOperation(format!(
+                "Invalid waveform size: {}x{}",
+                width, height
+            )));
+        }
+
+        let metadata = self.get_sound(id).await?.metadata;
+        let hash = match &metadata.hash {
+            Some(hash) => hash.clone(),
+            None => self.content_hash(&metadata)?,
+        };
+
+        let dir = self.image_dir(id, metadata.path.as_deref())?;
+        let key_prefix = format!("{}{}x{}-{}-", WAVEFORM_PREFIX, width, height, style.key());
+        let cached = dir.join(format!("{}{}.png", key_prefix, &hash[..hash.len().min(16)]));
+        if let Ok(bytes) = std::fs::read(&cached) {
+            self.touch_artifact(ArtifactKind::Waveform, id, &cached).await?;
+            return Ok(bytes);
+        }
+
+        self.stage_blob(&metadata).await?;
+        let content = self.sound_stream(&metadata)?;
+        let style = *style;
+        let png = self
+            .jobs
+            .run(move || {
+                let peaks = content.read(|reader| stream_waveform(reader, width as usize))?;
+                render(&peaks, height, &style)
+            })
+            .await?;
+
+        // Drop images of older content at this size and style
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&key_prefix)
+                    && std::fs::remove_file(entry.path()).is_ok()
+                {
+                    self.forget_artifact(&entry.path()).await?;
+                }
+            }
+        }
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to create waveform directory: {}", e))
+        })?;
+        write_atomic(&cached, &png).map_err(|e| {
+            VaultError::FileSystem(format!("Failed to write waveform: {}", e))
+        })?;
+        self.touch_artifact(ArtifactKind::Waveform, id, &cached).await?;
+
+        Ok(png)
+    }
+}
+
+/// Draw peaks, one per column, as PNG
+fn render(peaks: &[f32], height: u32, style: &WaveformStyle) -> Result<Vec<u8>> {
+    let (width, rows) = (peaks.len(), height as usize);
+    let mut pixels: Vec<u8> = style.background.repeat(width * rows);
+
+    // Rows of the top and bottom of each column's peak, row 0 at the top
+    let middle = (rows - 1) as f32 / 2.0;
+    let extent = |peak: f32| {
+        let reach = peak.clamp(0.0, 1.0) * middle;
+        ((middle - reach).round() as usize, (middle + reach).round() as usize)
+    };
+
+    let mut paint = |x: usize, from: usize, to: usize| {
+        for y in from.min(to)..=from.max(to) {
+            let offset = (y * width + x) * 4;
+            pixels[offset..offset + 4].copy_from_slice(&style.foreground);
+        }
+    };
+    for (x, &peak) in peaks.iter().enumerate() {
+        let (top, bottom) = extent(peak);
+        match style.mode {
+            WaveformMode::Filled => paint(x, top, bottom),
+            WaveformMode::Line => {
+                // Join each edge to the previous column's, so steep changes
+                // stay connected
+                let (previous_top, previous_bottom) = match x {
+                    0 => (top, bottom),
+                    _ => extent(peaks[x - 1]),
+                };
+                paint(x, top, previous_top);
+                paint(x, bottom, previous_bottom);
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| VaultError::InvalidOperation(format!("Failed to encode waveform: {}", e)))?;
+
+    Ok(png)
+}
@@ -0,0 +1,533 @@
+//! Zip archives of sounds, streamed to a writer
+//!
+//! Each entry is written as its file is read, with its CRC and sizes in a
+//! data descriptor after the content, so neither the files nor the archive
+//! are held in memory: only the central directory, a few dozen bytes per
+//! entry, grows with the number of sounds. Archives of 4 GiB or more, or of
+//! more than 65535 entries, end with Zip64 records.
+
+use crate::audio::export_file_name;
+use crate::context::{Deadline, OpContext};
+use crate::error::{Result, VaultError};
+use crate::levels::Content;
+use crate::local::LocalLibrary;
+use crate::models::SoundMetadata;
+use crate::pack::{CENTRAL_SIGNATURE, DEFLATED, END_SIGNATURE, LOCAL_SIGNATURE, STORED};
+use crate::paths::safe_file_name;
+use crate::schema::{Envelope, ZIP_MANIFEST_SCHEMA};
+use chrono::{Datelike, Timelike, Utc};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the manifest entry
+const MANIFEST_ENTRY: &str = "manifest.json";
+
+/// Name of the attribution entry
+const ATTRIBUTION_ENTRY: &str = "ATTRIBUTION.txt";
+
+/// Bytes of a file read and written at a time
+const CHUNK: usize = 64 * 1024;
+
+/// Largest file zipped; deflating may grow it a little, and entries must
+/// stay under 4 GiB without Zip64 sizes
+const MAX_ENTRY: u64 = 4_000_000_000;
+
+/// Signature of the data descriptor following an entry's content
+const DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+
+/// Signature of the Zip64 end of central directory record
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+
+/// Signature of the locator of the Zip64 end record
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+
+/// General purpose flags: sizes in a data descriptor, names in UTF-8
+const FLAGS: u16 = 0x0808;
+
+/// How [`SoundVault::zip_sounds`](crate::SoundVault::zip_sounds) stores
+/// files in the archive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZipCompression {
+    /// As they are; most audio formats barely deflate
+    #[default]
+    Store,
+    /// Deflated
+    Deflate,
+}
+
+/// Options of [`SoundVault::zip_sounds`](crate::SoundVault::zip_sounds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZipOptions {
+    /// How files are stored
+    pub compression: ZipCompression,
+
+    /// Path of each sound's entry, without extension: `{slug}`, `{name}`
+    /// and `{id}` are replaced by the sound's, and `/` makes folders. The
+    /// extension of the sound's format is added, and names already taken
+    /// get numbered, e.g. `rain (2).wav`.
+    pub name_template: String,
+
+    /// Add a `manifest.json` listing the entries, as an [`Envelope`] of
+    /// schema `soundvault.zip-manifest` holding [`ZipManifestEntry`]s
+    pub manifest: bool,
+
+    /// Add an `ATTRIBUTION.txt` crediting each sound
+    pub attribution: bool,
+}
+
+impl Default for ZipOptions {
+    fn default() -> Self {
+        Self {
+            compression: ZipCompression::Store,
+            name_template: "{slug}".to_string(),
+            manifest: true,
+            attribution: true,
+        }
+    }
+}
+
+/// Outcome of [`SoundVault::zip_sounds`](crate::SoundVault::zip_sounds)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZipReport {
+    /// IDs of the sounds zipped, with the name of their entry
+    pub entries: Vec<(String, String)>,
+
+    /// IDs of the sounds left out, with the reason
+    pub skipped: Vec<(String, String)>,
+
+    /// Size of the archive in bytes
+    pub bytes: u64,
+}
+
+/// A sound of a zip archive, as listed in its `manifest.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZipManifestEntry {
+    /// ID of the sound
+    pub sound_id: String,
+
+    /// Name of the entry
+    pub file: String,
+
+    /// Name of the sound
+    pub name: String,
+
+    /// License of the sound, as stored
+    pub license: String,
+
+    /// SHA-256 hash of the file, if known
+    pub hash: Option<String>,
+
+    /// One-line credit of the sound
+    pub credit: String,
+}
+
+/// An entry listed in the central directory
+struct Record {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+}
+
+/// The entry being written
+struct OpenEntry {
+    name: String,
+    method: u16,
+    offset: u64,
+    crc: crc32fast::Hasher,
+    size: u64,
+    compressed_size: u64,
+    deflater: Option<DeflateEncoder<Vec<u8>>>,
+}
+
+/// A zip archive written as a stream
+struct ZipStream<W> {
+    writer: W,
+    offset: u64,
+    records: Vec<Record>,
+    open: Option<OpenEntry>,
+    time: u16,
+    date: u16,
+}
+
+impl<W: AsyncWrite + Unpin> ZipStream<W> {
+    fn new(writer: W) -> Self {
+        let now = Utc::now();
+        Self {
+            writer,
+            offset: 0,
+            records: Vec::new(),
+            open: None,
+            time: ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+            date: ((((now.year() - 1980).max(0) as u32) << 9) | (now.month() << 5) | now.day()) as u16,
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes).await?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Start an entry, whose content follows through [`push`](Self::push)
+    async fn begin(&mut self, name: &str, compression: ZipCompression) -> Result<()> {
+        let method = match compression {
+            ZipCompression::Store => STORED,
+            ZipCompression::Deflate => DEFLATED,
+        };
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&LOCAL_SIGNATURE.to_le_bytes());
+        header.extend_from_slice(&20u16.to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&self.time.to_le_bytes());
+        header.extend_from_slice(&self.date.to_le_bytes());
+        // CRC and sizes come in the data descriptor
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+
+        let offset = self.offset;
+        self.write(&header).await?;
+        self.open = Some(OpenEntry {
+            name: name.to_string(),
+            method,
+            offset,
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            compressed_size: 0,
+            deflater: (method == DEFLATED).then(|| DeflateEncoder::new(Vec::new(), Compression::default())),
+        });
+        Ok(())
+    }
+
+    /// Write content of the open entry
+    async fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        let Some(entry) = self.open.as_mut() else {
+            return Ok(());
+        };
+        entry.crc.update(bytes);
+        entry.size += bytes.len() as u64;
+        let out = match &mut entry.deflater {
+            Some(deflater) => {
+                deflater.write_all(bytes)?;
+                std::mem::take(deflater.get_mut())
+            }
+            None => bytes.to_vec(),
+        };
+        entry.compressed_size += out.len() as u64;
+        self.write(&out).await
+    }
+
+    /// Close the open entry with its data descriptor
+    async fn end(&mut self) -> Result<()> {
+        let Some(mut entry) = self.open.take() else {
+            return Ok(());
+        };
+        if let Some(deflater) = entry.deflater.take() {
+            let rest = deflater.finish()?;
+            entry.compressed_size += rest.len() as u64;
+            self.write(&rest).await?;
+        }
+
+        let crc = entry.crc.finalize();
+        let mut descriptor = Vec::with_capacity(16);
+        descriptor.extend_from_slice(&DESCRIPTOR_SIGNATURE.to_le_bytes());
+        descriptor.extend_from_slice(&crc.to_le_bytes());
+        descriptor.extend_from_slice(&(entry.compressed_size as u32).to_le_bytes());
+        descriptor.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        self.write(&descriptor).await?;
+
+        self.records.push(Record {
+            name: entry.name,
+            method: entry.method,
+            crc,
+            compressed_size: entry.compressed_size,
+            size: entry.size,
+            offset: entry.offset,
+        });
+        Ok(())
+    }
+
+    /// Leave the open entry out of the archive; what was written of it
+    /// stays, but isn't listed
+    fn abandon(&mut self) {
+        self.open = None;
+    }
+
+    /// Write a whole entry
+    async fn add(&mut self, name: &str, bytes: &[u8], compression: ZipCompression) -> Result<()> {
+        self.begin(name, compression).await?;
+        self.push(bytes).await?;
+        self.end().await
+    }
+
+    /// Write the central directory of the entries ended, leaving out the
+    /// open one, and flush
+    ///
+    /// # Returns
+    ///
+    /// The size of the archive
+    async fn finish(mut self) -> Result<u64> {
+        self.abandon();
+        let directory_offset = self.offset;
+        let records = std::mem::take(&mut self.records);
+        for record in &records {
+            let zip64 = record.offset >= u32::MAX as u64;
+            let mut header = Vec::with_capacity(46 + record.name.len() + 12);
+            header.extend_from_slice(&CENTRAL_SIGNATURE.to_le_bytes());
+            header.extend_from_slice(&(if zip64 { 45u16 } else { 20 }).to_le_bytes());
+            header.extend_from_slice(&(if zip64 { 45u16 } else { 20 }).to_le_bytes());
+            header.extend_from_slice(&FLAGS.to_le_bytes());
+            header.extend_from_slice(&record.method.to_le_bytes());
+            header.extend_from_slice(&self.time.to_le_bytes());
+            header.extend_from_slice(&self.date.to_le_bytes());
+            header.extend_from_slice(&record.crc.to_le_bytes());
+            header.extend_from_slice(&(record.compressed_size as u32).to_le_bytes());
+            header.extend_from_slice(&(record.size as u32).to_le_bytes());
+            header.extend_from_slice(&(record.name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(if zip64 { 12u16 } else { 0 }).to_le_bytes());
+            // Comment length, disk, internal and external attributes
+            header.extend_from_slice(&[0; 10]);
+            header.extend_from_slice(&(record.offset.min(u32::MAX as u64) as u32).to_le_bytes());
+            header.extend_from_slice(record.name.as_bytes());
+            if zip64 {
+                header.extend_from_slice(&1u16.to_le_bytes());
+                header.extend_from_slice(&8u16.to_le_bytes());
+                header.extend_from_slice(&record.offset.to_le_bytes());
+            }
+            self.write(&header).await?;
+        }
+        let count = records.len() as u64;
+        let directory_size = self.offset - directory_offset;
+
+        let mut end = Vec::with_capacity(98);
+        if count >= u16::MAX as u64 || directory_offset >= u32::MAX as u64 || directory_size >= u32::MAX as u64 {
+            let zip64_end = self.offset;
+            end.extend_from_slice(&ZIP64_END_SIGNATURE.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&45u16.to_le_bytes());
+            end.extend_from_slice(&45u16.to_le_bytes());
+            end.extend_from_slice(&[0; 8]);
+            end.extend_from_slice(&count.to_le_bytes());
+            end.extend_from_slice(&count.to_le_bytes());
+            end.extend_from_slice(&directory_size.to_le_bytes());
+            end.extend_from_slice(&directory_offset.to_le_bytes());
+
+            end.extend_from_slice(&ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&zip64_end.to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+        end.extend_from_slice(&END_SIGNATURE.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        let count = (count.min(u16::MAX as u64) as u16).to_le_bytes();
+        end.extend_from_slice(&count);
+        end.extend_from_slice(&count);
+        end.extend_from_slice(&(directory_size.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend_from_slice(&(directory_offset.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.write(&end).await?;
+        self.writer.flush().await?;
+
+        Ok(self.offset)
+    }
+}
+
+/// Name of a sound's entry from the template, unique among `taken`
+fn entry_name(template: &str, metadata: &SoundMetadata, taken: &mut HashSet<String>) -> String {
+    let placeholder = regex::Regex::new(r"\{(id|slug|name)\}").expect("valid regex");
+    let rendered = placeholder.replace_all(template, |captures: &regex::Captures| match &captures[1] {
+        "id" => metadata.id.clone(),
+        "slug" => metadata.slug.clone().unwrap_or_else(|| metadata.id.clone()),
+        _ => metadata.name.clone(),
+    });
+    let stem = rendered
+        .split('/')
+        .filter(|part| !matches!(part.trim(), "" | "." | ".."))
+        .map(|part| safe_file_name(part, "_"))
+        .collect::<Vec<_>>()
+        .join("/");
+    let stem = if stem.is_empty() { metadata.id.clone() } else { stem };
+
+    // Names often end with the extension already
+    let exported = export_file_name(metadata);
+    let extension = exported.extension().map(|ext| ext.to_string_lossy().to_string());
+    let stem = match (Path::new(&stem).extension(), &extension) {
+        (Some(own), Some(extension)) if own.eq_ignore_ascii_case(extension) => {
+            stem[..stem.len() - extension.len() - 1].to_string()
+        }
+        _ => stem,
+    };
+    let with_extension = |stem: &str| match &extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    };
+
+    let mut name = with_extension(&stem);
+    let mut number = 1;
+    while !taken.insert(name.to_lowercase()) {
+        number += 1;
+        name = with_extension(&format!("{} ({})", stem, number));
+    }
+    name
+}
+
+impl LocalLibrary {
+    /// Stream a zip archive of sounds to `writer`
+    ///
+    /// Files are written as stored, one chunk at a time; archived and
+    /// packed sounds are inflated one at a time. Sounds that aren't in the
+    /// vault, were never downloaded, expired, or whose file can't be read
+    /// are left out and reported. If `context` stops the archive, it is
+    /// closed with the entries written in full so far, without manifest nor
+    /// attribution, before the error is returned: the writer then holds a
+    /// valid archive of those entries.
+    pub async fn zip_sounds<W: AsyncWrite + Unpin>(
+        &self,
+        ids: &[String],
+        writer: W,
+        options: &ZipOptions,
+        context: &OpContext,
+    ) -> Result<ZipReport> {
+        let deadline = context.start("zip_sounds");
+        let mut zip = ZipStream::new(writer);
+        let mut report = ZipReport::default();
+        let mut listed = Vec::new();
+
+        match self.zip_entries(&mut zip, ids, options, &deadline, &mut report, &mut listed).await {
+            Ok(()) => {}
+            Err(e @ (VaultError::Cancelled { .. } | VaultError::Timeout { .. })) => {
+                zip.finish().await?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+
+        if options.manifest {
+            let manifest = serde_json::to_string_pretty(&Envelope::wrap(ZIP_MANIFEST_SCHEMA, &listed)?)?;
+            zip.add(MANIFEST_ENTRY, manifest.as_bytes(), options.compression).await?;
+        }
+        if options.attribution {
+            let mut text = String::new();
+            for entry in &listed {
+                text.push_str(&format!("{}: {}\n", entry.file, entry.credit));
+            }
+            zip.add(ATTRIBUTION_ENTRY, text.as_bytes(), options.compression).await?;
+        }
+
+        report.bytes = zip.finish().await?;
+        Ok(report)
+    }
+
+    /// Write an entry per sound, stopping between chunks if `deadline` says
+    async fn zip_entries<W: AsyncWrite + Unpin>(
+        &self,
+        zip: &mut ZipStream<W>,
+        ids: &[String],
+        options: &ZipOptions,
+        deadline: &Deadline<'_>,
+        report: &mut ZipReport,
+        listed: &mut Vec<ZipManifestEntry>,
+    ) -> Result<()> {
+        let mut taken = HashSet::new();
+        if options.manifest {
+            taken.insert(MANIFEST_ENTRY.to_lowercase());
+        }
+        if options.attribution {
+            taken.insert(ATTRIBUTION_ENTRY.to_lowercase());
+        }
+        let progress = |report: &ZipReport| format!("{} of {} sounds zipped", report.entries.len(), ids.len());
+
+        let mut buffer = vec![0; CHUNK];
+        'sounds: for id in ids {
+            deadline.check(|| progress(report))?;
+            let metadata = match self.get_sound(id).await {
+                Ok(sound) => sound.metadata,
+                Err(VaultError::NotFound(_)) => {
+                    report.skipped.push((id.clone(), "not in the vault".to_string()));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if metadata.path.is_none() {
+                report.skipped.push((id.clone(), "not downloaded".to_string()));
+                continue;
+            }
+            if self.exclude_expired(&metadata, "zip_sounds") {
+                report.skipped.push((id.clone(), "expired".to_string()));
+                continue;
+            }
+
+            let opened = async {
+                self.stage_blob(&metadata).await?;
+                let source: (Box<dyn AsyncRead + Unpin + Send>, u64) = match self.sound_stream(&metadata)? {
+                    Content::File(path) => {
+                        let file = tokio::fs::File::open(&path)
+                            .await
+                            .map_err(|_| VaultError::FileMissing(path.to_string_lossy().to_string()))?;
+                        let size = file.metadata().await?.len();
+                        (Box::new(file), size)
+                    }
+                    Content::Bytes(bytes) => {
+                        let size = bytes.len() as u64;
+                        (Box::new(std::io::Cursor::new(bytes)), size)
+                    }
+                };
+                Ok::<_, VaultError>(source)
+            }
+            .await;
+            let mut source = match opened {
+                Ok((_, size)) if size > MAX_ENTRY => {
+                    report.skipped.push((id.clone(), format!("{} bytes is too large", size)));
+                    continue;
+                }
+                Ok((source, _)) => source,
+                Err(e) => {
+                    report.skipped.push((id.clone(), e.to_string()));
+                    continue;
+                }
+            };
+
+            let name = entry_name(&options.name_template, &metadata, &mut taken);
+            zip.begin(&name, options.compression).await?;
+            loop {
+                deadline.check(|| progress(report))?;
+                let read = match source.read(&mut buffer).await {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) => {
+                        zip.abandon();
+                        taken.remove(&name.to_lowercase());
+                        report.skipped.push((id.clone(), e.to_string()));
+                        continue 'sounds;
+                    }
+                };
+                zip.push(&buffer[..read]).await?;
+            }
+            zip.end().await?;
+
+            listed.push(ZipManifestEntry {
+                sound_id: id.clone(),
+                file: name.clone(),
+                name: metadata.name.clone(),
+                license: metadata.license.clone(),
+                hash: metadata.hash.clone(),
+                credit: self.attribution(&metadata).await?.credit(),
+            });
+            report.entries.push((id.clone(), name));
+        }
+
+        Ok(())
+    }
+}